@@ -0,0 +1,5 @@
+-- `password` holds a bcrypt hash, checked by auth_backend::SqliteBackend
+CREATE TABLE IF NOT EXISTS users (
+    username TEXT PRIMARY KEY,
+    password TEXT NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Items of the warp item API, kept by item_store::SqliteStore; ids are UUIDs in text form
+CREATE TABLE IF NOT EXISTS api_items (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL
+);
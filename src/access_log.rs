@@ -0,0 +1,214 @@
+use chrono::{DateTime, Local};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// One served request, written in the Combined Log Format with the response
+/// time in milliseconds appended:
+///
+/// `127.0.0.1 - - [10/Oct/2024:13:55:36 +0000] "GET /a.css HTTP/1.1" 200 2326 "-" "curl/8.0" 3`
+#[derive(Debug, Clone)]
+pub struct AccessLogEntry {
+    pub client: Option<IpAddr>,
+    pub user: Option<String>,
+    pub time: DateTime<Local>,
+    pub method: String,
+    pub target: String,
+    pub version: String,
+    pub status: u16,
+    /// Body size, when known up front
+    pub bytes: Option<u64>,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub elapsed: Duration,
+}
+
+// Request headers go inside quotes, so keep clients from closing them early
+// or starting a fake line of their own
+fn quoted(value: Option<&str>) -> String {
+    match value {
+        Some(value) => value
+            .chars()
+            .flat_map(|c| match c {
+                '"' => vec!['\\', '"'],
+                '\\' => vec!['\\', '\\'],
+                c if c.is_control() => c.escape_default().collect(),
+                c => vec![c],
+            })
+            .collect(),
+        None => "-".to_string(),
+    }
+}
+
+impl fmt::Display for AccessLogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} - {} [{}] \"{} {} {}\" {} {} \"{}\" \"{}\" {}",
+            self.client.map_or_else(|| "-".to_string(), |ip| ip.to_string()),
+            self.user.as_deref().map_or_else(|| "-".to_string(), |user| quoted(Some(user)).replace(' ', "_")),
+            self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            quoted(Some(&self.target)),
+            self.version,
+            self.status,
+            // CLF writes empty bodies as "-"
+            self.bytes.filter(|&bytes| bytes > 0).map_or_else(|| "-".to_string(), |bytes| bytes.to_string()),
+            quoted(self.referer.as_deref()),
+            quoted(self.user_agent.as_deref()),
+            self.elapsed.as_millis(),
+        )
+    }
+}
+
+struct LogFile {
+    file: File,
+    len: u64,
+}
+
+/// An append-only access log that rotates by size: once writing a line would
+/// take the file past `max_bytes`, `access.log` becomes `access.log.1`, the
+/// older `.1`, `.2`, ... files shift up, and anything past `max_files` is
+/// deleted.
+///
+/// Writes block on file I/O under a lock, so async callers should make them
+/// from a blocking task.
+pub struct AccessLog {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: Mutex<LogFile>,
+}
+
+impl AccessLog {
+    pub fn open(path: impl Into<PathBuf>, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = open_append(&path)?;
+        let len = file.metadata()?.len();
+        Ok(AccessLog { path, max_bytes, max_files, file: Mutex::new(LogFile { file, len }) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write(&self, entry: &AccessLogEntry) -> io::Result<()> {
+        let line = format!("{}\n", entry);
+        let mut log = self.file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // A line longer than the limit still gets a file to itself
+        if log.len > 0 && log.len + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+            *log = LogFile { file: open_append(&self.path)?, len: 0 };
+        }
+        log.file.write_all(line.as_bytes())?;
+        log.len += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        if self.max_files == 0 {
+            return fs::remove_file(&self.path);
+        }
+        remove_if_exists(&self.rotated(self.max_files))?;
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated(index);
+            if from.exists() {
+                fs::rename(from, self.rotated(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn entry(target: &str) -> AccessLogEntry {
+        AccessLogEntry {
+            client: Some("203.0.113.9".parse().unwrap()),
+            user: Some("ops".to_string()),
+            time: Local.with_ymd_and_hms(2024, 10, 10, 13, 55, 36).unwrap(),
+            method: "GET".to_string(),
+            target: target.to_string(),
+            version: "HTTP/1.1".to_string(),
+            status: 200,
+            bytes: Some(2326),
+            referer: None,
+            user_agent: Some("curl/8.0 \"quoted\"".to_string()),
+            elapsed: Duration::from_micros(3500),
+        }
+    }
+
+    #[test]
+    fn test_combined_log_format() {
+        let line = entry("/a.css?v=1").to_string();
+        let offset = Local.with_ymd_and_hms(2024, 10, 10, 13, 55, 36).unwrap().format("%z").to_string();
+        assert_eq!(
+            line,
+            format!(
+                "203.0.113.9 - ops [10/Oct/2024:13:55:36 {}] \"GET /a.css?v=1 HTTP/1.1\" 200 2326 \"-\" \"curl/8.0 \\\"quoted\\\"\" 3",
+                offset
+            )
+        );
+
+        let empty = AccessLogEntry { client: None, user: None, bytes: Some(0), ..entry("/") };
+        assert!(empty.to_string().starts_with("- - - ["));
+        assert!(empty.to_string().contains("\" 200 - \""));
+        assert!(!entry("/a\nforged").to_string().contains('\n'));
+    }
+
+    #[test]
+    fn test_rotates_by_size() {
+        let dir = std::env::temp_dir().join(format!("noxium_access_log_rotate_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("access.log");
+        let line_len = entry("/0").to_string().len() as u64 + 1;
+        // Room for two lines per file, keeping two old files
+        let log = AccessLog::open(&path, line_len * 2, 2).unwrap();
+
+        for i in 0..7 {
+            log.write(&entry(&format!("/{}", i))).unwrap();
+        }
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert!(read(path.clone()).contains("GET /6 "));
+        assert!(read(dir.join("access.log.1")).contains("GET /4 "));
+        assert!(read(dir.join("access.log.2")).contains("GET /2 "));
+        assert_eq!(read(dir.join("access.log.2")).lines().count(), 2);
+        // Lines 0 and 1 were rotated out
+        assert!(!dir.join("access.log.3").exists());
+
+        // Reopening keeps appending to the current file
+        drop(log);
+        let log = AccessLog::open(&path, line_len * 2, 2).unwrap();
+        log.write(&entry("/7")).unwrap();
+        assert_eq!(read(path).lines().count(), 2);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -1,10 +1,57 @@
 mod analytics;
 
 use analytics::data_analysis::{analyze_data, DataAnalyzer, DataSummary};
-use analytics::real_time_processing::{start_real_time_processing, create_record_batch, RealTimeProcessor, RecordBatch};
-use std::thread;
-use std::sync::{mpsc, Arc, Mutex};
+use analytics::real_time_processing::{start_real_time_processing, create_record_batch, ProcessorPool, RecordBatch};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::env;
+use std::sync::Arc;
 use std::time::Duration;
+use thiserror::Error;
+
+#[allow(dead_code)]
+mod live_processor;
+use live_processor::{analyze_csv, UptimeStats};
+
+mod notifier;
+use notifier::{notifier_from_env, render_body, LogNotifier, Notifier};
+
+#[path = "../rate_limit.rs"]
+#[allow(dead_code)]
+mod rate_limit;
+
+mod retry_queue;
+use retry_queue::RetryQueue;
+
+// Body of the notification sent when a processed batch has uptime anomalies
+const ANOMALY_TEMPLATE: &str = "\
+{{{ count }}} uptime anomalies in batch {{{ batch }}} (average {{{ average }}}, std dev {{{ std_dev }}}):
+{{#each anomalies}}- row {{{ index }}}: uptime {{{ uptime }}}, z-score {{{ z_score }}}
+{{/each}}";
+
+// Real-time processor workers when ANALYTICS_WORKERS is unset or invalid
+const DEFAULT_WORKERS: usize = 4;
+
+// Uptimes the rest of the fleet reported in the same window, which a record's
+// uptime is checked against for anomalies
+const FLEET_UPTIMES: [(&str, i64); 15] = [
+    ("edge-01", 86_391),
+    ("edge-02", 86_402),
+    ("edge-03", 86_377),
+    ("edge-04", 86_415),
+    ("edge-05", 86_388),
+    ("edge-06", 86_409),
+    ("edge-07", 86_396),
+    ("edge-08", 86_381),
+    ("edge-09", 86_420),
+    ("edge-10", 86_393),
+    ("edge-11", 86_404),
+    ("edge-12", 86_385),
+    ("edge-13", 86_399),
+    ("edge-14", 86_412),
+    ("edge-15", 86_390),
+];
 
 // Define a new enum for log levels
 enum LogLevel {
@@ -38,18 +85,111 @@ fn save_results_to_db(results: &str) {
     log(LogLevel::Info, &format!("Saving results to database: {}", results));
 }
 
-// Define a function to simulate sending notifications
-fn send_notification(message: &str) {
-    log(LogLevel::Info, &format!("Sending notification: {}", message));
+// Render `template` against `context` and send it. A failed notification is
+// logged rather than stopping the pipeline
+async fn send_notification(notifier: &dyn Notifier, subject: &str, template: &str, context: &Value) {
+    let result = match render_body(template, context) {
+        Ok(body) => notifier.send(subject, &body).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        log(LogLevel::Error, &format!("Failed to send notification '{}': {}", subject, e));
+    }
+}
+
+// Notify about a processed batch if it has anomalies; returns whether it did
+async fn notify_anomalies(notifier: &dyn Notifier, batch: &str, stats: &UptimeStats) -> bool {
+    if stats.anomalies.is_empty() {
+        return false;
+    }
+    let anomalies: Vec<Value> = stats
+        .anomalies
+        .iter()
+        .map(|anomaly| {
+            json!({ "index": anomaly.index, "uptime": anomaly.uptime, "z_score": format!("{:.2}", anomaly.z_score) })
+        })
+        .collect();
+    let context = json!({
+        "batch": batch,
+        "count": anomalies.len(),
+        "average": format!("{:.2}", stats.average),
+        "std_dev": format!("{:.2}", stats.std_dev),
+        "anomalies": anomalies,
+    });
+    send_notification(notifier, &format!("Uptime anomalies in {}", batch), ANOMALY_TEMPLATE, &context).await;
+    true
+}
+
+// Typed telemetry record accepted by the pipeline
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct TelemetryRecord {
+    name: String,
+    status: String,
+    uptime: i64,
+}
+
+// Define the errors produced while validating telemetry JSON
+#[derive(Debug, Error)]
+enum ValidationError {
+    #[error("Malformed JSON: {0}")]
+    Malformed(#[from] serde_json::Error),
+    #[error("Field '{0}' must be a non-empty string")]
+    EmptyField(&'static str),
+    #[error("Field 'uptime' must be a positive integer, got {0}")]
+    InvalidUptime(i64),
+}
+
+// Number of real-time processor workers, from ANALYTICS_WORKERS
+fn worker_count() -> usize {
+    match env::var("ANALYTICS_WORKERS") {
+        Ok(value) => match value.trim().parse() {
+            Ok(workers) if workers > 0 => workers,
+            _ => {
+                log(
+                    LogLevel::Warning,
+                    &format!("Ignoring invalid ANALYTICS_WORKERS {:?}, using {}", value, DEFAULT_WORKERS),
+                );
+                DEFAULT_WORKERS
+            }
+        },
+        Err(_) => DEFAULT_WORKERS,
+    }
+}
+
+// CSV telemetry for the fleet with `record` as the last row, all reported at `timestamp`
+fn fleet_telemetry_csv(record: &TelemetryRecord, timestamp: i64) -> String {
+    let quoted = |field: &str| format!("\"{}\"", field.replace('"', "\"\""));
+    let mut csv = String::from("name,status,uptime,timestamp,is_active\n");
+    for (name, uptime) in FLEET_UPTIMES {
+        csv.push_str(&format!("{},running,{},{},true\n", name, uptime, timestamp));
+    }
+    let (name, status) = (quoted(&record.name), quoted(&record.status));
+    csv.push_str(&format!("{},{},{},{},true\n", name, status, record.uptime, timestamp));
+    csv
 }
 
 // Define a function to validate JSON data
-fn validate_json(json: &str) -> bool {
-    // Simple validation; in a real application, use a JSON library
-    json.contains("name") && json.contains("status")
+fn validate_json(json: &str) -> Result<TelemetryRecord, ValidationError> {
+    let record: TelemetryRecord = serde_json::from_str(json)?;
+
+    if record.name.trim().is_empty() {
+        return Err(ValidationError::EmptyField("name"));
+    }
+    if record.status.trim().is_empty() {
+        return Err(ValidationError::EmptyField("status"));
+    }
+    if record.uptime <= 0 {
+        return Err(ValidationError::InvalidUptime(record.uptime));
+    }
+
+    Ok(record)
 }
 
-fn main() {
+#[tokio::main]
+async fn main() {
+    // LogNotifier writes through `log`; show its notifications unless RUST_LOG says otherwise
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
     // Example JSON data
     let json_data = r#"
     {
@@ -63,11 +203,29 @@ fn main() {
     let config = fetch_config();
     log(LogLevel::Info, &format!("Configuration: {}", config));
 
+    // Pick where notifications go, retrying failed deliveries in the background
+    let notifier: Box<dyn Notifier> = match notifier_from_env() {
+        Ok(notifier) => notifier,
+        Err(e) => {
+            log(LogLevel::Warning, &format!("{}, logging notifications instead", e));
+            Box::new(LogNotifier)
+        }
+    };
+    let notifier = Arc::new(RetryQueue::from_env(notifier));
+    tokio::spawn(notifier.clone().run(Duration::from_secs(1)));
+
     // Validate JSON data
-    if !validate_json(json_data) {
-        log(LogLevel::Error, "Invalid JSON data");
-        return;
-    }
+    let record = match validate_json(json_data) {
+        Ok(record) => record,
+        Err(e) => {
+            log(LogLevel::Error, &format!("Invalid JSON data: {}", e));
+            return;
+        }
+    };
+    log(
+        LogLevel::Info,
+        &format!("Validated record '{}' ({}), uptime {}", record.name, record.status, record.uptime),
+    );
 
     // Create a DataAnalyzer instance
     let analyzer = DataAnalyzer::new();
@@ -83,26 +241,19 @@ fn main() {
     // Save results to the database
     save_results_to_db(&summary.to_string());
 
-    // Send notification
-    send_notification("Data processing complete");
+    // Check the record's uptime against the fleet's for anomalies, notifying if there are any
+    match analyze_csv(fleet_telemetry_csv(&record, Utc::now().timestamp()).as_bytes()) {
+        Ok(stats) => {
+            notify_anomalies(notifier.as_ref(), &record.name, &stats).await;
+        }
+        Err(e) => log(LogLevel::Error, &format!("Failed to analyze telemetry batch: {}", e)),
+    }
+    log(LogLevel::Info, &format!("Data processing complete for {}", record.name));
 
-    // Start real-time processing
+    // Start real-time processing on a pool of workers draining the same channel
     let (tx, rx) = start_real_time_processing();
-
-    // Create an Arc for shared state
-    let shared_state = Arc::new(Mutex::new(0));
-
-    // Spawn a thread to handle real-time processing
-    let processor_shared = Arc::clone(&shared_state);
-    thread::spawn(move || {
-        let processor = RealTimeProcessor::new(rx);
-        processor.process_data();
-
-        // Update shared state
-        let mut state = processor_shared.lock().unwrap();
-        *state += 1;
-        log(LogLevel::Info, &format!("Real-time processor state updated: {}", *state));
-    });
+    let pool = ProcessorPool::spawn(rx, worker_count());
+    log(LogLevel::Info, &format!("Real-time processing started with {} workers", pool.size()));
 
     // Create a record batch and send it for processing
     let batch = create_record_batch(json_data);
@@ -110,12 +261,6 @@ fn main() {
     
     // Log the batch creation
     log(LogLevel::Info, &format!("Record batch created and sent"));
-
-    // Simulate some delay to allow processing to complete
-    thread::sleep(Duration::from_secs(5));
-    
-    // Log completion
-    log(LogLevel::Info, "Processing completed");
     
     // Additional features
     let batch_count = 5;
@@ -127,4 +272,126 @@ fn main() {
 
     // Log total batches sent
     log(LogLevel::Info, &format!("Total batches sent: {}", batch_count));
-}
\ No newline at end of file
+
+    // Close the channel and wait for the workers so no batch is lost on shutdown
+    drop(tx);
+    let totals = match pool.join() {
+        Ok(totals) => totals,
+        Err(_) => {
+            log(LogLevel::Error, "Real-time processor thread panicked");
+            return;
+        }
+    };
+
+    // Log completion
+    log(LogLevel::Info, &format!("Processing completed: {} batches, {} bytes", totals.batches, totals.bytes));
+
+    // Keep notifications still waiting for a retry for the next run
+    if let Err(e) = notifier.spill() {
+        log(LogLevel::Error, &format!("Failed to save undelivered notifications: {}", e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_validate_json_accepts_valid_record() {
+        let record = validate_json(r#"{"name": "noxium", "status": "running", "uptime": 12345}"#).unwrap();
+        assert_eq!(
+            record,
+            TelemetryRecord {
+                name: "noxium".to_string(),
+                status: "running".to_string(),
+                uptime: 12345,
+            }
+        );
+    }
+
+    #[test]
+    fn test_validate_json_rejects_wrong_types() {
+        let result = validate_json(r#"{"name": "noxium", "status": "running", "uptime": "long"}"#);
+        assert!(matches!(result, Err(ValidationError::Malformed(_))));
+
+        let result = validate_json(r#"{"name": 7, "status": "running", "uptime": 1}"#);
+        assert!(matches!(result, Err(ValidationError::Malformed(_))));
+
+        let result = validate_json(r#"{"name": "", "status": "running", "uptime": 1}"#);
+        assert!(matches!(result, Err(ValidationError::EmptyField("name"))));
+
+        let result = validate_json(r#"{"name": "noxium", "status": "running", "uptime": -5}"#);
+        assert!(matches!(result, Err(ValidationError::InvalidUptime(-5))));
+    }
+
+    #[test]
+    fn test_validate_json_rejects_substring_false_positive() {
+        assert!(validate_json("name status").is_err());
+    }
+
+    // Keeps what it is asked to send
+    #[derive(Default)]
+    struct RecordingNotifier {
+        sent: Mutex<Vec<(String, String)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Notifier for RecordingNotifier {
+        async fn send(&self, subject: &str, body: &str) -> Result<(), notifier::NotifyError> {
+            self.sent.lock().unwrap().push((subject.to_string(), body.to_string()));
+            Ok(())
+        }
+    }
+
+    fn stats_with(anomalies: Vec<live_processor::Anomaly>) -> UptimeStats {
+        UptimeStats {
+            count: 21,
+            total: 70_200,
+            average: 3342.857,
+            max: 50_000,
+            min: 1000,
+            variance: 1.0e8,
+            std_dev: 10_435.2,
+            histogram: Default::default(),
+            anomalies,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recently_restarted_record_stands_out_from_the_fleet() {
+        let record = validate_json(r#"{"name": "noxium", "status": "running", "uptime": 12345}"#).unwrap();
+        let stats = analyze_csv(fleet_telemetry_csv(&record, 1_700_000_000).as_bytes()).unwrap();
+        assert_eq!(stats.count, FLEET_UPTIMES.len() + 1);
+        assert_eq!(stats.anomalies.len(), 1, "{:?}", stats.anomalies);
+        assert_eq!((stats.anomalies[0].index, stats.anomalies[0].uptime), (FLEET_UPTIMES.len(), 12345));
+
+        let notifier = RecordingNotifier::default();
+        assert!(notify_anomalies(&notifier, &record.name, &stats).await);
+        assert_eq!(notifier.sent.lock().unwrap()[0].0, "Uptime anomalies in noxium");
+
+        // Names with commas or quotes stay in their column
+        let record = TelemetryRecord { name: "a, \"b\"".to_string(), ..record };
+        let stats = analyze_csv(fleet_telemetry_csv(&record, 1_700_000_000).as_bytes()).unwrap();
+        assert_eq!(stats.anomalies.len(), 1, "{:?}", stats.anomalies);
+    }
+
+    #[tokio::test]
+    async fn test_anomalies_trigger_a_templated_notification() {
+        let notifier = RecordingNotifier::default();
+        assert!(!notify_anomalies(&notifier, "server-1", &stats_with(Vec::new())).await);
+        assert!(notifier.sent.lock().unwrap().is_empty());
+
+        let anomaly = live_processor::Anomaly { index: 7, uptime: 50_000, z_score: 4.4711 };
+        assert!(notify_anomalies(&notifier, "server-1", &stats_with(vec![anomaly])).await);
+        assert_eq!(
+            *notifier.sent.lock().unwrap(),
+            vec![(
+                "Uptime anomalies in server-1".to_string(),
+                "1 uptime anomalies in batch server-1 (average 3342.86, std dev 10435.20):\n\
+                 - row 7: uptime 50000, z-score 4.47\n"
+                    .to_string()
+            )]
+        );
+    }
+}
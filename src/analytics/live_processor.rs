@@ -1,14 +1,101 @@
-use arrow::array::{Float64Array, Int64Array, StringArray, BooleanArray};
-use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::array::{Array, ArrayRef, Float64Array, Int64Array, StringArray, BooleanArray, TimestampSecondArray};
+use arrow::compute::{cast, concat_batches};
+use arrow::csv::reader::Format;
+use arrow::csv::ReaderBuilder;
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::error::ArrowError;
 use arrow::record_batch::RecordBatch;
 use arrow::util::pretty::pretty_format_batches;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::errors::ParquetError;
 use serde_json::Value;
 use std::sync::Arc;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, BufWriter, Cursor, Read, Write};
 use std::path::Path;
 use chrono::Utc;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use thiserror::Error;
+
+#[path = "../escaping.rs"]
+#[allow(dead_code)]
+mod escaping;
+use escaping::{html_escape, xml_escape};
+
+// Errors from building or reading telemetry batches
+#[derive(Debug, Error)]
+pub enum AnalyticsError {
+    #[error("Arrow error: {0}")]
+    Arrow(#[from] ArrowError),
+    #[error("Parquet error: {0}")]
+    Parquet(#[from] ParquetError),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Missing column '{0}'")]
+    MissingColumn(String),
+    #[error("Column '{column}' has type {found:?}, which can't be read as {expected:?}")]
+    IncompatibleType {
+        column: String,
+        expected: DataType,
+        found: DataType,
+    },
+}
+
+// Rows whose uptime is more than this many standard deviations from the mean are anomalies
+pub const DEFAULT_Z_THRESHOLD: f64 = 3.0;
+
+// A row whose uptime is a statistical outlier
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    /// Row index within the analyzed batch
+    pub index: usize,
+    pub uptime: i64,
+    pub z_score: f64,
+}
+
+// Uptime statistics computed for every ingestion format
+#[derive(Debug, Clone, PartialEq)]
+pub struct UptimeStats {
+    pub count: usize,
+    pub total: i64,
+    pub average: f64,
+    pub max: i64,
+    pub min: i64,
+    pub variance: f64,
+    pub std_dev: f64,
+    pub histogram: BTreeMap<i64, usize>,
+    /// Rows beyond the z-score threshold, in row order
+    pub anomalies: Vec<Anomaly>,
+}
+
+impl UptimeStats {
+    pub fn report(&self) -> String {
+        format!(
+            "Summary Report:\n\
+            - Total Uptime: {}\n\
+            - Average Uptime: {:.2}\n\
+            - Max Uptime: {}\n\
+            - Min Uptime: {}\n\
+            - Uptime Variance: {:.2}\n\
+            - Uptime Standard Deviation: {:.2}",
+            self.total, self.average, self.max, self.min, self.variance, self.std_dev
+        )
+    }
+}
+
+// Schema shared by the JSON, CSV and Parquet ingestion paths
+pub fn telemetry_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("name", DataType::Utf8, false),
+        Field::new("status", DataType::Utf8, false),
+        Field::new("uptime", DataType::Int64, false),
+        Field::new("timestamp", DataType::Timestamp(TimeUnit::Second, None), false),
+        Field::new("is_active", DataType::Boolean, false),
+    ]))
+}
 
 pub fn analyze_data(json_data: &str) {
     let data: Value = match serde_json::from_str(json_data) {
@@ -56,32 +143,10 @@ pub fn analyze_data(json_data: &str) {
     };
 
     // Define the schema for the data
-    let schema = Arc::new(Schema::new(vec![
-        Field::new("name", DataType::Utf8, false),
-        Field::new("status", DataType::Utf8, false),
-        Field::new("uptime", DataType::Int64, false),
-        Field::new("timestamp", DataType::Timestamp(TimeUnit::Second, None), false),
-        Field::new("is_active", DataType::Boolean, false),
-    ]));
-
-    // Create Arrow arrays
-    let name_array = StringArray::from(vec![name]);
-    let status_array = StringArray::from(vec![status]);
-    let uptime_array = Int64Array::from(vec![uptime]);
-    let timestamp_array = Int64Array::from(vec![timestamp]);
-    let is_active_array = BooleanArray::from(vec![is_active]);
+    let schema = telemetry_schema();
 
     // Create a record batch
-    let batch = match RecordBatch::try_new(
-        schema.clone(),
-        vec![
-            Arc::new(name_array) as Arc<dyn arrow::array::Array>,
-            Arc::new(status_array),
-            Arc::new(uptime_array),
-            Arc::new(timestamp_array),
-            Arc::new(is_active_array),
-        ],
-    ) {
+    let batch = match json_record_batch(name, status, uptime, timestamp, is_active) {
         Ok(b) => b,
         Err(e) => {
             eprintln!("Error creating RecordBatch: {}", e);
@@ -101,28 +166,15 @@ pub fn analyze_data(json_data: &str) {
 
     // Additional features
 
-    // 1. Basic statistics
+    // 1-4. Basic statistics, shared with the CSV and Parquet paths
+    let stats = match analyze_batch(&batch) {
+        Ok(stats) => stats,
+        Err(e) => {
+            eprintln!("Error analyzing batch: {}", e);
+            return;
+        }
+    };
     let uptime_col = batch.column(2).as_any().downcast_ref::<Int64Array>().unwrap();
-    let total_uptime: i64 = uptime_col.iter().map(|v| v.unwrap_or(&0)).sum();
-    let count = uptime_col.len();
-    let avg_uptime = if count > 0 { total_uptime as f64 / count as f64 } else { 0.0 };
-    println!("Total Uptime: {}", total_uptime);
-    println!("Average Uptime: {:.2}", avg_uptime);
-
-    // 2. Find max uptime
-    let max_uptime = uptime_col.iter().filter_map(|v| v).max().unwrap_or(&0);
-    println!("Max Uptime: {}", max_uptime);
-
-    // 3. Find min uptime
-    let min_uptime = uptime_col.iter().filter_map(|v| v).min().unwrap_or(&0);
-    println!("Min Uptime: {}", min_uptime);
-
-    // 4. Generate a histogram
-    let mut histogram = HashMap::new();
-    for value in uptime_col.iter().filter_map(|v| v) {
-        *histogram.entry(value).or_insert(0) += 1;
-    }
-    println!("Uptime Histogram: {:?}", histogram);
 
     // 5. Filter records based on status
     if status == "Active" {
@@ -131,7 +183,7 @@ pub fn analyze_data(json_data: &str) {
         println!("Record is Inactive");
     }
 
-    // 6. Write record to file
+    // 6. Write record to file, as one line of NDJSON
     let file_path = Path::new("record_output.json");
     let json_output = serde_json::json!({
         "name": name,
@@ -140,7 +192,10 @@ pub fn analyze_data(json_data: &str) {
         "timestamp": timestamp,
         "is_active": is_active
     });
-    if let Err(e) = write_to_file(&json_output.to_string(), file_path) {
+    let written = File::create(file_path)
+        .map_err(AnalyticsError::from)
+        .and_then(|file| export_ndjson(std::slice::from_ref(&batch), file, ExportCompression::None));
+    if let Err(e) = written {
         eprintln!("Error writing to file: {}", e);
     }
 
@@ -158,29 +213,8 @@ pub fn analyze_data(json_data: &str) {
         println!("Column {} Type: {:?}", i, column.data_type());
     }
 
-    // 10. Calculate uptime variance
-    let variance: f64 = uptime_col.iter()
-        .filter_map(|v| v)
-        .map(|&v| (v as f64 - avg_uptime).powi(2))
-        .sum::<f64>() / count as f64;
-    println!("Uptime Variance: {:.2}", variance);
-
-    // 11. Calculate uptime standard deviation
-    let std_dev = variance.sqrt();
-    println!("Uptime Standard Deviation: {:.2}", std_dev);
-
-    // 12. Create a summary report
-    let report = format!(
-        "Summary Report:\n\
-        - Total Uptime: {}\n\
-        - Average Uptime: {:.2}\n\
-        - Max Uptime: {}\n\
-        - Min Uptime: {}\n\
-        - Uptime Variance: {:.2}\n\
-        - Uptime Standard Deviation: {:.2}",
-        total_uptime, avg_uptime, max_uptime, min_uptime, variance, std_dev
-    );
-    println!("{}", report);
+    // 10-12. Variance, standard deviation and the summary report
+    let report = stats.report();
 
     // 13. Compare record against a threshold
     let threshold = 1000;
@@ -207,7 +241,7 @@ pub fn analyze_data(json_data: &str) {
         <timestamp>{}</timestamp>\n\
         <is_active>{}</is_active>\n\
         </record>",
-        name, status, uptime, timestamp, is_active
+        xml_escape(&name), xml_escape(&status), uptime, timestamp, is_active
     );
     println!("XML Output:\n{}", xml_output);
 
@@ -397,10 +431,10 @@ pub fn analyze_data(json_data: &str) {
     println!("Data Types Summary: {}", data_types_summary);
 
     // 51. Analyze record for anomalies
-    let anomalies = if uptime < 1000 {
-        "Anomaly detected: Low uptime"
+    let anomalies = if stats.anomalies.is_empty() {
+        "No anomalies detected".to_string()
     } else {
-        "No anomalies detected"
+        format!("Anomalies detected: {:?}", stats.anomalies)
     };
     println!("{}", anomalies);
 
@@ -424,7 +458,7 @@ pub fn analyze_data(json_data: &str) {
         "| Name | Status | Uptime | Timestamp | Active |\n\
         |------|--------|--------|-----------|--------|\n\
         "| {} | {} | {} | {} | {} |\n",
-        name, status, uptime, timestamp, is_active
+        markdown_cell(&name), markdown_cell(&status), uptime, timestamp, is_active
     );
     println!("Markdown Table:\n{}", markdown_table);
 
@@ -458,6 +492,278 @@ pub fn analyze_data(json_data: &str) {
     println!("JSON Data with Timestamp:\n{}", json_with_timestamp);
 }
 
+// Build a single-row telemetry batch from validated JSON fields
+pub fn json_record_batch(name: &str, status: &str, uptime: i64, timestamp: i64, is_active: bool) -> Result<RecordBatch, AnalyticsError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(vec![name])),
+        Arc::new(StringArray::from(vec![status])),
+        Arc::new(Int64Array::from(vec![uptime])),
+        Arc::new(TimestampSecondArray::from(vec![timestamp])),
+        Arc::new(BooleanArray::from(vec![is_active])),
+    ];
+    Ok(RecordBatch::try_new(telemetry_schema(), columns)?)
+}
+
+// Analyze CSV telemetry with a header row; column types are inferred and then checked
+pub fn analyze_csv(mut reader: impl Read) -> Result<UptimeStats, AnalyticsError> {
+    // Inference consumes the input, so buffer it to read it a second time
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    let format = Format::default().with_header(true);
+    let (schema, _) = format.infer_schema(Cursor::new(&data), None)?;
+    let schema = Arc::new(schema);
+    let csv_reader = ReaderBuilder::new(schema.clone())
+        .with_format(format)
+        .build(Cursor::new(&data))?;
+    let batches = csv_reader.collect::<Result<Vec<_>, _>>()?;
+
+    analyze_batch(&concat_batches(&schema, &batches)?)
+}
+
+// Analyze telemetry stored in a Parquet file
+pub fn analyze_parquet(path: &Path) -> Result<UptimeStats, AnalyticsError> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    let schema = builder.schema().clone();
+    let batches = builder.build()?.collect::<Result<Vec<_>, _>>()?;
+
+    analyze_batch(&concat_batches(&schema, &batches)?)
+}
+
+// Write a batch to `path` as Parquet
+pub fn save_batch_to_parquet(batch: &RecordBatch, path: &Path) -> Result<(), AnalyticsError> {
+    let mut writer = ArrowWriter::try_new(File::create(path)?, batch.schema(), None)?;
+    writer.write(batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+// Whether an export is written as-is or gzip-compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportCompression {
+    #[default]
+    None,
+    Gzip,
+}
+
+// Buffered export output, compressed on the way through if asked for
+enum ExportWriter<W: Write> {
+    Plain(BufWriter<W>),
+    Gzip(GzEncoder<BufWriter<W>>),
+}
+
+impl<W: Write> ExportWriter<W> {
+    fn new(w: W, compression: ExportCompression) -> Self {
+        let buffered = BufWriter::new(w);
+        match compression {
+            ExportCompression::None => ExportWriter::Plain(buffered),
+            ExportCompression::Gzip => ExportWriter::Gzip(GzEncoder::new(buffered, Compression::default())),
+        }
+    }
+
+    // Write out what is buffered, and the gzip trailer. Dropping the writer
+    // instead would swallow any error from doing so.
+    fn finish(self) -> io::Result<()> {
+        let mut buffered = match self {
+            ExportWriter::Plain(buffered) => buffered,
+            ExportWriter::Gzip(encoder) => encoder.finish()?,
+        };
+        buffered.flush()
+    }
+}
+
+impl<W: Write> Write for ExportWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ExportWriter::Plain(w) => w.write(buf),
+            ExportWriter::Gzip(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ExportWriter::Plain(w) => w.flush(),
+            ExportWriter::Gzip(w) => w.flush(),
+        }
+    }
+}
+
+// Export `batches` as newline-delimited JSON, one object per row. Rows are
+// written as they are read, so memory use is bounded by the largest batch.
+pub fn export_ndjson<W: Write>(batches: &[RecordBatch], w: W, compression: ExportCompression) -> Result<(), AnalyticsError> {
+    let mut out = ExportWriter::new(w, compression);
+    for batch in batches {
+        let batch = conform_to_schema(batch)?;
+        let (names, statuses, uptimes, timestamps, actives) = telemetry_columns(&batch);
+        for row in 0..batch.num_rows() {
+            let record = serde_json::json!({
+                "name": names.value(row),
+                "status": statuses.value(row),
+                "uptime": uptimes.value(row),
+                "timestamp": timestamps.value(row),
+                "is_active": actives.value(row),
+            });
+            serde_json::to_writer(&mut out, &record).map_err(io::Error::from)?;
+            out.write_all(b"\n")?;
+        }
+    }
+    out.finish()?;
+    Ok(())
+}
+
+// Export `batches` as CSV with a header row, in the layout `analyze_csv` reads.
+// Rows are written as they are read, like `export_ndjson`.
+pub fn export_csv<W: Write>(batches: &[RecordBatch], w: W, compression: ExportCompression) -> Result<(), AnalyticsError> {
+    let mut out = ExportWriter::new(w, compression);
+    let schema = telemetry_schema();
+    let header: Vec<&str> = schema.fields().iter().map(|field| field.name().as_str()).collect();
+    writeln!(out, "{}", header.join(","))?;
+    for batch in batches {
+        let batch = conform_to_schema(batch)?;
+        let (names, statuses, uptimes, timestamps, actives) = telemetry_columns(&batch);
+        for row in 0..batch.num_rows() {
+            writeln!(
+                out,
+                "{},{},{},{},{}",
+                csv_field(names.value(row)),
+                csv_field(statuses.value(row)),
+                uptimes.value(row),
+                timestamps.value(row),
+                actives.value(row)
+            )?;
+        }
+    }
+    out.finish()?;
+    Ok(())
+}
+
+// The typed columns of a batch already cast by `conform_to_schema`
+fn telemetry_columns(batch: &RecordBatch) -> (&StringArray, &StringArray, &Int64Array, &TimestampSecondArray, &BooleanArray) {
+    (
+        batch.column(0).as_any().downcast_ref().unwrap(),
+        batch.column(1).as_any().downcast_ref().unwrap(),
+        batch.column(2).as_any().downcast_ref().unwrap(),
+        batch.column(3).as_any().downcast_ref().unwrap(),
+        batch.column(4).as_any().downcast_ref().unwrap(),
+    )
+}
+
+// Quote a CSV field when it holds a delimiter, quote, or line break
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+// The statistics path every ingestion format goes through
+pub fn analyze_batch(batch: &RecordBatch) -> Result<UptimeStats, AnalyticsError> {
+    analyze_batch_with_threshold(batch, DEFAULT_Z_THRESHOLD)
+}
+
+// `analyze_batch`, flagging rows whose uptime z-score exceeds `z_threshold`
+pub fn analyze_batch_with_threshold(batch: &RecordBatch, z_threshold: f64) -> Result<UptimeStats, AnalyticsError> {
+    let batch = conform_to_schema(batch)?;
+    let stats = compute_stats(&batch, z_threshold);
+
+    println!("Total Uptime: {}", stats.total);
+    println!("Average Uptime: {:.2}", stats.average);
+    println!("Max Uptime: {}", stats.max);
+    println!("Min Uptime: {}", stats.min);
+    println!("Uptime Histogram: {:?}", stats.histogram);
+    println!("Uptime Anomalies: {:?}", stats.anomalies);
+    println!("{}", stats.report());
+    Ok(stats)
+}
+
+// Check that every telemetry column exists with a compatible type and cast it to the shared schema
+fn conform_to_schema(batch: &RecordBatch) -> Result<RecordBatch, AnalyticsError> {
+    let schema = telemetry_schema();
+    let mut columns = Vec::with_capacity(schema.fields().len());
+
+    for field in schema.fields() {
+        let index = batch
+            .schema()
+            .index_of(field.name())
+            .map_err(|_| AnalyticsError::MissingColumn(field.name().clone()))?;
+        let column = batch.column(index);
+
+        let compatible = match field.data_type() {
+            DataType::Utf8 => matches!(column.data_type(), DataType::Utf8 | DataType::LargeUtf8),
+            DataType::Int64 => column.data_type().is_integer(),
+            DataType::Timestamp(_, _) => matches!(
+                column.data_type(),
+                DataType::Int64 | DataType::Int32 | DataType::Timestamp(_, _)
+            ),
+            other => column.data_type() == other,
+        };
+        if !compatible {
+            return Err(AnalyticsError::IncompatibleType {
+                column: field.name().clone(),
+                expected: field.data_type().clone(),
+                found: column.data_type().clone(),
+            });
+        }
+
+        columns.push(cast(column, field.data_type())?);
+    }
+
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+fn compute_stats(batch: &RecordBatch, z_threshold: f64) -> UptimeStats {
+    let uptime_col = batch.column(2).as_any().downcast_ref::<Int64Array>().unwrap();
+    // Row index alongside each uptime, so anomalies point at the right row when some are null
+    let rows: Vec<(usize, i64)> =
+        uptime_col.iter().enumerate().filter_map(|(index, uptime)| Some((index, uptime?))).collect();
+    let values: Vec<i64> = rows.iter().map(|&(_, uptime)| uptime).collect();
+
+    let count = values.len();
+    let total: i64 = values.iter().sum();
+    let average = if count > 0 { total as f64 / count as f64 } else { 0.0 };
+    let variance = if count > 0 {
+        values.iter().map(|&v| (v as f64 - average).powi(2)).sum::<f64>() / count as f64
+    } else {
+        0.0
+    };
+
+    let std_dev = variance.sqrt();
+
+    // A constant column has no outliers, and no spread to divide by
+    let anomalies = if std_dev > 0.0 {
+        rows
+            .iter()
+            .map(|&(index, uptime)| Anomaly { index, uptime, z_score: (uptime as f64 - average) / std_dev })
+            .filter(|anomaly| anomaly.z_score.abs() > z_threshold)
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut histogram = BTreeMap::new();
+    for &value in &values {
+        *histogram.entry(value).or_insert(0) += 1;
+    }
+
+    UptimeStats {
+        count,
+        total,
+        average,
+        max: values.iter().copied().max().unwrap_or(0),
+        min: values.iter().copied().min().unwrap_or(0),
+        variance,
+        std_dev,
+        histogram,
+        anomalies,
+    }
+}
+
+// Markdown renderers pass inline HTML through, and a `|` would end the cell early
+fn markdown_cell(text: &str) -> String {
+    html_escape(text).replace('|', "\\|")
+}
+
 fn validate_data(data: &Value) -> bool {
     // Example validation logic (to be expanded)
     data.is_object()
@@ -479,4 +785,186 @@ fn append_to_file(content: &str, path: &Path) -> std::io::Result<()> {
     let mut file = OpenOptions::new().append(true).open(path)?;
     file.write_all(content.as_bytes())?;
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn json_batch() -> RecordBatch {
+        json_record_batch("server-1", "Active", 4200, 1_700_000_000, true).unwrap()
+    }
+
+    #[test]
+    fn test_parquet_round_trip_matches_json_stats() {
+        let batch = json_batch();
+        let expected = analyze_batch(&batch).unwrap();
+
+        let path = env::temp_dir().join("noxium_live_processor_round_trip.parquet");
+        save_batch_to_parquet(&batch, &path).unwrap();
+        let stats = analyze_parquet(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(stats, expected);
+        assert_eq!(stats.total, 4200);
+    }
+
+    #[test]
+    fn test_csv_matches_json_stats() {
+        let csv = "name,status,uptime,timestamp,is_active\nserver-1,Active,4200,1700000000,true\n";
+        assert_eq!(analyze_csv(csv.as_bytes()).unwrap(), analyze_batch(&json_batch()).unwrap());
+    }
+
+    #[test]
+    fn test_csv_aggregates_rows() {
+        let csv = "name,status,uptime,timestamp,is_active\n\
+                   a,Active,100,1700000000,true\n\
+                   b,Inactive,300,1700000060,false\n";
+        let stats = analyze_csv(csv.as_bytes()).unwrap();
+        assert_eq!(stats.count, 2);
+        assert_eq!(stats.total, 400);
+        assert_eq!((stats.min, stats.max), (100, 300));
+        assert_eq!(stats.average, 200.0);
+    }
+
+    // One telemetry row per uptime
+    fn uptime_batch(uptimes: &[i64]) -> RecordBatch {
+        let n = uptimes.len();
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from((0..n).map(|i| format!("server-{}", i)).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(vec!["Active"; n])),
+            Arc::new(Int64Array::from(uptimes.to_vec())),
+            Arc::new(TimestampSecondArray::from(vec![1_700_000_000; n])),
+            Arc::new(BooleanArray::from(vec![true; n])),
+        ];
+        RecordBatch::try_new(telemetry_schema(), columns).unwrap()
+    }
+
+    #[test]
+    fn test_outlier_is_flagged_by_z_score() {
+        let normal: Vec<i64> = (0..20).map(|i| 1000 + i % 5).collect();
+        let mut uptimes = normal.clone();
+        uptimes.insert(7, 50_000);
+        let stats = analyze_batch(&uptime_batch(&uptimes)).unwrap();
+
+        assert_eq!(stats.anomalies.len(), 1, "{:?}", stats.anomalies);
+        assert_eq!((stats.anomalies[0].index, stats.anomalies[0].uptime), (7, 50_000));
+        assert!(stats.anomalies[0].z_score > DEFAULT_Z_THRESHOLD);
+
+        // The tight distribution alone is clean, unless the threshold is lowered into its spread
+        assert!(analyze_batch(&uptime_batch(&normal)).unwrap().anomalies.is_empty());
+        assert!(!analyze_batch_with_threshold(&uptime_batch(&normal), 1.0).unwrap().anomalies.is_empty());
+    }
+
+    #[test]
+    fn test_anomaly_index_counts_null_rows() {
+        let mut uptimes: Vec<Option<i64>> = (0..20).map(|i| Some(1000 + i % 5)).collect();
+        uptimes[0] = None;
+        uptimes[7] = Some(50_000);
+        // `compute_stats` reads the uptime from the third column
+        let batch = RecordBatch::try_from_iter(vec![
+            ("name", Arc::new(StringArray::from(vec!["server"; 20])) as ArrayRef),
+            ("status", Arc::new(StringArray::from(vec!["Active"; 20])) as ArrayRef),
+            ("uptime", Arc::new(Int64Array::from(uptimes)) as ArrayRef),
+        ])
+        .unwrap();
+
+        let stats = compute_stats(&batch, DEFAULT_Z_THRESHOLD);
+        assert_eq!(stats.count, 19);
+        assert_eq!(stats.anomalies.len(), 1, "{:?}", stats.anomalies);
+        assert_eq!((stats.anomalies[0].index, stats.anomalies[0].uptime), (7, 50_000));
+    }
+
+    #[test]
+    fn test_constant_uptime_has_no_anomalies() {
+        let stats = analyze_batch(&uptime_batch(&[500; 10])).unwrap();
+        assert_eq!(stats.std_dev, 0.0);
+        assert!(stats.anomalies.is_empty());
+        assert!(analyze_batch(&json_batch()).unwrap().anomalies.is_empty());
+    }
+
+    // Rows whose names need quoting in CSV
+    fn export_batch() -> RecordBatch {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec!["server-1", "db, primary", "say \"hi\""])),
+            Arc::new(StringArray::from(vec!["Active", "Inactive", "Active"])),
+            Arc::new(Int64Array::from(vec![4200, 15, 9000])),
+            Arc::new(TimestampSecondArray::from(vec![1_700_000_000, 1_700_000_060, 1_700_000_120])),
+            Arc::new(BooleanArray::from(vec![true, false, true])),
+        ];
+        RecordBatch::try_new(telemetry_schema(), columns).unwrap()
+    }
+
+    #[test]
+    fn test_ndjson_export_reads_back() {
+        let batch = export_batch();
+        let mut out = Vec::new();
+        export_ndjson(&[batch.clone(), uptime_batch(&[7])], &mut out, ExportCompression::None).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        let rows = text
+            .lines()
+            .map(|line| {
+                let row: Value = serde_json::from_str(line).unwrap();
+                let field = |name: &str| row[name].as_str().unwrap().to_string();
+                json_record_batch(
+                    &field("name"),
+                    &field("status"),
+                    row["uptime"].as_i64().unwrap(),
+                    row["timestamp"].as_i64().unwrap(),
+                    row["is_active"].as_bool().unwrap(),
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(concat_batches(&telemetry_schema(), &rows[..3]).unwrap(), batch);
+        assert_eq!(rows[3], uptime_batch(&[7]));
+    }
+
+    #[test]
+    fn test_csv_export_quotes_fields_and_reads_back() {
+        let batch = export_batch();
+        let mut out = Vec::new();
+        export_csv(std::slice::from_ref(&batch), &mut out, ExportCompression::None).unwrap();
+
+        let text = String::from_utf8(out.clone()).unwrap();
+        assert_eq!(text.lines().next(), Some("name,status,uptime,timestamp,is_active"));
+        assert_eq!(text.lines().nth(2), Some("\"db, primary\",Inactive,15,1700000060,false"));
+        assert_eq!(text.lines().nth(3), Some("\"say \"\"hi\"\"\",Active,9000,1700000120,true"));
+        assert_eq!(analyze_csv(out.as_slice()).unwrap(), analyze_batch(&batch).unwrap());
+    }
+
+    #[test]
+    fn test_gzip_export_decompresses_to_plain_export() {
+        let batches = [export_batch(), uptime_batch(&[100, 200])];
+        let check = |export: &dyn Fn(&mut Vec<u8>, ExportCompression)| {
+            let (mut plain, mut gzipped) = (Vec::new(), Vec::new());
+            export(&mut plain, ExportCompression::None);
+            export(&mut gzipped, ExportCompression::Gzip);
+
+            let mut decompressed = Vec::new();
+            flate2::read::GzDecoder::new(gzipped.as_slice()).read_to_end(&mut decompressed).unwrap();
+            assert_eq!(decompressed, plain);
+            assert_ne!(gzipped, plain);
+        };
+        check(&|out, compression| export_ndjson(&batches, out, compression).unwrap());
+        check(&|out, compression| export_csv(&batches, out, compression).unwrap());
+    }
+
+    #[test]
+    fn test_csv_missing_column() {
+        let csv = "name,status,timestamp,is_active\nserver-1,Active,1700000000,true\n";
+        assert!(matches!(analyze_csv(csv.as_bytes()), Err(AnalyticsError::MissingColumn(c)) if c == "uptime"));
+    }
+
+    #[test]
+    fn test_csv_incompatible_type() {
+        let csv = "name,status,uptime,timestamp,is_active\nserver-1,Active,lots,1700000000,true\n";
+        assert!(matches!(
+            analyze_csv(csv.as_bytes()),
+            Err(AnalyticsError::IncompatibleType { column, .. }) if column == "uptime"
+        ));
+    }
+}
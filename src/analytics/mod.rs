@@ -1,2 +1,3 @@
 pub mod data_analysis;
 pub mod live_processor;
+pub mod real_time_processing;
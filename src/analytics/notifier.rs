@@ -0,0 +1,243 @@
+use async_trait::async_trait;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde_json::{json, Value};
+use std::env;
+use thiserror::Error;
+
+#[path = "../template_engine.rs"]
+#[allow(dead_code)]
+mod template_engine;
+use template_engine::{Template, TemplateError};
+
+#[path = "../http.rs"]
+#[allow(dead_code)]
+mod http;
+
+// Errors from rendering or delivering a notification
+#[derive(Debug, Error)]
+pub enum NotifyError {
+    #[error("Invalid notifier configuration: {0}")]
+    Config(String),
+    #[error("Template error: {0}")]
+    Template(#[from] TemplateError),
+    #[error("Webhook request failed: {0}")]
+    Webhook(#[from] reqwest::Error),
+    #[error("Invalid email: {0}")]
+    Email(String),
+    #[error("SMTP error: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+}
+
+// Where pipeline notifications go; picked at startup by `notifier_from_env`
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn send(&self, subject: &str, body: &str) -> Result<(), NotifyError>;
+}
+
+// Writes notifications to the log, at info level, instead of delivering them
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn send(&self, subject: &str, body: &str) -> Result<(), NotifyError> {
+        log::info!("Notification: {}\n{}", subject, body);
+        Ok(())
+    }
+}
+
+// POSTs `{"subject": ..., "body": ...}` as JSON to a webhook URL, with the
+// shared client's timeouts so a hung endpoint fails the attempt rather than
+// stalling retries
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: impl Into<String>) -> Self {
+        // The URL is the operator's, not a user's, so it may be on the private network
+        let fetch_policy = http::FetchPolicy { allow_private: true, ..http::FetchPolicy::default() };
+        let client = http::client_with(&http::HttpConfig { fetch_policy, ..http::HttpConfig::from_env() });
+        WebhookNotifier { client, url: url.into() }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    async fn send(&self, subject: &str, body: &str) -> Result<(), NotifyError> {
+        self.client
+            .post(&self.url)
+            .json(&json!({ "subject": subject, "body": body }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+// Sends plain-text email through an SMTP relay over STARTTLS
+pub struct SmtpNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl SmtpNotifier {
+    pub fn new(
+        host: &str,
+        port: Option<u16>,
+        credentials: Option<(String, String)>,
+        from: &str,
+        to: &[String],
+    ) -> Result<Self, NotifyError> {
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?;
+        if let Some(port) = port {
+            builder = builder.port(port);
+        }
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        let to = to.iter().map(|address| parse_mailbox(address)).collect::<Result<Vec<_>, _>>()?;
+        if to.is_empty() {
+            return Err(NotifyError::Config("SMTP notifier needs at least one recipient".to_string()));
+        }
+        Ok(SmtpNotifier { transport: builder.build(), from: parse_mailbox(from)?, to })
+    }
+}
+
+fn parse_mailbox(address: &str) -> Result<Mailbox, NotifyError> {
+    address.trim().parse().map_err(|e| NotifyError::Email(format!("{:?}: {}", address, e)))
+}
+
+#[async_trait]
+impl Notifier for SmtpNotifier {
+    async fn send(&self, subject: &str, body: &str) -> Result<(), NotifyError> {
+        let mut message = Message::builder().from(self.from.clone()).subject(subject).header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        let message = message.body(body.to_string()).map_err(|e| NotifyError::Email(e.to_string()))?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+fn required_env(name: &str) -> Result<String, NotifyError> {
+    env::var(name).map_err(|_| NotifyError::Config(format!("{} is not set", name)))
+}
+
+// Build the notifier named by NOTIFIER: "log" (the default), "webhook" or "smtp".
+//
+// - webhook: NOTIFY_WEBHOOK_URL
+// - smtp: SMTP_HOST, optional SMTP_PORT, SMTP_USERNAME and SMTP_PASSWORD,
+//   NOTIFY_FROM, and a comma-separated NOTIFY_TO
+pub fn notifier_from_env() -> Result<Box<dyn Notifier>, NotifyError> {
+    match env::var("NOTIFIER").unwrap_or_default().trim() {
+        "" | "log" => Ok(Box::new(LogNotifier)),
+        "webhook" => Ok(Box::new(WebhookNotifier::new(required_env("NOTIFY_WEBHOOK_URL")?))),
+        "smtp" => {
+            let port = match env::var("SMTP_PORT") {
+                Ok(port) => Some(
+                    port.trim().parse().map_err(|_| NotifyError::Config(format!("Invalid SMTP_PORT {:?}", port)))?,
+                ),
+                Err(_) => None,
+            };
+            let credentials = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+                (Ok(username), Ok(password)) => Some((username, password)),
+                _ => None,
+            };
+            let to: Vec<String> = required_env("NOTIFY_TO")?.split(',').map(str::to_string).collect();
+            let notifier =
+                SmtpNotifier::new(&required_env("SMTP_HOST")?, port, credentials, &required_env("NOTIFY_FROM")?, &to)?;
+            Ok(Box::new(notifier))
+        }
+        other => Err(NotifyError::Config(format!("Unknown NOTIFIER {:?}", other))),
+    }
+}
+
+// Render a notification body; templates are plain text, so use `{{{ }}}` for values
+pub fn render_body(template: &str, context: &Value) -> Result<String, NotifyError> {
+    Ok(Template::parse(template)?.render(context))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use warp::Filter;
+
+    // Accept webhook POSTs on an ephemeral port, keeping every JSON payload
+    fn mock_webhook() -> (SocketAddr, Arc<Mutex<Vec<Value>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        let route = warp::post().and(warp::path("hook")).and(warp::body::json()).map(move |payload: Value| {
+            sink.lock().unwrap().push(payload);
+            warp::reply()
+        });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (addr, received)
+    }
+
+    #[tokio::test]
+    async fn test_webhook_posts_subject_and_rendered_body() {
+        let (addr, received) = mock_webhook();
+        let notifier = WebhookNotifier::new(format!("http://{}/hook", addr));
+
+        let body =
+            render_body("{{{ count }}} anomalies in {{{ batch }}}", &json!({ "count": 2, "batch": "a&b" })).unwrap();
+        notifier.send("Uptime anomalies", &body).await.unwrap();
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![json!({ "subject": "Uptime anomalies", "body": "2 anomalies in a&b" })]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_webhook_error_status_is_reported() {
+        let route = warp::any().map(|| warp::reply::with_status("", warp::http::StatusCode::BAD_GATEWAY));
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let result = WebhookNotifier::new(format!("http://{}/hook", addr)).send("subject", "body").await;
+        assert!(matches!(result, Err(NotifyError::Webhook(_))), "{:?}", result);
+    }
+
+    // Keeps every message logged in this test binary
+    struct CaptureLogger;
+
+    static CAPTURED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            CAPTURED.lock().unwrap().push(format!("{} {}", record.level(), record.args()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[tokio::test]
+    async fn test_log_notifier_logs_the_rendered_body() {
+        static LOGGER: CaptureLogger = CaptureLogger;
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Info);
+
+        let context = json!({ "batch": "a&b", "rows": [{ "index": 3 }, { "index": 9 }] });
+        let body = render_body("Rows in {{{ batch }}}:\n{{#each rows}}- {{{ index }}}\n{{/each}}", &context).unwrap();
+        LogNotifier.send("Uptime anomalies", &body).await.unwrap();
+
+        // Other tests' servers and clients may log too
+        let captured = CAPTURED.lock().unwrap();
+        let notifications: Vec<&String> = captured.iter().filter(|line| line.contains("Notification")).collect();
+        assert_eq!(notifications, ["INFO Notification: Uptime anomalies\nRows in a&b:\n- 3\n- 9\n"]);
+    }
+}
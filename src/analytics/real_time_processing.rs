@@ -0,0 +1,158 @@
+use crossbeam_channel::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+// A batch of raw records queued for real-time processing
+#[derive(Debug, Clone)]
+pub struct RecordBatch {
+    pub data: String,
+    pub created_at: Instant,
+}
+
+// Build a batch from a raw payload
+pub fn create_record_batch(data: &str) -> RecordBatch {
+    RecordBatch {
+        data: data.to_string(),
+        created_at: Instant::now(),
+    }
+}
+
+// Create the channel batches are sent through. Receivers can be cloned, so
+// several processors can drain the same channel, each batch going to one of them.
+pub fn start_real_time_processing() -> (Sender<RecordBatch>, Receiver<RecordBatch>) {
+    crossbeam_channel::unbounded()
+}
+
+// What every processor sharing a `ProcessingTotals` handled between them
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ProcessingTotals {
+    pub batches: usize,
+    pub bytes: usize,
+}
+
+// Consumes batches from the channel until every sender has been dropped
+pub struct RealTimeProcessor {
+    receiver: Receiver<RecordBatch>,
+    processed: usize,
+    totals: Arc<Mutex<ProcessingTotals>>,
+}
+
+impl RealTimeProcessor {
+    pub fn new(receiver: Receiver<RecordBatch>) -> Self {
+        Self::with_totals(receiver, Arc::default())
+    }
+
+    // A processor that also adds each batch it handles to `totals`
+    pub fn with_totals(receiver: Receiver<RecordBatch>, totals: Arc<Mutex<ProcessingTotals>>) -> Self {
+        RealTimeProcessor {
+            receiver,
+            processed: 0,
+            totals,
+        }
+    }
+
+    // Process batches until the channel closes and return how many were handled.
+    // Blocking on the receiver keeps the worker alive for as long as any sender exists.
+    pub fn process_data(&mut self) -> usize {
+        while let Ok(batch) = self.receiver.recv() {
+            self.process_batch(&batch);
+        }
+        println!("[INFO]: Real-time processor drained, {} batches processed", self.processed);
+        self.processed
+    }
+
+    fn process_batch(&mut self, batch: &RecordBatch) {
+        self.processed += 1;
+        {
+            let mut totals = self.totals.lock().unwrap();
+            totals.batches += 1;
+            totals.bytes += batch.data.len();
+        }
+        println!(
+            "[INFO]: Processing batch {} ({} bytes, queued for {:?})",
+            self.processed,
+            batch.data.len(),
+            batch.created_at.elapsed()
+        );
+    }
+
+    pub fn processed(&self) -> usize {
+        self.processed
+    }
+}
+
+// A fixed number of processors draining the same channel on their own threads,
+// so a slow batch holds up only the worker handling it
+pub struct ProcessorPool {
+    workers: Vec<JoinHandle<usize>>,
+    totals: Arc<Mutex<ProcessingTotals>>,
+}
+
+impl ProcessorPool {
+    // Starts `size` workers, at least one, on `receiver`
+    pub fn spawn(receiver: Receiver<RecordBatch>, size: usize) -> Self {
+        let totals = Arc::new(Mutex::new(ProcessingTotals::default()));
+        let workers = (0..size.max(1))
+            .map(|_| {
+                let mut processor = RealTimeProcessor::with_totals(receiver.clone(), Arc::clone(&totals));
+                thread::spawn(move || processor.process_data())
+            })
+            .collect();
+        ProcessorPool { workers, totals }
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    // Waits for every worker to drain the channel, which happens once every
+    // sender has been dropped, and returns the final totals. Fails if a worker panicked.
+    pub fn join(self) -> thread::Result<ProcessingTotals> {
+        for worker in self.workers {
+            worker.join()?;
+        }
+        Ok(self.totals.lock().unwrap().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_processor_observes_every_batch() {
+        let (tx, rx) = start_real_time_processing();
+        let worker = thread::spawn(move || RealTimeProcessor::new(rx).process_data());
+
+        let batch_count = 25;
+        for i in 0..batch_count {
+            tx.send(create_record_batch(&format!("batch {}", i))).unwrap();
+        }
+        drop(tx);
+
+        let processed = worker.join().expect("Processor thread panicked");
+        assert_eq!(processed, batch_count);
+    }
+
+    #[test]
+    fn test_pool_processes_every_batch_exactly_once() {
+        let (tx, rx) = start_real_time_processing();
+        let pool = ProcessorPool::spawn(rx, 4);
+        assert_eq!(pool.size(), 4);
+
+        // Batches of different sizes, so a lost or doubled one changes the byte total
+        let batch_count = 1000;
+        let mut bytes = 0;
+        for i in 0..batch_count {
+            let batch = create_record_batch(&"x".repeat(i % 17 + 1));
+            bytes += batch.data.len();
+            tx.send(batch).unwrap();
+        }
+        drop(tx);
+
+        let totals = pool.join().expect("Processor thread panicked");
+        assert_eq!(totals, ProcessingTotals { batches: batch_count, bytes });
+    }
+}
@@ -0,0 +1,528 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::notifier::{Notifier, NotifyError};
+use crate::rate_limit::{RateLimiter, RetryAfter};
+
+// How failed deliveries are retried
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    // Attempts per delivery, counting the first one
+    pub max_attempts: u32,
+    // Wait before the first retry, doubled after each failed one up to `max_delay`
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // Deliveries waiting for a retry; the oldest is dead-lettered when a new one would exceed it
+    pub capacity: usize,
+    // Retries started in any one second, so a recovering endpoint isn't hit with the whole backlog at once
+    pub max_per_second: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(300),
+            capacity: 1000,
+            max_per_second: 10,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // NOTIFY_RETRY_MAX_ATTEMPTS, NOTIFY_RETRY_BASE_DELAY_MS, NOTIFY_RETRY_MAX_DELAY_MS,
+    // NOTIFY_RETRY_CAPACITY and NOTIFY_RETRY_MAX_PER_SECOND, each falling back to the
+    // default when unset or invalid
+    pub fn from_env() -> Self {
+        let defaults = RetryPolicy::default();
+        RetryPolicy {
+            max_attempts: env_number("NOTIFY_RETRY_MAX_ATTEMPTS", defaults.max_attempts),
+            base_delay: Duration::from_millis(env_number(
+                "NOTIFY_RETRY_BASE_DELAY_MS",
+                defaults.base_delay.as_millis() as u64,
+            )),
+            max_delay: Duration::from_millis(env_number(
+                "NOTIFY_RETRY_MAX_DELAY_MS",
+                defaults.max_delay.as_millis() as u64,
+            )),
+            capacity: env_number("NOTIFY_RETRY_CAPACITY", defaults.capacity),
+            max_per_second: env_number("NOTIFY_RETRY_MAX_PER_SECOND", defaults.max_per_second),
+        }
+    }
+
+    // Wait after the `attempts`-th failed attempt
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+fn env_number<T: std::str::FromStr + std::fmt::Display + Copy + PartialOrd + From<u8>>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => match value.trim().parse() {
+            Ok(value) if value > T::from(0) => value,
+            _ => {
+                eprintln!("[WARNING]: Ignoring invalid {} {:?}, using {}", name, value, default);
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+// A notification that hasn't been delivered yet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Delivery {
+    pub subject: String,
+    pub body: String,
+    pub attempts: u32,
+    pub last_error: String,
+}
+
+// One line of the dead-letter log
+#[derive(Debug, Serialize)]
+struct DeadLetter<'a> {
+    #[serde(flatten)]
+    delivery: &'a Delivery,
+    reason: &'a str,
+    at: String,
+}
+
+struct Pending {
+    delivery: Delivery,
+    due: Instant,
+}
+
+// Sends through another notifier, keeping deliveries that fail for a retry
+// instead of dropping them.
+//
+// `send` makes the first attempt and queues the delivery if it fails;
+// `retry_due` (or `run`, which calls it periodically) makes the later ones,
+// with exponential backoff and at most `max_per_second` a second. A delivery
+// that fails `max_attempts` times, or is pushed out of a full queue, goes to
+// the dead-letter log: an error line on stderr, plus a JSON line in the
+// dead-letter file if one is set.
+//
+// The queue lives in memory. With a spill file, `spill` writes it out on
+// shutdown, retries still being sent included, and the next `RetryQueue`
+// picks it up again.
+pub struct RetryQueue {
+    notifier: Box<dyn Notifier>,
+    policy: RetryPolicy,
+    limiter: RateLimiter,
+    pending: Mutex<VecDeque<Pending>>,
+    // Retries being sent, by a number `retry_due` gave them; `spill` takes these too
+    in_flight: Mutex<HashMap<u64, Delivery>>,
+    next_id: AtomicU64,
+    spill_file: Option<PathBuf>,
+    dead_letter_file: Option<PathBuf>,
+}
+
+impl RetryQueue {
+    pub fn new(notifier: Box<dyn Notifier>, policy: RetryPolicy) -> Self {
+        RetryQueue {
+            notifier,
+            limiter: RateLimiter::sliding_window(policy.max_per_second, Duration::from_secs(1)),
+            policy,
+            pending: Mutex::new(VecDeque::new()),
+            in_flight: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            spill_file: None,
+            dead_letter_file: None,
+        }
+    }
+
+    // Wraps `notifier` using `RetryPolicy::from_env`, NOTIFY_RETRY_SPILL_FILE and
+    // NOTIFY_DEAD_LETTER_FILE
+    pub fn from_env(notifier: Box<dyn Notifier>) -> Self {
+        let mut queue = RetryQueue::new(notifier, RetryPolicy::from_env());
+        if let Ok(path) = env::var("NOTIFY_DEAD_LETTER_FILE") {
+            queue = queue.with_dead_letter_file(path);
+        }
+        if let Ok(path) = env::var("NOTIFY_RETRY_SPILL_FILE") {
+            queue = queue.with_spill_file(path);
+        }
+        queue
+    }
+
+    pub fn with_dead_letter_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.dead_letter_file = Some(path.into());
+        self
+    }
+
+    // Loads whatever an earlier `spill` left in `path`; the deliveries are due right away.
+    // The file stays until the next `spill` replaces it, so a crash before then loses nothing
+    pub fn with_spill_file(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+                    match serde_json::from_str::<Delivery>(line) {
+                        Ok(delivery) => self.enqueue(delivery, Instant::now()),
+                        Err(e) => eprintln!("[ERROR]: Skipping unreadable delivery in {}: {}", path.display(), e),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("[ERROR]: Failed to read {}: {}", path.display(), e),
+        }
+        self.spill_file = Some(path);
+        self
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    // Retries every delivery whose backoff has passed, as far as the rate limit allows,
+    // returning how many were delivered. The rest wait for the limit to free up
+    pub async fn retry_due(&self) -> usize {
+        let now = Instant::now();
+        let due: Vec<(u64, Delivery)> = {
+            // Moved over under both locks, so `spill` always finds a delivery in one of them
+            let mut pending = self.pending.lock().unwrap();
+            let mut in_flight = self.in_flight.lock().unwrap();
+            let mut due = Vec::new();
+            let mut waiting = VecDeque::with_capacity(pending.len());
+            let mut limited_until = None;
+            for mut entry in pending.drain(..) {
+                if entry.due <= now && limited_until.is_none() {
+                    match self.limiter.check("retries") {
+                        Ok(()) => {
+                            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                            in_flight.insert(id, entry.delivery.clone());
+                            due.push((id, entry.delivery));
+                            continue;
+                        }
+                        Err(RetryAfter(wait)) => limited_until = Some(now + wait),
+                    }
+                }
+                if let Some(until) = limited_until.filter(|_| entry.due <= now) {
+                    entry.due = until;
+                }
+                waiting.push_back(entry);
+            }
+            *pending = waiting;
+            due
+        };
+
+        let mut delivered = 0;
+        for (id, delivery) in due {
+            let result = self.notifier.send(&delivery.subject, &delivery.body).await;
+            // Gone if `spill` saved it meanwhile; the spill file has it then
+            if self.in_flight.lock().unwrap().remove(&id).is_none() {
+                continue;
+            }
+            match result {
+                Ok(()) => delivered += 1,
+                Err(e) => self.failed(delivery, e),
+            }
+        }
+        delivered
+    }
+
+    // Calls `retry_due` every `interval`, for the lifetime of the program
+    pub async fn run(self: Arc<Self>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            self.retry_due().await;
+        }
+    }
+
+    // Writes the queue, retries still being sent included, to the spill file if there is
+    // one, leaving it empty. What the file held before is replaced, or removed when there
+    // is nothing left to save
+    pub fn spill(&self) -> std::io::Result<()> {
+        let Some(path) = &self.spill_file else {
+            return Ok(());
+        };
+        let deliveries: Vec<Delivery> = {
+            let mut pending = self.pending.lock().unwrap();
+            let mut in_flight: Vec<(u64, Delivery)> = self.in_flight.lock().unwrap().drain().collect();
+            in_flight.sort_by_key(|(id, _)| *id);
+            let pending = pending.drain(..).map(|entry| entry.delivery);
+            in_flight.into_iter().map(|(_, delivery)| delivery).chain(pending).collect()
+        };
+        if deliveries.is_empty() {
+            return match fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        let mut lines = String::new();
+        for delivery in &deliveries {
+            lines.push_str(&serde_json::to_string(delivery)?);
+            lines.push('\n');
+        }
+        // Written aside and renamed over, so a failed write leaves the previous file whole
+        let mut partial = path.clone().into_os_string();
+        partial.push(".partial");
+        fs::write(&partial, lines)?;
+        fs::rename(&partial, path)?;
+        println!("[INFO]: Saved {} undelivered notifications to {}", deliveries.len(), path.display());
+        Ok(())
+    }
+
+    fn failed(&self, mut delivery: Delivery, error: NotifyError) {
+        delivery.attempts += 1;
+        delivery.last_error = error.to_string();
+        if delivery.attempts >= self.policy.max_attempts {
+            self.dead_letter(&delivery, "retries exhausted");
+            return;
+        }
+        let wait = self.policy.backoff(delivery.attempts);
+        eprintln!(
+            "[WARNING]: Notification '{}' failed (attempt {}), retrying in {:?}: {}",
+            delivery.subject, delivery.attempts, wait, error
+        );
+        self.enqueue(delivery, Instant::now() + wait);
+    }
+
+    fn enqueue(&self, delivery: Delivery, due: Instant) {
+        let dropped = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.push_back(Pending { delivery, due });
+            if pending.len() > self.policy.capacity {
+                pending.pop_front()
+            } else {
+                None
+            }
+        };
+        if let Some(oldest) = dropped {
+            self.dead_letter(&oldest.delivery, "retry queue full");
+        }
+    }
+
+    fn dead_letter(&self, delivery: &Delivery, reason: &str) {
+        eprintln!(
+            "[ERROR]: Giving up on notification '{}' after {} attempts ({}): {}",
+            delivery.subject, delivery.attempts, reason, delivery.last_error
+        );
+        let Some(path) = &self.dead_letter_file else {
+            return;
+        };
+        let letter = DeadLetter { delivery, reason, at: chrono::Utc::now().to_rfc3339() };
+        let result = serde_json::to_string(&letter).map_err(std::io::Error::from).and_then(|line| {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", line)
+        });
+        if let Err(e) = result {
+            eprintln!("[ERROR]: Failed to write dead letter to {}: {}", path.display(), e);
+        }
+    }
+}
+
+// The first attempt is made right away. A failed one is queued rather than
+// returned, so `Ok` means the notification was delivered or will be retried
+#[async_trait]
+impl Notifier for RetryQueue {
+    async fn send(&self, subject: &str, body: &str) -> Result<(), NotifyError> {
+        if let Err(e) = self.notifier.send(subject, body).await {
+            let delivery = Delivery {
+                subject: subject.to_string(),
+                body: body.to_string(),
+                attempts: 0,
+                last_error: String::new(),
+            };
+            self.failed(delivery, e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Fails the first `failures` sends, then delivers, counting every attempt
+    struct FlakyNotifier {
+        failures: usize,
+        attempts: Arc<AtomicUsize>,
+        delivered: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Notifier for FlakyNotifier {
+        async fn send(&self, subject: &str, _body: &str) -> Result<(), NotifyError> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(NotifyError::Config("webhook unreachable".to_string()));
+            }
+            self.delivered.lock().unwrap().push(subject.to_string());
+            Ok(())
+        }
+    }
+
+    fn flaky(failures: usize) -> (Box<FlakyNotifier>, Arc<AtomicUsize>, Arc<Mutex<Vec<String>>>) {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        (
+            Box::new(FlakyNotifier { failures, attempts: attempts.clone(), delivered: delivered.clone() }),
+            attempts,
+            delivered,
+        )
+    }
+
+    fn policy(max_attempts: u32, capacity: usize) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(20),
+            max_delay: Duration::from_millis(50),
+            capacity,
+            max_per_second: 100,
+        }
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("noxium-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        let policy = policy(10, 10);
+        let waits: Vec<u64> = (1..=4).map(|attempts| policy.backoff(attempts).as_millis() as u64).collect();
+        assert_eq!(waits, vec![20, 40, 50, 50]);
+    }
+
+    #[tokio::test]
+    async fn test_delivery_is_retried_until_it_succeeds() {
+        let (notifier, attempts, delivered) = flaky(2);
+        let queue = RetryQueue::new(notifier, policy(5, 10));
+
+        queue.send("Uptime anomalies", "body").await.unwrap();
+        assert_eq!(queue.len(), 1);
+        // Still backing off
+        assert_eq!(queue.retry_due().await, 0);
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_millis(25)).await;
+        assert_eq!(queue.retry_due().await, 0);
+        assert_eq!(queue.len(), 1);
+
+        tokio::time::sleep(Duration::from_millis(45)).await;
+        assert_eq!(queue.retry_due().await, 1);
+        assert_eq!(queue.len(), 0);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(*delivered.lock().unwrap(), vec!["Uptime anomalies"]);
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_and_overflowing_deliveries_are_dead_lettered() {
+        let dead_letters = temp_file("dead-letters.jsonl");
+        let (notifier, attempts, delivered) = flaky(usize::MAX);
+        let queue = RetryQueue::new(notifier, policy(2, 1)).with_dead_letter_file(&dead_letters);
+
+        queue.send("first", "body").await.unwrap();
+        // Pushes "first" out of the one-slot queue
+        queue.send("second", "body").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(25)).await;
+        queue.retry_due().await;
+        assert_eq!(queue.len(), 0);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert!(delivered.lock().unwrap().is_empty());
+
+        let letters: Vec<serde_json::Value> = fs::read_to_string(&dead_letters)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        fs::remove_file(&dead_letters).unwrap();
+        assert_eq!(letters.len(), 2);
+        assert_eq!(letters[0]["subject"], "first");
+        assert_eq!(letters[0]["reason"], "retry queue full");
+        assert_eq!(letters[1]["subject"], "second");
+        assert_eq!(letters[1]["attempts"], 2);
+        assert_eq!(letters[1]["reason"], "retries exhausted");
+        assert_eq!(letters[1]["last_error"], "Invalid notifier configuration: webhook unreachable");
+    }
+
+    #[tokio::test]
+    async fn test_spilled_queue_is_picked_up_again() {
+        let spill = temp_file("retry-spill.jsonl");
+        let (notifier, _, _) = flaky(usize::MAX);
+        let queue = RetryQueue::new(notifier, policy(5, 10)).with_spill_file(&spill);
+        queue.send("Uptime anomalies", "body").await.unwrap();
+        queue.spill().unwrap();
+        assert_eq!(queue.len(), 0);
+
+        let (notifier, _, delivered) = flaky(0);
+        let queue = RetryQueue::new(notifier, policy(5, 10)).with_spill_file(&spill);
+        // Kept until the next spill in case this run dies first
+        assert!(spill.exists());
+        assert_eq!(queue.retry_due().await, 1);
+        assert_eq!(*delivered.lock().unwrap(), vec!["Uptime anomalies"]);
+        queue.spill().unwrap();
+        assert!(!spill.exists());
+    }
+
+    #[tokio::test]
+    async fn test_retries_are_rate_limited() {
+        let (notifier, attempts, delivered) = flaky(3);
+        let queue = RetryQueue::new(notifier, RetryPolicy { max_per_second: 2, ..policy(5, 10) });
+        for subject in ["first", "second", "third"] {
+            queue.send(subject, "body").await.unwrap();
+        }
+
+        tokio::time::sleep(Duration::from_millis(25)).await;
+        assert_eq!(queue.retry_due().await, 2);
+        assert_eq!(queue.retry_due().await, 0);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(attempts.load(Ordering::SeqCst), 5);
+        assert_eq!(*delivered.lock().unwrap(), vec!["first", "second"]);
+    }
+
+    // Fails the first attempt right away and every later one once released
+    struct StuckNotifier {
+        attempts: AtomicUsize,
+        release: Arc<tokio::sync::Notify>,
+    }
+
+    #[async_trait]
+    impl Notifier for StuckNotifier {
+        async fn send(&self, _subject: &str, _body: &str) -> Result<(), NotifyError> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) > 0 {
+                self.release.notified().await;
+            }
+            Err(NotifyError::Config("webhook unreachable".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spill_saves_retries_still_being_sent() {
+        let spill = temp_file("retry-in-flight.jsonl");
+        let release = Arc::new(tokio::sync::Notify::new());
+        let notifier = Box::new(StuckNotifier { attempts: AtomicUsize::new(0), release: release.clone() });
+        let queue = Arc::new(RetryQueue::new(notifier, policy(5, 10)).with_spill_file(&spill));
+        queue.send("Uptime anomalies", "body").await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(25)).await;
+        let retrying = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.retry_due().await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        queue.spill().unwrap();
+        let saved = fs::read_to_string(&spill).unwrap();
+        fs::remove_file(&spill).unwrap();
+        assert!(saved.contains("Uptime anomalies"), "{}", saved);
+
+        // The spill file has it now, so the failed retry doesn't queue it again
+        release.notify_one();
+        assert_eq!(retrying.await.unwrap(), 0);
+        assert_eq!(queue.len(), 0);
+    }
+}
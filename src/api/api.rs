@@ -1,136 +1,243 @@
 use warp::Filter;
 use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
 use uuid::Uuid;
-use std::sync::{Arc, RwLock};
-use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use log::info;
+use warp::http::StatusCode;
 
-// Define the Item struct for our API
-#[derive(Serialize, Deserialize, Clone)]
-struct Item {
-    id: Uuid,
-    name: String,
-}
+#[path = "../error.rs"]
+mod error;
+use error::{handle_rejection, NoxiumError};
 
-// In-memory database to hold items
-#[derive(Clone)]
-struct Database {
-    items: Arc<RwLock<HashMap<Uuid, Item>>>,
-}
+#[path = "../shutdown.rs"]
+#[allow(dead_code)]
+mod shutdown;
+use shutdown::shutdown_signal;
 
-impl Database {
-    fn new() -> Self {
-        let mut items = HashMap::new();
-        items.insert(Uuid::new_v4(), Item { id: Uuid::new_v4(), name: "Initial Item".to_string() });
-        Database {
-            items: Arc::new(RwLock::new(items)),
-        }
-    }
+#[path = "../bind_addr.rs"]
+mod bind_addr;
+use bind_addr::bind_addr;
 
-    fn get_items(&self) -> Vec<Item> {
-        let items = self.items.read().unwrap();
-        items.values().cloned().collect()
-    }
+#[path = "../body_limit.rs"]
+#[allow(dead_code)]
+mod body_limit;
+use body_limit::{max_body_bytes, warp_body_limit};
 
-    fn get_item(&self, id: Uuid) -> Option<Item> {
-        let items = self.items.read().unwrap();
-        items.get(&id).cloned()
-    }
+#[path = "../compression.rs"]
+#[allow(dead_code)]
+mod compression;
+use compression::with_compression;
 
-    fn add_item(&self, item: Item) {
-        let mut items = self.items.write().unwrap();
-        items.insert(item.id, item);
-    }
+mod openapi;
 
-    fn update_item(&self, id: Uuid, name: String) -> Result<(), &'static str> {
-        let mut items = self.items.write().unwrap();
-        if let Some(item) = items.get_mut(&id) {
-            item.name = name;
-            Ok(())
-        } else {
-            Err("Item not found")
-        }
-    }
+mod response_cache;
+use response_cache::{cache_ttl, with_response_cache, ResponseCache};
 
-    fn delete_item(&self, id: Uuid) -> Result<(), &'static str> {
-        let mut items = self.items.write().unwrap();
-        if items.remove(&id).is_some() {
-            Ok(())
-        } else {
-            Err("Item not found")
-        }
-    }
+mod idempotency;
+use idempotency::{idempotency_ttl, IdempotencyStore, Outcome, Refused, IDEMPOTENCY_KEY_HEADER};
+
+mod item_store;
+use item_store::{ItemStore, MemoryStore, SqliteStore};
+
+// Define the Item struct for our API
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, JsonSchema)]
+struct Item {
+    id: Uuid,
+    name: String,
+}
+
+// Body of `PUT /items/{id}`
+#[derive(Deserialize, JsonSchema)]
+struct UpdateItem {
+    name: String,
 }
 
 // Create the warp filters for the API
 #[tokio::main]
 async fn main() {
-    let db = Database::new();
-    let db = Arc::new(db);
+    env_logger::init();
+
+    // DATABASE_URL keeps items in SQLite, where they survive restarts and can be shared between instances
+    let store: Arc<dyn ItemStore> = match env::var("DATABASE_URL") {
+        Ok(url) => Arc::new(SqliteStore::connect(&url).await.expect("Failed to open database")),
+        Err(_) => Arc::new(MemoryStore::with_items([Item { id: Uuid::new_v4(), name: "Initial Item".to_string() }])),
+    };
+    let body_limit = warp_body_limit(max_body_bytes());
+    let cache = Arc::new(ResponseCache::new(cache_ttl()));
 
     // GET /items - Retrieve all items
     let get_items = warp::path("items")
         .and(warp::get())
-        .and(with_db(db.clone()))
-        .map(|db: Arc<Database>| {
-            warp::reply::json(&db.get_items())
+        .and(with_store(store.clone()))
+        .and_then(|store: Arc<dyn ItemStore>| async move {
+            store.list().await.map(|items| warp::reply::json(&items)).map_err(warp::reject::custom)
         });
 
     // GET /items/{id} - Retrieve a single item by ID
     let get_item = warp::path!("items" / Uuid)
         .and(warp::get())
-        .and(with_db(db.clone()))
-        .map(|id: Uuid, db: Arc<Database>| {
-            match db.get_item(id) {
-                Some(item) => warp::reply::json(&item),
-                None => warp::reply::with_status("Item not found", warp::http::StatusCode::NOT_FOUND),
+        .and(with_store(store.clone()))
+        .and_then(|id: Uuid, store: Arc<dyn ItemStore>| async move {
+            match store.get(id).await.map_err(warp::reject::custom)? {
+                Some(item) => Ok(warp::reply::json(&item)),
+                None => Err(warp::reject::custom(NoxiumError::NotFound(format!("Item {} not found", id)))),
             }
         });
 
     // POST /items - Add a new item
-    let post_item = warp::path("items")
-        .and(warp::post())
-        .and(warp::body::json())
-        .and(with_db(db.clone()))
-        .map(|item: Item, db: Arc<Database>| {
-            db.add_item(item);
-            warp::reply::with_status("Item added", warp::http::StatusCode::CREATED)
-        });
+    let post_item = post_item_route(store.clone(), Arc::new(IdempotencyStore::new(idempotency_ttl())), body_limit);
 
     // PUT /items/{id} - Update an item by ID
     let put_item = warp::path!("items" / Uuid)
         .and(warp::put())
+        .and(body_limit)
         .and(warp::body::json())
-        .and(with_db(db.clone()))
-        .map(|id: Uuid, name: String, db: Arc<Database>| {
-            match db.update_item(id, name) {
-                Ok(()) => warp::reply::with_status("Item updated", warp::http::StatusCode::OK),
-                Err(e) => warp::reply::with_status(e, warp::http::StatusCode::NOT_FOUND),
-            }
+        .and(with_store(store.clone()))
+        .and_then(|id: Uuid, update: UpdateItem, store: Arc<dyn ItemStore>| async move {
+            store.update(id, update.name).await
+                .map(|()| warp::reply::with_status("Item updated", warp::http::StatusCode::OK))
+                .map_err(warp::reject::custom)
         });
 
     // DELETE /items/{id} - Delete an item by ID
     let delete_item = warp::path!("items" / Uuid)
         .and(warp::delete())
-        .and(with_db(db.clone()))
-        .map(|id: Uuid, db: Arc<Database>| {
-            match db.delete_item(id) {
-                Ok(()) => warp::reply::with_status("Item deleted", warp::http::StatusCode::OK),
-                Err(e) => warp::reply::with_status(e, warp::http::StatusCode::NOT_FOUND),
-            }
+        .and(with_store(store.clone()))
+        .and_then(|id: Uuid, store: Arc<dyn ItemStore>| async move {
+            store.delete(id).await
+                .map(|()| warp::reply::with_status("Item deleted", warp::http::StatusCode::OK))
+                .map_err(warp::reject::custom)
         });
 
-    // Combine all routes into a single filter
-    let routes = get_items
-        .or(get_item)
-        .or(post_item)
-        .or(put_item)
-        .or(delete_item);
+    // Combine all routes into a single filter, caching GET responses until the items change and
+    // compressing responses the client accepts encoded
+    let routes = with_compression(with_response_cache(
+        cache,
+        get_items
+            .or(get_item)
+            .or(post_item)
+            .or(put_item)
+            .or(delete_item)
+            .or(openapi::routes())  // GET /openapi.json and /docs
+            .recover(handle_rejection),
+    ));
+
+    // Start the warp server on NOXIUM_BIND_ADDR or 127.0.0.1:3030, finishing in-flight requests on shutdown
+    let addr = bind_addr(SocketAddr::from(([127, 0, 0, 1], 3030)));
+    let (addr, server) = warp::serve(routes).bind_with_graceful_shutdown(addr, shutdown_signal());
+    info!("API server running on http://{}", addr);
+    server.await;
+    info!("API server stopped");
+}
 
-    // Start the warp server
-    warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
+// `POST /items`, replying with the added item. A retry sending the same
+// `Idempotency-Key` gets that reply again, with 200 instead of 201, and adds nothing.
+fn post_item_route(
+    store: Arc<dyn ItemStore>,
+    idempotency: Arc<IdempotencyStore<Item, Item>>,
+    body_limit: impl Filter<Extract = (), Error = warp::Rejection> + Copy + Send + Sync + 'static,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("items")
+        .and(warp::post())
+        .and(body_limit)
+        .and(warp::header::optional::<String>(IDEMPOTENCY_KEY_HEADER))
+        .and(warp::body::json())
+        .and(with_store(store))
+        .and_then(move |key: Option<String>, item: Item, store: Arc<dyn ItemStore>| {
+            let idempotency = idempotency.clone();
+            async move {
+                let add = |item: Item| async move { store.add(item.clone()).await.map(|()| item) };
+                let (item, status) = match key {
+                    None => (add(item).await.map_err(warp::reject::custom)?, StatusCode::CREATED),
+                    Some(key) => match idempotency.run(&key, &item, add).await {
+                        Ok(Outcome::Created(item)) => (item, StatusCode::CREATED),
+                        Ok(Outcome::Replayed(item)) => (item, StatusCode::OK),
+                        Err(Refused::Failed(e)) => return Err(warp::reject::custom(e)),
+                        Err(Refused::KeyReused) => {
+                            return Err(warp::reject::custom(NoxiumError::Validation(format!(
+                                "Idempotency-Key {:?} was already used for a different item",
+                                key
+                            ))))
+                        }
+                    },
+                };
+                Ok(warp::reply::with_status(warp::reply::json(&item), status))
+            }
+        })
 }
 
-// Helper function to pass the database to the warp filters
-fn with_db(db: Arc<Database>) -> impl Filter<Extract = (Arc<Database>,), Error = warp::Rejection> + Clone {
-    warp::any().map(move || db.clone())
-}
\ No newline at end of file
+// Helper function to pass the item store to the warp filters
+fn with_store(
+    store: Arc<dyn ItemStore>,
+) -> impl Filter<Extract = (Arc<dyn ItemStore>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || store.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn post_filter(store: Arc<dyn ItemStore>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        post_item_route(store, Arc::new(IdempotencyStore::new(Duration::from_secs(60))), warp_body_limit(1024))
+    }
+
+    async fn post<F>(filter: &F, key: &str, item: &Item) -> (StatusCode, Vec<u8>)
+    where
+        F: Filter + 'static,
+        F::Extract: warp::Reply + Send,
+    {
+        let response = warp::test::request()
+            .method("POST")
+            .path("/items")
+            .header(IDEMPOTENCY_KEY_HEADER, key)
+            .json(item)
+            .reply(filter)
+            .await;
+        (response.status(), response.body().to_vec())
+    }
+
+    fn item(name: &str) -> Item {
+        Item { id: Uuid::new_v4(), name: name.to_string() }
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_same_key_adds_one_item() {
+        let store: Arc<dyn ItemStore> = Arc::new(MemoryStore::default());
+        let filter = post_filter(store.clone());
+
+        let widget = item("Widget");
+        let first = post(&filter, "retry-1", &widget).await;
+        let second = post(&filter, "retry-1", &widget).await;
+
+        assert_eq!(first.0, StatusCode::CREATED);
+        assert_eq!(second, (StatusCode::OK, first.1.clone()));
+        assert_eq!(store.list().await.unwrap().len(), 1);
+        assert_eq!(serde_json::from_slice::<Item>(&first.1).unwrap(), widget);
+        assert_eq!(store.get(widget.id).await.unwrap(), Some(widget));
+    }
+
+    #[tokio::test]
+    async fn test_different_keys_add_separate_items() {
+        let store: Arc<dyn ItemStore> = Arc::new(MemoryStore::default());
+        let filter = post_filter(store.clone());
+
+        let (first, second) = (item("Widget"), item("Widget"));
+        assert_eq!(post(&filter, "key-a", &first).await.0, StatusCode::CREATED);
+        assert_eq!(post(&filter, "key-b", &second).await.0, StatusCode::CREATED);
+        assert_eq!(store.list().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_reused_key_with_another_item_is_rejected() {
+        let store: Arc<dyn ItemStore> = Arc::new(MemoryStore::default());
+        let filter = post_filter(store.clone()).recover(handle_rejection);
+
+        assert_eq!(post(&filter, "key-a", &item("Widget")).await.0, StatusCode::CREATED);
+        assert_eq!(post(&filter, "key-a", &item("Gadget")).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(store.list().await.unwrap().len(), 1);
+    }
+}
@@ -1,7 +1,9 @@
 use reqwest::{Client, Error, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::time::Duration;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use tokio::time::sleep;
 use log::{info, warn, error};
 use config::{Config, File, Environment};
@@ -19,6 +21,18 @@ struct AppConfig {
     timeout: u64,
     retry_attempts: u32,
     retry_delay: u64,
+    #[serde(default = "default_circuit_failure_threshold")]
+    circuit_failure_threshold: u32,
+    #[serde(default = "default_circuit_cooldown")]
+    circuit_cooldown: u64,
+}
+
+fn default_circuit_failure_threshold() -> u32 {
+    5
+}
+
+fn default_circuit_cooldown() -> u64 {
+    30
 }
 
 #[derive(Debug)]
@@ -27,6 +41,7 @@ enum ApiClientError {
     Unauthorized,
     Timeout,
     TooManyRequests,
+    CircuitOpen(String),
     Unexpected(String),
 }
 
@@ -37,6 +52,7 @@ impl fmt::Display for ApiClientError {
             ApiClientError::Unauthorized => write!(f, "Unauthorized access"),
             ApiClientError::Timeout => write!(f, "Request timed out"),
             ApiClientError::TooManyRequests => write!(f, "Too many requests"),
+            ApiClientError::CircuitOpen(base_url) => write!(f, "Circuit open for {}, not sending request", base_url),
             ApiClientError::Unexpected(err) => write!(f, "Unexpected error: {}", err),
         }
     }
@@ -44,6 +60,143 @@ impl fmt::Display for ApiClientError {
 
 impl std::error::Error for ApiClientError {}
 
+impl ApiClientError {
+    // Errors that suggest the endpoint itself is unhealthy, as opposed to a bad request
+    fn trips_circuit(&self) -> bool {
+        match self {
+            ApiClientError::RequestFailed(status) => status.is_server_error(),
+            ApiClientError::Timeout | ApiClientError::TooManyRequests | ApiClientError::Unexpected(_) => true,
+            ApiClientError::Unauthorized | ApiClientError::CircuitOpen(_) => false,
+        }
+    }
+}
+
+/// Observable state of the circuit for one base URL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests go through; consecutive failures are being counted.
+    Closed,
+    /// Requests fail fast with `CircuitOpen` until the cooldown ends.
+    Open,
+    /// The cooldown has ended; the next request is let through as a probe.
+    HalfOpen,
+}
+
+impl fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CircuitState::Closed => write!(f, "closed"),
+            CircuitState::Open => write!(f, "open"),
+            CircuitState::HalfOpen => write!(f, "half-open"),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Circuit {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    // A probe request is in flight
+    Probing,
+}
+
+/// Stops calling a base URL after `failure_threshold` consecutive failures.
+///
+/// Once open, calls fail fast with `CircuitOpen` for `cooldown`; after that a
+/// single probe is let through, which closes the circuit on success or opens
+/// it for another cooldown on failure.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            circuits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn state(&self, base_url: &str) -> CircuitState {
+        match self.circuits.lock().unwrap().get(base_url) {
+            None | Some(Circuit::Closed { .. }) => CircuitState::Closed,
+            Some(Circuit::Open { until }) if Instant::now() < *until => CircuitState::Open,
+            Some(Circuit::Open { .. }) | Some(Circuit::Probing) => CircuitState::HalfOpen,
+        }
+    }
+
+    // Checks whether a request may be sent, claiming the probe if the cooldown has ended.
+    // Keep the returned attempt until the result is recorded
+    fn try_acquire<'a>(&'a self, base_url: &'a str) -> Result<Attempt<'a>, ApiClientError> {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(base_url.to_string()).or_insert(Circuit::Closed { failures: 0 });
+        match circuit {
+            Circuit::Closed { .. } => Ok(Attempt { breaker: self, base_url, probe: false }),
+            Circuit::Open { until } if Instant::now() >= *until => {
+                info!("Circuit for {} is half-open, sending a probe request", base_url);
+                *circuit = Circuit::Probing;
+                Ok(Attempt { breaker: self, base_url, probe: true })
+            }
+            Circuit::Open { .. } | Circuit::Probing => Err(ApiClientError::CircuitOpen(base_url.to_string())),
+        }
+    }
+
+    fn record_success(&self, base_url: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        if let Some(Circuit::Probing) = circuits.get(base_url) {
+            info!("Probe to {} succeeded, closing circuit", base_url);
+        }
+        circuits.insert(base_url.to_string(), Circuit::Closed { failures: 0 });
+    }
+
+    fn record_failure(&self, base_url: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(base_url.to_string()).or_insert(Circuit::Closed { failures: 0 });
+        let open = Circuit::Open { until: Instant::now() + self.cooldown };
+        match circuit {
+            Circuit::Closed { failures } => {
+                *failures += 1;
+                if *failures >= self.failure_threshold {
+                    warn!("{} consecutive failures from {}, opening circuit for {:?}", failures, base_url, self.cooldown);
+                    *circuit = open;
+                }
+            }
+            Circuit::Probing => {
+                warn!("Probe to {} failed, reopening circuit for {:?}", base_url, self.cooldown);
+                *circuit = open;
+            }
+            Circuit::Open { .. } => {}
+        }
+    }
+}
+
+// A request let through by `try_acquire`. A probe dropped before its result was
+// recorded, because the caller's future was cancelled, leaves the circuit half-open
+// for the next request instead of stuck waiting on a probe that never finishes
+#[must_use]
+struct Attempt<'a> {
+    breaker: &'a CircuitBreaker,
+    base_url: &'a str,
+    probe: bool,
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        if !self.probe {
+            return;
+        }
+        let mut circuits = self.breaker.circuits.lock().unwrap();
+        if let Some(circuit @ Circuit::Probing) = circuits.get_mut(self.base_url) {
+            warn!("Probe to {} was abandoned, letting the next request probe", self.base_url);
+            *circuit = Circuit::Open { until: Instant::now() };
+        }
+    }
+}
+
 async fn handle_response(response: Response) -> Result<ApiResponse, ApiClientError> {
     let status = response.status();
     match status {
@@ -92,15 +245,33 @@ async fn post_request(client: &Client, url: &str, headers: Option<HashMap<String
     handle_response(response).await
 }
 
-async fn request_with_retries<F>(config: &AppConfig, operation: F) -> Result<ApiResponse, ApiClientError>
+async fn request_with_retries<F, Fut>(config: &AppConfig, breaker: &CircuitBreaker, operation: F) -> Result<ApiResponse, ApiClientError>
 where
-    F: Fn() -> Result<ApiResponse, ApiClientError> + Copy,
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<ApiResponse, ApiClientError>>,
 {
     let mut attempts = config.retry_attempts;
     loop {
-        match operation() {
-            Ok(response) => return Ok(response),
+        // Retrying against an open circuit would only add load, so give up right away
+        let _attempt = match breaker.try_acquire(&config.api_base_url) {
+            Ok(attempt) => attempt,
+            Err(e) => {
+                warn!("{}", e);
+                return Err(e);
+            }
+        };
+
+        match operation().await {
+            Ok(response) => {
+                breaker.record_success(&config.api_base_url);
+                return Ok(response);
+            }
             Err(e) => {
+                if e.trips_circuit() {
+                    breaker.record_failure(&config.api_base_url);
+                } else {
+                    breaker.record_success(&config.api_base_url);
+                }
                 if attempts == 0 {
                     error!("Failed after multiple retries: {:?}", e);
                     return Err(e);
@@ -137,6 +308,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let config = load_config()?;
 
+    let breaker = CircuitBreaker::new(config.circuit_failure_threshold, Duration::from_secs(config.circuit_cooldown));
+
     let client = Client::builder()
         .timeout(Duration::from_secs(config.timeout))
         .build().map_err(|e| ApiClientError::Unexpected(e.to_string()))?;
@@ -152,7 +325,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     query_params.insert("query_param1", "value1");
     query_params.insert("query_param2", "value2");
 
-    let get_response = request_with_retries(&config, || {
+    let get_response = request_with_retries(&config, &breaker, || {
         get_request(&client, &get_url, Some(headers.clone()), Some(query_params.clone()))
     }).await?;
 
@@ -160,11 +333,105 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let post_payload = ApiResponse { data: "Some JSON data".into() };
 
-    let post_response = request_with_retries(&config, || {
+    let post_response = request_with_retries(&config, &breaker, || {
         post_request(&client, &post_url, Some(headers.clone()), &post_payload)
     }).await?;
 
     info!("POST Response: {:?}", post_response);
+    info!("Circuit for {} is {}", config.api_base_url, breaker.state(&config.api_base_url));
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn test_config() -> AppConfig {
+        AppConfig {
+            api_base_url: "http://api.test".to_string(),
+            api_key: String::new(),
+            timeout: 1,
+            retry_attempts: 0,
+            retry_delay: 0,
+            circuit_failure_threshold: 3,
+            circuit_cooldown: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_circuit_opens_after_consecutive_failures() {
+        let config = test_config();
+        let breaker = CircuitBreaker::new(config.circuit_failure_threshold, Duration::from_secs(60));
+        let calls = AtomicUsize::new(0);
+        let failing = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(ApiClientError::RequestFailed(StatusCode::SERVICE_UNAVAILABLE))
+        };
+
+        for _ in 0..3 {
+            let result = request_with_retries(&config, &breaker, failing).await;
+            assert!(matches!(result, Err(ApiClientError::RequestFailed(_))));
+        }
+        assert_eq!(breaker.state(&config.api_base_url), CircuitState::Open);
+
+        let result = request_with_retries(&config, &breaker, failing).await;
+        assert!(matches!(result, Err(ApiClientError::CircuitOpen(_))));
+        assert_eq!(calls.load(Ordering::SeqCst), 3, "an open circuit must not send the request");
+    }
+
+    #[tokio::test]
+    async fn test_circuit_recovers_after_cooldown_and_successful_probe() {
+        let config = test_config();
+        let cooldown = Duration::from_millis(50);
+        let breaker = CircuitBreaker::new(config.circuit_failure_threshold, cooldown);
+        let calls = AtomicUsize::new(0);
+        let failing = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(ApiClientError::Timeout)
+        };
+        let succeeding = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok(ApiResponse { data: "ok".to_string() })
+        };
+
+        for _ in 0..3 {
+            let _ = request_with_retries(&config, &breaker, failing).await;
+        }
+        assert!(matches!(request_with_retries(&config, &breaker, succeeding).await, Err(ApiClientError::CircuitOpen(_))));
+
+        // A failed probe reopens the circuit for another cooldown
+        sleep(cooldown).await;
+        assert_eq!(breaker.state(&config.api_base_url), CircuitState::HalfOpen);
+        let _ = request_with_retries(&config, &breaker, failing).await;
+        assert_eq!(breaker.state(&config.api_base_url), CircuitState::Open);
+
+        sleep(cooldown).await;
+        let response = request_with_retries(&config, &breaker, succeeding).await.unwrap();
+        assert_eq!(response.data, "ok");
+        assert_eq!(breaker.state(&config.api_base_url), CircuitState::Closed);
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_probe_lets_the_next_request_probe() {
+        let config = test_config();
+        let cooldown = Duration::from_millis(50);
+        let breaker = CircuitBreaker::new(config.circuit_failure_threshold, cooldown);
+        for _ in 0..3 {
+            let _ = request_with_retries(&config, &breaker, || async { Err(ApiClientError::Timeout) }).await;
+        }
+
+        // The caller gives up on the probe before it answers
+        sleep(cooldown).await;
+        let hanging = || std::future::pending::<Result<ApiResponse, ApiClientError>>();
+        let probe = tokio::time::timeout(Duration::from_millis(10), request_with_retries(&config, &breaker, hanging));
+        assert!(probe.await.is_err());
+        assert_eq!(breaker.state(&config.api_base_url), CircuitState::HalfOpen);
+
+        let succeeding = || async { Ok(ApiResponse { data: "ok".to_string() }) };
+        assert_eq!(request_with_retries(&config, &breaker, succeeding).await.unwrap().data, "ok");
+        assert_eq!(breaker.state(&config.api_base_url), CircuitState::Closed);
+    }
+}
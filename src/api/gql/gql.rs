@@ -1,11 +1,70 @@
-use async_graphql::{Schema, Object, Context, FieldResult, EmptyMutation, EmptySubscription, Enum, ID, InputObject};
+use async_graphql::{Schema, Object, SimpleObject, Context, FieldResult, EmptyMutation, EmptySubscription, Enum, ID, InputObject, Guard, Error, ErrorExtensions};
 use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
 use actix_web::{web, App, HttpServer, HttpResponse, HttpRequest, Result as ActixResult};
 use serde::{Deserialize, Serialize};
+use std::env;
 use std::sync::Arc;
 use actix_service::Service;
 use actix_web::middleware::Logger;
 
+#[path = "../../body_limit.rs"]
+#[allow(dead_code)]
+mod body_limit;
+use body_limit::{actix_body_limits, max_body_bytes};
+
+#[path = "../../request_log.rs"]
+#[allow(dead_code)]
+mod request_log;
+use request_log::RequestLogger;
+
+#[path = "../../timeout.rs"]
+#[allow(dead_code)]
+mod timeout;
+use timeout::{request_timeout, Timeout};
+
+#[path = "../../shutdown.rs"]
+#[allow(dead_code)]
+mod shutdown;
+use shutdown::shutdown_signal;
+
+#[path = "../../server/auth.rs"]
+#[allow(dead_code)]
+mod auth;
+use auth::{validate_token, Claims};
+
+// Secret the bearer tokens' signatures are checked with
+struct JwtSecret(String);
+
+/// Lets a resolver run only for callers whose token grants `scope`, as one
+/// of its roles or permissions: `#[graphql(guard = "ScopeGuard::new(\"admin\")")]`.
+///
+/// The caller's identity is the `Claims` the handler puts in the request
+/// data. Without one the error's `code` extension is `UNAUTHENTICATED`, and
+/// without the scope it's `FORBIDDEN`.
+pub struct ScopeGuard {
+    scope: &'static str,
+}
+
+impl ScopeGuard {
+    pub fn new(scope: &'static str) -> Self {
+        ScopeGuard { scope }
+    }
+}
+
+impl Guard for ScopeGuard {
+    async fn check(&self, ctx: &Context<'_>) -> async_graphql::Result<()> {
+        let Some(claims) = ctx.data_opt::<Claims>() else {
+            return Err(Error::new("Authentication required").extend_with(|_, e| e.set("code", "UNAUTHENTICATED")));
+        };
+        let granted = claims.roles.iter().chain(&claims.permissions).any(|scope| scope == self.scope);
+        if granted {
+            Ok(())
+        } else {
+            Err(Error::new(format!("Requires the {:?} scope", self.scope)).extend_with(|_, e| e.set("code", "FORBIDDEN")))
+        }
+    }
+}
+
 // Define a User struct for the GraphQL schema
 #[derive(SimpleObject, Clone)]
 struct User {
@@ -60,6 +119,7 @@ struct Mutation;
 
 #[Object]
 impl Mutation {
+    #[graphql(guard = "ScopeGuard::new(\"user\")")]
     async fn create_user(&self, ctx: &Context<'_>, new_user: NewUser) -> FieldResult<User> {
         // Dummy data for example
         Ok(User {
@@ -69,6 +129,7 @@ impl Mutation {
         })
     }
 
+    #[graphql(guard = "ScopeGuard::new(\"user\")")]
     async fn update_user(&self, ctx: &Context<'_>, id: ID, new_name: String) -> FieldResult<User> {
         // Dummy data for example
         Ok(User {
@@ -78,6 +139,7 @@ impl Mutation {
         })
     }
 
+    #[graphql(guard = "ScopeGuard::new(\"admin\")")]
     async fn delete_user(&self, ctx: &Context<'_>, id: ID) -> FieldResult<String> {
         // Dummy data for example
         Ok(format!("User with ID {} deleted", id))
@@ -86,9 +148,25 @@ impl Mutation {
 
 type MySchema = Schema<Query, Mutation, EmptySubscription>;
 
-// GraphQL handler
-async fn graphql_handler(schema: web::Data<Arc<MySchema>>, req: GraphQLRequest) -> GraphQLResponse {
-    schema.execute(req.into_inner()).await.into()
+// GraphQL handler, running the query as the caller its bearer token names, if any
+async fn graphql_handler(
+    schema: web::Data<Arc<MySchema>>,
+    secret: web::Data<JwtSecret>,
+    http_req: HttpRequest,
+    req: GraphQLRequest,
+) -> GraphQLResponse {
+    let mut request = req.into_inner();
+    if let Some(claims) = bearer_claims(&http_req, &secret.0) {
+        request = request.data(claims);
+    }
+    schema.execute(request).await.into()
+}
+
+// The claims of a valid `Authorization: Bearer` token
+fn bearer_claims(req: &HttpRequest, secret: &str) -> Option<Claims> {
+    let header = req.headers().get("Authorization")?.to_str().ok()?;
+    let token = header.strip_prefix("Bearer ")?;
+    validate_token(secret, token).ok()
 }
 
 // REST API handler
@@ -116,16 +194,77 @@ where
 async fn main() -> std::io::Result<()> {
     let schema = Arc::new(Schema::build(Query::default(), Mutation::default(), EmptySubscription)
         .finish());
+    let jwt_secret = web::Data::new(JwtSecret(env::var("JWT_SECRET").expect("JWT_SECRET must be set")));
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
+            .configure(actix_body_limits(max_body_bytes()))
             .wrap(Logger::default())
             .app_data(web::Data::new(schema.clone()))
+            .app_data(jwt_secret.clone())
             .service(web::resource("/graphql").guard(web::guard().post()).to(graphql_handler))
             .service(web::resource("/api").route(web::get().to(rest_api_handler)))
             .wrap_fn(auth_middleware) // Add authentication middleware
+            .wrap(Timeout::new(request_timeout()))
+            .wrap(RequestLogger)
     })
+    // Stopped below on shutdown_signal, like the other servers
+    .disable_signals()
     .bind("127.0.0.1:8080")?
-    .run()
-    .await
-}
\ No newline at end of file
+    .run();
+
+    // Finish in-flight requests before exiting
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        handle.stop(true).await;
+    });
+    server.await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_graphql::Request;
+
+    fn claims(roles: &[&str]) -> Claims {
+        Claims {
+            sub: "alice".to_string(),
+            exp: usize::MAX,
+            roles: roles.iter().map(|role| role.to_string()).collect(),
+            permissions: Vec::new(),
+        }
+    }
+
+    async fn delete_user(claims: Option<Claims>) -> async_graphql::Response {
+        let schema = Schema::build(Query::default(), Mutation::default(), EmptySubscription).finish();
+        let mut request = Request::new(r#"mutation { deleteUser(id: "7") }"#);
+        if let Some(claims) = claims {
+            request = request.data(claims);
+        }
+        schema.execute(request).await
+    }
+
+    fn error_code(response: &async_graphql::Response) -> String {
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+        extensions.get("code").unwrap().clone().into_json().unwrap().as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_is_denied_without_admin() {
+        let response = delete_user(Some(claims(&["user"]))).await;
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(error_code(&response), "FORBIDDEN");
+        assert_eq!(response.errors[0].path.len(), 1);
+
+        let response = delete_user(None).await;
+        assert_eq!(error_code(&response), "UNAUTHENTICATED");
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_is_allowed_for_admin() {
+        let response = delete_user(Some(claims(&["user", "admin"]))).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(response.data.into_json().unwrap()["deleteUser"], "User with ID 7 deleted");
+    }
+}
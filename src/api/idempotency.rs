@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Header a client sets so that retrying a POST can't apply it twice.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+const DEFAULT_TTL_SECS: u64 = 600;
+
+/// How a keyed request was handled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome<T> {
+    /// First use of the key; the request ran and this is its result
+    Created(T),
+    /// A retry; the request did not run again and this is the first result
+    Replayed(T),
+}
+
+/// Why a keyed request produced no result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Refused<E> {
+    /// The key was already used for a different request
+    KeyReused,
+    /// The request ran and failed. Nothing was stored, so a retry runs it again
+    Failed(E),
+}
+
+struct Entry<R, T> {
+    request: R,
+    result: T,
+    stored: Instant,
+}
+
+// One key's result, locked while its request runs so retries wait for it
+type Slot<R, T> = Arc<tokio::sync::Mutex<Option<Entry<R, T>>>>;
+
+/// Results of keyed requests, kept for `ttl` so retries get them back.
+///
+/// `R` is the request a key was first used with, compared on every retry so
+/// a key reused for something else is refused rather than answered with an
+/// unrelated result; `T` is what the request produced.
+pub struct IdempotencyStore<R, T> {
+    ttl: Duration,
+    slots: Mutex<HashMap<String, Slot<R, T>>>,
+}
+
+impl<R: PartialEq + Clone, T: Clone> IdempotencyStore<R, T> {
+    pub fn new(ttl: Duration) -> Self {
+        IdempotencyStore { ttl, slots: Mutex::new(HashMap::new()) }
+    }
+
+    /// Runs `create` for the first request with `key`, and returns its
+    /// result again for every retry until the key expires.
+    ///
+    /// `create` runs with the key locked, so concurrent retries of one
+    /// request wait for it instead of both getting past the lookup; requests
+    /// with other keys go ahead.
+    pub async fn run<E, F, Fut>(&self, key: &str, request: &R, create: F) -> Result<Outcome<T>, Refused<E>>
+    where
+        F: FnOnce(R) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let slot = {
+            let mut slots = self.slots.lock().unwrap();
+            // Slots are only cloned under this lock, so one nobody else holds can't be waited on
+            slots.retain(|_, slot| {
+                Arc::strong_count(slot) > 1
+                    || slot.try_lock().is_ok_and(|entry| entry.as_ref().is_some_and(|entry| !self.expired(entry)))
+            });
+            slots.entry(key.to_string()).or_default().clone()
+        };
+
+        let mut entry = slot.lock().await;
+        if let Some(entry) = entry.as_ref().filter(|entry| !self.expired(entry)) {
+            return if entry.request == *request {
+                Ok(Outcome::Replayed(entry.result.clone()))
+            } else {
+                Err(Refused::KeyReused)
+            };
+        }
+
+        let result = create(request.clone()).await.map_err(Refused::Failed)?;
+        *entry = Some(Entry { request: request.clone(), result: result.clone(), stored: Instant::now() });
+        Ok(Outcome::Created(result))
+    }
+
+    fn expired(&self, entry: &Entry<R, T>) -> bool {
+        entry.stored.elapsed() >= self.ttl
+    }
+}
+
+/// How long a key is remembered: `NOXIUM_IDEMPOTENCY_TTL_SECS`, or 10 minutes.
+pub fn idempotency_ttl() -> Duration {
+    match env::var("NOXIUM_IDEMPOTENCY_TTL_SECS") {
+        Ok(value) => match value.trim().parse::<u64>() {
+            Ok(secs) if secs > 0 => Duration::from_secs(secs),
+            _ => {
+                log::warn!(
+                    "Ignoring invalid NOXIUM_IDEMPOTENCY_TTL_SECS {:?}, using {} seconds",
+                    value,
+                    DEFAULT_TTL_SECS
+                );
+                Duration::from_secs(DEFAULT_TTL_SECS)
+            }
+        },
+        Err(_) => Duration::from_secs(DEFAULT_TTL_SECS),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn times_ten(n: i32) -> Result<i32, String> {
+        Ok(n * 10)
+    }
+
+    #[tokio::test]
+    async fn test_expired_key_runs_again() {
+        let store = IdempotencyStore::new(Duration::ZERO);
+        assert_eq!(store.run("k", &1, times_ten).await, Ok(Outcome::Created(10)));
+        assert_eq!(store.run("k", &2, times_ten).await, Ok(Outcome::Created(20)));
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_not_replayed() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+        let failed = store.run("k", &1, |_| async { Err("store unavailable".to_string()) }).await;
+        assert_eq!(failed, Err(Refused::Failed("store unavailable".to_string())));
+        assert_eq!(store.run("k", &1, times_ten).await, Ok(Outcome::Created(10)));
+        assert_eq!(store.run("k", &1, times_ten).await, Ok(Outcome::Replayed(10)));
+    }
+
+    #[tokio::test]
+    async fn test_only_the_same_key_waits() {
+        let store = Arc::new(IdempotencyStore::new(Duration::from_secs(60)));
+        let runs = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let release = Arc::new(tokio::sync::Notify::new());
+
+        // The first request for "slow" holds its key until released
+        let slow = |store: Arc<IdempotencyStore<i32, i32>>| {
+            let (runs, release) = (runs.clone(), release.clone());
+            tokio::spawn(async move {
+                store
+                    .run("slow", &1, |n| async move {
+                        runs.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        release.notified().await;
+                        times_ten(n).await
+                    })
+                    .await
+            })
+        };
+        let first = slow(store.clone());
+        tokio::task::yield_now().await;
+        let retry = slow(store.clone());
+
+        let other = tokio::time::timeout(Duration::from_secs(1), store.run("other", &2, times_ten)).await;
+        assert_eq!(other.expect("another key waited on the slow one"), Ok(Outcome::Created(20)));
+
+        release.notify_one();
+        assert_eq!(first.await.unwrap(), Ok(Outcome::Created(10)));
+        assert_eq!(retry.await.unwrap(), Ok(Outcome::Replayed(10)));
+        assert_eq!(runs.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+}
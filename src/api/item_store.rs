@@ -0,0 +1,221 @@
+use async_trait::async_trait;
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use uuid::Uuid;
+
+use super::error::NoxiumError;
+use super::Item;
+
+// Upper bound on open connections for a file-backed database
+const MAX_CONNECTIONS: u32 = 8;
+
+/// Where the item API keeps its items.
+///
+/// The warp filters take an `Arc<dyn ItemStore>`, so items can live in
+/// memory or in SQLite, where they survive restarts and can be shared by
+/// several instances, without the routes knowing which. Every store lists
+/// items by name, then id.
+#[async_trait]
+pub trait ItemStore: Send + Sync {
+    async fn list(&self) -> Result<Vec<Item>, NoxiumError>;
+
+    async fn get(&self, id: Uuid) -> Result<Option<Item>, NoxiumError>;
+
+    /// Adds `item`, replacing any item with the same id.
+    async fn add(&self, item: Item) -> Result<(), NoxiumError>;
+
+    /// Renames the item with `id`; `NotFound` if there is none.
+    async fn update(&self, id: Uuid, name: String) -> Result<(), NoxiumError>;
+
+    /// Removes the item with `id`; `NotFound` if there is none.
+    async fn delete(&self, id: Uuid) -> Result<(), NoxiumError>;
+}
+
+fn not_found(id: Uuid) -> NoxiumError {
+    NoxiumError::NotFound(format!("Item {} not found", id))
+}
+
+/// Items held in memory; they are gone when the process exits.
+#[derive(Default)]
+pub struct MemoryStore {
+    items: RwLock<HashMap<Uuid, Item>>,
+}
+
+impl MemoryStore {
+    pub fn with_items(items: impl IntoIterator<Item = Item>) -> Self {
+        let items = items.into_iter().map(|item| (item.id, item)).collect();
+        MemoryStore { items: RwLock::new(items) }
+    }
+}
+
+#[async_trait]
+impl ItemStore for MemoryStore {
+    async fn list(&self) -> Result<Vec<Item>, NoxiumError> {
+        let mut items: Vec<Item> = self.items.read().unwrap().values().cloned().collect();
+        items.sort_by(|a, b| (&a.name, a.id).cmp(&(&b.name, b.id)));
+        Ok(items)
+    }
+
+    async fn get(&self, id: Uuid) -> Result<Option<Item>, NoxiumError> {
+        Ok(self.items.read().unwrap().get(&id).cloned())
+    }
+
+    async fn add(&self, item: Item) -> Result<(), NoxiumError> {
+        self.items.write().unwrap().insert(item.id, item);
+        Ok(())
+    }
+
+    async fn update(&self, id: Uuid, name: String) -> Result<(), NoxiumError> {
+        let mut items = self.items.write().unwrap();
+        let item = items.get_mut(&id).ok_or_else(|| not_found(id))?;
+        item.name = name;
+        Ok(())
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), NoxiumError> {
+        self.items.write().unwrap().remove(&id).map(|_| ()).ok_or_else(|| not_found(id))
+    }
+}
+
+/// Items in the `api_items` table (see `migrations/`).
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        SqliteStore { pool }
+    }
+
+    /// Opens `database_url` and brings its schema up to date.
+    ///
+    /// Every connection in an in-memory pool would see its own empty
+    /// database, so `:memory:` URLs get a single connection that is never
+    /// recycled.
+    pub async fn connect(database_url: &str) -> Result<Self, sqlx::Error> {
+        let options = if database_url.contains(":memory:") {
+            SqlitePoolOptions::new().max_connections(1).idle_timeout(None).max_lifetime(None)
+        } else {
+            SqlitePoolOptions::new().max_connections(MAX_CONNECTIONS)
+        };
+        let pool = options.connect(database_url).await?;
+        sqlx::migrate!().run(&pool).await?;
+        Ok(SqliteStore::new(pool))
+    }
+}
+
+// Ids are stored as hyphenated text, which sorts the same way `Uuid` does
+fn item_from_row((id, name): (String, String)) -> Result<Item, NoxiumError> {
+    let id = Uuid::parse_str(&id).map_err(|e| NoxiumError::Database(format!("Bad item id {:?}: {}", id, e)))?;
+    Ok(Item { id, name })
+}
+
+#[async_trait]
+impl ItemStore for SqliteStore {
+    async fn list(&self) -> Result<Vec<Item>, NoxiumError> {
+        let rows: Vec<(String, String)> = sqlx::query_as("SELECT id, name FROM api_items ORDER BY name, id")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.into_iter().map(item_from_row).collect()
+    }
+
+    async fn get(&self, id: Uuid) -> Result<Option<Item>, NoxiumError> {
+        let row: Option<(String, String)> = sqlx::query_as("SELECT id, name FROM api_items WHERE id = ?")
+            .bind(id.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        row.map(item_from_row).transpose()
+    }
+
+    async fn add(&self, item: Item) -> Result<(), NoxiumError> {
+        sqlx::query("INSERT INTO api_items (id, name) VALUES (?, ?) ON CONFLICT (id) DO UPDATE SET name = excluded.name")
+            .bind(item.id.to_string())
+            .bind(item.name)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn update(&self, id: Uuid, name: String) -> Result<(), NoxiumError> {
+        let result = sqlx::query("UPDATE api_items SET name = ? WHERE id = ?")
+            .bind(name)
+            .bind(id.to_string())
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(not_found(id));
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), NoxiumError> {
+        let result = sqlx::query("DELETE FROM api_items WHERE id = ?").bind(id.to_string()).execute(&self.pool).await?;
+        if result.rows_affected() == 0 {
+            return Err(not_found(id));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(name: &str) -> Item {
+        Item { id: Uuid::new_v4(), name: name.to_string() }
+    }
+
+    // Run against every store, so they can't drift apart
+    async fn assert_store_behaves(store: &dyn ItemStore) {
+        assert_eq!(store.list().await.unwrap(), vec![]);
+
+        let (widget, gadget) = (item("Widget"), item("Gadget"));
+        store.add(widget.clone()).await.unwrap();
+        store.add(gadget.clone()).await.unwrap();
+        assert_eq!(store.list().await.unwrap(), vec![gadget.clone(), widget.clone()]);
+        assert_eq!(store.get(widget.id).await.unwrap(), Some(widget.clone()));
+        assert_eq!(store.get(Uuid::new_v4()).await.unwrap(), None);
+
+        // Adding an existing id replaces the item
+        let renamed = Item { id: gadget.id, name: "Zapper".to_string() };
+        store.add(renamed.clone()).await.unwrap();
+        assert_eq!(store.list().await.unwrap(), vec![widget.clone(), renamed]);
+
+        store.update(widget.id, "Sprocket".to_string()).await.unwrap();
+        assert_eq!(store.get(widget.id).await.unwrap().unwrap().name, "Sprocket");
+        assert!(matches!(store.update(Uuid::new_v4(), "Nothing".to_string()).await, Err(NoxiumError::NotFound(_))));
+
+        store.delete(widget.id).await.unwrap();
+        assert!(matches!(store.delete(widget.id).await, Err(NoxiumError::NotFound(_))));
+        assert_eq!(store.get(widget.id).await.unwrap(), None);
+        assert_eq!(store.list().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        assert_store_behaves(&MemoryStore::default()).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        assert_store_behaves(&SqliteStore::connect("sqlite::memory:").await.unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_items_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("noxium-api-items-{}.db", std::process::id()));
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+        let widget = item("Widget");
+
+        let store = SqliteStore::connect(&url).await.unwrap();
+        store.add(widget.clone()).await.unwrap();
+        store.pool.close().await;
+
+        let reopened = SqliteStore::connect(&url).await.unwrap();
+        assert_eq!(reopened.list().await.unwrap(), vec![widget]);
+        reopened.pool.close().await;
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use warp::{Filter, Rejection, Reply};
+
+use super::error::ErrorBody;
+use super::{Item, UpdateItem};
+
+// Swagger UI loaded from a CDN, pointed at the spec served next to it
+const SWAGGER_UI: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Noxium Item API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js" crossorigin></script>
+  <script>
+    window.onload = () => {
+      window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+    };
+  </script>
+</body>
+</html>
+"#;
+
+fn json_content(schema: Value) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+
+// A response whose body is described by `T`'s schema
+fn response_with<T: JsonSchema>(generator: &mut SchemaGenerator, description: &str) -> Value {
+    json!({ "description": description, "content": json_content(json!(generator.subschema_for::<T>())) })
+}
+
+fn plain_response(description: &str) -> Value {
+    json!({ "description": description, "content": { "text/plain": { "schema": { "type": "string" } } } })
+}
+
+fn request_body<T: JsonSchema>(generator: &mut SchemaGenerator) -> Value {
+    json!({ "required": true, "content": json_content(json!(generator.subschema_for::<T>())) })
+}
+
+/// Builds the OpenAPI 3.0 document for the `/items` routes.
+///
+/// Request and response schemas are generated from the same structs the
+/// handlers (de)serialize, so changing a field changes the spec with it.
+pub fn spec() -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+
+    let id_parameter = json!({
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "description": "Id of the item",
+        "schema": { "type": "string", "format": "uuid" },
+    });
+
+    let paths = json!({
+        "/items": {
+            "get": {
+                "operationId": "listItems",
+                "summary": "List all items",
+                "responses": {
+                    "200": response_with::<Vec<Item>>(&mut generator, "All items"),
+                },
+            },
+            "post": {
+                "operationId": "createItem",
+                "summary": "Add an item",
+                "parameters": [{
+                    "name": "Idempotency-Key",
+                    "in": "header",
+                    "required": false,
+                    "description": "Makes the request safe to retry: a repeat with the same key and item returns the first response instead of adding it again",
+                    "schema": { "type": "string" },
+                }],
+                "requestBody": request_body::<Item>(&mut generator),
+                "responses": {
+                    "200": response_with::<Item>(&mut generator, "A retry of an already added item"),
+                    "201": response_with::<Item>(&mut generator, "The added item"),
+                    "400": response_with::<ErrorBody>(&mut generator, "Malformed item, or an Idempotency-Key already used for a different item"),
+                    "413": response_with::<ErrorBody>(&mut generator, "Request body too large"),
+                },
+            },
+        },
+        "/items/{id}": {
+            "parameters": [id_parameter],
+            "get": {
+                "operationId": "getItem",
+                "summary": "Get an item by id",
+                "responses": {
+                    "200": response_with::<Item>(&mut generator, "The item"),
+                    "404": response_with::<ErrorBody>(&mut generator, "No item with this id"),
+                },
+            },
+            "put": {
+                "operationId": "updateItem",
+                "summary": "Rename an item",
+                "requestBody": request_body::<UpdateItem>(&mut generator),
+                "responses": {
+                    "200": plain_response("Item updated"),
+                    "400": response_with::<ErrorBody>(&mut generator, "Malformed update"),
+                    "404": response_with::<ErrorBody>(&mut generator, "No item with this id"),
+                    "413": response_with::<ErrorBody>(&mut generator, "Request body too large"),
+                },
+            },
+            "delete": {
+                "operationId": "deleteItem",
+                "summary": "Delete an item",
+                "responses": {
+                    "200": plain_response("Item deleted"),
+                    "404": response_with::<ErrorBody>(&mut generator, "No item with this id"),
+                },
+            },
+        },
+    });
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Noxium Item API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": { "schemas": generator.definitions() },
+    })
+}
+
+/// `GET /openapi.json` with the spec and `GET /docs` with a Swagger UI for it.
+pub fn routes() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let spec = Arc::new(spec());
+    let openapi = warp::path("openapi.json")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || warp::reply::json(&*spec));
+    let docs = warp::path("docs")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|| warp::reply::html(SWAGGER_UI));
+    openapi.or(docs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_spec_lists_every_item_operation() {
+        let response = warp::test::request().path("/openapi.json").reply(&routes()).await;
+        assert_eq!(response.status(), 200);
+        let spec: Value = serde_json::from_slice(response.body()).expect("spec should be valid JSON");
+        assert_eq!(spec["openapi"], "3.0.3");
+
+        let mut operations: Vec<String> = Vec::new();
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            for method in ["get", "post", "put", "delete", "patch"] {
+                if let Some(operation) = item.get(method) {
+                    operations.push(format!("{} {} {}", method.to_uppercase(), path, operation["operationId"].as_str().unwrap()));
+                }
+            }
+        }
+        operations.sort();
+        assert_eq!(
+            operations,
+            [
+                "DELETE /items/{id} deleteItem",
+                "GET /items listItems",
+                "GET /items/{id} getItem",
+                "POST /items createItem",
+                "PUT /items/{id} updateItem",
+            ]
+        );
+
+        // Schemas come from the structs the handlers use
+        let item = &spec["components"]["schemas"]["Item"];
+        assert_eq!(item["properties"]["id"]["format"], "uuid");
+        assert_eq!(spec["paths"]["/items/{id}"]["put"]["requestBody"]["content"]["application/json"]["schema"]["$ref"], "#/components/schemas/UpdateItem");
+        assert!(spec["components"]["schemas"]["UpdateItem"]["required"].as_array().unwrap().contains(&json!("name")));
+    }
+
+    #[tokio::test]
+    async fn test_docs_serves_swagger_ui() {
+        let response = warp::test::request().path("/docs").reply(&routes()).await;
+        assert_eq!(response.status(), 200);
+        assert!(String::from_utf8_lossy(response.body()).contains("url: \"/openapi.json\""));
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::http::header::HeaderValue;
+use warp::http::{HeaderMap, Method, StatusCode};
+use warp::hyper::body::{self, Body, Bytes};
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+/// Set on every GET response: `HIT` when it came from the cache, `MISS` when
+/// the routes computed it.
+pub const CACHE_STATUS_HEADER: &str = "x-cache";
+
+const DEFAULT_TTL_SECS: u64 = 5;
+
+// Beyond this many live entries, new responses are served but not stored,
+// so a crawl over many query strings can't grow the cache without bound
+const MAX_ENTRIES: usize = 1024;
+
+struct CachedResponse {
+    path: String,
+    headers: HeaderMap,
+    body: Bytes,
+    stored: Instant,
+}
+
+/// Successful GET responses kept for `ttl`, keyed by method, path, and query.
+///
+/// A POST, PUT, PATCH, or DELETE evicts the entries for its path, the paths
+/// above it, and the paths below it: `PUT /items/7` drops `/items/7` and
+/// every `/items?...` listing, while `POST /items` drops everything under
+/// `/items`. Other items stay cached.
+pub struct ResponseCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, CachedResponse>>,
+    // Bumped on every invalidation, so a GET that started before a write
+    // doesn't store what it read from before that write
+    generation: AtomicU64,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration) -> Self {
+        ResponseCache { ttl, entries: Mutex::new(HashMap::new()), generation: AtomicU64::new(0) }
+    }
+
+    fn get(&self, key: &str) -> Option<Response> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+        if entry.stored.elapsed() >= self.ttl {
+            entries.remove(key);
+            return None;
+        }
+        let mut response = Response::new(Body::from(entry.body.clone()));
+        *response.headers_mut() = entry.headers.clone();
+        response.headers_mut().insert(CACHE_STATUS_HEADER, HeaderValue::from_static("HIT"));
+        Some(response)
+    }
+
+    fn insert(&self, key: String, path: &str, generation: u64, headers: HeaderMap, body: Bytes) {
+        let mut entries = self.entries.lock().unwrap();
+        // Checked under the lock `invalidate` takes, so the two can't interleave
+        if self.generation.load(Ordering::SeqCst) != generation {
+            return;
+        }
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, entry| entry.stored.elapsed() < self.ttl);
+            if entries.len() >= MAX_ENTRIES {
+                return;
+            }
+        }
+        entries.insert(key, CachedResponse { path: path.to_string(), headers, body, stored: Instant::now() });
+    }
+
+    /// Evicts the entries for `path`, its ancestors, and its descendants.
+    pub fn invalidate(&self, path: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        entries.retain(|_, entry| !is_related(&entry.path, path));
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+}
+
+/// How long GET responses stay cached: `NOXIUM_RESPONSE_CACHE_TTL_SECS`, or
+/// 5 seconds. 0 turns caching off.
+pub fn cache_ttl() -> Duration {
+    match env::var("NOXIUM_RESPONSE_CACHE_TTL_SECS") {
+        Ok(value) => match value.trim().parse::<u64>() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => {
+                log::warn!(
+                    "Ignoring invalid NOXIUM_RESPONSE_CACHE_TTL_SECS {:?}, using {} seconds",
+                    value,
+                    DEFAULT_TTL_SECS
+                );
+                Duration::from_secs(DEFAULT_TTL_SECS)
+            }
+        },
+        Err(_) => Duration::from_secs(DEFAULT_TTL_SECS),
+    }
+}
+
+// Whether one of the paths contains the other, segment-wise
+fn is_related(a: &str, b: &str) -> bool {
+    let within = |inner: &str, outer: &str| {
+        let outer = outer.trim_end_matches('/');
+        inner.strip_prefix(outer).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    };
+    within(a, b) || within(b, a)
+}
+
+// What a request needs to be looked up, stored, or invalidated
+struct RequestKey {
+    method: Method,
+    path: String,
+    key: String,
+    generation: u64,
+}
+
+fn request_key(cache: Arc<ResponseCache>) -> impl Filter<Extract = (RequestKey,), Error = Infallible> + Clone {
+    let query = warp::query::raw().or(warp::any().map(String::new)).unify();
+    warp::method().and(warp::path::full()).and(query).map(move |method: Method, path: FullPath, query: String| {
+        let path = path.as_str().to_string();
+        let key = if query.is_empty() { format!("{} {}", method, path) } else { format!("{} {}?{}", method, path, query) };
+        RequestKey { method, path, key, generation: cache.generation.load(Ordering::SeqCst) }
+    })
+}
+
+async fn store(cache: Arc<ResponseCache>, request: RequestKey, reply: impl Reply) -> Response {
+    let response = reply.into_response();
+    if matches!(request.method, Method::POST | Method::PUT | Method::PATCH | Method::DELETE) {
+        cache.invalidate(&request.path);
+        return response;
+    }
+    if request.method != Method::GET {
+        return response;
+    }
+    if response.status() != StatusCode::OK {
+        let mut response = response;
+        response.headers_mut().insert(CACHE_STATUS_HEADER, HeaderValue::from_static("MISS"));
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            log::error!("Failed to read response body for caching: {}", e);
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            return response;
+        }
+    };
+    cache.insert(request.key, &request.path, request.generation, parts.headers.clone(), bytes.clone());
+    parts.headers.insert(CACHE_STATUS_HEADER, HeaderValue::from_static("MISS"));
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// Wraps a server's recovered routes with `cache`:
+/// `with_response_cache(cache, routes.recover(handle_rejection))`. Apply it
+/// inside `with_compression` so one cached body serves every encoding.
+pub fn with_response_cache<F, R>(
+    cache: Arc<ResponseCache>,
+    routes: F,
+) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply + Send,
+{
+    let hit_cache = cache.clone();
+    let hit = warp::get().and(request_key(cache.clone())).and_then(move |request: RequestKey| {
+        let cached = hit_cache.get(&request.key);
+        // A miss falls through to the routes
+        async move { cached.ok_or_else(warp::reject) }
+    });
+
+    let store_cache = cache.clone();
+    let miss = request_key(cache)
+        .and(routes)
+        .then(move |request: RequestKey, reply: R| store(store_cache.clone(), request, reply));
+
+    hit.or(miss).unify()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    // A stand-in for the item API that counts how often each GET is computed
+    fn counted_routes(
+        computed: Arc<AtomicUsize>,
+    ) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone {
+        let list_count = computed.clone();
+        let list = warp::path!("items").and(warp::get()).map(move || {
+            let n = list_count.fetch_add(1, Ordering::SeqCst);
+            warp::reply::json(&vec![format!("list {}", n)]).into_response()
+        });
+        let item_count = computed.clone();
+        let item = warp::path!("items" / u32).and(warp::get()).map(move |id: u32| {
+            let n = item_count.fetch_add(1, Ordering::SeqCst);
+            warp::reply::json(&format!("item {} v{}", id, n)).into_response()
+        });
+        let put = warp::path!("items" / u32).and(warp::put()).map(|_| "Item updated".into_response());
+        let missing = warp::any().map(|| StatusCode::NOT_FOUND.into_response());
+        list.or(item).unify().or(put).unify().or(missing).unify()
+    }
+
+    async fn get(
+        filter: &(impl Filter<Extract = (Response,), Error = Infallible> + Clone + 'static),
+        path: &str,
+    ) -> (String, String) {
+        let response = warp::test::request().path(path).reply(filter).await;
+        let status = response.headers()[CACHE_STATUS_HEADER].to_str().unwrap().to_string();
+        (status, String::from_utf8(response.body().to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_repeat_gets_hit_until_a_write_invalidates() {
+        let computed = Arc::new(AtomicUsize::new(0));
+        let cache = Arc::new(ResponseCache::new(Duration::from_secs(60)));
+        let filter = with_response_cache(cache.clone(), counted_routes(computed.clone()));
+
+        assert_eq!(get(&filter, "/items").await, ("MISS".to_string(), "[\"list 0\"]".to_string()));
+        assert_eq!(get(&filter, "/items").await, ("HIT".to_string(), "[\"list 0\"]".to_string()));
+        assert_eq!(computed.load(Ordering::SeqCst), 1);
+
+        // The query is part of the key
+        assert_eq!(get(&filter, "/items?page=2").await.0, "MISS");
+        assert_eq!(get(&filter, "/items/1").await.0, "MISS");
+        assert_eq!(get(&filter, "/items/2").await.0, "MISS");
+        assert_eq!(cache.len(), 4);
+
+        let put = warp::test::request().method("PUT").path("/items/1").reply(&filter).await;
+        assert_eq!(put.status(), StatusCode::OK);
+        assert!(put.headers().get(CACHE_STATUS_HEADER).is_none());
+
+        // Both listings and item 1 were evicted; item 2 was not
+        assert_eq!(cache.len(), 1);
+        assert_eq!(get(&filter, "/items").await, ("MISS".to_string(), "[\"list 4\"]".to_string()));
+        assert_eq!(get(&filter, "/items/1").await, ("MISS".to_string(), "\"item 1 v5\"".to_string()));
+        assert_eq!(get(&filter, "/items/2").await, ("HIT".to_string(), "\"item 2 v3\"".to_string()));
+        assert_eq!(computed.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn test_errors_and_expired_entries_are_not_served() {
+        let computed = Arc::new(AtomicUsize::new(0));
+        let cache = Arc::new(ResponseCache::new(Duration::ZERO));
+        let filter = with_response_cache(cache.clone(), counted_routes(computed.clone()));
+
+        assert_eq!(get(&filter, "/items").await.0, "MISS");
+        assert_eq!(get(&filter, "/items").await.0, "MISS");
+        assert_eq!(computed.load(Ordering::SeqCst), 2);
+
+        let cache = Arc::new(ResponseCache::new(Duration::from_secs(60)));
+        let filter = with_response_cache(cache.clone(), counted_routes(computed));
+        let missing = warp::test::request().path("/nothing").reply(&filter).await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_related_paths() {
+        assert!(is_related("/items", "/items/1"));
+        assert!(is_related("/items/1", "/items"));
+        assert!(is_related("/items/1", "/items/1"));
+        assert!(!is_related("/items/1", "/items/2"));
+        assert!(!is_related("/items", "/itemsets"));
+    }
+}
@@ -1,6 +1,21 @@
 use actix_web::{web, App, HttpResponse, HttpServer, Responder};
 use serde::{Deserialize, Serialize};
 
+#[path = "../../body_limit.rs"]
+#[allow(dead_code)]
+mod body_limit;
+use body_limit::{actix_body_limits, max_body_bytes};
+
+#[path = "../../request_log.rs"]
+#[allow(dead_code)]
+mod request_log;
+use request_log::RequestLogger;
+
+#[path = "../../timeout.rs"]
+#[allow(dead_code)]
+mod timeout;
+use timeout::{request_timeout, Timeout};
+
 #[derive(Debug, Serialize, Deserialize)]
 struct RequestData {
     message: String,
@@ -20,8 +35,11 @@ async fn receive_post_request(data: web::Json<RequestData>) -> impl Responder {
 async fn main() -> std::io::Result<()> {
     HttpServer::new(|| {
         App::new()
+            .configure(actix_body_limits(max_body_bytes()))
             .route("/receive_get_request", web::get().to(receive_get_request))
             .route("/receive_post_request", web::post().to(receive_post_request))
+            .wrap(Timeout::new(request_timeout()))
+            .wrap(RequestLogger)
     })
     .bind("127.0.0.1:5500")?
     .run()
@@ -1,6 +1,26 @@
 use actix_web::{web, App, HttpResponse, HttpServer, Responder};
 use serde::Deserialize;
 
+#[path = "../../body_limit.rs"]
+#[allow(dead_code)]
+mod body_limit;
+use body_limit::{actix_body_limits, max_body_bytes};
+
+#[path = "../../request_log.rs"]
+#[allow(dead_code)]
+mod request_log;
+use request_log::RequestLogger;
+
+#[path = "../../timeout.rs"]
+#[allow(dead_code)]
+mod timeout;
+use timeout::{request_timeout, Timeout};
+
+#[path = "../../shutdown.rs"]
+#[allow(dead_code)]
+mod shutdown;
+use shutdown::shutdown_signal;
+
 #[derive(Deserialize)]
 struct Info {
     username: String,
@@ -17,11 +37,23 @@ async fn validate_user(info: web::Json<Info>) -> impl Responder {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    HttpServer::new(|| {
+    let server = HttpServer::new(|| {
         App::new()
+            .configure(actix_body_limits(max_body_bytes()))
             .route("/validate", web::post().to(validate_user))
+            .wrap(Timeout::new(request_timeout()))
+            .wrap(RequestLogger)
     })
+    // Stopped below on shutdown_signal, like the other servers
+    .disable_signals()
     .bind("127.0.0.1:5500")?
-    .run()
-    .await
+    .run();
+
+    // Finish in-flight requests before exiting
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        handle.stop(true).await;
+    });
+    server.await
 }
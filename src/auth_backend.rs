@@ -0,0 +1,151 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// A user whose password has been checked.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuthedUser {
+    pub username: String,
+}
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    /// Unknown user or wrong password; callers should not say which
+    #[error("Invalid username or password")]
+    InvalidCredentials,
+    #[error("Authentication backend failed: {0}")]
+    Backend(String),
+}
+
+/// Where usernames and their bcrypt password hashes come from.
+///
+/// Handlers take it as `web::Data<Box<dyn AuthBackend>>` (actix) or an
+/// `Arc<dyn AuthBackend>` (warp), so the store can be swapped without
+/// touching them.
+#[async_trait]
+pub trait AuthBackend: Send + Sync {
+    async fn verify(&self, username: &str, password: &str) -> Result<AuthedUser, AuthError>;
+}
+
+// bcrypt is deliberately slow, so keep it off the async worker threads
+async fn check_password(username: &str, password: &str, password_hash: String) -> Result<AuthedUser, AuthError> {
+    let password = password.to_string();
+    let matches = tokio::task::spawn_blocking(move || bcrypt::verify(password, &password_hash))
+        .await
+        .map_err(|e| AuthError::Backend(e.to_string()))?;
+    match matches {
+        Ok(true) => Ok(AuthedUser { username: username.to_string() }),
+        Ok(false) => Err(AuthError::InvalidCredentials),
+        // A stored value that isn't a bcrypt hash can never match
+        Err(e) => {
+            log::error!("Unusable password hash for {}: {}", username, e);
+            Err(AuthError::InvalidCredentials)
+        }
+    }
+}
+
+/// Users held in memory, e.g. from `AUTH_USERS` (see [`MemoryBackend::parse`]).
+#[derive(Debug, Default, Clone)]
+pub struct MemoryBackend {
+    // Username -> bcrypt hash
+    users: HashMap<String, String>,
+}
+
+impl MemoryBackend {
+    pub fn insert(&mut self, username: impl Into<String>, password_hash: impl Into<String>) {
+        self.users.insert(username.into(), password_hash.into());
+    }
+
+    /// Reads `user:hash` pairs separated by commas, e.g.
+    /// `AUTH_USERS='admin:$2b$12$...,ops:$2b$12$...'`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut backend = MemoryBackend::default();
+        for entry in spec.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (username, password_hash) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("Expected user:bcrypt-hash, got {:?}", entry))?;
+            backend.insert(username, password_hash);
+        }
+        Ok(backend)
+    }
+}
+
+#[async_trait]
+impl AuthBackend for MemoryBackend {
+    async fn verify(&self, username: &str, password: &str) -> Result<AuthedUser, AuthError> {
+        let password_hash = self.users.get(username).ok_or(AuthError::InvalidCredentials)?;
+        check_password(username, password, password_hash.clone()).await
+    }
+}
+
+/// Users from the `users (username, password)` table, `password` holding a bcrypt hash.
+#[derive(Debug, Clone)]
+pub struct SqliteBackend {
+    pool: SqlitePool,
+}
+
+impl SqliteBackend {
+    pub fn new(pool: SqlitePool) -> Self {
+        SqliteBackend { pool }
+    }
+}
+
+#[async_trait]
+impl AuthBackend for SqliteBackend {
+    async fn verify(&self, username: &str, password: &str) -> Result<AuthedUser, AuthError> {
+        let row: Option<(String,)> = sqlx::query_as("SELECT password FROM users WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| AuthError::Backend(e.to_string()))?;
+        let (password_hash,) = row.ok_or(AuthError::InvalidCredentials)?;
+        check_password(username, password, password_hash).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    // The lowest cost bcrypt allows, to keep the tests fast
+    fn quick_hash(password: &str) -> String {
+        bcrypt::hash(password, 4).unwrap()
+    }
+
+    async fn assert_verifies_alice(backend: &dyn AuthBackend) {
+        assert_eq!(backend.verify("alice", "wonderland").await.unwrap(), AuthedUser { username: "alice".to_string() });
+        assert!(matches!(backend.verify("alice", "looking-glass").await, Err(AuthError::InvalidCredentials)));
+        assert!(matches!(backend.verify("bob", "wonderland").await, Err(AuthError::InvalidCredentials)));
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend() {
+        let backend = MemoryBackend::parse(&format!("alice:{}, carol:not-a-hash", quick_hash("wonderland"))).unwrap();
+        assert_verifies_alice(&backend).await;
+        assert!(matches!(backend.verify("carol", "not-a-hash").await, Err(AuthError::InvalidCredentials)));
+        assert!(MemoryBackend::parse("alice").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_backend() {
+        let pool = SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+        let backend = SqliteBackend::new(pool.clone());
+        // No users table yet
+        assert!(matches!(backend.verify("alice", "wonderland").await, Err(AuthError::Backend(_))));
+
+        sqlx::query("CREATE TABLE users (username TEXT PRIMARY KEY, password TEXT NOT NULL)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO users (username, password) VALUES (?, ?)")
+            .bind("alice")
+            .bind(quick_hash("wonderland"))
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_verifies_alice(&backend).await;
+    }
+}
@@ -0,0 +1,32 @@
+use std::env;
+use std::net::SocketAddr;
+
+/// The address a server listens on, taken from `NOXIUM_BIND_ADDR`
+/// (`host:port`; port 0 picks a free one), or `default` when it is unset.
+pub fn bind_addr(default: SocketAddr) -> SocketAddr {
+    parse_bind_addr(env::var("NOXIUM_BIND_ADDR").ok().as_deref(), default)
+}
+
+fn parse_bind_addr(value: Option<&str>, default: SocketAddr) -> SocketAddr {
+    match value {
+        Some(value) => value.trim().parse().unwrap_or_else(|_| {
+            log::warn!("Ignoring invalid NOXIUM_BIND_ADDR {:?}, using {}", value, default);
+            default
+        }),
+        None => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_the_configured_address_or_falls_back() {
+        let default = SocketAddr::from(([127, 0, 0, 1], 3030));
+        assert_eq!(parse_bind_addr(None, default), default);
+        assert_eq!(parse_bind_addr(Some(" 0.0.0.0:8080 "), default), SocketAddr::from(([0, 0, 0, 0], 8080)));
+        assert_eq!(parse_bind_addr(Some("127.0.0.1:0"), default).port(), 0);
+        assert_eq!(parse_bind_addr(Some("localhost"), default), default);
+    }
+}
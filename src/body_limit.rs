@@ -0,0 +1,101 @@
+use actix_web::web;
+use std::env;
+use warp::{Filter, Rejection};
+
+/// Request body cap used when `NOXIUM_MAX_BODY_BYTES` is unset: 1 MiB.
+pub const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// The request body limit shared by the actix and warp servers, taken from
+/// `NOXIUM_MAX_BODY_BYTES` (in bytes).
+pub fn max_body_bytes() -> usize {
+    match env::var("NOXIUM_MAX_BODY_BYTES") {
+        Ok(value) => match value.trim().parse::<usize>() {
+            Ok(limit) if limit > 0 => limit,
+            _ => {
+                log::warn!(
+                    "Ignoring invalid NOXIUM_MAX_BODY_BYTES {:?}, using {} bytes",
+                    value,
+                    DEFAULT_MAX_BODY_BYTES
+                );
+                DEFAULT_MAX_BODY_BYTES
+            }
+        },
+        Err(_) => DEFAULT_MAX_BODY_BYTES,
+    }
+}
+
+/// Caps the `Json`, `Form`, `Bytes`, and `String` extractors at `limit`
+/// bytes, mounted with `App::new().configure(actix_body_limits(limit))`.
+///
+/// Oversized bodies are answered with `413 Payload Too Large` before the
+/// handler runs. Streaming extractors (`web::Payload`, multipart) are not
+/// covered and must enforce their own limits.
+pub fn actix_body_limits(limit: usize) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |cfg| {
+        cfg.app_data(web::JsonConfig::default().limit(limit))
+            .app_data(web::FormConfig::default().limit(limit))
+            .app_data(web::PayloadConfig::new(limit));
+    }
+}
+
+/// Rejects requests whose `Content-Length` exceeds `limit` bytes with
+/// `413 Payload Too Large`; place it before `warp::body::json()`.
+///
+/// Requests without a `Content-Length` are rejected with `411 Length Required`.
+pub fn warp_body_limit(limit: usize) -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    warp::body::content_length_limit(limit as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App, HttpResponse};
+    use serde_json::{json, Value};
+
+    const LIMIT: usize = 64;
+
+    // A JSON body of exactly `len` bytes
+    fn body_of_len(len: usize) -> String {
+        let body = json!({ "data": "x".repeat(len - 11) }).to_string();
+        assert_eq!(body.len(), len);
+        body
+    }
+
+    #[actix_web::test]
+    async fn test_actix_json_limit() {
+        let app = test::init_service(
+            App::new()
+                .configure(actix_body_limits(LIMIT))
+                .route("/echo", web::post().to(|body: web::Json<Value>| async move { HttpResponse::Ok().json(body.into_inner()) })),
+        )
+        .await;
+
+        let request = test::TestRequest::post()
+            .uri("/echo")
+            .insert_header(("content-type", "application/json"))
+            .set_payload(body_of_len(LIMIT))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), 200);
+
+        let request = test::TestRequest::post()
+            .uri("/echo")
+            .insert_header(("content-type", "application/json"))
+            .set_payload(body_of_len(LIMIT + 1))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), 413);
+    }
+
+    #[tokio::test]
+    async fn test_warp_json_limit() {
+        let route = warp::post()
+            .and(warp_body_limit(LIMIT))
+            .and(warp::body::json())
+            .map(|body: Value| warp::reply::json(&body));
+
+        let response = warp::test::request().method("POST").body(body_of_len(LIMIT)).reply(&route).await;
+        assert_eq!(response.status(), 200);
+
+        let response = warp::test::request().method("POST").body(body_of_len(LIMIT + 1)).reply(&route).await;
+        assert_eq!(response.status(), 413);
+    }
+}
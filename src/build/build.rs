@@ -1,14 +1,20 @@
-use notify::{watcher, RecursiveMode, Watcher};
 use regex::Regex;
 use std::env;
 use std::fs;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::mpsc::channel;
+use std::thread;
 use std::time::Duration;
 use toml::de::from_str as toml_from_str;
 
+#[path = "../fingerprint.rs"]
+mod fingerprint;
+use fingerprint::fingerprint_assets;
+
+mod watcher;
+use watcher::watch_debounced;
+
 const CONFIG_FILE: &str = "build.toml";
 
 #[derive(Debug, serde::Deserialize)]
@@ -19,6 +25,7 @@ struct BuildConfig {
     html: Option<ConfigOptions>,
     images: Option<ConfigOptions>,
     custom_commands: Option<Vec<String>>,
+    fingerprint: Option<FingerprintConfig>,
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -28,6 +35,15 @@ struct ConfigOptions {
     options: Option<Vec<String>>,
 }
 
+#[derive(Debug, serde::Deserialize)]
+struct FingerprintConfig {
+    // Root of the built site; every asset under it is renamed to name.<hash>.ext
+    output: String,
+    // Also keep the unhashed files, for links from outside the site
+    #[serde(default)]
+    keep_originals: bool,
+}
+
 fn main() {
     // Load configuration
     let config = match load_config(CONFIG_FILE) {
@@ -38,24 +54,19 @@ fn main() {
         }
     };
 
-    // Create a channel for file system events
-    let (tx, rx) = channel();
-
-    // Create a watcher to monitor changes in the "src" directory
-    let mut watcher = watcher(tx, Duration::from_secs(2)).unwrap();
-    watcher.watch("src", RecursiveMode::Recursive).unwrap();
+    // Rebuild once changes in the "src" directory settle
+    let _watcher = match watch_debounced(&[PathBuf::from("src")], None, Duration::from_secs(2), move || build(&config)) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            eprintln!("Failed to watch 'src': {:?}", e);
+            return;
+        }
+    };
     println!("Watching for changes in the 'src' directory...");
 
-    // Main loop to handle file system events
+    // Rebuilds happen on the watcher's thread
     loop {
-        match rx.recv() {
-            Ok(_) => {
-                // Rebuild when changes are detected
-                println!("Changes detected. Rebuilding...");
-                build(&config);
-            }
-            Err(e) => eprintln!("Watch error: {:?}", e),
-        }
+        thread::park();
     }
 }
 
@@ -130,6 +141,14 @@ fn build(config: &BuildConfig) {
         }
     }
 
+    // Fingerprint last, once every step has written its output
+    if let Some(fingerprint) = &config.fingerprint {
+        match fingerprint_assets(Path::new(&fingerprint.output), fingerprint.keep_originals) {
+            Ok(manifest) => println!("Fingerprinted {} assets.", manifest.len()),
+            Err(e) => eprintln!("Failed to fingerprint assets: {:?}", e),
+        }
+    }
+
     println!("Build complete.");
 }
 
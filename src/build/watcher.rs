@@ -0,0 +1,61 @@
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Calls `on_change` on a background thread when anything under `paths` is
+/// created, modified or removed.
+///
+/// Changes are debounced: `on_change` runs once nothing has changed for
+/// `debounce`, so saving several files at once triggers it only once.
+/// Changes under `ignored`, e.g. a build's own output directory, are left
+/// out. Watching stops when the returned watcher is dropped.
+pub fn watch_debounced<F>(
+    paths: &[PathBuf],
+    ignored: Option<&Path>,
+    debounce: Duration,
+    mut on_change: F,
+) -> notify::Result<RecommendedWatcher>
+where
+    F: FnMut() + Send + 'static,
+{
+    let ignored = ignored.map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let relevant = match event {
+            Ok(event) => {
+                matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_))
+                    && event.paths.iter().any(|path| ignored.as_ref().is_none_or(|ignored| !path.starts_with(ignored)))
+            }
+            Err(e) => {
+                log::error!("Watch error: {}", e);
+                false
+            }
+        };
+        if relevant {
+            let _ = tx.send(());
+        }
+    })?;
+    for path in paths {
+        let mode = if path.is_dir() { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        watcher.watch(path, mode)?;
+    }
+
+    thread::spawn(move || {
+        // Ends once the watcher, and with it the sender, is dropped
+        while rx.recv().is_ok() {
+            loop {
+                match rx.recv_timeout(debounce) {
+                    Ok(()) => continue,
+                    Err(mpsc::RecvTimeoutError::Timeout) => break,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                }
+            }
+            println!("Changes detected. Rebuilding...");
+            on_change();
+        }
+    });
+    Ok(watcher)
+}
@@ -1,54 +1,686 @@
-use hyper::{Body, Request, Response, Server, Method, StatusCode};
+use hyper::{Body, Client, Request, Response, Server, Method, StatusCode, Uri};
+use hyper::client::HttpConnector;
+use hyper::server::accept;
+use hyper::server::conn::{AddrIncoming, AddrStream};
 use hyper::service::{make_service_fn, service_fn};
-use hyper::header::{CONTENT_TYPE, CONTENT_ENCODING, CACHE_CONTROL, AUTHORIZATION};
-use hyper_rustls::HttpsConnectorBuilder;
+use hyper::header::{HeaderMap, HeaderName, CONTENT_TYPE, CONTENT_ENCODING, CACHE_CONTROL, AUTHORIZATION, ETAG, HOST, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, REFERER, RETRY_AFTER, USER_AGENT};
+use hyper::body::HttpBody;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
 use tokio::fs::{File, read_dir};
 use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Mutex;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
 use std::convert::Infallible;
-use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
-use std::path::PathBuf;
-use std::time::{SystemTime, Duration};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, Duration, Instant};
 use mime_guess::from_path;
 use futures::future::{BoxFuture, FutureExt};
+use futures::stream::{self, StreamExt};
 use log::{info, warn, error};
-use env_logger;
-use rustls::{Certificate, PrivateKey, ServerConfig};
-use std::fs;
-use serde::Deserialize;
+use std::io::Write;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+#[allow(dead_code)]
+mod rate_limit;
+use rate_limit::RateLimiter;
+
+mod access_log;
+use access_log::{AccessLog, AccessLogEntry};
+
+#[allow(dead_code)]
+mod shutdown;
+use shutdown::shutdown_signal;
+
+#[allow(dead_code)]
+mod escaping;
+use escaping::{attr_escape, html_escape, path_segment_encode};
+
+mod reload;
+use reload::{diff, read_env_file, reload_on_sighup, LiveConfig};
+
+mod tls;
+use tls::tls_config;
 
 #[derive(Debug, Deserialize)]
 struct Config {
     rate_limit: u32,
+    // Proxies whose X-Forwarded-For is believed when rate limiting; everyone
+    // else is limited by the address they connect from
+    trusted_proxies: Vec<IpAddr>,
     cache_duration: u64,
-    auth_username: String,
-    auth_password: String,
+    // Seconds past `cache_duration` that an expired entry is still served while
+    // a background task refreshes it; 0 disables stale-while-revalidate
+    stale_grace: u64,
+    // Logins accepted for Basic auth
+    credentials: Vec<Credential>,
+    // Upper bound on bytes held in memory by the cache
+    max_cache_bytes: usize,
+    // Directory that evicted or oversized entries spill to; disabled when unset
+    cache_dir: Option<PathBuf>,
+    // Directory request paths are served from; defaults to the working directory
+    root: PathBuf,
+    bind_addr: IpAddr,
+    // Defaults to 443 when TLS is configured and 8080 otherwise
+    port: u16,
+    // Both must be set to serve HTTPS; with neither the server speaks plain HTTP
+    tls_cert_path: Option<PathBuf>,
+    tls_key_path: Option<PathBuf>,
+    // Upstream (`scheme://host[:port]`) that requests missing on disk are proxied to
+    origin_url: Option<String>,
+    // Preload the cache from the served directory before accepting requests
+    warm_cache: bool,
+    // Files read at once during warmup
+    warm_concurrency: usize,
+    // Combined Log Format access log, separate from the console logger; disabled when unset
+    access_log: Option<PathBuf>,
+    // Size at which the access log is rotated, and how many rotated files are kept
+    access_log_max_bytes: u64,
+    access_log_max_files: usize,
+}
+
+// A Basic auth login. Passwords in bcrypt's `$2a$`/`$2b$`/`$2y$` format are
+// checked as hashes, anything else as plaintext
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct Credential {
+    username: String,
+    password: String,
+}
+
+impl Credential {
+    fn is_bcrypt(&self) -> bool {
+        ["$2a$", "$2b$", "$2y$"].iter().any(|prefix| self.password.starts_with(prefix))
+    }
+}
+
+// Parse `AUTH_CREDENTIALS`: comma-separated `username:password` pairs
+fn parse_credentials(spec: &str) -> Result<Vec<Credential>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once(':') {
+            Some((username, password)) if !username.is_empty() => Ok(Credential {
+                username: username.to_string(),
+                password: password.to_string(),
+            }),
+            _ => Err(format!("AUTH_CREDENTIALS entries must be username:password, got {:?}", entry)),
+        })
+        .collect()
+}
+
+impl Config {
+    // `env`, which is the process environment outside tests, over the
+    // `KEY=VALUE` file at `file` when there is one, as with `NoxiumConfig`.
+    // Only the file can change on reload, so keys meant to be reloaded are
+    // best left out of the environment
+    fn load(
+        file: Option<&Path>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let file = match file {
+            Some(path) => read_env_file(path)?,
+            None => HashMap::new(),
+        };
+        Self::from_lookup(|key| env(key).or_else(|| file.get(key).cloned()))
+    }
+
+    fn from_lookup(var: impl Fn(&str) -> Option<String>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let env_or = |key: &str, default: &str| var(key).unwrap_or_else(|| default.to_string());
+
+        let tls_cert_path = var("TLS_CERT_PATH").map(PathBuf::from);
+        let tls_key_path = var("TLS_KEY_PATH").map(PathBuf::from);
+        if tls_cert_path.is_some() != tls_key_path.is_some() {
+            return Err("TLS_CERT_PATH and TLS_KEY_PATH must be set together".into());
+        }
+        let default_port = if tls_cert_path.is_some() { "443" } else { "8080" };
+
+        let origin_url = var("ORIGIN_URL");
+        if let Some(url) = &origin_url {
+            let uri: Uri = url.parse()?;
+            if !matches!(uri.scheme_str(), Some("http" | "https")) || uri.host().is_none() {
+                return Err(format!("ORIGIN_URL must be an http(s) URL, got {}", url).into());
+            }
+        }
+
+        let warm_concurrency: usize = env_or("WARM_CONCURRENCY", "8").parse()?;
+        if warm_concurrency == 0 {
+            return Err("WARM_CONCURRENCY must be greater than 0".into());
+        }
+
+        let rate_limit: u32 = env_or("RATE_LIMIT", "100").parse()?;
+        if rate_limit == 0 {
+            return Err("RATE_LIMIT must be greater than 0".into());
+        }
+
+        // Comma-separated addresses of reverse proxies in front of the CDN
+        let trusted_proxies = match var("TRUSTED_PROXIES") {
+            Some(spec) => spec
+                .split(',')
+                .map(str::trim)
+                .filter(|ip| !ip.is_empty())
+                .map(|ip| ip.parse().map_err(|_| format!("TRUSTED_PROXIES has an invalid address {:?}", ip)))
+                .collect::<Result<Vec<IpAddr>, _>>()?,
+            None => Vec::new(),
+        };
+
+        // AUTH_CREDENTIALS takes precedence over the single AUTH_USERNAME/AUTH_PASSWORD login
+        let credentials = match var("AUTH_CREDENTIALS") {
+            Some(spec) => parse_credentials(&spec)?,
+            None => vec![Credential {
+                username: env_or("AUTH_USERNAME", "user"),
+                password: env_or("AUTH_PASSWORD", "pass"),
+            }],
+        };
+        if credentials.is_empty() {
+            return Err("AUTH_CREDENTIALS must list at least one username:password".into());
+        }
+
+        Ok(Config {
+            rate_limit,
+            trusted_proxies,
+            cache_duration: env_or("CACHE_DURATION", "600").parse()?,
+            stale_grace: env_or("CACHE_STALE_GRACE", "0").parse()?,
+            credentials,
+            max_cache_bytes: env_or("MAX_CACHE_BYTES", &(64 * 1024 * 1024).to_string()).parse()?,
+            cache_dir: var("CACHE_DIR").map(PathBuf::from),
+            root: PathBuf::from(env_or("ROOT_DIR", ".")),
+            bind_addr: env_or("BIND_ADDR", &Ipv4Addr::LOCALHOST.to_string()).parse()?,
+            port: env_or("PORT", default_port).parse()?,
+            tls_cert_path,
+            tls_key_path,
+            origin_url,
+            warm_cache: env_or("WARM_CACHE", "false").parse()?,
+            warm_concurrency,
+            access_log: var("ACCESS_LOG").map(PathBuf::from),
+            access_log_max_bytes: env_or("ACCESS_LOG_MAX_BYTES", &(10 * 1024 * 1024).to_string()).parse()?,
+            access_log_max_files: env_or("ACCESS_LOG_MAX_FILES", "5").parse()?,
+        })
+    }
+
+    // What a reload changed. Rate limit, cache lifetimes and logins apply to the
+    // next request; the rest is only read at startup
+    fn changes(old: &Config, new: &Config) -> Vec<String> {
+        let mut changes = Vec::new();
+        diff(&mut changes, "rate_limit", &old.rate_limit, &new.rate_limit);
+        diff(&mut changes, "trusted_proxies", &old.trusted_proxies, &new.trusted_proxies);
+        diff(&mut changes, "cache_duration", &old.cache_duration, &new.cache_duration);
+        diff(&mut changes, "stale_grace", &old.stale_grace, &new.stale_grace);
+        // Never log passwords
+        if old.credentials != new.credentials {
+            let usernames: Vec<&str> = new.credentials.iter().map(|c| c.username.as_str()).collect();
+            changes.push(format!("credentials: now for {}", usernames.join(", ")));
+        }
+
+        let mut restart = Vec::new();
+        diff(&mut restart, "max_cache_bytes", &old.max_cache_bytes, &new.max_cache_bytes);
+        diff(&mut restart, "cache_dir", &old.cache_dir, &new.cache_dir);
+        diff(&mut restart, "root", &old.root, &new.root);
+        diff(&mut restart, "bind_addr", &old.bind_addr, &new.bind_addr);
+        diff(&mut restart, "port", &old.port, &new.port);
+        diff(&mut restart, "tls_cert_path", &old.tls_cert_path, &new.tls_cert_path);
+        diff(&mut restart, "tls_key_path", &old.tls_key_path, &new.tls_key_path);
+        diff(&mut restart, "origin_url", &old.origin_url, &new.origin_url);
+        diff(&mut restart, "access_log", &old.access_log, &new.access_log);
+        changes.extend(restart.into_iter().map(|change| format!("{} (applies after a restart)", change)));
+        changes
+    }
+}
+
+// Where a cached body currently lives
+enum CacheData {
+    Memory(Vec<u8>),
+    Disk(PathBuf),
+}
+
+// Metadata written next to spilled bodies so the on-disk cache is self-describing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheMeta {
+    content_type: String,
+    encoding: Option<String>,
+    // Validator and freshness from the origin, for proxied entries
+    #[serde(default)]
+    etag: Option<String>,
+    #[serde(default)]
+    cache_control: Option<String>,
+    // HTTP date the body was last changed: the file's mtime, or the origin's header
+    #[serde(default)]
+    last_modified: Option<String>,
 }
 
 struct CacheEntry {
+    data: CacheData,
+    size: usize,
+    created: SystemTime,
+    last_access: u64,
+    meta: CacheMeta,
+}
+
+// A cache hit, ready to be written into a response
+struct CachedFile {
     data: Vec<u8>,
-    last_access: SystemTime,
     content_type: String,
     encoding: Option<String>,
+    etag: Option<String>,
+    cache_control: Option<String>,
+    last_modified: Option<String>,
+    // Past its freshness window but within the grace period
+    stale: bool,
+}
+
+// Two-tier cache: a size-bounded in-memory LRU that spills cold entries to disk
+struct CdnCache {
+    entries: HashMap<String, CacheEntry>,
+    // Access tick -> key, oldest first
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    memory_bytes: usize,
+    max_memory_bytes: usize,
+    spill_dir: Option<PathBuf>,
+    // Keys with a background refresh in flight. Shared with each refresh's
+    // claim, which has to release the key from `Drop`, outside the cache lock
+    refreshing: Arc<std::sync::Mutex<HashSet<String>>>,
+}
+
+// A claimed background refresh; dropping it, even while unwinding from a
+// panicked refresh, lets the next stale hit start another
+struct RefreshClaim {
+    refreshing: Arc<std::sync::Mutex<HashSet<String>>>,
+    key: String,
+}
+
+impl Drop for RefreshClaim {
+    fn drop(&mut self) {
+        self.refreshing.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&self.key);
+    }
+}
+
+impl CdnCache {
+    fn new(max_memory_bytes: usize, spill_dir: Option<PathBuf>) -> Self {
+        CdnCache {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            memory_bytes: 0,
+            max_memory_bytes,
+            spill_dir,
+            refreshing: Arc::new(std::sync::Mutex::new(HashSet::new())),
+        }
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.memory_bytes
+    }
+
+    #[cfg(test)]
+    fn contains(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    fn is_in_memory(&self, key: &str) -> bool {
+        matches!(self.entries.get(key), Some(CacheEntry { data: CacheData::Memory(_), .. }))
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn touch(&mut self, key: &str) {
+        let tick = self.next_tick();
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.recency.remove(&entry.last_access);
+            entry.last_access = tick;
+            self.recency.insert(tick, key.to_string());
+        }
+    }
+
+    // `max_age` applies unless the entry carries its own `max-age` from the origin.
+    // Entries up to `stale_grace` past it are returned marked stale; older ones are dropped
+    async fn get(&mut self, key: &str, max_age: Duration, stale_grace: Duration) -> Option<CachedFile> {
+        let (stale, expired) = match self.entries.get(key) {
+            Some(entry) => {
+                let max_age = entry
+                    .meta
+                    .cache_control
+                    .as_deref()
+                    .and_then(cache_control_max_age)
+                    .map_or(max_age, Duration::from_secs);
+                match entry.created.elapsed() {
+                    Ok(age) => (age >= max_age, age >= max_age.saturating_add(stale_grace)),
+                    Err(_) => (true, true),
+                }
+            }
+            None => return None,
+        };
+        if expired {
+            self.remove(key).await;
+            return None;
+        }
+
+        self.touch(key);
+        let entry = self.entries.get(key)?;
+        let data = match &entry.data {
+            CacheData::Memory(data) => data.clone(),
+            CacheData::Disk(path) => match tokio::fs::read(path).await {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!("Failed to read spilled cache entry {}: {}", key, e);
+                    self.remove(key).await;
+                    return None;
+                }
+            },
+        };
+        Some(CachedFile {
+            data,
+            content_type: entry.meta.content_type.clone(),
+            encoding: entry.meta.encoding.clone(),
+            etag: entry.meta.etag.clone(),
+            cache_control: entry.meta.cache_control.clone(),
+            last_modified: entry.meta.last_modified.clone(),
+            stale,
+        })
+    }
+
+    // Claim the background refresh of `key`, unless one is already running
+    fn begin_refresh(&self, key: &str) -> Option<RefreshClaim> {
+        let mut refreshing = self.refreshing.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        refreshing
+            .insert(key.to_string())
+            .then(|| RefreshClaim { refreshing: self.refreshing.clone(), key: key.to_string() })
+    }
+
+    // Shorthand for entries without validators, which only tests create now
+    #[cfg(test)]
+    async fn insert(&mut self, key: String, data: Vec<u8>, content_type: String, encoding: Option<String>) {
+        let meta = CacheMeta { content_type, encoding, etag: None, cache_control: None, last_modified: None };
+        self.insert_with_meta(key, data, meta).await;
+    }
+
+    async fn insert_with_meta(&mut self, key: String, data: Vec<u8>, meta: CacheMeta) {
+        self.remove(&key).await;
+
+        let size = data.len();
+
+        // Entries that can never fit in memory go straight to disk, if enabled
+        let data = if size > self.max_memory_bytes {
+            match self.write_spill(&key, &data, &meta).await {
+                Some(path) => CacheData::Disk(path),
+                None => return,
+            }
+        } else {
+            self.evict_until_fits(size).await;
+            self.memory_bytes += size;
+            CacheData::Memory(data)
+        };
+
+        let tick = self.next_tick();
+        self.recency.insert(tick, key.clone());
+        self.entries.insert(key, CacheEntry {
+            data,
+            size,
+            created: SystemTime::now(),
+            last_access: tick,
+            meta,
+        });
+    }
+
+    async fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_access);
+            match entry.data {
+                CacheData::Memory(_) => self.memory_bytes -= entry.size,
+                CacheData::Disk(path) => {
+                    let _ = tokio::fs::remove_file(&path).await;
+                    let _ = tokio::fs::remove_file(path.with_extension("meta")).await;
+                }
+            }
+        }
+    }
+
+    // Evict least-recently-used in-memory entries until `incoming` more bytes fit
+    async fn evict_until_fits(&mut self, incoming: usize) {
+        while self.memory_bytes + incoming > self.max_memory_bytes {
+            let victim = self
+                .recency
+                .values()
+                .find(|key| self.is_in_memory(key))
+                .cloned();
+            let Some(victim) = victim else { break };
+
+            if self.spill_dir.is_some() {
+                self.spill(&victim).await;
+            } else {
+                info!("Evicting cache entry: {}", victim);
+                self.remove(&victim).await;
+            }
+        }
+    }
+
+    // Move an in-memory entry to the disk tier, dropping it if the write fails
+    async fn spill(&mut self, key: &str) {
+        let Some(entry) = self.entries.get(key) else { return };
+        let CacheData::Memory(data) = &entry.data else { return };
+        let (data, meta) = (data.clone(), entry.meta.clone());
+
+        match self.write_spill(key, &data, &meta).await {
+            Some(path) => {
+                info!("Spilling cache entry to disk: {}", key);
+                if let Some(entry) = self.entries.get_mut(key) {
+                    entry.data = CacheData::Disk(path);
+                    self.memory_bytes -= entry.size;
+                }
+            }
+            None => self.remove(key).await,
+        }
+    }
+
+    async fn write_spill(&self, key: &str, data: &[u8], meta: &CacheMeta) -> Option<PathBuf> {
+        let dir = self.spill_dir.as_ref()?;
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let path = dir.join(format!("{:016x}", hasher.finish()));
+
+        let meta_json = serde_json::to_vec(meta).ok()?;
+        let written = async {
+            tokio::fs::create_dir_all(dir).await?;
+            tokio::fs::write(&path, data).await?;
+            tokio::fs::write(path.with_extension("meta"), meta_json).await
+        };
+        match written.await {
+            Ok(()) => Some(path),
+            Err(e) => {
+                error!("Failed to spill cache entry {}: {}", key, e);
+                None
+            }
+        }
+    }
+}
+
+type Cache = Arc<Mutex<CdnCache>>;
+
+// Upstream that requests missing on local disk are pulled from
+struct Origin {
+    base: String,
+    client: Client<HttpsConnector<HttpConnector>>,
+}
+
+impl Origin {
+    fn new(base: &str) -> Self {
+        let connector = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Origin {
+            base: base.trim_end_matches('/').to_string(),
+            client: Client::builder().build(connector),
+        }
+    }
+}
+
+// Headers scoped to a single connection, which a proxy must not forward
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+fn is_hop_by_hop(name: &HeaderName) -> bool {
+    HOP_BY_HOP_HEADERS.contains(&name.as_str())
+}
+
+// Freshness lifetime in seconds; `s-maxage` wins since the CDN is a shared cache
+fn cache_control_max_age(value: &str) -> Option<u64> {
+    let directive = |name: &str| {
+        value.split(',').find_map(|part| {
+            let (key, seconds) = part.trim().split_once('=')?;
+            key.trim().eq_ignore_ascii_case(name).then(|| seconds.trim().trim_matches('"').parse().ok())?
+        })
+    };
+    directive("s-maxage").or_else(|| directive("max-age"))
+}
+
+fn is_cacheable(cache_control: Option<&str>) -> bool {
+    cache_control.is_none_or(|value| {
+        value.split(',').all(|part| {
+            let directive = part.split('=').next().unwrap_or("").trim();
+            !["no-store", "no-cache", "private"].iter().any(|d| directive.eq_ignore_ascii_case(d))
+        })
+    })
+}
+
+fn bad_gateway_response(message: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .body(Body::from(message.to_string()))
+        .unwrap()
+}
+
+// Forward the request to the origin and cache successful GET responses it allows us to keep
+async fn proxy_to_origin(req: Request<Body>, origin: &Origin, cache: Cache, cache_key: String) -> Response<Body> {
+    let path_and_query = req.uri().path_and_query().map_or("/", |pq| pq.as_str());
+    let uri: Uri = match format!("{}{}", origin.base, path_and_query).parse() {
+        Ok(uri) => uri,
+        Err(e) => {
+            error!("Invalid origin URI for {}: {}", path_and_query, e);
+            return bad_gateway_response("Bad Gateway");
+        }
+    };
+
+    let (parts, body) = req.into_parts();
+    let mut upstream = Request::builder().method(parts.method.clone()).uri(uri);
+    for (name, value) in &parts.headers {
+        // Host comes from the origin URI; our own credentials stay with us
+        if *name != HOST && *name != AUTHORIZATION && !is_hop_by_hop(name) {
+            upstream = upstream.header(name, value);
+        }
+    }
+    let upstream = match upstream.body(body) {
+        Ok(request) => request,
+        Err(e) => {
+            error!("Failed to build origin request: {}", e);
+            return bad_gateway_response("Bad Gateway");
+        }
+    };
+
+    let response = match origin.client.request(upstream).await {
+        Ok(response) => response,
+        Err(e) => {
+            warn!("Origin request for {} failed: {}", cache_key, e);
+            return bad_gateway_response("Bad Gateway");
+        }
+    };
+    let (response_parts, body) = response.into_parts();
+    let data = match hyper::body::to_bytes(body).await {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("Reading origin response for {} failed: {}", cache_key, e);
+            return bad_gateway_response("Bad Gateway");
+        }
+    };
+
+    let header = |name: HeaderName| response_parts.headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let cache_control = header(CACHE_CONTROL);
+    if parts.method == Method::GET && response_parts.status == StatusCode::OK && is_cacheable(cache_control.as_deref()) {
+        info!("Caching origin response: {}", cache_key);
+        let meta = CacheMeta {
+            content_type: header(CONTENT_TYPE).unwrap_or_else(|| "application/octet-stream".to_string()),
+            encoding: header(CONTENT_ENCODING),
+            etag: header(ETAG),
+            cache_control,
+            last_modified: header(LAST_MODIFIED),
+        };
+        cache.lock().await.insert_with_meta(cache_key, data.to_vec(), meta).await;
+    }
+
+    let mut builder = Response::builder().status(response_parts.status);
+    for (name, value) in &response_parts.headers {
+        if !is_hop_by_hop(name) {
+            builder = builder.header(name, value);
+        }
+    }
+    builder.body(Body::from(data)).unwrap()
+}
+
+// The rate limiter for the configured `rate_limit` (requests per client per
+// minute). A reload that changes the limit gets a new limiter, which starts
+// every client's count over
+struct ClientLimiter {
+    current: std::sync::Mutex<(u32, Arc<RateLimiter>)>,
 }
 
-type Cache = Arc<Mutex<HashMap<String, CacheEntry>>>;
-type RateLimiter = Arc<Mutex<HashMap<String, (u32, SystemTime)>>>;
+impl ClientLimiter {
+    fn new(limit: u32) -> Self {
+        ClientLimiter { current: std::sync::Mutex::new((limit, Self::limiter(limit))) }
+    }
+
+    fn limiter(limit: u32) -> Arc<RateLimiter> {
+        Arc::new(RateLimiter::sliding_window(limit, Duration::from_secs(60)))
+    }
 
-async fn serve_file(req: Request<Body>, cache: Cache, rate_limiter: RateLimiter, config: Arc<Config>) -> Result<Response<Body>, Infallible> {
-    let client_ip = req.headers().get("x-forwarded-for")
-        .and_then(|ip| ip.to_str().ok())
-        .unwrap_or("unknown");
+    fn for_limit(&self, limit: u32) -> Arc<RateLimiter> {
+        let mut current = self.current.lock().unwrap();
+        if current.0 != limit {
+            *current = (limit, Self::limiter(limit));
+        }
+        current.1.clone()
+    }
+}
 
-    if !rate_limit(client_ip, rate_limiter.clone(), config.rate_limit).await {
+async fn serve_file(
+    req: Request<Body>,
+    client: Option<IpAddr>,
+    cache: Cache,
+    rate_limiter: Arc<RateLimiter>,
+    config: Arc<Config>,
+    root: Arc<Path>,
+    origin: Option<Arc<Origin>>,
+) -> Result<Response<Body>, Infallible> {
+    let client_ip = rate_limit_key(&req, client, &config.trusted_proxies);
+    if let Err(retry_after) = rate_limiter.check(&client_ip) {
+        warn!("Rate limit exceeded for IP: {}", client_ip);
         return Ok(Response::builder()
-            .status(429)
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(RETRY_AFTER, retry_after.as_secs_ceil())
             .body(Body::from("Too Many Requests"))
             .unwrap());
     }
 
-    if !authorize(&req, &config) {
+    if !authorize(&req, &config).await {
         return Ok(Response::builder()
             .status(StatusCode::UNAUTHORIZED)
             .header("WWW-Authenticate", "Basic realm=\"User Visible Realm\"")
@@ -56,54 +688,89 @@ async fn serve_file(req: Request<Body>, cache: Cache, rate_limiter: RateLimiter,
             .unwrap());
     }
 
-    let path = format!(".{}", req.uri().path());
+    if !stays_under_root(req.uri().path()) {
+        warn!("Refusing path outside the served root: {}", req.uri().path());
+        return Ok(Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from("Forbidden"))
+            .unwrap());
+    }
+
+    // Appended rather than joined, so a path like `//etc/passwd` isn't taken as
+    // absolute; with `..` refused above, that keeps it under the root
+    let mut path = root.as_os_str().to_owned();
+    path.push(req.uri().path());
     let path = PathBuf::from(path);
 
-    let cache_key = req.uri().path().to_string();
-    {
-        let mut cache = cache.lock().await;
-        if let Some(entry) = cache.get(&cache_key) {
-            if entry.last_access.elapsed().unwrap() < Duration::new(config.cache_duration, 0) {
+    // The query is part of the key since the origin may vary on it
+    let cache_key = req.uri().path_and_query().map_or_else(|| req.uri().path().to_string(), |pq| pq.to_string());
+    if req.method() == Method::GET || req.method() == Method::HEAD {
+        let mut cached = cache.lock().await;
+        let max_age = Duration::from_secs(config.cache_duration);
+        if let Some(entry) = cached.get(&cache_key, max_age, Duration::from_secs(config.stale_grace)).await {
+            if !entry.stale {
                 info!("Serving from cache: {}", cache_key);
-                let mut builder = Response::builder()
-                    .header(CONTENT_TYPE, entry.content_type.clone())
-                    .header(CACHE_CONTROL, "max-age=31536000");
-                if let Some(encoding) = &entry.encoding {
-                    builder = builder.header(CONTENT_ENCODING, encoding.clone());
-                }
-                return Ok(builder.body(Body::from(entry.data.clone())).unwrap());
+            } else if let Some(claim) = cached.begin_refresh(&cache_key) {
+                info!("Serving stale cache entry while refreshing: {}", cache_key);
+                tokio::spawn(refresh_entry(cache.clone(), cache_key.clone(), path.clone(), origin.clone(), claim));
+            }
+            if is_not_modified(req.headers(), entry.etag.as_deref(), entry.last_modified.as_deref()) {
+                return Ok(not_modified_response(entry.etag.as_deref(), entry.last_modified.as_deref()));
+            }
+
+            let mut builder = Response::builder()
+                .header(CONTENT_TYPE, entry.content_type)
+                .header(CACHE_CONTROL, entry.cache_control.as_deref().unwrap_or("max-age=31536000"));
+            if let Some(encoding) = entry.encoding {
+                builder = builder.header(CONTENT_ENCODING, encoding);
+            }
+            if let Some(etag) = entry.etag {
+                builder = builder.header(ETAG, etag);
             }
+            if let Some(last_modified) = entry.last_modified {
+                builder = builder.header(LAST_MODIFIED, last_modified);
+            }
+            return Ok(builder.body(Body::from(entry.data)).unwrap());
         }
     }
 
-    let mut response = if path.is_file() {
+    let response = if path.is_file() {
+        let last_modified = file_last_modified(&path).await;
+        let conditional = req.method() == Method::GET || req.method() == Method::HEAD;
+        if conditional && is_not_modified(req.headers(), None, last_modified.as_deref()) {
+            return Ok(not_modified_response(None, last_modified.as_deref()));
+        }
+
         match File::open(&path).await {
             Ok(mut file) => {
                 let mut buf = Vec::new();
                 file.read_to_end(&mut buf).await.unwrap();
 
                 let mime_type = from_path(&path).first_or_octet_stream();
-                let compressed = compress_if_needed(&buf, mime_type.essence_str());
+                let (body, encoding) = compress_if_needed(&buf, mime_type.essence_str());
 
                 {
                     let mut cache = cache.lock().await;
-                    cache.insert(
-                        cache_key.clone(),
-                        CacheEntry {
-                            data: compressed.clone(),
-                            last_access: SystemTime::now(),
-                            content_type: mime_type.to_string(),
-                            encoding: Some("gzip".to_string()),
-                        },
-                    );
+                    let meta = CacheMeta {
+                        content_type: mime_type.to_string(),
+                        encoding: encoding.map(str::to_string),
+                        etag: None,
+                        cache_control: None,
+                        last_modified: last_modified.clone(),
+                    };
+                    cache.insert_with_meta(cache_key.clone(), body.clone(), meta).await;
                 }
 
-                Response::builder()
+                let mut builder = Response::builder()
                     .header(CONTENT_TYPE, mime_type.as_ref())
-                    .header(CONTENT_ENCODING, "gzip")
-                    .header(CACHE_CONTROL, "max-age=31536000")
-                    .body(Body::from(compressed))
-                    .unwrap()
+                    .header(CACHE_CONTROL, "max-age=31536000");
+                if let Some(encoding) = encoding {
+                    builder = builder.header(CONTENT_ENCODING, encoding);
+                }
+                if let Some(last_modified) = last_modified {
+                    builder = builder.header(LAST_MODIFIED, last_modified);
+                }
+                builder.body(Body::from(body)).unwrap()
             },
             Err(_) => not_found_response("File not found"),
         }
@@ -115,6 +782,8 @@ async fn serve_file(req: Request<Body>, cache: Cache, rate_limiter: RateLimiter,
                 .unwrap(),
             Err(_) => not_found_response("Directory listing failed"),
         }
+    } else if let Some(origin) = origin {
+        proxy_to_origin(req, &origin, cache, cache_key).await
     } else {
         not_found_response("File not found")
     };
@@ -122,10 +791,110 @@ async fn serve_file(req: Request<Body>, cache: Cache, rate_limiter: RateLimiter,
     Ok(response)
 }
 
+// Who a request is rate limited as: the address it connects from or, when that
+// is a trusted proxy, the nearest address in X-Forwarded-For that isn't one
+fn rate_limit_key(req: &Request<Body>, peer: Option<IpAddr>, trusted_proxies: &[IpAddr]) -> String {
+    let Some(peer) = peer else {
+        return "unknown".to_string();
+    };
+    if !trusted_proxies.contains(&peer) {
+        return peer.to_string();
+    }
+    // Each proxy appends the address it got the request from, so read from the right
+    let forwarded: Vec<IpAddr> = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|ip| ip.trim().parse().ok())
+        .collect();
+    forwarded.into_iter().rev().find(|ip| !trusted_proxies.contains(ip)).unwrap_or(peer).to_string()
+}
+
+// Whether a request path names something under the served root: no segment may
+// be `..`. The path reaches the filesystem undecoded, but the percent-encoded
+// forms are refused as well, since the origin or a proxy in front may decode them
+fn stays_under_root(path: &str) -> bool {
+    path.split('/').all(|segment| segment.to_ascii_lowercase().replace("%2e", ".") != "..")
+}
+
+// Read and compress a file for the cache, as `serve_file` does on a miss
+async fn load_file(path: &Path) -> std::io::Result<(Vec<u8>, CacheMeta)> {
+    let data = tokio::fs::read(path).await?;
+    let mime_type = from_path(path).first_or_octet_stream();
+    let (data, encoding) = compress_if_needed(&data, mime_type.essence_str());
+    let meta = CacheMeta {
+        content_type: mime_type.to_string(),
+        encoding: encoding.map(str::to_string),
+        etag: None,
+        cache_control: None,
+        last_modified: file_last_modified(path).await,
+    };
+    Ok((data, meta))
+}
+
+// Reload a stale entry from disk, or from the origin for paths not on disk,
+// while requests keep getting the stale copy. The caller has claimed the key
+// with `begin_refresh` and its claim is released when this returns or panics;
+// if the reload fails the stale entry stays until its grace period runs out,
+// and the next request past that point retries.
+async fn refresh_entry(
+    cache: Cache,
+    key: String,
+    path: PathBuf,
+    origin: Option<Arc<Origin>>,
+    _claim: RefreshClaim,
+) {
+    if path.is_file() {
+        match load_file(&path).await {
+            Ok((data, meta)) => cache.lock().await.insert_with_meta(key.clone(), data, meta).await,
+            Err(e) => warn!("Refreshing cache entry {} failed: {}", key, e),
+        }
+    } else if let Some(origin) = origin {
+        // Caches the response when the origin still allows it
+        match Request::get(key.as_str()).body(Body::empty()) {
+            Ok(req) => {
+                proxy_to_origin(req, &origin, cache.clone(), key.clone()).await;
+            }
+            Err(e) => warn!("Refreshing cache entry {} failed: {}", key, e),
+        }
+    }
+}
+
+// The file's mtime as an HTTP date, which truncates it to whole seconds
+async fn file_last_modified(path: &Path) -> Option<String> {
+    let modified = tokio::fs::metadata(path).await.ok()?.modified().ok()?;
+    Some(httpdate::fmt_http_date(modified))
+}
+
+// Whether the client's copy is still current. `If-None-Match` takes precedence:
+// when it is sent, `If-Modified-Since` is ignored (RFC 9110, section 13.2.2)
+fn is_not_modified(headers: &HeaderMap, etag: Option<&str>, last_modified: Option<&str>) -> bool {
+    let header = |name: HeaderName| headers.get(name).and_then(|v| v.to_str().ok());
+    if let Some(if_none_match) = header(IF_NONE_MATCH) {
+        return etag.is_some_and(|etag| if_none_match.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+    }
+    let since = header(IF_MODIFIED_SINCE).and_then(|date| httpdate::parse_http_date(date).ok());
+    let modified = last_modified.and_then(|date| httpdate::parse_http_date(date).ok());
+    matches!((since, modified), (Some(since), Some(modified)) if modified <= since)
+}
+
+fn not_modified_response(etag: Option<&str>, last_modified: Option<&str>) -> Response<Body> {
+    let mut builder = Response::builder().status(StatusCode::NOT_MODIFIED);
+    if let Some(etag) = etag {
+        builder = builder.header(ETAG, etag);
+    }
+    if let Some(last_modified) = last_modified {
+        builder = builder.header(LAST_MODIFIED, last_modified);
+    }
+    builder.body(Body::empty()).unwrap()
+}
+
 fn not_found_response(message: &str) -> Response<Body> {
     Response::builder()
         .status(404)
-        .body(Body::from(message))
+        .body(Body::from(message.to_string()))
         .unwrap()
 }
 
@@ -134,112 +903,1015 @@ async fn serve_directory(path: &PathBuf) -> Result<String, std::io::Error> {
     let mut list = String::from("<html><body><ul>");
 
     while let Some(entry) = entries.next_entry().await? {
-        let entry_name = entry.file_name().into_string().unwrap();
-        list.push_str(&format!("<li><a href=\"{0}\">{0}</a></li>", entry_name));
+        let entry_name = entry.file_name().to_string_lossy().into_owned();
+        // `./` keeps names like `javascript:alert(1)` relative links
+        list.push_str(&format!(
+            "<li><a href=\"./{}\">{}</a></li>",
+            attr_escape(&path_segment_encode(&entry_name)),
+            html_escape(&entry_name)
+        ));
     }
 
     list.push_str("</ul></body></html>");
     Ok(list)
 }
 
-fn compress_if_needed(data: &[u8], mime_type: &str) -> Vec<u8> {
+// Gzip text bodies, returning the body with the Content-Encoding it was given, if any
+fn compress_if_needed(data: &[u8], mime_type: &str) -> (Vec<u8>, Option<&'static str>) {
     if mime_type.starts_with("text/") || mime_type == "application/javascript" {
         let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
         encoder.write_all(data).unwrap();
-        encoder.finish().unwrap()
+        (encoder.finish().unwrap(), Some("gzip"))
     } else {
-        data.to_vec()
+        (data.to_vec(), None)
+    }
+}
+
+// Every regular file under `root` keyed by the request path it is served at,
+// skipping hidden files and directories
+async fn cacheable_files(root: &Path) -> Vec<(String, PathBuf)> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = match read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Cache warmup skipping {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            match entry.file_type().await {
+                Ok(file_type) if file_type.is_dir() => dirs.push(path),
+                Ok(file_type) if file_type.is_file() => {
+                    let Ok(relative) = path.strip_prefix(root) else { continue };
+                    let key = relative
+                        .components()
+                        .map(|c| format!("/{}", c.as_os_str().to_string_lossy()))
+                        .collect();
+                    files.push((key, path));
+                }
+                _ => {}
+            }
+        }
     }
+    files.sort();
+    files
 }
 
-async fn rate_limit(ip: &str, rate_limiter: RateLimiter, max_requests: u32) -> bool {
-    let mut limiter = rate_limiter.lock().await;
-    let entry = limiter.entry(ip.to_string()).or_insert((0, SystemTime::now()));
+// Load the files under `root` into the cache the same way `serve_file` would,
+// so the first requests after a restart are hits. At most `warm_concurrency`
+// files are read at once, and warmup only fills free memory: it never evicts
+// or spills entries, and stops adding once `max_cache_bytes` is reached.
+// Returns how many files were cached.
+async fn warm_cache(root: &Path, cache: Cache, config: &Config) -> usize {
+    let mut loaded = stream::iter(cacheable_files(root).await)
+        .map(|(key, path)| async move {
+            match load_file(&path).await {
+                Ok((data, meta)) => Some((key, data, meta)),
+                Err(e) => {
+                    warn!("Cache warmup failed to read {}: {}", path.display(), e);
+                    None
+                }
+            }
+        })
+        .buffered(config.warm_concurrency);
+
+    let mut warmed = 0;
+    while let Some(file) = loaded.next().await {
+        let Some((key, data, meta)) = file else { continue };
+        let mut cache = cache.lock().await;
+        if cache.memory_bytes() + data.len() > config.max_cache_bytes {
+            continue;
+        }
+        cache.insert_with_meta(key, data, meta).await;
+        warmed += 1;
+    }
+    warmed
+}
 
-    if entry.0 > max_requests && entry.1.elapsed().unwrap() < Duration::new(60, 0) {
-        warn!("Rate limit exceeded for IP: {}", ip);
+// Check the request's Basic auth login against every configured credential.
+// Comparisons are constant-time and don't stop at the first match, so response
+// timing reveals neither how much of a login was right nor which one matched
+async fn authorize(req: &Request<Body>, config: &Config) -> bool {
+    let Some((username, password)) = basic_credentials(req) else {
         return false;
-    } else if entry.1.elapsed().unwrap() >= Duration::new(60, 0) {
-        *entry = (1, SystemTime::now());
-    } else {
-        entry.0 += 1;
+    };
+
+    let mut authorized = false;
+    let mut named_hash = None;
+    for credential in &config.credentials {
+        let username_matches = constant_time_eq(username.as_bytes(), credential.username.as_bytes());
+        if credential.is_bcrypt() {
+            // bcrypt is too slow to run for every credential, so only the named user's hash is checked, below
+            if username_matches {
+                named_hash = Some(credential.password.as_str());
+            }
+        } else {
+            authorized |= username_matches & constant_time_eq(password.as_bytes(), credential.password.as_bytes());
+        }
     }
 
-    true
+    // One bcrypt check whether or not a bcrypt user has that name, so the time
+    // it takes doesn't tell which usernames exist
+    if config.credentials.iter().any(Credential::is_bcrypt) {
+        let password_matches = bcrypt_matches(&password, named_hash.unwrap_or(DUMMY_BCRYPT_HASH)).await;
+        authorized |= named_hash.is_some() & password_matches;
+    }
+    authorized
 }
 
-fn authorize(req: &Request<Body>, config: &Config) -> bool {
-    if let Some(auth) = req.headers().get(AUTHORIZATION) {
-        if let Ok(auth_str) = auth.to_str() {
-            let encoded = base64::encode(format!("{}:{}", config.auth_username, config.auth_password));
-            return auth_str == format!("Basic {}", encoded);
-        }
+// The username and password from an `Authorization: Basic` header, or `None` if it is missing or malformed
+fn basic_credentials(req: &Request<Body>) -> Option<(String, String)> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, encoded) = header.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
     }
-    false
+    let decoded = String::from_utf8(base64::decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
 }
 
-fn tls_config(cert_path: &str, key_path: &str) -> ServerConfig {
-    let certs = load_certs(cert_path);
-    let key = load_private_key(key_path);
-    let mut config = ServerConfig::new(rustls::NoClientAuth::new());
-    config.set_single_cert(certs, key).unwrap();
-    config
+// `subtle` returns early when lengths differ, so compare fixed-size digests
+// to keep the expected value's length from leaking too
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    Sha256::digest(a).ct_eq(&Sha256::digest(b)).into()
 }
 
-fn load_certs(path: &str) -> Vec<Certificate> {
-    let certfile = fs::File::open(path).unwrap();
-    let mut reader = std::io::BufReader::new(certfile);
-    rustls_pemfile::certs(&mut reader).unwrap().into_iter().map(Certificate).collect()
+// Checked when no bcrypt credential has the login's username. Hashed at
+// `bcrypt::DEFAULT_COST`, what `bcrypt::hash` uses unless told otherwise
+const DUMMY_BCRYPT_HASH: &str = "$2b$12$IQds9tLKzZNTLTM35ocbxuEs64kCOCRci3fxAuc7hG6b/sASRNtzO";
+
+async fn bcrypt_matches(password: &str, hash: &str) -> bool {
+    let (password, hash) = (password.to_string(), hash.to_string());
+    // Hashing takes long enough to stall the other connections on this worker
+    tokio::task::spawn_blocking(move || bcrypt::verify(password, &hash).unwrap_or(false))
+        .await
+        .unwrap_or(false)
 }
 
-fn load_private_key(path: &str) -> PrivateKey {
-    let keyfile = fs::File::open(path).unwrap();
-    let mut reader = std::io::BufReader::new(keyfile);
-    let keys = rustls_pemfile::pkcs8_private_keys(&mut reader).unwrap();
-    PrivateKey(keys[0].clone())
+// Serve a request and, when enabled, append it to the access log once the response is ready
+async fn serve_logged<F, Fut>(
+    req: Request<Body>,
+    client: Option<IpAddr>,
+    access_log: Option<Arc<AccessLog>>,
+    serve: F,
+) -> Result<Response<Body>, Infallible>
+where
+    F: FnOnce(Request<Body>) -> Fut,
+    Fut: Future<Output = Result<Response<Body>, Infallible>>,
+{
+    let Some(access_log) = access_log else {
+        return serve(req).await;
+    };
+
+    let started = Instant::now();
+    let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let mut entry = AccessLogEntry {
+        client,
+        user: basic_credentials(&req).map(|(username, _)| username),
+        time: chrono::Local::now(),
+        method: req.method().to_string(),
+        target: req.uri().path_and_query().map_or_else(|| req.uri().path().to_string(), |pq| pq.to_string()),
+        version: format!("{:?}", req.version()),
+        status: 0,
+        bytes: None,
+        referer: header(REFERER),
+        user_agent: header(USER_AGENT),
+        elapsed: Duration::ZERO,
+    };
+
+    let response = serve(req).await?;
+    entry.status = response.status().as_u16();
+    entry.bytes = response.body().size_hint().exact();
+    entry.elapsed = started.elapsed();
+    // Only a user who got in is known; a rejected name could be anything, even a mistyped password
+    if response.status() == StatusCode::UNAUTHORIZED {
+        entry.user = None;
+    }
+    // Writing, and now and then rotating, the file would stall the other connections on this worker
+    let _ = tokio::task::spawn_blocking(move || {
+        if let Err(e) = access_log.write(&entry) {
+            warn!("failed to write access log {}: {}", access_log.path().display(), e);
+        }
+    })
+    .await;
+    Ok(response)
 }
 
-#[tokio::main]
-async fn main() {
-    env_logger::init();
+// How long a client gets to finish the TLS handshake before it is dropped
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+type ServerFuture = BoxFuture<'static, Result<(), hyper::Error>>;
 
-    let config = Arc::new(Config {
-        rate_limit: std::env::var("RATE_LIMIT").unwrap_or("100".to_string()).parse().unwrap(),
-        cache_duration: std::env::var("CACHE_DURATION").unwrap_or("600".to_string()).parse().unwrap(),
-        auth_username: std::env::var("AUTH_USERNAME").unwrap_or("user".to_string()),
-        auth_password: std::env::var("AUTH_PASSWORD").unwrap_or("pass".to_string()),
-    });
+// Bind the configured address and return the bound address alongside the server future.
+// The future completes once `shutdown` resolves and in-flight requests have drained.
+// Each request reads `live_config` afresh, so reloads apply without a restart
+async fn start_server(
+    live_config: Arc<LiveConfig<Config>>,
+    cache: Cache,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<(SocketAddr, ServerFuture), Box<dyn std::error::Error + Send + Sync>> {
+    let config = live_config.get();
+    let addr = SocketAddr::new(config.bind_addr, config.port);
 
-    let cache: Cache = Arc::new(Mutex::new(HashMap::new()));
-    let rate_limiter: RateLimiter = Arc::new(Mutex::new(HashMap::new()));
+    let origin = config.origin_url.as_deref().map(|url| Arc::new(Origin::new(url)));
+    if let Some(origin) = &origin {
+        info!("pulling missing paths from origin {}", origin.base);
+    }
 
-    let addr = ([127, 0, 0, 1], 443).into();
-    let cert_path = "cert.pem";
-    let key_path = "key.pem";
-    let tls_cfg = tls_config(cert_path, key_path);
+    let rate_limiter = Arc::new(ClientLimiter::new(config.rate_limit));
+    let root: Arc<Path> = Arc::from(config.root.as_path());
 
-    let https = HttpsConnectorBuilder::new()
-        .with_tls_config(tls_cfg)
-        .https_only()
-        .enable_http1()
-        .build();
+    let access_log = match &config.access_log {
+        Some(path) => {
+            let log = AccessLog::open(path, config.access_log_max_bytes, config.access_log_max_files)?;
+            info!("writing access log to {}", path.display());
+            Some(Arc::new(log))
+        }
+        None => None,
+    };
 
-    let make_svc = make_service_fn(|_| {
+    let new_service = move |client: Option<IpAddr>| {
         let cache = cache.clone();
         let rate_limiter = rate_limiter.clone();
-        let config = config.clone();
+        let live_config = live_config.clone();
+        let root = root.clone();
+        let origin = origin.clone();
+        let access_log = access_log.clone();
+        service_fn(move |req| {
+            let config = live_config.get();
+            let rate_limiter = rate_limiter.for_limit(config.rate_limit);
+            let (cache, root, origin) = (cache.clone(), root.clone(), origin.clone());
+            serve_logged(req, client, access_log.clone(), move |req| {
+                serve_file(req, client, cache, rate_limiter, config, root, origin)
+            })
+        })
+    };
+
+    match (&config.tls_cert_path, &config.tls_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let acceptor = TlsAcceptor::from(Arc::new(tls_config(cert_path, key_path)?));
+            let listener = TcpListener::bind(addr).await?;
+            let local_addr = listener.local_addr()?;
+
+            // Each handshake runs in its own task, so a client stalling mid-handshake can't hold
+            // up later accepts; failed or slow ones are logged and skipped rather than stopping the server
+            let (handshaken, mut incoming) = tokio::sync::mpsc::channel(64);
+            tokio::spawn(async move {
+                loop {
+                    let (socket, peer) = tokio::select! {
+                        accepted = listener.accept() => match accepted {
+                            Ok(conn) => conn,
+                            Err(e) => {
+                                warn!("failed to accept connection: {}", e);
+                                continue;
+                            }
+                        },
+                        // The server has stopped and dropped the receiving end
+                        _ = handshaken.closed() => break,
+                    };
+                    let (acceptor, handshaken) = (acceptor.clone(), handshaken.clone());
+                    tokio::spawn(async move {
+                        match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+                            Ok(Ok(tls)) => {
+                                let _ = handshaken.send(Ok::<_, std::io::Error>(tls)).await;
+                            }
+                            Ok(Err(e)) => warn!("TLS handshake with {} failed: {}", peer, e),
+                            Err(_) => warn!("TLS handshake with {} timed out after {:?}", peer, TLS_HANDSHAKE_TIMEOUT),
+                        }
+                    });
+                }
+            });
+            let incoming = stream::poll_fn(move |cx| incoming.poll_recv(cx));
+
+            let make_svc = make_service_fn(move |conn: &TlsStream<TcpStream>| {
+                let service = new_service(conn.get_ref().0.peer_addr().ok().map(|addr| addr.ip()));
+                async move { Ok::<_, Infallible>(service) }
+            });
+            let server = Server::builder(accept::from_stream(incoming))
+                .serve(make_svc)
+                .with_graceful_shutdown(shutdown);
+            info!("serving HTTPS on {}", local_addr);
+            Ok((local_addr, server.boxed()))
+        }
+        _ => {
+            let incoming = AddrIncoming::bind(&addr)?;
+            let local_addr = incoming.local_addr();
+
+            let make_svc = make_service_fn(move |conn: &AddrStream| {
+                let service = new_service(Some(conn.remote_addr().ip()));
+                async move { Ok::<_, Infallible>(service) }
+            });
+            let server = Server::builder(incoming)
+                .serve(make_svc)
+                .with_graceful_shutdown(shutdown);
+            warn!("no TLS certificate configured, serving plain HTTP on {}", local_addr);
+            Ok((local_addr, server.boxed()))
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
 
-        async move {
-            Ok::<_, Infallible>(service_fn(move |req| {
-                serve_file(req, cache.clone(), rate_limiter.clone(), config.clone())
-            }))
+    // CDN_CONFIG_FILE is read again on SIGHUP
+    let config_file = std::env::var_os("CDN_CONFIG_FILE").map(PathBuf::from);
+    let env = |key: &str| std::env::var(key).ok();
+    let live_config = match Config::load(config_file.as_deref(), env) {
+        Ok(config) => Arc::new(LiveConfig::new(config)),
+        Err(e) => {
+            error!("invalid configuration: {}", e);
+            std::process::exit(1);
         }
-    });
+    };
+    let reload = move || Config::load(config_file.as_deref(), env);
+    if let Err(e) = reload_on_sighup(live_config.clone(), reload, Config::changes) {
+        warn!("failed to listen for SIGHUP, configuration can't be reloaded: {}", e);
+    }
+    let config = live_config.get();
+
+    let cache: Cache = Arc::new(Mutex::new(CdnCache::new(config.max_cache_bytes, config.cache_dir.clone())));
+    if config.warm_cache {
+        let warmed = warm_cache(&config.root, cache.clone(), &config).await;
+        info!("warmed cache with {} files ({} bytes)", warmed, cache.lock().await.memory_bytes());
+    }
 
-    let server = Server::builder(https)
-        .serve(make_svc);
+    let server = match start_server(live_config, cache, shutdown_signal()).await {
+        Ok((_, server)) => server,
+        Err(e) => {
+            error!("failed to start server: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     if let Err(e) = server.await {
         error!("server error: {}", e);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRESH: Duration = Duration::from_secs(600);
+
+    async fn insert(cache: &mut CdnCache, key: &str, size: usize) {
+        cache.insert(key.to_string(), vec![b'x'; size], "text/plain".to_string(), None).await;
+    }
+
+    #[tokio::test]
+    async fn test_lru_eviction_order() {
+        let mut cache = CdnCache::new(300, None);
+        insert(&mut cache, "/a", 100).await;
+        insert(&mut cache, "/b", 100).await;
+        insert(&mut cache, "/c", 100).await;
+
+        // Touch /a so /b becomes the least recently used
+        assert!(cache.get("/a", FRESH, Duration::ZERO).await.is_some());
+        insert(&mut cache, "/d", 100).await;
+
+        assert!(!cache.contains("/b"));
+        assert!(cache.contains("/a") && cache.contains("/c") && cache.contains("/d"));
+        assert_eq!(cache.memory_bytes(), 300);
+
+        // Next eviction takes /c, then /a
+        insert(&mut cache, "/e", 200).await;
+        assert!(!cache.contains("/c"));
+        assert!(!cache.contains("/a"));
+        assert_eq!(cache.memory_bytes(), 300);
+    }
+
+    #[tokio::test]
+    async fn test_spilled_entry_is_servable() {
+        let dir = std::env::temp_dir().join("noxium_cdn_spill_test");
+        let mut cache = CdnCache::new(150, Some(dir.clone()));
+        cache.insert("/cold".to_string(), b"cold body".to_vec(), "text/css".to_string(), Some("gzip".to_string())).await;
+        insert(&mut cache, "/hot", 145).await;
+
+        assert!(cache.contains("/cold"));
+        assert!(!cache.is_in_memory("/cold"));
+        assert_eq!(cache.memory_bytes(), 145);
+
+        let hit = cache.get("/cold", FRESH, Duration::ZERO).await.expect("Spilled entry should still be served");
+        assert_eq!(hit.data, b"cold body");
+        assert_eq!(hit.content_type, "text/css");
+        assert_eq!(hit.encoding.as_deref(), Some("gzip"));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_expired_entry_is_dropped() {
+        let mut cache = CdnCache::new(1000, None);
+        insert(&mut cache, "/old", 10).await;
+        assert!(cache.get("/old", Duration::ZERO, Duration::ZERO).await.is_none());
+        assert_eq!(cache.memory_bytes(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_stale_entry_within_grace_is_served() {
+        let mut cache = CdnCache::new(1000, None);
+        insert(&mut cache, "/old", 10).await;
+
+        let hit = cache.get("/old", Duration::ZERO, FRESH).await.expect("entry within grace should be served");
+        assert!(hit.stale);
+        assert!(!cache.get("/old", FRESH, Duration::ZERO).await.unwrap().stale);
+
+        // One refresh per key at a time
+        let claim = cache.begin_refresh("/old").unwrap();
+        assert!(cache.begin_refresh("/old").is_none());
+        assert!(cache.begin_refresh("/other").is_some());
+        drop(claim);
+
+        // A refresh that panics still gives up its claim
+        let claim = cache.begin_refresh("/old").unwrap();
+        let refresh = tokio::spawn(async move {
+            let _claim = claim;
+            panic!("refresh failed");
+        });
+        assert!(refresh.await.unwrap_err().is_panic());
+        assert!(cache.begin_refresh("/old").is_some());
+    }
+
+    fn test_config() -> Config {
+        Config {
+            rate_limit: 100,
+            trusted_proxies: Vec::new(),
+            cache_duration: 600,
+            stale_grace: 0,
+            credentials: vec![Credential { username: "user".to_string(), password: "pass".to_string() }],
+            max_cache_bytes: 1024 * 1024,
+            cache_dir: None,
+            root: PathBuf::from("."),
+            bind_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 0,
+            tls_cert_path: None,
+            tls_key_path: None,
+            origin_url: None,
+            warm_cache: false,
+            warm_concurrency: 4,
+            access_log: None,
+            access_log_max_bytes: 1024 * 1024,
+            access_log_max_files: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_warm_cache_preloads_served_files() {
+        let root = std::env::temp_dir().join("noxium_cdn_warm_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("js")).unwrap();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::write(root.join("index.html"), "<h1>hello</h1>").unwrap();
+        std::fs::write(root.join("js/app.js"), "console.log(1);").unwrap();
+        std::fs::write(root.join(".git/HEAD"), "ref: refs/heads/main").unwrap();
+
+        let config = test_config();
+        let cache: Cache = Arc::new(Mutex::new(CdnCache::new(config.max_cache_bytes, None)));
+        assert_eq!(warm_cache(&root, cache.clone(), &config).await, 2);
+
+        // Present without any request having been made
+        let mut cache = cache.lock().await;
+        let hit = cache.get("/js/app.js", FRESH, Duration::ZERO).await.expect("warmed file should be cached");
+        assert_eq!(hit.encoding.as_deref(), Some("gzip"));
+        let mut body = String::new();
+        std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(&hit.data[..]), &mut body).unwrap();
+        assert_eq!(body, "console.log(1);");
+        assert!(cache.contains("/index.html"));
+        assert!(!cache.contains("/.git/HEAD"));
+
+        // A budget smaller than everything only takes what fits
+        let small = Config { max_cache_bytes: cache.memory_bytes() - 1, ..test_config() };
+        let budgeted: Cache = Arc::new(Mutex::new(CdnCache::new(small.max_cache_bytes, None)));
+        assert_eq!(warm_cache(&root, budgeted.clone(), &small).await, 1);
+        assert!(budgeted.lock().await.memory_bytes() <= small.max_cache_bytes);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_server_serves_then_shuts_down() {
+        let config = Arc::new(LiveConfig::new(test_config()));
+        let cache: Cache = Arc::new(Mutex::new(CdnCache::new(config.get().max_cache_bytes, None)));
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+
+        let (addr, server) = start_server(config, cache, async {
+            shutdown_rx.await.ok();
+        })
+        .await
+        .expect("server should bind");
+        assert_ne!(addr.port(), 0);
+        let server = tokio::spawn(server);
+
+        let request = Request::builder()
+            .uri(format!("http://{}/", addr))
+            .header(AUTHORIZATION, format!("Basic {}", base64::encode("user:pass")))
+            .body(Body::empty())
+            .unwrap();
+        let response = hyper::Client::new().request(request).await.expect("request should succeed");
+        assert_eq!(response.status(), StatusCode::OK);
+
+        shutdown_tx.send(()).unwrap();
+        let result = tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server should stop after shutdown is signalled");
+        assert!(result.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_stalled_tls_handshake_does_not_block_other_clients() {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("noxium-cdn-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+        std::fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+        let config = Config { tls_cert_path: Some(cert_path), tls_key_path: Some(key_path), ..test_config() };
+        let config = Arc::new(LiveConfig::new(config));
+        let cache: Cache = Arc::new(Mutex::new(CdnCache::new(config.get().max_cache_bytes, None)));
+        let (addr, server) = start_server(config, cache, futures::future::pending()).await.unwrap();
+        tokio::spawn(server);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // Connects but never sends a ClientHello
+        let _stalled = TcpStream::connect(addr).await.unwrap();
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let client_config = rustls::ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let tls = tokio::time::timeout(Duration::from_secs(5), connector.connect("localhost".try_into().unwrap(), tcp))
+            .await
+            .expect("handshake should not wait for the stalled client")
+            .expect("TLS handshake should succeed");
+
+        let (mut sender, connection) = hyper::client::conn::handshake(tls).await.unwrap();
+        tokio::spawn(connection);
+        let request = Request::builder()
+            .uri("/")
+            .header(AUTHORIZATION, format!("Basic {}", base64::encode("user:pass")))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(sender.send_request(request).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_written_to_access_log() {
+        let dir = std::env::temp_dir().join(format!("noxium_cdn_access_log_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let log_path = dir.join("access.log");
+        let config = Arc::new(LiveConfig::new(Config { access_log: Some(log_path.clone()), ..test_config() }));
+        let cache: Cache = Arc::new(Mutex::new(CdnCache::new(config.get().max_cache_bytes, None)));
+        let (addr, server) = start_server(config, cache, futures::future::pending()).await.unwrap();
+        tokio::spawn(server);
+
+        let request = Request::builder()
+            .uri(format!("http://{}/?page=1", addr))
+            .header(AUTHORIZATION, format!("Basic {}", base64::encode("user:pass")))
+            .header(USER_AGENT, "noxium-test")
+            .body(Body::empty())
+            .unwrap();
+        let response = hyper::Client::new().request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let request = Request::builder().uri(format!("http://{}/", addr)).body(Body::empty()).unwrap();
+        assert_eq!(hyper::Client::new().request(request).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        let request = Request::builder()
+            .uri(format!("http://{}/", addr))
+            .header(AUTHORIZATION, format!("Basic {}", base64::encode("admin:wrong")))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(hyper::Client::new().request(request).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+        let log = std::fs::read_to_string(&log_path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3, "{}", log);
+        let line_re = regex::Regex::new(
+            r#"^127\.0\.0\.1 - (\S+) \[\d{2}/\w{3}/\d{4}:\d{2}:\d{2}:\d{2} [+-]\d{4}\] "GET (\S+) HTTP/1\.1" (\d{3}) (\d+|-) "-" "([^"]*)" \d+$"#,
+        )
+        .unwrap();
+        let served = line_re.captures(lines[0]).unwrap_or_else(|| panic!("bad log line: {}", lines[0]));
+        assert_eq!(&served[1], "user");
+        assert_eq!(&served[2], "/?page=1");
+        assert_eq!(&served[3], "200");
+        assert_eq!(served[4], body.len().to_string());
+        assert_eq!(&served[5], "noxium-test");
+
+        let rejected = line_re.captures(lines[1]).unwrap_or_else(|| panic!("bad log line: {}", lines[1]));
+        assert_eq!(&rejected[1], "-");
+        assert_eq!(&rejected[3], "401");
+        assert_eq!(&rejected[4], "Unauthorized".len().to_string());
+        // Failed credentials don't name a user
+        let failed = line_re.captures(lines[2]).unwrap_or_else(|| panic!("bad log line: {}", lines[2]));
+        assert_eq!(&failed[1], "-");
+        assert_eq!(&failed[3], "401");
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    async fn get_with(addr: SocketAddr, path: &str, headers: &[(HeaderName, &str)]) -> Response<Body> {
+        let mut request = Request::builder()
+            .uri(format!("http://{}{}", addr, path))
+            .header(AUTHORIZATION, format!("Basic {}", base64::encode("user:pass")));
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        hyper::Client::new().request(request.body(Body::empty()).unwrap()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_conditional_get_by_last_modified() {
+        let root = std::env::temp_dir().join(format!("noxium_cdn_last_modified_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("dated.txt"), "dated").unwrap();
+        // Half a second past a whole second, which the header truncates away
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+        std::fs::File::options().write(true).open(root.join("dated.txt")).unwrap().set_modified(mtime).unwrap();
+        let last_modified = "Tue, 14 Nov 2023 22:13:20 GMT";
+
+        let config = Arc::new(LiveConfig::new(Config { root: root.clone(), ..test_config() }));
+        let cache: Cache = Arc::new(Mutex::new(CdnCache::new(config.get().max_cache_bytes, None)));
+        let (addr, server) = start_server(config, cache.clone(), futures::future::pending()).await.unwrap();
+        tokio::spawn(server);
+        let path = "/dated.txt";
+
+        let fresh = get_with(addr, path, &[]).await;
+        assert_eq!(fresh.status(), StatusCode::OK);
+        assert_eq!(fresh.headers()[LAST_MODIFIED], last_modified);
+
+        // Now answered from the cache, which keeps the date
+        let cached = get_with(addr, path, &[(IF_MODIFIED_SINCE, last_modified)]).await;
+        assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(cached.headers()[LAST_MODIFIED], last_modified);
+        assert!(hyper::body::to_bytes(cached.into_body()).await.unwrap().is_empty());
+
+        let older = get_with(addr, path, &[(IF_MODIFIED_SINCE, "Tue, 14 Nov 2023 22:13:19 GMT")]).await;
+        assert_eq!(older.status(), StatusCode::OK);
+        assert_eq!(older.headers()[LAST_MODIFIED], last_modified);
+
+        // A non-matching If-None-Match overrides a satisfied If-Modified-Since
+        let etag_mismatch = get_with(addr, path, &[(IF_NONE_MATCH, "\"other\""), (IF_MODIFIED_SINCE, last_modified)]).await;
+        assert_eq!(etag_mismatch.status(), StatusCode::OK);
+
+        // Straight from disk too
+        cache.lock().await.remove(path).await;
+        assert_eq!(get_with(addr, path, &[(IF_MODIFIED_SINCE, last_modified)]).await.status(), StatusCode::NOT_MODIFIED);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_paths_outside_the_root_are_refused() {
+        let dir = std::env::temp_dir().join(format!("noxium_cdn_traversal_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let root = dir.join("public");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("inside.txt"), "public").unwrap();
+        std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+
+        let config = Arc::new(LiveConfig::new(Config { root: root.clone(), ..test_config() }));
+        let cache: Cache = Arc::new(Mutex::new(CdnCache::new(config.get().max_cache_bytes, None)));
+        let (addr, server) = start_server(config, cache, futures::future::pending()).await.unwrap();
+        tokio::spawn(server);
+
+        assert_eq!(get_with(addr, "/inside.txt", &[]).await.status(), StatusCode::OK);
+        for path in ["/../secret.txt", "/docs/../../secret.txt", "/%2e%2e/secret.txt", "/.%2E/secret.txt"] {
+            let response = get_with(addr, path, &[]).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", path);
+            assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "Forbidden");
+        }
+        // Dots that aren't a whole segment are ordinary names
+        assert!(stays_under_root("/a..b/..c/.d"));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_stale_hit_is_served_then_refreshed() {
+        let root = std::env::temp_dir().join(format!("noxium_cdn_stale_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        // Not a text type, so the body is cached uncompressed
+        let name = root.join("stale.bin");
+        std::fs::write(&name, "old").unwrap();
+
+        // Every cached entry is stale right away, but within its grace period
+        let config = Config { root: root.clone(), cache_duration: 0, stale_grace: 600, ..test_config() };
+        let config = Arc::new(LiveConfig::new(config));
+        let cache: Cache = Arc::new(Mutex::new(CdnCache::new(config.get().max_cache_bytes, None)));
+        let (addr, server) = start_server(config, cache.clone(), futures::future::pending()).await.unwrap();
+        tokio::spawn(server);
+        let path = "/stale.bin";
+        let body = |response: Response<Body>| async { hyper::body::to_bytes(response.into_body()).await.unwrap() };
+
+        let fresh = get_with(addr, path, &[]).await;
+        assert!(!fresh.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(body(fresh).await, "old");
+        std::fs::write(&name, "new").unwrap();
+
+        // Answered from the stale entry without waiting for the re-read
+        let stale = get_with(addr, path, &[]).await;
+        assert!(!stale.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(body(stale).await, "old");
+
+        let refreshed = async {
+            loop {
+                if let Some(hit) = cache.lock().await.get(path, FRESH, Duration::ZERO).await {
+                    if hit.data == b"new" {
+                        break;
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), refreshed).await.expect("cache entry was not refreshed");
+        let refreshed = get_with(addr, path, &[]).await;
+        assert!(!refreshed.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(body(refreshed).await, "new");
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_directory_listing_escapes_file_names() {
+        let dir = std::env::temp_dir().join(format!("noxium_cdn_listing_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("<img src=x onerror=alert(1)>.txt"), "").unwrap();
+        std::fs::write(dir.join("\" onmouseover=\"alert(2)"), "").unwrap();
+        std::fs::write(dir.join("javascript:alert(3)"), "").unwrap();
+        std::fs::write(dir.join("notes #1?.txt"), "").unwrap();
+
+        let listing = serve_directory(&dir).await.unwrap();
+        assert!(!listing.contains("<img"), "{}", listing);
+        assert!(listing.contains(
+            "<a href=\"./%3Cimg%20src%3Dx%20onerror%3Dalert%281%29%3E.txt\">&lt;img src=x onerror=alert(1)&gt;.txt</a>"
+        ));
+        assert!(listing.contains("<a href=\"./%22%20onmouseover%3D%22alert%282%29\">\" onmouseover=\"alert(2)</a>"));
+        assert!(listing.contains("<a href=\"./javascript%3Aalert%283%29\">"));
+        assert!(listing.contains("<a href=\"./notes%20%231%3F.txt\">notes #1?.txt</a>"));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_etag_takes_precedence_over_last_modified() {
+        let last_modified = Some("Tue, 14 Nov 2023 22:13:20 GMT");
+        let headers = |pairs: &[(HeaderName, &str)]| {
+            let mut map = HeaderMap::new();
+            for (name, value) in pairs {
+                map.insert(name.clone(), value.parse().unwrap());
+            }
+            map
+        };
+        let later = "Wed, 15 Nov 2023 00:00:00 GMT";
+
+        assert!(is_not_modified(&headers(&[(IF_MODIFIED_SINCE, later)]), Some("\"v1\""), last_modified));
+        assert!(is_not_modified(&headers(&[(IF_NONE_MATCH, "\"v1\"")]), Some("\"v1\""), None));
+        assert!(!is_not_modified(&headers(&[(IF_NONE_MATCH, "\"v2\""), (IF_MODIFIED_SINCE, later)]), Some("\"v1\""), last_modified));
+        assert!(!is_not_modified(&headers(&[(IF_MODIFIED_SINCE, "Mon, 13 Nov 2023 00:00:00 GMT")]), None, last_modified));
+        assert!(!is_not_modified(&headers(&[(IF_MODIFIED_SINCE, "not a date")]), None, last_modified));
+        assert!(!is_not_modified(&headers(&[(IF_MODIFIED_SINCE, later)]), None, None));
+    }
+
+    // Serve a fixed body from an ephemeral port, counting the requests that reach it
+    async fn mock_origin() -> (SocketAddr, Arc<std::sync::atomic::AtomicUsize>) {
+        let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = hits.clone();
+        let make_svc = make_service_fn(move |_| {
+            let counter = counter.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    let leaked_auth = req.headers().contains_key(AUTHORIZATION);
+                    async move {
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .header(CONTENT_TYPE, "text/plain")
+                                .header(CACHE_CONTROL, "public, max-age=60")
+                                .header(ETAG, "\"v1\"")
+                                .body(Body::from(if leaked_auth { "leaked" } else { "from origin" }))
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        (addr, hits)
+    }
+
+    async fn get(addr: SocketAddr, path: &str, if_none_match: Option<&str>) -> (StatusCode, String) {
+        let mut request = Request::builder()
+            .uri(format!("http://{}{}", addr, path))
+            .header(AUTHORIZATION, format!("Basic {}", base64::encode("user:pass")));
+        if let Some(etag) = if_none_match {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let response = hyper::Client::new().request(request.body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8_lossy(&body).to_string())
+    }
+
+    #[tokio::test]
+    async fn test_origin_pull_then_cache_hit() {
+        let (origin_addr, hits) = mock_origin().await;
+        let config = Arc::new(LiveConfig::new(Config { origin_url: Some(format!("http://{}", origin_addr)), ..test_config() }));
+        let cache: Cache = Arc::new(Mutex::new(CdnCache::new(config.get().max_cache_bytes, None)));
+        let (addr, server) = start_server(config, cache.clone(), futures::future::pending()).await.unwrap();
+        tokio::spawn(server);
+
+        let path = "/noxium-origin-pull-test.txt?v=1";
+        assert_eq!(get(addr, path, None).await, (StatusCode::OK, "from origin".to_string()));
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert!(cache.lock().await.contains(path));
+
+        assert_eq!(get(addr, path, None).await, (StatusCode::OK, "from origin".to_string()));
+        assert_eq!(get(addr, path, Some("\"v1\"")).await.0, StatusCode::NOT_MODIFIED);
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 1, "cache hits must not reach the origin");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_clients_get_retry_after() {
+        let config = Arc::new(LiveConfig::new(Config { rate_limit: 2, ..test_config() }));
+        let cache: Cache = Arc::new(Mutex::new(CdnCache::new(config.get().max_cache_bytes, None)));
+        let (addr, server) = start_server(config, cache, futures::future::pending()).await.unwrap();
+        tokio::spawn(server);
+
+        for _ in 0..2 {
+            assert_ne!(get(addr, "/missing.txt", None).await.0, StatusCode::TOO_MANY_REQUESTS);
+        }
+        let request = Request::builder().uri(format!("http://{}/missing.txt", addr)).body(Body::empty()).unwrap();
+        let response = hyper::Client::new().request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response.headers()[RETRY_AFTER].to_str().unwrap().parse().unwrap();
+        assert!((1..=60).contains(&retry_after), "{}", retry_after);
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_for_only_counts_behind_a_trusted_proxy() {
+        let forwarded_for = HeaderName::from_static("x-forwarded-for");
+        let status = |addr, ip: &'static str| {
+            let forwarded_for = forwarded_for.clone();
+            async move { get_with(addr, "/missing.txt", &[(forwarded_for, ip)]).await.status() }
+        };
+
+        // A direct client can't get a fresh allowance by rotating the header
+        let config = Arc::new(LiveConfig::new(Config { rate_limit: 2, ..test_config() }));
+        let cache: Cache = Arc::new(Mutex::new(CdnCache::new(config.get().max_cache_bytes, None)));
+        let (addr, server) = start_server(config, cache, futures::future::pending()).await.unwrap();
+        tokio::spawn(server);
+        assert_ne!(status(addr, "198.51.100.1").await, StatusCode::TOO_MANY_REQUESTS);
+        assert_ne!(status(addr, "198.51.100.2").await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(status(addr, "198.51.100.3").await, StatusCode::TOO_MANY_REQUESTS);
+
+        // Behind a trusted proxy each forwarded client has its own allowance
+        let trusted = vec![IpAddr::V4(Ipv4Addr::LOCALHOST)];
+        let config = Arc::new(LiveConfig::new(Config { rate_limit: 2, trusted_proxies: trusted, ..test_config() }));
+        let cache: Cache = Arc::new(Mutex::new(CdnCache::new(config.get().max_cache_bytes, None)));
+        let (addr, server) = start_server(config, cache, futures::future::pending()).await.unwrap();
+        tokio::spawn(server);
+        for _ in 0..2 {
+            assert_ne!(status(addr, "198.51.100.1").await, StatusCode::TOO_MANY_REQUESTS);
+        }
+        assert_eq!(status(addr, "198.51.100.1").await, StatusCode::TOO_MANY_REQUESTS);
+        // The client's own claim on the left doesn't matter, only what the proxy appended
+        assert_ne!(status(addr, "198.51.100.1, 198.51.100.2").await, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_reload_changes_rate_limit() {
+        let path = std::env::temp_dir().join(format!("noxium-cdn-reload-{}.env", std::process::id()));
+        std::fs::write(&path, "# limits\nRATE_LIMIT=2\nPORT=0\n").unwrap();
+        let live_config = Arc::new(LiveConfig::new(Config::load(Some(&path), |_| None).unwrap()));
+
+        let cache: Cache = Arc::new(Mutex::new(CdnCache::new(live_config.get().max_cache_bytes, None)));
+        let (addr, server) = start_server(live_config.clone(), cache, futures::future::pending()).await.unwrap();
+        tokio::spawn(server);
+        for _ in 0..2 {
+            assert_ne!(get(addr, "/missing.txt", None).await.0, StatusCode::TOO_MANY_REQUESTS);
+        }
+        assert_eq!(get(addr, "/missing.txt", None).await.0, StatusCode::TOO_MANY_REQUESTS);
+
+        std::fs::write(&path, "RATE_LIMIT=5\nPORT=0\n").unwrap();
+        assert!(reload::reload(&live_config, || Config::load(Some(&path), |_| None), Config::changes));
+        assert_eq!(live_config.get().rate_limit, 5);
+        std::fs::remove_file(&path).unwrap();
+
+        // The same running server now allows the new limit
+        for _ in 0..5 {
+            assert_ne!(get(addr, "/missing.txt", None).await.0, StatusCode::TOO_MANY_REQUESTS);
+        }
+        assert_eq!(get(addr, "/missing.txt", None).await.0, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn test_environment_wins_over_the_config_file() {
+        let env = HashMap::from([("CACHE_STALE_GRACE", "5")]);
+        let env = |key: &str| env.get(key).map(|value| value.to_string());
+        let path = std::env::temp_dir().join(format!("noxium-cdn-precedence-{}.env", std::process::id()));
+        std::fs::write(&path, "CACHE_STALE_GRACE=30\nRATE_LIMIT=7\n").unwrap();
+        let config = Config::load(Some(&path), env).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((config.stale_grace, config.rate_limit), (5, 7));
+        assert_eq!(Config::load(None, env).unwrap().stale_grace, 5);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_origin_is_bad_gateway() {
+        // Bind then drop a listener so the port is known to be closed
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let config = Arc::new(LiveConfig::new(Config { origin_url: Some(format!("http://{}", closed)), ..test_config() }));
+        let cache: Cache = Arc::new(Mutex::new(CdnCache::new(config.get().max_cache_bytes, None)));
+        let (addr, server) = start_server(config, cache, futures::future::pending()).await.unwrap();
+        tokio::spawn(server);
+
+        assert_eq!(get(addr, "/noxium-missing.txt", None).await.0, StatusCode::BAD_GATEWAY);
+    }
+
+    fn request_with_auth(value: &str) -> Request<Body> {
+        Request::builder().header(AUTHORIZATION, value).body(Body::empty()).unwrap()
+    }
+
+    fn basic(login: &str) -> String {
+        format!("Basic {}", base64::encode(login))
+    }
+
+    #[tokio::test]
+    async fn test_authorize_checks_every_credential() {
+        let config = Config {
+            credentials: parse_credentials(&format!("user:pass, ops:{}", bcrypt::hash("s3cret:x", 4).unwrap())).unwrap(),
+            ..test_config()
+        };
+        assert!(authorize(&request_with_auth(&basic("user:pass")), &config).await);
+        assert!(authorize(&request_with_auth(&basic("ops:s3cret:x")), &config).await);
+        assert!(authorize(&request_with_auth(&format!("basic  {}", base64::encode("user:pass"))), &config).await);
+
+        assert!(!authorize(&request_with_auth(&basic("user:wrong")), &config).await);
+        assert!(!authorize(&request_with_auth(&basic("user:passs")), &config).await);
+        assert!(!authorize(&request_with_auth(&basic("ops:pass")), &config).await);
+        // An unknown user is checked against the dummy hash, which no password matches
+        assert!(!authorize(&request_with_auth(&basic("nobody:s3cret:x")), &config).await);
+        assert!(!bcrypt_matches("", DUMMY_BCRYPT_HASH).await);
+        // The bcrypt hash itself is not a valid password
+        assert!(!authorize(&request_with_auth(&basic(&format!("ops:{}", config.credentials[1].password))), &config).await);
+        assert!(!authorize(&Request::new(Body::empty()), &config).await);
+    }
+
+    #[tokio::test]
+    async fn test_authorize_rejects_malformed_headers() {
+        let config = test_config();
+        for header in [
+            "Basic",
+            "Basic !!!not-base64!!!",
+            &basic("userpass"),
+            &format!("Basic {}", base64::encode([0xff, 0xfe, b':', b'x'])),
+            &format!("Bearer {}", base64::encode("user:pass")),
+            &base64::encode("user:pass"),
+        ] {
+            assert!(!authorize(&request_with_auth(header), &config).await, "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"pass", b"pass"));
+        assert!(!constant_time_eq(b"pass", b"pasS"));
+        assert!(!constant_time_eq(b"pass", b"pas"));
+        assert!(!constant_time_eq(b"", b"pass"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn test_parse_credentials() {
+        let credentials = parse_credentials("a:1, b:$2b$04$abc,").unwrap();
+        assert_eq!(credentials.len(), 2);
+        assert!(!credentials[0].is_bcrypt());
+        assert!(credentials[1].is_bcrypt());
+        assert!(parse_credentials("nopassword").is_err());
+        assert!(parse_credentials(":pass").is_err());
+    }
+
+    #[test]
+    fn test_cache_control_parsing() {
+        assert_eq!(cache_control_max_age("public, max-age=60"), Some(60));
+        assert_eq!(cache_control_max_age("max-age=60, s-maxage=300"), Some(300));
+        assert_eq!(cache_control_max_age("no-cache"), None);
+        assert!(is_cacheable(None));
+        assert!(is_cacheable(Some("public, max-age=60")));
+        assert!(!is_cacheable(Some("private, max-age=60")));
+        assert!(!is_cacheable(Some("no-store")));
+    }
+}
@@ -1,60 +1,286 @@
 use std::process::Command;
 use std::fs;
-use serde_json::Value;
-use reqwest::Client;
-use serde::Deserialize;
+use serde_json::{json, Value};
+use serde::{Deserialize, Serialize};
 use std::path::Path;
+use std::sync::Arc;
 use tokio::fs as async_fs;
 use std::error::Error;
 use log::{info, error};
 use clap::{Arg, Command as ClapCommand};
 
-// Define a struct to represent the security report
-#[derive(Deserialize)]
+#[allow(dead_code)]
+mod http;
+
+// How serious a finding is, using SARIF's level names
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Level {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Level {
+    fn parse(level: &str) -> Option<Level> {
+        match level {
+            "error" => Some(Level::Error),
+            "warning" => Some(Level::Warning),
+            "note" => Some(Level::Note),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Note => "note",
+        }
+    }
+}
+
+// One finding as a single tool reported it
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Finding {
+    id: String,
+    level: Option<Level>,
+    line: Option<u64>,
+}
+
+impl Finding {
+    fn new(id: impl Into<String>) -> Self {
+        Finding { id: id.into(), ..Finding::default() }
+    }
+}
+
+// A vulnerability at one line (or at no particular line) and the tools that
+// reported it there. The level is the first any tool gave; a vulnerability
+// without a level counts as an error
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Vulnerability {
+    id: String,
+    sources: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    level: Option<Level>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    line: Option<u64>,
+}
+
+// Define a struct to represent the security report, merged across all tools
+#[derive(Debug, Serialize, Deserialize)]
 struct SecurityReport {
-    vulnerabilities: Vec<String>,
+    vulnerabilities: Vec<Vulnerability>,
     file_path: String,
     analysis_time: String,
 }
 
+// Normalizes a tool's JSON output into the vulnerabilities it found
+trait OutputAdapter: Send + Sync {
+    fn parse(&self, output: &str) -> Result<Vec<Finding>, serde_json::Error>;
+}
+
+// `{"vulnerabilities": ["CVE-...", ...]}`, the format tools were originally expected to emit
+struct NativeAdapter;
+
+impl OutputAdapter for NativeAdapter {
+    fn parse(&self, output: &str) -> Result<Vec<Finding>, serde_json::Error> {
+        #[derive(Deserialize)]
+        struct NativeOutput {
+            vulnerabilities: Vec<String>,
+        }
+        Ok(serde_json::from_str::<NativeOutput>(output)?.vulnerabilities.into_iter().map(Finding::new).collect())
+    }
+}
+
+// SARIF, emitted by most linters: the `ruleId` of every result in every run,
+// with its level and the line of its first location
+struct SarifAdapter;
+
+impl OutputAdapter for SarifAdapter {
+    fn parse(&self, output: &str) -> Result<Vec<Finding>, serde_json::Error> {
+        let sarif: Value = serde_json::from_str(output)?;
+        let runs = sarif["runs"].as_array().into_iter().flatten();
+        Ok(runs
+            .flat_map(|run| run["results"].as_array().into_iter().flatten())
+            .filter_map(|result| {
+                Some(Finding {
+                    id: result["ruleId"].as_str()?.to_string(),
+                    level: result["level"].as_str().and_then(Level::parse),
+                    line: result["locations"][0]["physicalLocation"]["region"]["startLine"].as_u64(),
+                })
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OutputFormat {
+    #[default]
+    Native,
+    Sarif,
+}
+
+impl OutputFormat {
+    fn adapter(self) -> &'static dyn OutputAdapter {
+        match self {
+            OutputFormat::Native => &NativeAdapter,
+            OutputFormat::Sarif => &SarifAdapter,
+        }
+    }
+}
+
+// An external analysis tool, run as `path args... <file>`
+#[derive(Debug, Clone, Deserialize)]
+struct ToolConfig {
+    name: String,
+    path: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    format: OutputFormat,
+}
+
 // Configuration struct
 struct Config {
-    tool_path: String,
+    tools: Vec<ToolConfig>,
     vulnerability_db_url: String,
     file_paths: Vec<String>,
+    // Also write each report as SARIF, for code scanning in CI
+    sarif: bool,
 }
 
-// Function to fetch the vulnerability database from a remote URL
-async fn fetch_vulnerability_db(url: &str) -> Result<Value, reqwest::Error> {
-    let client = Client::new();
+// Function to fetch the vulnerability database from a remote URL.
+// An internal database has to be listed in HTTP_ALLOWED_HOSTS
+async fn fetch_vulnerability_db(url: &str) -> Result<Value, Box<dyn Error>> {
+    let config = http::HttpConfig::from_env();
+    let url = http::check_url_async(url, &config.fetch_policy).await?;
+    let client = http::client_with(&config);
     let res = client.get(url).send().await?;
-    res.json().await
+    Ok(res.json().await?)
 }
 
 // Function to execute the security analysis tool on a specified file
-fn run_analysis_tool(tool_path: &str, file_path: &str) -> Result<String, std::io::Error> {
-    let output = Command::new(tool_path)
+fn run_analysis_tool(tool: &ToolConfig, file_path: &str) -> Result<String, std::io::Error> {
+    let output = Command::new(&tool.path)
+        .args(&tool.args)
         .arg(file_path)
         .output()?;
     
     if output.status.success() {
         Ok(String::from_utf8_lossy(&output.stdout).to_string())
     } else {
-        Err(std::io::Error::new(std::io::ErrorKind::Other, "Analysis failed"))
+        Err(std::io::Error::other(format!("{} failed", tool.name)))
     }
 }
 
-// Function to analyze the security report and print vulnerabilities
-fn analyze_report(report: &str) -> Result<(), serde_json::Error> {
-    let report: SecurityReport = serde_json::from_str(report)?;
-    
+// Function to merge each tool's findings, reporting a vulnerability found at
+// the same line by several tools once, and once per line it was found at
+fn merge_findings(file_path: &str, findings: Vec<(String, Vec<Finding>)>) -> SecurityReport {
+    let mut vulnerabilities: Vec<Vulnerability> = Vec::new();
+    for (tool, found) in findings {
+        for Finding { id, level, line } in found {
+            let id = id.trim().to_string();
+            let existing =
+                vulnerabilities.iter_mut().find(|vulnerability| vulnerability.id == id && vulnerability.line == line);
+            let Some(existing) = existing else {
+                vulnerabilities.push(Vulnerability { id, sources: vec![tool.clone()], level, line });
+                continue;
+            };
+            if !existing.sources.contains(&tool) {
+                existing.sources.push(tool.clone());
+            }
+            existing.level = existing.level.or(level);
+        }
+    }
+
+    SecurityReport {
+        vulnerabilities,
+        file_path: file_path.to_string(),
+        analysis_time: chrono::Utc::now().to_rfc3339(),
+    }
+}
+
+// Function to run every tool on a file; a failing tool is logged and left out of the report
+fn analyze_file(tools: &[ToolConfig], file_path: &str) -> SecurityReport {
+    let mut findings = Vec::new();
+    for tool in tools {
+        let output = match run_analysis_tool(tool, file_path) {
+            Ok(output) => output,
+            Err(e) => {
+                error!("Analysis failed for {}: {}", file_path, e);
+                continue;
+            }
+        };
+        match tool.format.adapter().parse(&output) {
+            Ok(found) => findings.push((tool.name.clone(), found)),
+            Err(e) => error!("Failed to parse {} output for {}: {}", tool.name, file_path, e),
+        }
+    }
+    merge_findings(file_path, findings)
+}
+
+// Function to print the vulnerabilities in a security report
+fn analyze_report(report: &SecurityReport) {
     println!("Analysis Report for File: {}", report.file_path);
     println!("Analysis Time: {}", report.analysis_time);
     
     for vulnerability in report.vulnerabilities.iter() {
-        println!("Vulnerability found: {}", vulnerability);
+        println!("Vulnerability found: {} (reported by {})", vulnerability.id, vulnerability.sources.join(", "));
     }
-    
+}
+
+// Write `report` as a SARIF 2.1.0 log with one run: a rule per distinct
+// vulnerability id, and a result for each vulnerability at the report's file
+// (and line, when a tool gave one)
+fn write_sarif(report: &SecurityReport, out: &Path) -> Result<(), Box<dyn Error>> {
+    let mut rule_ids: Vec<&str> = Vec::new();
+    let mut rules: Vec<Value> = Vec::new();
+    for vulnerability in &report.vulnerabilities {
+        if rule_ids.contains(&vulnerability.id.as_str()) {
+            continue;
+        }
+        rule_ids.push(&vulnerability.id);
+        rules.push(json!({
+            "id": vulnerability.id,
+            "shortDescription": { "text": vulnerability.id },
+            "defaultConfiguration": { "level": vulnerability.level.unwrap_or(Level::Error).as_str() },
+        }));
+    }
+    let results: Vec<Value> = report
+        .vulnerabilities
+        .iter()
+        .map(|vulnerability| {
+            let rule_index = rule_ids.iter().position(|id| *id == vulnerability.id).unwrap_or_default();
+            let mut location = json!({ "artifactLocation": { "uri": report.file_path } });
+            if let Some(line) = vulnerability.line {
+                location["region"] = json!({ "startLine": line });
+            }
+            json!({
+                "ruleId": vulnerability.id,
+                "ruleIndex": rule_index,
+                "level": vulnerability.level.unwrap_or(Level::Error).as_str(),
+                "message": {
+                    "text": format!("{} (reported by {})", vulnerability.id, vulnerability.sources.join(", ")),
+                },
+                "locations": [{ "physicalLocation": location }],
+            })
+        })
+        .collect();
+
+    let sarif = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": { "name": "noxium-codeanalyzer", "version": env!("CARGO_PKG_VERSION"), "rules": rules },
+            },
+            "invocations": [{ "executionSuccessful": true, "endTimeUtc": report.analysis_time }],
+            "results": results,
+        }],
+    });
+    fs::write(out, serde_json::to_string_pretty(&sarif)?)?;
     Ok(())
 }
 
@@ -69,8 +295,8 @@ fn compare_vulnerabilities(local_report: &str, fetched_db: &Value) -> Vec<String
     let local_report: SecurityReport = serde_json::from_str(local_report).unwrap();
     
     for vulnerability in local_report.vulnerabilities.iter() {
-        if fetched_db["vulnerabilities"].as_array().unwrap_or(&vec![]).contains(&Value::String(vulnerability.clone())) {
-            detected_vulnerabilities.push(vulnerability.clone());
+        if fetched_db["vulnerabilities"].as_array().unwrap_or(&vec![]).contains(&Value::String(vulnerability.id.clone())) {
+            detected_vulnerabilities.push(vulnerability.id.clone());
         }
     }
     
@@ -91,28 +317,34 @@ fn validate_file_path(file_path: &str) -> Result<(), Box<dyn Error>> {
 // Function to analyze multiple files concurrently
 async fn analyze_files(file_paths: Vec<String>, config: &Config) -> Result<(), Box<dyn Error>> {
     let mut handles = Vec::new();
+    let tools = Arc::new(config.tools.clone());
+    let sarif = config.sarif;
     
     for file_path in file_paths {
-        let tool_path = config.tool_path.clone();
+        let tools = tools.clone();
         let report_file_path = format!("{}.report.json", file_path);
         
         handles.push(tokio::spawn(async move {
-            match validate_file_path(&file_path) {
-                Ok(()) => {
-                    match run_analysis_tool(&tool_path, &file_path) {
-                        Ok(analysis_report) => {
-                            if let Err(e) = save_report_to_file(&analysis_report, &report_file_path).await {
-                                error!("Failed to save report for {}: {}", file_path, e);
-                            }
-                            if let Err(e) = analyze_report(&analysis_report) {
-                                error!("Failed to analyze report for {}: {}", file_path, e);
-                            }
-                        },
-                        Err(e) => error!("Analysis failed for {}: {}", file_path, e),
+            if let Err(e) = validate_file_path(&file_path) {
+                error!("Validation failed for {}: {}", file_path, e);
+                return;
+            }
+
+            let report = analyze_file(&tools, &file_path);
+            match serde_json::to_string_pretty(&report) {
+                Ok(json) => {
+                    if let Err(e) = save_report_to_file(&json, &report_file_path).await {
+                        error!("Failed to save report for {}: {}", file_path, e);
                     }
                 },
-                Err(e) => error!("Validation failed for {}: {}", file_path, e),
+                Err(e) => error!("Failed to serialize report for {}: {}", file_path, e),
             }
+            if sarif {
+                if let Err(e) = write_sarif(&report, Path::new(&format!("{}.sarif", file_path))) {
+                    error!("Failed to write SARIF report for {}: {}", file_path, e);
+                }
+            }
+            analyze_report(&report);
         }));
     }
     
@@ -153,8 +385,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .arg(Arg::new("tool_path")
             .long("tool")
             .takes_value(true)
-            .required(true)
-            .help("Path to the security analysis tool"))
+            .help("Path to a security analysis tool emitting the native report format"))
+        .arg(Arg::new("tools_config")
+            .long("tools")
+            .takes_value(true)
+            .help("JSON file listing analysis tools as {name, path, args, format}"))
         .arg(Arg::new("db_url")
             .long("db")
             .takes_value(true)
@@ -166,13 +401,34 @@ async fn main() -> Result<(), Box<dyn Error>> {
             .multiple_values(true)
             .required(true)
             .help("Paths to files to analyze"))
+        .arg(Arg::new("sarif")
+            .long("sarif")
+            .help("Also write each report as <file>.sarif (SARIF 2.1.0)"))
         .get_matches();
     
+    // Tools from the config file, plus the one given with --tool
+    let mut tools: Vec<ToolConfig> = match matches.value_of("tools_config") {
+        Some(path) => serde_json::from_str(&fs::read_to_string(path)?)?,
+        None => Vec::new(),
+    };
+    if let Some(tool_path) = matches.value_of("tool_path") {
+        tools.push(ToolConfig {
+            name: tool_path.to_string(),
+            path: tool_path.to_string(),
+            args: Vec::new(),
+            format: OutputFormat::Native,
+        });
+    }
+    if tools.is_empty() {
+        return Err("No analysis tools configured; pass --tool or --tools".into());
+    }
+
     // Configuration settings
     let config = Config {
-        tool_path: matches.value_of("tool_path").unwrap().to_string(),
+        tools,
         vulnerability_db_url: matches.value_of("db_url").unwrap().to_string(),
         file_paths: matches.values_of("files").unwrap().map(|s| s.to_string()).collect(),
+        sarif: matches.is_present("sarif"),
     };
     
     // Analyze multiple files
@@ -188,4 +444,128 @@ async fn main() -> Result<(), Box<dyn Error>> {
     print_summary(&config.file_paths, &fetched_db)?;
     
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    // A tool that ignores the file and prints canned output
+    fn mock_tool(name: &str, format: OutputFormat, output: &str) -> ToolConfig {
+        ToolConfig {
+            name: name.to_string(),
+            path: "sh".to_string(),
+            args: vec!["-c".to_string(), format!("printf '%s' '{}'", output)],
+            format,
+        }
+    }
+
+    #[test]
+    fn test_findings_from_several_tools_are_merged() {
+        let auditor = mock_tool(
+            "auditor",
+            OutputFormat::Native,
+            r#"{"vulnerabilities": ["CVE-2024-0001", "CVE-2024-0002"], "file_path": "app.js", "analysis_time": "now"}"#,
+        );
+        let linter = mock_tool(
+            "linter",
+            OutputFormat::Sarif,
+            r#"{"version": "2.1.0", "runs": [{"results": [{"ruleId": "CVE-2024-0002"}, {"ruleId": "no-eval"}, {"ruleId": "no-eval"}]}]}"#,
+        );
+        let broken = mock_tool("broken", OutputFormat::Sarif, "not json");
+
+        let report = analyze_file(&[auditor, linter, broken], "app.js");
+        let found: Vec<(&str, Vec<&str>)> = report
+            .vulnerabilities
+            .iter()
+            .map(|v| (v.id.as_str(), v.sources.iter().map(String::as_str).collect()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("CVE-2024-0001", vec!["auditor"]),
+                ("CVE-2024-0002", vec!["auditor", "linter"]),
+                ("no-eval", vec!["linter"]),
+            ]
+        );
+        assert_eq!(report.file_path, "app.js");
+    }
+
+    #[test]
+    fn test_report_is_written_as_sarif() {
+        let linter = mock_tool(
+            "linter",
+            OutputFormat::Sarif,
+            r#"{"runs": [{"results": [{"ruleId": "no-eval", "level": "warning",
+                "locations": [{"physicalLocation": {"region": {"startLine": 12}}}]}]}]}"#,
+        );
+        let auditor = mock_tool("auditor", OutputFormat::Native, r#"{"vulnerabilities": ["CVE-2024-0001"]}"#);
+        let report = analyze_file(&[linter, auditor], "src/app.js");
+
+        let out = std::env::temp_dir().join(format!("noxium-codeanalyzer-{}.sarif", std::process::id()));
+        write_sarif(&report, &out).unwrap();
+        let sarif: Value = serde_json::from_str(&fs::read_to_string(&out).unwrap()).unwrap();
+        fs::remove_file(&out).unwrap();
+
+        assert_eq!(sarif["version"], "2.1.0");
+        assert!(sarif["$schema"].is_string());
+        let run = &sarif["runs"][0];
+        assert_eq!(run["tool"]["driver"]["name"], "noxium-codeanalyzer");
+        assert_eq!(run["tool"]["driver"]["rules"].as_array().unwrap().len(), 2);
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+
+        assert_eq!(results[0]["ruleId"], "no-eval");
+        assert_eq!(results[0]["level"], "warning");
+        let location = &results[0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/app.js");
+        assert_eq!(location["region"]["startLine"], 12);
+
+        assert_eq!(results[1]["ruleId"], "CVE-2024-0001");
+        assert_eq!(results[1]["ruleIndex"], 1);
+        assert_eq!(results[1]["level"], "error");
+        assert!(results[1]["message"]["text"].as_str().unwrap().contains("auditor"));
+        assert!(results[1]["locations"][0]["physicalLocation"].get("region").is_none());
+    }
+
+    #[test]
+    fn test_each_occurrence_gets_a_result_under_one_rule() {
+        let linter = mock_tool(
+            "linter",
+            OutputFormat::Sarif,
+            r#"{"runs": [{"results": [
+                {"ruleId": "no-eval", "locations": [{"physicalLocation": {"region": {"startLine": 3}}}]},
+                {"ruleId": "no-unused", "locations": [{"physicalLocation": {"region": {"startLine": 5}}}]},
+                {"ruleId": "no-eval", "locations": [{"physicalLocation": {"region": {"startLine": 9}}}]}]}]}"#,
+        );
+        let scanner = mock_tool(
+            "scanner",
+            OutputFormat::Sarif,
+            r#"{"runs": [{"results": [{"ruleId": "no-eval", "locations": [{"physicalLocation": {"region": {"startLine": 9}}}]}]}]}"#,
+        );
+        let report = analyze_file(&[linter, scanner], "app.js");
+        let found: Vec<(&str, Option<u64>, usize)> =
+            report.vulnerabilities.iter().map(|v| (v.id.as_str(), v.line, v.sources.len())).collect();
+        assert_eq!(found, [("no-eval", Some(3), 1), ("no-unused", Some(5), 1), ("no-eval", Some(9), 2)]);
+
+        let out = std::env::temp_dir().join(format!("noxium-codeanalyzer-occurrences-{}.sarif", std::process::id()));
+        write_sarif(&report, &out).unwrap();
+        let sarif: Value = serde_json::from_str(&fs::read_to_string(&out).unwrap()).unwrap();
+        fs::remove_file(&out).unwrap();
+
+        let run = &sarif["runs"][0];
+        let rules: Vec<&str> =
+            run["tool"]["driver"]["rules"].as_array().unwrap().iter().map(|rule| rule["id"].as_str().unwrap()).collect();
+        assert_eq!(rules, ["no-eval", "no-unused"]);
+        let results: Vec<(&str, u64, u64)> = run["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| {
+                let line = result["locations"][0]["physicalLocation"]["region"]["startLine"].as_u64().unwrap();
+                (result["ruleId"].as_str().unwrap(), result["ruleIndex"].as_u64().unwrap(), line)
+            })
+            .collect();
+        assert_eq!(results, [("no-eval", 0, 3), ("no-unused", 1, 5), ("no-eval", 0, 9)]);
+    }
+}
@@ -0,0 +1,208 @@
+use std::convert::Infallible;
+use std::io::{self, Write};
+use warp::http::header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use warp::http::StatusCode;
+use warp::hyper::body::{self, Body};
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+/// Bodies smaller than this are sent as-is; compressing them saves too
+/// little to be worth the CPU and the encoding overhead.
+pub const MIN_COMPRESS_BYTES: usize = 1024;
+
+/// A content coding the warp servers can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+        }
+    }
+
+    fn encode(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Encoding::Brotli => {
+                // Quality 5 keeps per-request latency low while still beating gzip on text
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                Ok(encoder.into_inner())
+            }
+        }
+    }
+}
+
+/// Picks the coding to use for a request's `Accept-Encoding` header,
+/// preferring brotli when the client weighs both equally. Returns `None`
+/// when the client only accepts `identity` or refuses both with `q=0`.
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let (mut brotli, mut gzip, mut any) = (None, None, None);
+    for part in accept_encoding.split(',') {
+        let mut params = part.split(';');
+        let coding = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let quality = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .map_or(1.0, |q| q.trim().parse::<f32>().unwrap_or(0.0));
+        match coding.as_str() {
+            "br" => brotli = Some(quality),
+            "gzip" | "x-gzip" => gzip = Some(quality),
+            "*" => any = Some(quality),
+            _ => {}
+        }
+    }
+
+    // `*` only covers codings the client did not list explicitly
+    let brotli = brotli.or(any).unwrap_or(0.0);
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    if brotli <= 0.0 && gzip <= 0.0 {
+        None
+    } else if brotli >= gzip {
+        Some(Encoding::Brotli)
+    } else {
+        Some(Encoding::Gzip)
+    }
+}
+
+// Text-like bodies shrink well; images, archives, and other binary formats are already compressed
+fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime.as_str(),
+            "application/json" | "application/javascript" | "application/xml" | "image/svg+xml"
+        )
+}
+
+/// Compresses `reply` for a client that sent `accept_encoding`, setting
+/// `Content-Encoding` and `Vary: Accept-Encoding`.
+///
+/// Responses that already carry a `Content-Encoding`, are not text-like, or
+/// are smaller than [`MIN_COMPRESS_BYTES`] are passed through unchanged
+/// (apart from `Vary` for compressible types).
+pub async fn compress_reply(accept_encoding: Option<String>, reply: impl Reply) -> Response {
+    let response = reply.into_response();
+    let compressible = !response.headers().contains_key(CONTENT_ENCODING)
+        && response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(is_compressible);
+    if !compressible {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    parts.headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+    let Some(encoding) = accept_encoding.as_deref().and_then(negotiate) else {
+        return Response::from_parts(parts, body);
+    };
+
+    let bytes = match body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            log::error!("Failed to read response body for compression: {}", e);
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            return response;
+        }
+    };
+    if bytes.len() < MIN_COMPRESS_BYTES {
+        return Response::from_parts(parts, Body::from(bytes));
+    }
+
+    match encoding.encode(&bytes) {
+        Ok(compressed) => {
+            parts.headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.as_str()));
+            parts.headers.remove(CONTENT_LENGTH);
+            Response::from_parts(parts, Body::from(compressed))
+        }
+        Err(e) => {
+            log::warn!("Failed to {} response body, sending it uncompressed: {}", encoding.as_str(), e);
+            Response::from_parts(parts, Body::from(bytes))
+        }
+    }
+}
+
+/// Wraps a server's recovered routes so every response is compressed per
+/// the request's `Accept-Encoding`:
+/// `warp::serve(with_compression(routes.recover(handle_rejection)))`.
+pub fn with_compression<F, R>(routes: F) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply + Send,
+{
+    warp::header::optional::<String>("accept-encoding")
+        .and(routes)
+        .then(compress_reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::Read;
+
+    fn large_json_routes() -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+        let items: Vec<_> = (0..200).map(|i| json!({ "id": i, "name": format!("item {}", i) })).collect();
+        let route = warp::path("items").map(move || warp::reply::json(&items));
+        with_compression(route.recover(|_: Rejection| async { Ok::<_, Infallible>(StatusCode::NOT_FOUND) }))
+    }
+
+    #[tokio::test]
+    async fn test_large_json_is_gzipped_when_requested() {
+        let response = warp::test::request()
+            .path("/items")
+            .header("accept-encoding", "gzip, deflate")
+            .reply(&large_json_routes())
+            .await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[VARY], "accept-encoding");
+
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&response.body()[..]).read_to_string(&mut decoded).unwrap();
+        assert!(response.body().len() < decoded.len());
+        let items: serde_json::Value = serde_json::from_str(&decoded).unwrap();
+        assert_eq!(items[199]["name"], "item 199");
+    }
+
+    #[tokio::test]
+    async fn test_identity_client_gets_uncompressed_body() {
+        let response = warp::test::request()
+            .path("/items")
+            .header("accept-encoding", "identity")
+            .reply(&large_json_routes())
+            .await;
+
+        assert_eq!(response.status(), 200);
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(response.headers()[VARY], "accept-encoding");
+        let items: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(items.as_array().unwrap().len(), 200);
+    }
+
+    #[test]
+    fn test_negotiation_honors_quality_values() {
+        assert_eq!(negotiate("gzip, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("br;q=0.5, gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br;q=0, *"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip;q=0, br;q=0"), None);
+        assert_eq!(negotiate("identity"), None);
+        assert!(!is_compressible("image/png"));
+        assert!(is_compressible("application/json; charset=utf-8"));
+    }
+}
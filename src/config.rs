@@ -0,0 +1,139 @@
+use ::config::{Config, Environment, File, FileFormat};
+use serde::Deserialize;
+use std::path::Path;
+use thiserror::Error;
+
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_RATE_LIMIT: u32 = 100;
+const DEFAULT_CACHE_DURATION: u64 = 3600;
+const ENV_PREFIX: &str = "NOXIUM";
+
+/// Settings shared by the server binaries, loaded from a TOML file with
+/// `NOXIUM_*` environment variables taking precedence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoxiumConfig {
+    pub port: u16,
+    pub database_url: String,
+    /// Requests allowed per client per minute.
+    pub rate_limit: u32,
+    /// Cache lifetime in seconds.
+    pub cache_duration: u64,
+    pub jwt_secret: String,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to load configuration: {0}")]
+    Load(#[from] ::config::ConfigError),
+    #[error("Missing required configuration value: {0}")]
+    Missing(&'static str),
+    #[error("Invalid configuration value for {field}: {reason}")]
+    Invalid { field: &'static str, reason: String },
+}
+
+// Raw shape as read from the sources, before defaults and validation
+#[derive(Debug, Default, Deserialize)]
+struct RawConfig {
+    port: Option<u16>,
+    database_url: Option<String>,
+    rate_limit: Option<u32>,
+    cache_duration: Option<u64>,
+    jwt_secret: Option<String>,
+}
+
+impl NoxiumConfig {
+    /// Loads the configuration from `path` (optional) and `NOXIUM_*` env vars.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        Self::load_with_prefix(path, ENV_PREFIX)
+    }
+
+    fn load_with_prefix(path: impl AsRef<Path>, env_prefix: &str) -> Result<Self, ConfigError> {
+        let raw: RawConfig = Config::builder()
+            .add_source(File::from(path.as_ref()).format(FileFormat::Toml).required(false))
+            .add_source(Environment::with_prefix(env_prefix).prefix_separator("_").try_parsing(true))
+            .build()?
+            .try_deserialize()?;
+
+        Self::from_raw(raw)
+    }
+
+    fn from_raw(raw: RawConfig) -> Result<Self, ConfigError> {
+        let database_url = required(raw.database_url, "database_url")?;
+        let jwt_secret = required(raw.jwt_secret, "jwt_secret")?;
+
+        let port = raw.port.unwrap_or(DEFAULT_PORT);
+        if port == 0 {
+            return Err(ConfigError::Invalid {
+                field: "port",
+                reason: "must be greater than 0".to_string(),
+            });
+        }
+
+        let rate_limit = raw.rate_limit.unwrap_or(DEFAULT_RATE_LIMIT);
+        if rate_limit == 0 {
+            return Err(ConfigError::Invalid {
+                field: "rate_limit",
+                reason: "must be greater than 0".to_string(),
+            });
+        }
+
+        Ok(NoxiumConfig {
+            port,
+            database_url,
+            rate_limit,
+            cache_duration: raw.cache_duration.unwrap_or(DEFAULT_CACHE_DURATION),
+            jwt_secret,
+        })
+    }
+}
+
+fn required(value: Option<String>, field: &'static str) -> Result<String, ConfigError> {
+    match value {
+        Some(v) if !v.trim().is_empty() => Ok(v),
+        _ => Err(ConfigError::Missing(field)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn write_config(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = env::temp_dir().join(name);
+        fs::write(&path, contents).expect("Failed to write test config");
+        path
+    }
+
+    #[test]
+    fn test_env_overrides_file() {
+        let path = write_config(
+            "noxium_precedence.toml",
+            "port = 3000\ndatabase_url = \"sqlite://file.db\"\njwt_secret = \"file-secret\"\nrate_limit = 10\n",
+        );
+        env::set_var("NOXTESTA_PORT", "4000");
+        env::set_var("NOXTESTA_JWT_SECRET", "env-secret");
+
+        let config = NoxiumConfig::load_with_prefix(&path, "NOXTESTA").expect("Config should load");
+        assert_eq!(config.port, 4000);
+        assert_eq!(config.jwt_secret, "env-secret");
+        assert_eq!(config.database_url, "sqlite://file.db");
+        assert_eq!(config.rate_limit, 10);
+        assert_eq!(config.cache_duration, DEFAULT_CACHE_DURATION);
+
+        env::remove_var("NOXTESTA_PORT");
+        env::remove_var("NOXTESTA_JWT_SECRET");
+        fs::remove_file(path).expect("Failed to remove test config");
+    }
+
+    #[test]
+    fn test_missing_required_field() {
+        let path = write_config("noxium_missing.toml", "port = 3000\ndatabase_url = \"sqlite://file.db\"\n");
+
+        let result = NoxiumConfig::load_with_prefix(&path, "NOXTESTB");
+        assert!(matches!(result, Err(ConfigError::Missing("jwt_secret"))));
+
+        fs::remove_file(path).expect("Failed to remove test config");
+    }
+}
@@ -1,10 +1,60 @@
 use std::collections::HashMap;
+use std::env;
+use std::fmt;
 use std::process::Command;
 use std::io::{self, Write};
 use std::fs;
+use std::str::FromStr;
 use std::time::Duration;
 use std::thread;
 
+// Docker restart policy, passed as `--restart`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RestartPolicy {
+    No,
+    Always,
+    UnlessStopped,
+    // Restart on non-zero exit, optionally giving up after this many retries
+    OnFailure(Option<u32>),
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestartPolicy::No => write!(f, "no"),
+            RestartPolicy::Always => write!(f, "always"),
+            RestartPolicy::UnlessStopped => write!(f, "unless-stopped"),
+            RestartPolicy::OnFailure(None) => write!(f, "on-failure"),
+            RestartPolicy::OnFailure(Some(retries)) => write!(f, "on-failure:{}", retries),
+        }
+    }
+}
+
+// Parses Docker's `--restart` syntax, e.g. `unless-stopped` or `on-failure:5`
+impl FromStr for RestartPolicy {
+    type Err = io::Error;
+
+    fn from_str(value: &str) -> io::Result<Self> {
+        let policy = match value.split_once(':') {
+            None => match value {
+                "no" => Some(RestartPolicy::No),
+                "always" => Some(RestartPolicy::Always),
+                "unless-stopped" => Some(RestartPolicy::UnlessStopped),
+                "on-failure" => Some(RestartPolicy::OnFailure(None)),
+                _ => None,
+            },
+            Some(("on-failure", retries)) => retries.parse().ok().map(|retries| RestartPolicy::OnFailure(Some(retries))),
+            Some(_) => None,
+        };
+        policy.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid restart policy '{}', expected no, always, unless-stopped or on-failure[:N]", value),
+            )
+        })
+    }
+}
+
 // Struct to represent a container
 #[derive(Debug)]
 struct Container {
@@ -12,6 +62,9 @@ struct Container {
     image: String,
     ports: HashMap<u16, u16>,
     environment: HashMap<String, String>,
+    memory_limit: Option<String>,
+    cpu_limit: Option<f64>,
+    restart_policy: Option<RestartPolicy>,
 }
 
 impl Container {
@@ -22,31 +75,53 @@ impl Container {
             image: image.to_string(),
             ports: HashMap::new(),
             environment: HashMap::new(),
+            memory_limit: None,
+            cpu_limit: None,
+            restart_policy: None,
         }
     }
 
+    // Build the `docker run` arguments; limits and restart policy are only passed when set
+    fn run_args(&self) -> Vec<String> {
+        let mut args = vec!["run".to_string(), "-d".to_string(), "--name".to_string(), self.id.clone()];
+
+        // Sorted so the command line is stable
+        let mut ports: Vec<_> = self.ports.iter().collect();
+        ports.sort();
+        for (host_port, container_port) in ports {
+            args.push("-p".to_string());
+            args.push(format!("{}:{}", host_port, container_port));
+        }
+
+        let mut env_vars: Vec<_> = self.environment.iter().collect();
+        env_vars.sort();
+        for (key, value) in env_vars {
+            args.push("-e".to_string());
+            args.push(format!("{}={}", key, value));
+        }
+
+        if let Some(memory) = &self.memory_limit {
+            args.push("--memory".to_string());
+            args.push(memory.clone());
+        }
+        if let Some(cpus) = self.cpu_limit {
+            args.push("--cpus".to_string());
+            args.push(cpus.to_string());
+        }
+        if let Some(policy) = self.restart_policy {
+            args.push("--restart".to_string());
+            args.push(policy.to_string());
+        }
+
+        args.push(self.image.clone());
+        args
+    }
+
     // Start the container
     fn start(&self) -> io::Result<()> {
-        // Build port mappings argument for Docker
-        let port_mappings: Vec<String> = self.ports.iter()
-            .map(|(host_port, container_port)| format!("{}:{}", host_port, container_port))
-            .collect();
-        let port_mapping_arg = port_mappings.join(" ");
-
-        // Build environment variables argument for Docker
-        let env_vars: Vec<String> = self.environment.iter()
-            .map(|(key, value)| format!("{}={}", key, value))
-            .collect();
-        let env_vars_arg = env_vars.join(" ");
-
-        // Run Docker container
+        // Run Docker container in detached mode
         let output = Command::new("docker")
-            .arg("run")
-            .arg("-d") // Run container in detached mode
-            .arg("--name").arg(&self.id)
-            .arg("-p").arg(port_mapping_arg)
-            .args(env_vars.iter().map(|var| ["-e", var]).flatten())
-            .arg(&self.image)
+            .args(self.run_args())
             .output()?;
 
         // Check if Docker command was successful
@@ -90,6 +165,37 @@ impl Container {
         self.environment = environment;
     }
 
+    // Set the memory limit in Docker's format: a number with an optional b/k/m/g suffix, e.g. `512m` or `2g`
+    fn set_memory_limit(&mut self, limit: &str) -> io::Result<()> {
+        let digits = limit.strip_suffix(['b', 'k', 'm', 'g', 'B', 'K', 'M', 'G']).unwrap_or(limit);
+        let valid = !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) && digits.chars().any(|c| c != '0');
+        if !valid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid memory limit '{}', expected e.g. 512m or 2g", limit),
+            ));
+        }
+        self.memory_limit = Some(limit.to_string());
+        Ok(())
+    }
+
+    // Set how many CPUs the container may use, e.g. `1.5`
+    fn set_cpu_limit(&mut self, cpus: f64) -> io::Result<()> {
+        if !(cpus.is_finite() && cpus > 0.0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid CPU limit {}, expected a positive number", cpus),
+            ));
+        }
+        self.cpu_limit = Some(cpus);
+        Ok(())
+    }
+
+    // Set what Docker does when the container exits
+    fn set_restart_policy(&mut self, policy: RestartPolicy) {
+        self.restart_policy = Some(policy);
+    }
+
     // Get the logs of the container
     fn logs(&self) -> io::Result<String> {
         let output = Command::new("docker")
@@ -127,6 +233,12 @@ fn main() -> io::Result<()> {
     env_vars.insert("TZ".to_string(), "UTC".to_string());
     container.set_environment(env_vars);
 
+    // Keep the container from starving the host, and bring it back if it crashes
+    container.set_memory_limit("512m")?;
+    container.set_cpu_limit(1.0)?;
+    let restart_policy = env::var("CONTAINER_RESTART_POLICY").unwrap_or_else(|_| "on-failure:5".to_string());
+    container.set_restart_policy(restart_policy.parse()?);
+
     // Start the container
     container.start()?;
     println!("Container started");
@@ -154,4 +266,59 @@ fn main() -> io::Result<()> {
     println!("Container removed");
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Find the value following `flag` in the argument list
+    fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+        args.iter().position(|arg| arg == flag).map(|i| args[i + 1].as_str())
+    }
+
+    #[test]
+    fn test_run_args_include_limits_when_set() {
+        let mut container = Container::new("web", "nginx:latest");
+        container.set_ports(HashMap::from([(8080, 80)]));
+        container.set_memory_limit("512m").unwrap();
+        container.set_cpu_limit(1.5).unwrap();
+        container.set_restart_policy(RestartPolicy::OnFailure(None));
+
+        let args = container.run_args();
+        assert_eq!(flag_value(&args, "--memory"), Some("512m"));
+        assert_eq!(flag_value(&args, "--cpus"), Some("1.5"));
+        assert_eq!(flag_value(&args, "--restart"), Some("on-failure"));
+        assert_eq!(flag_value(&args, "-p"), Some("8080:80"));
+        assert_eq!(args.last().map(String::as_str), Some("nginx:latest"));
+    }
+
+    #[test]
+    fn test_run_args_omit_limits_when_unset() {
+        let args = Container::new("web", "nginx:latest").run_args();
+        assert_eq!(args, ["run", "-d", "--name", "web", "nginx:latest"]);
+    }
+
+    #[test]
+    fn test_memory_limit_format_is_validated() {
+        let mut container = Container::new("web", "nginx:latest");
+        for valid in ["512m", "2g", "2G", "1048576", "64k"] {
+            assert!(container.set_memory_limit(valid).is_ok(), "{}", valid);
+        }
+        for invalid in ["", "m", "0m", "512mb", "1.5g", "-2g", "lots"] {
+            assert!(container.set_memory_limit(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(container.memory_limit.as_deref(), Some("64k"));
+        assert!(container.set_cpu_limit(0.0).is_err());
+    }
+
+    #[test]
+    fn test_restart_policy_round_trips_docker_syntax() {
+        for value in ["no", "always", "unless-stopped", "on-failure", "on-failure:5"] {
+            assert_eq!(value.parse::<RestartPolicy>().unwrap().to_string(), value);
+        }
+        for invalid in ["", "never", "on-failure:", "on-failure:-1", "always:3"] {
+            assert!(invalid.parse::<RestartPolicy>().is_err(), "{}", invalid);
+        }
+    }
+}
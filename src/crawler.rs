@@ -0,0 +1,600 @@
+use reqwest::Client;
+use scraper::{Html, Selector};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+use url::Url;
+
+#[allow(dead_code)]
+#[path = "seo-analyze.rs"]
+mod seo_analyze;
+
+// Shared with the analyzer rather than compiled into the crawler twice
+use seo_analyze::http;
+use seo_analyze::{analyze_page, SeoResult};
+
+const USER_AGENT: &str = "noxium-crawler";
+
+// A robots.txt Crawl-delay longer than this is treated as this; some sites
+// ask for hours, which would stall the crawl rather than slow it
+const MAX_CRAWL_DELAY: Duration = Duration::from_secs(30);
+
+#[tokio::main]
+async fn main() {
+    let start_url = std::env::args().nth(1).unwrap_or_else(|| "https://example.com".to_string());
+
+    let opts = CrawlOptions { http: http::HttpConfig::from_env(), ..CrawlOptions::default() };
+    let report = crawl(&start_url, opts).await;
+    for page in &report.pages {
+        println!("[depth {}] {} ({}) - {:?}", page.depth, page.url, page.status, page.seo.title);
+    }
+    for error in &report.errors {
+        println!("[error] {}: {}", error.url, error.message);
+    }
+    println!(
+        "Crawled {} pages, {} words, {} missing titles, {} missing descriptions, average reading ease {:.1}",
+        report.pages.len(),
+        report.total_words(),
+        report.pages_missing_title().len(),
+        report.pages_missing_description().len(),
+        report.average_reading_ease()
+    );
+}
+
+/// Limits applied to a crawl.
+#[derive(Debug, Clone)]
+pub struct CrawlOptions {
+    /// How many links away from the start page to follow; the start page is depth 0.
+    pub max_depth: usize,
+    /// Upper bound on the number of pages fetched.
+    pub max_pages: usize,
+    /// Number of requests in flight at once, across all hosts.
+    pub concurrency: usize,
+    /// Least time between the starts of two requests to the same host. A
+    /// longer `Crawl-delay` in robots.txt takes precedence.
+    pub min_delay: Duration,
+    /// Timeouts for every request, and which hosts the start page and its
+    /// links may point at.
+    pub http: http::HttpConfig,
+}
+
+impl Default for CrawlOptions {
+    fn default() -> Self {
+        CrawlOptions {
+            max_depth: 3,
+            max_pages: 100,
+            concurrency: 4,
+            min_delay: Duration::from_millis(250),
+            http: http::HttpConfig::default(),
+        }
+    }
+}
+
+/// SEO analysis for a single crawled page.
+#[derive(Debug)]
+pub struct PageReport {
+    pub url: String,
+    pub depth: usize,
+    pub status: u16,
+    pub seo: SeoResult,
+}
+
+/// A page that couldn't be fetched.
+#[derive(Debug, Clone)]
+pub struct CrawlError {
+    pub url: String,
+    pub message: String,
+}
+
+/// Aggregated results of a site crawl.
+#[derive(Debug, Default)]
+pub struct CrawlReport {
+    pub start_url: String,
+    /// Pages in the order they were discovered.
+    pub pages: Vec<PageReport>,
+    pub errors: Vec<CrawlError>,
+    /// Same-origin URLs that robots.txt told us not to fetch.
+    pub disallowed: BTreeSet<String>,
+    /// Links to other origins; recorded but never followed.
+    pub external_links: BTreeSet<String>,
+}
+
+impl CrawlReport {
+    pub fn total_words(&self) -> usize {
+        self.pages.iter().map(|page| page.seo.word_count).sum()
+    }
+
+    pub fn pages_missing_title(&self) -> Vec<&str> {
+        self.pages
+            .iter()
+            .filter(|page| page.seo.title.as_deref().is_none_or(|title| title.trim().is_empty()))
+            .map(|page| page.url.as_str())
+            .collect()
+    }
+
+    pub fn pages_missing_description(&self) -> Vec<&str> {
+        self.pages
+            .iter()
+            .filter(|page| page.seo.meta_description.is_none())
+            .map(|page| page.url.as_str())
+            .collect()
+    }
+
+    pub fn average_reading_ease(&self) -> f64 {
+        if self.pages.is_empty() {
+            return 0.0;
+        }
+        self.pages.iter().map(|page| page.seo.readability_ease).sum::<f64>() / self.pages.len() as f64
+    }
+}
+
+/// `Allow`/`Disallow` rules and `Crawl-delay` from the robots.txt group that applies to us.
+#[derive(Debug, Default)]
+struct RobotsRules {
+    allow: Vec<String>,
+    disallow: Vec<String>,
+    crawl_delay: Option<Duration>,
+}
+
+impl RobotsRules {
+    // Parse the `*` group (or one naming our user agent) of a robots.txt file
+    fn parse(body: &str) -> Self {
+        let mut rules = RobotsRules::default();
+        let mut in_group = false;
+        let mut group_has_rules = false;
+
+        for line in body.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let Some((field, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+
+            match field.trim().to_ascii_lowercase().as_str() {
+                "user-agent" => {
+                    // Consecutive user-agent lines share one group
+                    if group_has_rules {
+                        in_group = false;
+                        group_has_rules = false;
+                    }
+                    let agent = value.to_ascii_lowercase();
+                    in_group |= agent == "*" || USER_AGENT.starts_with(&agent);
+                }
+                "allow" if in_group && !value.is_empty() => {
+                    group_has_rules = true;
+                    rules.allow.push(value.to_string());
+                }
+                "disallow" if in_group => {
+                    group_has_rules = true;
+                    // An empty Disallow allows everything
+                    if !value.is_empty() {
+                        rules.disallow.push(value.to_string());
+                    }
+                }
+                "crawl-delay" if in_group => {
+                    group_has_rules = true;
+                    // Seconds, sometimes fractional; anything unreadable is ignored
+                    if let Ok(secs) = value.parse::<f64>() {
+                        if secs.is_finite() && secs >= 0.0 {
+                            rules.crawl_delay = Some(Duration::from_secs_f64(secs).min(MAX_CRAWL_DELAY));
+                        }
+                    }
+                }
+                _ => group_has_rules |= in_group,
+            }
+        }
+        rules
+    }
+
+    // The longest matching prefix wins, with Allow winning ties
+    fn is_allowed(&self, path: &str) -> bool {
+        let longest = |prefixes: &[String]| {
+            prefixes
+                .iter()
+                .filter(|prefix| path.starts_with(prefix.as_str()))
+                .map(|prefix| prefix.len())
+                .max()
+        };
+        match (longest(&self.allow), longest(&self.disallow)) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(allow), Some(disallow)) => allow >= disallow,
+        }
+    }
+}
+
+/// Spaces out requests to each host and caps how many run at once overall.
+///
+/// A request waits for its host's delay before taking one of the shared
+/// permits, so a slow host never ties up permits other hosts could use.
+struct Throttle {
+    permits: Arc<Semaphore>,
+    min_delay: Duration,
+    // Per-host Crawl-delay, used when longer than `min_delay`
+    crawl_delays: HashMap<String, Duration>,
+    // When each host's last request started; the async lock queues that host's requests
+    last_request: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Option<Instant>>>>>,
+}
+
+impl Throttle {
+    fn new(concurrency: usize, min_delay: Duration) -> Self {
+        Throttle {
+            permits: Arc::new(Semaphore::new(concurrency.max(1))),
+            min_delay,
+            crawl_delays: HashMap::new(),
+            last_request: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn set_crawl_delay(&mut self, host: &str, delay: Duration) {
+        self.crawl_delays.insert(host.to_string(), delay);
+    }
+
+    fn delay_for(&self, host: &str) -> Duration {
+        self.crawl_delays.get(host).map_or(self.min_delay, |delay| (*delay).max(self.min_delay))
+    }
+
+    /// Waits until a request to `host` may start; hold the permit until it is done.
+    async fn acquire(&self, host: &str) -> OwnedSemaphorePermit {
+        let slot = self.last_request.lock().unwrap().entry(host.to_string()).or_default().clone();
+        let mut last = slot.lock().await;
+        if let Some(last) = *last {
+            tokio::time::sleep_until((last + self.delay_for(host)).into()).await;
+        }
+        let permit = Arc::clone(&self.permits).acquire_owned().await.expect("Semaphore closed");
+        *last = Some(Instant::now());
+        permit
+    }
+}
+
+// Round-robin across hosts, keeping each host's own order, so one host with
+// many links doesn't queue ahead of the rest
+fn interleave_by_host(urls: Vec<(usize, Url)>) -> Vec<(usize, Url)> {
+    let mut hosts: Vec<VecDeque<(usize, Url)>> = Vec::new();
+    let mut host_index: HashMap<String, usize> = HashMap::new();
+    for entry in urls {
+        let host = entry.1.host_str().unwrap_or_default().to_string();
+        let index = *host_index.entry(host).or_insert_with(|| {
+            hosts.push(VecDeque::new());
+            hosts.len() - 1
+        });
+        hosts[index].push_back(entry);
+    }
+
+    let mut interleaved = Vec::new();
+    while hosts.iter().any(|queue| !queue.is_empty()) {
+        interleaved.extend(hosts.iter_mut().filter_map(VecDeque::pop_front));
+    }
+    interleaved
+}
+
+// Fetch and parse robots.txt; a missing or unreachable file allows everything
+async fn fetch_robots(client: &Client, origin: &Url) -> Option<RobotsRules> {
+    let robots_url = origin.join("/robots.txt").ok()?;
+    let response = client.get(robots_url).send().await.ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    Some(RobotsRules::parse(&response.text().await.ok()?))
+}
+
+async fn has_sitemap(client: &Client, origin: &Url) -> bool {
+    let Ok(sitemap_url) = origin.join("/sitemap.xml") else {
+        return false;
+    };
+    match client.get(sitemap_url).send().await {
+        Ok(response) => response.status().is_success(),
+        Err(_) => false,
+    }
+}
+
+// Resolve `href` against `base` and drop the fragment so `/a` and `/a#top` dedupe
+fn normalize_link(base: &Url, href: &str) -> Option<Url> {
+    let mut url = base.join(href.trim()).ok()?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return None;
+    }
+    url.set_fragment(None);
+    Some(url)
+}
+
+fn extract_links(html: &str, base: &Url) -> Vec<Url> {
+    let document = Html::parse_document(html);
+    let selector = Selector::parse("a[href]").unwrap();
+    document
+        .select(&selector)
+        .filter_map(|element| element.value().attr("href"))
+        .filter_map(|href| normalize_link(base, href))
+        .collect()
+}
+
+// Outcome of fetching one page: its analysis and outgoing links, or an error message
+type FetchResult = Result<(PageReport, Vec<Url>), String>;
+
+async fn fetch_page(client: Client, url: Url, depth: usize, robots_found: bool, sitemap_found: bool) -> FetchResult {
+    let response = client.get(url.clone()).send().await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("HTTP {}", status));
+    }
+    let is_html = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_none_or(|value| value.contains("html"));
+    if !is_html {
+        return Err("Not an HTML page".to_string());
+    }
+
+    let body = response.text().await.map_err(|e| e.to_string())?;
+    let page = PageReport {
+        url: url.to_string(),
+        depth,
+        status: status.as_u16(),
+        seo: analyze_page(&body, url.as_str(), robots_found, sitemap_found),
+    };
+    Ok((page, extract_links(&body, &url)))
+}
+
+/// Crawl a site breadth-first from `start_url`, following same-origin links
+/// within the limits in `opts` and running the SEO analysis on every page.
+pub async fn crawl(start_url: &str, opts: CrawlOptions) -> CrawlReport {
+    let mut report = CrawlReport {
+        start_url: start_url.to_string(),
+        ..CrawlReport::default()
+    };
+
+    let Some(start) = Url::parse(start_url).ok().and_then(|url| normalize_link(&url, start_url)) else {
+        report.errors.push(CrawlError {
+            url: start_url.to_string(),
+            message: "Invalid start URL".to_string(),
+        });
+        return report;
+    };
+    // Links on fetched pages can point anywhere, the start page's host
+    // included, so each is checked before it's fetched
+    let policy = &opts.http.fetch_policy;
+    if !http::is_url_allowed_async(&start, policy).await {
+        report.errors.push(CrawlError {
+            url: start.to_string(),
+            message: http::BlockedUrl(start.to_string()).to_string(),
+        });
+        return report;
+    }
+
+    let client = http::client_builder_with(&opts.http)
+        .user_agent(USER_AGENT)
+        .build()
+        .expect("HTTP client should build with the TLS backend");
+    let robots = fetch_robots(&client, &start).await;
+    let sitemap_found = has_sitemap(&client, &start).await;
+    let robots_found = robots.is_some();
+    let robots = robots.unwrap_or_default();
+
+    let mut throttle = Throttle::new(opts.concurrency, opts.min_delay);
+    if let (Some(host), Some(delay)) = (start.host_str(), robots.crawl_delay) {
+        throttle.set_crawl_delay(host, delay);
+    }
+    let throttle = Arc::new(throttle);
+    let mut seen: HashSet<Url> = HashSet::new();
+    let mut blocked: HashSet<Url> = HashSet::new();
+    let mut frontier = Vec::new();
+    if robots.is_allowed(start.path()) {
+        seen.insert(start.clone());
+        frontier.push(start.clone());
+    } else {
+        report.disallowed.insert(start.to_string());
+    }
+
+    for depth in 0..=opts.max_depth {
+        if frontier.is_empty() {
+            break;
+        }
+
+        // Fetch the whole level concurrently, as fast as the throttle allows
+        let mut tasks = JoinSet::new();
+        for (index, url) in interleave_by_host(frontier.drain(..).enumerate().collect()) {
+            let client = client.clone();
+            let throttle = Arc::clone(&throttle);
+            tasks.spawn(async move {
+                let _permit = throttle.acquire(url.host_str().unwrap_or_default()).await;
+                let result = fetch_page(client, url.clone(), depth, robots_found, sitemap_found).await;
+                (index, url, result)
+            });
+        }
+
+        let mut results = Vec::new();
+        while let Some(joined) = tasks.join_next().await {
+            match joined {
+                Ok(result) => results.push(result),
+                Err(e) => log::error!("Crawl task failed: {}", e),
+            }
+        }
+        // Keep discovery order stable regardless of which request finished first
+        results.sort_by_key(|(index, _, _)| *index);
+
+        for (_, url, result) in results {
+            let (page, links) = match result {
+                Ok(fetched) => fetched,
+                Err(message) => {
+                    report.errors.push(CrawlError {
+                        url: url.to_string(),
+                        message,
+                    });
+                    continue;
+                }
+            };
+            report.pages.push(page);
+
+            if depth == opts.max_depth {
+                continue;
+            }
+            for link in links {
+                if link.origin() != start.origin() {
+                    report.external_links.insert(link.to_string());
+                } else if seen.contains(&link) {
+                    continue;
+                } else if !robots.is_allowed(link.path()) {
+                    report.disallowed.insert(link.to_string());
+                } else if blocked.contains(&link) {
+                    continue;
+                } else if !http::is_url_allowed_async(&link, policy).await {
+                    blocked.insert(link.clone());
+                    report.errors.push(CrawlError {
+                        url: link.to_string(),
+                        message: http::BlockedUrl(link.to_string()).to_string(),
+                    });
+                } else if seen.len() < opts.max_pages {
+                    seen.insert(link.clone());
+                    frontier.push(link);
+                }
+            }
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::Filter;
+
+    // Serve three interlinked pages plus a robots.txt on an ephemeral port
+    async fn fixture_site() -> String {
+        let page = |body: &'static str| warp::reply::html(body);
+        let index = warp::path::end().map(move || {
+            page(r#"<html><head><title>Home</title></head><body>
+                <a href="/about">About</a> <a href="/blog#latest">Blog</a>
+                <a href="https://external.example/">Elsewhere</a>
+                </body></html>"#)
+        });
+        let about = warp::path("about").map(move || {
+            page(r#"<html><head><title>About</title></head><body>
+                <a href="/">Home</a> <a href="blog">Blog</a> <a href="/private/admin">Admin</a>
+                </body></html>"#)
+        });
+        let blog = warp::path("blog").map(move || {
+            page(r#"<html><head><title>Blog</title></head><body>
+                <a href="/about">About</a> <a href="/">Home</a> <a href="mailto:me@example.com">Mail</a>
+                </body></html>"#)
+        });
+        let robots = warp::path("robots.txt").map(|| "User-agent: *\nDisallow: /private\n");
+
+        let routes = index.or(about).or(blog).or(robots);
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{}/", addr)
+    }
+
+    // The fixture site is on loopback, which the default policy refuses
+    fn local_options() -> CrawlOptions {
+        let fetch_policy = http::FetchPolicy { allowed_hosts: vec!["127.0.0.1".to_string()], ..Default::default() };
+        CrawlOptions { http: http::HttpConfig { fetch_policy, ..Default::default() }, ..CrawlOptions::default() }
+    }
+
+    #[test]
+    fn test_robots_rules() {
+        let rules = RobotsRules::parse(
+            "User-agent: googlebot\nDisallow: /\nCrawl-delay: 10\n\n\
+             User-agent: *\nDisallow: /private\nAllow: /private/public\nCrawl-delay: 1.5\n",
+        );
+        assert!(rules.is_allowed("/"));
+        assert!(!rules.is_allowed("/private/admin"));
+        assert!(rules.is_allowed("/private/public/page"));
+        assert_eq!(rules.crawl_delay, Some(Duration::from_millis(1500)));
+
+        assert_eq!(RobotsRules::parse("User-agent: *\nCrawl-delay: 86400\n").crawl_delay, Some(MAX_CRAWL_DELAY));
+        assert_eq!(RobotsRules::parse("User-agent: *\nCrawl-delay: soon\n").crawl_delay, None);
+    }
+
+    // Start times of requests through `throttle`, relative to when they were queued
+    async fn start_offsets(throttle: Arc<Throttle>, hosts: &[&'static str]) -> Vec<Duration> {
+        let queued = Instant::now();
+        let tasks = hosts.iter().map(|host| {
+            let throttle = Arc::clone(&throttle);
+            async move {
+                let _permit = throttle.acquire(host).await;
+                queued.elapsed()
+            }
+        });
+        futures::future::join_all(tasks).await
+    }
+
+    #[tokio::test]
+    async fn test_same_host_requests_are_spaced_by_the_delay() {
+        let throttle = Arc::new(Throttle::new(4, Duration::from_millis(100)));
+        let offsets = start_offsets(throttle, &["a.test", "a.test", "a.test"]).await;
+        assert!(offsets[0] < Duration::from_millis(50), "{:?}", offsets);
+        assert!(offsets[1] >= Duration::from_millis(100), "{:?}", offsets);
+        assert!(offsets[2] >= Duration::from_millis(200), "{:?}", offsets);
+
+        // A longer Crawl-delay wins over the minimum
+        let mut throttle = Throttle::new(4, Duration::from_millis(100));
+        throttle.set_crawl_delay("a.test", Duration::from_millis(300));
+        let offsets = start_offsets(Arc::new(throttle), &["a.test", "a.test"]).await;
+        assert!(offsets[1] >= Duration::from_millis(300), "{:?}", offsets);
+    }
+
+    #[tokio::test]
+    async fn test_different_hosts_proceed_concurrently() {
+        let throttle = Arc::new(Throttle::new(4, Duration::from_millis(500)));
+        let offsets = start_offsets(throttle, &["a.test", "b.test", "c.test"]).await;
+        assert!(offsets.iter().all(|offset| *offset < Duration::from_millis(100)), "{:?}", offsets);
+    }
+
+    #[test]
+    fn test_frontier_is_interleaved_by_host() {
+        let urls = ["http://a.test/1", "http://a.test/2", "http://a.test/3", "http://b.test/1", "http://c.test/1"];
+        let frontier = urls.iter().map(|url| Url::parse(url).unwrap()).enumerate().collect();
+        let order: Vec<usize> = interleave_by_host(frontier).into_iter().map(|(index, _)| index).collect();
+        assert_eq!(order, vec![0, 3, 4, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_crawl_discovers_each_page_once() {
+        let base = fixture_site().await;
+        let report = crawl(&base, local_options()).await;
+
+        let urls: Vec<&str> = report.pages.iter().map(|page| page.url.as_str()).collect();
+        assert_eq!(urls, vec![base.clone(), format!("{}about", base), format!("{}blog", base)]);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(report.pages[2].seo.title.as_deref(), Some("Blog"));
+
+        // The external link is recorded but never fetched
+        assert!(report.external_links.contains("https://external.example/"));
+        assert!(report.pages.iter().all(|page| !page.url.contains("external.example")));
+        assert!(report.disallowed.contains(&format!("{}private/admin", base)));
+    }
+
+    #[tokio::test]
+    async fn test_crawl_respects_limits() {
+        let base = fixture_site().await;
+
+        let report = crawl(&base, CrawlOptions { max_depth: 0, ..local_options() }).await;
+        assert_eq!(report.pages.len(), 1);
+
+        let report = crawl(&base, CrawlOptions { max_pages: 2, ..local_options() }).await;
+        assert_eq!(report.pages.len(), 2);
+
+        // Three pages on one host, so at least two delays
+        let started = Instant::now();
+        let polite = CrawlOptions { min_delay: Duration::from_millis(200), ..local_options() };
+        let report = crawl(&base, polite).await;
+        assert_eq!(report.pages.len(), 3);
+        assert!(started.elapsed() >= Duration::from_millis(400), "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn test_crawl_refuses_private_start_urls() {
+        let base = fixture_site().await;
+        for start in [base.as_str(), "http://169.254.169.254/latest/meta-data/"] {
+            let report = crawl(start, CrawlOptions::default()).await;
+            assert!(report.pages.is_empty());
+            assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+            assert!(report.errors[0].message.starts_with("Refusing to fetch"), "{:?}", report.errors);
+        }
+    }
+}
@@ -2,93 +2,141 @@ use actix_web::{web, App, HttpServer, HttpResponse, Responder, middleware};
 use std::sync::{Arc, Mutex};
 use tiberius::{Client, Config, AuthMethod};
 use tokio::net::TcpStream;
-use tokio_util::compat::TokioAsyncWriteCompatExt;
-use futures::stream::StreamExt;
+use tokio_util::compat::{Compat, TokioAsyncWriteCompatExt};
+
+#[path = "../body_limit.rs"]
+#[allow(dead_code)]
+mod body_limit;
+use body_limit::{actix_body_limits, max_body_bytes};
+
+#[path = "../request_log.rs"]
+#[allow(dead_code)]
+mod request_log;
+use request_log::RequestLogger;
+
+#[path = "../timeout.rs"]
+#[allow(dead_code)]
+mod timeout;
+use timeout::{request_timeout, Timeout};
+
+#[path = "../shutdown.rs"]
+#[allow(dead_code)]
+mod shutdown;
+use shutdown::shutdown_signal;
+
+#[allow(dead_code)]
+mod msql_query;
+use msql_query::{QueryBuilder, QueryError};
 
 struct AppState {
-    client: Mutex<Client<TcpStream>>,
+    // Held across queries, so it has to be an async lock
+    client: tokio::sync::Mutex<Client<Compat<TcpStream>>>,
     allowed_tables: Mutex<Vec<String>>,
 }
 
-async fn get_user(data: web::Data<Arc<AppState>>, id: web::Path<i32>) -> impl Responder {
-    let mut client = data.client.lock().unwrap();
-    let allowed_tables = data.allowed_tables.lock().unwrap();
-
-    if !allowed_tables.contains(&"users".to_string()) {
-        return HttpResponse::Forbidden().body("Access denied");
+// A table outside the allowlist is a client problem; anything else is a bug in the handler
+fn query_error_response(e: QueryError) -> HttpResponse {
+    match e {
+        QueryError::TableNotAllowed(_) => HttpResponse::Forbidden().body("Access denied"),
+        e => HttpResponse::InternalServerError().body(e.to_string()),
     }
+}
 
-    let query = format!("SELECT name FROM users WHERE id = {}", id);
-    let result = client.simple_query(query).await;
+async fn get_user(data: web::Data<Arc<AppState>>, id: web::Path<i32>) -> impl Responder {
+    let query = QueryBuilder::select(&data.allowed_tables.lock().unwrap(), "users", &["name"])
+        .filter("id", id.into_inner())
+        .build();
+    let query = match query {
+        Ok(query) => query,
+        Err(e) => return query_error_response(e),
+    };
+
+    let mut client = data.client.lock().await;
+    let result = match query.into_query().query(&mut client).await {
+        Ok(stream) => stream.into_row().await,
+        Err(e) => Err(e),
+    };
 
     match result {
-        Ok(mut row) => {
-            if let Some(row) = row.next().await.unwrap() {
-                let name: &str = row.get(0).unwrap();
-                HttpResponse::Ok().body(format!("User: {}", name))
-            } else {
-                HttpResponse::NotFound().body("User not found")
-            }
+        Ok(Some(row)) => {
+            let name: &str = row.get(0).unwrap_or_default();
+            HttpResponse::Ok().body(format!("User: {}", name))
         },
+        Ok(None) => HttpResponse::NotFound().body("User not found"),
         Err(_) => HttpResponse::InternalServerError().body("Error querying the database"),
     }
 }
 
 async fn set_user(data: web::Data<Arc<AppState>>, info: web::Json<(i32, String)>) -> impl Responder {
-    let mut client = data.client.lock().unwrap();
     let (id, name) = info.into_inner();
-
-    let query = format!("INSERT INTO users (id, name) VALUES ({}, '{}')", id, name);
-    let result = client.simple_query(query).await;
-
-    match result {
+    let query = QueryBuilder::insert(&data.allowed_tables.lock().unwrap(), "users")
+        .value("id", id)
+        .value("name", name)
+        .build();
+    let query = match query {
+        Ok(query) => query,
+        Err(e) => return query_error_response(e),
+    };
+
+    let mut client = data.client.lock().await;
+    match query.into_query().execute(&mut client).await {
         Ok(_) => HttpResponse::Created().body("User added"),
         Err(_) => HttpResponse::InternalServerError().body("Error inserting into the database"),
     }
 }
 
 async fn update_user(data: web::Data<Arc<AppState>>, info: web::Json<(i32, String)>) -> impl Responder {
-    let mut client = data.client.lock().unwrap();
     let (id, name) = info.into_inner();
-
-    let query = format!("UPDATE users SET name = '{}' WHERE id = {}", name, id);
-    let result = client.simple_query(query).await;
-
-    match result {
+    let query = QueryBuilder::update(&data.allowed_tables.lock().unwrap(), "users")
+        .value("name", name)
+        .filter("id", id)
+        .build();
+    let query = match query {
+        Ok(query) => query,
+        Err(e) => return query_error_response(e),
+    };
+
+    let mut client = data.client.lock().await;
+    match query.into_query().execute(&mut client).await {
         Ok(_) => HttpResponse::Ok().body("User updated"),
         Err(_) => HttpResponse::InternalServerError().body("Error updating the database"),
     }
 }
 
 async fn delete_user(data: web::Data<Arc<AppState>>, id: web::Path<i32>) -> impl Responder {
-    let mut client = data.client.lock().unwrap();
-
-    let query = format!("DELETE FROM users WHERE id = {}", id);
-    let result = client.simple_query(query).await;
-
-    match result {
+    let query = QueryBuilder::delete(&data.allowed_tables.lock().unwrap(), "users")
+        .filter("id", id.into_inner())
+        .build();
+    let query = match query {
+        Ok(query) => query,
+        Err(e) => return query_error_response(e),
+    };
+
+    let mut client = data.client.lock().await;
+    match query.into_query().execute(&mut client).await {
         Ok(_) => HttpResponse::Ok().body("User deleted"),
         Err(_) => HttpResponse::InternalServerError().body("Error deleting from the database"),
     }
 }
 
 async fn list_users(data: web::Data<Arc<AppState>>) -> impl Responder {
-    let mut client = data.client.lock().unwrap();
-    let allowed_tables = data.allowed_tables.lock().unwrap();
-
-    if !allowed_tables.contains(&"users".to_string()) {
-        return HttpResponse::Forbidden().body("Access denied");
-    }
+    let query = match QueryBuilder::select(&data.allowed_tables.lock().unwrap(), "users", &["id", "name"]).build() {
+        Ok(query) => query,
+        Err(e) => return query_error_response(e),
+    };
 
-    let query = "SELECT id, name FROM users";
-    let result = client.simple_query(query).await;
+    let mut client = data.client.lock().await;
+    let result = match query.into_query().query(&mut client).await {
+        Ok(stream) => stream.into_first_result().await,
+        Err(e) => Err(e),
+    };
 
     match result {
-        Ok(mut rows) => {
+        Ok(rows) => {
             let mut response = String::new();
-            while let Some(row) = rows.next().await.unwrap() {
-                let id: i32 = row.get(0).unwrap();
-                let name: &str = row.get(1).unwrap();
+            for row in rows {
+                let id: i32 = row.get(0).unwrap_or_default();
+                let name: &str = row.get(1).unwrap_or_default();
                 response.push_str(&format!("ID: {}, Name: {}\n", id, name));
             }
             HttpResponse::Ok().body(response)
@@ -109,12 +157,13 @@ async fn main() -> std::io::Result<()> {
     let client = Client::connect(config, tcp.compat_write()).await.unwrap();
 
     let data = web::Data::new(Arc::new(AppState {
-        client: Mutex::new(client),
+        client: tokio::sync::Mutex::new(client),
         allowed_tables: Mutex::new(vec!["users".to_string()]),
     }));
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
+            .configure(actix_body_limits(max_body_bytes()))
             .app_data(data.clone())
             .wrap(middleware::Logger::default())
             .wrap(middleware::Compress::default())
@@ -123,8 +172,19 @@ async fn main() -> std::io::Result<()> {
             .service(web::resource("/user/update").route(web::put().to(update_user)))
             .service(web::resource("/user/delete/{id}").route(web::delete().to(delete_user)))
             .service(web::resource("/users").route(web::get().to(list_users)))
+            .wrap(Timeout::new(request_timeout()))
+            .wrap(RequestLogger)
     })
+    // Stopped below on shutdown_signal, like the other servers
+    .disable_signals()
     .bind("127.0.0.1:5500")?
-    .run()
-    .await
+    .run();
+
+    // Finish in-flight requests before exiting
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        handle.stop(true).await;
+    });
+    server.await
 }
\ No newline at end of file
@@ -0,0 +1,309 @@
+use thiserror::Error;
+use tiberius::Query;
+
+/// A value bound to a query parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    I32(i32),
+    I64(i64),
+    F64(f64),
+    Bool(bool),
+    Text(String),
+    Null,
+}
+
+impl From<i32> for SqlValue {
+    fn from(value: i32) -> Self {
+        SqlValue::I32(value)
+    }
+}
+
+impl From<i64> for SqlValue {
+    fn from(value: i64) -> Self {
+        SqlValue::I64(value)
+    }
+}
+
+impl From<f64> for SqlValue {
+    fn from(value: f64) -> Self {
+        SqlValue::F64(value)
+    }
+}
+
+impl From<bool> for SqlValue {
+    fn from(value: bool) -> Self {
+        SqlValue::Bool(value)
+    }
+}
+
+impl From<String> for SqlValue {
+    fn from(value: String) -> Self {
+        SqlValue::Text(value)
+    }
+}
+
+impl From<&str> for SqlValue {
+    fn from(value: &str) -> Self {
+        SqlValue::Text(value.to_string())
+    }
+}
+
+impl<T: Into<SqlValue>> From<Option<T>> for SqlValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(SqlValue::Null, Into::into)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Error)]
+pub enum QueryError {
+    #[error("table {0:?} is not in the allowed tables")]
+    TableNotAllowed(String),
+    #[error("{0:?} is not a valid column name")]
+    InvalidColumn(String),
+    #[error("{0} needs at least one column")]
+    NoColumns(&'static str),
+    // Guards against wiping or rewriting a whole table by leaving out a filter
+    #[error("{0} needs at least one filter")]
+    Unfiltered(&'static str),
+}
+
+enum Statement {
+    Select(Vec<String>),
+    Insert(Vec<(String, SqlValue)>),
+    Update(Vec<(String, SqlValue)>),
+    Delete,
+}
+
+impl Statement {
+    fn name(&self) -> &'static str {
+        match self {
+            Statement::Select(_) => "SELECT",
+            Statement::Insert(_) => "INSERT",
+            Statement::Update(_) => "UPDATE",
+            Statement::Delete => "DELETE",
+        }
+    }
+}
+
+/// Builds SELECT/INSERT/UPDATE/DELETE statements whose values are all bound
+/// as `@P1`, `@P2`, ... parameters, so request data never becomes SQL text.
+///
+/// The table must be in `allowed_tables`, and column names must be plain
+/// identifiers (letters, digits, and `_`); both are checked by `build`,
+/// before anything reaches the database.
+///
+/// ```ignore
+/// let query = QueryBuilder::insert(&allowed_tables, "users")
+///     .value("id", 7)
+///     .value("name", "Ada")
+///     .build()?;
+/// query.into_query().execute(&mut client).await?;
+/// ```
+pub struct QueryBuilder<'a> {
+    allowed_tables: &'a [String],
+    table: String,
+    statement: Statement,
+    filters: Vec<(String, SqlValue)>,
+}
+
+/// A validated statement and the values for its parameters, in order.
+#[derive(Debug, PartialEq)]
+pub struct BuiltQuery {
+    pub sql: String,
+    pub params: Vec<SqlValue>,
+}
+
+impl<'a> QueryBuilder<'a> {
+    fn new(allowed_tables: &'a [String], table: &str, statement: Statement) -> Self {
+        QueryBuilder { allowed_tables, table: table.to_string(), statement, filters: Vec::new() }
+    }
+
+    pub fn select(allowed_tables: &'a [String], table: &str, columns: &[&str]) -> Self {
+        let columns = columns.iter().map(|column| column.to_string()).collect();
+        Self::new(allowed_tables, table, Statement::Select(columns))
+    }
+
+    pub fn insert(allowed_tables: &'a [String], table: &str) -> Self {
+        Self::new(allowed_tables, table, Statement::Insert(Vec::new()))
+    }
+
+    pub fn update(allowed_tables: &'a [String], table: &str) -> Self {
+        Self::new(allowed_tables, table, Statement::Update(Vec::new()))
+    }
+
+    pub fn delete(allowed_tables: &'a [String], table: &str) -> Self {
+        Self::new(allowed_tables, table, Statement::Delete)
+    }
+
+    /// A column to insert, or to set in an update. Ignored by SELECT and DELETE.
+    pub fn value(mut self, column: &str, value: impl Into<SqlValue>) -> Self {
+        if let Statement::Insert(values) | Statement::Update(values) = &mut self.statement {
+            values.push((column.to_string(), value.into()));
+        }
+        self
+    }
+
+    /// Adds `column = value` to the WHERE clause; filters are joined with AND.
+    /// A NULL value matches with `IS NULL`.
+    pub fn filter(mut self, column: &str, value: impl Into<SqlValue>) -> Self {
+        self.filters.push((column.to_string(), value.into()));
+        self
+    }
+
+    pub fn build(self) -> Result<BuiltQuery, QueryError> {
+        if !self.allowed_tables.contains(&self.table) {
+            return Err(QueryError::TableNotAllowed(self.table));
+        }
+        let table = quote(&self.table)?;
+        let name = self.statement.name();
+        let mut params = Vec::new();
+        let mut bind = |value: SqlValue| {
+            params.push(value);
+            format!("@P{}", params.len())
+        };
+
+        let mut sql = match self.statement {
+            Statement::Select(columns) => {
+                if columns.is_empty() {
+                    return Err(QueryError::NoColumns(name));
+                }
+                let columns = columns.iter().map(|column| quote(column)).collect::<Result<Vec<_>, _>>()?;
+                format!("SELECT {} FROM {}", columns.join(", "), table)
+            }
+            Statement::Insert(values) => {
+                if values.is_empty() {
+                    return Err(QueryError::NoColumns(name));
+                }
+                let mut columns = Vec::new();
+                let mut placeholders = Vec::new();
+                for (column, value) in values {
+                    columns.push(quote(&column)?);
+                    placeholders.push(bind(value));
+                }
+                format!("INSERT INTO {} ({}) VALUES ({})", table, columns.join(", "), placeholders.join(", "))
+            }
+            Statement::Update(values) => {
+                if values.is_empty() {
+                    return Err(QueryError::NoColumns(name));
+                }
+                let mut sets = Vec::new();
+                for (column, value) in values {
+                    sets.push(format!("{} = {}", quote(&column)?, bind(value)));
+                }
+                format!("UPDATE {} SET {}", table, sets.join(", "))
+            }
+            Statement::Delete => format!("DELETE FROM {}", table),
+        };
+
+        if self.filters.is_empty() && matches!(name, "UPDATE" | "DELETE") {
+            return Err(QueryError::Unfiltered(name));
+        }
+        let mut conditions = Vec::new();
+        for (column, value) in self.filters {
+            let column = quote(&column)?;
+            conditions.push(match value {
+                SqlValue::Null => format!("{} IS NULL", column),
+                value => format!("{} = {}", column, bind(value)),
+            });
+        }
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+
+        Ok(BuiltQuery { sql, params })
+    }
+}
+
+impl BuiltQuery {
+    /// The tiberius query with every parameter bound, ready to run.
+    pub fn into_query(self) -> Query<'static> {
+        let mut query = Query::new(self.sql);
+        for param in self.params {
+            match param {
+                SqlValue::I32(value) => query.bind(value),
+                SqlValue::I64(value) => query.bind(value),
+                SqlValue::F64(value) => query.bind(value),
+                SqlValue::Bool(value) => query.bind(value),
+                SqlValue::Text(value) => query.bind(value),
+                SqlValue::Null => query.bind(Option::<i32>::None),
+            }
+        }
+        query
+    }
+}
+
+// Bracket-quote a plain identifier. Anything else is rejected rather than
+// escaped, since no column here needs more than letters, digits, and `_`
+fn quote(identifier: &str) -> Result<String, QueryError> {
+    let mut chars = identifier.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(format!("[{}]", identifier))
+    } else {
+        Err(QueryError::InvalidColumn(identifier.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed() -> Vec<String> {
+        vec!["users".to_string()]
+    }
+
+    #[test]
+    fn test_insert_binds_every_value() {
+        let name = "Robert'); DROP TABLE users;--";
+        let query = QueryBuilder::insert(&allowed(), "users").value("id", 7).value("name", name).build().unwrap();
+
+        assert_eq!(query.sql, "INSERT INTO [users] ([id], [name]) VALUES (@P1, @P2)");
+        assert_eq!(query.params, vec![SqlValue::I32(7), SqlValue::Text(name.to_string())]);
+    }
+
+    #[test]
+    fn test_select_update_and_delete() {
+        let allowed = allowed();
+        let select = QueryBuilder::select(&allowed, "users", &["id", "name"]).filter("id", 3).build().unwrap();
+        assert_eq!(select.sql, "SELECT [id], [name] FROM [users] WHERE [id] = @P1");
+        assert_eq!(select.params, vec![SqlValue::I32(3)]);
+
+        let update = QueryBuilder::update(&allowed, "users")
+            .value("name", "Ada")
+            .filter("id", 3)
+            .filter("deleted_at", Option::<i64>::None)
+            .build()
+            .unwrap();
+        assert_eq!(update.sql, "UPDATE [users] SET [name] = @P1 WHERE [id] = @P2 AND [deleted_at] IS NULL");
+        assert_eq!(update.params, vec![SqlValue::Text("Ada".to_string()), SqlValue::I32(3)]);
+
+        let delete = QueryBuilder::delete(&allowed, "users").filter("id", 3i64).build().unwrap();
+        assert_eq!(delete.sql, "DELETE FROM [users] WHERE [id] = @P1");
+        assert_eq!(delete.params, vec![SqlValue::I64(3)]);
+    }
+
+    #[test]
+    fn test_disallowed_identifiers_are_rejected() {
+        let allowed = allowed();
+        assert_eq!(
+            QueryBuilder::select(&allowed, "secrets", &["token"]).build(),
+            Err(QueryError::TableNotAllowed("secrets".to_string()))
+        );
+        assert_eq!(
+            QueryBuilder::insert(&allowed, "users; DROP TABLE users").value("id", 1).build(),
+            Err(QueryError::TableNotAllowed("users; DROP TABLE users".to_string()))
+        );
+        assert_eq!(
+            QueryBuilder::select(&allowed, "users", &["name FROM users--"]).build(),
+            Err(QueryError::InvalidColumn("name FROM users--".to_string()))
+        );
+        assert_eq!(
+            QueryBuilder::update(&allowed, "users").value("name", "x").filter("1 = 1 OR id", 1).build(),
+            Err(QueryError::InvalidColumn("1 = 1 OR id".to_string()))
+        );
+        assert_eq!(QueryBuilder::delete(&allowed, "users").build(), Err(QueryError::Unfiltered("DELETE")));
+        assert_eq!(QueryBuilder::insert(&allowed, "users").build(), Err(QueryError::NoColumns("INSERT")));
+    }
+}
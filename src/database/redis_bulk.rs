@@ -0,0 +1,109 @@
+use redis::{Connection, RedisResult};
+use serde::Serialize;
+
+// Keeps one request from holding Redis up with a huge MULTI block
+pub const MAX_BATCH: usize = 1000;
+
+/// Every `SET` of a batch sent together, as a single round trip.
+pub trait SetMany {
+    fn set_many(&mut self, entries: &[(String, String)]) -> RedisResult<()>;
+}
+
+impl SetMany for Connection {
+    // MULTI/EXEC rather than a bare pipeline, so a failure leaves no key of
+    // the batch half-written
+    fn set_many(&mut self, entries: &[(String, String)]) -> RedisResult<()> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (key, value) in entries {
+            pipe.set(key, value).ignore();
+        }
+        pipe.query(self)
+    }
+}
+
+/// What a bulk write did: how many keys were written, and which were not.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct BulkWrite {
+    pub written: usize,
+    pub failed: Vec<String>,
+}
+
+/// The batch had more than [`MAX_BATCH`] keys; nothing was sent.
+#[derive(Debug, PartialEq)]
+pub struct BatchTooLarge(pub usize);
+
+/// Writes `entries` in one round trip.
+///
+/// The batch is applied as a whole or not at all, so on error every key is
+/// reported failed and the client can retry just those.
+pub fn bulk_write(con: &mut impl SetMany, entries: &[(String, String)]) -> Result<BulkWrite, BatchTooLarge> {
+    if entries.len() > MAX_BATCH {
+        return Err(BatchTooLarge(entries.len()));
+    }
+    if entries.is_empty() {
+        return Ok(BulkWrite { written: 0, failed: Vec::new() });
+    }
+
+    match con.set_many(entries) {
+        Ok(()) => Ok(BulkWrite { written: entries.len(), failed: Vec::new() }),
+        Err(e) => {
+            log::error!("Bulk write of {} keys failed: {}", entries.len(), e);
+            Ok(BulkWrite { written: 0, failed: entries.iter().map(|(key, _)| key.clone()).collect() })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // Applies each batch to a map, counting round trips
+    #[derive(Default)]
+    struct FakeRedis {
+        data: HashMap<String, String>,
+        calls: usize,
+        down: bool,
+    }
+
+    impl SetMany for FakeRedis {
+        fn set_many(&mut self, entries: &[(String, String)]) -> RedisResult<()> {
+            self.calls += 1;
+            if self.down {
+                return Err((redis::ErrorKind::IoError, "connection reset").into());
+            }
+            self.data.extend(entries.iter().cloned());
+            Ok(())
+        }
+    }
+
+    fn entries(n: usize) -> Vec<(String, String)> {
+        (0..n).map(|i| (format!("key:{}", i), format!("value {}", i))).collect()
+    }
+
+    #[test]
+    fn test_batch_is_written_in_one_call() {
+        let mut con = FakeRedis::default();
+        let report = bulk_write(&mut con, &entries(100)).unwrap();
+
+        assert_eq!(report, BulkWrite { written: 100, failed: vec![] });
+        assert_eq!(con.calls, 1);
+        for (key, value) in entries(100) {
+            assert_eq!(con.data.get(&key), Some(&value));
+        }
+    }
+
+    #[test]
+    fn test_oversized_and_failed_batches() {
+        let mut con = FakeRedis::default();
+        assert_eq!(bulk_write(&mut con, &entries(MAX_BATCH + 1)), Err(BatchTooLarge(MAX_BATCH + 1)));
+        assert_eq!(bulk_write(&mut con, &[]).unwrap().written, 0);
+        assert_eq!(con.calls, 0);
+
+        con.down = true;
+        let report = bulk_write(&mut con, &entries(3)).unwrap();
+        assert_eq!(report.written, 0);
+        assert_eq!(report.failed, vec!["key:0", "key:1", "key:2"]);
+    }
+}
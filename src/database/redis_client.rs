@@ -3,6 +3,32 @@ use actix_web::{web, App, HttpServer, HttpResponse, Responder};
 use std::sync::{Arc, Mutex};
 use serde::Deserialize;
 
+#[path = "../body_limit.rs"]
+#[allow(dead_code)]
+mod body_limit;
+use body_limit::{actix_body_limits, max_body_bytes};
+
+#[path = "../request_log.rs"]
+#[allow(dead_code)]
+mod request_log;
+use request_log::RequestLogger;
+
+#[path = "../timeout.rs"]
+#[allow(dead_code)]
+mod timeout;
+use timeout::{request_timeout, Timeout};
+
+#[path = "../shutdown.rs"]
+#[allow(dead_code)]
+mod shutdown;
+use shutdown::shutdown_signal;
+
+mod redis_health;
+use redis_health::{wait_until_ready, Backoff};
+
+mod redis_keys;
+use redis_keys::{list_keys_page, ListKeysQuery};
+
 #[derive(Deserialize)]
 struct KeyValue {
     key: String,
@@ -21,19 +47,21 @@ struct AllowedKey {
 }
 
 struct AppState {
+    // Handlers clone the client out rather than connecting under the lock
     redis_client: Mutex<Client>,
     allowed_keys: Mutex<Vec<String>>,
 }
 
 async fn get_value(data: web::Data<Arc<AppState>>, key: web::Path<String>) -> impl Responder {
-    let client = data.redis_client.lock().unwrap();
-    let allowed_keys = data.allowed_keys.lock().unwrap();
-    
-    if !allowed_keys.contains(&key.into_inner()) {
+    if !data.allowed_keys.lock().unwrap().contains(&*key) {
         return HttpResponse::Forbidden().body("Access denied");
     }
+    let client = data.redis_client.lock().unwrap().clone();
 
-    let mut con = client.get_connection().unwrap();
+    let mut con = match redis_health::connection(&client) {
+        Ok(con) => con,
+        Err(unavailable) => return unavailable,
+    };
     let value: RedisResult<String> = con.get(&*key);
     match value {
         Ok(val) => HttpResponse::Ok().body(val),
@@ -42,42 +70,53 @@ async fn get_value(data: web::Data<Arc<AppState>>, key: web::Path<String>) -> im
 }
 
 async fn set_value(data: web::Data<Arc<AppState>>, info: web::Json<KeyValue>) -> impl Responder {
-    let client = data.redis_client.lock().unwrap();
+    let client = data.redis_client.lock().unwrap().clone();
     let KeyValue { key, value } = info.into_inner();
 
-    let mut con = client.get_connection().unwrap();
+    let mut con = match redis_health::connection(&client) {
+        Ok(con) => con,
+        Err(unavailable) => return unavailable,
+    };
     let _: RedisResult<()> = con.set(&key, value);
 
     HttpResponse::Ok().body("Value set")
 }
 
 async fn set_expiration(data: web::Data<Arc<AppState>>, info: web::Json<Expiration>) -> impl Responder {
-    let client = data.redis_client.lock().unwrap();
+    let client = data.redis_client.lock().unwrap().clone();
     let Expiration { key, expiration } = info.into_inner();
 
-    let mut con = client.get_connection().unwrap();
+    let mut con = match redis_health::connection(&client) {
+        Ok(con) => con,
+        Err(unavailable) => return unavailable,
+    };
     let _: RedisResult<()> = con.set_ex(&key, "dummy_value", expiration);
 
     HttpResponse::Ok().body("Expiration set")
 }
 
 async fn delete_key(data: web::Data<Arc<AppState>>, key: web::Path<String>) -> impl Responder {
-    let client = data.redis_client.lock().unwrap();
+    let client = data.redis_client.lock().unwrap().clone();
 
-    let mut con = client.get_connection().unwrap();
+    let mut con = match redis_health::connection(&client) {
+        Ok(con) => con,
+        Err(unavailable) => return unavailable,
+    };
     let _: RedisResult<()> = con.del(&*key);
 
     HttpResponse::Ok().body("Key deleted")
 }
 
-async fn list_keys(data: web::Data<Arc<AppState>>) -> impl Responder {
-    let client = data.redis_client.lock().unwrap();
+async fn list_keys(data: web::Data<Arc<AppState>>, query: web::Query<ListKeysQuery>) -> impl Responder {
+    let client = data.redis_client.lock().unwrap().clone();
 
-    let mut con = client.get_connection().unwrap();
-    let keys: RedisResult<Vec<String>> = con.keys("*");
-
-    match keys {
-        Ok(key_list) => HttpResponse::Ok().json(key_list),
+    let mut con = match redis_health::connection(&client) {
+        Ok(con) => con,
+        Err(unavailable) => return unavailable,
+    };
+    // SCAN in pages; KEYS blocks Redis for the whole keyspace
+    match list_keys_page(&mut con, &query) {
+        Ok(page) => HttpResponse::Ok().json(page),
         Err(_) => HttpResponse::InternalServerError().body("Failed to list keys"),
     }
 }
@@ -90,9 +129,12 @@ async fn update_allowed_keys(data: web::Data<Arc<AppState>>, key: web::Json<Allo
 }
 
 async fn ping_redis(data: web::Data<Arc<AppState>>) -> impl Responder {
-    let client = data.redis_client.lock().unwrap();
+    let client = data.redis_client.lock().unwrap().clone();
 
-    let mut con = client.get_connection().unwrap();
+    let mut con = match redis_health::connection(&client) {
+        Ok(con) => con,
+        Err(unavailable) => return unavailable,
+    };
     let pong: RedisResult<String> = con.ping();
     match pong {
         Ok(_) => HttpResponse::Ok().body("Pong"),
@@ -102,14 +144,29 @@ async fn ping_redis(data: web::Data<Arc<AppState>>) -> impl Responder {
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
-    let redis_client = Client::open("redis://127.0.0.1/").unwrap();
+    let redis_client = Client::open("redis://127.0.0.1/").map_err(std::io::Error::other)?;
+
+    // Refuse to start without Redis rather than failing every request later
+    let ready = wait_until_ready(
+        || {
+            let client = redis_client.clone();
+            async move { redis_health::ping(&client) }
+        },
+        Backoff::default(),
+    )
+    .await;
+    if let Err(e) = ready {
+        log::error!("Redis is unreachable, not starting: {}", e);
+        return Err(std::io::Error::other(e));
+    }
     let data = web::Data::new(Arc::new(AppState {
         redis_client: Mutex::new(redis_client),
         allowed_keys: Mutex::new(vec!["allowed_key".to_string()]),
     }));
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
+            .configure(actix_body_limits(max_body_bytes()))
             .app_data(data.clone())
             .wrap(actix_web::middleware::Logger::default())
             .wrap(actix_web::middleware::Compress::default())
@@ -120,8 +177,19 @@ async fn main() -> std::io::Result<()> {
             .service(web::resource("/list_keys").route(web::get().to(list_keys)))
             .service(web::resource("/update_allowed_keys").route(web::post().to(update_allowed_keys)))
             .service(web::resource("/ping").route(web::get().to(ping_redis)))
+            .wrap(Timeout::new(request_timeout()))
+            .wrap(RequestLogger)
     })
+    // Stopped below on shutdown_signal, like the other servers
+    .disable_signals()
     .bind("127.0.0.1:5500")?
-    .run()
-    .await
+    .run();
+
+    // Finish in-flight requests before exiting
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        handle.stop(true).await;
+    });
+    server.await
 }
\ No newline at end of file
@@ -6,6 +6,30 @@ use std::collections::HashMap;
 use std::time::Duration;
 use actix_web::middleware::Logger;
 
+#[path = "../body_limit.rs"]
+#[allow(dead_code)]
+mod body_limit;
+use body_limit::{actix_body_limits, max_body_bytes};
+
+#[path = "../request_log.rs"]
+#[allow(dead_code)]
+mod request_log;
+use request_log::RequestLogger;
+
+#[path = "../timeout.rs"]
+#[allow(dead_code)]
+mod timeout;
+use timeout::{request_timeout, Timeout};
+
+mod redis_health;
+use redis_health::{wait_until_ready, Backoff};
+
+mod redis_keys;
+use redis_keys::{list_keys_page, ListKeysQuery};
+
+mod redis_bulk;
+use redis_bulk::{bulk_write, BatchTooLarge, MAX_BATCH};
+
 #[derive(Debug, Deserialize, Serialize)]
 struct KeyValue {
     key: String,
@@ -26,7 +50,10 @@ async fn read_data(data: web::Data<Arc<AppState>>, key: web::Path<String>) -> im
         return HttpResponse::Forbidden().body("Access denied");
     }
 
-    let mut con = client.get_connection().unwrap();
+    let mut con = match redis_health::connection(&client) {
+        Ok(con) => con,
+        Err(unavailable) => return unavailable,
+    };
     let value: RedisResult<String> = con.get(&*key);
     match value {
         Ok(val) => HttpResponse::Ok().body(val),
@@ -38,7 +65,10 @@ async fn write_data(data: web::Data<Arc<AppState>>, info: web::Json<KeyValue>) -
     let client = data.redis_client.lock().unwrap();
     let KeyValue { key, value } = info.into_inner();
 
-    let mut con = client.get_connection().unwrap();
+    let mut con = match redis_health::connection(&client) {
+        Ok(con) => con,
+        Err(unavailable) => return unavailable,
+    };
     let _: RedisResult<()> = con.set(&key, value);
 
     HttpResponse::Ok().body("Data written")
@@ -46,7 +76,10 @@ async fn write_data(data: web::Data<Arc<AppState>>, info: web::Json<KeyValue>) -
 
 async fn delete_data(data: web::Data<Arc<AppState>>, key: web::Path<String>) -> impl Responder {
     let client = data.redis_client.lock().unwrap();
-    let mut con = client.get_connection().unwrap();
+    let mut con = match redis_health::connection(&client) {
+        Ok(con) => con,
+        Err(unavailable) => return unavailable,
+    };
     let result: RedisResult<()> = con.del(&*key);
 
     match result {
@@ -55,31 +88,41 @@ async fn delete_data(data: web::Data<Arc<AppState>>, key: web::Path<String>) ->
     }
 }
 
-async fn list_keys(data: web::Data<Arc<AppState>>) -> impl Responder {
+async fn list_keys(data: web::Data<Arc<AppState>>, query: web::Query<ListKeysQuery>) -> impl Responder {
     let client = data.redis_client.lock().unwrap();
-    let mut con = client.get_connection().unwrap();
-    let keys: RedisResult<Vec<String>> = con.keys("*");
-    
-    match keys {
-        Ok(key_list) => HttpResponse::Ok().json(key_list),
+    let mut con = match redis_health::connection(&client) {
+        Ok(con) => con,
+        Err(unavailable) => return unavailable,
+    };
+    // SCAN in pages; KEYS blocks Redis for the whole keyspace
+    match list_keys_page(&mut con, &query) {
+        Ok(page) => HttpResponse::Ok().json(page),
         Err(_) => HttpResponse::InternalServerError().body("Error retrieving keys"),
     }
 }
 
 async fn bulk_write_data(data: web::Data<Arc<AppState>>, info: web::Json<Vec<KeyValue>>) -> impl Responder {
     let client = data.redis_client.lock().unwrap();
-    let mut con = client.get_connection().unwrap();
-
-    for KeyValue { key, value } in info.into_inner() {
-        let _: RedisResult<()> = con.set(&key, value);
+    let mut con = match redis_health::connection(&client) {
+        Ok(con) => con,
+        Err(unavailable) => return unavailable,
+    };
+
+    let entries: Vec<(String, String)> = info.into_inner().into_iter().map(|kv| (kv.key, kv.value)).collect();
+    match bulk_write(&mut con, &entries) {
+        Ok(report) if report.failed.is_empty() => HttpResponse::Ok().json(report),
+        Ok(report) => HttpResponse::InternalServerError().json(report),
+        Err(BatchTooLarge(count)) => HttpResponse::PayloadTooLarge()
+            .body(format!("Bulk write of {} keys refused; at most {} per request", count, MAX_BATCH)),
     }
-
-    HttpResponse::Ok().body("Bulk data written")
 }
 
 async fn check_key_existence(data: web::Data<Arc<AppState>>, key: web::Path<String>) -> impl Responder {
     let client = data.redis_client.lock().unwrap();
-    let mut con = client.get_connection().unwrap();
+    let mut con = match redis_health::connection(&client) {
+        Ok(con) => con,
+        Err(unavailable) => return unavailable,
+    };
     let exists: RedisResult<bool> = con.exists(&*key);
 
     match exists {
@@ -108,7 +151,21 @@ async fn get_allowed_keys(data: web::Data<Arc<AppState>>) -> impl Responder {
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
-    let redis_client = Client::open("redis://127.0.0.1/").unwrap();
+    let redis_client = Client::open("redis://127.0.0.1/").map_err(std::io::Error::other)?;
+
+    // Refuse to start without Redis rather than failing every request later
+    let ready = wait_until_ready(
+        || {
+            let client = redis_client.clone();
+            async move { redis_health::ping(&client) }
+        },
+        Backoff::default(),
+    )
+    .await;
+    if let Err(e) = ready {
+        log::error!("Redis is unreachable, not starting: {}", e);
+        return Err(std::io::Error::other(e));
+    }
     let data = web::Data::new(Arc::new(AppState {
         redis_client: Mutex::new(redis_client),
         allowed_keys: Mutex::new(HashMap::new()),
@@ -117,6 +174,7 @@ async fn main() -> std::io::Result<()> {
 
     HttpServer::new(move || {
         App::new()
+            .configure(actix_body_limits(max_body_bytes()))
             .app_data(data.clone())
             .wrap(Logger::default())
             .wrap(middleware::Compress::default())
@@ -128,6 +186,8 @@ async fn main() -> std::io::Result<()> {
             .service(web::resource("/check/{key}").route(web::get().to(check_key_existence)))
             .service(web::resource("/allowed_keys").route(web::post().to(set_allowed_keys)))
             .service(web::resource("/allowed_keys").route(web::get().to(get_allowed_keys)))
+            .wrap(Timeout::new(request_timeout()))
+            .wrap(RequestLogger)
     })
     .bind("127.0.0.1:5500")?
     .run()
@@ -0,0 +1,138 @@
+use actix_web::HttpResponse;
+use redis::{Client, Connection, RedisResult};
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+// Longest a single startup ping may wait for a connection
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Longest a request handler waits for a connection before answering 503
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often, and how patiently, the startup check retries Redis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    /// Pings in total, including the first
+    pub attempts: u32,
+    /// Delay after the first failure, doubled after each one after that
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            attempts: 6,
+            initial: Duration::from_millis(250),
+            max: Duration::from_secs(4),
+        }
+    }
+}
+
+impl Backoff {
+    // Delay after the `failures`th consecutive failure (1-based)
+    fn delay(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+/// Runs `ping` until it succeeds, sleeping with exponential backoff between
+/// failures, and returns the last error once `backoff.attempts` are used up.
+pub async fn wait_until_ready<F, Fut, E>(mut ping: F, backoff: Backoff) -> Result<(), E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+    E: Display,
+{
+    let mut failures = 0;
+    loop {
+        match ping().await {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                failures += 1;
+                if failures >= backoff.attempts {
+                    return Err(e);
+                }
+                let delay = backoff.delay(failures);
+                log::warn!(
+                    "Redis not ready (attempt {}/{}): {}; retrying in {:?}",
+                    failures,
+                    backoff.attempts,
+                    e,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+/// Connects and sends `PING`.
+pub fn ping(client: &Client) -> RedisResult<()> {
+    let mut con = client.get_connection_with_timeout(PING_TIMEOUT)?;
+    redis::cmd("PING").query::<String>(&mut con).map(|_| ())
+}
+
+/// A connection for a request handler, or the `503 Service Unavailable` to
+/// answer with when Redis cannot be reached within a couple of seconds.
+#[allow(clippy::result_large_err)] // Handlers return the response as it is
+pub fn connection(client: &Client) -> Result<Connection, HttpResponse> {
+    client.get_connection_with_timeout(CONNECT_TIMEOUT).map_err(|e| {
+        log::error!("Failed to connect to Redis: {}", e);
+        HttpResponse::ServiceUnavailable().body("Redis is unavailable")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    const QUICK: Backoff = Backoff {
+        attempts: 4,
+        initial: Duration::from_millis(1),
+        max: Duration::from_millis(2),
+    };
+
+    #[tokio::test]
+    async fn test_retries_until_ping_succeeds() {
+        let calls = Cell::new(0);
+        let result = wait_until_ready(
+            || {
+                calls.set(calls.get() + 1);
+                let attempt = calls.get();
+                async move { if attempt < 3 { Err("connection refused") } else { Ok(()) } }
+            },
+            QUICK,
+        )
+        .await;
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_last_attempt() {
+        let calls = Cell::new(0);
+        let result = wait_until_ready(
+            || {
+                calls.set(calls.get() + 1);
+                async { Err::<(), _>("connection refused") }
+            },
+            QUICK,
+        )
+        .await;
+
+        assert_eq!(result, Err("connection refused"));
+        assert_eq!(calls.get(), QUICK.attempts);
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let backoff = Backoff::default();
+        let delays: Vec<_> = (1..=6).map(|failures| backoff.delay(failures).as_millis()).collect();
+        assert_eq!(delays, vec![250, 500, 1000, 2000, 4000, 4000]);
+    }
+}
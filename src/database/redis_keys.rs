@@ -0,0 +1,136 @@
+use redis::{Connection, RedisResult};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_COUNT: usize = 100;
+// Keeps one request from walking the whole keyspace in a single call
+const MAX_COUNT: usize = 1000;
+// SCAN round trips per page; a sparse pattern gets a short page and its cursor instead
+const MAX_SCANS: usize = 10;
+
+/// `?cursor=&count=&pattern=` for the key listing endpoints. Start with no
+/// cursor (or `0`) and pass back each `next_cursor` until it is `0` again.
+#[derive(Debug, Default, Deserialize)]
+pub struct ListKeysQuery {
+    pub cursor: Option<u64>,
+    pub count: Option<usize>,
+    pub pattern: Option<String>,
+}
+
+/// One page of keys; `next_cursor` is `0` once the keyspace is exhausted.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct KeyPage {
+    pub keys: Vec<String>,
+    pub next_cursor: u64,
+}
+
+/// A single `SCAN cursor MATCH pattern COUNT count` round trip.
+pub trait Scan {
+    fn scan(&mut self, cursor: u64, pattern: &str, count: usize) -> RedisResult<(u64, Vec<String>)>;
+}
+
+impl Scan for Connection {
+    fn scan(&mut self, cursor: u64, pattern: &str, count: usize) -> RedisResult<(u64, Vec<String>)> {
+        redis::cmd("SCAN").arg(cursor).arg("MATCH").arg(pattern).arg("COUNT").arg(count).query(self)
+    }
+}
+
+/// Collects at least `count` matching keys, or the rest of the keyspace.
+///
+/// `MATCH` filters after Redis has picked a batch, so a single `SCAN` can
+/// return few or no keys without being finished; this keeps scanning until
+/// the page is full, for at most `MAX_SCANS` round trips. A page may hold
+/// fewer keys than `count`, even none, while `next_cursor` is not yet `0`, or
+/// slightly more, and keys changed mid-iteration may be missed or repeated,
+/// as with `SCAN` itself.
+pub fn list_keys_page(con: &mut impl Scan, query: &ListKeysQuery) -> RedisResult<KeyPage> {
+    let count = query.count.unwrap_or(DEFAULT_COUNT).clamp(1, MAX_COUNT);
+    let pattern = query.pattern.as_deref().unwrap_or("*");
+    let mut cursor = query.cursor.unwrap_or(0);
+    let mut keys = Vec::new();
+
+    for _ in 0..MAX_SCANS {
+        let (next, batch) = con.scan(cursor, pattern, count)?;
+        keys.extend(batch);
+        cursor = next;
+        if cursor == 0 || keys.len() >= count {
+            break;
+        }
+    }
+    Ok(KeyPage { keys, next_cursor: cursor })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Mimics SCAN over a fixed keyspace: each call looks at `count` slots and
+    // then drops the ones that don't match, like Redis does
+    struct FakeKeyspace {
+        keys: Vec<String>,
+        calls: usize,
+    }
+
+    impl Scan for FakeKeyspace {
+        fn scan(&mut self, cursor: u64, pattern: &str, count: usize) -> RedisResult<(u64, Vec<String>)> {
+            self.calls += 1;
+            let start = cursor as usize;
+            let end = (start + count).min(self.keys.len());
+            let prefix = pattern.strip_suffix('*').expect("fake only supports prefix patterns");
+            let batch = self.keys[start..end].iter().filter(|key| key.starts_with(prefix)).cloned().collect();
+            let next = if end == self.keys.len() { 0 } else { end as u64 };
+            Ok((next, batch))
+        }
+    }
+
+    fn keyspace() -> FakeKeyspace {
+        // Users are bunched at the end, so early SCAN batches match nothing
+        let mut keys: Vec<String> = (0..40).map(|i| format!("session:{}", i)).collect();
+        keys.extend((0..25).map(|i| format!("user:{}", i)));
+        FakeKeyspace { keys, calls: 0 }
+    }
+
+    #[test]
+    fn test_pages_cover_matching_keyspace() {
+        let mut con = keyspace();
+        let mut query = ListKeysQuery { cursor: None, count: Some(10), pattern: Some("user:*".to_string()) };
+        let mut seen = Vec::new();
+        let mut pages = 0;
+
+        loop {
+            let page = list_keys_page(&mut con, &query).unwrap();
+            pages += 1;
+            assert!(page.keys.iter().all(|key| key.starts_with("user:")));
+            seen.extend(page.keys);
+            if page.next_cursor == 0 {
+                break;
+            }
+            query.cursor = Some(page.next_cursor);
+        }
+
+        let expected: Vec<String> = (0..25).map(|i| format!("user:{}", i)).collect();
+        assert_eq!(seen, expected);
+        assert_eq!(pages, 3);
+        // The first page had to skip four batches of sessions
+        assert_eq!(con.calls, 7);
+    }
+
+    #[test]
+    fn test_defaults_and_count_limits() {
+        let mut con = keyspace();
+        let page = list_keys_page(&mut con, &ListKeysQuery::default()).unwrap();
+        assert_eq!(page.keys.len(), 65);
+        assert_eq!(page.next_cursor, 0);
+
+        let page = list_keys_page(&mut con, &ListKeysQuery { count: Some(0), ..ListKeysQuery::default() }).unwrap();
+        assert_eq!(page, KeyPage { keys: vec!["session:0".to_string()], next_cursor: 1 });
+    }
+
+    #[test]
+    fn test_sparse_pattern_stops_after_max_scans() {
+        let mut con = keyspace();
+        let query = ListKeysQuery { cursor: None, count: Some(1), pattern: Some("user:*".to_string()) };
+        let page = list_keys_page(&mut con, &query).unwrap();
+        assert_eq!(page, KeyPage { keys: Vec::new(), next_cursor: MAX_SCANS as u64 });
+        assert_eq!(con.calls, MAX_SCANS);
+    }
+}
@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use validator::Validate;
+
+use crate::validation::not_blank;
+
+// Largest page `list_items` returns, whatever the caller asks for
+pub const MAX_PAGE_SIZE: i64 = 100;
+const DEFAULT_PAGE_SIZE: i64 = 20;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Item {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct NewItem {
+    #[validate(custom(function = "not_blank"))]
+    pub name: String,
+}
+
+// Which slice of the items to list, e.g. from `?limit=20&offset=40`
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Pagination {
+    #[serde(default = "default_page_size")]
+    pub limit: i64,
+    #[serde(default)]
+    pub offset: i64,
+}
+
+fn default_page_size() -> i64 {
+    DEFAULT_PAGE_SIZE
+}
+
+impl Default for Pagination {
+    fn default() -> Self {
+        Pagination { limit: DEFAULT_PAGE_SIZE, offset: 0 }
+    }
+}
+
+// Items ordered by id, `page.limit` (clamped to 1..=MAX_PAGE_SIZE) at a time
+pub async fn list_items(pool: &SqlitePool, page: &Pagination) -> Result<Vec<Item>, sqlx::Error> {
+    let limit = page.limit.clamp(1, MAX_PAGE_SIZE);
+    let offset = page.offset.max(0);
+    sqlx::query_as!(
+        Item,
+        r#"SELECT id AS "id!", name FROM items ORDER BY id LIMIT ? OFFSET ?"#,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_item(pool: &SqlitePool, id: i64) -> Result<Option<Item>, sqlx::Error> {
+    sqlx::query_as!(Item, r#"SELECT id AS "id!", name FROM items WHERE id = ?"#, id)
+        .fetch_optional(pool)
+        .await
+}
+
+// Insert `item`, returning it with its assigned id
+pub async fn insert_item(pool: &SqlitePool, item: &NewItem) -> Result<Item, sqlx::Error> {
+    sqlx::query_as!(Item, r#"INSERT INTO items (name) VALUES (?) RETURNING id AS "id!", name"#, item.name)
+        .fetch_one(pool)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A migrated in-memory database seeded with `count` items named item-1, item-2, ...
+    async fn seeded_pool(count: usize) -> SqlitePool {
+        let pool = super::super::connect("sqlite::memory:").await.unwrap();
+        for i in 1..=count {
+            insert_item(&pool, &NewItem { name: format!("item-{}", i) }).await.unwrap();
+        }
+        pool
+    }
+
+    #[tokio::test]
+    async fn test_insert_then_get_item() {
+        let pool = seeded_pool(2).await;
+
+        let inserted = insert_item(&pool, &NewItem { name: "widget".to_string() }).await.unwrap();
+        assert_eq!(inserted, Item { id: 3, name: "widget".to_string() });
+        assert_eq!(get_item(&pool, 3).await.unwrap(), Some(inserted));
+        assert_eq!(get_item(&pool, 1).await.unwrap().unwrap().name, "item-1");
+        assert_eq!(get_item(&pool, 42).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_list_items_pages_in_id_order() {
+        let pool = seeded_pool(5).await;
+
+        let first = list_items(&pool, &Pagination { limit: 2, offset: 0 }).await.unwrap();
+        assert_eq!(first, vec![Item { id: 1, name: "item-1".to_string() }, Item { id: 2, name: "item-2".to_string() }]);
+
+        let last = list_items(&pool, &Pagination { limit: 2, offset: 4 }).await.unwrap();
+        assert_eq!(last.iter().map(|item| item.id).collect::<Vec<_>>(), vec![5]);
+        assert!(list_items(&pool, &Pagination { limit: 2, offset: 10 }).await.unwrap().is_empty());
+
+        // Out-of-range requests are clamped rather than rejected
+        assert_eq!(list_items(&pool, &Pagination { limit: 0, offset: -3 }).await.unwrap().len(), 1);
+        assert_eq!(list_items(&pool, &Pagination::default()).await.unwrap().len(), 5);
+    }
+}
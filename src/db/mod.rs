@@ -0,0 +1,27 @@
+// Typed access to the SQLite database shared by the actix apps.
+//
+// Queries use sqlx's checked macros, which need the schema at build time:
+// run `sqlx migrate run` against `DATABASE_URL` (or `cargo sqlx prepare` for
+// offline builds) after changing anything under `migrations/`.
+
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+
+pub mod items;
+
+// Upper bound on open connections for a file-backed database
+const MAX_CONNECTIONS: u32 = 8;
+
+// Open the shared pool for `database_url` and bring its schema up to date.
+//
+// Every connection in an in-memory pool would see its own empty database,
+// so `:memory:` URLs get a single connection that is never recycled.
+pub async fn connect(database_url: &str) -> Result<SqlitePool, sqlx::Error> {
+    let options = if database_url.contains(":memory:") {
+        SqlitePoolOptions::new().max_connections(1).idle_timeout(None).max_lifetime(None)
+    } else {
+        SqlitePoolOptions::new().max_connections(MAX_CONNECTIONS)
+    };
+    let pool = options.connect(database_url).await?;
+    sqlx::migrate!().run(&pool).await?;
+    Ok(pool)
+}
@@ -0,0 +1,209 @@
+use futures::{SinkExt, StreamExt};
+use notify::RecommendedWatcher;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use warp::http::header::CONTENT_TYPE;
+use warp::ws::{Message, WebSocket, Ws};
+use warp::{Filter, Rejection, Reply};
+
+#[path = "build/watcher.rs"]
+mod watcher;
+use watcher::watch_debounced;
+
+/// Path of the websocket that pushes reloads to the pages.
+pub const RELOAD_PATH: &str = "__livereload";
+
+/// Text of the message sent to every connected page after a rebuild.
+pub const RELOAD_MESSAGE: &str = "reload";
+
+// Reconnects after the server restarts, reloading once it is back
+const RELOAD_SNIPPET: &str = r#"<script>
+(function connect(reconnected) {
+  var ws = new WebSocket((location.protocol === "https:" ? "wss://" : "ws://") + location.host + "/__livereload");
+  ws.onopen = function () { if (reconnected) location.reload(); };
+  ws.onmessage = function (event) { if (event.data === "reload") location.reload(); };
+  ws.onclose = function () { setTimeout(function () { connect(true); }, 1000); };
+})(false);
+</script>"#;
+
+/// Adds the live reload client to a page, just before its closing `</body>`,
+/// or at the end when it has none.
+pub fn inject_reload_snippet(html: &str) -> String {
+    match html.rfind("</body>") {
+        Some(end) => format!("{}{}{}", &html[..end], RELOAD_SNIPPET, &html[end..]),
+        None => format!("{}{}", html, RELOAD_SNIPPET),
+    }
+}
+
+/// Calls `rebuild` when anything under `paths` changes, then tells every
+/// connected page to reload through `reloads`.
+///
+/// Changes are debounced as in the build watcher, see [`watch_debounced`].
+/// Changes under `ignored`, the output directory, are not watched for. A
+/// failed rebuild is logged and sends no reload. Watching stops when the
+/// returned watcher is dropped.
+pub fn watch<F>(
+    paths: &[PathBuf],
+    ignored: &Path,
+    debounce: Duration,
+    mut rebuild: F,
+    reloads: broadcast::Sender<()>,
+) -> notify::Result<RecommendedWatcher>
+where
+    F: FnMut() -> io::Result<()> + Send + 'static,
+{
+    watch_debounced(paths, Some(ignored), debounce, move || match rebuild() {
+        // No pages connected is fine
+        Ok(()) => {
+            let _ = reloads.send(());
+        }
+        Err(e) => log::error!("Rebuild failed, not reloading: {}", e),
+    })
+}
+
+/// Serves the files under `root` on `addr`, with the live reload client in
+/// every HTML page and its websocket at [`RELOAD_PATH`]. Returns the bound
+/// address and the server to run, which finishes open requests and
+/// completes once `shutdown` resolves.
+pub fn bind(
+    addr: SocketAddr,
+    root: PathBuf,
+    reloads: broadcast::Sender<()>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<(SocketAddr, impl Future<Output = ()>), warp::Error> {
+    warp::serve(routes(root, reloads)).try_bind_with_graceful_shutdown(addr, shutdown)
+}
+
+fn routes(
+    root: PathBuf,
+    reloads: broadcast::Sender<()>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let live_reload = warp::path(RELOAD_PATH).and(warp::path::end()).and(warp::ws()).map(move |ws: Ws| {
+        let reloads = reloads.subscribe();
+        ws.on_upgrade(move |socket| push_reloads(socket, reloads))
+    });
+    let files = warp::get()
+        .and(warp::path::tail())
+        .and_then(move |tail: warp::path::Tail| serve_file(root.clone(), tail.as_str().to_string()));
+    live_reload.or(files)
+}
+
+async fn push_reloads(socket: WebSocket, mut reloads: broadcast::Receiver<()>) {
+    let (mut sender, mut receiver) = socket.split();
+    loop {
+        tokio::select! {
+            reload = reloads.recv() => match reload {
+                // Reloads that piled up still mean one reload
+                Ok(()) | Err(RecvError::Lagged(_)) => {
+                    if sender.send(Message::text(RELOAD_MESSAGE)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            },
+            // Pages send nothing; this only notices them going away
+            incoming = receiver.next() => match incoming {
+                Some(Ok(message)) if !message.is_close() => {}
+                _ => break,
+            },
+        }
+    }
+}
+
+// The file under `root` for a request path: `index.html` for directories,
+// and `page.html` for `page` when there is no file by that name
+fn resolve(root: &Path, tail: &str) -> Option<PathBuf> {
+    let relative = Path::new(tail);
+    if !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+        return None;
+    }
+    let path = root.join(relative);
+    if path.is_dir() {
+        return Some(path.join("index.html")).filter(|index| index.is_file());
+    }
+    if path.is_file() {
+        return Some(path);
+    }
+    Some(path.with_extension("html")).filter(|page| page.is_file())
+}
+
+async fn serve_file(root: PathBuf, tail: String) -> Result<warp::reply::Response, Rejection> {
+    let decoded = percent_decode(&tail);
+    let path = resolve(&root, &decoded).ok_or_else(warp::reject::not_found)?;
+    let contents = tokio::fs::read(&path).await.map_err(|_| warp::reject::not_found())?;
+    let mime = mime_guess::from_path(&path).first_or_octet_stream();
+
+    let body = if mime.essence_str() == "text/html" {
+        inject_reload_snippet(&String::from_utf8_lossy(&contents)).into_bytes()
+    } else {
+        contents
+    };
+    let mut response = warp::reply::Response::new(body.into());
+    let content_type = mime.as_ref().parse().expect("a mime type is a valid header value");
+    response.headers_mut().insert(CONTENT_TYPE, content_type);
+    // Always serve what is on disk now
+    response.headers_mut().insert(warp::http::header::CACHE_CONTROL, "no-store".parse().unwrap());
+    Ok(response)
+}
+
+// Request paths arrive percent-encoded; generated file names may have spaces
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_snippet_goes_before_closing_body() {
+        let html = inject_reload_snippet("<html><body><p>Hi</p></body></html>");
+        assert!(html.starts_with("<html><body><p>Hi</p><script>"));
+        assert!(html.ends_with("</script></body></html>"));
+        assert!(inject_reload_snippet("<p>fragment</p>").ends_with("</script>"));
+    }
+
+    #[tokio::test]
+    async fn test_pages_get_the_snippet_and_other_files_do_not() {
+        let root = std::env::temp_dir().join(format!("noxium-dev-server-{}", std::process::id()));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("index.html"), "<body>home</body>").unwrap();
+        fs::write(root.join("docs/intro page.html"), "<body>intro</body>").unwrap();
+        fs::write(root.join("style.css"), "body {}").unwrap();
+        let routes = routes(root.clone(), broadcast::channel(1).0);
+
+        let get = |path: &'static str| warp::test::request().path(path).reply(&routes);
+        let home = get("/").await;
+        assert_eq!(home.headers()[CONTENT_TYPE], "text/html");
+        assert!(String::from_utf8_lossy(home.body()).contains(RELOAD_PATH));
+        let intro = get("/docs/intro%20page").await;
+        assert!(String::from_utf8_lossy(intro.body()).contains("intro<script>"));
+        let style = get("/style.css").await;
+        assert_eq!(style.body().as_ref(), b"body {}");
+        assert_eq!(get("/missing").await.status(), 404);
+        assert_eq!(get("/docs/../../etc/passwd").await.status(), 404);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use thiserror::Error;
+use validator::ValidationErrors;
+use warp::{Rejection, Reply};
+
+/// Messages for each invalid request field, keyed by field name.
+pub type FieldErrors = BTreeMap<String, Vec<String>>;
+
+/// Error type shared by the actix and warp handlers.
+///
+/// Every variant renders as `{"error": "<kind>", "message": "<details>"}`
+/// with a matching HTTP status code; `InvalidFields` adds a `fields` map.
+#[derive(Debug, Clone, Error)]
+pub enum NoxiumError {
+    #[error("Validation failed: {0}")]
+    Validation(String),
+    #[error("Validation failed for {}", field_names(.0))]
+    InvalidFields(FieldErrors),
+    #[error("Not found: {0}")]
+    NotFound(String),
+    #[error("Method not allowed: {0}")]
+    MethodNotAllowed(String),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+    #[error("Length required: {0}")]
+    LengthRequired(String),
+    #[error("Database error: {0}")]
+    Database(String),
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+/// JSON body returned for every `NoxiumError`.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ErrorBody {
+    pub error: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<FieldErrors>,
+}
+
+fn field_names(fields: &FieldErrors) -> String {
+    fields.keys().cloned().collect::<Vec<_>>().join(", ")
+}
+
+impl NoxiumError {
+    pub fn status(&self) -> u16 {
+        match self {
+            NoxiumError::Validation(_) | NoxiumError::InvalidFields(_) => 400,
+            NoxiumError::NotFound(_) => 404,
+            NoxiumError::MethodNotAllowed(_) => 405,
+            NoxiumError::Unauthorized(_) => 401,
+            NoxiumError::PayloadTooLarge(_) => 413,
+            NoxiumError::LengthRequired(_) => 411,
+            NoxiumError::Database(_) | NoxiumError::Internal(_) => 500,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NoxiumError::Validation(_) | NoxiumError::InvalidFields(_) => "validation",
+            NoxiumError::NotFound(_) => "not_found",
+            NoxiumError::MethodNotAllowed(_) => "method_not_allowed",
+            NoxiumError::Unauthorized(_) => "unauthorized",
+            NoxiumError::PayloadTooLarge(_) => "payload_too_large",
+            NoxiumError::LengthRequired(_) => "length_required",
+            NoxiumError::Database(_) => "database",
+            NoxiumError::Internal(_) => "internal",
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        // Don't leak database/internal details to clients
+        let message = match self {
+            NoxiumError::Database(_) | NoxiumError::Internal(_) => "An internal error occurred".to_string(),
+            NoxiumError::InvalidFields(fields) => format!("Invalid fields: {}", field_names(fields)),
+            NoxiumError::Validation(msg)
+            | NoxiumError::NotFound(msg)
+            | NoxiumError::MethodNotAllowed(msg)
+            | NoxiumError::Unauthorized(msg)
+            | NoxiumError::PayloadTooLarge(msg)
+            | NoxiumError::LengthRequired(msg) => msg.clone(),
+        };
+        let fields = match self {
+            NoxiumError::InvalidFields(fields) => Some(fields.clone()),
+            _ => None,
+        };
+        ErrorBody {
+            error: self.kind(),
+            message,
+            fields,
+        }
+    }
+}
+
+impl From<std::io::Error> for NoxiumError {
+    fn from(err: std::io::Error) -> Self {
+        NoxiumError::Internal(err.to_string())
+    }
+}
+
+// Field rules without a `message` are reported by their code, e.g. "invalid length"
+impl From<ValidationErrors> for NoxiumError {
+    fn from(errors: ValidationErrors) -> Self {
+        let fields = errors
+            .field_errors()
+            .into_iter()
+            .map(|(field, errors)| {
+                let messages = errors
+                    .iter()
+                    .map(|error| match &error.message {
+                        Some(message) => message.to_string(),
+                        None => format!("invalid {}", error.code),
+                    })
+                    .collect();
+                (field.to_string(), messages)
+            })
+            .collect();
+        NoxiumError::InvalidFields(fields)
+    }
+}
+
+impl From<sqlx::Error> for NoxiumError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::RowNotFound => NoxiumError::NotFound("Record not found".to_string()),
+            other => NoxiumError::Database(other.to_string()),
+        }
+    }
+}
+
+// Actix integration: handlers returning `Result<_, NoxiumError>` can use `?`
+impl ResponseError for NoxiumError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if self.status() >= 500 {
+            log::error!("{}", self);
+        }
+        HttpResponse::build(self.status_code()).json(self.body())
+    }
+}
+
+// Warp integration: reject with `warp::reject::custom(err)` and recover with `handle_rejection`
+impl warp::reject::Reject for NoxiumError {}
+
+impl Reply for NoxiumError {
+    fn into_response(self) -> warp::reply::Response {
+        if self.status() >= 500 {
+            log::error!("{}", self);
+        }
+        let status = warp::http::StatusCode::from_u16(self.status())
+            .unwrap_or(warp::http::StatusCode::INTERNAL_SERVER_ERROR);
+        warp::reply::with_status(warp::reply::json(&self.body()), status).into_response()
+    }
+}
+
+/// Recovery filter for warp routes, converting rejections into JSON error bodies.
+pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    if let Some(e) = err.find::<NoxiumError>() {
+        return Ok(e.clone().into_response());
+    }
+
+    let error = if err.is_not_found() {
+        NoxiumError::NotFound("Route not found".to_string())
+    } else if let Some(e) = err.find::<warp::filters::body::BodyDeserializeError>() {
+        NoxiumError::Validation(e.to_string())
+    } else if let Some(e) = err.find::<warp::reject::PayloadTooLarge>() {
+        NoxiumError::PayloadTooLarge(e.to_string())
+    } else if let Some(e) = err.find::<warp::reject::LengthRequired>() {
+        NoxiumError::LengthRequired(e.to_string())
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        NoxiumError::MethodNotAllowed("Method not allowed".to_string())
+    } else {
+        NoxiumError::Internal(format!("Unhandled rejection: {:?}", err))
+    };
+    Ok(error.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+    use serde_json::Value;
+
+    fn all_variants() -> Vec<(NoxiumError, u16, &'static str)> {
+        vec![
+            (NoxiumError::Validation("bad port".into()), 400, "validation"),
+            (NoxiumError::InvalidFields(FieldErrors::from([("port".into(), vec!["too low".into()])])), 400, "validation"),
+            (NoxiumError::NotFound("no item".into()), 404, "not_found"),
+            (NoxiumError::MethodNotAllowed("use GET".into()), 405, "method_not_allowed"),
+            (NoxiumError::Unauthorized("bad token".into()), 401, "unauthorized"),
+            (NoxiumError::PayloadTooLarge("body over 1 MiB".into()), 413, "payload_too_large"),
+            (NoxiumError::LengthRequired("no content-length".into()), 411, "length_required"),
+            (NoxiumError::Database("pool closed".into()), 500, "database"),
+            (NoxiumError::Internal("boom".into()), 500, "internal"),
+        ]
+    }
+
+    #[actix_web::test]
+    async fn test_actix_status_and_body() {
+        for (error, status, kind) in all_variants() {
+            let response = error.error_response();
+            assert_eq!(response.status().as_u16(), status);
+
+            let bytes = to_bytes(response.into_body()).await.unwrap();
+            let body: Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body["error"], kind);
+            assert!(body["message"].is_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_warp_status_and_body() {
+        for (error, status, kind) in all_variants() {
+            let response = handle_rejection(warp::reject::custom(error)).await.unwrap().into_response();
+            assert_eq!(response.status().as_u16(), status);
+
+            let bytes = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let body: Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body["error"], kind);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_warp_body_rejections_keep_their_status() {
+        use warp::Filter;
+
+        let route = warp::body::content_length_limit(16)
+            .and(warp::body::json())
+            .map(|body: serde_json::Value| warp::reply::json(&body))
+            .recover(handle_rejection);
+
+        let response = warp::test::request().method("POST").reply(&route).await;
+        assert_eq!(response.status(), 411);
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["error"], "length_required");
+
+        let response = warp::test::request().method("POST").body("x".repeat(17)).reply(&route).await;
+        assert_eq!(response.status(), 413);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_method_is_405() {
+        use warp::Filter;
+
+        let route = warp::path("items").and(warp::get()).map(|| "items").recover(handle_rejection);
+
+        let response = warp::test::request().method("DELETE").path("/items").reply(&route).await;
+        assert_eq!(response.status(), 405);
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["error"], "method_not_allowed");
+
+        let response = warp::test::request().path("/other").reply(&route).await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[test]
+    fn test_field_errors_are_listed() {
+        let body = NoxiumError::InvalidFields(FieldErrors::from([
+            ("port".into(), vec!["must be between 1 and 65535".into()]),
+            ("name".into(), vec!["invalid length".into()]),
+        ]))
+        .body();
+        let body = serde_json::to_value(body).unwrap();
+        assert_eq!(body["message"], "Invalid fields: name, port");
+        assert_eq!(body["fields"]["port"][0], "must be between 1 and 65535");
+
+        let plain = serde_json::to_value(NoxiumError::Validation("bad".into()).body()).unwrap();
+        assert!(plain.get("fields").is_none());
+    }
+
+    #[test]
+    fn test_internal_details_are_hidden() {
+        let body = NoxiumError::Database("password=hunter2".into()).body();
+        assert!(!body.message.contains("hunter2"));
+    }
+}
@@ -0,0 +1,104 @@
+//! Escaping for text interpolated into HTML and XML output.
+//!
+//! Anything built with `format!` that ends up in markup goes through one of
+//! these: `html_escape` for element content, `attr_escape` for quoted
+//! attribute values, and `xml_escape` for XML documents. A name going into a
+//! URL path goes through `path_segment_encode` first.
+
+/// Escapes text placed between HTML tags.
+pub fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes a value placed inside a single- or double-quoted HTML attribute.
+///
+/// This keeps the value from closing the attribute, but doesn't check what
+/// it means there: a URL still needs its scheme checked before going into
+/// `href` or `src`.
+pub fn attr_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Percent-encodes one URL path segment, so `/`, `?`, `#` and `%` in a file
+/// name stay part of the name. Only unreserved characters are left as they are.
+pub fn path_segment_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Escapes text for XML element content or attribute values. Control
+/// characters XML 1.0 can't represent, even as references, become U+FFFD.
+pub fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\u{0}'..='\u{8}' | '\u{B}' | '\u{C}' | '\u{E}'..='\u{1F}' => escaped.push('\u{FFFD}'),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_escape() {
+        assert_eq!(html_escape("<b>Tom & \"Jerry\"</b>"), "&lt;b&gt;Tom &amp; \"Jerry\"&lt;/b&gt;");
+        assert_eq!(html_escape("&amp;"), "&amp;amp;");
+        assert_eq!(html_escape("plain text"), "plain text");
+    }
+
+    #[test]
+    fn test_attr_escape_keeps_value_inside_quotes() {
+        let value = "x\" onmouseover=\"alert(1)' <img>";
+        let escaped = attr_escape(value);
+        assert_eq!(escaped, "x&quot; onmouseover=&quot;alert(1)&#39; &lt;img&gt;");
+        assert!(!escaped.contains(['"', '\'', '<', '>']));
+    }
+
+    #[test]
+    fn test_path_segment_encode() {
+        assert_eq!(path_segment_encode("a b#1?x=%.txt"), "a%20b%231%3Fx%3D%25.txt");
+        assert_eq!(path_segment_encode("dir/ä"), "dir%2F%C3%A4");
+        assert_eq!(path_segment_encode("plain-name_1.txt"), "plain-name_1.txt");
+    }
+
+    #[test]
+    fn test_xml_escape() {
+        assert_eq!(xml_escape("<a href='x'>Q&A</a>"), "&lt;a href=&apos;x&apos;&gt;Q&amp;A&lt;/a&gt;");
+        assert_eq!(xml_escape("tab\tline\nbell\u{7}"), "tab\tline\nbell\u{FFFD}");
+    }
+}
@@ -0,0 +1,386 @@
+use regex::{Captures, Regex};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+/// Written to the output root: logical asset paths to their fingerprinted paths.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Files that get a content hash in their name. Pages, feeds, and the
+/// manifest keep their URLs, since nothing can learn the new ones.
+const ASSET_EXTENSIONS: &[&str] = &[
+    "css", "js", "mjs", "map", "png", "jpg", "jpeg", "gif", "svg", "webp", "avif", "ico", "woff", "woff2", "ttf", "otf",
+];
+
+/// Assets that name other assets, and so are rewritten before being hashed.
+const REFERENCING_ASSETS: &[&str] = &["css", "js", "mjs"];
+
+/// Hex digits of the SHA-256 kept in a fingerprinted name.
+const HASH_LEN: usize = 10;
+
+static ATTRIBUTE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\b(?:href|src)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+static SRCSET_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"\bsrcset\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+static CSS_URL_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\burl\(\s*(?:"([^"]*)"|'([^']*)'|([^)"'\s]+))\s*\)"#).unwrap());
+static CSS_IMPORT_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"@import\s+(?:"([^"]*)"|'([^']*)')"#).unwrap());
+// `//# sourceMappingURL=app.js.map` in scripts, `/*# ... */` in stylesheets
+static SOURCE_MAP_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[#@]\s*sourceMappingURL=([^\s*]+)").unwrap());
+
+/// Logical path -> fingerprinted path, both relative to the output root and
+/// `/`-separated, e.g. `css/site.css` -> `css/site.3f2a9c41d0.css`.
+pub type Manifest = BTreeMap<String, String>;
+
+/// Renames every asset under `root` to `name.<hash>.ext`, points the
+/// references to them at the new names, and writes the mapping to
+/// `root/manifest.json`.
+///
+/// References are rewritten in the HTML files under `root` (`href`, `src`
+/// and `srcset` attributes, and `url(...)` in inline styles), in stylesheets
+/// (`url(...)`, `@import` and the `sourceMappingURL` comment) and in scripts
+/// (`sourceMappingURL`). Stylesheets and scripts are hashed after the assets
+/// they reference, so their hash covers the rewritten content.
+///
+/// The hash only depends on the file's content, so an unchanged asset keeps
+/// its name across builds and stays in browser caches, while a changed one
+/// gets a URL no browser has cached. With `keep_originals`, the unhashed
+/// files are left in place, unchanged, for anything that links to them from
+/// outside.
+///
+/// Expects the build to have just written the unhashed assets: files that
+/// already carry a hash are left alone, so running it twice is harmless.
+pub fn fingerprint_assets(root: &Path, keep_originals: bool) -> io::Result<Manifest> {
+    let mut files = Vec::new();
+    collect_files(root, &mut files)?;
+
+    let mut manifest = Manifest::new();
+    let (referencing, plain): (Vec<&PathBuf>, Vec<&PathBuf>) =
+        files.iter().filter(|path| is_asset(path)).partition(|path| has_extension(path, REFERENCING_ASSETS));
+    for path in plain {
+        let content = fs::read(path)?;
+        store_hashed(root, path, &content, keep_originals, &mut manifest)?;
+    }
+
+    let mut pending =
+        referencing.into_iter().map(|path| Ok((path, fs::read(path)?))).collect::<io::Result<Vec<_>>>()?;
+    while !pending.is_empty() {
+        // One whose references are all hashed already; in a cycle, any of them
+        let waiting: HashSet<String> = pending.iter().map(|(path, _)| relative_url(root, path)).collect();
+        let next = pending
+            .iter()
+            .position(|(path, content)| {
+                let dir = dir_url(root, path);
+                let mut waits = false;
+                if let Ok(text) = std::str::from_utf8(content) {
+                    rewrite_references(text, path, &mut |url| {
+                        let logical = logical_path(url, &dir);
+                        waits |= logical
+                            .is_some_and(|logical| logical != relative_url(root, path) && waiting.contains(&logical));
+                        None
+                    });
+                }
+                !waits
+            })
+            .unwrap_or(0);
+        let (path, content) = pending.remove(next);
+        let dir = dir_url(root, path);
+        let content = match std::str::from_utf8(&content) {
+            Ok(text) => rewrite_references(text, path, &mut |url| hashed_url(url, &dir, &manifest)).into_bytes(),
+            Err(_) => content,
+        };
+        store_hashed(root, path, &content, keep_originals, &mut manifest)?;
+    }
+
+    for path in files.iter().filter(|path| has_extension(path, &["html", "htm"])) {
+        let page_dir = dir_url(root, path);
+        let html = fs::read_to_string(path)?;
+        let rewritten = rewrite_references(&html, path, &mut |url| hashed_url(url, &page_dir, &manifest));
+        if rewritten != html {
+            fs::write(path, rewritten.as_bytes())?;
+        }
+    }
+
+    let json = serde_json::to_string_pretty(&manifest).map_err(io::Error::other)?;
+    fs::write(root.join(MANIFEST_FILE), json)?;
+    Ok(manifest)
+}
+
+// Writes `content` under the fingerprinted name of `path`, removing `path`
+// unless the originals are kept
+fn store_hashed(
+    root: &Path,
+    path: &Path,
+    content: &[u8],
+    keep_originals: bool,
+    manifest: &mut Manifest,
+) -> io::Result<()> {
+    let hashed = path.with_file_name(fingerprinted_name(path, content));
+    fs::write(&hashed, content)?;
+    if !keep_originals {
+        fs::remove_file(path)?;
+    }
+    manifest.insert(relative_url(root, path), relative_url(root, &hashed));
+    Ok(())
+}
+
+// Calls `map` with every asset reference in `text`, the content of `path`,
+// replacing the ones it returns a new URL for
+fn rewrite_references(text: &str, path: &Path, map: &mut dyn FnMut(&str) -> Option<String>) -> String {
+    if has_extension(path, &["html", "htm"]) {
+        let text = replace_captured(text, &ATTRIBUTE_RE, map);
+        let text = replace_captured(&text, &SRCSET_RE, &mut |srcset| Some(rewrite_srcset(srcset, map)));
+        replace_captured(&text, &CSS_URL_RE, map)
+    } else if has_extension(path, &["css"]) {
+        let text = replace_captured(text, &CSS_URL_RE, map);
+        let text = replace_captured(&text, &CSS_IMPORT_RE, map);
+        replace_captured(&text, &SOURCE_MAP_RE, map)
+    } else {
+        replace_captured(text, &SOURCE_MAP_RE, map)
+    }
+}
+
+// Replaces the URL in whichever group of `re` matched, keeping the rest of the match
+fn replace_captured(text: &str, re: &Regex, map: &mut dyn FnMut(&str) -> Option<String>) -> String {
+    re.replace_all(text, |caps: &Captures| {
+        let whole = caps.get(0).expect("group 0 always matches");
+        let replaced = caps.iter().skip(1).flatten().next().and_then(|url| {
+            let new_url = map(url.as_str())?;
+            Some(format!("{}{}{}", &text[whole.start()..url.start()], new_url, &text[url.end()..whole.end()]))
+        });
+        replaced.unwrap_or_else(|| whole.as_str().to_string())
+    })
+    .into_owned()
+}
+
+// `srcset` lists `url [descriptor]` candidates separated by commas
+fn rewrite_srcset(srcset: &str, map: &mut dyn FnMut(&str) -> Option<String>) -> String {
+    srcset
+        .split(',')
+        .map(|candidate| {
+            let trimmed = candidate.trim_start();
+            let (url, descriptor) = trimmed.split_at(trimmed.find(char::is_whitespace).unwrap_or(trimmed.len()));
+            let leading = &candidate[..candidate.len() - trimmed.len()];
+            format!("{}{}{}", leading, map(url).unwrap_or_else(|| url.to_string()), descriptor)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.path());
+    for entry in entries {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extensions.iter().any(|candidate| extension.eq_ignore_ascii_case(candidate)))
+}
+
+// An asset that doesn't already end in `.<hash>.ext`
+fn is_asset(path: &Path) -> bool {
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+    let hashed = stem.rsplit_once('.').is_some_and(|(_, hash)| {
+        hash.len() == HASH_LEN && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    });
+    has_extension(path, ASSET_EXTENSIONS) && !hashed
+}
+
+fn fingerprinted_name(path: &Path, content: &[u8]) -> String {
+    let hash: String = Sha256::digest(content).iter().map(|byte| format!("{:02x}", byte)).collect();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    format!("{}.{}.{}", stem, &hash[..HASH_LEN], extension)
+}
+
+fn relative_url(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative.components().map(|component| component.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+// The directory of `path` as a URL relative to `root`, "" at the root
+fn dir_url(root: &Path, path: &Path) -> String {
+    let url = relative_url(root, path);
+    url.rsplit_once('/').map_or(String::new(), |(dir, _)| dir.to_string())
+}
+
+// The output-root-relative path a reference from a file in `dir` names, or
+// None for external URLs and paths that climb above the root
+fn logical_path(url: &str, dir: &str) -> Option<String> {
+    let external = url.starts_with("//") || url.split('/').next().is_some_and(|first| first.contains(':'));
+    if external {
+        return None;
+    }
+    let path = &url[..url.find(['?', '#']).unwrap_or(url.len())];
+    match path.strip_prefix('/') {
+        Some(absolute) => normalize(absolute),
+        None => normalize(&format!("{}/{}", dir, path)),
+    }
+}
+
+// The fingerprinted form of a reference from a file in `dir`, keeping it
+// relative or root-absolute as written, with its query and fragment
+fn hashed_url(url: &str, dir: &str, manifest: &Manifest) -> Option<String> {
+    let hashed = manifest.get(&logical_path(url, dir)?)?;
+    let (path, suffix) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+    let file_name = hashed.rsplit('/').next()?;
+    let dir = path.rfind('/').map_or("", |slash| &path[..=slash]);
+    Some(format!("{}{}{}", dir, file_name, suffix))
+}
+
+// Resolves `.` and `..` segments; None if the path climbs above the root
+fn normalize(path: &str) -> Option<String> {
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+    Some(segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn site(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("noxium-fingerprint-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("css")).unwrap();
+        fs::write(root.join("css/site.css"), "body { color: #333; }\n").unwrap();
+        root
+    }
+
+    #[test]
+    fn test_css_name_carries_a_stable_content_hash() {
+        let root = site("css");
+        let manifest = fingerprint_assets(&root, false).unwrap();
+        let hashed = manifest["css/site.css"].clone();
+        assert!(hashed.starts_with("css/site.") && hashed.ends_with(".css"), "{}", hashed);
+        assert_eq!(hashed.len(), "css/site..css".len() + HASH_LEN);
+        assert_eq!(fs::read_to_string(root.join(&hashed)).unwrap(), "body { color: #333; }\n");
+        assert!(!root.join("css/site.css").exists());
+
+        // A rebuild with the same content lands on the same name, and only
+        // the freshly written file is hashed
+        fs::write(root.join("css/site.css"), "body { color: #333; }\n").unwrap();
+        let rebuilt = fingerprint_assets(&root, false).unwrap();
+        assert_eq!(rebuilt, manifest);
+
+        fs::write(root.join("css/site.css"), "body { color: #000; }\n").unwrap();
+        let changed = fingerprint_assets(&root, false).unwrap();
+        assert_ne!(changed["css/site.css"], hashed);
+
+        let written: Manifest = serde_json::from_str(&fs::read_to_string(root.join(MANIFEST_FILE)).unwrap()).unwrap();
+        assert_eq!(written, changed);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_html_references_point_at_hashed_names() {
+        let root = site("html");
+        fs::create_dir_all(root.join("js")).unwrap();
+        fs::create_dir_all(root.join("guides")).unwrap();
+        fs::write(root.join("js/app.js"), "console.log(1);\n").unwrap();
+        fs::write(
+            root.join("index.html"),
+            "<link rel=\"stylesheet\" href=\"css/site.css\"><script src='/js/app.js?v=1'></script>\
+             <a href=\"guides/setup.html\">Setup</a><img src=\"https://cdn.example.com/css/site.css\">",
+        )
+        .unwrap();
+        fs::write(root.join("guides/setup.html"), "<link href=\"../css/site.css\">").unwrap();
+
+        let manifest = fingerprint_assets(&root, true).unwrap();
+        let css = manifest["css/site.css"].trim_start_matches("css/").to_string();
+        let js = manifest["js/app.js"].trim_start_matches("js/").to_string();
+        assert_eq!(
+            fs::read_to_string(root.join("index.html")).unwrap(),
+            format!(
+                "<link rel=\"stylesheet\" href=\"css/{}\"><script src='/js/{}?v=1'></script>\
+                 <a href=\"guides/setup.html\">Setup</a><img src=\"https://cdn.example.com/css/site.css\">",
+                css, js
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("guides/setup.html")).unwrap(),
+            format!("<link href=\"../css/{}\">", css)
+        );
+        // Kept on request, for links from outside the site
+        assert!(root.join("css/site.css").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_stylesheets_scripts_and_srcset_point_at_hashed_names() {
+        let root = site("refs");
+        fs::create_dir_all(root.join("fonts")).unwrap();
+        fs::create_dir_all(root.join("img")).unwrap();
+        fs::create_dir_all(root.join("js")).unwrap();
+        fs::write(root.join("fonts/inter.woff2"), "font").unwrap();
+        fs::write(root.join("img/hero.png"), "small").unwrap();
+        fs::write(root.join("img/hero-2x.png"), "large").unwrap();
+        fs::write(root.join("js/app.js.map"), "{}").unwrap();
+        fs::write(root.join("js/app.js"), "console.log(1);\n//# sourceMappingURL=app.js.map\n").unwrap();
+        fs::write(
+            root.join("css/theme.css"),
+            "@import \"site.css\";\n@font-face { src: url(../fonts/inter.woff2) format(\"woff2\"); }\n\
+             .hero { background: url('/img/hero.png'); }\n.icon { mask: url(data:image/svg+xml;base64,AA==); }\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("index.html"),
+            "<img srcset=\"img/hero.png 1x, img/hero-2x.png 2x\"><div style=\"background: url(img/hero.png)\"></div>",
+        )
+        .unwrap();
+
+        let manifest = fingerprint_assets(&root, false).unwrap();
+        let file = |logical: &str| manifest[logical].rsplit('/').next().unwrap().to_string();
+        assert_eq!(
+            fs::read_to_string(root.join(&manifest["js/app.js"])).unwrap(),
+            format!("console.log(1);\n//# sourceMappingURL={}\n", file("js/app.js.map"))
+        );
+        let theme = fs::read_to_string(root.join(&manifest["css/theme.css"])).unwrap();
+        assert_eq!(
+            theme,
+            format!(
+                "@import \"{}\";\n@font-face {{ src: url(../fonts/{}) format(\"woff2\"); }}\n\
+                 .hero {{ background: url('/img/{}'); }}\n.icon {{ mask: url(data:image/svg+xml;base64,AA==); }}\n",
+                file("css/site.css"),
+                file("fonts/inter.woff2"),
+                file("img/hero.png")
+            )
+        );
+        // The stylesheet's hash covers what it references
+        assert_eq!(
+            manifest["css/theme.css"],
+            format!("css/{}", fingerprinted_name(Path::new("theme.css"), theme.as_bytes()))
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("index.html")).unwrap(),
+            format!(
+                "<img srcset=\"img/{} 1x, img/{} 2x\"><div style=\"background: url(img/{})\"></div>",
+                file("img/hero.png"),
+                file("img/hero-2x.png"),
+                file("img/hero.png")
+            )
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
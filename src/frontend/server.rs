@@ -4,6 +4,16 @@ use serde_json::json;
 use jsonwebtoken::{encode, Header, EncodingKey};
 use std::collections::HashMap;
 
+#[path = "../body_limit.rs"]
+#[allow(dead_code)]
+mod body_limit;
+use body_limit::{max_body_bytes, warp_body_limit};
+
+#[path = "../shutdown.rs"]
+#[allow(dead_code)]
+mod shutdown;
+use shutdown::shutdown_signal;
+
 #[derive(Serialize, Deserialize)]
 struct Claims {
     sub: String,
@@ -19,6 +29,7 @@ async fn main() {
 
     let auth = warp::path("auth")
         .and(warp::post())
+        .and(warp_body_limit(max_body_bytes()))
         .and(warp::body::json())
         .and_then(authenticate);
 
@@ -28,7 +39,9 @@ async fn main() {
 
     let routes = static_files.or(frontend1).or(frontend2).or(auth).or(api);
 
-    warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
+    // Finish in-flight requests on ctrl-c or SIGTERM
+    let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(([127, 0, 0, 1], 3030), shutdown_signal());
+    server.await;
 }
 
 async fn authenticate(credentials: HashMap<String, String>) -> Result<impl Reply, Rejection> {
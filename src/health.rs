@@ -0,0 +1,297 @@
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, Error, HttpResponse};
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Upper bounds (seconds) of the request latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Request metrics collected by [`RequestMetrics`] and rendered by `/metrics`.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    // (method, status) -> count
+    requests: Mutex<BTreeMap<(String, u16), u64>>,
+    // Cumulative counts per bucket, plus the running sum in microseconds
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_count: AtomicU64,
+    latency_sum_micros: AtomicU64,
+    in_flight: AtomicI64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn record(&self, method: &str, status: u16, elapsed: Duration) {
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((method.to_string(), status))
+            .or_insert(0) += 1;
+
+        let seconds = elapsed.as_secs_f64();
+        for (bucket, bound) in self.latency_buckets.iter().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total Total HTTP requests handled.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((method, status), count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(out, "http_requests_total{{method=\"{}\",status=\"{}\"}} {}", method, status, count);
+        }
+
+        out.push_str("# HELP http_request_duration_seconds Request latency in seconds.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for (bucket, bound) in self.latency_buckets.iter().zip(LATENCY_BUCKETS) {
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound,
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let count = self.latency_count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "http_request_duration_seconds_bucket{{le=\"+Inf\"}} {}", count);
+        let sum = self.latency_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "http_request_duration_seconds_sum {}", sum);
+        let _ = writeln!(out, "http_request_duration_seconds_count {}", count);
+
+        out.push_str("# HELP http_requests_in_flight Requests currently being handled.\n");
+        out.push_str("# TYPE http_requests_in_flight gauge\n");
+        let _ = writeln!(out, "http_requests_in_flight {}", self.in_flight.load(Ordering::Relaxed));
+
+        out
+    }
+}
+
+// Keeps the in-flight gauge accurate even if the request future is dropped early
+struct InFlightGuard(Arc<Metrics>);
+
+impl InFlightGuard {
+    fn new(metrics: Arc<Metrics>) -> Self {
+        metrics.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard(metrics)
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Middleware recording request counts, latency, and in-flight requests.
+///
+/// Wrap an app with `.wrap(RequestMetrics::new(metrics.clone()))` and mount
+/// [`health_routes`] with the same `Metrics` to expose them.
+pub struct RequestMetrics {
+    metrics: Arc<Metrics>,
+}
+
+impl RequestMetrics {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        RequestMetrics { metrics }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestMetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service,
+            metrics: self.metrics.clone(),
+        }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let metrics = self.metrics.clone();
+        let method = req.method().to_string();
+        let guard = InFlightGuard::new(metrics.clone());
+        let start = Instant::now();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let result = fut.await;
+            let status = match &result {
+                Ok(response) => response.status().as_u16(),
+                Err(e) => e.as_response_error().status_code().as_u16(),
+            };
+            metrics.record(&method, status, start.elapsed());
+            drop(guard);
+            result
+        })
+    }
+}
+
+// Shared by the health handlers
+struct HealthState {
+    metrics: Arc<Metrics>,
+    pool: Option<SqlitePool>,
+    shutting_down: Arc<AtomicBool>,
+}
+
+/// Routes for `/healthz`, `/readyz`, and `/metrics`, mounted with
+/// `App::new().configure(health_routes(metrics, pool, shutting_down))`.
+///
+/// `/readyz` checks the pool with a trivial query; apps without a database
+/// pass `None` and are always ready. Once `shutting_down` is set it reports
+/// 503 regardless, so load balancers stop sending traffic while the server
+/// drains.
+pub fn health_routes(
+    metrics: Arc<Metrics>,
+    pool: Option<SqlitePool>,
+    shutting_down: Arc<AtomicBool>,
+) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |cfg| {
+        cfg.app_data(web::Data::new(HealthState { metrics, pool, shutting_down }))
+            .route("/healthz", web::get().to(healthz))
+            .route("/readyz", web::get().to(readyz))
+            .route("/metrics", web::get().to(metrics_handler));
+    }
+}
+
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+async fn readyz(state: web::Data<HealthState>) -> HttpResponse {
+    if state.shutting_down.load(Ordering::SeqCst) {
+        return HttpResponse::ServiceUnavailable().json(json!({ "status": "shutting down" }));
+    }
+    let Some(pool) = &state.pool else {
+        return HttpResponse::Ok().json(json!({ "status": "ready" }));
+    };
+
+    match sqlx::query("SELECT 1").execute(pool).await {
+        Ok(_) => HttpResponse::Ok().json(json!({ "status": "ready", "database": "ok" })),
+        Err(e) => {
+            log::warn!("Readiness check failed: {}", e);
+            HttpResponse::ServiceUnavailable().json(json!({ "status": "unavailable", "database": "unreachable" }))
+        }
+    }
+}
+
+async fn metrics_handler(state: web::Data<HealthState>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(state.metrics.render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    async fn memory_pool() -> SqlitePool {
+        SqlitePool::connect("sqlite::memory:").await.expect("Failed to open in-memory database")
+    }
+
+    #[actix_web::test]
+    async fn test_readyz_with_healthy_pool() {
+        let metrics = Arc::new(Metrics::new());
+        let pool = memory_pool().await;
+        let app = test::init_service(App::new().configure(health_routes(metrics, Some(pool), Arc::default()))).await;
+
+        let response = test::call_service(&app, test::TestRequest::get().uri("/readyz").to_request()).await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_readyz_with_broken_pool() {
+        let pool = memory_pool().await;
+        pool.close().await;
+        let metrics = Arc::new(Metrics::new());
+        let app = test::init_service(App::new().configure(health_routes(metrics, Some(pool), Arc::default()))).await;
+
+        let response = test::call_service(&app, test::TestRequest::get().uri("/readyz").to_request()).await;
+        assert_eq!(response.status(), 503);
+    }
+
+    #[actix_web::test]
+    async fn test_readyz_fails_once_shutting_down() {
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let metrics = Arc::new(Metrics::new());
+        let app = test::init_service(
+            App::new().configure(health_routes(metrics, Some(memory_pool().await), shutting_down.clone())),
+        )
+        .await;
+
+        shutting_down.store(true, Ordering::SeqCst);
+        let response = test::call_service(&app, test::TestRequest::get().uri("/readyz").to_request()).await;
+        assert_eq!(response.status(), 503);
+        // Still alive, just not taking new traffic
+        let response = test::call_service(&app, test::TestRequest::get().uri("/healthz").to_request()).await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_metrics_count_requests() {
+        let metrics = Arc::new(Metrics::new());
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestMetrics::new(metrics.clone()))
+                .configure(health_routes(metrics.clone(), None, Arc::default()))
+                .route("/hello", web::get().to(|| async { HttpResponse::Ok().body("hi") })),
+        )
+        .await;
+
+        for _ in 0..3 {
+            test::call_service(&app, test::TestRequest::get().uri("/hello").to_request()).await;
+        }
+        test::call_service(&app, test::TestRequest::get().uri("/missing").to_request()).await;
+
+        let response = test::call_service(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+        assert_eq!(response.status(), 200);
+        let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+
+        assert!(body.contains("http_requests_total{method=\"GET\",status=\"200\"} 3"), "{}", body);
+        assert!(body.contains("http_requests_total{method=\"GET\",status=\"404\"} 1"), "{}", body);
+        assert!(body.contains("http_request_duration_seconds_count 4"), "{}", body);
+        // The scrape itself is still in flight while rendering
+        assert!(body.contains("http_requests_in_flight 1"), "{}", body);
+    }
+}
@@ -0,0 +1,223 @@
+use regex::Regex;
+use std::sync::LazyLock;
+
+// Comments, and the elements whose whitespace is content. A comment
+// inside a raw element is part of it, and a tag inside a comment is not one
+static KEPT_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?is)<!--.*?-->|<pre\b.*?</pre>|<textarea\b.*?</textarea>|<script\b.*?</script>|<style\b.*?</style>")
+        .unwrap()
+});
+static WHITESPACE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s+").unwrap());
+
+// `<!--[if IE]>...<![endif]-->`, and the `<!--[if !IE]><!-->` ... `<!--<![endif]-->`
+// pair that hides markup from old IE, mean something to the browser
+fn is_conditional_comment(comment: &str) -> bool {
+    let body = &comment["<!--".len()..];
+    body.starts_with("[if") || body.starts_with("<![endif]")
+}
+
+// Elements laid out as blocks, or not rendered at all. Whitespace next to
+// their tags never shows; between inline elements it renders as a space
+const BLOCK_TAGS: &[&str] = &[
+    "address", "article", "aside", "base", "blockquote", "body", "br", "caption", "col", "colgroup", "dd",
+    "details", "dialog", "div", "dl", "dt", "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2",
+    "h3", "h4", "h5", "h6", "head", "header", "hr", "html", "legend", "li", "link", "main", "meta", "nav", "ol",
+    "optgroup", "option", "p", "pre", "script", "section", "style", "summary", "table", "tbody", "td", "tfoot",
+    "th", "thead", "title", "tr", "ul",
+];
+
+// The lowercased name of the tag `out` ends with, or `None` when it ends in
+// text. Comments and doctypes are named ""
+fn last_tag_name(out: &str) -> Option<String> {
+    let tag = &out[out.rfind('<')?..];
+    let inner = tag.strip_suffix('>')?;
+    (!inner.contains('>')).then(|| tag_name(inner))
+}
+
+fn tag_name(tag: &str) -> String {
+    let name = tag.trim_start_matches('<').trim_start_matches('/');
+    name.chars().take_while(|c| c.is_ascii_alphanumeric()).collect::<String>().to_ascii_lowercase()
+}
+
+// Whether the whitespace between the end of `out` and `following` can be
+// dropped: it must sit between two tags, or a tag and either end of the
+// document, with at least one side not inline
+fn space_is_droppable(out: &str, following: &str) -> bool {
+    let before = if out.is_empty() { Some(String::new()) } else { last_tag_name(out) };
+    let after = if following.is_empty() {
+        Some(String::new())
+    } else {
+        following.starts_with('<').then(|| tag_name(following))
+    };
+    let breaks_line = |name: &str| name.is_empty() || BLOCK_TAGS.contains(&name);
+    match (before, after) {
+        (Some(before), Some(after)) => breaks_line(&before) || breaks_line(&after),
+        _ => false,
+    }
+}
+
+// Where the tag starting `text` ends: after its `>`, skipping any inside
+// quoted attribute values
+fn tag_end(text: &str) -> usize {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return i + 1,
+            (Some(open), _) if c == open => quote = None,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+// Where the next tag in `text` starts; a `<` not followed by a name, `/` or
+// `!` is text
+fn next_tag_start(text: &str) -> Option<usize> {
+    text.match_indices('<')
+        .map(|(i, _)| i)
+        .find(|&i| text[i + 1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!'))
+}
+
+// Collapses whitespace runs in a tag to one space, except inside quoted
+// attribute values
+fn minify_tag(tag: &str, out: &mut String) {
+    let mut quote = None;
+    let mut in_space = false;
+    for c in tag.chars() {
+        if quote.is_none() && c.is_whitespace() {
+            in_space = true;
+            continue;
+        }
+        if in_space {
+            in_space = false;
+            if c != '>' {
+                out.push(' ');
+            }
+        }
+        match quote {
+            None if c == '"' || c == '\'' => quote = Some(c),
+            Some(open) if c == open => quote = None,
+            _ => {}
+        }
+        out.push(c);
+    }
+}
+
+// `next` is the kept markup after the text, or "" at the end of the document.
+// Tags in the text keep their attribute values as written
+fn minify_text(text: &str, next: &str, out: &mut String) {
+    let mut rest = text;
+    while !rest.is_empty() {
+        if next_tag_start(rest) == Some(0) {
+            let end = tag_end(rest);
+            minify_tag(&rest[..end], out);
+            rest = &rest[end..];
+            continue;
+        }
+        let (run, after) = rest.split_at(next_tag_start(rest).unwrap_or(rest.len()));
+        minify_run(run, if after.is_empty() { next } else { after }, out);
+        rest = after;
+    }
+}
+
+// Text without tags, followed by `next`
+fn minify_run(text: &str, next: &str, out: &mut String) {
+    let text = WHITESPACE_RE.replace_all(text, " ");
+    let mut rest = text.as_ref();
+    while let Some(space) = rest.find(' ') {
+        out.push_str(&rest[..space]);
+        rest = &rest[space + 1..];
+        let following = if rest.is_empty() { next } else { rest };
+        if !space_is_droppable(out, following) {
+            out.push(' ');
+        }
+    }
+    out.push_str(rest);
+}
+
+/// Shrinks `html` without changing what it renders.
+///
+/// Runs of whitespace become one space, and whitespace between tags is
+/// dropped when a block-level tag is on either side; between inline elements,
+/// as in `<b>a</b> <i>b</i>`, it renders as a space and is kept. Quoted
+/// attribute values are left as written. Comments are removed, except IE
+/// conditional comments. The contents of `<pre>`, `<textarea>`, `<script>` and
+/// `<style>` are left alone.
+pub fn minify_html(html: &str) -> String {
+    let mut minified = String::with_capacity(html.len());
+    // Text around a removed comment is minified as one piece, so the
+    // whitespace on either side of it collapses together
+    let mut text = String::new();
+    let mut last = 0;
+    for kept in KEPT_RE.find_iter(html) {
+        text.push_str(&html[last..kept.start()]);
+        last = kept.end();
+        let kept = kept.as_str();
+        if kept.starts_with("<!--") && !is_conditional_comment(kept) {
+            continue;
+        }
+        minify_text(&text, kept, &mut minified);
+        text.clear();
+        minified.push_str(kept);
+    }
+    text.push_str(&html[last..]);
+    minify_text(&text, "", &mut minified);
+    minified
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_whitespace_and_comments_are_removed_outside_raw_elements() {
+        let html = "<html>\n  <body>\n    <!-- nav -->\n    <h1>Hello,   <em>world</em></h1>\n    \
+                    <p>one <!-- two --> three</p>\n    <pre>  keep\n    this  </pre>\n  </body>\n</html>\n";
+        assert_eq!(
+            minify_html(html),
+            "<html><body><h1>Hello, <em>world</em></h1><p>one three</p><pre>  keep\n    this  </pre></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_space_between_inline_siblings_is_kept() {
+        assert_eq!(minify_html("<p><b>a</b> <i>b</i></p>"), "<p><b>a</b> <i>b</i></p>");
+        assert_eq!(
+            minify_html("<p>\n  <a href=\"/\">Home</a>\n  <a href=\"/about\">About</a>\n</p>"),
+            "<p><a href=\"/\">Home</a> <a href=\"/about\">About</a></p>"
+        );
+        assert_eq!(minify_html("<span>x</span> <!-- gap --> <em>y</em>"), "<span>x</span> <em>y</em>");
+        // Next to a block, the same whitespace never renders
+        assert_eq!(
+            minify_html("<ul>\n  <li>one</li>\n  <li>two</li>\n</ul> <b>c</b>"),
+            "<ul><li>one</li><li>two</li></ul><b>c</b>"
+        );
+    }
+
+    #[test]
+    fn test_attribute_values_are_kept_as_written() {
+        assert_eq!(
+            minify_html("<p\n   class=\"note\"  title=\"x\n  y\">\n  <input value=\"a  b\"  >\n</p>"),
+            "<p class=\"note\" title=\"x\n  y\"><input value=\"a  b\"></p>"
+        );
+        assert_eq!(minify_html("<b title='a > b'>1  <  2</b>"), "<b title='a > b'>1 < 2</b>");
+    }
+
+    #[test]
+    fn test_raw_elements_and_conditional_comments_are_kept() {
+        let html = "<head>\n<!--[if lt IE 9]>\n<script src=\"shiv.js\"></script>\n<![endif]-->\n\
+                    <style>\n  p  { margin: 0 }\n</style>\n</head>\n\
+                    <!--[if !IE]><!--> <p>modern</p> <!--<![endif]-->\n\
+                    <script>\n// <!-- not a comment here -->\nlet  x = 1;\n</script>\n\
+                    <textarea>\n  typed  </textarea>";
+        assert_eq!(
+            minify_html(html),
+            "<head><!--[if lt IE 9]>\n<script src=\"shiv.js\"></script>\n<![endif]-->\
+             <style>\n  p  { margin: 0 }\n</style></head>\
+             <!--[if !IE]><!--><p>modern</p><!--<![endif]-->\
+             <script>\n// <!-- not a comment here -->\nlet  x = 1;\n</script>\
+             <textarea>\n  typed  </textarea>"
+        );
+    }
+}
@@ -0,0 +1,634 @@
+use encoding_rs::{Encoding, UTF_8};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::{HeaderMap, CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::redirect::Policy;
+use std::env;
+use std::fmt;
+use std::io::{self, Read};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+use url::{Host, Url};
+
+/// Sent with every outbound request so site owners can tell who is crawling them.
+pub const USER_AGENT: &str = concat!("noxium/", env!("CARGO_PKG_VERSION"));
+
+/// Limits applied to every outbound client.
+///
+/// `from_env` reads `HTTP_CONNECT_TIMEOUT_SECS`, `HTTP_READ_TIMEOUT_SECS`,
+/// `HTTP_TIMEOUT_SECS`, and `HTTP_MAX_REDIRECTS`, falling back to the
+/// defaults for anything unset or unparseable, plus the [`FetchPolicy`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpConfig {
+    pub connect_timeout: Duration,
+    /// Longest wait for the next chunk of a response
+    pub read_timeout: Duration,
+    /// Longest a whole request may take, body included
+    pub timeout: Duration,
+    pub max_redirects: usize,
+    pub pool_max_idle_per_host: usize,
+    pub pool_idle_timeout: Duration,
+    pub fetch_policy: FetchPolicy,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(15),
+            timeout: Duration::from_secs(30),
+            max_redirects: 5,
+            pool_max_idle_per_host: 8,
+            pool_idle_timeout: Duration::from_secs(90),
+            fetch_policy: FetchPolicy::default(),
+        }
+    }
+}
+
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok()?.trim().parse().ok()
+}
+
+impl HttpConfig {
+    pub fn from_env() -> Self {
+        let defaults = HttpConfig::default();
+        let secs = |name: &str, default: Duration| env_parse(name).map_or(default, Duration::from_secs);
+        HttpConfig {
+            connect_timeout: secs("HTTP_CONNECT_TIMEOUT_SECS", defaults.connect_timeout),
+            read_timeout: secs("HTTP_READ_TIMEOUT_SECS", defaults.read_timeout),
+            timeout: secs("HTTP_TIMEOUT_SECS", defaults.timeout),
+            max_redirects: env_parse("HTTP_MAX_REDIRECTS").unwrap_or(defaults.max_redirects),
+            fetch_policy: FetchPolicy::from_env(),
+            ..defaults
+        }
+    }
+}
+
+/// Which targets outbound requests may reach, so user-supplied URLs can't be
+/// pointed at loopback, the private network, or cloud metadata endpoints.
+///
+/// `from_env` reads `HTTP_ALLOWED_HOSTS`, a comma-separated list that, when
+/// set, is the only hosts that may be fetched (and which are trusted even on
+/// private addresses), and `HTTP_ALLOW_PRIVATE`, which lets any host resolve
+/// to a private address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchPolicy {
+    pub schemes: Vec<String>,
+    pub allowed_hosts: Vec<String>,
+    pub allow_private: bool,
+}
+
+impl Default for FetchPolicy {
+    fn default() -> Self {
+        FetchPolicy {
+            schemes: vec!["http".to_string(), "https".to_string()],
+            allowed_hosts: Vec::new(),
+            allow_private: false,
+        }
+    }
+}
+
+impl FetchPolicy {
+    pub fn from_env() -> Self {
+        let allowed_hosts = env::var("HTTP_ALLOWED_HOSTS")
+            .map(|hosts| hosts.split(',').map(|host| host.trim().to_string()).filter(|host| !host.is_empty()).collect())
+            .unwrap_or_default();
+        let allow_private = env::var("HTTP_ALLOW_PRIVATE").is_ok_and(|value| matches!(value.trim(), "1" | "true"));
+        FetchPolicy { allowed_hosts, allow_private, ..FetchPolicy::default() }
+    }
+
+    // The allowlist's verdict on `host`, or `allow_private`'s; None if it
+    // depends on where the host resolves
+    fn decide_host(&self, host: &str) -> Option<bool> {
+        if !self.allowed_hosts.is_empty() {
+            return Some(self.allowed_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(host)));
+        }
+        if self.allow_private {
+            return Some(true);
+        }
+        None
+    }
+
+    // The decision for `url` that needs no DNS lookup, if there is one
+    fn precheck(&self, url: &Url) -> Option<bool> {
+        if !self.schemes.iter().any(|scheme| scheme == url.scheme()) {
+            return Some(false);
+        }
+        let (Some(host), Some(name)) = (url.host(), url.host_str()) else {
+            return Some(false);
+        };
+        self.decide_host(name).or(match host {
+            Host::Ipv4(ip) => Some(is_public_ip(IpAddr::V4(ip))),
+            Host::Ipv6(ip) => Some(is_public_ip(IpAddr::V6(ip))),
+            Host::Domain(_) => None,
+        })
+    }
+}
+
+/// Whether `url` may be fetched under `policy`: an allowed scheme, and a host
+/// that is allowlisted or resolves only to public addresses.
+///
+/// Hosts are resolved here, which blocks. The shared clients check each
+/// address again when connecting, so a name that later resolves somewhere
+/// else is still refused.
+pub fn is_url_allowed(url: &Url, policy: &FetchPolicy) -> bool {
+    policy.precheck(url).unwrap_or_else(|| {
+        let host = url.host_str().unwrap_or_default();
+        match (host, url.port_or_known_default().unwrap_or(0)).to_socket_addrs() {
+            Ok(addrs) => all_public(addrs),
+            Err(_) => false,
+        }
+    })
+}
+
+/// [`is_url_allowed`] for async code, resolving the host on the runtime
+/// instead of blocking its worker thread.
+pub async fn is_url_allowed_async(url: &Url, policy: &FetchPolicy) -> bool {
+    if let Some(allowed) = policy.precheck(url) {
+        return allowed;
+    }
+    let host = url.host_str().unwrap_or_default();
+    match tokio::net::lookup_host((host, url.port_or_known_default().unwrap_or(0))).await {
+        Ok(addrs) => all_public(addrs),
+        Err(_) => false,
+    }
+}
+
+// A host with no addresses is refused along with one that has a private address
+fn all_public(addrs: impl Iterator<Item = SocketAddr>) -> bool {
+    let ips: Vec<IpAddr> = addrs.map(|addr| addr.ip()).collect();
+    !ips.is_empty() && ips.into_iter().all(is_public_ip)
+}
+
+// Loopback, private, link-local, and other addresses that aren't on the public internet
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || first == 0
+                // 100.64.0.0/10, shared address space for carrier-grade NAT
+                || (first == 100 && second & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                // fc00::/7 is unique local, fe80::/10 link-local
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// A fetch target refused by the [`FetchPolicy`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockedUrl(pub String);
+
+impl fmt::Display for BlockedUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Refusing to fetch {:?}: not an allowed public http(s) target", self.0)
+    }
+}
+
+impl std::error::Error for BlockedUrl {}
+
+/// Parses a user-supplied URL and checks it against the policy from the
+/// environment; call it before fetching anything a user chose.
+pub fn check_url(url: &str) -> Result<Url, BlockedUrl> {
+    check_url_with(url, &FetchPolicy::from_env())
+}
+
+pub fn check_url_with(url: &str, policy: &FetchPolicy) -> Result<Url, BlockedUrl> {
+    match Url::parse(url) {
+        Ok(parsed) if is_url_allowed(&parsed, policy) => Ok(parsed),
+        _ => Err(BlockedUrl(url.to_string())),
+    }
+}
+
+/// [`check_url_with`] for async code; see [`is_url_allowed_async`].
+pub async fn check_url_async(url: &str, policy: &FetchPolicy) -> Result<Url, BlockedUrl> {
+    match Url::parse(url) {
+        Ok(parsed) if is_url_allowed_async(&parsed, policy).await => Ok(parsed),
+        _ => Err(BlockedUrl(url.to_string())),
+    }
+}
+
+// Resolves names for the shared clients, refusing hosts the policy blocks.
+// This catches redirects and DNS answers that change after `check_url`.
+// reqwest connects to IP literals without asking the resolver, and has no
+// hook before connecting, so a URL taken from a fetched page still needs
+// `check_url_with` first
+struct GuardedResolver {
+    policy: FetchPolicy,
+}
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let policy = self.policy.clone();
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs: Vec<_> = tokio::net::lookup_host((host, 0)).await?.collect();
+            let allowed = policy.decide_host(host).unwrap_or_else(|| addrs.iter().all(|addr| is_public_ip(addr.ip())));
+            if !allowed {
+                let error = io::Error::new(io::ErrorKind::PermissionDenied, BlockedUrl(host.to_string()));
+                return Err(error.into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+// Follows up to `max_redirects` hops, refusing any the policy blocks without
+// a lookup; names are checked by the resolver when connecting
+fn redirect_policy(config: &HttpConfig) -> Policy {
+    let (policy, max_redirects) = (config.fetch_policy.clone(), config.max_redirects);
+    Policy::custom(move |attempt| {
+        if attempt.previous().len() > max_redirects {
+            attempt.error("too many redirects")
+        } else if policy.precheck(attempt.url()) == Some(false) {
+            let blocked = BlockedUrl(attempt.url().to_string());
+            attempt.error(blocked)
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+/// An async client configured from the environment; see [`HttpConfig::from_env`].
+pub fn client() -> reqwest::Client {
+    client_with(&HttpConfig::from_env())
+}
+
+pub fn client_with(config: &HttpConfig) -> reqwest::Client {
+    client_builder_with(config).build().expect("HTTP client should build with the TLS backend")
+}
+
+/// The builder behind [`client_with`], for callers that set more options,
+/// such as their own user agent, before building.
+pub fn client_builder_with(config: &HttpConfig) -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .user_agent(USER_AGENT)
+        .gzip(true)
+        .brotli(true)
+        .deflate(true)
+        .connect_timeout(config.connect_timeout)
+        .read_timeout(config.read_timeout)
+        .timeout(config.timeout)
+        .redirect(redirect_policy(config))
+        .dns_resolver(Arc::new(GuardedResolver { policy: config.fetch_policy.clone() }))
+        .pool_max_idle_per_host(config.pool_max_idle_per_host)
+        .pool_idle_timeout(config.pool_idle_timeout)
+}
+
+/// A blocking client configured from the environment; see [`HttpConfig::from_env`].
+pub fn blocking_client() -> reqwest::blocking::Client {
+    blocking_client_with(&HttpConfig::from_env())
+}
+
+// The blocking client has no separate read timeout; `timeout` bounds each request
+pub fn blocking_client_with(config: &HttpConfig) -> reqwest::blocking::Client {
+    reqwest::blocking::Client::builder()
+        .user_agent(USER_AGENT)
+        .gzip(true)
+        .brotli(true)
+        .deflate(true)
+        .connect_timeout(config.connect_timeout)
+        .timeout(config.timeout)
+        .redirect(redirect_policy(config))
+        .dns_resolver(Arc::new(GuardedResolver { policy: config.fetch_policy.clone() }))
+        .pool_max_idle_per_host(config.pool_max_idle_per_host)
+        .pool_idle_timeout(config.pool_idle_timeout)
+        .build()
+        .expect("HTTP client should build with the TLS backend")
+}
+
+/// A response body that could not be read.
+#[derive(Debug)]
+pub enum BodyError {
+    Http(reqwest::Error),
+    Decompress(io::Error),
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::Http(e) => write!(f, "Failed to read response body: {}", e),
+            BodyError::Decompress(e) => write!(f, "Failed to decompress response body: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BodyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BodyError::Http(e) => Some(e),
+            BodyError::Decompress(e) => Some(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for BodyError {
+    fn from(e: reqwest::Error) -> Self {
+        BodyError::Http(e)
+    }
+}
+
+impl From<io::Error> for BodyError {
+    fn from(e: io::Error) -> Self {
+        BodyError::Decompress(e)
+    }
+}
+
+/// Wraps `reader` so what is read from it has the `Content-Encoding` codings
+/// in `content_encoding` undone, decompressing as it goes.
+///
+/// Codings are listed in the order they were applied, so they are undone
+/// last to first. An unknown coding is an `InvalidData` error.
+pub fn decoding_reader<'a>(reader: impl Read + 'a, content_encoding: &str) -> io::Result<Box<dyn Read + 'a>> {
+    let mut reader: Box<dyn Read + 'a> = Box::new(reader);
+    for coding in content_encoding.rsplit(',').map(str::trim) {
+        reader = match coding.to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Box::new(GzDecoder::new(reader)),
+            // HTTP's deflate is zlib-wrapped, not raw deflate
+            "deflate" => Box::new(ZlibDecoder::new(reader)),
+            "br" => Box::new(brotli::Decompressor::new(reader, 4096)),
+            "identity" | "" => reader,
+            other => {
+                let message = format!("unsupported Content-Encoding {:?}", other);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+        };
+    }
+    Ok(reader)
+}
+
+// The client drops the header when it decompresses a body itself, so one
+// still present means the body is still compressed
+fn content_encoding(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(CONTENT_ENCODING)?.to_str().ok()?.trim();
+    (!value.is_empty() && !value.eq_ignore_ascii_case("identity")).then(|| value.to_string())
+}
+
+// Decodes with the Content-Type charset, UTF-8 when there is none, like `Response::text`
+fn decode_text(bytes: &[u8], headers: &HeaderMap) -> String {
+    let encoding = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            value.split(';').skip(1).filter_map(|param| param.split_once('=')).find_map(|(name, label)| {
+                name.trim().eq_ignore_ascii_case("charset").then(|| label.trim().trim_matches('"').to_string())
+            })
+        })
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .unwrap_or(UTF_8);
+    encoding.decode(bytes).0.into_owned()
+}
+
+/// Reads a whole response body, decompressing it by its `Content-Encoding`
+/// if the client left it compressed. The shared clients decompress gzip,
+/// brotli, and deflate themselves; this covers other clients and servers
+/// that compress without being asked.
+pub async fn bytes(response: reqwest::Response) -> Result<Vec<u8>, BodyError> {
+    let encoding = content_encoding(response.headers());
+    let body = response.bytes().await?;
+    match encoding {
+        Some(encoding) => {
+            let mut decoded = Vec::new();
+            decoding_reader(&body[..], &encoding)?.read_to_end(&mut decoded)?;
+            Ok(decoded)
+        }
+        None => Ok(body.to_vec()),
+    }
+}
+
+/// [`bytes`], decoded as text with the response's charset.
+pub async fn text(response: reqwest::Response) -> Result<String, BodyError> {
+    let headers = response.headers().clone();
+    Ok(decode_text(&bytes(response).await?, &headers))
+}
+
+/// [`bytes`] for a blocking response, decompressed while it streams in.
+pub fn blocking_bytes(response: reqwest::blocking::Response) -> Result<Vec<u8>, BodyError> {
+    match content_encoding(response.headers()) {
+        Some(encoding) => {
+            let mut decoded = Vec::new();
+            decoding_reader(response, &encoding)?.read_to_end(&mut decoded)?;
+            Ok(decoded)
+        }
+        None => Ok(response.bytes()?.to_vec()),
+    }
+}
+
+/// [`text`] for a blocking response.
+pub fn blocking_text(response: reqwest::blocking::Response) -> Result<String, BodyError> {
+    let headers = response.headers().clone();
+    Ok(decode_text(&blocking_bytes(response)?, &headers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn quick_config() -> HttpConfig {
+        HttpConfig {
+            read_timeout: Duration::from_millis(200),
+            timeout: Duration::from_millis(300),
+            ..HttpConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_server_times_out() {
+        // Accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                held.push(stream);
+            }
+        });
+
+        let start = Instant::now();
+        let error = client_with(&quick_config()).get(&url).send().await.unwrap_err();
+        assert!(error.is_timeout(), "{}", error);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_blocking_slow_server_times_out() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || listener.incoming().collect::<Vec<_>>());
+
+        let start = Instant::now();
+        let error = blocking_client_with(&quick_config()).get(&url).send().unwrap_err();
+        assert!(error.is_timeout(), "{}", error);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_private_and_non_http_targets_are_blocked() {
+        let policy = FetchPolicy::default();
+        for url in [
+            "http://localhost/",
+            "http://localhost:8080/admin",
+            "http://127.0.0.1/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://10.1.2.3/",
+            "http://[::1]/",
+            "http://[::ffff:192.168.0.1]/",
+            "file:///etc/passwd",
+            "ftp://example.com/",
+        ] {
+            assert!(!is_url_allowed(&Url::parse(url).unwrap(), &policy), "{}", url);
+        }
+        assert_eq!(check_url_with("not a url", &policy), Err(BlockedUrl("not a url".to_string())));
+
+        // A public address needs no lookup
+        assert!(is_url_allowed(&Url::parse("https://93.184.215.14/page").unwrap(), &policy));
+        assert!(check_url_with("http://1.1.1.1/", &policy).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_async_check_resolves_names_too() {
+        let policy = FetchPolicy::default();
+        assert!(check_url_async("http://localhost:8080/admin", &policy).await.is_err());
+        assert!(check_url_async("http://10.0.0.1/", &policy).await.is_err());
+        assert!(check_url_async("https://1.1.1.1/", &policy).await.is_ok());
+
+        let trusting = FetchPolicy { allow_private: true, ..FetchPolicy::default() };
+        assert!(check_url_async("http://localhost:8080/admin", &trusting).await.is_ok());
+    }
+
+    #[test]
+    fn test_allowlist_is_exclusive_and_trusted() {
+        let policy = FetchPolicy { allowed_hosts: vec!["localhost".to_string()], ..FetchPolicy::default() };
+        assert!(is_url_allowed(&Url::parse("http://LOCALHOST:9000/").unwrap(), &policy));
+        assert!(!is_url_allowed(&Url::parse("https://1.1.1.1/").unwrap(), &policy));
+        assert!(!is_url_allowed(&Url::parse("file://localhost/etc/passwd").unwrap(), &policy));
+    }
+
+    #[tokio::test]
+    async fn test_client_refuses_names_resolving_to_private_addresses() {
+        use tokio::io::AsyncWriteExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://localhost:{}/", listener.local_addr().unwrap().port());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok").await;
+            }
+        });
+
+        let error = client_with(&quick_config()).get(&url).send().await.unwrap_err();
+        assert!(error.is_connect(), "{}", error);
+
+        let trusting = HttpConfig {
+            fetch_policy: FetchPolicy { allow_private: true, ..FetchPolicy::default() },
+            ..quick_config()
+        };
+        let response = client_with(&trusting).get(&url).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+    }
+
+    const PAGE: &str = "<html><head><title>Café menu</title></head><body><h1>Today</h1></body></html>";
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        io::Write::write_all(&mut encoder, bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    // Serves PAGE gzipped, with a Latin-1 charset so decoding is checked too, whatever the request asks for
+    fn gzip_server() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let (latin1, _, _) = encoding_rs::WINDOWS_1252.encode(PAGE);
+        let body = gzip(&latin1);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=ISO-8859-1\r\n\
+                     Content-Encoding: gzip\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = io::Write::write_all(&mut stream, head.as_bytes());
+                let _ = io::Write::write_all(&mut stream, &body);
+            }
+        });
+        url
+    }
+
+    fn assert_parses(html: &str) {
+        let document = scraper::Html::parse_document(html);
+        let title = document.select(&scraper::Selector::parse("title").unwrap()).next().unwrap().inner_html();
+        assert_eq!(title, "Café menu");
+        assert_eq!(document.select(&scraper::Selector::parse("h1").unwrap()).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_gzipped_page_is_decoded() {
+        let url = gzip_server();
+        let trusting = HttpConfig {
+            fetch_policy: FetchPolicy { allow_private: true, ..FetchPolicy::default() },
+            ..HttpConfig::default()
+        };
+
+        // The shared client decompresses it itself
+        let response = client_with(&trusting).get(&url).send().await.unwrap();
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+        assert_parses(&text(response).await.unwrap());
+
+        // A client that doesn't leaves it to the fallback
+        let response = reqwest::Client::builder().no_gzip().build().unwrap().get(&url).send().await.unwrap();
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+        assert_parses(&text(response).await.unwrap());
+
+        let url_for_blocking = url.clone();
+        let html = tokio::task::spawn_blocking(move || {
+            let client = reqwest::blocking::Client::builder().no_gzip().build().unwrap();
+            blocking_text(client.get(&url_for_blocking).send().unwrap())
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert_parses(&html);
+    }
+
+    #[test]
+    fn test_stacked_and_unknown_encodings() {
+        let mut deflated = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        io::Write::write_all(&mut deflated, &gzip(PAGE.as_bytes())).unwrap();
+        let body = deflated.finish().unwrap();
+
+        let mut decoded = String::new();
+        decoding_reader(&body[..], "gzip, deflate").unwrap().read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, PAGE);
+
+        let mut brotli_body = Vec::new();
+        brotli::BrotliCompress(&mut PAGE.as_bytes(), &mut brotli_body, &Default::default()).unwrap();
+        let mut decoded = String::new();
+        decoding_reader(&brotli_body[..], "BR").unwrap().read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, PAGE);
+
+        let error = decoding_reader(&body[..], "zstd").err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
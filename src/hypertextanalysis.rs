@@ -1,12 +1,72 @@
 // Import necessary crates for HTML parsing, file handling, HTTP requests, and asynchronous execution
-use scraper::{Html, Selector}; // For HTML parsing and element selection
-use std::collections::HashMap; // Standard library HashMap for storing tag and attribute counts
+use scraper::{ElementRef, Html, Selector}; // For HTML parsing and element selection
+use serde::Serialize; // For writing diffs as JSON
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet}; // Standard library collections for counts, label lookups, and ordered diff output
 use std::fmt; // For custom formatting of output
 use std::fs; // For reading HTML content from files
 use std::io; // For handling input/output errors
-use reqwest; // For making HTTP requests to fetch HTML content
 use std::env; // For handling environment variables
 
+#[allow(dead_code)]
+mod http; // Shared HTTP client, with timeouts and the fetch target policy
+
+// Define the kinds of accessibility problems found during analysis
+#[derive(Debug, Clone, PartialEq)]
+enum A11yIssueKind {
+    MissingAlt, // <img> without an alt attribute
+    UnlabeledInput, // Form control without a <label>, aria-label, aria-labelledby, or title
+    EmptyLink, // <a> with no text or accessible name
+    MissingLang, // <html> without a lang attribute
+}
+
+// Define a struct for a single accessibility finding
+#[derive(Debug, Clone, PartialEq)]
+struct A11yIssue {
+    kind: A11yIssueKind, // What is wrong
+    element: String, // Short description of the offending element, e.g. <img src="logo.png">
+}
+
+// Implement the Display trait so issues read as one line each
+impl fmt::Display for A11yIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self.kind {
+            A11yIssueKind::MissingAlt => "image has no alt text",
+            A11yIssueKind::UnlabeledInput => "form control has no label",
+            A11yIssueKind::EmptyLink => "link has no text",
+            A11yIssueKind::MissingLang => "document has no lang attribute",
+        };
+        write!(f, "{}: {}", self.element, message)
+    }
+}
+
+// Describe an element by its tag and first identifying attribute
+fn describe_element(element: &ElementRef) -> String {
+    let tag = element.value().name();
+    ["id", "name", "src", "href"]
+        .iter()
+        .find_map(|attr| element.value().attr(attr).map(|value| format!("<{} {}=\"{}\">", tag, attr, value)))
+        .unwrap_or_else(|| format!("<{}>", tag))
+}
+
+// Check if a form control needs a label; hidden fields and buttons carry their own text
+fn needs_label(element: &ElementRef) -> bool {
+    match element.value().name() {
+        "select" | "textarea" => true,
+        "input" => !matches!(
+            element.value().attr("type").map(|t| t.to_ascii_lowercase()).as_deref(),
+            Some("hidden" | "submit" | "button" | "reset" | "image")
+        ),
+        _ => false,
+    }
+}
+
+// Check if an element names itself through ARIA attributes or a title
+fn has_accessible_name(element: &ElementRef) -> bool {
+    ["aria-label", "aria-labelledby", "title"]
+        .iter()
+        .any(|attr| element.value().attr(attr).is_some_and(|value| !value.trim().is_empty()))
+}
+
 // Define a struct to hold the results of the HTML analysis
 // This struct will be responsible for counting and displaying tag frequencies, attributes, nesting levels, and text content
 struct AnalysisResult {
@@ -16,6 +76,7 @@ struct AnalysisResult {
     total_text_content: String, // String to store the accumulated text content from the HTML
     unique_tags: HashMap<String, usize>, // HashMap to store unique tags and their occurrences
     attribute_per_tag: HashMap<String, HashMap<String, usize>>, // Nested HashMap to store attribute counts per tag
+    a11y_issues: Vec<A11yIssue>, // Accessibility problems found while visiting elements
 }
 
 // Implement methods for the AnalysisResult struct
@@ -29,6 +90,7 @@ impl AnalysisResult {
             total_text_content: String::new(), // Initialize total_text_content as an empty string
             unique_tags: HashMap::new(), // Initialize unique_tags as an empty HashMap
             attribute_per_tag: HashMap::new(), // Initialize attribute_per_tag as an empty nested HashMap
+            a11y_issues: Vec::new(), // Initialize a11y_issues as an empty list
         }
     }
 
@@ -38,6 +100,8 @@ impl AnalysisResult {
         let selector = Selector::parse("*").unwrap(); // Create a Selector to select all elements
 
         let mut tag_stack: Vec<String> = Vec::new(); // Track the current nesting level of tags
+        let mut label_targets: HashSet<String> = HashSet::new(); // Ids referenced by <label for="...">
+        let mut unlabeled_inputs: Vec<(Option<String>, String)> = Vec::new(); // (id, description) of controls not yet known to be labeled
 
         for element in document.select(&selector) {
             let tag_name = element.value().name().to_string(); // Get the tag name
@@ -59,8 +123,8 @@ impl AnalysisResult {
             tag_stack.push(tag_name.clone());
 
             // Iterate over all attributes of the current element
-            for attr in element.attributes() {
-                let attr_name = attr.key().to_string();
+            for (attr_name, _) in element.value().attrs() {
+                let attr_name = attr_name.to_string();
 
                 // Update attribute count
                 let attr_count = self.attribute_count.entry(attr_name.clone()).or_insert(0);
@@ -78,9 +142,49 @@ impl AnalysisResult {
             let text_content = element.text().collect::<Vec<_>>().concat();
             self.total_text_content.push_str(&text_content);
 
+            // Collect accessibility findings for this element
+            match tag_name.as_str() {
+                "html" if element.value().attr("lang").is_none_or(|lang| lang.trim().is_empty()) => {
+                    self.a11y_issues.push(A11yIssue { kind: A11yIssueKind::MissingLang, element: describe_element(&element) });
+                }
+                "img" if element.value().attr("alt").is_none() => {
+                    self.a11y_issues.push(A11yIssue { kind: A11yIssueKind::MissingAlt, element: describe_element(&element) });
+                }
+                "a" if element.value().attr("href").is_some() => {
+                    // Images with alt text give a link its name too
+                    let img_selector = Selector::parse("img[alt]").unwrap();
+                    let has_image_name = element
+                        .select(&img_selector)
+                        .any(|img| img.value().attr("alt").is_some_and(|alt| !alt.trim().is_empty()));
+                    if text_content.trim().is_empty() && !has_image_name && !has_accessible_name(&element) {
+                        self.a11y_issues.push(A11yIssue { kind: A11yIssueKind::EmptyLink, element: describe_element(&element) });
+                    }
+                }
+                "label" => {
+                    if let Some(target) = element.value().attr("for") {
+                        label_targets.insert(target.to_string());
+                    }
+                }
+                _ if needs_label(&element) && !has_accessible_name(&element) => {
+                    // Wrapping <label> elements label their controls; `for` references are resolved after the pass
+                    let wrapped = element.ancestors().filter_map(ElementRef::wrap).any(|a| a.value().name() == "label");
+                    if !wrapped {
+                        unlabeled_inputs.push((element.value().id().map(str::to_string), describe_element(&element)));
+                    }
+                }
+                _ => {}
+            }
+
             // Remove the current tag from the stack after processing its children
             tag_stack.pop();
         }
+
+        // Report controls that no <label for> pointed at
+        for (id, element) in unlabeled_inputs {
+            if !id.is_some_and(|id| label_targets.contains(&id)) {
+                self.a11y_issues.push(A11yIssue { kind: A11yIssueKind::UnlabeledInput, element });
+            }
+        }
     }
 
     // Method to print the results of the HTML analysis
@@ -115,6 +219,11 @@ impl AnalysisResult {
 
         println!("\nTotal Text Content:");
         println!("{}", self.total_text_content);
+
+        println!("\nAccessibility Issues:");
+        for issue in &self.a11y_issues {
+            println!("{}", issue);
+        }
     }
 }
 
@@ -152,14 +261,93 @@ impl fmt::Display for AnalysisResult {
         writeln!(f, "\nTotal Text Content:")?;
         writeln!(f, "{}", self.total_text_content)?;
 
+        writeln!(f, "\nAccessibility Issues:")?;
+        for issue in &self.a11y_issues {
+            writeln!(f, "{}", issue)?;
+        }
+
         Ok(())
     }
 }
 
+// Define how one count moved between two analyses
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct CountChange {
+    old: usize,
+    new: usize,
+}
+
+// Define a struct describing how a page changed between two analyses, e.g. two crawls of the same URL
+// Maps are ordered so the JSON output is stable and easy to compare
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct AnalysisDiff {
+    added_tags: BTreeMap<String, usize>, // Tags only in the new analysis, with their count
+    removed_tags: BTreeMap<String, usize>, // Tags only in the old analysis, with their former count
+    changed_tag_counts: BTreeMap<String, CountChange>, // Tags in both whose count differs
+    added_attributes: BTreeMap<String, BTreeSet<String>>, // Per tag, attributes that started appearing on it
+    removed_attributes: BTreeMap<String, BTreeSet<String>>, // Per tag, attributes that no longer appear on it
+    text_length_delta: i64, // Change in the number of text characters
+}
+
+impl AnalysisDiff {
+    // Check whether the two analyses were equivalent as far as the diff can tell
+    fn is_empty(&self) -> bool {
+        *self == AnalysisDiff::default()
+    }
+}
+
+// Collect, per tag, the attributes seen in `from` but not in `other`
+fn attributes_missing_from(
+    from: &HashMap<String, HashMap<String, usize>>,
+    other: &HashMap<String, HashMap<String, usize>>,
+) -> BTreeMap<String, BTreeSet<String>> {
+    from.iter()
+        .filter_map(|(tag, attrs)| {
+            let missing: BTreeSet<String> = attrs
+                .keys()
+                .filter(|attr| !other.get(tag).is_some_and(|other_attrs| other_attrs.contains_key(*attr)))
+                .cloned()
+                .collect();
+            (!missing.is_empty()).then(|| (tag.clone(), missing))
+        })
+        .collect()
+}
+
+// Function to compare two analyses of the same page
+// Returns what was added, removed, or changed going from `old` to `new`
+fn diff(old: &AnalysisResult, new: &AnalysisResult) -> AnalysisDiff {
+    let mut result = AnalysisDiff::default();
+
+    for (tag, &new_count) in &new.tag_count {
+        match old.tag_count.get(tag) {
+            None => {
+                result.added_tags.insert(tag.clone(), new_count);
+            }
+            Some(&old_count) if old_count != new_count => {
+                result.changed_tag_counts.insert(tag.clone(), CountChange { old: old_count, new: new_count });
+            }
+            Some(_) => {}
+        }
+    }
+    for (tag, &old_count) in &old.tag_count {
+        if !new.tag_count.contains_key(tag) {
+            result.removed_tags.insert(tag.clone(), old_count);
+        }
+    }
+
+    result.added_attributes = attributes_missing_from(&new.attribute_per_tag, &old.attribute_per_tag);
+    result.removed_attributes = attributes_missing_from(&old.attribute_per_tag, &new.attribute_per_tag);
+    result.text_length_delta =
+        new.total_text_content.chars().count() as i64 - old.total_text_content.chars().count() as i64;
+    result
+}
+
 // Function to fetch HTML content from a URL
 // Takes a URL as a string and returns the HTML content as a String
-async fn fetch_html_from_url(url: &str) -> Result<String, reqwest::Error> {
-    let response = reqwest::get(url).await?; // Send HTTP GET request
+async fn fetch_html_from_url(url: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let config = http::HttpConfig::from_env();
+    let url = http::check_url_async(url, &config.fetch_policy).await?; // Refuse loopback, private, and non-http targets
+    let response = http::client_with(&config).get(url).send().await?; // Send HTTP GET request
     let html = response.text().await?; // Extract HTML text from the response
     Ok(html)
 }
@@ -190,6 +378,31 @@ async fn process_html_source(source_type: &str, source: &str) -> Result<String,
 // Main function to demonstrate the functionality of the analysis tool
 #[tokio::main]
 async fn main() {
+    // `hypertextanalysis diff <old.html> <new.html>` prints how the page changed as JSON
+    let args: Vec<String> = env::args().collect();
+    if let [_, command, old_path, new_path] = args.as_slice() {
+        if command == "diff" {
+            let mut analyses = Vec::new();
+            for path in [old_path, new_path] {
+                match read_html_from_file(path) {
+                    Ok(html) => {
+                        let mut analysis = AnalysisResult::new();
+                        analysis.analyze(&html);
+                        analyses.push(analysis);
+                    }
+                    Err(e) => {
+                        eprintln!("Error reading {}: {}", path, e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            let changes = diff(&analyses[0], &analyses[1]);
+            println!("{}", serde_json::to_string_pretty(&changes).expect("diff serializes to JSON"));
+            // Exit non-zero on changes so scripts can flag regressions
+            std::process::exit(if changes.is_empty() { 0 } else { 2 });
+        }
+    }
+
     // Example of analyzing HTML content from a string
     let html_string = "<html><head><title>Test</title></head><body><h1>Hello</h1><p id=\"para1\">World</p></body></html>";
     
@@ -231,4 +444,89 @@ async fn main() {
         }
         Err(e) => eprintln!("Error processing source: {}", e),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issues(html: &str) -> Vec<A11yIssue> {
+        let mut result = AnalysisResult::new();
+        result.analyze(html);
+        result.a11y_issues
+    }
+
+    #[test]
+    fn test_alt_less_image_and_unlabeled_input_are_reported() {
+        let found = issues(
+            "<html lang=\"en\"><body><img src=\"logo.png\"><img src=\"ok.png\" alt=\"\">\
+             <form><input id=\"email\" name=\"email\"><input type=\"submit\"></form></body></html>",
+        );
+        assert_eq!(
+            found,
+            vec![
+                A11yIssue { kind: A11yIssueKind::MissingAlt, element: "<img src=\"logo.png\">".to_string() },
+                A11yIssue { kind: A11yIssueKind::UnlabeledInput, element: "<input id=\"email\">".to_string() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_labeled_controls_and_named_links_pass() {
+        let found = issues(
+            "<html lang=\"en\"><body>\
+             <input id=\"q\"><label for=\"q\">Search</label>\
+             <label>Name <input name=\"name\"></label>\
+             <textarea aria-label=\"Comment\"></textarea>\
+             <a href=\"/\"><img src=\"home.png\" alt=\"Home\"></a><a href=\"/about\">About</a>\
+             </body></html>",
+        );
+        assert!(found.is_empty(), "{:?}", found);
+    }
+
+    fn analysis(html: &str) -> AnalysisResult {
+        let mut result = AnalysisResult::new();
+        result.analyze(html);
+        result
+    }
+
+    #[test]
+    fn test_diff_reports_added_script_and_removed_heading() {
+        let old = analysis("<html><body><h1>Welcome</h1><p class=\"lead\">Hi</p><p>There</p></body></html>");
+        let new = analysis(
+            "<html><body><p id=\"intro\">Hi</p><script src=\"/app.js\"></script></body></html>",
+        );
+        let changes = diff(&old, &new);
+
+        assert_eq!(changes.added_tags, BTreeMap::from([("script".to_string(), 1)]));
+        assert_eq!(changes.removed_tags, BTreeMap::from([("h1".to_string(), 1)]));
+        assert_eq!(changes.changed_tag_counts, BTreeMap::from([("p".to_string(), CountChange { old: 2, new: 1 })]));
+        assert_eq!(
+            changes.added_attributes,
+            BTreeMap::from([
+                ("p".to_string(), BTreeSet::from(["id".to_string()])),
+                ("script".to_string(), BTreeSet::from(["src".to_string()])),
+            ])
+        );
+        assert_eq!(changes.removed_attributes, BTreeMap::from([("p".to_string(), BTreeSet::from(["class".to_string()]))]));
+        assert!(changes.text_length_delta < 0);
+        assert!(!changes.is_empty());
+
+        let json = serde_json::to_value(&changes).unwrap();
+        assert_eq!(json["added_tags"]["script"], 1);
+        assert_eq!(json["removed_tags"]["h1"], 1);
+        assert_eq!(json["changed_tag_counts"]["p"], serde_json::json!({ "old": 2, "new": 1 }));
+    }
+
+    #[test]
+    fn test_diff_of_identical_pages_is_empty() {
+        let html = "<html lang=\"en\"><body><h1 id=\"t\">Same</h1></body></html>";
+        assert!(diff(&analysis(html), &analysis(html)).is_empty());
+    }
+
+    #[test]
+    fn test_missing_lang_and_empty_link() {
+        let kinds: Vec<_> = issues("<html><body><a href=\"/x\">  </a></body></html>").into_iter().map(|i| i.kind).collect();
+        assert_eq!(kinds, vec![A11yIssueKind::MissingLang, A11yIssueKind::EmptyLink]);
+    }
+}
@@ -1,4 +1,6 @@
 use regex::Regex;
+use serde::Serialize;
+use thiserror::Error;
 
 fn main() {
     let code = r#"
@@ -95,8 +97,39 @@ fn main() {
         const weakSet = new WeakSet();
     "#;
 
+    if let Err(errors) = validate(code) {
+        for error in errors {
+            eprintln!("Syntax error: {}", error);
+        }
+        std::process::exit(1);
+    }
+
     let compiled_code = compile_js(code);
     println!("{}", compiled_code);
+
+    let compile_options = CompileOptions {
+        optimize: std::env::args().any(|arg| arg == "--optimize"),
+        modules: std::env::args().any(|arg| arg == "--commonjs").then_some(ModuleFormat::CommonJs),
+    };
+    match compile(code, &compile_options) {
+        Ok(output) => println!("{}", output),
+        Err(e) => eprintln!("Failed to compile: {}", e),
+    }
+
+    let options = MinifyOptions {
+        source_map: true,
+        source_name: "input.js".to_string(),
+        source_map_url: Some("input.min.js.map".to_string()),
+    };
+    match minify_js(code, &options) {
+        Ok(output) => {
+            println!("{}", output.code);
+            if let Some(map) = output.source_map {
+                println!("{}", map.to_json());
+            }
+        }
+        Err(e) => eprintln!("Failed to minify: {}", e),
+    }
 }
 
 fn compile_js(code: &str) -> String {
@@ -111,7 +144,6 @@ fn compile_js(code: &str) -> String {
     let do_while_pattern = Regex::new(r"do\s*\{\s*([^}]*)\s*\}\s*while\s*\(([^)]*)\);").unwrap();
     let switch_pattern = Regex::new(r"switch\s*\(([^)]*)\)\s*\{\s*([^}]*)\s*\}").unwrap();
     let class_pattern = Regex::new(r"class\s+(\w+)\s*\{\s*(.*?)\s*\}").unwrap();
-    let comment_pattern = Regex::new(r"//.*").unwrap();
     let obj_pattern = Regex::new(r"\{[^}]*\}").unwrap();
     let arr_pattern = Regex::new(r"\[[^\]]*\]").unwrap();
     let arrow_function_pattern = Regex::new(r"(\w+)\s*=\s*\(([^)]*)\)\s*=>\s*\{([^}]*)\}").unwrap();
@@ -137,8 +169,14 @@ fn compile_js(code: &str) -> String {
     let symbol_liter_pattern = Regex::new(r"Symbol\s*\(\s*['\"][^'\"]*['\"]\s*\)").unwrap();
     let weak_map_weak_set_pattern = Regex::new(r"new\s+(WeakMap|WeakSet)\s*\(\)").unwrap();
 
-    // Remove comments
-    result = comment_pattern.replace_all(code, "").to_string();
+    // Remove comments using the tokenizer so `//` inside strings and templates survives
+    result = match strip_comments(code) {
+        Ok(stripped) => stripped,
+        Err(e) => {
+            eprintln!("Tokenizer error, leaving comments in place: {}", e);
+            code.to_string()
+        }
+    };
 
     // Replace variable declarations
     result = var_pattern.replace_all(&result, |caps: &regex::Captures| {
@@ -303,4 +341,2292 @@ fn compile_js(code: &str) -> String {
     }).to_string();
 
     result
-}
\ No newline at end of file
+}
+
+// Replace comment tokens in the source with a space, or a line break if they
+// spanned one, so the tokens around them stay apart and ASI is unchanged;
+// everything else is kept byte-for-byte
+fn strip_comments(code: &str) -> Result<String, LexError> {
+    let tokens = tokenize(code)?;
+    let mut output = String::with_capacity(code.len());
+    let mut last = 0;
+    for token in tokens.iter().filter(|t| t.kind == TokenKind::Comment) {
+        output.push_str(&code[last..token.start]);
+        output.push(if token.text.contains(is_line_terminator) { '\n' } else { ' ' });
+        last = token.end;
+    }
+    output.push_str(&code[last..]);
+    Ok(output)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MinifyOptions {
+    /// Build a source map for the minified output
+    pub source_map: bool,
+    /// Name recorded in the map's `sources`
+    pub source_name: String,
+    /// Appended as a `//# sourceMappingURL=` comment when set
+    pub source_map_url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinifyOutput {
+    pub code: String,
+    pub source_map: Option<SourceMap>,
+}
+
+// Source map v3; `mappings` holds Base64 VLQ segments
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceMap {
+    pub version: u8,
+    pub sources: Vec<String>,
+    pub names: Vec<String>,
+    pub mappings: String,
+}
+
+// One decoded mapping segment; lines and columns are 0-based
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    pub generated_line: usize,
+    pub generated_column: usize,
+    pub source: usize,
+    pub original_line: usize,
+    pub original_column: usize,
+    pub name: Option<usize>,
+}
+
+impl SourceMap {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("source maps always serialize")
+    }
+
+    // Decode `mappings` back into absolute positions
+    pub fn decode(&self) -> Option<Vec<Mapping>> {
+        let mut mappings = Vec::new();
+        let (mut source, mut original_line, mut original_column, mut name) = (0i64, 0i64, 0i64, 0i64);
+
+        for (generated_line, line) in self.mappings.split(';').enumerate() {
+            let mut generated_column = 0i64;
+            for segment in line.split(',').filter(|segment| !segment.is_empty()) {
+                let fields = decode_vlq(segment)?;
+                if fields.len() < 4 {
+                    continue; // Segments without a source position carry no mapping
+                }
+                generated_column += fields[0];
+                source += fields[1];
+                original_line += fields[2];
+                original_column += fields[3];
+                let name_index = fields.get(4).map(|delta| {
+                    name += delta;
+                    name as usize
+                });
+                mappings.push(Mapping {
+                    generated_line,
+                    generated_column: generated_column as usize,
+                    source: source as usize,
+                    original_line: original_line as usize,
+                    original_column: original_column as usize,
+                    name: name_index,
+                });
+            }
+        }
+        Some(mappings)
+    }
+
+    // Find the original position of the token starting at a generated position
+    pub fn lookup(&self, generated_line: usize, generated_column: usize) -> Option<Mapping> {
+        self.decode()?
+            .into_iter()
+            .find(|m| m.generated_line == generated_line && m.generated_column == generated_column)
+    }
+}
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Base64 VLQ: sign in the lowest bit, 5 data bits per digit, bit 6 marks continuation
+fn encode_vlq(value: i64, out: &mut String) {
+    let mut vlq = if value < 0 { ((-value) << 1) | 1 } else { value << 1 };
+    loop {
+        let mut digit = (vlq & 0b11111) as usize;
+        vlq >>= 5;
+        if vlq > 0 {
+            digit |= 0b100000;
+        }
+        out.push(BASE64_CHARS[digit] as char);
+        if vlq == 0 {
+            break;
+        }
+    }
+}
+
+fn decode_vlq(segment: &str) -> Option<Vec<i64>> {
+    let mut values = Vec::new();
+    let (mut value, mut shift) = (0i64, 0u32);
+    for c in segment.bytes() {
+        let digit = BASE64_CHARS.iter().position(|&b| b == c)? as i64;
+        value += (digit & 0b11111) << shift;
+        if digit & 0b100000 != 0 {
+            shift += 5;
+            continue;
+        }
+        values.push(if value & 1 == 1 { -(value >> 1) } else { value >> 1 });
+        value = 0;
+        shift = 0;
+    }
+    (shift == 0).then_some(values)
+}
+
+// Accumulates mapping segments as code is emitted, delta-encoding against the previous segment
+struct SourceMapBuilder {
+    names: Vec<String>,
+    mappings: String,
+    line: usize,
+    first_in_line: bool,
+    previous_generated_column: i64,
+    previous_original: (i64, i64),
+    previous_name: i64,
+}
+
+impl SourceMapBuilder {
+    fn new() -> Self {
+        SourceMapBuilder {
+            names: Vec::new(),
+            mappings: String::new(),
+            line: 0,
+            first_in_line: true,
+            previous_generated_column: 0,
+            previous_original: (0, 0),
+            previous_name: 0,
+        }
+    }
+
+    fn add(&mut self, generated: (usize, usize), original: (usize, usize), name: Option<&str>) {
+        while self.line < generated.0 {
+            self.mappings.push(';');
+            self.line += 1;
+            self.first_in_line = true;
+            self.previous_generated_column = 0;
+        }
+        if !self.first_in_line {
+            self.mappings.push(',');
+        }
+        self.first_in_line = false;
+
+        encode_vlq(generated.1 as i64 - self.previous_generated_column, &mut self.mappings);
+        encode_vlq(0, &mut self.mappings); // Single source
+        encode_vlq(original.0 as i64 - self.previous_original.0, &mut self.mappings);
+        encode_vlq(original.1 as i64 - self.previous_original.1, &mut self.mappings);
+        self.previous_generated_column = generated.1 as i64;
+        self.previous_original = (original.0 as i64, original.1 as i64);
+
+        if let Some(name) = name {
+            let index = match self.names.iter().position(|n| n == name) {
+                Some(index) => index,
+                None => {
+                    self.names.push(name.to_string());
+                    self.names.len() - 1
+                }
+            };
+            encode_vlq(index as i64 - self.previous_name, &mut self.mappings);
+            self.previous_name = index as i64;
+        }
+    }
+
+    fn finish(self, source_name: &str) -> SourceMap {
+        SourceMap {
+            version: 3,
+            sources: vec![source_name.to_string()],
+            names: self.names,
+            mappings: self.mappings,
+        }
+    }
+}
+
+// Whether a line break between `prev` and `next` may matter for automatic semicolon insertion
+fn newline_is_significant(prev: &JsToken, next: &JsToken) -> bool {
+    let prev_continues = prev.kind == TokenKind::Punctuator && !matches!(prev.text.as_str(), ")" | "]" | "}" | "++" | "--");
+    let next_continues = next.kind == TokenKind::Punctuator
+        && matches!(next.text.as_str(), ")" | "]" | "}" | "," | ";" | "." | "?." | ":" | "?");
+    !(prev_continues || next_continues)
+}
+
+// Whether `prev` and `next` would lex differently if written without a space
+fn needs_space(prev: &JsToken, next: &JsToken) -> bool {
+    let (Some(last), Some(first)) = (prev.text.chars().last(), next.text.chars().next()) else {
+        return false;
+    };
+    (is_ident_part(last) && (is_ident_part(first) || first == '#'))
+        || (prev.kind == TokenKind::Number && first == '.' && !prev.text.contains(['.', 'e', 'E', 'x', 'X']))
+        || (last == '+' && first == '+')
+        || (last == '-' && first == '-')
+        || (last == '/' && first == '/')
+}
+
+// Minify by dropping comments and redundant whitespace between tokens.
+// Line breaks are kept where automatic semicolon insertion could depend on them.
+pub fn minify_js(src: &str, options: &MinifyOptions) -> Result<MinifyOutput, LexError> {
+    let tokens: Vec<JsToken> = tokenize(src)?.into_iter().filter(|t| t.kind != TokenKind::Comment).collect();
+    let mut code = String::with_capacity(src.len() / 2);
+    let mut map = options.source_map.then(SourceMapBuilder::new);
+    let (mut line, mut column) = (0usize, 0usize);
+
+    for (i, token) in tokens.iter().enumerate() {
+        if let Some(prev) = i.checked_sub(1).map(|p| &tokens[p]) {
+            if token.newline_before && newline_is_significant(prev, token) {
+                code.push('\n');
+                line += 1;
+                column = 0;
+            } else if needs_space(prev, token) {
+                code.push(' ');
+                column += 1;
+            }
+        }
+
+        if let Some(map) = map.as_mut() {
+            let name = (token.kind == TokenKind::Identifier).then_some(token.text.as_str());
+            map.add((line, column), (token.line - 1, token.column - 1), name);
+        }
+
+        code.push_str(&token.text);
+        // Template literals can span lines
+        for c in token.text.chars() {
+            if c == '\n' {
+                line += 1;
+                column = 0;
+            } else {
+                column += 1;
+            }
+        }
+    }
+
+    if let Some(url) = &options.source_map_url {
+        code.push_str("\n//# sourceMappingURL=");
+        code.push_str(url);
+    }
+
+    Ok(MinifyOutput {
+        code,
+        source_map: map.map(|builder| builder.finish(&options.source_name)),
+    })
+}
+
+const KEYWORDS: &[&str] = &[
+    "async", "await", "break", "case", "catch", "class", "const", "continue", "debugger", "default",
+    "delete", "do", "else", "export", "extends", "false", "finally", "for", "function", "if",
+    "import", "in", "instanceof", "let", "new", "null", "return", "static", "super", "switch",
+    "this", "throw", "true", "try", "typeof", "var", "void", "while", "with", "yield",
+];
+
+// Longest punctuators first so the lexer always takes the maximal munch
+const PUNCTUATORS: &[&str] = &[
+    ">>>=", "...", "===", "!==", "**=", "<<=", ">>=", ">>>", "&&=", "||=", "??=", "=>", "==", "!=",
+    "<=", ">=", "&&", "||", "??", "?.", "++", "--", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=",
+    "**", "<<", ">>", "{", "}", "(", ")", "[", "]", ";", ",", "<", ">", "+", "-", "*", "/", "%",
+    "&", "|", "^", "!", "~", "?", ":", "=", ".", "@",
+];
+
+// Which piece of a template literal a token covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplatePart {
+    /// `` `text` `` with no substitutions
+    Full,
+    /// `` `text${ ``
+    Head,
+    /// `` }text${ ``
+    Middle,
+    /// `` }text` ``
+    Tail,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Identifier,
+    Keyword,
+    Number,
+    String,
+    Template(TemplatePart),
+    Regex,
+    Punctuator,
+    Comment,
+}
+
+// A lexed token with its raw source text and position
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsToken {
+    pub kind: TokenKind,
+    pub text: String,
+    /// Byte offsets into the source
+    pub start: usize,
+    pub end: usize,
+    /// 1-based line and column of the first character
+    pub line: usize,
+    pub column: usize,
+    /// Whether a line terminator precedes this token (needed for ASI)
+    pub newline_before: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum LexError {
+    #[error("Unterminated string literal at {line}:{column}")]
+    UnterminatedString { line: usize, column: usize },
+    #[error("Unterminated template literal at {line}:{column}")]
+    UnterminatedTemplate { line: usize, column: usize },
+    #[error("Unterminated comment at {line}:{column}")]
+    UnterminatedComment { line: usize, column: usize },
+    #[error("Unterminated regular expression at {line}:{column}")]
+    UnterminatedRegex { line: usize, column: usize },
+    #[error("Unexpected character '{ch}' at {line}:{column}")]
+    UnexpectedChar { ch: char, line: usize, column: usize },
+}
+
+// Tokenize JavaScript source into a flat token list, including comments
+pub fn tokenize(src: &str) -> Result<Vec<JsToken>, LexError> {
+    Lexer::new(src).run()
+}
+
+struct Lexer<'a> {
+    src: &'a str,
+    chars: Vec<(usize, char)>,
+    pos: usize,
+    line: usize,
+    column: usize,
+    tokens: Vec<JsToken>,
+    newline_before: bool,
+    brace_depth: usize,
+    // Brace depth at which each open `${` substitution started
+    template_stack: Vec<usize>,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a str) -> Self {
+        Lexer {
+            src,
+            chars: src.char_indices().collect(),
+            pos: 0,
+            line: 1,
+            column: 1,
+            tokens: Vec::new(),
+            newline_before: false,
+            brace_depth: 0,
+            template_stack: Vec::new(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).map(|&(_, c)| c)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).map(|&(_, c)| c)
+    }
+
+    fn offset(&self) -> usize {
+        self.chars.get(self.pos).map_or(self.src.len(), |&(i, _)| i)
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn run(mut self) -> Result<Vec<JsToken>, LexError> {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                if is_line_terminator(c) {
+                    self.newline_before = true;
+                }
+                self.advance();
+                continue;
+            }
+
+            let (start, line, column) = (self.offset(), self.line, self.column);
+            let kind = match c {
+                '/' if self.peek_at(1) == Some('/') => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.advance();
+                    }
+                    TokenKind::Comment
+                }
+                '/' if self.peek_at(1) == Some('*') => {
+                    self.advance();
+                    self.advance();
+                    loop {
+                        match self.advance() {
+                            None => return Err(LexError::UnterminatedComment { line, column }),
+                            Some(c) if is_line_terminator(c) => self.newline_before = true,
+                            Some('*') if self.peek() == Some('/') => {
+                                self.advance();
+                                break;
+                            }
+                            Some(_) => {}
+                        }
+                    }
+                    TokenKind::Comment
+                }
+                '/' if self.regex_allowed() => self.lex_regex(line, column)?,
+                '"' | '\'' => self.lex_string(c, line, column)?,
+                '`' => {
+                    self.advance();
+                    self.lex_template(true, line, column)?
+                }
+                '}' if self.template_stack.last() == Some(&self.brace_depth) => {
+                    self.template_stack.pop();
+                    self.advance();
+                    self.lex_template(false, line, column)?
+                }
+                c if c.is_ascii_digit() || (c == '.' && self.peek_at(1).is_some_and(|n| n.is_ascii_digit())) => {
+                    self.lex_number();
+                    TokenKind::Number
+                }
+                c if is_ident_start(c) => {
+                    self.advance();
+                    while self.peek().is_some_and(is_ident_part) {
+                        self.advance();
+                    }
+                    if KEYWORDS.contains(&&self.src[start..self.offset()]) {
+                        TokenKind::Keyword
+                    } else {
+                        TokenKind::Identifier
+                    }
+                }
+                _ => self.lex_punctuator(c, line, column)?,
+            };
+
+            let end = self.offset();
+            // A line break before or inside a comment still precedes the next real token
+            let newline_before = if kind == TokenKind::Comment {
+                self.newline_before
+            } else {
+                std::mem::take(&mut self.newline_before)
+            };
+            self.tokens.push(JsToken {
+                kind,
+                text: self.src[start..end].to_string(),
+                start,
+                end,
+                line,
+                column,
+                newline_before,
+            });
+        }
+
+        if !self.template_stack.is_empty() {
+            return Err(LexError::UnterminatedTemplate { line: self.line, column: self.column });
+        }
+
+        Ok(self.tokens)
+    }
+
+    // A `/` starts a regex unless the previous token could end an expression
+    fn regex_allowed(&self) -> bool {
+        let previous = self.tokens.iter().rev().find(|t| t.kind != TokenKind::Comment);
+        match previous {
+            None => true,
+            Some(token) => match token.kind {
+                TokenKind::Identifier | TokenKind::Number | TokenKind::String | TokenKind::Regex => false,
+                TokenKind::Template(TemplatePart::Full) | TokenKind::Template(TemplatePart::Tail) => false,
+                TokenKind::Template(_) => true,
+                TokenKind::Keyword => !matches!(token.text.as_str(), "this" | "super" | "null" | "true" | "false"),
+                TokenKind::Punctuator => !matches!(token.text.as_str(), ")" | "]" | "}" | "++" | "--"),
+                TokenKind::Comment => true,
+            },
+        }
+    }
+
+    fn lex_string(&mut self, quote: char, line: usize, column: usize) -> Result<TokenKind, LexError> {
+        self.advance();
+        loop {
+            match self.advance() {
+                None | Some('\n') => return Err(LexError::UnterminatedString { line, column }),
+                Some('\\') => {
+                    // Escapes, including line continuations
+                    if self.advance().is_none() {
+                        return Err(LexError::UnterminatedString { line, column });
+                    }
+                }
+                Some(c) if c == quote => return Ok(TokenKind::String),
+                Some(_) => {}
+            }
+        }
+    }
+
+    // Scan template text up to the closing backtick or the next `${`
+    fn lex_template(&mut self, opened_with_backtick: bool, line: usize, column: usize) -> Result<TokenKind, LexError> {
+        loop {
+            match self.advance() {
+                None => return Err(LexError::UnterminatedTemplate { line, column }),
+                Some('\\') => {
+                    if self.advance().is_none() {
+                        return Err(LexError::UnterminatedTemplate { line, column });
+                    }
+                }
+                Some('`') => {
+                    let part = if opened_with_backtick { TemplatePart::Full } else { TemplatePart::Tail };
+                    return Ok(TokenKind::Template(part));
+                }
+                Some('$') if self.peek() == Some('{') => {
+                    self.advance();
+                    self.template_stack.push(self.brace_depth);
+                    let part = if opened_with_backtick { TemplatePart::Head } else { TemplatePart::Middle };
+                    return Ok(TokenKind::Template(part));
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    fn lex_regex(&mut self, line: usize, column: usize) -> Result<TokenKind, LexError> {
+        self.advance();
+        let mut in_class = false;
+        loop {
+            match self.advance() {
+                None | Some('\n') => return Err(LexError::UnterminatedRegex { line, column }),
+                Some('\\') => {
+                    if matches!(self.advance(), None | Some('\n')) {
+                        return Err(LexError::UnterminatedRegex { line, column });
+                    }
+                }
+                Some('[') => in_class = true,
+                Some(']') => in_class = false,
+                Some('/') if !in_class => break,
+                Some(_) => {}
+            }
+        }
+        // Flags
+        while self.peek().is_some_and(is_ident_part) {
+            self.advance();
+        }
+        Ok(TokenKind::Regex)
+    }
+
+    fn lex_number(&mut self) {
+        let radix_prefix = self.peek() == Some('0')
+            && matches!(self.peek_at(1), Some('x' | 'X' | 'b' | 'B' | 'o' | 'O'));
+        if radix_prefix {
+            self.advance();
+            self.advance();
+            while self.peek().is_some_and(|c| c.is_ascii_hexdigit() || c == '_') {
+                self.advance();
+            }
+        } else {
+            while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '_') {
+                self.advance();
+            }
+            if self.peek() == Some('.') {
+                self.advance();
+                while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '_') {
+                    self.advance();
+                }
+            }
+            if matches!(self.peek(), Some('e' | 'E')) {
+                let sign = matches!(self.peek_at(1), Some('+' | '-'));
+                let digit_at = if sign { 2 } else { 1 };
+                if self.peek_at(digit_at).is_some_and(|c| c.is_ascii_digit()) {
+                    for _ in 0..digit_at {
+                        self.advance();
+                    }
+                    while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '_') {
+                        self.advance();
+                    }
+                }
+            }
+        }
+        // BigInt suffix
+        if self.peek() == Some('n') {
+            self.advance();
+        }
+    }
+
+    fn lex_punctuator(&mut self, c: char, line: usize, column: usize) -> Result<TokenKind, LexError> {
+        let rest = &self.src[self.offset()..];
+        let punctuator = PUNCTUATORS
+            .iter()
+            .find(|p| rest.starts_with(*p))
+            // `a?.5:b` is a conditional, not optional chaining
+            .filter(|p| !(**p == "?." && rest[2..].starts_with(|n: char| n.is_ascii_digit())))
+            .copied()
+            .or((c == '?').then_some("?"));
+
+        match punctuator {
+            Some(p) => {
+                for _ in 0..p.chars().count() {
+                    self.advance();
+                }
+                match p {
+                    "{" => self.brace_depth += 1,
+                    "}" => self.brace_depth = self.brace_depth.saturating_sub(1),
+                    _ => {}
+                }
+                Ok(TokenKind::Punctuator)
+            }
+            None => Err(LexError::UnexpectedChar { ch: c, line, column }),
+        }
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c == '$' || c == '_' || c == '#' || c.is_alphabetic()
+}
+
+fn is_ident_part(c: char) -> bool {
+    c == '$' || c == '_' || c.is_alphanumeric() || c == '\u{200C}' || c == '\u{200D}'
+}
+
+fn is_line_terminator(c: char) -> bool {
+    matches!(c, '\n' | '\r' | '\u{2028}' | '\u{2029}')
+}
+
+// A problem found by `validate`, with the 1-based position it was found at
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{message} at {line}:{column}")]
+pub struct SyntaxError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl SyntaxError {
+    fn at(token: &JsToken, message: String) -> Self {
+        SyntaxError { message, line: token.line, column: token.column }
+    }
+}
+
+impl From<LexError> for SyntaxError {
+    fn from(error: LexError) -> Self {
+        let (line, column) = match error {
+            LexError::UnterminatedString { line, column }
+            | LexError::UnterminatedTemplate { line, column }
+            | LexError::UnterminatedComment { line, column }
+            | LexError::UnterminatedRegex { line, column }
+            | LexError::UnexpectedChar { line, column, .. } => (line, column),
+        };
+        // The position is carried separately, so keep only the description
+        let message = error.to_string();
+        let message = message.rsplit_once(" at ").map_or(message.as_str(), |(text, _)| text).to_string();
+        SyntaxError { message, line, column }
+    }
+}
+
+// Operators that need an operand on both sides
+const BINARY_ONLY: &[&str] = &[
+    "=", "+=", "-=", "*=", "/=", "%=", "**=", "<<=", ">>=", ">>>=", "&=", "|=", "^=", "&&=", "||=", "??=",
+    "==", "!=", "===", "!==", "<", ">", "<=", ">=", "*", "/", "%", "**", "<<", ">>", ">>>", "&", "|", "^",
+    "&&", "||", "??", ".", "?.", "=>",
+];
+
+// Identifiers that act as keywords in some positions, so may sit next to another operand
+const CONTEXTUAL_KEYWORDS: &[&str] = &["of", "as", "from", "get", "set", "let", "yield", "await"];
+
+// Whether `token` is a complete operand on its own
+fn is_operand(token: &JsToken) -> bool {
+    match token.kind {
+        TokenKind::Identifier => !CONTEXTUAL_KEYWORDS.contains(&token.text.as_str()),
+        TokenKind::Number | TokenKind::String | TokenKind::Regex => true,
+        TokenKind::Keyword => matches!(token.text.as_str(), "this" | "null" | "true" | "false" | "super"),
+        _ => false,
+    }
+}
+
+// Check `src` for the mistakes that would otherwise pass through the compiler
+// as garbage: unterminated literals and comments, unbalanced brackets, and
+// tokens that cannot follow each other. Reports every problem found, in
+// source order; lexing stops at the first unterminated literal.
+pub fn validate(src: &str) -> Result<(), Vec<SyntaxError>> {
+    let tokens: Vec<JsToken> = match tokenize(src) {
+        Ok(tokens) => tokens.into_iter().filter(|t| t.kind != TokenKind::Comment).collect(),
+        Err(e) => return Err(vec![e.into()]),
+    };
+    let mut errors = Vec::new();
+
+    // Open brackets, with `${` standing in for template substitutions
+    let mut open: Vec<(&str, &JsToken)> = Vec::new();
+    for token in &tokens {
+        let (closes, opens) = match (token.kind, token.text.as_str()) {
+            (TokenKind::Punctuator, "(" | "[" | "{") => (None, Some(token.text.as_str())),
+            (TokenKind::Punctuator, ")") => (Some("("), None),
+            (TokenKind::Punctuator, "]") => (Some("["), None),
+            (TokenKind::Punctuator, "}") => (Some("{"), None),
+            (TokenKind::Template(TemplatePart::Head), _) => (None, Some("${")),
+            (TokenKind::Template(TemplatePart::Middle), _) => (Some("${"), Some("${")),
+            (TokenKind::Template(TemplatePart::Tail), _) => (Some("${"), None),
+            _ => (None, None),
+        };
+        if let Some(opener) = closes {
+            match open.iter().rposition(|(text, _)| *text == opener) {
+                // Anything opened since the match was never closed
+                Some(index) => {
+                    for (text, unclosed) in open.drain(index..).skip(1) {
+                        errors.push(SyntaxError::at(unclosed, format!("Unclosed '{}'", text)));
+                    }
+                }
+                None => errors.push(SyntaxError::at(token, format!("Unmatched '{}'", token.text))),
+            }
+        }
+        if let Some(opener) = opens {
+            open.push((opener, token));
+        }
+    }
+    for (text, unclosed) in open {
+        errors.push(SyntaxError::at(unclosed, format!("Unclosed '{}'", text)));
+    }
+
+    for (i, token) in tokens.iter().enumerate() {
+        let next = tokens.get(i + 1);
+        if token.kind == TokenKind::Punctuator {
+            match next {
+                // Unclosed brackets are already reported above
+                None if !matches!(token.text.as_str(), ")" | "]" | "}" | ";" | "++" | "--" | "(" | "[" | "{") => {
+                    errors.push(SyntaxError::at(token, format!("Unexpected end of input after '{}'", token.text)));
+                }
+                Some(next)
+                    if BINARY_ONLY.contains(&token.text.as_str())
+                        && next.kind == TokenKind::Punctuator
+                        && matches!(next.text.as_str(), ")" | "]" | "}" | ";" | ",") =>
+                {
+                    errors.push(SyntaxError::at(next, format!("Unexpected '{}'", next.text)));
+                }
+                _ => {}
+            }
+        }
+        // Two operands on one line, like `let x = 1 2`
+        if let Some(next) = next.filter(|next| !next.newline_before && is_operand(token) && is_operand(next)) {
+            errors.push(SyntaxError::at(next, format!("Unexpected '{}'", next.text)));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        errors.sort_by_key(|e| (e.line, e.column));
+        Err(errors)
+    }
+}
+
+// The statements `optimize` reasons about; everything else is kept verbatim as `Raw`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    /// `var`, `let`, or `const` binding a single identifier
+    VarDecl { kind: String, name: String, init: Option<Expr> },
+    Expr(Expr),
+    If { test: Expr, consequent: Box<Stmt>, alternate: Option<Box<Stmt>> },
+    Block(Vec<Stmt>),
+    Return(Option<Expr>),
+    /// `function name(params) { body }`; `header` is the source text before the body
+    Function { header: String, body: Vec<Stmt> },
+    Empty,
+    Import(Import),
+    Export(Export),
+    /// Source text of a statement the parser does not model, terminator included
+    Raw(String),
+}
+
+/// `import ... from "source"`; a bare `import "source"` has no bindings
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Import {
+    /// Raw string literal, quotes included
+    pub source: String,
+    pub default: Option<String>,
+    /// `* as namespace`
+    pub namespace: Option<String>,
+    /// `{ imported as local }` pairs, in order
+    pub named: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Export {
+    /// `export const ...`, `export function ...`, or `export class ...`, with the names it binds
+    Declaration { decl: Box<Stmt>, names: Vec<String> },
+    /// `export default function name() {}` or `export default class name {}`
+    DefaultDeclaration { decl: Box<Stmt>, name: String },
+    /// Source text of the expression in `export default expr`
+    Default(String),
+    /// `{ local as exported }` pairs, re-exported from `source` when it is set
+    Named { specifiers: Vec<(String, String)>, source: Option<String> },
+    /// `export * from source`, or `export * as alias from source`
+    All { source: String, alias: Option<String> },
+}
+
+/// What `transpile_modules` writes imports and exports as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleFormat {
+    /// `require` and `exports`
+    CommonJs,
+    /// `import` and `export`
+    Esm,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    /// Raw literal text, quotes included
+    Str(String),
+    Bool(bool),
+    Null,
+    Ident(String),
+    Unary { op: String, arg: Box<Expr> },
+    Binary { op: String, left: Box<Expr>, right: Box<Expr> },
+    /// Source text of an expression the parser does not model
+    Raw(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Ast {
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ParseError {
+    #[error(transparent)]
+    Lex(#[from] LexError),
+    #[error("Unexpected '{text}' at {line}:{column}")]
+    UnexpectedToken { text: String, line: usize, column: usize },
+    #[error("Unexpected end of input")]
+    UnexpectedEnd,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    /// Fold constants and drop dead code between parsing and emitting
+    pub optimize: bool,
+    /// Rewrite imports and exports into this format instead of keeping them as written
+    pub modules: Option<ModuleFormat>,
+}
+
+// Parse, optionally optimize, and re-emit `src`
+pub fn compile(src: &str, options: &CompileOptions) -> Result<String, ParseError> {
+    let mut ast = parse(src)?;
+    if options.optimize {
+        optimize(&mut ast);
+    }
+    Ok(match options.modules {
+        Some(target) => transpile_modules(&ast, target),
+        None => emit(&ast),
+    })
+}
+
+// Parse a script into statements. Syntax the AST does not model is not an
+// error: it is carried through as `Raw` source text.
+pub fn parse(src: &str) -> Result<Ast, ParseError> {
+    let tokens = tokenize(src)?.into_iter().filter(|t| t.kind != TokenKind::Comment).collect();
+    let mut parser = Parser { src, tokens, pos: 0 };
+    let body = parser.statements(false)?;
+    Ok(Ast { body })
+}
+
+// Binding power of a binary operator, or `None` for operators the AST does not model
+fn binary_precedence(op: &str) -> Option<u8> {
+    Some(match op {
+        "??" => 1,
+        "||" => 2,
+        "&&" => 3,
+        "|" => 4,
+        "^" => 5,
+        "&" => 6,
+        "==" | "!=" | "===" | "!==" => 7,
+        "<" | ">" | "<=" | ">=" => 8,
+        "<<" | ">>" | ">>>" => 9,
+        "+" | "-" => 10,
+        "*" | "/" | "%" => 11,
+        "**" => 12,
+        _ => return None,
+    })
+}
+
+const UNARY_PRECEDENCE: u8 = 13;
+const PRIMARY_PRECEDENCE: u8 = 14;
+
+// Decimal, hex, octal, and binary literals; BigInt and legacy octal are left alone
+fn parse_number(text: &str) -> Option<f64> {
+    let text = text.replace('_', "");
+    let radix = match text.get(..2).map(|p| p.to_ascii_lowercase()) {
+        Some(p) if p == "0x" => 16,
+        Some(p) if p == "0o" => 8,
+        Some(p) if p == "0b" => 2,
+        _ => {
+            let legacy_octal = text.len() > 1 && text.starts_with('0') && text.as_bytes()[1].is_ascii_digit();
+            return if legacy_octal || text.ends_with('n') { None } else { text.parse().ok() };
+        }
+    };
+    u64::from_str_radix(&text[2..], radix).ok().map(|v| v as f64)
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<JsToken>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&JsToken> {
+        self.tokens.get(self.pos)
+    }
+
+    // Whether the next token is the punctuator or keyword `text`
+    fn is(&self, text: &str) -> bool {
+        self.peek()
+            .is_some_and(|t| t.text == text && matches!(t.kind, TokenKind::Punctuator | TokenKind::Keyword))
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        let found = self.is(text);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), ParseError> {
+        if self.eat(text) {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(t) => ParseError::UnexpectedToken { text: t.text.clone(), line: t.line, column: t.column },
+            None => ParseError::UnexpectedEnd,
+        }
+    }
+
+    // Source text covering tokens `start..end`
+    fn slice(&self, start: usize, end: usize) -> String {
+        self.src[self.tokens[start].start..self.tokens[end - 1].end].to_string()
+    }
+
+    // Whether the statement being parsed can end before the next token
+    fn at_statement_end(&self) -> bool {
+        match self.peek() {
+            None => true,
+            Some(t) if t.kind == TokenKind::Punctuator => t.text == ";" || t.text == "}",
+            Some(t) => t.newline_before && !matches!(t.kind, TokenKind::Template(_) | TokenKind::Regex),
+        }
+    }
+
+    // Statements up to the closing `}` of a block (left unconsumed) or the end of input
+    fn statements(&mut self, in_block: bool) -> Result<Vec<Stmt>, ParseError> {
+        let mut body = Vec::new();
+        while let Some(token) = self.peek() {
+            if token.kind == TokenKind::Punctuator && token.text == "}" {
+                return if in_block { Ok(body) } else { Err(self.unexpected()) };
+            }
+            body.push(self.statement()?);
+        }
+        if in_block {
+            Err(ParseError::UnexpectedEnd)
+        } else {
+            Ok(body)
+        }
+    }
+
+    fn statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.pos;
+        let Some(token) = self.peek().cloned() else {
+            return Err(ParseError::UnexpectedEnd);
+        };
+        let parsed = match (token.kind, token.text.as_str()) {
+            (TokenKind::Punctuator, ";") => {
+                self.pos += 1;
+                Some(Stmt::Empty)
+            }
+            (TokenKind::Punctuator, "{") => {
+                self.pos += 1;
+                let body = self.statements(true)?;
+                self.expect("}")?;
+                Some(Stmt::Block(body))
+            }
+            (TokenKind::Keyword, "if") => Some(self.if_statement()?),
+            (TokenKind::Keyword, "return") => Some(self.return_statement()?),
+            (TokenKind::Keyword, "var" | "let" | "const") => self.var_declaration(),
+            (TokenKind::Keyword, "function" | "async") => self.function_declaration()?,
+            // Not `import(...)` or `import.meta`
+            (TokenKind::Keyword, "import") if !self.tokens.get(self.pos + 1).is_some_and(|t| t.text == "(" || t.text == ".") => {
+                self.import_declaration()
+            }
+            (TokenKind::Keyword, "export") => self.export_declaration()?,
+            _ => self.expression().filter(|_| self.at_statement_end()).map(|expr| {
+                self.eat(";");
+                Stmt::Expr(expr)
+            }),
+        };
+        match parsed {
+            Some(stmt) => Ok(stmt),
+            None => {
+                self.pos = start;
+                self.raw_statement()
+            }
+        }
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.pos += 1;
+        self.expect("(")?;
+        let start = self.pos;
+        let test = match self.expression() {
+            Some(expr) if self.is(")") => expr,
+            _ => {
+                self.pos = start;
+                self.skip_balanced_until(")")?;
+                Expr::Raw(self.slice(start, self.pos))
+            }
+        };
+        self.expect(")")?;
+        let consequent = Box::new(self.statement()?);
+        let alternate = if self.eat("else") { Some(Box::new(self.statement()?)) } else { None };
+        Ok(Stmt::If { test, consequent, alternate })
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.pos += 1;
+        // A line break right after `return` ends the statement
+        let bare = self.peek().is_none_or(|t| t.newline_before) || self.is(";") || self.is("}");
+        let arg = if bare {
+            None
+        } else {
+            let start = self.pos;
+            match self.expression() {
+                Some(expr) if self.at_statement_end() => Some(expr),
+                _ => {
+                    self.pos = start;
+                    self.skip_to_statement_end()?;
+                    Some(Expr::Raw(self.slice(start, self.pos)))
+                }
+            }
+        };
+        self.eat(";");
+        Ok(Stmt::Return(arg))
+    }
+
+    // Only the single-binding form with a modeled initializer; the caller falls back to `Raw`
+    fn var_declaration(&mut self) -> Option<Stmt> {
+        let kind = self.peek()?.text.clone();
+        self.pos += 1;
+        let name = self.peek().filter(|t| t.kind == TokenKind::Identifier)?.text.clone();
+        self.pos += 1;
+        let init = if self.eat("=") { Some(self.expression()?) } else { None };
+        if !self.at_statement_end() {
+            return None;
+        }
+        self.eat(";");
+        Some(Stmt::VarDecl { kind, name, init })
+    }
+
+    fn function_declaration(&mut self) -> Result<Option<Stmt>, ParseError> {
+        let start = self.pos;
+        self.eat("async");
+        if !self.eat("function") {
+            return Ok(None);
+        }
+        self.eat("*");
+        if !self.peek().is_some_and(|t| t.kind == TokenKind::Identifier) {
+            return Ok(None);
+        }
+        self.pos += 1;
+        self.expect("(")?;
+        self.skip_balanced_until(")")?;
+        self.expect(")")?;
+        if !self.is("{") {
+            return Ok(None);
+        }
+        let header = self.slice(start, self.pos);
+        self.pos += 1;
+        let body = self.statements(true)?;
+        self.expect("}")?;
+        Ok(Some(Stmt::Function { header, body }))
+    }
+
+    fn import_declaration(&mut self) -> Option<Stmt> {
+        self.pos += 1;
+        let mut import = Import::default();
+        if self.peek()?.kind != TokenKind::String {
+            import.default = self.identifier();
+            if import.default.is_none() || self.eat(",") {
+                if self.eat("*") {
+                    self.eat_contextual("as").then_some(())?;
+                    import.namespace = Some(self.identifier()?);
+                } else if self.eat("{") {
+                    import.named = self.specifiers()?;
+                } else {
+                    return None;
+                }
+            }
+            self.eat_contextual("from").then_some(())?;
+        }
+        import.source = self.string()?;
+        if !self.at_statement_end() {
+            return None;
+        }
+        self.eat(";");
+        Some(Stmt::Import(import))
+    }
+
+    fn export_declaration(&mut self) -> Result<Option<Stmt>, ParseError> {
+        self.pos += 1;
+        let start = self.pos;
+        if self.is("var") || self.is("let") || self.is("const") || self.is("function") || self.is("async") || self.is("class") {
+            let decl = Box::new(self.statement()?);
+            return Ok(self.declared_names(start).map(|names| Stmt::Export(Export::Declaration { decl, names })));
+        }
+
+        let export = if self.eat("default") {
+            let start = self.pos;
+            if self.is("function") || self.is("async") || self.is("class") {
+                let decl = Box::new(self.statement()?);
+                if let Some([name]) = self.declared_names(start).as_deref() {
+                    return Ok(Some(Stmt::Export(Export::DefaultDeclaration { decl, name: name.clone() })));
+                }
+                // Anonymous, so an expression after all
+                self.pos = start;
+            }
+            self.skip_to_statement_end()?;
+            Export::Default(self.slice(start, self.pos))
+        } else if self.eat("*") {
+            let alias = if self.eat_contextual("as") { self.name() } else { None };
+            let source = self.eat_contextual("from").then(|| self.string()).flatten();
+            let Some(source) = source else {
+                return Ok(None);
+            };
+            Export::All { source, alias }
+        } else if self.eat("{") {
+            let Some(specifiers) = self.specifiers() else {
+                return Ok(None);
+            };
+            let source = if self.eat_contextual("from") {
+                match self.string() {
+                    Some(source) => Some(source),
+                    None => return Ok(None),
+                }
+            } else {
+                None
+            };
+            Export::Named { specifiers, source }
+        } else {
+            return Ok(None);
+        };
+        if !self.at_statement_end() {
+            return Ok(None);
+        }
+        self.eat(";");
+        Ok(Some(Stmt::Export(export)))
+    }
+
+    // `name` or `name as alias` pairs up to and including the closing `}`
+    fn specifiers(&mut self) -> Option<Vec<(String, String)>> {
+        let mut specifiers = Vec::new();
+        while !self.eat("}") {
+            let name = self.name()?;
+            let alias = if self.eat_contextual("as") { self.name()? } else { name.clone() };
+            specifiers.push((name, alias));
+            if !self.eat(",") && !self.is("}") {
+                return None;
+            }
+        }
+        Some(specifiers)
+    }
+
+    // The names bound by the declaration in tokens `start..pos`, or `None`
+    // for destructuring patterns and anything else not worth tracking
+    fn declared_names(&self, start: usize) -> Option<Vec<String>> {
+        let tokens = &self.tokens[start..self.pos];
+        let is_name = |t: &&JsToken| t.kind == TokenKind::Identifier;
+        match tokens.first()?.text.as_str() {
+            "var" | "let" | "const" => {
+                let mut names = Vec::new();
+                let (mut depth, mut expect_name) = (0usize, true);
+                for token in &tokens[1..] {
+                    if depth == 0 && expect_name {
+                        names.push(Some(token).filter(is_name)?.text.clone());
+                        expect_name = false;
+                        continue;
+                    }
+                    if token.kind != TokenKind::Punctuator {
+                        continue;
+                    }
+                    match token.text.as_str() {
+                        "(" | "[" | "{" => depth += 1,
+                        ")" | "]" | "}" => depth = depth.checked_sub(1)?,
+                        "," if depth == 0 => expect_name = true,
+                        _ => {}
+                    }
+                }
+                (!expect_name).then_some(names)
+            }
+            "async" | "function" | "class" => {
+                let name = tokens.iter().skip(1).find(|t| !matches!(t.text.as_str(), "function" | "*"))?;
+                Some(vec![Some(name).filter(is_name)?.text.clone()])
+            }
+            _ => None,
+        }
+    }
+
+    fn identifier(&mut self) -> Option<String> {
+        let name = self.peek().filter(|t| t.kind == TokenKind::Identifier)?.text.clone();
+        self.pos += 1;
+        Some(name)
+    }
+
+    // An identifier or keyword, as allowed in import and export specifiers
+    fn name(&mut self) -> Option<String> {
+        let name = self.peek().filter(|t| matches!(t.kind, TokenKind::Identifier | TokenKind::Keyword))?.text.clone();
+        self.pos += 1;
+        Some(name)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let text = self.peek().filter(|t| t.kind == TokenKind::String)?.text.clone();
+        self.pos += 1;
+        Some(text)
+    }
+
+    // Consume the identifier `word` (`as`, `from`), which the lexer does not treat as a keyword
+    fn eat_contextual(&mut self, word: &str) -> bool {
+        let found = self.peek().is_some_and(|t| t.kind == TokenKind::Identifier && t.text == word);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn raw_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.pos;
+        self.skip_to_statement_end()?;
+        self.eat(";");
+        Ok(Stmt::Raw(self.slice(start, self.pos)))
+    }
+
+    // Advance to the `;` or `}` ending the current statement, or past the
+    // last token before a line break where ASI would end it
+    fn skip_to_statement_end(&mut self) -> Result<(), ParseError> {
+        let start = self.pos;
+        let mut depth = 0usize;
+        while let Some(token) = self.peek() {
+            if depth == 0 && self.pos > start {
+                let ends = token.kind == TokenKind::Punctuator && matches!(token.text.as_str(), ";" | "}");
+                if ends || (token.newline_before && newline_is_significant(&self.tokens[self.pos - 1], token)) {
+                    break;
+                }
+            }
+            self.step_nesting(&mut depth)?;
+        }
+        if depth > 0 {
+            return Err(ParseError::UnexpectedEnd);
+        }
+        if self.pos == start {
+            return Err(self.unexpected());
+        }
+        Ok(())
+    }
+
+    // Advance to the unnested `close`, leaving it unconsumed
+    fn skip_balanced_until(&mut self, close: &str) -> Result<(), ParseError> {
+        let mut depth = 0usize;
+        while let Some(token) = self.peek() {
+            if depth == 0 && token.kind == TokenKind::Punctuator && token.text == close {
+                return Ok(());
+            }
+            self.step_nesting(&mut depth)?;
+        }
+        Err(ParseError::UnexpectedEnd)
+    }
+
+    // Consume one token, tracking bracket nesting
+    fn step_nesting(&mut self, depth: &mut usize) -> Result<(), ParseError> {
+        let token = &self.tokens[self.pos];
+        if token.kind == TokenKind::Punctuator {
+            match token.text.as_str() {
+                "(" | "[" | "{" => *depth += 1,
+                ")" | "]" | "}" => *depth = depth.checked_sub(1).ok_or_else(|| self.unexpected())?,
+                _ => {}
+            }
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    // Precedence climbing over the modeled operators. Returns `None` on
+    // anything else (calls, members, assignments, ...) so the caller can
+    // fall back to raw text.
+    fn expression(&mut self) -> Option<Expr> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Option<Expr> {
+        let mut left = self.unary()?;
+        while let Some(token) = self.peek().filter(|t| t.kind == TokenKind::Punctuator) {
+            let Some(precedence) = binary_precedence(&token.text).filter(|&p| p >= min_precedence) else {
+                break;
+            };
+            let op = token.text.clone();
+            self.pos += 1;
+            // `**` is right-associative
+            let right = self.binary(if op == "**" { precedence } else { precedence + 1 })?;
+            left = Expr::Binary { op, left: Box::new(left), right: Box::new(right) };
+        }
+        Some(left)
+    }
+
+    fn unary(&mut self) -> Option<Expr> {
+        let token = self.peek()?;
+        if token.kind == TokenKind::Punctuator && matches!(token.text.as_str(), "-" | "+" | "!" | "~") {
+            let op = token.text.clone();
+            self.pos += 1;
+            return Some(Expr::Unary { op, arg: Box::new(self.unary()?) });
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Option<Expr> {
+        let token = self.peek()?;
+        let expr = match (token.kind, token.text.as_str()) {
+            (TokenKind::Number, text) => Expr::Number(parse_number(text)?),
+            (TokenKind::String, text) => Expr::Str(text.to_string()),
+            (TokenKind::Identifier, text) => Expr::Ident(text.to_string()),
+            (TokenKind::Keyword, "true") => Expr::Bool(true),
+            (TokenKind::Keyword, "false") => Expr::Bool(false),
+            (TokenKind::Keyword, "null") => Expr::Null,
+            (TokenKind::Punctuator, "(") => {
+                self.pos += 1;
+                let inner = self.expression()?;
+                return self.eat(")").then_some(inner);
+            }
+            _ => return None,
+        };
+        self.pos += 1;
+        Some(expr)
+    }
+}
+
+// Print an AST back to source, one statement per line
+pub fn emit(ast: &Ast) -> String {
+    let mut out = String::new();
+    for stmt in &ast.body {
+        emit_statement(stmt, 0, &mut out);
+    }
+    out
+}
+
+fn emit_statement(stmt: &Stmt, indent: usize, out: &mut String) {
+    out.push_str(&"    ".repeat(indent));
+    match stmt {
+        Stmt::VarDecl { kind, name, init } => {
+            out.push_str(&format!("{} {}", kind, name));
+            if let Some(init) = init {
+                out.push_str(" = ");
+                emit_expr(init, out);
+            }
+            out.push_str(";\n");
+        }
+        Stmt::Expr(expr) => {
+            emit_expr(expr, out);
+            out.push_str(";\n");
+        }
+        Stmt::If { .. } => {
+            emit_if(stmt, indent, out);
+            out.push('\n');
+        }
+        Stmt::Block(body) => {
+            emit_block(body, indent, out);
+            out.push('\n');
+        }
+        Stmt::Return(arg) => {
+            out.push_str("return");
+            if let Some(arg) = arg {
+                out.push(' ');
+                emit_expr(arg, out);
+            }
+            out.push_str(";\n");
+        }
+        Stmt::Function { header, body } => {
+            out.push_str(header);
+            out.push(' ');
+            emit_block(body, indent, out);
+            out.push('\n');
+        }
+        Stmt::Empty => out.push_str(";\n"),
+        Stmt::Import(import) => {
+            out.push_str("import ");
+            let mut bindings = Vec::new();
+            bindings.extend(import.default.clone());
+            bindings.extend(import.namespace.as_ref().map(|namespace| format!("* as {}", namespace)));
+            if !import.named.is_empty() {
+                bindings.push(specifier_list(&import.named));
+            }
+            if !bindings.is_empty() {
+                out.push_str(&format!("{} from ", bindings.join(", ")));
+            }
+            out.push_str(&format!("{};\n", import.source));
+        }
+        Stmt::Export(export) => {
+            out.push_str("export ");
+            match export {
+                Export::Declaration { decl, .. } => emit_statement(decl, 0, out),
+                Export::DefaultDeclaration { decl, .. } => {
+                    out.push_str("default ");
+                    emit_statement(decl, 0, out);
+                }
+                Export::Default(expr) => out.push_str(&format!("default {};\n", expr)),
+                Export::Named { specifiers, source } => {
+                    out.push_str(&specifier_list(specifiers));
+                    if let Some(source) = source {
+                        out.push_str(&format!(" from {}", source));
+                    }
+                    out.push_str(";\n");
+                }
+                Export::All { source, alias } => {
+                    out.push('*');
+                    if let Some(alias) = alias {
+                        out.push_str(&format!(" as {}", alias));
+                    }
+                    out.push_str(&format!(" from {};\n", source));
+                }
+            }
+        }
+        Stmt::Raw(text) => {
+            out.push_str(text);
+            out.push('\n');
+        }
+    }
+}
+
+// `{ a, b as c }` from `(name, alias)` pairs
+fn specifier_list(specifiers: &[(String, String)]) -> String {
+    let specifiers: Vec<String> = specifiers
+        .iter()
+        .map(|(name, alias)| if name == alias { name.clone() } else { format!("{} as {}", name, alias) })
+        .collect();
+    if specifiers.is_empty() {
+        "{}".to_string()
+    } else {
+        format!("{{ {} }}", specifiers.join(", "))
+    }
+}
+
+// `{ ... }` with the closing brace at `indent` and no trailing newline
+fn emit_block(body: &[Stmt], indent: usize, out: &mut String) {
+    out.push_str("{\n");
+    for stmt in body {
+        emit_statement(stmt, indent + 1, out);
+    }
+    out.push_str(&"    ".repeat(indent));
+    out.push('}');
+}
+
+// An `if` without leading indentation or a trailing newline, so `else if` chains stay on one line
+fn emit_if(stmt: &Stmt, indent: usize, out: &mut String) {
+    let Stmt::If { test, consequent, alternate } = stmt else {
+        return;
+    };
+    out.push_str("if (");
+    emit_expr(test, out);
+    out.push_str(") ");
+
+    // Non-block branches are braced too, which also keeps a nested
+    // else-less `if` from capturing our `else`
+    emit_block(branch_body(consequent), indent, out);
+    let Some(alternate) = alternate else {
+        return;
+    };
+    out.push_str(" else ");
+    match &**alternate {
+        nested @ Stmt::If { .. } => emit_if(nested, indent, out),
+        other => emit_block(branch_body(other), indent, out),
+    }
+}
+
+fn branch_body(stmt: &Stmt) -> &[Stmt] {
+    match stmt {
+        Stmt::Block(body) => body,
+        other => std::slice::from_ref(other),
+    }
+}
+
+fn expr_precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Binary { op, .. } => binary_precedence(op).unwrap_or(0),
+        Expr::Unary { .. } => UNARY_PRECEDENCE,
+        Expr::Number(v) if v.is_sign_negative() => UNARY_PRECEDENCE,
+        Expr::Raw(_) => 0,
+        _ => PRIMARY_PRECEDENCE,
+    }
+}
+
+// Whether `child` needs parentheses as an operand of `op`
+fn needs_parens(child: &Expr, op: &str, is_right: bool) -> bool {
+    let (child_precedence, precedence) = (expr_precedence(child), binary_precedence(op).unwrap_or(0));
+    // `??` cannot be mixed with `||` or `&&` without parentheses
+    let logical = |op: &str| matches!(op, "??" | "||" | "&&");
+    let mixes_nullish = matches!(child, Expr::Binary { op: child_op, .. }
+        if logical(child_op) && logical(op) && (child_op == "??") != (op == "??"));
+    child_precedence < precedence
+        || (child_precedence == precedence && is_right != (op == "**"))
+        // `-a ** b` is a syntax error
+        || (op == "**" && !is_right && child_precedence == UNARY_PRECEDENCE)
+        || mixes_nullish
+}
+
+fn emit_operand(expr: &Expr, parens: bool, out: &mut String) {
+    if parens {
+        out.push('(');
+        emit_expr(expr, out);
+        out.push(')');
+    } else {
+        emit_expr(expr, out);
+    }
+}
+
+fn emit_expr(expr: &Expr, out: &mut String) {
+    match expr {
+        Expr::Number(v) => {
+            if v.is_sign_negative() {
+                out.push('-');
+            }
+            out.push_str(&v.abs().to_string());
+        }
+        Expr::Str(text) | Expr::Ident(text) | Expr::Raw(text) => out.push_str(text),
+        Expr::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Expr::Null => out.push_str("null"),
+        Expr::Unary { op, arg } => {
+            out.push_str(op);
+            // Keep `- -x` from printing as `--x`
+            emit_operand(arg, expr_precedence(arg) < PRIMARY_PRECEDENCE, out);
+        }
+        Expr::Binary { op, left, right } => {
+            emit_operand(left, needs_parens(left, op, false), out);
+            out.push_str(&format!(" {} ", op));
+            emit_operand(right, needs_parens(right, op, true), out);
+        }
+    }
+}
+
+// Defined at the top of CommonJS output that needs them, with the same
+// semantics as the helpers Babel and TypeScript emit
+const IMPORT_DEFAULT_HELPER: &str =
+    "function __importDefault(mod) { return mod && mod.__esModule ? mod : { default: mod }; }\n";
+const EXPORT_STAR_HELPER: &str = "function __exportStar(mod, exports) { for (const key of Object.keys(mod)) if (key !== \"default\" && !(key in exports)) exports[key] = mod[key]; }\n";
+
+// Print `ast` with its top-level imports and exports written in `target`'s
+// format; everything else is printed as `emit` prints it.
+//
+// For CommonJS, the `require`s are hoisted to the top like the imports they
+// replace, and each export is assigned once, after its declaration runs, so
+// later reassignments of an exported `let` are not seen by importers. For
+// ESM, the simple top-level `require` and `exports` forms are rewritten:
+// `const x = require("m")`, `const { a, b: c } = require("m")`, a bare
+// `require("m")`, `module.exports = ...`, and `exports.name = ...`.
+pub fn transpile_modules(ast: &Ast, target: ModuleFormat) -> String {
+    match target {
+        ModuleFormat::CommonJs => to_commonjs(ast),
+        ModuleFormat::Esm => to_esm(ast),
+    }
+}
+
+fn to_commonjs(ast: &Ast) -> String {
+    let (mut requires, mut body) = (String::new(), String::new());
+    // `export { x }` may come before `let x`, so its assignment goes after the
+    // body instead of reading `x` in its temporal dead zone
+    let mut local_exports = String::new();
+    let (mut is_module, mut has_exports, mut import_default, mut export_star) = (false, false, false, false);
+    for stmt in &ast.body {
+        match stmt {
+            Stmt::Import(import) => {
+                is_module = true;
+                let require = format!("require({})", import.source);
+                if let Some(default) = &import.default {
+                    import_default = true;
+                    requires.push_str(&format!("const {} = __importDefault({}).default;\n", default, require));
+                }
+                if let Some(namespace) = &import.namespace {
+                    requires.push_str(&format!("const {} = {};\n", namespace, require));
+                }
+                if !import.named.is_empty() {
+                    let bindings: Vec<String> = import
+                        .named
+                        .iter()
+                        .map(|(name, local)| if name == local { name.clone() } else { format!("{}: {}", name, local) })
+                        .collect();
+                    requires.push_str(&format!("const {{ {} }} = {};\n", bindings.join(", "), require));
+                }
+                if import.default.is_none() && import.namespace.is_none() && import.named.is_empty() {
+                    requires.push_str(&format!("{};\n", require));
+                }
+            }
+            Stmt::Export(export) => {
+                (is_module, has_exports) = (true, true);
+                match export {
+                    Export::Declaration { decl, names } => {
+                        emit_statement(decl, 0, &mut body);
+                        for name in names {
+                            body.push_str(&format!("exports.{} = {};\n", name, name));
+                        }
+                    }
+                    Export::DefaultDeclaration { decl, name } => {
+                        emit_statement(decl, 0, &mut body);
+                        body.push_str(&format!("exports.default = {};\n", name));
+                    }
+                    Export::Default(expr) => body.push_str(&format!("exports.default = {};\n", expr)),
+                    Export::Named { specifiers, source } => {
+                        for (local, exported) in specifiers {
+                            match source {
+                                Some(source) => {
+                                    body.push_str(&format!("exports.{} = require({}).{};\n", exported, source, local))
+                                }
+                                None => local_exports.push_str(&format!("exports.{} = {};\n", exported, local)),
+                            }
+                        }
+                    }
+                    Export::All { source, alias: Some(alias) } => {
+                        body.push_str(&format!("exports.{} = require({});\n", alias, source))
+                    }
+                    Export::All { source, alias: None } => {
+                        export_star = true;
+                        body.push_str(&format!("__exportStar(require({}), exports);\n", source));
+                    }
+                }
+            }
+            other => emit_statement(other, 0, &mut body),
+        }
+    }
+
+    let mut out = String::new();
+    // ES modules are always strict
+    if is_module {
+        out.push_str("\"use strict\";\n");
+    }
+    if has_exports {
+        out.push_str("Object.defineProperty(exports, \"__esModule\", { value: true });\n");
+    }
+    if import_default {
+        out.push_str(IMPORT_DEFAULT_HELPER);
+    }
+    if export_star {
+        out.push_str(EXPORT_STAR_HELPER);
+    }
+    out.push_str(&requires);
+    out.push_str(&body);
+    out.push_str(&local_exports);
+    out
+}
+
+fn to_esm(ast: &Ast) -> String {
+    let mut out = String::new();
+    for stmt in &ast.body {
+        match stmt {
+            Stmt::Raw(text) => match commonjs_to_esm(text) {
+                Some(converted) => {
+                    out.push_str(&converted);
+                    out.push('\n');
+                }
+                None => emit_statement(stmt, 0, &mut out),
+            },
+            other => emit_statement(other, 0, &mut out),
+        }
+    }
+    out
+}
+
+// The ESM form of a raw statement using `require` or `exports`, if it has one
+fn commonjs_to_esm(text: &str) -> Option<String> {
+    let tokens: Vec<JsToken> = tokenize(text).ok()?.into_iter().filter(|t| t.kind != TokenKind::Comment).collect();
+    let tokens = match tokens.split_last() {
+        Some((last, rest)) if last.text == ";" => rest,
+        _ => &tokens[..],
+    };
+    let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+    // `require("m")` ending the statement, giving the module's string literal
+    let required = |from: usize| match &tokens[from..] {
+        [require, open, source, close] if require.text == "require" && open.text == "(" && close.text == ")" => {
+            (source.kind == TokenKind::String).then(|| source.text.clone())
+        }
+        _ => None,
+    };
+    let rest = |from: usize| tokens.get(from).map(|first| text[first.start..tokens[tokens.len() - 1].end].to_string());
+
+    match texts.as_slice() {
+        ["require", ..] => Some(format!("import {};", required(0)?)),
+        ["const" | "let" | "var", name, "=", ..] if tokens[1].kind == TokenKind::Identifier => {
+            Some(format!("import {} from {};", name, required(3)?))
+        }
+        ["const" | "let" | "var", "{", ..] => {
+            let close = texts.iter().position(|&t| t == "}")?;
+            if texts.get(close + 1) != Some(&"=") {
+                return None;
+            }
+            let source = required(close + 2)?;
+            let mut specifiers = Vec::new();
+            for binding in texts[2..close].split(|&t| t == ",").filter(|binding| !binding.is_empty()) {
+                specifiers.push(match binding {
+                    [name] => (name.to_string(), name.to_string()),
+                    [name, ":", local] => (name.to_string(), local.to_string()),
+                    _ => return None,
+                });
+            }
+            Some(format!("import {} from {};", specifier_list(&specifiers), source))
+        }
+        ["module", ".", "exports", "=", ..] => Some(format!("export default {};", rest(4)?)),
+        ["module", ".", "exports", ".", name, "=", ..] => export_assignment(name, &tokens[6..], rest(6)?),
+        ["exports", ".", name, "=", ..] => export_assignment(name, &tokens[4..], rest(4)?),
+        _ => None,
+    }
+}
+
+// `exports.name = value` as an export statement
+fn export_assignment(name: &str, value_tokens: &[JsToken], value: String) -> Option<String> {
+    Some(match value_tokens {
+        _ if name == "default" => format!("export default {};", value),
+        [single] if single.kind == TokenKind::Identifier => {
+            format!("export {};", specifier_list(&[(value, name.to_string())]))
+        }
+        _ => format!("export const {} = {};", name, value),
+    })
+}
+
+// Fold constant expressions and remove code that can never run: `if`
+// branches with a literal test and statements after a `return`.
+pub fn optimize(ast: &mut Ast) {
+    optimize_statements(&mut ast.body);
+}
+
+fn optimize_statements(stmts: &mut Vec<Stmt>) {
+    let mut reachable = true;
+    let mut out = Vec::with_capacity(stmts.len());
+    for stmt in stmts.drain(..) {
+        if !reachable {
+            // Declarations are hoisted, so they outlive the code around them
+            match stmt {
+                Stmt::Function { .. } => out.extend(optimize_statement(stmt)),
+                other => match hoisted_vars(&other) {
+                    Some(vars) => out.extend(vars),
+                    None => out.push(other),
+                },
+            }
+            continue;
+        }
+        for stmt in optimize_statement(stmt) {
+            if matches!(stmt, Stmt::Return(_)) {
+                reachable = false;
+            }
+            out.push(stmt);
+        }
+    }
+    *stmts = out;
+}
+
+// The optimized replacement for `stmt`: none, one, or a block's spliced-in contents
+fn optimize_statement(stmt: Stmt) -> Vec<Stmt> {
+    match stmt {
+        Stmt::VarDecl { kind, name, init } => vec![Stmt::VarDecl { kind, name, init: init.map(fold) }],
+        Stmt::Expr(expr) => vec![Stmt::Expr(fold(expr))],
+        Stmt::Return(arg) => vec![Stmt::Return(arg.map(fold))],
+        Stmt::Block(mut body) => {
+            optimize_statements(&mut body);
+            vec![Stmt::Block(body)]
+        }
+        Stmt::Function { header, mut body } => {
+            optimize_statements(&mut body);
+            vec![Stmt::Function { header, body }]
+        }
+        Stmt::If { test, consequent, alternate } => {
+            let test = fold(test);
+            let decided = truthiness(&test);
+            let dropped = match decided {
+                Some(true) => alternate.as_deref(),
+                Some(false) => Some(&*consequent),
+                None => None,
+            };
+            // A dead branch declaring vars that cannot be pulled out of it has to stay
+            let hoisted = match (decided, dropped) {
+                (Some(_), Some(dropped)) => hoisted_vars(dropped),
+                (Some(_), None) => Some(Vec::new()),
+                (None, _) => None,
+            };
+            let Some(mut out) = hoisted else {
+                let consequent = Box::new(into_single(optimize_statement(*consequent)));
+                let alternate = alternate.map(|a| Box::new(into_single(optimize_statement(*a))));
+                return vec![Stmt::If { test, consequent, alternate }];
+            };
+
+            let taken = if decided == Some(true) { Some(*consequent) } else { alternate.map(|a| *a) };
+            if let Some(taken) = taken {
+                let mut kept = optimize_statement(taken);
+                if let [Stmt::Block(body)] = kept.as_mut_slice() {
+                    if !body.iter().any(declares_lexically) {
+                        kept = std::mem::take(body);
+                    }
+                }
+                out.splice(0..0, kept);
+            }
+            out
+        }
+        Stmt::Export(Export::Declaration { decl, names }) => {
+            vec![Stmt::Export(Export::Declaration { decl: Box::new(into_single(optimize_statement(*decl))), names })]
+        }
+        Stmt::Empty | Stmt::Import(_) | Stmt::Export(_) | Stmt::Raw(_) => vec![stmt],
+    }
+}
+
+fn into_single(mut stmts: Vec<Stmt>) -> Stmt {
+    match stmts.len() {
+        0 => Stmt::Empty,
+        1 => stmts.remove(0),
+        _ => Stmt::Block(stmts),
+    }
+}
+
+// Whether `text` contains a `var` or `function` declaration somewhere inside
+fn raw_declares_vars(text: &str) -> bool {
+    tokenize(text).map_or(true, |tokens| {
+        tokens.iter().any(|t| t.kind == TokenKind::Keyword && matches!(t.text.as_str(), "var" | "function"))
+    })
+}
+
+// Bare `var` declarations standing in for unreachable `stmt`, or `None` when
+// it declares something that cannot be separated from its code
+fn hoisted_vars(stmt: &Stmt) -> Option<Vec<Stmt>> {
+    match stmt {
+        Stmt::VarDecl { kind, name, .. } if kind == "var" => {
+            Some(vec![Stmt::VarDecl { kind: kind.clone(), name: name.clone(), init: None }])
+        }
+        Stmt::VarDecl { .. } | Stmt::Expr(_) | Stmt::Return(_) | Stmt::Empty => Some(Vec::new()),
+        Stmt::Block(body) => body.iter().map(hoisted_vars).collect::<Option<Vec<_>>>().map(|v| v.concat()),
+        Stmt::If { consequent, alternate, .. } => {
+            let mut vars = hoisted_vars(consequent)?;
+            if let Some(alternate) = alternate {
+                vars.extend(hoisted_vars(alternate)?);
+            }
+            Some(vars)
+        }
+        Stmt::Function { .. } | Stmt::Import(_) | Stmt::Export(_) => None,
+        Stmt::Raw(text) => (!raw_declares_vars(text)).then(Vec::new),
+    }
+}
+
+// Whether unwrapping the block holding `stmt` would leak a binding into the outer scope
+fn declares_lexically(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::VarDecl { kind, .. } => kind != "var",
+        Stmt::Function { .. } | Stmt::Import(_) | Stmt::Export(_) => true,
+        Stmt::Raw(text) => tokenize(text).map_or(true, |tokens| {
+            tokens.first().is_some_and(|t| {
+                t.kind == TokenKind::Keyword && matches!(t.text.as_str(), "let" | "const" | "class" | "function" | "async")
+            })
+        }),
+        _ => false,
+    }
+}
+
+// The boolean value of a literal, if `expr` is one
+fn truthiness(expr: &Expr) -> Option<bool> {
+    match expr {
+        Expr::Bool(b) => Some(*b),
+        Expr::Number(v) => Some(*v != 0.0 && !v.is_nan()),
+        Expr::Null => Some(false),
+        // Quotes included, so only `""` and `''` are empty
+        Expr::Str(text) => Some(text.len() > 2),
+        _ => None,
+    }
+}
+
+fn fold(expr: Expr) -> Expr {
+    match expr {
+        Expr::Unary { op, arg } => {
+            let arg = fold(*arg);
+            match (op.as_str(), &arg, truthiness(&arg)) {
+                ("-", Expr::Number(v), _) => Expr::Number(-v),
+                ("+", Expr::Number(v), _) => Expr::Number(*v),
+                ("!", _, Some(truthy)) => Expr::Bool(!truthy),
+                _ => Expr::Unary { op, arg: Box::new(arg) },
+            }
+        }
+        Expr::Binary { op, left, right } => {
+            let (left, right) = (fold(*left), fold(*right));
+            if let (Expr::Number(a), Expr::Number(b)) = (&left, &right) {
+                if let Some(folded) = fold_numbers(&op, *a, *b) {
+                    return folded;
+                }
+            }
+            // A literal left operand decides which side a logical operator yields
+            match (op.as_str(), truthiness(&left)) {
+                ("&&", Some(true)) | ("||", Some(false)) => right,
+                ("&&", Some(false)) | ("||", Some(true)) => left,
+                ("??", Some(_)) if left == Expr::Null => right,
+                ("??", Some(_)) => left,
+                _ => Expr::Binary { op, left: Box::new(left), right: Box::new(right) },
+            }
+        }
+        other => other,
+    }
+}
+
+// Arithmetic and comparisons on two numbers; results that do not fit a literal are left unfolded
+fn fold_numbers(op: &str, a: f64, b: f64) -> Option<Expr> {
+    let value = match op {
+        "+" => a + b,
+        "-" => a - b,
+        "*" => a * b,
+        "/" => a / b,
+        "%" => a % b,
+        "**" => a.powf(b),
+        "<" => return Some(Expr::Bool(a < b)),
+        ">" => return Some(Expr::Bool(a > b)),
+        "<=" => return Some(Expr::Bool(a <= b)),
+        ">=" => return Some(Expr::Bool(a >= b)),
+        "==" | "===" => return Some(Expr::Bool(a == b)),
+        "!=" | "!==" => return Some(Expr::Bool(a != b)),
+        _ => return None,
+    };
+    value.is_finite().then_some(Expr::Number(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds_and_text(src: &str) -> Vec<(TokenKind, String)> {
+        tokenize(src).unwrap().into_iter().map(|t| (t.kind, t.text)).collect()
+    }
+
+    #[test]
+    fn test_string_containing_comment_marker() {
+        let tokens = kinds_and_text(r#"const url = "https://example.com"; // trailing"#);
+        assert_eq!(tokens[3], (TokenKind::String, r#""https://example.com""#.to_string()));
+        assert_eq!(tokens.last().unwrap(), &(TokenKind::Comment, "// trailing".to_string()));
+        assert_eq!(
+            strip_comments(r#"let a = "//not a comment"; // gone"#).unwrap(),
+            r#"let a = "//not a comment";  "#
+        );
+        // Comments still separate the tokens around them
+        assert_eq!(strip_comments("a/**/b").unwrap(), "a b");
+        assert_eq!(strip_comments("return /*\n*/ 42").unwrap(), "return \n 42");
+    }
+
+    #[test]
+    fn test_template_interpolation_boundaries() {
+        let tokens = kinds_and_text("`a ${ { b: 1 }.b } c ${d} e`");
+        let expected = vec![
+            (TokenKind::Template(TemplatePart::Head), "`a ${".to_string()),
+            (TokenKind::Punctuator, "{".to_string()),
+            (TokenKind::Identifier, "b".to_string()),
+            (TokenKind::Punctuator, ":".to_string()),
+            (TokenKind::Number, "1".to_string()),
+            (TokenKind::Punctuator, "}".to_string()),
+            (TokenKind::Punctuator, ".".to_string()),
+            (TokenKind::Identifier, "b".to_string()),
+            (TokenKind::Template(TemplatePart::Middle), "} c ${".to_string()),
+            (TokenKind::Identifier, "d".to_string()),
+            (TokenKind::Template(TemplatePart::Tail), "} e`".to_string()),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_nested_quotes_and_escapes() {
+        let tokens = kinds_and_text(r#"say("it's \"quoted\"", 'say "hi"', 'don\'t')"#);
+        let strings: Vec<_> = tokens.into_iter().filter(|(k, _)| *k == TokenKind::String).map(|(_, t)| t).collect();
+        assert_eq!(strings, vec![r#""it's \"quoted\"""#, r#"'say "hi"'"#, r#"'don\'t'"#]);
+    }
+
+    #[test]
+    fn test_regex_versus_division() {
+        let tokens = kinds_and_text("a = b / c; re = /[/]+/g;");
+        assert_eq!(tokens[3], (TokenKind::Punctuator, "/".to_string()));
+        assert!(tokens.contains(&(TokenKind::Regex, "/[/]+/g".to_string())));
+    }
+
+    #[test]
+    fn test_unterminated_string_position() {
+        assert_eq!(
+            tokenize("let x = 1;\nlet s = 'oops;"),
+            Err(LexError::UnterminatedString { line: 2, column: 9 })
+        );
+    }
+
+    #[test]
+    fn test_vlq_round_trip() {
+        for value in [0, 1, -1, 15, -16, 16, 123456, -987654] {
+            let mut encoded = String::new();
+            encode_vlq(value, &mut encoded);
+            assert_eq!(decode_vlq(&encoded), Some(vec![value]), "{}", encoded);
+        }
+        let mut encoded = String::new();
+        encode_vlq(16, &mut encoded);
+        assert_eq!(encoded, "gB");
+    }
+
+    #[test]
+    fn test_minify_strips_comments_and_whitespace() {
+        let src = "// greeting\nfunction greet(name) {\n    return 'hi ' + name; /* done */\n}\nlet a = b\n++c\n";
+        let output = minify_js(src, &MinifyOptions::default()).unwrap();
+        assert_eq!(output.code, "function greet(name){return'hi '+name;}\nlet a=b\n++c");
+        assert!(output.source_map.is_none());
+    }
+
+    #[test]
+    fn test_minify_keeps_line_breaks_next_to_comments() {
+        let minify = |src| minify_js(src, &MinifyOptions::default()).unwrap().code;
+        assert_eq!(minify("x = 1\n/* note */ y = 2"), "x=1\ny=2");
+        assert_eq!(minify("x = 1 // note\ny = 2"), "x=1\ny=2");
+        assert_eq!(minify("function f() { return /*\n*/ 42 }"), "function f(){return\n42}");
+    }
+
+    #[test]
+    fn test_source_map_round_trips_token_positions() {
+        let src = "const total = 1;\n\nfunction add(first, second) {\n  // sum\n  return first + second;\n}\n";
+        let options = MinifyOptions {
+            source_map: true,
+            source_name: "add.js".to_string(),
+            source_map_url: Some("add.min.js.map".to_string()),
+        };
+        let output = minify_js(src, &options).unwrap();
+        let map = output.source_map.unwrap();
+        assert_eq!(map.sources, vec!["add.js".to_string()]);
+        assert!(output.code.ends_with("\n//# sourceMappingURL=add.min.js.map"));
+
+        // `second` in `return first + second` sits at line 5, column 18 of the source (1-based)
+        let generated_line = output.code.lines().next().unwrap();
+        let generated_column = generated_line.rfind("second").unwrap();
+        let mapping = map.lookup(0, generated_column).unwrap();
+        assert_eq!((mapping.original_line, mapping.original_column), (4, 17));
+        assert_eq!(map.names[mapping.name.unwrap()], "second");
+
+        // Every token maps back to source text with the same spelling
+        for mapping in map.decode().unwrap() {
+            let original_line = src.lines().nth(mapping.original_line).unwrap();
+            let generated_line = output.code.lines().nth(mapping.generated_line).unwrap();
+            let first = |text: &str, column: usize| text[column..].chars().next();
+            assert_eq!(first(original_line, mapping.original_column), first(generated_line, mapping.generated_column));
+        }
+
+        let json: serde_json::Value = serde_json::from_str(&map.to_json()).unwrap();
+        assert_eq!(json["version"], 3);
+        assert!(json["mappings"].is_string());
+    }
+
+    fn optimized(src: &str) -> String {
+        compile(src, &CompileOptions { optimize: true, ..Default::default() }).unwrap()
+    }
+
+    #[test]
+    fn test_optimize_folds_constants() {
+        assert_eq!(optimized("const x = 1 + 2 * 3;"), "const x = 7;\n");
+        assert_eq!(optimized("let y = (2 ** 3 - 10) / 4"), "let y = -0.5;\n");
+        assert_eq!(optimized("var ok = 1 < 2 && !false;"), "var ok = true;\n");
+        // Only the constant part of a mixed expression folds
+        assert_eq!(optimized("const t = 60 * 60 * hours;"), "const t = 3600 * hours;\n");
+        // Division by zero is left for the engine
+        assert_eq!(optimized("const inf = 1 / 0;"), "const inf = 1 / 0;\n");
+    }
+
+    #[test]
+    fn test_optimize_removes_dead_branches() {
+        assert_eq!(optimized("if (false) { dead(); }\nalive();"), "alive();\n");
+        assert_eq!(optimized("if (true) { run(); } else { skip(); }"), "run();\n");
+        assert_eq!(optimized("if (1 > 2) { a(); } else { b(); }"), "b();\n");
+
+        // Unwrapping would leak block-scoped bindings, so the block stays
+        assert_eq!(optimized("if (true) { let x = 1; use(x); }"), "{\n    let x = 1;\n    use(x);\n}\n");
+
+        // A dead branch's `var` is still declared, just never assigned
+        assert_eq!(optimized("if (false) { var flag = 1; }"), "var flag;\n");
+    }
+
+    #[test]
+    fn test_optimize_drops_code_after_return() {
+        let src = "function f() {\n  return 1;\n  sideEffect();\n  var y = 2;\n  function g() {}\n}";
+        assert_eq!(optimized(src), "function f() {\n    return 1;\n    var y;\n    function g() {\n    }\n}\n");
+    }
+
+    #[test]
+    fn test_compile_without_optimize_keeps_code() {
+        let src = "const x = 1 + 2 * 3;\nif (false) { dead(); }";
+        assert_eq!(
+            compile(src, &CompileOptions::default()).unwrap(),
+            "const x = 1 + 2 * 3;\nif (false) {\n    dead();\n}\n"
+        );
+
+        // Syntax the AST does not model passes through as written
+        let src = "class A { m() { return `${this.x}`; } }\nconst f = (a, b) => a + b;\nlabel: for (;;) break label;";
+        let output = compile(src, &CompileOptions { optimize: true, ..Default::default() }).unwrap();
+        assert_eq!(output, format!("{}\n", src));
+    }
+
+    fn commonjs(src: &str) -> String {
+        transpile_modules(&parse(src).unwrap(), ModuleFormat::CommonJs)
+    }
+
+    #[test]
+    fn test_commonjs_default_import() {
+        let src = "import React from \"react\";\nimport \"./polyfills.js\";\nrender(React);";
+        assert_eq!(
+            commonjs(src),
+            format!(
+                "\"use strict\";\n{}const React = __importDefault(require(\"react\")).default;\nrequire(\"./polyfills.js\");\nrender(React);\n",
+                IMPORT_DEFAULT_HELPER
+            )
+        );
+
+        // Dynamic imports are expressions, and leave a script a script
+        assert_eq!(commonjs("import(\"./lazy.js\").then(load);"), "import(\"./lazy.js\").then(load);\n");
+    }
+
+    #[test]
+    fn test_commonjs_named_and_namespace_imports() {
+        let src = "log(1);\nimport { readFile, writeFile as write } from 'fs';\nimport * as path from 'path';\nimport def, { a } from './m.js';";
+        assert_eq!(
+            commonjs(src),
+            format!(
+                "\"use strict\";\n{}{}{}{}{}log(1);\n",
+                IMPORT_DEFAULT_HELPER,
+                "const { readFile, writeFile: write } = require('fs');\n",
+                "const path = require('path');\n",
+                "const def = __importDefault(require('./m.js')).default;\n",
+                "const { a } = require('./m.js');\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_commonjs_exports_and_reexports() {
+        let src = "export const answer = 42, half = answer / 2;\n\
+                   export function greet(name) { return name; }\n\
+                   export { answer as theAnswer };\n\
+                   export { parse, default as Parser } from './parser.js';\n\
+                   export * from './utils.js';\n\
+                   export * as fs from 'fs';";
+        let expected = [
+            "\"use strict\";",
+            "Object.defineProperty(exports, \"__esModule\", { value: true });",
+            EXPORT_STAR_HELPER.trim_end(),
+            "const answer = 42, half = answer / 2;",
+            "exports.answer = answer;",
+            "exports.half = half;",
+            "function greet(name) {\n    return name;\n}",
+            "exports.greet = greet;",
+            "exports.parse = require('./parser.js').parse;",
+            "exports.Parser = require('./parser.js').default;",
+            "__exportStar(require('./utils.js'), exports);",
+            "exports.fs = require('fs');",
+            "exports.theAnswer = answer;",
+        ];
+        assert_eq!(commonjs(src), format!("{}\n", expected.join("\n")));
+    }
+
+    #[test]
+    fn test_commonjs_export_list_before_declaration() {
+        let src = "export { config, load as default };\nlet config = {};\nfunction load() { return config; }";
+        let expected = [
+            "\"use strict\";",
+            "Object.defineProperty(exports, \"__esModule\", { value: true });",
+            "let config = {};",
+            "function load() {\n    return config;\n}",
+            "exports.config = config;",
+            "exports.default = load;",
+        ];
+        assert_eq!(commonjs(src), format!("{}\n", expected.join("\n")));
+    }
+
+    #[test]
+    fn test_commonjs_default_export() {
+        let header = "\"use strict\";\nObject.defineProperty(exports, \"__esModule\", { value: true });\n";
+        assert_eq!(
+            commonjs("export default function App() { return 1; }"),
+            format!("{}function App() {{\n    return 1;\n}}\nexports.default = App;\n", header)
+        );
+        assert_eq!(commonjs("export default { name: 'x' };"), format!("{}exports.default = {{ name: 'x' }};\n", header));
+        assert_eq!(
+            commonjs("export default class extends Base {}"),
+            format!("{}exports.default = class extends Base {{}};\n", header)
+        );
+    }
+
+    #[test]
+    fn test_module_syntax_round_trips() {
+        let src = "import d, { a as b } from \"m\";\nexport { b };\nexport default d;\nexport * as all from \"m\";\n";
+        assert_eq!(compile(src, &CompileOptions::default()).unwrap(), src);
+    }
+
+    #[test]
+    fn test_commonjs_to_esm() {
+        let src = "const fs = require('fs');\n\
+                   const { join, resolve: abs } = require('path');\n\
+                   require('./setup');\n\
+                   function main() {}\n\
+                   exports.main = main;\n\
+                   exports.version = '1.0';\n\
+                   module.exports.helper = () => 1;\n\
+                   const lazy = require(name);";
+        let expected = [
+            "import fs from 'fs';",
+            "import { join, resolve as abs } from 'path';",
+            "import './setup';",
+            "function main() {\n}",
+            "export { main };",
+            "export const version = '1.0';",
+            "export const helper = () => 1;",
+            "const lazy = require(name);",
+        ];
+        assert_eq!(transpile_modules(&parse(src).unwrap(), ModuleFormat::Esm), format!("{}\n", expected.join("\n")));
+        assert_eq!(
+            transpile_modules(&parse("module.exports = createApp;").unwrap(), ModuleFormat::Esm),
+            "export default createApp;\n"
+        );
+    }
+
+    fn syntax_error(message: &str, line: usize, column: usize) -> SyntaxError {
+        SyntaxError { message: message.to_string(), line, column }
+    }
+
+    #[test]
+    fn test_validate_reports_unterminated_string() {
+        let errors = validate("let ok = 1;\nlet s = \"oops;\n").unwrap_err();
+        assert_eq!(errors, vec![syntax_error("Unterminated string literal", 2, 9)]);
+        assert_eq!(errors[0].to_string(), "Unterminated string literal at 2:9");
+    }
+
+    #[test]
+    fn test_validate_reports_unbalanced_brackets() {
+        let src = "function f(a) {\n    if (a) {\n        return [a, (a + 1];\n    }\n";
+        assert_eq!(
+            validate(src),
+            Err(vec![syntax_error("Unclosed '{'", 1, 15), syntax_error("Unclosed '('", 3, 20)])
+        );
+        assert_eq!(validate("call(a));"), Err(vec![syntax_error("Unmatched ')'", 1, 8)]));
+    }
+
+    #[test]
+    fn test_validate_reports_unexpected_tokens() {
+        assert_eq!(
+            validate("let x = 1 2;\nlet y = x * ;\nlet z = y +"),
+            Err(vec![
+                syntax_error("Unexpected '2'", 1, 11),
+                syntax_error("Unexpected ';'", 2, 13),
+                syntax_error("Unexpected end of input after '+'", 3, 11),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_validate_accepts_valid_program() {
+        let src = r#"
+            import { load as fetchAll } from './data.js';
+            class Store {
+                static #count = 0;
+                get size() { return this.items?.length ?? 0; }
+            }
+            for (const [key, value] of Object.entries({ a: 1 })) {
+                console.log(tag`${key}=${value}`, /[)}]+/g.test(key));
+            }
+            export default async function* run(items = []) {
+                yield* items.map(item => ({ item }));
+            }
+        "#;
+        assert_eq!(validate(src), Ok(()));
+    }
+}
@@ -1,111 +1,347 @@
-use reqwest::Client;
 use select::document::Document;
 use select::node::Node;
 use select::predicate::{Name, Predicate};
 use regex::Regex;
 use tokio;
-use luminance::color::RGB;
 use url::Url;
-use std::collections::{HashMap, HashSet};
-use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Instant;
+use serde::Serialize;
+use serde_json::{json, Value};
+use futures::stream::{self, StreamExt};
+
+#[allow(dead_code)]
+mod http;
+
+#[allow(dead_code)]
+mod single_flight;
+use single_flight::DedupClient;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let url = "https://example.com"; // Replace with the URL to test
-    let body = fetch_page(url).await?;
-    let document = Document::from(body.as_str());
+    let report = run_audit(url).await?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+/// A single scored audit within a category.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditResult {
+    pub id: String,
+    pub title: String,
+    /// Score between 0.0 and 1.0.
+    pub score: f64,
+    pub weight: f64,
+    pub details: Value,
+}
 
-    // Performance Metrics
-    let (load_time, resource_sizes, fcp, tti) = get_page_performance(url).await?;
-    println!("Page load time: {} ms", load_time);
-    println!("First Contentful Paint: {} ms", fcp);
-    println!("Time to Interactive: {} ms", tti);
-    for (resource, size) in resource_sizes {
-        println!("Resource: {}, Size: {} bytes", resource, size);
+/// A Lighthouse category with its weighted score.
+#[derive(Debug, Clone, Serialize)]
+pub struct CategoryReport {
+    pub id: String,
+    pub title: String,
+    /// Weighted score between 0 and 100.
+    pub score: u32,
+    pub audits: Vec<AuditResult>,
+}
+
+/// Consolidated report for a page, mirroring the Lighthouse category layout.
+#[derive(Debug, Clone, Serialize)]
+pub struct LighthouseReport {
+    pub requested_url: String,
+    pub fetch_time: String,
+    pub categories: BTreeMap<String, CategoryReport>,
+}
+
+impl LighthouseReport {
+    /// Returns the score (0–100) of the given category, if present.
+    pub fn category_score(&self, id: &str) -> Option<u32> {
+        self.categories.get(id).map(|category| category.score)
     }
+}
 
-    // Accessibility Audits
-    let alt_count = count_missing_alt(&document);
-    println!("Images without alt attributes: {}", alt_count);
+/// Performance measurements fed into the performance category.
+#[derive(Debug, Clone, Default)]
+pub struct PerformanceMetrics {
+    /// Milliseconds taken to fetch the page's HTML.
+    pub load_time: u64,
+    /// Paint and interactivity timings need a browser to observe, so a
+    /// fetched page leaves these `None` and their audits unscored.
+    pub first_contentful_paint: Option<u64>,
+    pub time_to_interactive: Option<u64>,
+    /// Bytes of the page and each script, stylesheet, and image it loads, by URL.
+    pub resource_sizes: HashMap<String, u64>,
+}
 
-    let aria_role_count = count_missing_aria_roles(&document);
-    println!("Elements without ARIA roles: {}", aria_role_count);
+/// Runs every audit against the given URL and returns the consolidated report.
+///
+/// # Arguments
+///
+/// * `url` - A string slice representing the URL to audit.
+///
+/// # Returns
+///
+/// A `Result` containing the `LighthouseReport` or an error.
+pub async fn run_audit(url: &str) -> Result<LighthouseReport, Box<dyn std::error::Error>> {
+    let config = http::HttpConfig::from_env();
+    // The URL is user-supplied; refuse loopback, private, and non-http targets
+    http::check_url_async(url, &config.fetch_policy).await?;
+    let http_client = http::client_with(&config);
+    // Shared by every fetch in this audit, so the page and its links go out once each
+    let client = DedupClient::new(http_client.clone());
+    let started = Instant::now();
+    let body = fetch_page(&client, url).await?;
+    let load_time = started.elapsed().as_millis() as u64;
+    let document = Document::from(body.as_str());
 
-    let aria_label_count = count_missing_aria_labels(&document);
-    println!("Elements without aria-labels: {}", aria_label_count);
+    let mut resource_sizes = get_resource_sizes(&http_client, &config.fetch_policy, &document, url).await?;
+    resource_sizes.insert(url.to_string(), body.len() as u64);
+    let performance = PerformanceMetrics {
+        load_time,
+        first_contentful_paint: None,
+        time_to_interactive: None,
+        resource_sizes,
+    };
+    let broken_links = check_broken_links(&client, &config.fetch_policy, &document, url).await?;
+
+    Ok(audit_document(url, &document, &performance, &broken_links))
+}
 
-    let interactive_focusable_count = count_non_focusable_interactives(&document);
-    println!("Interactive elements not focusable: {}", interactive_focusable_count);
+/// Scores an already-fetched document without any further network access.
+///
+/// # Arguments
+///
+/// * `url` - The URL the document was fetched from.
+/// * `document` - A `select::Document` object representing the parsed HTML content.
+/// * `performance` - Performance measurements for the page.
+/// * `broken_links` - Links found to be broken on the page.
+///
+/// # Returns
+///
+/// The consolidated `LighthouseReport`.
+pub fn audit_document(url: &str, document: &Document, performance: &PerformanceMetrics, broken_links: &HashSet<String>) -> LighthouseReport {
+    let mut categories = BTreeMap::new();
+    for category in [
+        performance_category(performance),
+        accessibility_category(document),
+        seo_category(document, broken_links),
+    ] {
+        categories.insert(category.id.clone(), category);
+    }
 
-    let semantic_elements = check_semantic_html(&document);
-    println!("Non-semantic elements: {:?}", semantic_elements);
+    LighthouseReport {
+        requested_url: url.to_string(),
+        fetch_time: chrono::Utc::now().to_rfc3339(),
+        categories,
+    }
+}
 
-    let contrast_warnings = check_color_contrast(&document);
-    for (element, ratio) in contrast_warnings {
-        println!("Low contrast in element '{}': ratio {}", element, ratio);
+/// Combines weighted audits into a category with a 0–100 score.
+fn build_category(id: &str, title: &str, audits: Vec<AuditResult>) -> CategoryReport {
+    let total_weight: f64 = audits.iter().map(|a| a.weight).sum();
+    let weighted: f64 = audits.iter().map(|a| a.score * a.weight).sum();
+    let score = if total_weight > 0.0 { (weighted / total_weight * 100.0).round() as u32 } else { 0 };
+
+    CategoryReport {
+        id: id.to_string(),
+        title: title.to_string(),
+        score,
+        audits,
     }
+}
 
-    // SEO Audits
-    let title = document.find(Name("title")).next().map_or("", |node| node.text());
-    println!("Page title: {}", title);
-
-    let meta_description = document.find(Name("meta"))
-        .filter_map(|node| node.attr("name").and_then(|name| if name == "description" { node.attr("content") } else { None }))
-        .next()
-        .unwrap_or("No meta description");
-    println!("Meta description: {}", meta_description);
-
-    let canonical = document.find(Name("link"))
-        .filter_map(|node| node.attr("rel").and_then(|rel| if rel == "canonical" { node.attr("href") } else { None }))
-        .next()
-        .unwrap_or("No canonical URL");
-    println!("Canonical URL: {}", canonical);
-
-    let open_graph_tags = get_open_graph_tags(&document);
-    for (property, content) in open_graph_tags {
-        println!("Open Graph tag - Property: {}, Content: {}", property, content);
+fn audit(id: &str, title: &str, score: f64, weight: f64, details: Value) -> AuditResult {
+    AuditResult {
+        id: id.to_string(),
+        title: title.to_string(),
+        score: score.clamp(0.0, 1.0),
+        weight,
+        details,
     }
+}
 
-    let broken_links = check_broken_links(&document, url).await?;
-    for link in broken_links {
-        println!("Broken link: {}", link);
+/// Scores a metric linearly between a "good" and a "poor" threshold.
+fn linear_score(value: f64, good: f64, poor: f64) -> f64 {
+    if value <= good {
+        1.0
+    } else if value >= poor {
+        0.0
+    } else {
+        1.0 - (value - good) / (poor - good)
     }
+}
 
-    Ok(())
+/// Fraction of `total` items that pass, treating an empty set as passing.
+fn pass_ratio(failing: usize, total: usize) -> f64 {
+    if total == 0 { 1.0 } else { 1.0 - failing as f64 / total as f64 }
+}
+
+/// Scores a timing in milliseconds; one that wasn't measured is reported as
+/// unavailable and weighted 0, so it doesn't count toward the category.
+fn timing_audit(id: &str, title: &str, ms: Option<u64>, good: f64, poor: f64, weight: f64) -> AuditResult {
+    match ms {
+        Some(ms) => audit(id, title, linear_score(ms as f64, good, poor), weight, json!({ "ms": ms })),
+        None => audit(id, title, 0.0, 0.0, json!({ "ms": null, "unavailable": true })),
+    }
+}
+
+fn performance_category(metrics: &PerformanceMetrics) -> CategoryReport {
+    let total_bytes: u64 = metrics.resource_sizes.values().sum();
+    build_category("performance", "Performance", vec![
+        timing_audit("first-contentful-paint", "First Contentful Paint",
+                     metrics.first_contentful_paint, 1800.0, 3000.0, 10.0),
+        timing_audit("interactive", "Time to Interactive", metrics.time_to_interactive, 3800.0, 7300.0, 10.0),
+        audit("load-time", "Page Load Time",
+              linear_score(metrics.load_time as f64, 2500.0, 6000.0), 5.0,
+              json!({ "ms": metrics.load_time })),
+        audit("total-byte-weight", "Total Byte Weight",
+              linear_score(total_bytes as f64, 1_600_000.0, 4_000_000.0), 3.0,
+              json!({ "bytes": total_bytes })),
+    ])
+}
+
+fn accessibility_category(document: &Document) -> CategoryReport {
+    let images = document.find(Name("img")).count();
+    let missing_alt = count_missing_alt(document);
+
+    let links: Vec<_> = document.find(Name("a")).collect();
+    let unnamed_links = links
+        .iter()
+        .filter(|link| link.text().trim().is_empty() && link.attr("aria-label").is_none_or(|l| l.trim().is_empty()))
+        .count();
+
+    let inputs: Vec<_> = document
+        .find(Name("input").or(Name("textarea")).or(Name("select")))
+        .filter(|node| !matches!(node.attr("type"), Some("hidden" | "submit" | "button")))
+        .collect();
+    let label_targets: HashSet<&str> = document.find(Name("label")).filter_map(|l| l.attr("for")).collect();
+    let unlabeled_inputs = inputs
+        .iter()
+        .filter(|input| {
+            let labelled_by_for = input.attr("id").is_some_and(|id| label_targets.contains(id));
+            !labelled_by_for && input.attr("aria-label").is_none() && input.attr("aria-labelledby").is_none()
+        })
+        .count();
+
+    let has_lang = document.find(Name("html")).next().and_then(|html| html.attr("lang")).is_some_and(|lang| !lang.is_empty());
+    let has_title = document.find(Name("title")).next().is_some_and(|title| !title.text().trim().is_empty());
+    let contrast_warnings = check_color_contrast(document);
+    let custom_controls = custom_interactives(document).len();
+    let missing_roles = count_missing_aria_roles(document);
+    let (unnamed_buttons, buttons) = count_missing_aria_labels(document);
+    let (unfocusable, interactives) = count_non_focusable_interactives(document);
+    let semantic_elements = check_semantic_html(document);
+    let has_main = semantic_elements.contains("main");
+    let mut semantic_elements: Vec<_> = semantic_elements.into_iter().collect();
+    semantic_elements.sort();
+
+    build_category("accessibility", "Accessibility", vec![
+        audit("image-alt", "Image elements have [alt] attributes",
+              pass_ratio(missing_alt, images), 10.0, json!({ "failing": missing_alt, "total": images })),
+        audit("label", "Form elements have associated labels",
+              pass_ratio(unlabeled_inputs, inputs.len()), 7.0, json!({ "failing": unlabeled_inputs, "total": inputs.len() })),
+        audit("link-name", "Links have a discernible name",
+              pass_ratio(unnamed_links, links.len()), 7.0, json!({ "failing": unnamed_links, "total": links.len() })),
+        audit("html-has-lang", "<html> element has a [lang] attribute",
+              if has_lang { 1.0 } else { 0.0 }, 3.0, json!({})),
+        audit("document-title", "Document has a <title> element",
+              if has_title { 1.0 } else { 0.0 }, 3.0, json!({})),
+        audit("color-contrast", "Text has sufficient color contrast",
+              if contrast_warnings.is_empty() { 1.0 } else { 0.0 }, 3.0, json!({ "failing": contrast_warnings.len() })),
+        audit("aria-role", "Custom controls have an ARIA [role]",
+              pass_ratio(missing_roles, custom_controls), 7.0, json!({ "failing": missing_roles, "total": custom_controls })),
+        audit("button-name", "Buttons have an accessible name",
+              pass_ratio(unnamed_buttons, buttons), 7.0, json!({ "failing": unnamed_buttons, "total": buttons })),
+        audit("focusable-controls", "Interactive controls are keyboard focusable",
+              pass_ratio(unfocusable, interactives), 7.0, json!({ "failing": unfocusable, "total": interactives })),
+        audit("landmark-one-main", "Document has a main landmark",
+              if has_main { 1.0 } else { 0.0 }, 3.0, json!({ "semantic_elements": semantic_elements })),
+    ])
+}
+
+fn seo_category(document: &Document, broken_links: &HashSet<String>) -> CategoryReport {
+    let has_title = document.find(Name("title")).next().is_some_and(|title| !title.text().trim().is_empty());
+    let has_description = document
+        .find(Name("meta"))
+        .any(|node| node.attr("name") == Some("description") && node.attr("content").is_some_and(|c| !c.trim().is_empty()));
+    let has_canonical = document.find(Name("link")).any(|node| node.attr("rel") == Some("canonical") && node.attr("href").is_some());
+    let h1_count = get_heading_structure(document)[0].1;
+    let open_graph_tags = get_open_graph_tags(document);
+    let structured_data = validate_structured_data(document);
+
+    build_category("seo", "SEO", vec![
+        audit("document-title", "Document has a <title> element", if has_title { 1.0 } else { 0.0 }, 1.0, json!({})),
+        audit("meta-description", "Document has a meta description", if has_description { 1.0 } else { 0.0 }, 1.0, json!({})),
+        audit("canonical", "Document has a valid rel=canonical", if has_canonical { 1.0 } else { 0.0 }, 1.0, json!({})),
+        audit("heading-h1", "Document has a single <h1>", if h1_count == 1 { 1.0 } else { 0.0 }, 1.0, json!({ "count": h1_count })),
+        audit("open-graph", "Document has Open Graph tags", if open_graph_tags.is_empty() { 0.0 } else { 1.0 }, 0.5, json!(open_graph_tags)),
+        audit("structured-data", "Structured data is valid", if structured_data.is_empty() { 0.0 } else { 1.0 }, 0.5, json!({ "items": structured_data.len() })),
+        audit("broken-links", "Links are not broken", if broken_links.is_empty() { 1.0 } else { 0.0 }, 1.0, json!(broken_links)),
+    ])
 }
 
 /// Fetches the HTML content of the given URL.
 ///
 /// # Arguments
 ///
+/// * `client` - The audit's shared client.
 /// * `url` - A string slice representing the URL to fetch.
 ///
 /// # Returns
 ///
 /// A `Result` containing the HTML body as a string or an error.
-async fn fetch_page(url: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let client = Client::new();
-    let response = client.get(url).send().await?;
-    let body = response.text().await?;
-    Ok(body)
+async fn fetch_page(client: &DedupClient, url: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let response = client.get(url).await?;
+    Ok(response.body.to_string())
 }
 
-/// Simulates performance metrics such as load time, resource sizes, FCP, and TTI.
+/// How many resources `get_resource_sizes` fetches at once.
+const MAX_RESOURCE_FETCHES: usize = 8;
+
+/// Fetches the scripts, stylesheets, and images the page loads and measures their size.
 ///
 /// # Arguments
 ///
-/// * `url` - A string slice representing the URL to analyze.
+/// * `client` - The audit's client; sizes are of the decompressed bodies.
+/// * `policy` - Which resources may be fetched; the page chose them, so others are skipped.
+/// * `document` - A `select::Document` object representing the parsed HTML content.
+/// * `base_url` - The URL of the page the resources are loaded by.
 ///
 /// # Returns
 ///
-/// A `Result` containing a tuple of simulated performance metrics and resource sizes or an error.
-async fn get_page_performance(url: &str) -> Result<(u64, HashMap<String, u64>, u64, u64), Box<dyn std::error::Error>> {
-    // Simulated data for demonstration purposes
-    let mut resource_sizes = HashMap::new();
-    resource_sizes.insert("example.js".to_string(), 4567);
-    resource_sizes.insert("style.css".to_string(), 7890);
+/// A `HashMap` of resource URLs to their sizes in bytes. Resources that can't be fetched are left out.
+async fn get_resource_sizes(
+    client: &reqwest::Client,
+    policy: &http::FetchPolicy,
+    document: &Document,
+    base_url: &str,
+) -> Result<HashMap<String, u64>, Box<dyn std::error::Error>> {
+    let base = Url::parse(base_url)?;
+
+    let is_stylesheet = |rel: &str| rel.split_whitespace().any(|rel| rel.eq_ignore_ascii_case("stylesheet"));
+    let stylesheets = document
+        .find(Name("link"))
+        .filter(|node| node.attr("rel").is_some_and(is_stylesheet))
+        .filter_map(|node| node.attr("href"));
+    let sources = document.find(Name("script").or(Name("img"))).filter_map(|node| node.attr("src"));
+    let resources: HashSet<Url> = stylesheets
+        .chain(sources)
+        .filter_map(|src| base.join(src).ok())
+        .filter(|url| url.scheme() == "http" || url.scheme() == "https")
+        .collect();
+
+    let fetches = resources.into_iter().map(|url| async move {
+        if let Err(blocked) = http::check_url_async(url.as_str(), policy).await {
+            eprintln!("Skipping resource: {}", blocked);
+            return None;
+        }
+        let response = client.get(url.as_str()).send().await.ok()?.error_for_status().ok()?;
+        let body = http::bytes(response).await.ok()?;
+        Some((url.to_string(), body.len() as u64))
+    });
 
-    Ok((123, resource_sizes, 321, 456)) // Load time, FCP, TTI
+    Ok(stream::iter(fetches).buffer_unordered(MAX_RESOURCE_FETCHES).filter_map(|size| async { size }).collect().await)
 }
 
 /// Counts the number of images without 'alt' attributes.
@@ -123,7 +359,25 @@ fn count_missing_alt(document: &Document) -> usize {
         .count()
 }
 
-/// Counts the number of elements without 'aria-role' attributes.
+/// Elements that are focusable and keyboard-operable without any ARIA.
+const NATIVE_INTERACTIVES: &[&str] = &["a", "button", "input", "textarea", "select", "summary"];
+
+/// Sectioning elements that give assistive technology landmarks to navigate by.
+const SEMANTIC_ELEMENTS: &[&str] = &["header", "footer", "main", "nav", "article", "section", "aside", "figure", "figcaption"];
+
+/// Elements that act as controls without being native ones: a click handler
+/// or a `tabindex` on something like a `<div>`.
+fn custom_interactives<'a>(document: &'a Document) -> Vec<Node<'a>> {
+    document
+        .find(|node: &Node| {
+            node.name().is_some_and(|name| !NATIVE_INTERACTIVES.contains(&name))
+                && (node.attr("onclick").is_some() || node.attr("tabindex").is_some())
+        })
+        .collect()
+}
+
+/// Counts custom controls with no `role`, which assistive technology
+/// announces as plain text.
 ///
 /// # Arguments
 ///
@@ -131,14 +385,16 @@ fn count_missing_alt(document: &Document) -> usize {
 ///
 /// # Returns
 ///
-/// The count of elements missing 'aria-role' attributes.
+/// The count of custom controls missing a `role` attribute.
 fn count_missing_aria_roles(document: &Document) -> usize {
-    document.find(Name("*"))
-        .filter(|node| !node.attr("role").is_some())
+    custom_interactives(document)
+        .iter()
+        .filter(|node| node.attr("role").is_none_or(|role| role.trim().is_empty()))
         .count()
 }
 
-/// Counts the number of elements without 'aria-label' attributes.
+/// Counts buttons, native or by `role`, with no text, `aria-label` or
+/// `aria-labelledby` to name them.
 ///
 /// # Arguments
 ///
@@ -146,14 +402,25 @@ fn count_missing_aria_roles(document: &Document) -> usize {
 ///
 /// # Returns
 ///
-/// The count of elements missing 'aria-label' attributes.
-fn count_missing_aria_labels(document: &Document) -> usize {
-    document.find(Name("*"))
-        .filter(|node| !node.attr("aria-label").is_some())
-        .count()
+/// The count of buttons without an accessible name, and the count of buttons.
+fn count_missing_aria_labels(document: &Document) -> (usize, usize) {
+    let buttons: Vec<_> = document
+        .find(|node: &Node| node.name() == Some("button") || node.attr("role") == Some("button"))
+        .collect();
+    let unnamed = buttons
+        .iter()
+        .filter(|button| {
+            button.text().trim().is_empty()
+                && button.attr("aria-label").is_none_or(|label| label.trim().is_empty())
+                && button.attr("aria-labelledby").is_none()
+        })
+        .count();
+    (unnamed, buttons.len())
 }
 
-/// Counts the number of interactive elements that are not focusable.
+/// Counts interactive elements a keyboard user cannot reach: native controls
+/// taken out of the tab order, links without an `href`, and custom controls
+/// without a `tabindex`.
 ///
 /// # Arguments
 ///
@@ -161,19 +428,28 @@ fn count_missing_aria_labels(document: &Document) -> usize {
 ///
 /// # Returns
 ///
-/// The count of interactive elements that lack focusability.
-fn count_non_focusable_interactives(document: &Document) -> usize {
-    let interactive_elements = vec!["button", "a", "input", "textarea", "select"];
-    
-    document.find(Name("*"))
-        .filter(|node| {
-            let name = node.name();
-            interactive_elements.contains(&name) && !node.attr("tabindex").map_or(false, |tabindex| tabindex == "0")
+/// The count of interactive elements that lack focusability, and the count of interactive elements.
+fn count_non_focusable_interactives(document: &Document) -> (usize, usize) {
+    let removed_from_tab_order =
+        |node: &Node| node.attr("tabindex").and_then(|tabindex| tabindex.trim().parse::<i32>().ok()).is_some_and(|i| i < 0);
+    let native: Vec<_> = document
+        .find(|node: &Node| {
+            node.name().is_some_and(|name| NATIVE_INTERACTIVES.contains(&name)) && !matches!(node.attr("type"), Some("hidden"))
         })
-        .count()
+        .collect();
+    let custom = custom_interactives(document);
+
+    let unreachable_native = native
+        .iter()
+        .filter(|node| removed_from_tab_order(node) || (node.name() == Some("a") && node.attr("href").is_none()))
+        .count();
+    let unreachable_custom =
+        custom.iter().filter(|node| node.attr("tabindex").is_none() || removed_from_tab_order(node)).count();
+    (unreachable_native + unreachable_custom, native.len() + custom.len())
 }
 
-/// Checks for the use of non-semantic HTML elements.
+/// Lists the semantic sectioning elements the page uses, counting an element
+/// with the matching landmark `role` (e.g. `role="main"`) as well.
 ///
 /// # Arguments
 ///
@@ -181,22 +457,29 @@ fn count_non_focusable_interactives(document: &Document) -> usize {
 ///
 /// # Returns
 ///
-/// A `HashSet` containing the names of non-semantic elements.
+/// A `HashSet` containing the names of the semantic elements found.
 fn check_semantic_html(document: &Document) -> HashSet<String> {
-    let semantic_elements = ["header", "footer", "main", "nav", "article", "section", "aside", "figure", "figcaption"];
-    let mut non_semantic = HashSet::new();
+    let landmark_roles = [("banner", "header"), ("contentinfo", "footer"), ("main", "main"), ("navigation", "nav")];
+    let mut semantic = HashSet::new();
 
-    document.find(Name("*")).for_each(|node| {
-        let name = node.name();
-        if !semantic_elements.contains(&name) && !name.starts_with("h") {
-            non_semantic.insert(name.to_string());
+    for node in document.find(|node: &Node| node.name().is_some()) {
+        if let Some(name) = node.name().filter(|name| SEMANTIC_ELEMENTS.contains(name)) {
+            semantic.insert(name.to_string());
         }
-    });
+        if let Some((_, element)) = landmark_roles.iter().find(|(role, _)| node.attr("role") == Some(role)) {
+            semantic.insert(element.to_string());
+        }
+    }
 
-    non_semantic
+    semantic
 }
 
-/// Checks the color contrast of elements and warns if below a certain ratio.
+/// WCAG AA minimum contrast ratio for body text.
+const CONTRAST_RATIO_THRESHOLD: f32 = 4.5;
+
+/// Checks the contrast between the inline `color` and `background-color` of
+/// elements, taking a white background when none is set, and warns about
+/// those below the WCAG AA ratio. Only `#rgb` and `#rrggbb` colors are understood.
 ///
 /// # Arguments
 ///
@@ -207,28 +490,58 @@ fn check_semantic_html(document: &Document) -> HashSet<String> {
 /// A `Vec` of tuples containing element names and their contrast ratios if the ratio is below the threshold.
 fn check_color_contrast(document: &Document) -> Vec<(String, f32)> {
     let mut warnings = Vec::new();
-    let contrast_ratio_threshold = 4.5;
-    
-    for node in document.find(Name("*")) {
-        let element_name = node.name().to_string();
-        let color = node.attr("style").and_then(|style| {
-            let re = Regex::new(r"color:\s*([^;]+)").ok()?;
-            re.captures(style).and_then(|caps| caps.get(1)).map(|m| m.as_str())
-        });
-
-        if let Some(color) = color {
-            let rgb = RGB::from_hex(color).unwrap_or(RGB::new(0.0, 0.0, 0.0));
-            let contrast_ratio = 6.0; // Simulated value
+    let declaration = Regex::new(r"(?:^|;)\s*(color|background-color)\s*:\s*([^;]+)").unwrap();
+
+    for node in document.find(|node: &Node| node.attr("style").is_some()) {
+        let style = node.attr("style").unwrap_or_default();
+        let mut foreground = None;
+        let mut background = [1.0, 1.0, 1.0];
+        for caps in declaration.captures_iter(style) {
+            let Some(rgb) = parse_hex_color(caps[2].trim()) else { continue };
+            match &caps[1] {
+                "color" => foreground = Some(rgb),
+                _ => background = rgb,
+            }
+        }
 
-            if contrast_ratio < contrast_ratio_threshold {
-                warnings.push((element_name, contrast_ratio));
+        if let Some(foreground) = foreground {
+            let contrast_ratio = contrast_ratio(foreground, background);
+            if contrast_ratio < CONTRAST_RATIO_THRESHOLD {
+                warnings.push((node.name().unwrap_or_default().to_string(), contrast_ratio));
             }
         }
     }
-    
+
     warnings
 }
 
+/// Parses `#rgb` or `#rrggbb` into sRGB channels between 0.0 and 1.0.
+fn parse_hex_color(color: &str) -> Option<[f32; 3]> {
+    let hex = color.strip_prefix('#')?;
+    let channel = |digits: &str| u8::from_str_radix(digits, 16).ok().map(|value| value as f32 / 255.0);
+    match hex.len() {
+        3 => {
+            let mut rgb = [0.0; 3];
+            for (i, digit) in hex.char_indices() {
+                rgb[i] = channel(&digit.to_string().repeat(2))?;
+            }
+            Some(rgb)
+        }
+        6 if hex.is_ascii() => Some([channel(&hex[0..2])?, channel(&hex[2..4])?, channel(&hex[4..6])?]),
+        _ => None,
+    }
+}
+
+/// The WCAG contrast ratio between two colors, from 1.0 to 21.0.
+fn contrast_ratio(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let luminance = |rgb: [f32; 3]| {
+        let [r, g, b] = rgb.map(|c| if c <= 0.03928 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) });
+        0.2126 * r + 0.7152 * g + 0.0722 * b
+    };
+    let (la, lb) = (luminance(a), luminance(b));
+    (la.max(lb) + 0.05) / (la.min(lb) + 0.05)
+}
+
 /// Retrieves the heading structure of the document.
 ///
 /// # Arguments
@@ -242,7 +555,7 @@ fn get_heading_structure(document: &Document) -> Vec<(u8, usize)> {
     let mut headings = vec![0; 6];
     
     for i in 1..=6 {
-        let count = document.find(Name(&format!("h{}", i)))
+        let count = document.find(Name(format!("h{}", i).as_str()))
             .count();
         headings[i - 1] = count;
     }
@@ -271,36 +584,51 @@ fn validate_structured_data(document: &Document) -> Vec<Value> {
     structured_data
 }
 
+/// How many links `check_broken_links` has in flight at once.
+const MAX_LINK_CHECKS: usize = 8;
+
 /// Checks for broken links on the page and categorizes them into internal and external.
 ///
 /// # Arguments
 ///
+/// * `client` - The audit's shared client; each distinct link's status is fetched once.
+/// * `policy` - Which links may be fetched; the page chose them, so others are skipped.
 /// * `document` - A `select::Document` object representing the parsed HTML content.
 /// * `base_url` - The base URL of the page being checked.
 ///
 /// # Returns
 ///
 /// A `Vec` of broken links found on the page.
-async fn check_broken_links(document: &Document, base_url: &str) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
+async fn check_broken_links(
+    client: &DedupClient,
+    policy: &http::FetchPolicy,
+    document: &Document,
+    base_url: &str,
+) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
     let base = Url::parse(base_url)?;
-    let mut broken_links = HashSet::new();
-    let client = Client::new();
-    
-    for node in document.find(Name("a")).filter_map(|node| node.attr("href")) {
-        let link = Url::parse(&node)?;
-        let url = if link.scheme().is_empty() {
-            base.join(&node)?
-        } else {
-            link
-        };
-        
-        let response = client.get(url.clone()).send().await?;
-        if !response.status().is_success() {
-            broken_links.insert(url.to_string());
+
+    // `join` resolves relative links and leaves absolute ones untouched
+    let links = document
+        .find(Name("a"))
+        .filter_map(|node| node.attr("href"))
+        .filter_map(|href| base.join(href).ok())
+        .filter(|url| url.scheme() == "http" || url.scheme() == "https");
+
+    // Checked a few at a time; a link that appears several times is checked once
+    let checks = links.map(|url| async move {
+        // Not fetched at all, so a link into the private network can't be probed through its status
+        if let Err(blocked) = http::check_url_async(url.as_str(), policy).await {
+            eprintln!("Skipping link: {}", blocked);
+            return None;
         }
-    }
-    
-    Ok(broken_links)
+        // Unreachable links count as broken rather than aborting the whole audit
+        match client.status(url.as_str()).await {
+            Ok(status) if status.is_success() => None,
+            _ => Some(url.to_string()),
+        }
+    });
+
+    Ok(stream::iter(checks).buffer_unordered(MAX_LINK_CHECKS).filter_map(|broken| async { broken }).collect().await)
 }
 
 /// Retrieves Open Graph meta tags from the page.
@@ -326,4 +654,207 @@ fn get_open_graph_tags(document: &Document) -> HashMap<String, String> {
     }
     
     og_tags
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLEAN_PAGE: &str = r#"<html lang="en"><head><title>Clean</title>
+        <meta name="description" content="A clean page"></head>
+        <body><main><h1>Welcome</h1>
+        <img src="logo.png" alt="Logo">
+        <a href="/about">About us</a>
+        <label for="email">Email</label><input id="email" type="email">
+        <button aria-label="Close">&times;</button>
+        <div role="button" tabindex="0" onclick="toggle()">Menu</div>
+        </main></body></html>"#;
+
+    const BAD_PAGE: &str = r#"<html><head></head>
+        <body><img src="logo.png"><img src="hero.png">
+        <a href="/about"></a>
+        <input type="email">
+        <button></button>
+        <div onclick="toggle()">Menu</div>
+        </body></html>"#;
+
+    fn report_for(html: &str) -> LighthouseReport {
+        let document = Document::from(html);
+        audit_document("https://example.com", &document, &PerformanceMetrics::default(), &HashSet::new())
+    }
+
+    #[test]
+    fn test_bad_page_scores_lower_on_accessibility() {
+        let clean = report_for(CLEAN_PAGE);
+        let bad = report_for(BAD_PAGE);
+
+        assert_eq!(clean.category_score("accessibility"), Some(100));
+        assert!(bad.category_score("accessibility").unwrap() < clean.category_score("accessibility").unwrap());
+        assert!(bad.category_score("seo").unwrap() < clean.category_score("seo").unwrap());
+    }
+
+    #[test]
+    fn test_aria_focus_and_landmark_audits() {
+        let clean = Document::from(CLEAN_PAGE);
+        let bad = Document::from(BAD_PAGE);
+
+        assert_eq!(count_missing_aria_roles(&clean), 0);
+        assert_eq!(count_missing_aria_roles(&bad), 1);
+        assert_eq!(count_missing_aria_labels(&clean), (0, 2));
+        assert_eq!(count_missing_aria_labels(&bad), (1, 1));
+        // The clean page's link, input, button and custom control are all reachable; the bad page's
+        // custom control has no tabindex
+        assert_eq!(count_non_focusable_interactives(&clean), (0, 4));
+        assert_eq!(count_non_focusable_interactives(&bad), (1, 4));
+        let unreachable = Document::from(r#"<a>Anchor</a><button tabindex="-1">Hidden</button>"#);
+        assert_eq!(count_non_focusable_interactives(&unreachable), (2, 2));
+
+        assert_eq!(check_semantic_html(&clean), HashSet::from(["main".to_string()]));
+        assert!(check_semantic_html(&bad).is_empty());
+        let by_role = Document::from(r#"<div role="main"></div><nav></nav>"#);
+        assert_eq!(check_semantic_html(&by_role), HashSet::from(["main".to_string(), "nav".to_string()]));
+
+        let audits = &report_for(BAD_PAGE).categories["accessibility"].audits;
+        let score = |id: &str| audits.iter().find(|audit| audit.id == id).unwrap().score;
+        assert_eq!(score("aria-role"), 0.0);
+        assert_eq!(score("button-name"), 0.0);
+        assert_eq!(score("focusable-controls"), 0.75);
+        assert_eq!(score("landmark-one-main"), 0.0);
+    }
+
+    #[test]
+    fn test_low_contrast_inline_colors_are_flagged() {
+        let document = Document::from(
+            r#"<p style="color: #777">Grey on white</p><p style="color:#000;background-color:#fff">Black</p>
+            <span style="background-color: #000; color: #222">Dark on black</span><p style="color: red">Named</p>"#,
+        );
+        let warnings = check_color_contrast(&document);
+        assert_eq!(warnings.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["p", "span"]);
+        assert!(warnings.iter().all(|(_, ratio)| *ratio < CONTRAST_RATIO_THRESHOLD));
+        assert!((contrast_ratio([0.0; 3], [1.0; 3]) - 21.0).abs() < 0.01);
+        assert_eq!(parse_hex_color("#fff"), Some([1.0; 3]));
+        assert_eq!(parse_hex_color("#zzz"), None);
+    }
+
+    #[tokio::test]
+    async fn test_links_into_private_network_are_not_fetched() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use tokio::io::AsyncWriteExt;
+
+        // Every link it serves is broken, so a fetch shows up in the result as well as in `hits`
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+            }
+        });
+        let html = format!(
+            r#"<a href="http://{}/admin">Admin</a>
+            <a href="http://169.254.169.254/latest/meta-data/">Metadata</a>
+            <a href="http://10.0.0.1/">Router</a>"#,
+            addr
+        );
+        let document = Document::from(html.as_str());
+        let client = DedupClient::new(http::client_with(&http::HttpConfig::default()));
+
+        let broken = check_broken_links(&client, &http::FetchPolicy::default(), &document, "https://example.com/")
+            .await
+            .unwrap();
+        assert!(broken.is_empty(), "{:?}", broken);
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+
+        // Allowlisting the host is what lets its link through
+        let policy = http::FetchPolicy { allowed_hosts: vec!["127.0.0.1".to_string()], ..http::FetchPolicy::default() };
+        let client = DedupClient::new(http::client_with(&http::HttpConfig { fetch_policy: policy.clone(), ..Default::default() }));
+        let broken = check_broken_links(&client, &policy, &document, "https://example.com/").await.unwrap();
+        assert_eq!(broken, HashSet::from([format!("http://{}/admin", addr)]));
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_link_checks_are_bounded_and_skip_bodies() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use warp::Filter;
+
+        let [in_flight, most, heads] = [(); 3].map(|_| Arc::new(AtomicUsize::new(0)));
+        let (current, peak, head_count) = (in_flight.clone(), most.clone(), heads.clone());
+        let route = warp::method().and_then(move |method: warp::http::Method| {
+            let (current, peak, head_count) = (current.clone(), peak.clone(), head_count.clone());
+            async move {
+                if method == warp::http::Method::HEAD {
+                    head_count.fetch_add(1, Ordering::SeqCst);
+                }
+                peak.fetch_max(current.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                current.fetch_sub(1, Ordering::SeqCst);
+                Ok::<_, std::convert::Infallible>("ok")
+            }
+        });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        // 20 pages, each linked twice: with and without a fragment
+        let html: String = (0..20)
+            .map(|i| format!(r#"<a href="http://{0}/{1}">{1}</a><a href="http://{0}/{1}#top">top</a>"#, addr, i))
+            .collect();
+        let document = Document::from(html.as_str());
+        let policy = http::FetchPolicy { allowed_hosts: vec!["127.0.0.1".to_string()], ..http::FetchPolicy::default() };
+        let client = DedupClient::new(http::client_with(&http::HttpConfig { fetch_policy: policy.clone(), ..Default::default() }));
+
+        let broken = check_broken_links(&client, &policy, &document, "https://example.com/").await.unwrap();
+        assert!(broken.is_empty(), "{:?}", broken);
+        assert_eq!(heads.load(Ordering::SeqCst), 20);
+        assert!(most.load(Ordering::SeqCst) <= MAX_LINK_CHECKS, "{} in flight", most.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_resource_sizes_are_measured() {
+        use warp::Filter;
+
+        let route = warp::path!("assets" / String)
+            .map(|name: String| "x".repeat(if name == "app.js" { 300 } else { 40 }));
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let html = r#"<link rel="icon" href="/assets/favicon.ico"><link rel="Stylesheet" href="/assets/site.css">
+            <script src="/assets/app.js"></script><script>inline()</script>
+            <img src="/assets/logo.png"><img src="/assets/logo.png"><img src="/missing.png">"#;
+        let document = Document::from(html);
+        let policy = http::FetchPolicy { allowed_hosts: vec!["127.0.0.1".to_string()], ..http::FetchPolicy::default() };
+        let client = http::client_with(&http::HttpConfig { fetch_policy: policy.clone(), ..Default::default() });
+
+        let base = format!("http://{}/", addr);
+        let sizes = get_resource_sizes(&client, &policy, &document, &base).await.unwrap();
+        let expected = [("assets/site.css", 40), ("assets/app.js", 300), ("assets/logo.png", 40)]
+            .map(|(path, size)| (format!("{}{}", base, path), size));
+        assert_eq!(sizes, HashMap::from(expected));
+
+        // Not fetched under the default policy
+        let blocked = get_resource_sizes(&client, &http::FetchPolicy::default(), &document, &base).await.unwrap();
+        assert!(blocked.is_empty(), "{:?}", blocked);
+    }
+
+    #[test]
+    fn test_unmeasured_timings_are_unavailable_and_unscored() {
+        let performance = PerformanceMetrics { load_time: 6000, ..PerformanceMetrics::default() };
+        let category = performance_category(&performance);
+        let fcp = category.audits.iter().find(|audit| audit.id == "first-contentful-paint").unwrap();
+        assert_eq!((fcp.weight, &fcp.details), (0.0, &json!({ "ms": null, "unavailable": true })));
+        // Only the measured load time and byte weight count: (0 * 5 + 1 * 3) / 8
+        assert_eq!(category.score, 38);
+    }
+
+    #[test]
+    fn test_report_serializes_categories() {
+        let json = serde_json::to_value(report_for(CLEAN_PAGE)).unwrap();
+        for category in ["performance", "accessibility", "seo"] {
+            let score = json["categories"][category]["score"].as_u64().unwrap();
+            assert!(score <= 100);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use actix_ws::{CloseCode, CloseReason, Message};
+use futures::StreamExt;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::vdom::NodePatch;
+
+/// How many patch sets a client may fall behind before it is disconnected.
+const LIVE_BUFFER: usize = 64;
+
+/// Fans serialized vdom patch sets out to every client connected to `/ws`.
+///
+/// Trees hold `Rc`s and stay on the thread that renders them; only the
+/// serialized JSON crosses over to the websocket sessions.
+pub struct LiveUpdates {
+    sender: broadcast::Sender<Arc<str>>,
+}
+
+impl LiveUpdates {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(LIVE_BUFFER);
+        LiveUpdates { sender }
+    }
+
+    /// Sends the output of `vdom::diff` to connected clients as
+    /// `{"type":"patches","patches":[...]}` (see `vdom::WirePatch` for the
+    /// patch schema), returning how many received it.
+    pub fn publish(&self, patches: &[NodePatch]) -> usize {
+        if patches.is_empty() {
+            return 0;
+        }
+        let patches: Vec<_> = patches.iter().map(NodePatch::to_wire).collect();
+        let message = json!({ "type": "patches", "patches": patches }).to_string();
+        // Sending only fails when nobody is listening
+        self.sender.send(message.into()).unwrap_or(0)
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
+
+impl Default for LiveUpdates {
+    fn default() -> Self {
+        LiveUpdates::new()
+    }
+}
+
+/// Routes for the `/ws` live update socket, mounted with
+/// `App::new().configure(live_routes(updates))`.
+///
+/// An app-wide `Timeout` only times the upgrade: the socket is served from
+/// its own task and stays open past the request time limit.
+pub fn live_routes(updates: Arc<LiveUpdates>) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |cfg| {
+        cfg.app_data(web::Data::from(updates))
+            .route("/ws", web::get().to(live_socket));
+    }
+}
+
+async fn live_socket(req: HttpRequest, body: web::Payload, updates: web::Data<LiveUpdates>) -> Result<HttpResponse, Error> {
+    let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+    let mut patches = updates.sender.subscribe();
+
+    actix_web::rt::spawn(async move {
+        let reason = loop {
+            tokio::select! {
+                message = messages.next() => match message {
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(Message::Close(reason))) => break reason,
+                    Some(Ok(_)) => {}
+                    // The client went away without a close frame
+                    Some(Err(_)) | None => return,
+                },
+                update = patches.recv() => match update {
+                    Ok(message) => {
+                        if session.text(&*message).await.is_err() {
+                            return;
+                        }
+                    }
+                    // Skipped patch sets would leave the client's tree inconsistent,
+                    // so make it reconnect and re-render instead
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("Disconnecting live client that fell {} updates behind", skipped);
+                        break Some(CloseReason {
+                            code: CloseCode::Again,
+                            description: Some("fell behind on updates".to_string()),
+                        });
+                    }
+                    Err(RecvError::Closed) => break None,
+                },
+            }
+        };
+        let _ = session.close(reason).await;
+    });
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timeout::Timeout;
+    use crate::vdom::{diff, VNode, WireNodePatch};
+    use actix_web::{App, HttpServer};
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite::Message as ClientMessage;
+
+    #[actix_web::test]
+    async fn test_client_receives_published_patches() {
+        let updates = Arc::new(LiveUpdates::new());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_updates = updates.clone();
+        let limit = Duration::from_millis(100);
+        let server = HttpServer::new(move || {
+            App::new().configure(live_routes(server_updates.clone())).wrap(Timeout::new(limit))
+        })
+            .workers(1)
+            .listen(listener)
+            .unwrap()
+            .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        while updates.client_count() == 0 {
+            actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+        }
+        // Outlive the request time limit
+        actix_web::rt::time::sleep(limit * 2).await;
+
+        let old = VNode::new_element("p", HashMap::new(), vec![VNode::new_text("before")], HashMap::new());
+        let new = VNode::new_element(
+            "p",
+            HashMap::from([("class".to_string(), "live".to_string())]),
+            vec![VNode::new_text("after")],
+            HashMap::new(),
+        );
+        let patches = diff(&old, &new);
+        assert_eq!(updates.publish(&patches), 1);
+
+        let received = match client.next().await.unwrap().unwrap() {
+            ClientMessage::Text(text) => serde_json::from_str::<serde_json::Value>(&text).unwrap(),
+            other => panic!("expected a text frame, got {:?}", other),
+        };
+        let expected: Vec<_> = patches.iter().map(NodePatch::to_wire).collect();
+        assert_eq!(received["type"], "patches");
+        let received: Vec<WireNodePatch> = serde_json::from_value(received["patches"].clone()).unwrap();
+        assert_eq!(received, expected);
+
+        client.close(None).await.unwrap();
+        while updates.client_count() > 0 {
+            actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+        }
+        handle.stop(true).await;
+    }
+}
@@ -13,6 +13,14 @@ use std::fmt;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+#[allow(dead_code)]
+mod body_limit;
+use body_limit::{max_body_bytes, warp_body_limit};
+
+#[allow(dead_code)]
+mod shutdown;
+use shutdown::shutdown_signal;
+
 #[derive(Deserialize)]
 struct CompileRequest {
     files: Option<HashMap<String, String>>,
@@ -188,8 +196,11 @@ fn generate_source_map(code: &str) -> Result<String, CompileError> {
 async fn main() {
     let compile = warp::post()
         .and(warp::path("compile"))
+        .and(warp_body_limit(max_body_bytes()))
         .and(warp::body::json())
         .and_then(compile);
 
-    warp::serve(compile).run(([127, 0, 0, 1], 3030)).await;
+    // Finish in-flight compiles on ctrl-c or SIGTERM
+    let (_, server) = warp::serve(compile).bind_with_graceful_shutdown(([127, 0, 0, 1], 3030), shutdown_signal());
+    server.await;
 }
\ No newline at end of file
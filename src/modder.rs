@@ -2,6 +2,10 @@ use serde::{Serialize, Deserialize}; // Import Serde for serializing and deseria
 use std::fs; // Import standard library filesystem module
 use std::collections::HashMap; // Import HashMap for simulating DOM attributes
 
+#[allow(dead_code)]
+mod escaping;
+use escaping::attr_escape;
+
 // Define a struct to represent a DOM element with attributes and children
 #[derive(Serialize, Deserialize, Clone)]
 struct DomElement {
@@ -45,7 +49,7 @@ impl DomElement {
         // Start with the opening tag and add attributes
         let mut html = format!("<{}", self.tag);
         for (key, value) in &self.attributes {
-            html.push_str(&format!(" {}=\"{}\"", key, value));
+            html.push_str(&format!(" {}=\"{}\"", key, attr_escape(value)));
         }
         html.push('>');
 
@@ -1,7 +1,8 @@
+use kafka::client::KafkaClient;
 use kafka::consumer::{Consumer, FetchOffset, GroupOffsetStorage};
 use std::time::{Duration, Instant};
 use std::fs::{OpenOptions, File};
-use std::io::{Write, BufWriter};
+use std::io::{self, Write, BufWriter};
 use log::{info, error, warn};
 use serde::{Serialize, Deserialize};
 use std::env;
@@ -9,6 +10,24 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::process::exit;
 use signal_hook::{consts::TERM_SIGNALS, iterator::Signals};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use tracing::info_span;
+use tracing_subscriber::EnvFilter;
+use serde_json::json;
+
+#[allow(dead_code)]
+mod envelope;
+use envelope::EnvelopeError;
+
+#[allow(dead_code)]
+mod metrics;
+use metrics::{LagTracker, PipelineMetrics};
+
+#[path = "../reload.rs"]
+mod reload;
+use reload::{diff, read_env_file, reload_on_sighup, LiveConfig};
 
 // Struct for configuration settings
 #[derive(Serialize, Deserialize, Debug)]
@@ -17,7 +36,11 @@ struct Config {
     topic: String,
     group_id: String,
     output_file: String,
+    dead_letter_file: String, // Corrupt messages are kept here as JSON lines
     polling_interval_secs: u64,
+    metrics_port: Option<u16>, // Serve /metrics on this port when set
+    metrics_bind_addr: IpAddr, // Address the /metrics endpoint listens on
+    metrics_summary_secs: u64, // How often to log a metrics summary
 }
 
 // Default values for configuration
@@ -28,37 +51,135 @@ impl Default for Config {
             topic: String::from(DEFAULT_TOPIC),
             group_id: String::from(DEFAULT_GROUP_ID),
             output_file: String::from("data/output.txt"),
+            dead_letter_file: String::from("data/dead_letter.jsonl"),
             polling_interval_secs: 1,
+            metrics_port: None,
+            metrics_bind_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            metrics_summary_secs: 60,
         }
     }
 }
 
-// Function to load configuration from environment variables
-fn load_config() -> Config {
-    let kafka_broker = env::var("KAFKA_BROKER").unwrap_or_else(|_| DEFAULT_KAFKA_BROKER.to_string());
-    let topic = env::var("TOPIC").unwrap_or_else(|_| DEFAULT_TOPIC.to_string());
-    let group_id = env::var("GROUP_ID").unwrap_or_else(|_| DEFAULT_GROUP_ID.to_string());
-    let output_file = env::var("OUTPUT_FILE").unwrap_or_else(|_| "data/output.txt".to_string());
-    let polling_interval_secs = env::var("POLLING_INTERVAL_SECS")
-        .unwrap_or_else(|_| "1".to_string())
-        .parse::<u64>()
-        .unwrap_or(1);
-
-    Config {
+// Load configuration from environment variables, over the `KEY=VALUE` file at
+// `file` when there is one (CONSUMER_CONFIG_FILE). Only the polling interval
+// is picked up by a reload; the rest is read at startup
+fn load_config(file: Option<&Path>) -> Result<Config, String> {
+    let file = match file {
+        Some(path) => read_env_file(path)?,
+        None => HashMap::new(),
+    };
+    let var = |key: &str| env::var(key).ok().or_else(|| file.get(key).cloned());
+
+    let kafka_broker = var("KAFKA_BROKER").unwrap_or_else(|| DEFAULT_KAFKA_BROKER.to_string());
+    let topic = var("TOPIC").unwrap_or_else(|| DEFAULT_TOPIC.to_string());
+    let group_id = var("GROUP_ID").unwrap_or_else(|| DEFAULT_GROUP_ID.to_string());
+    let output_file = var("OUTPUT_FILE").unwrap_or_else(|| "data/output.txt".to_string());
+    let dead_letter_file = var("DEAD_LETTER_FILE").unwrap_or_else(|| "data/dead_letter.jsonl".to_string());
+    let polling_interval_secs = match var("POLLING_INTERVAL_SECS") {
+        Some(value) => value.trim().parse::<u64>().map_err(|_| format!("Invalid POLLING_INTERVAL_SECS {:?}", value))?,
+        None => 1,
+    };
+    let metrics_port = var("METRICS_PORT").and_then(|port| port.parse::<u16>().ok());
+    let metrics_bind_addr = match var("METRICS_BIND_ADDR") {
+        Some(value) => value.trim().parse::<IpAddr>().map_err(|_| format!("Invalid METRICS_BIND_ADDR {:?}", value))?,
+        None => IpAddr::V4(Ipv4Addr::LOCALHOST),
+    };
+    let metrics_summary_secs = match var("METRICS_SUMMARY_SECS") {
+        Some(value) => match value.trim().parse::<u64>() {
+            Ok(secs) if secs > 0 => secs,
+            _ => return Err(format!("METRICS_SUMMARY_SECS must be a positive number of seconds, got {:?}", value)),
+        },
+        None => 60,
+    };
+
+    Ok(Config {
         kafka_broker,
         topic,
         group_id,
         output_file,
+        dead_letter_file,
         polling_interval_secs,
+        metrics_port,
+        metrics_bind_addr,
+        metrics_summary_secs,
+    })
+}
+
+// What a reload changed, for the log
+fn config_changes(old: &Config, new: &Config) -> Vec<String> {
+    let mut changes = Vec::new();
+    diff(&mut changes, "polling_interval_secs", &old.polling_interval_secs, &new.polling_interval_secs);
+
+    let mut restart = Vec::new();
+    diff(&mut restart, "kafka_broker", &old.kafka_broker, &new.kafka_broker);
+    diff(&mut restart, "topic", &old.topic, &new.topic);
+    diff(&mut restart, "group_id", &old.group_id, &new.group_id);
+    diff(&mut restart, "output_file", &old.output_file, &new.output_file);
+    diff(&mut restart, "dead_letter_file", &old.dead_letter_file, &new.dead_letter_file);
+    diff(&mut restart, "metrics_port", &old.metrics_port, &new.metrics_port);
+    diff(&mut restart, "metrics_bind_addr", &old.metrics_bind_addr, &new.metrics_bind_addr);
+    diff(&mut restart, "metrics_summary_secs", &old.metrics_summary_secs, &new.metrics_summary_secs);
+    changes.extend(restart.into_iter().map(|change| format!("{} (applies after a restart)", change)));
+    changes
+}
+
+// What became of one consumed message
+#[derive(Debug)]
+enum Delivery {
+    Written,
+    DeadLettered(EnvelopeError),
+    NotUtf8,
+}
+
+// Writes the payload of a consumed message to `out`. A message whose envelope
+// is corrupt goes to `dead_letters` instead, with where it came from and why,
+// so it can be inspected or replayed
+fn deliver<W: Write, D: Write>(
+    value: &[u8],
+    partition: i32,
+    offset: i64,
+    out: &mut W,
+    dead_letters: &mut D,
+) -> io::Result<Delivery> {
+    let payload = match envelope::open(value) {
+        Ok(payload) => payload,
+        Err(e) => {
+            let entry = json!({
+                "partition": partition,
+                "offset": offset,
+                "error": e.to_string(),
+                "payload": base64::encode(value),
+            });
+            writeln!(dead_letters, "{}", entry)?;
+            dead_letters.flush()?;
+            return Ok(Delivery::DeadLettered(e));
+        }
+    };
+    match String::from_utf8(payload.into_owned()) {
+        Ok(chunk) => {
+            info!("Received: {}", chunk);
+            writeln!(out, "{}", chunk)?;
+            Ok(Delivery::Written)
+        }
+        Err(_) => Ok(Delivery::NotUtf8),
     }
 }
 
 // Main function
 fn main() {
-    env_logger::init(); // Initialize logger
+    // Log records go through tracing too, so they are printed inside the spans they were logged in
+    tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env()).init();
 
-    let config = load_config();
+    let config_file = env::var_os("CONSUMER_CONFIG_FILE").map(PathBuf::from);
+    let live_config = Arc::new(LiveConfig::new(load_config(config_file.as_deref()).unwrap_or_else(|e| {
+        error!("Invalid configuration: {}", e);
+        exit(1);
+    })));
+    let config = live_config.get();
     info!("Loaded configuration: {:?}", config);
+    if let Err(e) = reload_on_sighup(live_config.clone(), move || load_config(config_file.as_deref()), config_changes) {
+        warn!("Failed to listen for SIGHUP, configuration can't be reloaded: {}", e);
+    }
 
     let consumer = Consumer::from_hosts(vec![config.kafka_broker.clone()])
         .with_topic(config.topic.clone())
@@ -76,6 +197,11 @@ fn main() {
         error!("Failed to open output file: {}", e);
         exit(1);
     }));
+    let dead_letter_file = OpenOptions::new().create(true).append(true).open(&config.dead_letter_file);
+    let mut dead_letters = dead_letter_file.unwrap_or_else(|e| {
+        error!("Failed to open dead-letter file: {}", e);
+        exit(1);
+    });
 
     // Graceful shutdown handling
     let running = Arc::new(AtomicBool::new(true));
@@ -89,37 +215,134 @@ fn main() {
         }
     });
 
+    let metrics = Arc::new(PipelineMetrics::consumer());
+    if let Some(port) = config.metrics_port {
+        if let Err(e) = metrics::serve(metrics.clone(), SocketAddr::new(config.metrics_bind_addr, port)) {
+            error!("Failed to start metrics endpoint: {}", e);
+            exit(1);
+        }
+    }
+    metrics::log_summaries(metrics.clone(), Duration::from_secs(config.metrics_summary_secs));
+
+    // A separate client for the latest offsets, so lag checks don't disturb the consumer
+    let mut offsets_client = KafkaClient::new(vec![config.kafka_broker.clone()]);
+    if let Err(e) = offsets_client.load_metadata_all() {
+        warn!("Failed to load metadata for lag estimates: {}", e);
+    }
+    let mut lag = LagTracker::default();
+
     let mut consumer = consumer;
-    let polling_interval = Duration::from_secs(config.polling_interval_secs);
 
     // Main polling loop
     while running.load(Ordering::SeqCst) {
+        let poll_span = info_span!("poll", topic = %config.topic).entered();
         match consumer.poll() {
             Ok(message_sets) => {
                 for ms in message_sets.iter() {
                     for m in ms.messages() {
-                        if let Ok(chunk) = String::from_utf8(m.value.to_vec()) {
-                            info!("Received: {}", chunk);
-                            if let Err(e) = writeln!(writer, "{}", chunk) {
+                        metrics.record_message(m.value.len());
+                        lag.consumed(ms.partition(), m.offset);
+                        match deliver(m.value, ms.partition(), m.offset, &mut writer, &mut dead_letters) {
+                            Ok(Delivery::Written) => {}
+                            Ok(Delivery::DeadLettered(e)) => {
+                                metrics.record_error();
+                                warn!("Dead-lettered corrupt message at {}/{}: {}", ms.partition(), m.offset, e);
+                            }
+                            Ok(Delivery::NotUtf8) => {
+                                metrics.record_error();
+                                warn!("Failed to parse message as UTF-8");
+                            }
+                            Err(e) => {
+                                metrics.record_error();
                                 error!("Failed to write to file: {}", e);
                             }
-                        } else {
-                            warn!("Failed to parse message as UTF-8");
                         }
                     }
                     if let Err(e) = consumer.consume_messageset(ms) {
+                        metrics.record_error();
                         error!("Failed to consume message set: {}", e);
                     }
                 }
                 if let Err(e) = consumer.commit_consumed() {
+                    metrics.record_error();
                     error!("Failed to commit consumed messages: {}", e);
                 }
             }
-            Err(e) => error!("Error polling messages: {}", e),
+            Err(e) => {
+                metrics.record_error();
+                error!("Error polling messages: {}", e);
+            }
         }
+        drop(poll_span);
+
+        match offsets_client.fetch_topic_offsets(&config.topic, FetchOffset::Latest) {
+            Ok(latest) => metrics.set_lag(lag.lag(latest.iter().map(|p| (p.partition, p.offset)))),
+            Err(e) => warn!("Failed to fetch latest offsets: {}", e),
+        }
+
+        // Read on every poll, so a reload changes it
+        std::thread::sleep(Duration::from_secs(live_config.get().polling_interval_secs));
+    }
+
+    info!("Shutting down gracefully after {}", metrics.snapshot());
+}
 
-        std::thread::sleep(polling_interval);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sealed_message_is_consumed_as_produced() {
+        let line = "sensor=42 status=ok ".repeat(100);
+        let sealed = envelope::seal(line.as_bytes(), 512).unwrap();
+        let (mut out, mut dead_letters) = (Vec::new(), Vec::new());
+
+        let delivery = deliver(&sealed, 0, 7, &mut out, &mut dead_letters).unwrap();
+        assert!(matches!(delivery, Delivery::Written), "{:?}", delivery);
+        assert_eq!(String::from_utf8(out).unwrap(), format!("{}\n", line));
+        assert!(dead_letters.is_empty());
+    }
+
+    #[test]
+    fn test_corrupt_message_is_dead_lettered() {
+        let mut sealed = envelope::seal("sensor=42 status=ok ".repeat(100).as_bytes(), 512).unwrap();
+        let middle = sealed.len() / 2;
+        sealed[middle] ^= 0xff;
+        let (mut out, mut dead_letters) = (Vec::new(), Vec::new());
+
+        let delivery = deliver(&sealed, 2, 41, &mut out, &mut dead_letters).unwrap();
+        assert!(matches!(delivery, Delivery::DeadLettered(EnvelopeError::Checksum { .. })), "{:?}", delivery);
+        assert!(out.is_empty());
+        let entry: serde_json::Value = serde_json::from_slice(&dead_letters).unwrap();
+        assert_eq!(entry["partition"], 2);
+        assert_eq!(entry["offset"], 41);
+        assert_eq!(base64::decode(entry["payload"].as_str().unwrap()).unwrap(), sealed);
     }
 
-    info!("Shutting down gracefully");
-}
\ No newline at end of file
+    #[test]
+    fn test_oversized_message_is_dead_lettered() {
+        let sealed = envelope::seal(&vec![b'a'; envelope::MAX_PAYLOAD + 1], 512).unwrap();
+        let (mut out, mut dead_letters) = (Vec::new(), Vec::new());
+
+        let delivery = deliver(&sealed, 0, 3, &mut out, &mut dead_letters).unwrap();
+        assert!(matches!(delivery, Delivery::DeadLettered(EnvelopeError::TooLarge)), "{:?}", delivery);
+        assert!(out.is_empty());
+        let entry: serde_json::Value = serde_json::from_slice(&dead_letters).unwrap();
+        assert_eq!(entry["offset"], 3);
+    }
+
+    #[test]
+    fn test_metrics_config() {
+        let path = std::env::temp_dir().join(format!("noxium-consumer-metrics-{}.env", std::process::id()));
+        std::fs::write(&path, "METRICS_PORT=9100\n").unwrap();
+        let config = load_config(Some(&path)).unwrap();
+        assert_eq!(config.metrics_port, Some(9100));
+        // Not exposed beyond this host unless asked for
+        assert_eq!(config.metrics_bind_addr, IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        std::fs::write(&path, "METRICS_SUMMARY_SECS=0\n").unwrap();
+        let error = load_config(Some(&path)).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.contains("METRICS_SUMMARY_SECS"), "{}", error);
+    }
+}
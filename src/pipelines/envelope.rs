@@ -0,0 +1,163 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::{Compression, Crc};
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// Marks an enveloped message. Produced lines are text, so none starts with a NUL.
+pub const MAGIC: &[u8; 4] = b"\0NXE";
+
+// Magic, flags byte, then the body's length and CRC32, both big-endian
+const HEADER_LEN: usize = MAGIC.len() + 1 + 4 + 4;
+
+const FLAG_GZIP: u8 = 0b1;
+
+/// Largest payload [`open`] decompresses to, so a small compressed body can't
+/// expand into more memory than the consumer has.
+pub const MAX_PAYLOAD: usize = 16 * 1024 * 1024;
+
+/// Why an enveloped message could not be opened.
+#[derive(Debug)]
+pub enum EnvelopeError {
+    Truncated(usize),
+    UnknownFlags(u8),
+    Length { expected: usize, actual: usize },
+    Checksum { expected: u32, actual: u32 },
+    Decompress(io::Error),
+    TooLarge,
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvelopeError::Truncated(len) => write!(f, "envelope truncated to {} bytes", len),
+            EnvelopeError::UnknownFlags(flags) => write!(f, "unknown envelope flags {:#04x}", flags),
+            EnvelopeError::Length { expected, actual } => {
+                write!(f, "body is {} bytes, header says {}", actual, expected)
+            }
+            EnvelopeError::Checksum { expected, actual } => {
+                write!(f, "body CRC32 is {:08x}, header says {:08x}", actual, expected)
+            }
+            EnvelopeError::Decompress(e) => write!(f, "failed to decompress body: {}", e),
+            EnvelopeError::TooLarge => write!(f, "body decompresses to more than {} bytes", MAX_PAYLOAD),
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+/// Wraps `payload` in an envelope, gzip-compressing it first when it is at
+/// least `compress_min_bytes` long. Small payloads often grow when compressed.
+pub fn seal(payload: &[u8], compress_min_bytes: usize) -> io::Result<Vec<u8>> {
+    let (flags, body) = if payload.len() >= compress_min_bytes {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(payload)?;
+        (FLAG_GZIP, Cow::Owned(encoder.finish()?))
+    } else {
+        (0, Cow::Borrowed(payload))
+    };
+    let length =
+        u32::try_from(body.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "payload too large"))?;
+
+    let mut message = Vec::with_capacity(HEADER_LEN + body.len());
+    message.extend_from_slice(MAGIC);
+    message.push(flags);
+    message.extend_from_slice(&length.to_be_bytes());
+    message.extend_from_slice(&crc32(&body).to_be_bytes());
+    message.extend_from_slice(&body);
+    Ok(message)
+}
+
+/// The payload of a message from [`seal`], after checking its length and CRC32.
+/// Messages without an envelope are passed through as they are, so producers
+/// can turn it on before or after the consumers are updated.
+pub fn open(message: &[u8]) -> Result<Cow<'_, [u8]>, EnvelopeError> {
+    if !message.starts_with(MAGIC) {
+        return Ok(Cow::Borrowed(message));
+    }
+    if message.len() < HEADER_LEN {
+        return Err(EnvelopeError::Truncated(message.len()));
+    }
+    let flags = message[MAGIC.len()];
+    let length = u32::from_be_bytes(message[5..9].try_into().unwrap()) as usize;
+    let checksum = u32::from_be_bytes(message[9..13].try_into().unwrap());
+    let body = &message[HEADER_LEN..];
+
+    if flags & !FLAG_GZIP != 0 {
+        return Err(EnvelopeError::UnknownFlags(flags));
+    }
+    if body.len() != length {
+        return Err(EnvelopeError::Length { expected: length, actual: body.len() });
+    }
+    let actual = crc32(body);
+    if actual != checksum {
+        return Err(EnvelopeError::Checksum { expected: checksum, actual });
+    }
+    if flags & FLAG_GZIP == 0 {
+        return Ok(Cow::Borrowed(body));
+    }
+    let mut payload = Vec::new();
+    // One byte past the limit is enough to know it was exceeded
+    GzDecoder::new(body).take(MAX_PAYLOAD as u64 + 1).read_to_end(&mut payload).map_err(EnvelopeError::Decompress)?;
+    if payload.len() > MAX_PAYLOAD {
+        return Err(EnvelopeError::TooLarge);
+    }
+    Ok(Cow::Owned(payload))
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(bytes);
+    crc.sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sealed_payloads_open_to_the_original() {
+        let large = "sensor=42 status=ok ".repeat(100);
+        let compressed = seal(large.as_bytes(), 512).unwrap();
+        assert_eq!(compressed[MAGIC.len()], FLAG_GZIP);
+        assert!(compressed.len() < large.len());
+        assert_eq!(open(&compressed).unwrap(), large.as_bytes());
+
+        let small = seal(b"short line", 512).unwrap();
+        assert_eq!(small[MAGIC.len()], 0);
+        assert_eq!(&small[HEADER_LEN..], b"short line");
+        assert_eq!(open(&small).unwrap(), &b"short line"[..]);
+
+        assert_eq!(open(b"plain line").unwrap(), &b"plain line"[..]);
+    }
+
+    #[test]
+    fn test_corrupt_envelopes_are_rejected() {
+        let sealed = seal("sensor=42 status=ok ".repeat(100).as_bytes(), 0).unwrap();
+
+        let mut flipped = sealed.clone();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 0x01;
+        assert!(matches!(open(&flipped), Err(EnvelopeError::Checksum { .. })));
+
+        let cut = &sealed[..sealed.len() - 3];
+        assert!(matches!(open(cut), Err(EnvelopeError::Length { expected, actual }) if expected == actual + 3));
+        assert!(matches!(open(&sealed[..8]), Err(EnvelopeError::Truncated(8))));
+
+        let mut flags = sealed.clone();
+        flags[MAGIC.len()] = 0x80;
+        assert!(matches!(open(&flags), Err(EnvelopeError::UnknownFlags(0x80))));
+    }
+
+    #[test]
+    fn test_payload_past_the_limit_is_rejected() {
+        let at_limit = seal(&vec![0; MAX_PAYLOAD], 0).unwrap();
+        assert_eq!(open(&at_limit).unwrap().len(), MAX_PAYLOAD);
+
+        // Compresses to a few kilobytes
+        let bomb = seal(&vec![0; MAX_PAYLOAD + 1], 0).unwrap();
+        assert!(bomb.len() < 64 * 1024, "{}", bomb.len());
+        assert!(matches!(open(&bomb), Err(EnvelopeError::TooLarge)));
+    }
+}
@@ -0,0 +1,240 @@
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Throughput counters for one end of the pipeline, shared between the
+/// produce/poll loop, the `/metrics` endpoint, and the periodic log summary.
+#[derive(Debug)]
+pub struct PipelineMetrics {
+    // "producer" or "consumer", used as the `role` label
+    role: &'static str,
+    messages: AtomicU64,
+    bytes: AtomicU64,
+    errors: AtomicU64,
+    // Estimated messages still to consume; only the consumer reports it
+    lag: Option<AtomicU64>,
+}
+
+/// The counters at one point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    pub messages: u64,
+    pub bytes: u64,
+    pub errors: u64,
+    pub lag: Option<u64>,
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} messages, {} bytes, {} errors", self.messages, self.bytes, self.errors)?;
+        if let Some(lag) = self.lag {
+            write!(f, ", lag {}", lag)?;
+        }
+        Ok(())
+    }
+}
+
+impl PipelineMetrics {
+    pub fn producer() -> Self {
+        Self::new("producer", false)
+    }
+
+    pub fn consumer() -> Self {
+        Self::new("consumer", true)
+    }
+
+    fn new(role: &'static str, tracks_lag: bool) -> Self {
+        PipelineMetrics {
+            role,
+            messages: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            lag: tracks_lag.then(|| AtomicU64::new(0)),
+        }
+    }
+
+    /// Counts a message produced or consumed, with its payload size.
+    pub fn record_message(&self, bytes: usize) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_lag(&self, lag: u64) {
+        if let Some(gauge) = &self.lag {
+            gauge.store(lag, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            messages: self.messages.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            lag: self.lag.as_ref().map(|gauge| gauge.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let snapshot = self.snapshot();
+        let mut out = String::new();
+        let counters = [
+            ("pipeline_messages_total", "Messages handled.", snapshot.messages),
+            ("pipeline_bytes_total", "Payload bytes handled.", snapshot.bytes),
+            ("pipeline_errors_total", "Failed sends, polls, reads, or writes.", snapshot.errors),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{}{{role=\"{}\"}} {}", name, self.role, value);
+        }
+        if let Some(lag) = snapshot.lag {
+            out.push_str("# HELP pipeline_consumer_lag Estimated messages between the consumed and latest offsets.\n");
+            out.push_str("# TYPE pipeline_consumer_lag gauge\n");
+            let _ = writeln!(out, "pipeline_consumer_lag {}", lag);
+        }
+        out
+    }
+}
+
+/// Tracks the next offset to consume per partition, to estimate lag
+/// against the latest offsets the broker reports.
+#[derive(Debug, Default)]
+pub struct LagTracker {
+    next_offsets: BTreeMap<i32, i64>,
+}
+
+impl LagTracker {
+    pub fn consumed(&mut self, partition: i32, offset: i64) {
+        let next = self.next_offsets.entry(partition).or_insert(0);
+        *next = (*next).max(offset + 1);
+    }
+
+    /// Messages between what was consumed and `latest` (partition, offset)
+    /// pairs. Partitions nothing has been consumed from yet are left out,
+    /// since their starting offset is not known here.
+    pub fn lag(&self, latest: impl IntoIterator<Item = (i32, i64)>) -> u64 {
+        latest
+            .into_iter()
+            .filter_map(|(partition, latest)| self.next_offsets.get(&partition).map(|next| (latest - next).max(0) as u64))
+            .sum()
+    }
+}
+
+/// Serves `GET /metrics` on `addr` from a background thread and returns the
+/// bound address. The pipelines are synchronous, so this is a plain
+/// blocking listener rather than a full HTTP server.
+pub fn serve(metrics: Arc<PipelineMetrics>, addr: SocketAddr) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    info!("Serving {} metrics on http://{}/metrics", metrics.role, local_addr);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| respond(stream, &metrics));
+            if let Err(e) = result {
+                warn!("Metrics request failed: {}", e);
+            }
+        }
+    });
+    Ok(local_addr)
+}
+
+fn respond(mut stream: TcpStream, metrics: &PipelineMetrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let (status, content_type, body) = match request_line.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => ("200 OK", "text/plain; version=0.0.4", metrics.render()),
+        _ => ("404 Not Found", "text/plain", "Not Found\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+/// Logs a one-line summary of the counters every `every`, which must not be
+/// zero.
+pub fn log_summaries(metrics: Arc<PipelineMetrics>, every: Duration) {
+    assert!(!every.is_zero(), "metrics summary interval must not be zero");
+    thread::spawn(move || loop {
+        thread::sleep(every);
+        info!("{} metrics: {}", metrics.role, metrics.snapshot());
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_counters_follow_a_produced_batch() {
+        let metrics = PipelineMetrics::producer();
+        for payload in ["alpha", "beta", "gamma"] {
+            metrics.record_message(payload.len());
+        }
+        metrics.record_error();
+
+        assert_eq!(metrics.snapshot(), Snapshot { messages: 3, bytes: 14, errors: 1, lag: None });
+        metrics.set_lag(5);
+        assert_eq!(metrics.snapshot().lag, None);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("pipeline_messages_total{role=\"producer\"} 3\n"), "{}", rendered);
+        assert!(rendered.contains("pipeline_bytes_total{role=\"producer\"} 14\n"));
+        assert!(rendered.contains("pipeline_errors_total{role=\"producer\"} 1\n"));
+        assert!(!rendered.contains("pipeline_consumer_lag"));
+    }
+
+    #[test]
+    fn test_consumed_batch_updates_lag() {
+        let metrics = PipelineMetrics::consumer();
+        let mut tracker = LagTracker::default();
+        // (partition, offset, payload) as a poll would return them
+        for (partition, offset, payload) in [(0, 10, "a"), (0, 11, "bb"), (1, 4, "ccc")] {
+            metrics.record_message(payload.len());
+            tracker.consumed(partition, offset);
+        }
+        // Partition 0 has 20 messages, partition 1 is caught up, partition 2 is unseen
+        metrics.set_lag(tracker.lag([(0, 20), (1, 5), (2, 100)]));
+
+        assert_eq!(metrics.snapshot(), Snapshot { messages: 3, bytes: 6, errors: 0, lag: Some(8) });
+        assert!(metrics.render().contains("pipeline_consumer_lag 8\n"));
+        assert_eq!(metrics.snapshot().to_string(), "3 messages, 6 bytes, 0 errors, lag 8");
+    }
+
+    #[test]
+    fn test_metrics_endpoint() {
+        let metrics = Arc::new(PipelineMetrics::consumer());
+        metrics.record_message(42);
+        let addr = serve(metrics, "127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = get("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("pipeline_bytes_total{role=\"consumer\"} 42\n"));
+        assert!(get("/other").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
@@ -10,6 +10,16 @@ use log::{info, error, warn};
 use signal_hook::{consts::TERM_SIGNALS, iterator::Signals};
 use serde::{Serialize, Deserialize};
 use std::process::exit;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use tracing::info_span;
+use tracing_subscriber::EnvFilter;
+
+#[allow(dead_code)]
+mod envelope;
+
+#[allow(dead_code)]
+mod metrics;
+use metrics::PipelineMetrics;
 
 // Struct for configuration settings
 #[derive(Serialize, Deserialize, Debug)]
@@ -19,6 +29,11 @@ struct Config {
     input_file: String,
     ack_timeout_secs: u64,
     required_acks: i16,
+    metrics_port: Option<u16>, // Serve /metrics on this port when set
+    metrics_bind_addr: IpAddr, // Address the /metrics endpoint listens on
+    metrics_summary_secs: u64, // How often to log a metrics summary
+    envelope: bool,            // Wrap messages in a checksummed envelope
+    compress_min_bytes: usize, // Gzip enveloped messages at least this long
 }
 
 // Default values for configuration
@@ -30,6 +45,11 @@ impl Default for Config {
             input_file: String::from("data/input.txt"),
             ack_timeout_secs: 1,
             required_acks: 1, // Corresponds to RequiredAcks::One
+            metrics_port: None,
+            metrics_bind_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            metrics_summary_secs: 60,
+            envelope: false,
+            compress_min_bytes: 512,
         }
     }
 }
@@ -47,6 +67,23 @@ fn load_config() -> Config {
         .unwrap_or_else(|_| "1".to_string())
         .parse::<i16>()
         .unwrap_or(1);
+    let metrics_port = env::var("METRICS_PORT").ok().and_then(|port| port.parse::<u16>().ok());
+    let metrics_bind_addr = env::var("METRICS_BIND_ADDR")
+        .unwrap_or_else(|_| Ipv4Addr::LOCALHOST.to_string())
+        .parse::<IpAddr>()
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    // A zero interval would log summaries back to back
+    let metrics_summary_secs = env::var("METRICS_SUMMARY_SECS")
+        .unwrap_or_else(|_| "60".to_string())
+        .parse::<u64>()
+        .ok()
+        .filter(|&secs| secs > 0)
+        .unwrap_or(60);
+    let envelope = env::var("MESSAGE_ENVELOPE").map(|value| value == "true" || value == "1").unwrap_or(false);
+    let compress_min_bytes = env::var("COMPRESS_MIN_BYTES")
+        .unwrap_or_else(|_| "512".to_string())
+        .parse::<usize>()
+        .unwrap_or(512);
 
     Config {
         kafka_broker,
@@ -54,12 +91,18 @@ fn load_config() -> Config {
         input_file,
         ack_timeout_secs,
         required_acks,
+        metrics_port,
+        metrics_bind_addr,
+        metrics_summary_secs,
+        envelope,
+        compress_min_bytes,
     }
 }
 
 // Main function
 fn main() {
-    env_logger::init(); // Initialize logger
+    // Log records go through tracing too, so they are printed inside the spans they were logged in
+    tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env()).init();
 
     let config = load_config();
     info!("Loaded configuration: {:?}", config);
@@ -78,6 +121,15 @@ fn main() {
         exit(1);
     });
 
+    let metrics = Arc::new(PipelineMetrics::producer());
+    if let Some(port) = config.metrics_port {
+        if let Err(e) = metrics::serve(metrics.clone(), SocketAddr::new(config.metrics_bind_addr, port)) {
+            error!("Failed to start metrics endpoint: {}", e);
+            exit(1);
+        }
+    }
+    metrics::log_summaries(metrics.clone(), Duration::from_secs(config.metrics_summary_secs));
+
     let reader = BufReader::new(file);
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
@@ -100,17 +152,39 @@ fn main() {
 
         match line {
             Ok(chunk) => {
-                match producer.send(&Record::from_value(&config.topic, chunk.clone())) {
-                    Ok(_) => info!("Sent: {}", chunk),
-                    Err(e) => error!("Failed to send message: {}", e),
+                let _span = info_span!("produce", topic = %config.topic, bytes = chunk.len()).entered();
+                let value = if config.envelope {
+                    match envelope::seal(chunk.as_bytes(), config.compress_min_bytes) {
+                        Ok(sealed) => sealed,
+                        Err(e) => {
+                            metrics.record_error();
+                            error!("Failed to seal message: {}", e);
+                            continue;
+                        }
+                    }
+                } else {
+                    chunk.clone().into_bytes()
+                };
+                match producer.send(&Record::from_value(&config.topic, value.as_slice())) {
+                    Ok(_) => {
+                        metrics.record_message(value.len());
+                        info!("Sent: {}", chunk);
+                    }
+                    Err(e) => {
+                        metrics.record_error();
+                        error!("Failed to send message: {}", e);
+                    }
                 }
             }
-            Err(e) => error!("Failed to read line: {}", e),
+            Err(e) => {
+                metrics.record_error();
+                error!("Failed to read line: {}", e);
+            }
         }
 
         // Simulate processing delay or to avoid tight loop in case of no data
         thread::sleep(Duration::from_millis(100));
     }
 
-    info!("Producer has been stopped");
+    info!("Producer has been stopped after {}", metrics.snapshot());
 }
\ No newline at end of file
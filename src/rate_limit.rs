@@ -0,0 +1,243 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Returned by [`RateLimiter::check`] when a key is over its limit: the
+/// earliest the next request from that key would be allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryAfter(pub Duration);
+
+impl RetryAfter {
+    /// Whole seconds for a `Retry-After` header, rounded up so clients never retry early.
+    pub fn as_secs_ceil(&self) -> u64 {
+        self.0.as_secs() + u64::from(self.0.subsec_nanos() > 0)
+    }
+}
+
+impl fmt::Display for RetryAfter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rate limited, retry after {}s", self.as_secs_ceil())
+    }
+}
+
+/// How a key's allowance is measured.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// Bursts of up to `capacity` requests, then one more every `refill_every`.
+    TokenBucket { capacity: u32, refill_every: Duration },
+    /// At most `limit` requests in any trailing `window`.
+    SlidingWindow { limit: u32, window: Duration },
+}
+
+impl Policy {
+    // After this long without requests a key is back to a full allowance,
+    // so forgetting it changes nothing
+    fn idle_after(&self) -> Duration {
+        match *self {
+            Policy::TokenBucket { capacity, refill_every } => refill_every * capacity,
+            Policy::SlidingWindow { window, .. } => window,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum KeyState {
+    Bucket { tokens: f64, updated: Instant },
+    // Times of the requests still inside the window, oldest first
+    Window(VecDeque<Instant>),
+}
+
+#[derive(Debug)]
+struct Entry {
+    state: KeyState,
+    last_seen: Instant,
+}
+
+/// Per-key rate limiter shared across threads, e.g. keyed by client IP.
+///
+/// Keys idle long enough to have their full allowance back are evicted as
+/// other keys are checked, so the map does not grow with every client ever seen.
+#[derive(Debug)]
+pub struct RateLimiter {
+    policy: Policy,
+    keys: Mutex<HashMap<String, Entry>>,
+    last_sweep: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(policy: Policy) -> Self {
+        let (Policy::TokenBucket { capacity: limit, .. } | Policy::SlidingWindow { limit, .. }) = policy;
+        assert!(limit > 0, "a rate limit must allow at least one request");
+        RateLimiter {
+            policy,
+            keys: Mutex::new(HashMap::new()),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn token_bucket(capacity: u32, refill_every: Duration) -> Self {
+        RateLimiter::new(Policy::TokenBucket { capacity, refill_every })
+    }
+
+    pub fn sliding_window(limit: u32, window: Duration) -> Self {
+        RateLimiter::new(Policy::SlidingWindow { limit, window })
+    }
+
+    /// Counts a request from `key`, or returns how long it must wait.
+    pub fn check(&self, key: &str) -> Result<(), RetryAfter> {
+        self.check_at(key, Instant::now())
+    }
+
+    /// Number of keys currently tracked.
+    pub fn len(&self) -> usize {
+        self.keys.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<(), RetryAfter> {
+        self.sweep(now);
+
+        let mut keys = self.keys.lock().unwrap();
+        let entry = keys.entry(key.to_string()).or_insert_with(|| Entry {
+            state: match self.policy {
+                Policy::TokenBucket { capacity, .. } => KeyState::Bucket { tokens: f64::from(capacity), updated: now },
+                Policy::SlidingWindow { .. } => KeyState::Window(VecDeque::new()),
+            },
+            last_seen: now,
+        });
+        entry.last_seen = now;
+
+        match (&mut entry.state, self.policy) {
+            (KeyState::Bucket { tokens, updated }, Policy::TokenBucket { capacity, refill_every }) => {
+                let refilled = now.saturating_duration_since(*updated).as_secs_f64() / refill_every.as_secs_f64();
+                *tokens = (*tokens + refilled).min(f64::from(capacity));
+                *updated = now;
+                if *tokens >= 1.0 {
+                    *tokens -= 1.0;
+                    Ok(())
+                } else {
+                    Err(RetryAfter(refill_every.mul_f64(1.0 - *tokens)))
+                }
+            }
+            (KeyState::Window(requests), Policy::SlidingWindow { limit, window }) => {
+                while requests.front().is_some_and(|&at| now.saturating_duration_since(at) >= window) {
+                    requests.pop_front();
+                }
+                if requests.len() < limit as usize {
+                    requests.push_back(now);
+                    Ok(())
+                } else {
+                    // The oldest request leaving the window frees the next slot
+                    Err(RetryAfter(requests[0] + window - now))
+                }
+            }
+            _ => unreachable!("key state always matches the limiter's policy"),
+        }
+    }
+
+    // Drop idle keys, at most once per idle period so checks stay cheap
+    fn sweep(&self, now: Instant) {
+        let idle_after = self.policy.idle_after();
+        let mut last_sweep = self.last_sweep.lock().unwrap();
+        if now.saturating_duration_since(*last_sweep) < idle_after {
+            return;
+        }
+        *last_sweep = now;
+        self.keys
+            .lock()
+            .unwrap()
+            .retain(|_, entry| now.saturating_duration_since(entry.last_seen) < idle_after);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn test_token_bucket_allows_burst_then_limits() {
+        let limiter = RateLimiter::token_bucket(3, SECOND);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at("client", now), Ok(()));
+        }
+        assert_eq!(limiter.check_at("client", now), Err(RetryAfter(SECOND)));
+    }
+
+    #[test]
+    fn test_token_bucket_refills_steadily() {
+        let limiter = RateLimiter::token_bucket(2, SECOND);
+        let start = Instant::now();
+        limiter.check_at("client", start).unwrap();
+        limiter.check_at("client", start).unwrap();
+
+        // Half a token back after half a second: not enough yet
+        assert_eq!(limiter.check_at("client", start + SECOND / 2), Err(RetryAfter(SECOND / 2)));
+
+        // Then one request per refill interval, never more
+        for i in 1..=5 {
+            let at = start + SECOND * i;
+            assert_eq!(limiter.check_at("client", at), Ok(()), "request at {}s", i);
+            assert!(limiter.check_at("client", at).is_err(), "second request at {}s", i);
+        }
+
+        // A long pause refills only up to capacity
+        let later = start + SECOND * 60;
+        assert!(limiter.check_at("client", later).is_ok());
+        assert!(limiter.check_at("client", later).is_ok());
+        assert!(limiter.check_at("client", later).is_err());
+    }
+
+    #[test]
+    fn test_keys_are_isolated() {
+        for limiter in [RateLimiter::token_bucket(1, SECOND), RateLimiter::sliding_window(1, SECOND)] {
+            let now = Instant::now();
+            assert!(limiter.check_at("10.0.0.1", now).is_ok());
+            assert!(limiter.check_at("10.0.0.1", now).is_err());
+            assert!(limiter.check_at("10.0.0.2", now).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_sliding_window_resets_as_requests_age_out() {
+        let window = Duration::from_secs(60);
+        let limiter = RateLimiter::sliding_window(2, window);
+        let start = Instant::now();
+
+        limiter.check_at("client", start).unwrap();
+        limiter.check_at("client", start + SECOND * 10).unwrap();
+        assert_eq!(limiter.check_at("client", start + SECOND * 30), Err(RetryAfter(SECOND * 30)));
+
+        // The first request leaves the window at exactly 60s, freeing one slot
+        assert!(limiter.check_at("client", start + window - SECOND / 10).is_err());
+        assert_eq!(limiter.check_at("client", start + window), Ok(()));
+        assert_eq!(limiter.check_at("client", start + window), Err(RetryAfter(SECOND * 10)));
+    }
+
+    #[test]
+    fn test_idle_keys_are_evicted() {
+        let limiter = RateLimiter::sliding_window(5, SECOND);
+        let start = Instant::now();
+        limiter.check_at("old", start).unwrap();
+        limiter.check_at("recent", start + SECOND / 2).unwrap();
+        assert_eq!(limiter.len(), 2);
+
+        // "old" has been idle a full window and is forgotten; "recent" is still limited
+        limiter.check_at("new", start + SECOND * 5 / 4).unwrap();
+        assert_eq!(limiter.len(), 2);
+        assert!(!limiter.keys.lock().unwrap().contains_key("old"));
+    }
+
+    #[test]
+    fn test_retry_after_rounds_up() {
+        assert_eq!(RetryAfter(Duration::from_millis(1500)).as_secs_ceil(), 2);
+        assert_eq!(RetryAfter(SECOND * 3).as_secs_ceil(), 3);
+    }
+}
@@ -0,0 +1,152 @@
+use log::{error, info};
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+
+/// Config a running server can swap out without restarting.
+///
+/// Code that should see reloads calls `get` where it uses the config (per
+/// request, per poll) instead of holding on to one; each call returns a
+/// snapshot that a later reload doesn't change.
+pub struct LiveConfig<T> {
+    current: RwLock<Arc<T>>,
+}
+
+impl<T> LiveConfig<T> {
+    pub fn new(config: T) -> Self {
+        LiveConfig { current: RwLock::new(Arc::new(config)) }
+    }
+
+    pub fn get(&self) -> Arc<T> {
+        self.current.read().unwrap().clone()
+    }
+
+    fn replace(&self, config: T) -> Arc<T> {
+        std::mem::replace(&mut *self.current.write().unwrap(), Arc::new(config))
+    }
+}
+
+/// Reads a config file of `KEY=VALUE` lines, using the same keys as the
+/// environment variables it stands in for. Blank lines and lines starting
+/// with `#` are skipped.
+///
+/// The environment of a running process can't be changed from outside, so
+/// settings meant to be reloadable go in a file like this.
+pub fn read_env_file(path: &Path) -> Result<HashMap<String, String>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.trim().to_string())),
+            _ => Err(format!("{}: lines must be KEY=VALUE, got {:?}", path.display(), line)),
+        })
+        .collect()
+}
+
+/// Adds `field: old -> new` to `changes` if the value differs.
+pub fn diff<V: PartialEq + Debug>(changes: &mut Vec<String>, field: &str, old: &V, new: &V) {
+    if old != new {
+        changes.push(format!("{}: {:?} -> {:?}", field, old, new));
+    }
+}
+
+/// Loads the config again and, if it is valid, swaps it in and logs what
+/// changed according to `changes`. An invalid config is logged and the
+/// current one kept. Returns whether the config was replaced.
+pub fn reload<T, E: Display>(
+    live: &LiveConfig<T>,
+    load: impl FnOnce() -> Result<T, E>,
+    changes: impl FnOnce(&T, &T) -> Vec<String>,
+) -> bool {
+    let config = match load() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Keeping the current configuration, the new one is invalid: {}", e);
+            return false;
+        }
+    };
+    let changed = changes(&live.get(), &config);
+    live.replace(config);
+    if changed.is_empty() {
+        info!("Reloaded configuration, nothing changed");
+    } else {
+        info!("Reloaded configuration: {}", changed.join(", "));
+    }
+    true
+}
+
+/// Calls [`reload`] on a background thread every time the process gets
+/// SIGHUP. The handler is installed before this returns, so a SIGHUP sent
+/// afterwards never stops the process.
+pub fn reload_on_sighup<T, E>(
+    live: Arc<LiveConfig<T>>,
+    load: impl Fn() -> Result<T, E> + Send + 'static,
+    changes: fn(&T, &T) -> Vec<String>,
+) -> io::Result<JoinHandle<()>>
+where
+    T: Send + Sync + 'static,
+    E: Display,
+{
+    let mut signals = Signals::new([SIGHUP])?;
+    Ok(thread::spawn(move || {
+        for _ in signals.forever() {
+            info!("Received SIGHUP, reloading configuration");
+            reload(&live, &load, changes);
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Limits {
+        rate_limit: u32,
+        burst: u32,
+    }
+
+    fn changes(old: &Limits, new: &Limits) -> Vec<String> {
+        let mut changes = Vec::new();
+        diff(&mut changes, "rate_limit", &old.rate_limit, &new.rate_limit);
+        diff(&mut changes, "burst", &old.burst, &new.burst);
+        changes
+    }
+
+    #[test]
+    fn test_env_file_parsing() {
+        let path = std::env::temp_dir().join(format!("noxium-reload-{}.env", std::process::id()));
+        fs::write(&path, "# limits\n\nRATE_LIMIT = 5\nORIGIN_URL=http://origin/?a=b\n").unwrap();
+        let vars = read_env_file(&path).unwrap();
+        assert_eq!(vars.len(), 2);
+        assert_eq!(vars["RATE_LIMIT"], "5");
+        assert_eq!(vars["ORIGIN_URL"], "http://origin/?a=b");
+
+        fs::write(&path, "RATE_LIMIT=5\nBURST\n").unwrap();
+        assert!(read_env_file(&path).unwrap_err().contains("\"BURST\""));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_config_keeps_the_current_one() {
+        let live = LiveConfig::new(Limits { rate_limit: 100, burst: 10 });
+        let before = live.get();
+
+        assert!(!reload(&live, || Err("rate_limit must be greater than 0"), changes));
+        assert_eq!(*live.get(), Limits { rate_limit: 100, burst: 10 });
+
+        assert!(reload(&live, || Ok::<_, String>(Limits { rate_limit: 5, burst: 10 }), changes));
+        assert_eq!(live.get().rate_limit, 5);
+        // Earlier snapshots are left as they were
+        assert_eq!(before.rate_limit, 100);
+        assert_eq!(changes(&before, &live.get()), vec!["rate_limit: 100 -> 5"]);
+    }
+}
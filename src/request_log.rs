@@ -0,0 +1,153 @@
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{Error, HttpMessage};
+use serde_json::json;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::time::Instant;
+use tracing::Instrument;
+use uuid::Uuid;
+
+/// Header carrying the request id in both directions.
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest inbound `X-Request-ID` that is propagated; longer ones are replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The id of the current request, available to handlers through
+/// `req.extensions().get::<RequestId>()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+// Reuse the client's id so traces join up across services, as long as it is
+// something safe to echo into headers and logs
+fn inbound_request_id(req: &ServiceRequest) -> Option<String> {
+    let id = req.headers().get(X_REQUEST_ID)?.to_str().ok()?.trim();
+    let valid = !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic());
+    valid.then(|| id.to_string())
+}
+
+/// Middleware giving every request an id and logging it as one JSON line
+/// with the method, path, status, and latency once the response is ready.
+///
+/// The id comes from the request's `X-Request-ID` header when present,
+/// otherwise a new UUID, and is echoed in the response's `X-Request-ID`.
+/// Handlers run inside a `request` tracing span carrying the same id.
+///
+/// Wrap an app with `.wrap(RequestLogger)`; register it last so it sees
+/// the final status from every other middleware.
+pub struct RequestLogger;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestLogger
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestLoggerMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestLoggerMiddleware { service }))
+    }
+}
+
+pub struct RequestLoggerMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestLoggerMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let request_id = inbound_request_id(&req).unwrap_or_else(|| Uuid::new_v4().to_string());
+        let method = req.method().to_string();
+        let path = req.path().to_string();
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+
+        let span = tracing::info_span!("request", request_id = %request_id, method = %method, path = %path);
+        let start = Instant::now();
+        let fut = span.in_scope(|| self.service.call(req));
+
+        Box::pin(
+            async move {
+                let mut result = fut.await;
+                let status = match &result {
+                    Ok(response) => response.status().as_u16(),
+                    Err(e) => e.as_response_error().status_code().as_u16(),
+                };
+                log::info!(
+                    "{}",
+                    json!({
+                        "request_id": request_id,
+                        "method": method,
+                        "path": path,
+                        "status": status,
+                        "latency_ms": start.elapsed().as_secs_f64() * 1000.0,
+                    })
+                );
+
+                if let (Ok(response), Ok(value)) = (&mut result, HeaderValue::from_str(&request_id)) {
+                    response.headers_mut().insert(X_REQUEST_ID, value);
+                }
+                result
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+
+    // Responds with the id handlers see, so tests can check it matches the header
+    async fn echo_request_id(req: HttpRequest) -> HttpResponse {
+        let id = req.extensions().get::<RequestId>().map(|id| id.0.clone()).unwrap_or_default();
+        HttpResponse::Ok().body(id)
+    }
+
+    #[actix_web::test]
+    async fn test_each_response_gets_a_distinct_request_id() {
+        let app = test::init_service(App::new().wrap(RequestLogger).route("/", web::get().to(echo_request_id))).await;
+
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let response = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+            let header = response.headers().get(X_REQUEST_ID).unwrap().to_str().unwrap().to_string();
+            let body = test::read_body(response).await;
+            assert_eq!(body, header.as_bytes());
+            assert!(Uuid::parse_str(&header).is_ok(), "{}", header);
+            ids.push(header);
+        }
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 3);
+    }
+
+    #[actix_web::test]
+    async fn test_inbound_request_id_is_echoed() {
+        let app = test::init_service(App::new().wrap(RequestLogger).route("/", web::get().to(echo_request_id))).await;
+
+        let request = test::TestRequest::get().uri("/").insert_header((X_REQUEST_ID, "trace-abc-123")).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.headers().get(X_REQUEST_ID).unwrap(), "trace-abc-123");
+        assert_eq!(test::read_body(response).await, "trace-abc-123");
+
+        // Unusable ids are replaced rather than echoed
+        let request = test::TestRequest::get().uri("/").insert_header((X_REQUEST_ID, "has spaces")).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_ne!(response.headers().get(X_REQUEST_ID).unwrap(), "has spaces");
+    }
+}
@@ -1,8 +1,10 @@
-use wasmtime::{Engine, Linker, Module, Store, Instance, Val, Trap};
+use wasmtime::{Engine, Linker, Module, Store, Instance, Val};
 use std::fs::File;
 use std::io::prelude::*;
 use std::env;
 use std::error::Error;
+use std::sync::Arc;
+use thiserror::Error;
 use log::{info, error};
 use tokio::task;
 use futures::future::join_all;
@@ -10,6 +12,35 @@ use hyper::{Body, Request, Response, Server, service::{make_service_fn, service_
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
+#[allow(dead_code)]
+mod shutdown;
+use shutdown::shutdown_signal;
+
+/// Errors from loading, instantiating, or running a sandboxed WASM module.
+#[derive(Debug, Error)]
+pub enum SandboxError {
+    #[error("Failed to load WASM module: {0}")]
+    Load(#[from] std::io::Error),
+    #[error("Failed to instantiate WASM module: {0}")]
+    Instantiate(wasmtime::Error),
+    #[error("Function '{0}' not found in WASM module")]
+    MissingFunction(String),
+    #[error("Execution error: {0}")]
+    Execution(wasmtime::Error),
+    #[error("Sandbox task failed: {0}")]
+    Task(#[from] task::JoinError),
+    #[error("WASM module does not export its memory as '{0}'")]
+    MissingMemory(String),
+    #[error("Snapshot of {snapshot} bytes does not fit memory of {memory} bytes")]
+    SnapshotSize { snapshot: usize, memory: usize },
+}
+
+/// Name under which modules export the linear memory that snapshots cover.
+const MEMORY_EXPORT: &str = "memory";
+
+/// Size of a WASM memory page; memories are always a whole number of them.
+const WASM_PAGE_SIZE: usize = 65536;
+
 /// Loads a WASM module from a file.
 ///
 /// # Arguments
@@ -18,8 +49,8 @@ use serde_json::json;
 ///
 /// # Returns
 ///
-/// * `Result<Vec<u8>, Box<dyn Error>>` - Returns the module bytes or an error.
-fn load_wasm_module(path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+/// * `Result<Vec<u8>, SandboxError>` - Returns the module bytes or an error.
+fn load_wasm_module(path: &str) -> Result<Vec<u8>, SandboxError> {
     info!("Loading WASM module from: {}", path);
     let mut file = File::open(path)?;
     let mut buffer = Vec::new();
@@ -31,43 +62,45 @@ fn load_wasm_module(path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
 ///
 /// # Arguments
 ///
-/// * `wasm_bytes` - The byte code of the WASM module.
+/// * `wasm_bytes` - The byte code (or text format) of the WASM module.
 ///
 /// # Returns
 ///
-/// * `Result<Instance, Box<dyn Error>>` - Returns the instance or an error.
-fn create_wasm_instance(wasm_bytes: &[u8]) -> Result<Instance, Box<dyn Error>> {
+/// * `Result<(Store<()>, Instance), SandboxError>` - Returns the instance with the store that owns it, or an error.
+fn create_wasm_instance(wasm_bytes: &[u8]) -> Result<(Store<()>, Instance), SandboxError> {
     info!("Creating WASM instance");
     let engine = Engine::default();
-    let store = Store::new(&engine);
-    let module = Module::new(&engine, wasm_bytes)?;
-    let mut linker = Linker::new(&engine);
+    let mut store = Store::new(&engine, ());
+    let module = Module::new(&engine, wasm_bytes).map_err(SandboxError::Instantiate)?;
+    let linker = Linker::new(&engine);
 
     // Example configuration for linker
-    // linker.func("env", "log", |s: &str| println!("{}", s))?;
+    // linker.func_wrap("env", "log", |x: i32| println!("{}", x))?;
 
-    let instance = linker.instantiate(&store, &module)?;
-    Ok(instance)
+    let instance = linker.instantiate(&mut store, &module).map_err(SandboxError::Instantiate)?;
+    Ok((store, instance))
 }
 
 /// Executes a function from the WASM instance and processes the result.
 ///
 /// # Arguments
 ///
+/// * `store` - The store owning the instance.
 /// * `instance` - The WASM instance.
 /// * `func_name` - The name of the function to call.
 ///
 /// # Returns
 ///
-/// * `Result<String, Box<dyn Error>>` - Returns the result of the function or an error.
-async fn execute_wasm_function(instance: &Instance, func_name: &str) -> Result<String, Box<dyn Error>> {
+/// * `Result<String, SandboxError>` - Returns the result of the function or an error.
+async fn execute_wasm_function(store: &mut Store<()>, instance: &Instance, func_name: &str) -> Result<String, SandboxError> {
     info!("Executing function: {}", func_name);
-    let func = instance.get_func(func_name)
-        .ok_or_else(|| format!("Function '{}' not found in WASM module", func_name))?;
-    
-    let result = func.call(&[]).map_err(|trap| {
+    let func = instance.get_func(&mut *store, func_name)
+        .ok_or_else(|| SandboxError::MissingFunction(func_name.to_string()))?;
+
+    let mut result = vec![Val::I32(0); func.ty(&*store).results().len()];
+    func.call(&mut *store, &[], &mut result).map_err(|trap| {
         error!("Execution error: {:?}", trap);
-        Box::new(trap) as Box<dyn Error>
+        SandboxError::Execution(trap)
     })?;
 
     let mut output = String::new();
@@ -75,8 +108,8 @@ async fn execute_wasm_function(instance: &Instance, func_name: &str) -> Result<S
         match val {
             Val::I32(i) => output.push_str(&format!("I32: {}\n", i)),
             Val::I64(i) => output.push_str(&format!("I64: {}\n", i)),
-            Val::F32(f) => output.push_str(&format!("F32: {}\n", f)),
-            Val::F64(f) => output.push_str(&format!("F64: {}\n", f)),
+            Val::F32(f) => output.push_str(&format!("F32: {}\n", f32::from_bits(f))),
+            Val::F64(f) => output.push_str(&format!("F64: {}\n", f64::from_bits(f))),
             _ => output.push_str("Other type\n"),
         }
     }
@@ -84,6 +117,64 @@ async fn execute_wasm_function(instance: &Instance, func_name: &str) -> Result<S
     Ok(output)
 }
 
+/// Copies the instance's exported linear memory, to restore later with `restore_memory`.
+///
+/// Only memory is captured: globals and tables keep whatever values they have
+/// when the snapshot is restored.
+///
+/// # Arguments
+///
+/// * `store` - The store owning the instance.
+/// * `instance` - The WASM instance.
+///
+/// # Returns
+///
+/// * `Result<Vec<u8>, SandboxError>` - Returns the memory contents, or an error if the module exports no memory.
+#[allow(dead_code)] // For embedders replaying runs; the server starts each one fresh
+fn snapshot_memory(store: &mut Store<()>, instance: &Instance) -> Result<Vec<u8>, SandboxError> {
+    let memory = instance
+        .get_memory(&mut *store, MEMORY_EXPORT)
+        .ok_or_else(|| SandboxError::MissingMemory(MEMORY_EXPORT.to_string()))?;
+    Ok(memory.data(&*store).to_vec())
+}
+
+/// Puts the instance's exported linear memory back to a snapshot from `snapshot_memory`.
+///
+/// Memory can grow but never shrink, so when it has grown since the snapshot
+/// was taken, the pages past the snapshot are zeroed, as they were then.
+///
+/// # Arguments
+///
+/// * `store` - The store owning the instance.
+/// * `instance` - The WASM instance.
+/// * `snapshot` - Memory contents from `snapshot_memory`.
+///
+/// # Returns
+///
+/// * `Result<(), SandboxError>` - Returns an error if the module exports no memory, or if the snapshot is larger
+///   than the memory or not a whole number of pages.
+#[allow(dead_code)]
+fn restore_memory(store: &mut Store<()>, instance: &Instance, snapshot: &[u8]) -> Result<(), SandboxError> {
+    let memory = instance
+        .get_memory(&mut *store, MEMORY_EXPORT)
+        .ok_or_else(|| SandboxError::MissingMemory(MEMORY_EXPORT.to_string()))?;
+    let data = memory.data_mut(&mut *store);
+    if snapshot.len() > data.len() || !snapshot.len().is_multiple_of(WASM_PAGE_SIZE) {
+        return Err(SandboxError::SnapshotSize { snapshot: snapshot.len(), memory: data.len() });
+    }
+    let (restored, grown) = data.split_at_mut(snapshot.len());
+    restored.copy_from_slice(snapshot);
+    grown.fill(0);
+    Ok(())
+}
+
+// Load, instantiate, and run one module
+async fn run_wasm_module(path: &str, func_name: &str) -> Result<String, SandboxError> {
+    let wasm_bytes = load_wasm_module(path)?;
+    let (mut store, instance) = create_wasm_instance(&wasm_bytes)?;
+    execute_wasm_function(&mut store, &instance, func_name).await
+}
+
 /// Runs multiple WASM modules in parallel.
 ///
 /// # Arguments
@@ -93,39 +184,28 @@ async fn execute_wasm_function(instance: &Instance, func_name: &str) -> Result<S
 ///
 /// # Returns
 ///
-/// * `Result<(), Box<dyn Error>>` - Returns `Ok(())` on success or an error.
-async fn run_parallel_wasm_modules(paths: Vec<&str>, func_name: &str) -> Result<(), Box<dyn Error>> {
-    let tasks: Vec<_> = paths.into_iter().map(|path| {
+/// * `Vec<(String, Result<String, SandboxError>)>` - The output or error of each module, in the order of `paths`.
+///   One module failing does not stop the others.
+async fn run_parallel_wasm_modules(paths: Vec<String>, func_name: &str) -> Vec<(String, Result<String, SandboxError>)> {
+    let func_name: Arc<str> = Arc::from(func_name);
+    let tasks: Vec<_> = paths.iter().cloned().map(|path| {
+        let func_name = func_name.clone();
         task::spawn(async move {
-            let wasm_bytes = match load_wasm_module(path) {
-                Ok(bytes) => bytes,
-                Err(err) => {
-                    error!("Failed to load WASM module from {}: {}", path, err);
-                    return Err(err);
-                }
-            };
-
-            let instance = match create_wasm_instance(&wasm_bytes) {
-                Ok(inst) => inst,
-                Err(err) => {
-                    error!("Failed to create WASM instance from {}: {}", path, err);
-                    return Err(err);
-                }
-            };
-
-            let result = execute_wasm_function(&instance, func_name).await?;
-            info!("Execution result from {}: {}", path, result);
-
-            Ok(())
+            let result = run_wasm_module(&path, &func_name).await;
+            match &result {
+                Ok(output) => info!("Execution result from {}: {}", path, output),
+                Err(err) => error!("Failed to run WASM module {}: {}", path, err),
+            }
+            result
         })
     }).collect();
 
     let results = join_all(tasks).await;
-    for result in results {
-        result??; // Unwrap result
-    }
-
-    Ok(())
+    paths
+        .into_iter()
+        .zip(results)
+        .map(|(path, result)| (path, result.unwrap_or_else(|e| Err(SandboxError::Task(e)))))
+        .collect()
 }
 
 /// Handles HTTP requests for executing WASM code.
@@ -151,9 +231,10 @@ async fn handle_request(req: Request<Body>) -> Result<Response<Body>, hyper::Err
         let func_name = params[1];
         
         // Run the WASM module and execute the function
-        match run_parallel_wasm_modules(vec![wasm_path], func_name).await {
-            Ok(_) => Ok(Response::new(Body::from("Execution completed successfully"))),
-            Err(e) => Ok(Response::new(Body::from(format!("Execution failed: {}", e)))),
+        match run_parallel_wasm_modules(vec![wasm_path.to_string()], func_name).await.pop() {
+            Some((_, Ok(output))) => Ok(Response::new(Body::from(format!("Execution completed successfully\n{}", output)))),
+            Some((_, Err(e))) => Ok(Response::new(Body::from(format!("Execution failed: {}", e)))),
+            None => Ok(Response::new(Body::from("Execution failed: no module was run"))),
         }
     } else {
         Ok(Response::new(Body::from("Invalid request method")))
@@ -175,10 +256,92 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     // Define the HTTP server service
     let make_svc = make_service_fn(|_conn| async { Ok::<_, hyper::Error>(service_fn(handle_request)) });
-    let server = Server::bind(&addr).serve(make_svc);
+    // Finish in-flight requests on ctrl-c or SIGTERM
+    let server = Server::bind(&addr).serve(make_svc).with_graceful_shutdown(shutdown_signal());
 
     info!("Listening on http://{}", addr);
     server.await?;
     
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_failing_module_does_not_hide_other_results() {
+        let dir = env::temp_dir().join(format!("noxium-sandboxer-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let good = dir.join("good.wat");
+        let trapping = dir.join("trapping.wat");
+        std::fs::write(&good, r#"(module (func (export "run") (result i32) i32.const 42))"#).unwrap();
+        std::fs::write(&trapping, r#"(module (func (export "run") (result i32) unreachable))"#).unwrap();
+
+        let paths = vec![trapping.display().to_string(), good.display().to_string()];
+        let results = run_parallel_wasm_modules(paths.clone(), "run").await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, paths[0]);
+        assert!(matches!(results[0].1, Err(SandboxError::Execution(_))), "{:?}", results[0].1);
+        assert_eq!(results[1].0, paths[1]);
+        assert_eq!(results[1].1.as_ref().unwrap(), "I32: 42\n");
+    }
+
+    // `bump` increments the counter at address 0 and returns it; `grow` adds a page and writes to the second one
+    const COUNTER_MODULE: &str = r#"(module
+        (memory (export "memory") 1 4)
+        (func (export "bump") (result i32)
+            (i32.store (i32.const 0) (i32.add (i32.load (i32.const 0)) (i32.const 1)))
+            (i32.load (i32.const 0)))
+        (func (export "grow") (result i32)
+            (drop (memory.grow (i32.const 1)))
+            (i32.store (i32.const 65536) (i32.const 7))
+            (memory.size)))"#;
+
+    #[tokio::test]
+    async fn test_restoring_a_snapshot_replays_from_the_same_state() {
+        let (mut store, instance) = create_wasm_instance(COUNTER_MODULE.as_bytes()).unwrap();
+        assert_eq!(execute_wasm_function(&mut store, &instance, "bump").await.unwrap(), "I32: 1\n");
+        let snapshot = snapshot_memory(&mut store, &instance).unwrap();
+        assert_eq!(snapshot.len(), WASM_PAGE_SIZE);
+        assert_eq!(&snapshot[..4], &1i32.to_le_bytes());
+
+        execute_wasm_function(&mut store, &instance, "bump").await.unwrap();
+        execute_wasm_function(&mut store, &instance, "bump").await.unwrap();
+        restore_memory(&mut store, &instance, &snapshot).unwrap();
+        assert_eq!(snapshot_memory(&mut store, &instance).unwrap(), snapshot);
+        assert_eq!(execute_wasm_function(&mut store, &instance, "bump").await.unwrap(), "I32: 2\n");
+    }
+
+    #[tokio::test]
+    async fn test_restore_checks_the_snapshot_size() {
+        let (mut store, instance) = create_wasm_instance(COUNTER_MODULE.as_bytes()).unwrap();
+        let one_page = snapshot_memory(&mut store, &instance).unwrap();
+
+        // Memory that grew since the snapshot keeps its size, with the new pages zeroed
+        execute_wasm_function(&mut store, &instance, "grow").await.unwrap();
+        execute_wasm_function(&mut store, &instance, "grow").await.unwrap();
+        let grown = snapshot_memory(&mut store, &instance).unwrap();
+        assert_eq!(grown[WASM_PAGE_SIZE], 7);
+        restore_memory(&mut store, &instance, &one_page).unwrap();
+        let restored = snapshot_memory(&mut store, &instance).unwrap();
+        assert_eq!(restored.len(), 3 * WASM_PAGE_SIZE);
+        assert!(restored.iter().all(|&byte| byte == 0));
+
+        let (mut store, instance) = create_wasm_instance(COUNTER_MODULE.as_bytes()).unwrap();
+        let result = restore_memory(&mut store, &instance, &grown);
+        assert!(
+            matches!(result, Err(SandboxError::SnapshotSize { snapshot, memory })
+                if snapshot == 3 * WASM_PAGE_SIZE && memory == WASM_PAGE_SIZE),
+            "{:?}",
+            result
+        );
+        let result = restore_memory(&mut store, &instance, &one_page[..100]);
+        assert!(matches!(result, Err(SandboxError::SnapshotSize { .. })), "{:?}", result);
+
+        let (mut store, instance) = create_wasm_instance(br#"(module (func (export "run")))"#).unwrap();
+        assert!(matches!(snapshot_memory(&mut store, &instance), Err(SandboxError::MissingMemory(_))));
+    }
+}
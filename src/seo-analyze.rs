@@ -1,23 +1,69 @@
+use flate2::read::GzDecoder;
+use quick_xml::events::Event;
 use reqwest::blocking::Client;
 use scraper::{Html, Selector};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Read;
+use url::Url;
+
+#[allow(dead_code)]
+pub mod http;
 
 fn main() {
     let url = "https://example.com"; // Replace with the URL you want to analyze
 
     // Analyze the SEO and print the results or errors
-    match analyze_seo(url) {
-        Ok(result) => println!("{:#?}", result), // Pretty-print the SEO results
-        Err(e) => println!("Error: {}", e), // Print any errors encountered
+    let result = match analyze_seo(url, &http::HttpConfig::from_env()) {
+        Ok(result) => result,
+        Err(e) => {
+            println!("Error: {}", e); // Print any errors encountered
+            return;
+        }
+    };
+    println!("{:#?}", result); // Pretty-print the SEO results
+
+    // With a rules file (SEO_RULES), fail the build when the page breaks any of them
+    if let Ok(path) = std::env::var("SEO_RULES") {
+        let rules = match load_rules(&path) {
+            Ok(rules) => rules,
+            Err(e) => {
+                eprintln!("Failed to load SEO rules from {}: {}", path, e);
+                std::process::exit(2);
+            }
+        };
+        let violations = result.check_against(&rules);
+        for violation in &violations {
+            eprintln!("SEO check failed: {}", violation);
+        }
+        if !violations.is_empty() {
+            std::process::exit(1);
+        }
     }
 }
 
-// Function to analyze various SEO aspects of a webpage
-fn analyze_seo(url: &str) -> Result<SeoResult, Box<dyn std::error::Error>> {
-    let client = Client::new(); // Create a new HTTP client
-    let response = client.get(url).send()?.text()?; // Send a GET request and get the response text
+// Function to analyze various SEO aspects of a webpage, fetching it with the given limits and policy
+fn analyze_seo(url: &str, config: &http::HttpConfig) -> Result<SeoResult, Box<dyn std::error::Error>> {
+    http::check_url_with(url, &config.fetch_policy)?; // Refuse loopback, private, and non-http targets first
+    let client = http::blocking_client_with(config); // Create an HTTP client with timeouts
+    let response = http::blocking_text(client.get(url).send()?)?; // Send a GET request and read the decompressed text
+
+    let origin = site_origin(url)?; // robots.txt and sitemap.xml live at the site root, not under the page
+    let robots_txt = check_robots_txt(&client, &origin)?;
+    let has_robots_txt = robots_txt.is_some();
+    let declared_sitemaps = robots_txt.as_deref().map(sitemap_locations).unwrap_or_default();
+    let sitemap = check_sitemap(&client, &origin, &declared_sitemaps)?;
+    let mut result = analyze_page(&response, url, has_robots_txt, sitemap.is_some()); // Analyze the fetched page
+    if let Some(entries) = &sitemap {
+        result.links_not_in_sitemap = links_missing_from_sitemap(&response, url, entries);
+    }
+    Ok(result)
+}
 
-    let document = Html::parse_document(&response); // Parse the HTML content into a document structure
+// Function to analyze an already fetched page; site-wide checks are passed in so no requests are made
+pub fn analyze_page(html: &str, url: &str, has_robots_txt: bool, has_sitemap: bool) -> SeoResult {
+    let document = Html::parse_document(html); // Parse the HTML content into a document structure
 
     // Extract various SEO elements using helper functions
     let title = get_title(&document);
@@ -29,14 +75,15 @@ fn analyze_seo(url: &str) -> Result<SeoResult, Box<dyn std::error::Error>> {
     let external_links = get_external_links(&document, url);
     let meta_keywords = get_meta_keywords(&document);
     let content_length = get_content_length(&document);
-    let has_robots_txt = check_robots_txt(url)?;
-    let has_sitemap = check_sitemap(url)?;
     let meta_tag_count = count_meta_tags(&document);
     let external_js_css_count = count_external_js_css(&document);
     let nofollow_links_count = count_nofollow_links(&document);
+    let body_text = get_body_text(&document);
+    let readability_ease = flesch_reading_ease(&body_text);
+    let readability_grade = flesch_kincaid_grade(&body_text);
 
     // Return all collected SEO data encapsulated in a structured format
-    Ok(SeoResult {
+    SeoResult {
         title,
         meta_description,
         heading_counts,
@@ -51,7 +98,10 @@ fn analyze_seo(url: &str) -> Result<SeoResult, Box<dyn std::error::Error>> {
         meta_tag_count,
         external_js_css_count,
         nofollow_links_count,
-    })
+        readability_ease,
+        readability_grade,
+        links_not_in_sitemap: Vec::new(), // Filled in by the caller once the sitemap is known
+    }
 }
 
 // Function to extract the title of the webpage
@@ -152,20 +202,270 @@ fn get_content_length(document: &Html) -> usize {
     }
 }
 
-// Function to check if a site has a robots.txt file
-fn check_robots_txt(url: &str) -> Result<bool, Box<dyn std::error::Error>> {
-    let robots_txt_url = format!("{}/robots.txt", url); // Construct the URL for robots.txt
-    let client = Client::new();
-    let response = client.get(&robots_txt_url).send()?; // Send a GET request to check if robots.txt exists
-    Ok(response.status().is_success()) // Return true if the request is successful
+// Function to collect the visible text of the <body> for readability scoring
+fn get_body_text(document: &Html) -> String {
+    let selector = Selector::parse("body").unwrap(); // Create a selector for the <body> tag
+    document
+        .select(&selector)
+        .next()
+        .map(|body| body.text().collect::<Vec<_>>().join(" ")) // Join all text nodes with spaces
+        .unwrap_or_default() // Empty string if the body is not found
+}
+
+// Function to count sentences, words, and syllables in a block of text
+fn text_statistics(text: &str) -> (usize, usize, usize) {
+    let words: Vec<&str> = text
+        .split_whitespace()
+        .filter(|w| w.chars().any(|c| c.is_alphanumeric())) // Ignore stray punctuation
+        .collect();
+    let syllables = words.iter().map(|w| count_syllables(w)).sum(); // Total syllables across all words
+    let sentences = text
+        .split(['.', '!', '?'])
+        .filter(|s| s.chars().any(|c| c.is_alphanumeric())) // Only count fragments containing words
+        .count()
+        .max(if words.is_empty() { 0 } else { 1 }); // Text without terminators is still one sentence
+    (sentences, words.len(), syllables)
+}
+
+// Function to estimate syllables in a word by counting vowel groups
+fn count_syllables(word: &str) -> usize {
+    let word: String = word.chars().filter(|c| c.is_alphabetic()).flat_map(char::to_lowercase).collect();
+    if word.is_empty() {
+        return 0; // Numbers and symbols carry no syllables
+    }
+
+    let is_vowel = |c: char| matches!(c, 'a' | 'e' | 'i' | 'o' | 'u' | 'y');
+    let mut count = 0;
+    let mut previous_vowel = false;
+    for c in word.chars() {
+        let vowel = is_vowel(c);
+        if vowel && !previous_vowel {
+            count += 1; // Each run of vowels is one syllable
+        }
+        previous_vowel = vowel;
+    }
+
+    // A trailing silent "e" (but not "-le") doesn't add a syllable
+    if word.ends_with('e') && !word.ends_with("le") && count > 1 {
+        count -= 1;
+    }
+    count.max(1) // Every word has at least one syllable
+}
+
+// Function to compute the Flesch reading ease score (higher is easier to read)
+fn flesch_reading_ease(text: &str) -> f64 {
+    let (sentences, words, syllables) = text_statistics(text);
+    if sentences == 0 || words == 0 {
+        return 0.0; // Nothing to score
+    }
+    let words_per_sentence = words as f64 / sentences as f64;
+    let syllables_per_word = syllables as f64 / words as f64;
+    206.835 - 1.015 * words_per_sentence - 84.6 * syllables_per_word
+}
+
+// Function to compute the Flesch-Kincaid grade level (US school grade)
+fn flesch_kincaid_grade(text: &str) -> f64 {
+    let (sentences, words, syllables) = text_statistics(text);
+    if sentences == 0 || words == 0 {
+        return 0.0; // Nothing to score
+    }
+    let words_per_sentence = words as f64 / sentences as f64;
+    let syllables_per_word = syllables as f64 / words as f64;
+    0.39 * words_per_sentence + 11.8 * syllables_per_word - 15.59
+}
+
+// Function to derive the origin (scheme://host[:port]/) of a page URL
+fn site_origin(url: &str) -> Result<Url, url::ParseError> {
+    Url::parse(url)?.join("/") // Joining an absolute path drops the page path, query, and fragment
+}
+
+// Function to fetch a site's robots.txt, returning its contents if it exists
+fn check_robots_txt(client: &Client, origin: &Url) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let robots_txt_url = origin.join("/robots.txt")?; // Construct the URL for robots.txt
+    let response = client.get(robots_txt_url).send()?; // Send a GET request to check if robots.txt exists
+    if !response.status().is_success() {
+        return Ok(None); // No robots.txt on this site
+    }
+    Ok(Some(http::blocking_text(response)?))
+}
+
+// Function to extract the `Sitemap:` locations declared in a robots.txt file
+fn sitemap_locations(robots_txt: &str) -> Vec<String> {
+    robots_txt
+        .lines()
+        .filter_map(|line| {
+            let line = line.split('#').next().unwrap_or("").trim(); // Ignore comments
+            let (field, value) = line.split_once(':')?;
+            (field.trim().eq_ignore_ascii_case("sitemap") && !value.trim().is_empty()).then(|| value.trim().to_string())
+        })
+        .collect()
+}
+
+// Function to find a site's sitemap, trying declared locations before /sitemap.xml, and read its entries
+fn check_sitemap(client: &Client, origin: &Url, declared: &[String]) -> Result<Option<Vec<SitemapEntry>>, Box<dyn std::error::Error>> {
+    let mut candidates = Vec::new();
+    for location in declared {
+        candidates.push(origin.join(location)?); // Relative locations resolve against the origin
+    }
+    if candidates.is_empty() {
+        candidates.push(origin.join("/sitemap.xml")?); // Fall back to the conventional location
+    }
+
+    for sitemap_url in candidates {
+        if let Some(body) = fetch_sitemap(client, &sitemap_url)? {
+            let mut entries = Vec::new();
+            collect_sitemap_entries(client, &sitemap_url, &body, 0, &mut entries)?;
+            return Ok(Some(entries));
+        }
+    }
+    Ok(None)
+}
+
+// A page listed in a sitemap
+#[derive(Debug, Clone, PartialEq)]
+pub struct SitemapEntry {
+    pub loc: String, // Absolute URL of the page
+    pub lastmod: Option<String>, // W3C datetime of the last change, as written
+    pub priority: Option<f64>, // Relative priority from 0.0 to 1.0
+}
+
+// The two kinds of sitemap file: a list of pages, or an index of other sitemaps
+#[derive(Debug, PartialEq)]
+enum Sitemap {
+    UrlSet(Vec<SitemapEntry>),
+    Index(Vec<String>),
+}
+
+// Indexes may not nest per the protocol; this bounds misbehaving sites
+const MAX_SITEMAP_DEPTH: usize = 3;
+
+// Function to fetch and parse a sitemap, following sitemap indexes to their child sitemaps
+pub fn parse_sitemap(url: &str, config: &http::HttpConfig) -> Result<Vec<SitemapEntry>, Box<dyn std::error::Error>> {
+    let client = http::blocking_client_with(config);
+    let url = http::check_url_with(url, &config.fetch_policy)?;
+    let body = fetch_sitemap(&client, &url)?.ok_or_else(|| format!("No sitemap at {}", url))?;
+    let mut entries = Vec::new();
+    collect_sitemap_entries(&client, &url, &body, 0, &mut entries)?;
+    Ok(entries)
+}
+
+// Function to download a sitemap's raw bytes, or None if the server doesn't have it
+fn fetch_sitemap(client: &Client, url: &Url) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    let response = client.get(url.clone()).send()?;
+    if !response.status().is_success() {
+        return Ok(None);
+    }
+    Ok(Some(http::blocking_bytes(response)?))
+}
+
+// Function to add the entries of a fetched sitemap, recursing into the children of an index
+fn collect_sitemap_entries(client: &Client, url: &Url, body: &[u8], depth: usize, entries: &mut Vec<SitemapEntry>) -> Result<(), Box<dyn std::error::Error>> {
+    match parse_sitemap_xml(&decode_sitemap(body)?)? {
+        Sitemap::UrlSet(found) => entries.extend(found),
+        Sitemap::Index(children) => {
+            if depth >= MAX_SITEMAP_DEPTH {
+                return Err(format!("Sitemap indexes nested too deeply at {}", url).into());
+            }
+            for child in children {
+                let child_url = url.join(&child)?;
+                // A missing child sitemap leaves the others usable
+                if let Some(child_body) = fetch_sitemap(client, &child_url)? {
+                    collect_sitemap_entries(client, &child_url, &child_body, depth + 1, entries)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Function to turn sitemap bytes into text, gunzipping `sitemap.xml.gz` style files
+fn decode_sitemap(body: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+    // Servers send .gz sitemaps as-is, so detect gzip by its magic bytes rather than headers
+    if body.starts_with(&[0x1f, 0x8b]) {
+        let mut xml = String::new();
+        GzDecoder::new(body).read_to_string(&mut xml)?;
+        Ok(xml)
+    } else {
+        Ok(String::from_utf8(body.to_vec())?)
+    }
 }
 
-// Function to check if a site has a sitemap
-fn check_sitemap(url: &str) -> Result<bool, Box<dyn std::error::Error>> {
-    let sitemap_url = format!("{}/sitemap.xml", url); // Construct the URL for sitemap.xml
-    let client = Client::new();
-    let response = client.get(&sitemap_url).send()?; // Send a GET request to check if sitemap.xml exists
-    Ok(response.status().is_success()) // Return true if the request is successful
+// Function to parse a `<urlset>` or `<sitemapindex>` document
+fn parse_sitemap_xml(xml: &str) -> Result<Sitemap, Box<dyn std::error::Error>> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut is_index = false;
+    let (mut entries, mut children) = (Vec::new(), Vec::new());
+    let mut entry: Option<SitemapEntry> = None;
+    let (mut field, mut raw) = (None::<String>, String::new());
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(tag) => match tag.local_name().into_inner() {
+                "sitemapindex" => is_index = true,
+                "url" | "sitemap" => entry = Some(SitemapEntry { loc: String::new(), lastmod: None, priority: None }),
+                name @ ("loc" | "lastmod" | "priority") if entry.is_some() => {
+                    field = Some(name.to_string());
+                    raw.clear();
+                }
+                _ => {}
+            },
+            Event::Text(text) if field.is_some() => raw.push_str(&text.xml10_content()),
+            Event::CData(text) if field.is_some() => raw.push_str(&quick_xml::escape::escape(text.into_inner())),
+            // Entities such as `&amp;` in query strings arrive as their own events
+            Event::GeneralRef(reference) if field.is_some() => raw.push_str(&format!("&{};", &*reference)),
+            Event::End(tag) => match tag.local_name().into_inner() {
+                "url" | "sitemap" => {
+                    if let Some(done) = entry.take().filter(|done| !done.loc.is_empty()) {
+                        if is_index {
+                            children.push(done.loc);
+                        } else {
+                            entries.push(done);
+                        }
+                    }
+                }
+                _ => {
+                    if let (Some(name), Some(current)) = (field.take(), entry.as_mut()) {
+                        let value = quick_xml::escape::unescape(&raw)?.trim().to_string();
+                        match name.as_str() {
+                            "loc" => current.loc = value,
+                            "lastmod" => current.lastmod = Some(value),
+                            _ => current.priority = value.parse().ok(),
+                        }
+                    }
+                }
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(if is_index { Sitemap::Index(children) } else { Sitemap::UrlSet(entries) })
+}
+
+// Function to list internal links on a page whose targets aren't in the sitemap
+fn links_missing_from_sitemap(html: &str, page_url: &str, entries: &[SitemapEntry]) -> Vec<String> {
+    // Fragments point into a page rather than at a different one
+    let normalize = |mut url: Url| {
+        url.set_fragment(None);
+        url.to_string()
+    };
+    let Ok(base) = Url::parse(page_url) else {
+        return Vec::new();
+    };
+    let listed: HashSet<String> = entries.iter().filter_map(|entry| Url::parse(&entry.loc).ok()).map(normalize).collect();
+
+    let document = Html::parse_document(html);
+    let selector = Selector::parse("a[href]").unwrap(); // Create a selector for anchor tags with href attributes
+    let mut missing: Vec<String> = document
+        .select(&selector)
+        .filter_map(|a| base.join(a.value().attr("href")?).ok())
+        .filter(|link| link.origin() == base.origin())
+        .map(normalize)
+        .filter(|link| !listed.contains(link))
+        .collect();
+    missing.sort();
+    missing.dedup();
+    missing
 }
 
 // Function to count the number of meta tags on the webpage
@@ -202,19 +502,311 @@ fn count_nofollow_links(document: &Html) -> usize {
 
 // Struct to encapsulate the SEO results
 #[derive(Debug)]
-struct SeoResult {
-    title: Option<String>, // Title of the webpage
-    meta_description: Option<String>, // Meta description of the webpage
-    heading_counts: Vec<(String, usize)>, // Counts of heading tags (h1 to h6)
-    image_alt_count: usize, // Count of images with alt attributes
-    word_count: usize, // Count of words on the webpage
-    internal_links: usize, // Count of internal links on the webpage
-    external_links: usize, // Count of external links on the webpage
-    meta_keywords: Option<String>, // Meta keywords of the webpage
-    content_length: usize, // Length of the content on the webpage
-    has_robots_txt: bool, // Indicates if the site has a robots.txt file
-    has_sitemap: bool, // Indicates if the site has a sitemap.xml file
-    meta_tag_count: usize, // Count of meta tags on the webpage
-    external_js_css_count: HashMap<String, usize>, // Counts of external JavaScript and CSS files
-    nofollow_links_count: usize, // Count of links with "nofollow" attribute
-}
\ No newline at end of file
+pub struct SeoResult {
+    pub title: Option<String>, // Title of the webpage
+    pub meta_description: Option<String>, // Meta description of the webpage
+    pub heading_counts: Vec<(String, usize)>, // Counts of heading tags (h1 to h6)
+    pub image_alt_count: usize, // Count of images with alt attributes
+    pub word_count: usize, // Count of words on the webpage
+    pub internal_links: usize, // Count of internal links on the webpage
+    pub external_links: usize, // Count of external links on the webpage
+    pub meta_keywords: Option<String>, // Meta keywords of the webpage
+    pub content_length: usize, // Length of the content on the webpage
+    pub has_robots_txt: bool, // Indicates if the site has a robots.txt file
+    pub has_sitemap: bool, // Indicates if the site has a sitemap.xml file
+    pub meta_tag_count: usize, // Count of meta tags on the webpage
+    pub external_js_css_count: HashMap<String, usize>, // Counts of external JavaScript and CSS files
+    pub nofollow_links_count: usize, // Count of links with "nofollow" attribute
+    pub readability_ease: f64, // Flesch reading ease of the body text
+    pub readability_grade: f64, // Flesch-Kincaid grade level of the body text
+    pub links_not_in_sitemap: Vec<String>, // Internal links whose pages the sitemap doesn't list
+}
+
+// Thresholds a page must meet, e.g. for a CI build to fail on SEO regressions.
+// Unset fields are not checked, so a rules file only lists the ones it cares about
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SeoRules {
+    pub min_word_count: Option<usize>, // Fewest words the body may have
+    pub require_meta_description: bool, // Whether a non-empty meta description is required
+    pub max_external_links: Option<usize>, // Most links to other sites
+    pub min_title_length: Option<usize>, // Shortest title, in characters; a missing title fails either bound
+    pub max_title_length: Option<usize>, // Longest title, in characters
+}
+
+// A rule a page broke, with the threshold and what the page had
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum SeoViolation {
+    WordCountTooLow { min: usize, actual: usize },
+    MissingMetaDescription,
+    TooManyExternalLinks { max: usize, actual: usize },
+    MissingTitle,
+    TitleTooShort { min: usize, actual: usize },
+    TitleTooLong { max: usize, actual: usize },
+}
+
+impl fmt::Display for SeoViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SeoViolation::WordCountTooLow { min, actual } => write!(f, "{} words, at least {} required", actual, min),
+            SeoViolation::MissingMetaDescription => write!(f, "no meta description"),
+            SeoViolation::TooManyExternalLinks { max, actual } => {
+                write!(f, "{} external links, at most {} allowed", actual, max)
+            }
+            SeoViolation::MissingTitle => write!(f, "no title"),
+            SeoViolation::TitleTooShort { min, actual } => {
+                write!(f, "title is {} characters, at least {} required", actual, min)
+            }
+            SeoViolation::TitleTooLong { max, actual } => {
+                write!(f, "title is {} characters, at most {} allowed", actual, max)
+            }
+        }
+    }
+}
+
+// Function to read rules from a JSON file
+fn load_rules(path: &str) -> Result<SeoRules, Box<dyn std::error::Error>> {
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+impl SeoResult {
+    // Function to list the rules this page breaks, empty when it passes them all
+    pub fn check_against(&self, rules: &SeoRules) -> Vec<SeoViolation> {
+        let mut violations = Vec::new();
+        if let Some(min) = rules.min_word_count.filter(|&min| self.word_count < min) {
+            violations.push(SeoViolation::WordCountTooLow { min, actual: self.word_count });
+        }
+        let has_description = self.meta_description.as_deref().is_some_and(|text| !text.trim().is_empty());
+        if rules.require_meta_description && !has_description {
+            violations.push(SeoViolation::MissingMetaDescription);
+        }
+        if let Some(max) = rules.max_external_links.filter(|&max| self.external_links > max) {
+            violations.push(SeoViolation::TooManyExternalLinks { max, actual: self.external_links });
+        }
+        if rules.min_title_length.is_some() || rules.max_title_length.is_some() {
+            match self.title.as_deref().map(|title| title.trim().chars().count()).filter(|&len| len > 0) {
+                None => violations.push(SeoViolation::MissingTitle),
+                Some(actual) => {
+                    if let Some(min) = rules.min_title_length.filter(|&min| actual < min) {
+                        violations.push(SeoViolation::TitleTooShort { min, actual });
+                    }
+                    if let Some(max) = rules.max_title_length.filter(|&max| actual > max) {
+                        violations.push(SeoViolation::TitleTooLong { max, actual });
+                    }
+                }
+            }
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    #[test]
+    fn test_simple_text_is_easier_than_complex_text() {
+        let simple = "The cat sat on the mat. It was a good cat.";
+        let complex = "Notwithstanding considerable institutional opposition, the administration \
+                       systematically implemented comprehensive organizational restructuring initiatives.";
+
+        assert!(flesch_reading_ease(simple) > flesch_reading_ease(complex));
+        assert!(flesch_kincaid_grade(simple) < flesch_kincaid_grade(complex));
+    }
+
+    #[test]
+    fn test_empty_text_scores_zero() {
+        assert_eq!(flesch_reading_ease(""), 0.0);
+        assert_eq!(flesch_kincaid_grade("   ... !! "), 0.0);
+        assert!(flesch_reading_ease("Hello").is_finite());
+    }
+
+    // The fetch policy refuses loopback targets unless told otherwise, as `serve` needs
+    fn trusting() -> http::HttpConfig {
+        let fetch_policy = http::FetchPolicy { allow_private: true, ..http::FetchPolicy::default() };
+        http::HttpConfig { fetch_policy, ..http::HttpConfig::default() }
+    }
+
+    // Serve `files` (path -> body) over HTTP, recording every requested path
+    fn serve(files: &'static [(&'static str, &'static str)]) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let log = requested.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request_line = String::new();
+                BufReader::new(&stream).read_line(&mut request_line).unwrap();
+                let path = request_line.split_whitespace().nth(1).unwrap_or("/").to_string();
+                let response = match files.iter().find(|(file, _)| *file == path) {
+                    Some((_, body)) => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body),
+                    None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                };
+                log.lock().unwrap().push(path);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (base, requested)
+    }
+
+    #[test]
+    fn test_site_origin_drops_page_path() {
+        assert_eq!(site_origin("https://site.com/blog/post?page=2#top").unwrap().as_str(), "https://site.com/");
+        assert_eq!(site_origin("http://localhost:8080/a/b/").unwrap().as_str(), "http://localhost:8080/");
+        assert!(site_origin("not a url").is_err());
+    }
+
+    #[test]
+    fn test_deep_page_checks_origin_root() {
+        let (base, requested) = serve(&[
+            ("/blog/post", "<html><head><title>Post</title></head><body>Hello there.</body></html>"),
+            ("/robots.txt", "User-agent: *\nDisallow: /private\n"),
+            ("/sitemap.xml", "<urlset></urlset>"),
+        ]);
+
+        let result = analyze_seo(&format!("{}/blog/post", base), &trusting()).unwrap();
+        assert!(result.has_robots_txt);
+        assert!(result.has_sitemap);
+        assert_eq!(*requested.lock().unwrap(), vec!["/blog/post", "/robots.txt", "/sitemap.xml"]);
+    }
+
+    #[test]
+    fn test_declared_sitemap_is_used() {
+        let robots = "User-agent: *\n# Sitemap: /ignored.xml\nsitemap: /maps/index.xml\n";
+        assert_eq!(sitemap_locations(robots), vec!["/maps/index.xml"]);
+
+        let (base, requested) = serve(&[
+            ("/docs/guide", "<html><body>Guide.</body></html>"),
+            ("/robots.txt", "User-agent: *\n# Sitemap: /ignored.xml\nsitemap: /maps/index.xml\n"),
+            ("/maps/index.xml", "<sitemapindex></sitemapindex>"),
+        ]);
+
+        let result = analyze_seo(&format!("{}/docs/guide", base), &trusting()).unwrap();
+        assert!(result.has_sitemap);
+        assert_eq!(*requested.lock().unwrap(), vec!["/docs/guide", "/robots.txt", "/maps/index.xml"]);
+    }
+
+    #[test]
+    fn test_urlset_entries_are_parsed() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <url><loc>https://site.com/</loc><lastmod>2024-05-01</lastmod><priority>1.0</priority></url>
+              <url><loc> https://site.com/search?q=a&amp;page=2 </loc></url>
+              <url><lastmod>2024-01-01</lastmod></url>
+            </urlset>"#;
+
+        let Sitemap::UrlSet(entries) = parse_sitemap_xml(xml).unwrap() else {
+            panic!("expected a urlset");
+        };
+        assert_eq!(entries, vec![
+            SitemapEntry { loc: "https://site.com/".into(), lastmod: Some("2024-05-01".into()), priority: Some(1.0) },
+            SitemapEntry { loc: "https://site.com/search?q=a&page=2".into(), lastmod: None, priority: None },
+        ]);
+    }
+
+    #[test]
+    fn test_gzipped_sitemap_is_decoded() {
+        let xml = "<urlset><url><loc>https://site.com/a</loc></url></urlset>";
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(xml.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        assert_eq!(decode_sitemap(&gzipped).unwrap(), xml);
+        assert_eq!(decode_sitemap(xml.as_bytes()).unwrap(), xml);
+    }
+
+    #[test]
+    fn test_sitemap_index_is_followed() {
+        let (base, requested) = serve(&[
+            ("/sitemap_index.xml", "<sitemapindex><sitemap><loc>/posts.xml</loc></sitemap><sitemap><loc>/gone.xml</loc></sitemap><sitemap><loc>/pages.xml</loc></sitemap></sitemapindex>"),
+            ("/posts.xml", "<urlset><url><loc>https://site.com/posts/1</loc></url></urlset>"),
+            ("/pages.xml", "<urlset><url><loc>https://site.com/about</loc></url></urlset>"),
+        ]);
+
+        let entries = parse_sitemap(&format!("{}/sitemap_index.xml", base), &trusting()).unwrap();
+        let locs: Vec<_> = entries.iter().map(|entry| entry.loc.as_str()).collect();
+        assert_eq!(locs, vec!["https://site.com/posts/1", "https://site.com/about"]);
+        assert_eq!(*requested.lock().unwrap(), vec!["/sitemap_index.xml", "/posts.xml", "/gone.xml", "/pages.xml"]);
+        assert!(parse_sitemap(&format!("{}/missing.xml", base), &trusting()).is_err());
+    }
+
+    #[test]
+    fn test_internal_links_missing_from_sitemap() {
+        let entries = vec![SitemapEntry { loc: "https://site.com/listed".into(), lastmod: None, priority: None }];
+        let html = "<a href=\"/listed#intro\">In</a> <a href=\"unlisted\">Out</a> <a href=\"/unlisted\">Again</a> \
+                    <a href=\"https://elsewhere.com/x\">Ext</a>";
+        assert_eq!(links_missing_from_sitemap(html, "https://site.com/", &entries), vec!["https://site.com/unlisted"]);
+
+        let (base, _) = serve(&[
+            ("/", "<html><body><a href=\"/about\">About</a></body></html>"),
+            ("/sitemap.xml", "<urlset></urlset>"),
+        ]);
+        let result = analyze_seo(&format!("{}/", base), &trusting()).unwrap();
+        assert_eq!(result.links_not_in_sitemap, vec![format!("{}/about", base)]);
+    }
+
+    // A short page with a good title but no meta description and three links to other sites
+    const THIN_PAGE: &str = "<html><head><title>Widgets for every workshop</title></head><body>\
+        <p>Our widgets are sturdy, cheap and ship fast.</p>\
+        <a href=\"https://a.example/\">A</a> <a href=\"https://b.example/\">B</a> <a href=\"https://c.example/\">C</a>\
+        </body></html>";
+
+    #[test]
+    fn test_rules_report_each_violation() {
+        let result = analyze_page(THIN_PAGE, "https://site.com/", true, true);
+        let rules: SeoRules = serde_json::from_str(
+            r#"{"min_word_count": 5, "require_meta_description": true, "max_external_links": 2,
+                "min_title_length": 10, "max_title_length": 60}"#,
+        )
+        .unwrap();
+
+        let violations = result.check_against(&rules);
+        assert_eq!(violations, vec![
+            SeoViolation::MissingMetaDescription,
+            SeoViolation::TooManyExternalLinks { max: 2, actual: 3 },
+        ]);
+        assert_eq!(
+            serde_json::to_value(&violations[1]).unwrap(),
+            serde_json::json!({ "rule": "too_many_external_links", "max": 2, "actual": 3 })
+        );
+        assert_eq!(violations[1].to_string(), "3 external links, at most 2 allowed");
+    }
+
+    #[test]
+    fn test_page_passes_rules_it_meets() {
+        let result = analyze_page(THIN_PAGE, "https://site.com/", true, true);
+        let rules = SeoRules {
+            min_word_count: Some(5),
+            max_external_links: Some(3),
+            max_title_length: Some(60),
+            ..SeoRules::default()
+        };
+        assert_eq!(result.check_against(&rules), vec![]);
+        assert_eq!(result.check_against(&SeoRules::default()), vec![]);
+
+        let untitled = analyze_page("<html><body>Hi</body></html>", "https://site.com/", true, true);
+        assert_eq!(untitled.check_against(&rules), vec![
+            SeoViolation::WordCountTooLow { min: 5, actual: 1 },
+            SeoViolation::MissingTitle,
+        ]);
+
+        // Rules round-trip through a file, and typos in one are caught
+        let saved = serde_json::to_string(&rules).unwrap();
+        assert_eq!(serde_json::from_str::<SeoRules>(&saved).unwrap(), rules);
+        assert!(serde_json::from_str::<SeoRules>(r#"{"min_words": 5}"#).is_err());
+    }
+
+    #[test]
+    fn test_syllable_heuristic() {
+        assert_eq!(count_syllables("cat"), 1);
+        assert_eq!(count_syllables("table"), 2);
+        assert_eq!(count_syllables("make"), 1);
+        assert_eq!(count_syllables("organization"), 5);
+    }
+}
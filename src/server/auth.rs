@@ -0,0 +1,69 @@
+use chrono::{Duration, Utc};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use std::env;
+
+// How long an access token stays valid
+const TOKEN_LIFETIME_HOURS: i64 = 1;
+
+// Define a struct to represent JWT claims
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: usize,
+    pub roles: Vec<String>,
+    pub permissions: Vec<String>,
+}
+
+// Sign a token for `user` with `secret`, returning the token and its expiry (unix seconds)
+pub fn issue_token(
+    secret: &str,
+    user: &str,
+    roles: Vec<String>,
+    permissions: Vec<String>,
+) -> Result<(String, usize), jsonwebtoken::errors::Error> {
+    let expiration = (Utc::now() + Duration::hours(TOKEN_LIFETIME_HOURS)).timestamp() as usize;
+    let claims = Claims {
+        sub: user.to_string(),
+        exp: expiration,
+        roles,
+        permissions,
+    };
+    let encoding_key = EncodingKey::from_secret(secret.as_ref());
+    let token = encode(&Header::default(), &claims, &encoding_key)?;
+    Ok((token, expiration))
+}
+
+// Check the signature and expiry of `token`, returning its claims
+pub fn validate_token(secret: &str, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+    let decoding_key = DecodingKey::from_secret(secret.as_ref());
+    decode::<Claims>(token, &decoding_key, &Validation::default()).map(|data| data.claims)
+}
+
+// Function to generate a JWT token signed with `JWT_SECRET`
+pub fn generate_token(user: &str, roles: Vec<String>, permissions: Vec<String>) -> String {
+    let secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    issue_token(&secret, user, roles, permissions)
+        .map(|(token, _)| token)
+        .expect("Failed to generate token")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issued_token_round_trips() {
+        let (token, exp) = issue_token("secret", "alice", vec!["user".to_string()], vec![]).unwrap();
+        let claims = validate_token("secret", &token).unwrap();
+        assert_eq!(claims.sub, "alice");
+        assert_eq!(claims.exp, exp);
+        assert_eq!(claims.roles, vec!["user".to_string()]);
+    }
+
+    #[test]
+    fn test_wrong_secret_is_rejected() {
+        let (token, _) = issue_token("secret", "alice", vec![], vec![]).unwrap();
+        assert!(validate_token("other-secret", &token).is_err());
+    }
+}
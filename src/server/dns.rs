@@ -1,20 +1,31 @@
+use std::env;
 use std::net::{Ipv4Addr, SocketAddr};
-use tokio::net::UdpSocket;
-use trust_dns_server::authority::{Authority, ZoneType};
+use std::path::Path;
+use tokio::net::{TcpListener, UdpSocket};
+use trust_dns_proto::op::{MessageType, ResponseCode};
+use trust_dns_proto::rr::rdata::{A, AAAA};
+use trust_dns_proto::rr::{LowerName, Name, RData, Record};
+use trust_dns_server::authority::{Authority, LookupError, LookupOptions, ZoneType};
+use trust_dns_server::store::in_memory::InMemoryAuthority;
 use trust_dns_server::proto::dns::{DnsResponse, Message, RecordType};
 use trust_dns_server::proto::xfer::{DnsRequest, DnsResponse as DnsResponseTrait};
 use trust_dns_server::server::{ServerFuture, ResponseHandler, RequestHandler};
 use trust_dns_server::server::response::Response;
-use trust_dns_client::client::Client;
-use trust_dns_client::proto::dns::DnsRequest as ClientDnsRequest;
-use trust_dns_client::proto::dns::DnsResponse as ClientDnsResponse;
 use std::sync::{Arc, Mutex};
-use log::{info, error};
+use log::{info, warn, error};
+
+mod dns_transport;
+mod zone_file;
+
+use zone_file::{parse_zone, ZoneParseError};
 
 /// DNS Server struct that contains zone data, cache, and upstream servers.
-#[derive(Debug)]
+///
+/// Clones share the zone and cache, so the UDP handler and TCP listener
+/// answer from the same state.
+#[derive(Debug, Clone)]
 struct DnsServer {
-    zone: Authority,
+    zone: Arc<InMemoryAuthority>,
     cache: Arc<Mutex<Cache>>,
     upstream_servers: Vec<SocketAddr>,
 }
@@ -27,37 +38,52 @@ struct Cache {
 
 impl DnsServer {
     /// Creates a new `DnsServer` with the given zone and upstream servers.
-    fn new(zone: Authority, upstream_servers: Vec<SocketAddr>) -> Self {
+    fn new(zone: InMemoryAuthority, upstream_servers: Vec<SocketAddr>) -> Self {
         Self {
-            zone,
+            zone: Arc::new(zone),
             cache: Arc::new(Mutex::new(Cache::default())),
             upstream_servers,
         }
     }
 
     /// Forwards DNS queries to upstream DNS servers if not found in the local zone.
+    ///
+    /// Upstreams are asked over UDP first; truncated answers are fetched again over TCP.
     async fn forward_query(&self, query: &Message) -> Result<DnsResponse, Box<dyn std::error::Error>> {
         info!("Forwarding query to upstream servers");
 
-        // Iterate through upstream servers and try to get a response
+        // Iterate through upstream servers until one answers
         for server in &self.upstream_servers {
-            // Create and connect a UDP socket to the upstream server
-            let client = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
-            client.connect(*server).await?;
-
-            // Send the DNS request to the upstream server
-            let request = ClientDnsRequest::new(query.clone());
-            client.send(&request.to_bytes()).await?;
-
-            // Receive the response from the upstream server
-            let mut buf = [0; 512];
-            let _ = client.recv(&mut buf).await?;
-            let response_msg = ClientDnsResponse::from_bytes(&buf)?;
-            return Ok(response_msg);
+            match dns_transport::exchange(*server, query).await {
+                Ok(response) => return Ok(DnsResponse::from_message(response)?),
+                Err(e) => warn!("Upstream {} failed: {}", server, e),
+            }
         }
 
         Err("No response from upstream servers".into())
     }
+
+    /// Answers a query from the cache, the local zone, or the upstream servers.
+    async fn resolve(&self, message: Message) -> Result<DnsResponse, Box<dyn std::error::Error>> {
+        // Check cache for a response
+        if let Some(cached_response) = self.cache.lock().unwrap().entries.get(&message.to_string()) {
+            info!("Cache hit for query: {:?}", message);
+            return Ok(cached_response.clone());
+        }
+
+        // Process the query
+        let in_zone = !message.queries().is_empty()
+            && message.queries().iter().all(|query| self.zone.origin().zone_of(&LowerName::from(query.name())));
+        let response = if in_zone {
+            DnsResponse::from_message(answer_from_zone(&self.zone, &message).await)?
+        } else {
+            self.forward_query(&message).await?
+        };
+
+        // Cache the full response; truncation only applies to what goes out over UDP
+        self.cache.lock().unwrap().entries.insert(message.to_string(), response.clone());
+        Ok(response)
+    }
 }
 
 #[tokio::main]
@@ -65,15 +91,37 @@ async fn main() -> std::io::Result<()> {
     env_logger::init();
     let address = "127.0.0.1:53".parse::<SocketAddr>()?;
     let socket = UdpSocket::bind(&address).await?;
+    let tcp_listener = TcpListener::bind(&address).await?;
 
-    let zone = create_zone();
+    // Operators point DNS_ZONE_FILE at a BIND-style zone file; without it the example zone is served
+    let zone = match env::var("DNS_ZONE_FILE") {
+        Ok(path) => {
+            let zone = load_zone_from_file(Path::new(&path)).map_err(|e| {
+                error!("Failed to load zone file {}: {}", path, e);
+                std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+            })?;
+            info!("Loaded zone from {}", path);
+            zone
+        }
+        Err(_) => create_zone(),
+    };
     let upstream_servers = vec!["8.8.8.8:53".parse().unwrap()]; // Example upstream server
     let server = DnsServer::new(zone, upstream_servers);
 
+    // TCP carries answers too large for UDP; clients retry here after seeing the TC bit
+    let tcp_server = server.clone();
+    tokio::spawn(dns_transport::serve_tcp(tcp_listener, move |query| {
+        let server = tcp_server.clone();
+        async move {
+            let response = server.resolve(query).await.map_err(|e| e.to_string())?;
+            Ok(response.into_message())
+        }
+    }));
+
     let mut dns_server = ServerFuture::new();
     dns_server.register_handler(Box::new(server));
 
-    info!("DNS server listening on {}", address);
+    info!("DNS server listening on {} (UDP and TCP)", address);
 
     dns_server.serve_with_socket(socket).await
 }
@@ -90,108 +138,169 @@ impl RequestHandler for DnsServer {
         let message = request.message().clone();
         info!("Received DNS request: {:?}", message);
 
-        // Check cache for a response
-        if let Some(cached_response) = self.cache.lock().unwrap().entries.get(&message.to_string()) {
-            info!("Cache hit for query: {:?}", message);
-            handler.send_response(cached_response.clone()).await?;
-            return Ok(cached_response.clone());
-        }
-
-        // Process the query
-        let response = if self.zone.contains(&message) {
-            self.handle_query(message)?
-        } else {
-            self.forward_query(&message).await?
-        };
+        let response = self.resolve(message).await?;
 
-        // Cache the response
-        self.cache.lock().unwrap().entries.insert(message.to_string(), response.clone());
-        handler.send_response(response).await?;
+        // Requests arriving here came over UDP; oversized answers go out with the TC bit set
+        let udp_response = DnsResponse::from_message(dns_transport::truncate_for_udp(&response)?)?;
+        handler.send_response(udp_response).await?;
         Ok(response)
     }
 }
 
-impl DnsServer {
-    /// Handles DNS queries for different record types and constructs responses.
-    fn handle_query(&self, message: Message) -> Result<DnsResponse, Box<dyn std::error::Error>> {
-        let mut response = message.response();
-        let mut message = response.message();
-        
-        for query in message.queries() {
-            let name = query.name();
-            let record_type = query.query_type();
-
-            match record_type {
-                RecordType::A => {
-                    let ip = Ipv4Addr::new(127, 0, 0, 1);
-                    let record = trust_dns_proto::rr::RData::A(ip);
-                    response.add_answer(name.clone(), 3600, record);
-                    info!("Added A record for {}: {:?}", name, ip);
-                }
-                RecordType::AAAA => {
-                    let ip = trust_dns_proto::rr::RData::AAAA(
-                        trust_dns_proto::rr::rdata::AAAA::new(0, 0, 0, 0, 0, 0, 0, 1),
-                    );
-                    response.add_answer(name.clone(), 3600, ip);
-                    info!("Added AAAA record for {}: {:?}", name, ip);
-                }
-                RecordType::CNAME => {
-                    let cname = trust_dns_proto::rr::RData::CNAME(name.clone());
-                    response.add_answer(name.clone(), 3600, cname);
-                    info!("Added CNAME record for {}: {:?}", name, cname);
-                }
-                RecordType::MX => {
-                    let mx = trust_dns_proto::rr::RData::MX(10, "mail.example.com.".to_string());
-                    response.add_answer(name.clone(), 3600, mx);
-                    info!("Added MX record for {}: {:?}", name, mx);
-                }
-                RecordType::TXT => {
-                    let txt = trust_dns_proto::rr::RData::TXT(vec!["v=spf1 include:_spf.example.com ~all".to_string()]);
-                    response.add_answer(name.clone(), 3600, txt);
-                    info!("Added TXT record for {}: {:?}", name, txt);
-                }
-                RecordType::PTR => {
-                    let ptr = trust_dns_proto::rr::RData::PTR("example.com.".to_string());
-                    response.add_answer(name.clone(), 3600, ptr);
-                    info!("Added PTR record for {}: {:?}", name, ptr);
-                }
-                RecordType::SRV => {
-                    let srv = trust_dns_proto::rr::RData::SRV(
-                        10, 5, 5060, "sip.example.com.".to_string()
-                    );
-                    response.add_answer(name.clone(), 3600, srv);
-                    info!("Added SRV record for {}: {:?}", name, srv);
-                }
-                _ => {
-                    // Log unsupported record types
-                    info!("Received unsupported record type: {:?}", record_type);
+/// Answers `query` from `zone` as its authority: the matching records, or
+/// the CNAME at the name followed by what its target has in the zone. Names
+/// the zone doesn't have get NXDOMAIN.
+async fn answer_from_zone(zone: &InMemoryAuthority, query: &Message) -> Message {
+    let mut response = Message::new();
+    response
+        .set_id(query.id())
+        .set_message_type(MessageType::Response)
+        .set_op_code(query.op_code())
+        .set_recursion_desired(query.recursion_desired())
+        .set_authoritative(true)
+        .add_queries(query.queries().iter().cloned());
+
+    for question in query.queries() {
+        let name = LowerName::from(question.name());
+        match zone.lookup(&name, question.query_type(), LookupOptions::default()).await {
+            Ok(mut lookup) => {
+                // What a CNAME leads to comes back as additionals, but belongs with the answers;
+                // for MX and NS it is the targets' addresses
+                let extra: Vec<Record> =
+                    lookup.take_additionals().iter().flat_map(|extra| extra.iter()).cloned().collect();
+                let is_alias = question.query_type() != RecordType::CNAME
+                    && lookup.iter().any(|record| record.record_type() == RecordType::CNAME);
+                response.add_answers(lookup.iter().cloned());
+                if is_alias {
+                    response.add_answers(extra);
+                } else {
+                    response.add_additionals(extra);
                 }
             }
+            // The name has other records, just not of this type
+            Err(LookupError::NameExists) => {}
+            Err(e) if e.is_nx_domain() => {
+                response.set_response_code(ResponseCode::NXDomain);
+            }
+            Err(e) => {
+                warn!("Lookup of {} {} failed: {}", name, question.query_type(), e);
+                response.set_response_code(ResponseCode::ServFail);
+            }
         }
-
-        Ok(response)
     }
+    response
 }
 
 /// Creates a sample DNS zone with example records.
-fn create_zone() -> Authority {
-    let zone_name = "example.com.".to_string();
-    let mut authority = Authority::new(zone_name, ZoneType::Master);
+fn create_zone() -> InMemoryAuthority {
+    let origin = Name::from_ascii("example.com.").unwrap();
+    let mut authority = InMemoryAuthority::empty(origin.clone(), ZoneType::Primary, false);
 
     // Insert example records into the zone
-    authority.insert_record(
-        "example.com.".to_string(),
-        RecordType::A,
-        3600,
-        Ipv4Addr::new(127, 0, 0, 1).into(),
-    );
-
-    authority.insert_record(
-        "example.com.".to_string(),
-        RecordType::AAAA,
-        3600,
-        trust_dns_proto::rr::rdata::AAAA::new(0, 0, 0, 0, 0, 0, 0, 1).into(),
-    );
+    authority.upsert_mut(Record::from_rdata(origin.clone(), 3600, RData::A(A(Ipv4Addr::new(127, 0, 0, 1)))), 0);
+    authority.upsert_mut(Record::from_rdata(origin, 3600, RData::AAAA(AAAA::new(0, 0, 0, 0, 0, 0, 0, 1))), 0);
 
     authority
-}
\ No newline at end of file
+}
+
+/// Loads a zone from a BIND-style zone file (see `zone_file::parse_zone` for
+/// what is supported), so records can change without a rebuild.
+fn load_zone_from_file(path: &Path) -> Result<InMemoryAuthority, ZoneParseError> {
+    let zone = parse_zone(&std::fs::read_to_string(path)?)?;
+    let mut authority = InMemoryAuthority::empty(zone.origin().clone(), ZoneType::Primary, false);
+
+    for record in zone.records() {
+        authority.upsert_mut(record.clone(), 0);
+    }
+
+    Ok(authority)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trust_dns_proto::op::Query;
+
+    const ZONE: &str = r#"
+$ORIGIN example.com.
+$TTL 1h
+@       IN  SOA ns1 hostmaster 2024010101 2h 15m 2w 300
+        IN  NS  ns1
+        IN  MX  10 mail
+        IN  TXT "v=spf1 include:_spf.example.com ~all"
+@   300 IN  A   192.0.2.1
+ns1     IN  A   192.0.2.53
+mail    IN  A   192.0.2.25
+www     IN  CNAME @
+"#;
+
+    fn zone() -> InMemoryAuthority {
+        let path = std::env::temp_dir().join(format!("noxium-dns-{}.zone", std::process::id()));
+        std::fs::write(&path, ZONE).unwrap();
+        let zone = load_zone_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        zone.unwrap()
+    }
+
+    // The answers to a `record_type` query for `name`, as (owner, TTL, data), and the response code
+    async fn ask(
+        zone: &InMemoryAuthority,
+        name: &str,
+        record_type: RecordType,
+    ) -> (Vec<(String, u32, String)>, ResponseCode) {
+        let mut query = Message::new();
+        query.set_id(7).add_query(Query::query(Name::from_ascii(name).unwrap(), record_type));
+        let response = answer_from_zone(zone, &query).await;
+        assert_eq!(response.id(), 7);
+        assert!(response.authoritative());
+        let answers = response
+            .answers()
+            .iter()
+            .map(|record| (record.name().to_string(), record.ttl(), record.data().unwrap().to_string()))
+            .collect();
+        (answers, response.response_code())
+    }
+
+    #[tokio::test]
+    async fn test_loaded_zone_answers_queries() {
+        let zone = zone();
+        assert_eq!(zone.origin(), &LowerName::from(Name::from_ascii("example.com.").unwrap()));
+
+        let apex = "example.com.".to_string();
+        assert_eq!(
+            ask(&zone, "example.com.", RecordType::A).await,
+            (vec![(apex.clone(), 300, "192.0.2.1".to_string())], ResponseCode::NoError)
+        );
+        assert_eq!(
+            ask(&zone, "EXAMPLE.com.", RecordType::MX).await,
+            (vec![(apex.clone(), 3600, "10 mail.example.com.".to_string())], ResponseCode::NoError)
+        );
+        assert_eq!(
+            ask(&zone, "example.com.", RecordType::TXT).await,
+            (vec![(apex.clone(), 3600, "v=spf1 include:_spf.example.com ~all".to_string())], ResponseCode::NoError)
+        );
+        assert_eq!(
+            ask(&zone, "example.com.", RecordType::SOA).await.0,
+            vec![(
+                apex.clone(),
+                3600,
+                "ns1.example.com. hostmaster.example.com. 2024010101 7200 900 1209600 300".to_string()
+            )]
+        );
+
+        // A CNAME is answered along with what it points at
+        assert_eq!(
+            ask(&zone, "www.example.com.", RecordType::A).await,
+            (
+                vec![
+                    ("www.example.com.".to_string(), 3600, "example.com.".to_string()),
+                    (apex.clone(), 300, "192.0.2.1".to_string()),
+                ],
+                ResponseCode::NoError
+            )
+        );
+        // The name exists without that type, or doesn't exist at all
+        assert_eq!(ask(&zone, "mail.example.com.", RecordType::AAAA).await, (vec![], ResponseCode::NoError));
+        assert_eq!(ask(&zone, "missing.example.com.", RecordType::A).await, (vec![], ResponseCode::NXDomain));
+    }
+}
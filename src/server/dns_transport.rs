@@ -0,0 +1,242 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use trust_dns_proto::error::ProtoError;
+use trust_dns_proto::op::{Message, ResponseCode};
+use log::{error, info, warn};
+
+/// Largest DNS message a plain (non-EDNS) UDP answer may carry (RFC 1035 4.2.1).
+pub const MAX_UDP_PAYLOAD: usize = 512;
+
+/// Receive buffer for upstream UDP answers; EDNS0 lets upstreams exceed 512 bytes.
+const UDP_RECEIVE_BUFFER: usize = 4096;
+
+/// How long an upstream gets to answer before the next one is tried.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long an idle client TCP connection is kept open (RFC 7766 6.2.3).
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub type TransportError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Returns `response` unchanged if it fits in a UDP datagram, otherwise a copy
+/// with the TC bit set and only the question kept, telling the client to retry
+/// over TCP.
+pub fn truncate_for_udp(response: &Message) -> Result<Message, ProtoError> {
+    if response.to_vec()?.len() <= MAX_UDP_PAYLOAD {
+        return Ok(response.clone());
+    }
+    let mut truncated = response.truncate();
+    truncated.add_queries(response.queries().to_vec());
+    Ok(truncated)
+}
+
+/// Reads one length-prefixed DNS message from a TCP stream, or `None` if the
+/// peer closed the connection cleanly between messages.
+pub async fn read_tcp_message<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0u8; 2];
+    match reader.read_exact(&mut length).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+    reader.read_exact(&mut message).await?;
+    Ok(Some(message))
+}
+
+/// Writes one DNS message to a TCP stream with its 2-byte length prefix.
+pub async fn write_tcp_message<W: AsyncWrite + Unpin>(writer: &mut W, message: &[u8]) -> io::Result<()> {
+    let length = u16::try_from(message.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "DNS message exceeds 65535 bytes"))?;
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&length.to_be_bytes());
+    framed.extend_from_slice(message);
+    writer.write_all(&framed).await?;
+    writer.flush().await
+}
+
+async fn exchange_udp(upstream: SocketAddr, query: &[u8]) -> Result<Message, TransportError> {
+    let bind_addr: SocketAddr = if upstream.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse()?;
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(upstream).await?;
+    socket.send(query).await?;
+
+    let mut buf = vec![0u8; UDP_RECEIVE_BUFFER];
+    let len = socket.recv(&mut buf).await?;
+    Ok(Message::from_vec(&buf[..len])?)
+}
+
+async fn exchange_tcp(upstream: SocketAddr, query: &[u8]) -> Result<Message, TransportError> {
+    let mut stream = TcpStream::connect(upstream).await?;
+    write_tcp_message(&mut stream, query).await?;
+    let response = read_tcp_message(&mut stream)
+        .await?
+        .ok_or("upstream closed the connection without answering")?;
+    Ok(Message::from_vec(&response)?)
+}
+
+/// Sends `query` to `upstream` over UDP, retrying over TCP when the answer
+/// comes back truncated.
+pub async fn exchange(upstream: SocketAddr, query: &Message) -> Result<Message, TransportError> {
+    let bytes = query.to_vec()?;
+    let response = tokio::time::timeout(UPSTREAM_TIMEOUT, exchange_udp(upstream, &bytes)).await??;
+    if !response.truncated() {
+        return Ok(response);
+    }
+    info!("Truncated answer from {}, retrying over TCP", upstream);
+    tokio::time::timeout(UPSTREAM_TIMEOUT, exchange_tcp(upstream, &bytes)).await?
+}
+
+/// Accepts DNS-over-TCP clients, answering each length-prefixed query with
+/// `resolve`. Responses are sent in full since TCP has no 512-byte limit.
+pub async fn serve_tcp<F, Fut>(listener: TcpListener, resolve: F)
+where
+    F: Fn(Message) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Result<Message, TransportError>> + Send,
+{
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("Failed to accept DNS TCP connection: {}", e);
+                continue;
+            }
+        };
+        let resolve = resolve.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_tcp_connection(stream, resolve).await {
+                warn!("DNS TCP connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+// Clients may pipeline several queries on one connection
+async fn handle_tcp_connection<F, Fut>(mut stream: TcpStream, resolve: F) -> Result<(), TransportError>
+where
+    F: Fn(Message) -> Fut,
+    Fut: Future<Output = Result<Message, TransportError>>,
+{
+    loop {
+        let Ok(read) = tokio::time::timeout(TCP_IDLE_TIMEOUT, read_tcp_message(&mut stream)).await else {
+            return Ok(()); // Idle too long
+        };
+        let Some(query) = read? else {
+            return Ok(());
+        };
+
+        let query = Message::from_vec(&query)?;
+        let (id, op_code, queries) = (query.id(), query.op_code(), query.queries().to_vec());
+        let response = match resolve(query).await {
+            Ok(response) => response,
+            Err(e) => {
+                // Answer anyway so the client doesn't wait out its timeout
+                error!("Failed to resolve query {}: {}", id, e);
+                let mut failure = Message::error_msg(id, op_code, ResponseCode::ServFail);
+                failure.add_queries(queries);
+                failure
+            }
+        };
+        write_tcp_message(&mut stream, &response.to_vec()?).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use trust_dns_proto::op::{MessageType, Query};
+    use trust_dns_proto::rr::rdata::TXT;
+    use trust_dns_proto::rr::{Name, RData, Record, RecordType};
+
+    fn txt_query() -> Message {
+        let mut query = Message::new();
+        query.set_id(4242).set_message_type(MessageType::Query);
+        query.add_query(Query::query(Name::from_str("big.example.com.").unwrap(), RecordType::TXT));
+        query
+    }
+
+    // An answer with enough TXT records to blow well past 512 bytes
+    fn large_response(query: &Message) -> Message {
+        let mut response = Message::new();
+        response.set_id(query.id()).set_message_type(MessageType::Response);
+        response.add_queries(query.queries().to_vec());
+        for i in 0..20 {
+            let text = format!("record-{:02}-{}", i, "x".repeat(40));
+            let record = Record::from_rdata(Name::from_str("big.example.com.").unwrap(), 300, RData::TXT(TXT::new(vec![text])));
+            response.add_answer(record);
+        }
+        response
+    }
+
+    #[test]
+    fn test_oversized_udp_answer_sets_tc_bit() {
+        let response = large_response(&txt_query());
+        assert!(response.to_vec().unwrap().len() > MAX_UDP_PAYLOAD);
+
+        let bytes = truncate_for_udp(&response).unwrap().to_vec().unwrap();
+        assert!(bytes.len() <= MAX_UDP_PAYLOAD);
+        let decoded = Message::from_vec(&bytes).unwrap();
+        assert!(decoded.truncated());
+        assert!(decoded.answers().is_empty());
+        assert_eq!(decoded.queries(), response.queries());
+    }
+
+    #[tokio::test]
+    async fn test_large_response_is_served_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_tcp(listener, |query: Message| async move { Ok(large_response(&query)) }));
+
+        // Two queries on one connection exercise the framing across messages
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        for _ in 0..2 {
+            write_tcp_message(&mut stream, &txt_query().to_vec().unwrap()).await.unwrap();
+            let bytes = read_tcp_message(&mut stream).await.unwrap().expect("an answer");
+            assert!(bytes.len() > MAX_UDP_PAYLOAD);
+
+            let response = Message::from_vec(&bytes).unwrap();
+            assert_eq!(response.id(), 4242);
+            assert!(!response.truncated());
+            assert_eq!(response.answers().len(), 20);
+        }
+
+        // Forwarding retries over TCP when the upstream's UDP answer is truncated
+        let udp = UdpSocket::bind(addr).await.unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_UDP_PAYLOAD];
+            let (len, peer) = udp.recv_from(&mut buf).await.unwrap();
+            let query = Message::from_vec(&buf[..len]).unwrap();
+            let truncated = truncate_for_udp(&large_response(&query)).unwrap();
+            udp.send_to(&truncated.to_vec().unwrap(), peer).await.unwrap();
+        });
+        let answer = exchange(addr, &txt_query()).await.unwrap();
+        assert!(!answer.truncated());
+        assert_eq!(answer.answers().len(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_failed_resolve_answers_servfail() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let resolve = |_query: Message| async move { Err::<Message, _>("no upstream answered".into()) };
+        tokio::spawn(serve_tcp(listener, resolve));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        write_tcp_message(&mut stream, &txt_query().to_vec().unwrap()).await.unwrap();
+        let bytes = tokio::time::timeout(Duration::from_secs(1), read_tcp_message(&mut stream))
+            .await
+            .expect("the server answered")
+            .unwrap()
+            .expect("an answer");
+
+        let response = Message::from_vec(&bytes).unwrap();
+        assert_eq!(response.id(), 4242);
+        assert_eq!(response.response_code(), ResponseCode::ServFail);
+        assert_eq!(response.queries(), txt_query().queries());
+    }
+}
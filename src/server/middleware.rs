@@ -3,16 +3,31 @@ use jsonwebtoken::{encode, decode, Header, EncodingKey, DecodingKey, Validation,
 use serde::{Deserialize, Serialize};
 use chrono::{Utc, Duration};
 use std::env;
-use ratelimit::RateLimiter;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use log::info;
 
-// Define a struct to represent JWT claims
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    exp: usize,
-    roles: Vec<String>,
-    permissions: Vec<String>,
-}
+mod auth;
+use auth::{generate_token, Claims};
+
+#[path = "../shutdown.rs"]
+#[allow(dead_code)]
+mod shutdown;
+use shutdown::shutdown_signal;
+
+#[path = "../bind_addr.rs"]
+mod bind_addr;
+use bind_addr::bind_addr;
+
+#[path = "../body_limit.rs"]
+#[allow(dead_code)]
+mod body_limit;
+use body_limit::{max_body_bytes, warp_body_limit};
+
+#[path = "../rate_limit.rs"]
+#[allow(dead_code)]
+mod rate_limit;
+use rate_limit::RateLimiter;
 
 // Define a struct for refresh token claims
 #[derive(Debug, Serialize, Deserialize)]
@@ -56,20 +71,6 @@ async fn authenticate(token: Option<String>) -> Result<TokenData<Claims>, Reject
     }
 }
 
-// Function to generate a JWT token
-fn generate_token(user: &str, roles: Vec<String>, permissions: Vec<String>) -> String {
-    let secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    let expiration = (Utc::now() + Duration::hours(1)).timestamp() as usize;
-    let claims = Claims {
-        sub: user.to_string(),
-        exp: expiration,
-        roles,
-        permissions,
-    };
-    let encoding_key = EncodingKey::from_secret(secret.as_ref());
-    encode(&Header::default(), &claims, &encoding_key).expect("Failed to generate token")
-}
-
 // Function to generate a refresh token
 fn generate_refresh_token(user: &str) -> String {
     let secret = env::var("REFRESH_TOKEN_SECRET").expect("REFRESH_TOKEN_SECRET must be set");
@@ -120,20 +121,32 @@ fn with_auth(required_role: Option<String>) -> impl Filter<Extract = (TokenData<
         })
 }
 
-// Middleware function for rate limiting
+// Middleware function for rate limiting: 10 requests per minute per client IP
 fn rate_limit() -> impl Filter<Extract = (), Error = Rejection> + Clone {
-    let limiter = RateLimiter::new(10, Duration::minutes(1));
-    warp::any().map(move || limiter.check().map_err(|_| warp::reject::custom(AuthError::RateLimited)))
+    let limiter = Arc::new(RateLimiter::sliding_window(10, std::time::Duration::from_secs(60)));
+    warp::addr::remote()
+        .and_then(move |addr: Option<SocketAddr>| {
+            let limiter = limiter.clone();
+            async move {
+                let client = addr.map(|addr| addr.ip().to_string()).unwrap_or_default();
+                limiter.check(&client).map_err(|_| warp::reject::custom(AuthError::RateLimited))
+            }
+        })
+        .untuple_one()
 }
 
 #[tokio::main]
 async fn main() {
+    env_logger::init();
+
     let auth_filter = with_auth(Some("admin".to_string()));
     let rate_limit_filter = rate_limit();
+    let body_limit = warp_body_limit(max_body_bytes());
 
     // Route to login and generate a token
     let login = warp::path("login")
         .and(warp::post())
+        .and(body_limit)
         .and(warp::body::json())
         .map(|user: String| {
             let token = generate_token(&user, vec!["admin".to_string()], vec!["read".to_string(), "write".to_string()]);
@@ -147,6 +160,7 @@ async fn main() {
     // Route to refresh a token
     let refresh = warp::path("refresh")
         .and(warp::post())
+        .and(body_limit)
         .and(warp::body::json())
         .map(|refresh_token: String| {
             let token_data = authenticate_refresh_token(Some(refresh_token)).await;
@@ -172,6 +186,10 @@ async fn main() {
     // Combine routes
     let routes = login.or(refresh).or(protected);
 
-    // Start the server on 127.0.0.1:3030
-    warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
+    // Start the server on NOXIUM_BIND_ADDR or 127.0.0.1:3030, finishing in-flight requests on shutdown
+    let addr = bind_addr(SocketAddr::from(([127, 0, 0, 1], 3030)));
+    let (addr, server) = warp::serve(routes).bind_with_graceful_shutdown(addr, shutdown_signal());
+    info!("Server running on http://{}", addr);
+    server.await;
+    info!("Server stopped");
 }
\ No newline at end of file
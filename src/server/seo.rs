@@ -1,4 +1,4 @@
-use reqwest::blocking::get;
+use reqwest::blocking::Client;
 use select::document::Document;
 use select::predicate::{Name, Predicate};
 use std::error::Error;
@@ -8,13 +8,22 @@ use serde_json::Value;
 use std::collections::HashSet;
 use std::time::Instant;
 
+#[allow(dead_code)]
+#[path = "../http.rs"]
+mod http;
+
+#[allow(dead_code)]
+#[path = "../single_flight.rs"]
+mod single_flight;
+use single_flight::BlockingDedupClient;
+
 /// Fetch the HTML content from a URL
-fn fetch_html(url: &str) -> Result<String, Box<dyn Error>> {
-    let response = get(url)?;
-    if !response.status().is_success() {
-        return Err(format!("Failed to fetch {}: {}", url, response.status()).into());
+fn fetch_html(client: &BlockingDedupClient, url: &str) -> Result<String, Box<dyn Error>> {
+    let response = client.get(url)?;
+    if !response.status.is_success() {
+        return Err(format!("Failed to fetch {}: {}", url, response.status).into());
     }
-    Ok(response.text()?)
+    Ok(response.body.to_string())
 }
 
 /// Extract and print the title tag content
@@ -69,13 +78,28 @@ fn print_image_alts(document: &Document) {
 }
 
 /// Check for broken links by making HTTP requests and printing status codes
-fn check_broken_links(document: &Document, base_url: &str) -> Result<(), Box<dyn Error>> {
+fn check_broken_links(
+    client: &BlockingDedupClient,
+    policy: &http::FetchPolicy,
+    document: &Document,
+    base_url: &str,
+) -> Result<(), Box<dyn Error>> {
     for link in document.find(Name("a")) {
         if let Some(href) = link.attr("href") {
             let absolute_url = resolve_url(base_url, href)?;
-            let response = get(&absolute_url)?;
-            if !response.status().is_success() {
-                println!("Broken link: {} (Status: {})", absolute_url, response.status());
+            // The page chose the link, so one into the private network is skipped rather than probed
+            if let Err(blocked) = http::check_url_with(&absolute_url, policy) {
+                println!("Skipping link: {}", blocked);
+                continue;
+            }
+            // A link that times out or refuses the connection is reported, not fatal.
+            // Only the status is fetched, once per link however often it repeats
+            match client.status(&absolute_url) {
+                Ok(status) if !status.is_success() => {
+                    println!("Broken link: {} (Status: {})", absolute_url, status);
+                }
+                Ok(_) => {}
+                Err(e) => println!("Broken link: {} ({})", absolute_url, e),
             }
         }
     }
@@ -90,9 +114,9 @@ fn resolve_url(base_url: &str, relative_url: &str) -> Result<String, Box<dyn Err
 }
 
 /// Print the response time of the URL
-fn print_response_time(url: &str) -> Result<(), Box<dyn Error>> {
+fn print_response_time(client: &Client, url: &str) -> Result<(), Box<dyn Error>> {
     let start_time = Instant::now();
-    let response = get(url)?;
+    let response = client.get(url).send()?;
     let duration = start_time.elapsed();
     if response.status().is_success() {
         println!("Response time for {}: {:?}", url, duration);
@@ -173,8 +197,13 @@ fn main() -> Result<(), Box<dyn Error>> {
     // Replace with the URL you want to analyze
     let url = "https://example.com";
     
+    let config = http::HttpConfig::from_env();
+    let client = http::blocking_client_with(&config);
+    // Page and link fetches share one result per URL
+    let fetcher = BlockingDedupClient::new(client.clone());
+
     // Fetch the HTML content
-    let html_content = fetch_html(url)?;
+    let html_content = fetch_html(&fetcher, url)?;
     let document = Document::from(html_content.clone());
     
     // Print various SEO elements
@@ -185,10 +214,10 @@ fn main() -> Result<(), Box<dyn Error>> {
     print_image_alts(&document);
     
     // Check for broken links
-    check_broken_links(&document, url)?;
+    check_broken_links(&fetcher, &config.fetch_policy, &document, url)?;
     
-    // Print the response time
-    print_response_time(url)?;
+    // Print the response time, timing a fresh request rather than a shared one
+    print_response_time(&client, url)?;
     
     // Print all meta tags
     print_meta_tags(&document);
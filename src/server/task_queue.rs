@@ -5,6 +5,32 @@ use tokio::task;
 use tokio::net::TcpListener;
 use uuid::Uuid;
 use std::sync::Arc;
+use std::time::Duration;
+
+#[path = "../body_limit.rs"]
+#[allow(dead_code)]
+mod body_limit;
+use body_limit::{actix_body_limits, max_body_bytes};
+
+#[path = "../request_log.rs"]
+#[allow(dead_code)]
+mod request_log;
+use request_log::RequestLogger;
+
+#[path = "../timeout.rs"]
+#[allow(dead_code)]
+mod timeout;
+use timeout::{request_timeout, Timeout};
+
+#[path = "../shutdown.rs"]
+#[allow(dead_code)]
+mod shutdown;
+use shutdown::shutdown_signal;
+
+const REDIS_URL: &str = "redis://127.0.0.1/";
+
+// How often a running task checks whether it has been asked to cancel
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(250);
 
 #[derive(Serialize, Deserialize)]
 struct Task {
@@ -15,36 +41,70 @@ struct Task {
 
 // Function to process a task by starting a server on a dynamic port
 async fn process_task(task_id: String, client: redis::Client) -> Result<(), redis::RedisError> {
+    // Create an asynchronous connection to Redis
+    let mut con = client.get_async_connection().await?;
+
+    // Claim the task by taking it off the queue; if it is gone it was cancelled before it started
+    let claimed: i64 = con.lrem("task_queue", 1, &task_id).await?;
+    if claimed == 0 {
+        println!("Task {} was cancelled before it started", task_id);
+        return Ok(());
+    }
+
     // Bind a new TcpListener to port 0 to get a dynamic port
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
     let port = listener.local_addr().unwrap().port();
 
-    // Create an asynchronous connection to Redis
-    let mut con = client.get_async_connection().await?;
-    
     // Update the task status to 'running' and store the assigned port in Redis
     con.hset(&task_id, "status", "running").await?;
     con.hset(&task_id, "port", port).await?;
 
     // Start a new Actix web server on the dynamic port
-    let server = HttpServer::new(|| {
+    let mut server = HttpServer::new(|| {
         App::new()
+            .configure(actix_body_limits(max_body_bytes()))
             .route("/", web::post().to(echo))  // Define a route for handling POST requests
     })
     .listen(listener)?  // Use the dynamically assigned listener
     .run();
+    let handle = server.handle();
 
     println!("Server started for task {} on port {}", task_id, port);
 
-    // Run the server until it's manually stopped
-    server.await?;
+    // Run the server until it's manually stopped or the task is cancelled
+    let status = tokio::select! {
+        result = &mut server => {
+            result?;
+            "completed"
+        }
+        result = wait_for_cancel(&mut con, &task_id) => {
+            result?;
+            // The server future has to keep running to process its own stop command
+            let ((), result) = tokio::join!(handle.stop(true), server);
+            result?;
+            println!("Task {} cancelled", task_id);
+            "cancelled"
+        }
+    };
 
-    // Update task status to 'completed' once the server stops
-    con.hset(&task_id, "status", "completed").await?;
+    // Record how the task ended once the server stops
+    let _: () = con.hset(&task_id, "status", status).await?;
 
     Ok(())
 }
 
+// Cooperative cancellation checkpoint: resolves once `DELETE /task/{id}` has flagged the task
+async fn wait_for_cancel(con: &mut impl AsyncCommands, task_id: &str) -> Result<(), redis::RedisError> {
+    let mut checkpoint = tokio::time::interval(CANCEL_POLL_INTERVAL);
+    loop {
+        checkpoint.tick().await;
+        let requested: bool = con.hexists(task_id, "cancel_requested").await?;
+        if requested {
+            return Ok(());
+        }
+    }
+}
+
 // Echo handler that returns the received request body
 async fn echo(req_body: String) -> impl Responder {
     HttpResponse::Ok().body(req_body)
@@ -56,7 +116,7 @@ async fn add_task() -> impl Responder {
     let task_id = Uuid::new_v4().to_string();
     
     // Create a Redis client and establish a connection
-    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let client = redis::Client::open(REDIS_URL).unwrap();
     let mut con = client.get_async_connection().await.unwrap();
 
     // Create a new task in Redis with status 'pending'
@@ -83,7 +143,7 @@ async fn add_task() -> impl Responder {
 // Handler to get the status of a task
 async fn get_task_status(task_id: web::Path<String>) -> impl Responder {
     // Create a Redis client and establish a connection
-    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let client = redis::Client::open(REDIS_URL).unwrap();
     let mut con = client.get_async_connection().await.unwrap();
     
     // Retrieve the task status from Redis
@@ -101,16 +161,139 @@ async fn get_task_status(task_id: web::Path<String>) -> impl Responder {
     }
 }
 
+// Handler to cancel a task: pending tasks are dropped from the queue, running ones are
+// flagged and stop at their next checkpoint
+async fn cancel_task(task_id: web::Path<String>) -> impl Responder {
+    let client = redis::Client::open(REDIS_URL).unwrap();
+    let mut con = client.get_async_connection().await.unwrap();
+
+    let status: Option<String> = con.hget(&*task_id, "status").await.unwrap();
+    match status.as_deref() {
+        None => HttpResponse::NotFound().body("Task not found"),
+        Some(status @ ("completed" | "cancelled")) => HttpResponse::Conflict().body(format!("Task already {}", status)),
+        Some(status) => {
+            let removed: i64 = con.lrem("task_queue", 1, &*task_id).await.unwrap();
+            if removed > 0 {
+                // Never started, so there is nothing to wait for
+                let _: () = con.hset(&*task_id, "status", "cancelled").await.unwrap();
+                return HttpResponse::Ok().json(Task {
+                    id: task_id.to_string(),
+                    status: "cancelled".to_string(),
+                    port: None,
+                });
+            }
+
+            // Already claimed by its worker, which reports `cancelled` once it reaches a checkpoint
+            let _: () = con.hset(&*task_id, "cancel_requested", 1).await.unwrap();
+            let port: Option<u16> = con.hget(&*task_id, "port").await.ok();
+            HttpResponse::Accepted().json(Task {
+                id: task_id.to_string(),
+                status: status.to_string(),
+                port,
+            })
+        }
+    }
+}
+
 // Main function to start the Actix web server
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
     // Initialize and run the main Actix web server
-    HttpServer::new(|| {
+    let server = HttpServer::new(|| {
         App::new()
+            .configure(actix_body_limits(max_body_bytes()))
             .route("/add_task", web::post().to(add_task))  // Route to add a new task
             .route("/task/{task_id}", web::get().to(get_task_status))  // Route to get task status
+            .route("/task/{task_id}", web::delete().to(cancel_task))  // Route to cancel a task
+            .wrap(Timeout::new(request_timeout()))
+            .wrap(RequestLogger)
     })
+    .disable_signals()  // Stopped below on shutdown_signal instead
     .bind("127.0.0.1:5500")?  // Bind to the specified address and port
-    .run()
-    .await
-}
\ No newline at end of file
+    .run();
+
+    // Finish in-flight requests before exiting
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        handle.stop(true).await;
+    });
+    server.await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+
+    async fn connection() -> redis::aio::Connection {
+        redis::Client::open(REDIS_URL).unwrap().get_async_connection().await.unwrap()
+    }
+
+    // Queue a task the way `add_task` does, without starting a worker for it
+    async fn enqueue(con: &mut redis::aio::Connection) -> String {
+        let task_id = Uuid::new_v4().to_string();
+        let _: () = con.hset(&task_id, "status", "pending").await.unwrap();
+        let _: () = con.lpush("task_queue", &task_id).await.unwrap();
+        task_id
+    }
+
+    async fn status(con: &mut redis::aio::Connection, task_id: &str) -> String {
+        con.hget(task_id, "status").await.unwrap()
+    }
+
+    async fn delete_task(task_id: &str) -> (u16, String) {
+        let app = test::init_service(App::new().route("/task/{task_id}", web::delete().to(cancel_task))).await;
+        let request = test::TestRequest::delete().uri(&format!("/task/{}", task_id)).to_request();
+        let response = test::call_service(&app, request).await;
+        let status = response.status().as_u16();
+        (status, String::from_utf8(test::read_body(response).await.to_vec()).unwrap())
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires a Redis server on 127.0.0.1"]
+    async fn test_cancelled_pending_task_never_runs() {
+        let mut con = connection().await;
+        let task_id = enqueue(&mut con).await;
+
+        let (code, body) = delete_task(&task_id).await;
+        assert_eq!(code, 200);
+        assert!(body.contains("\"status\":\"cancelled\""), "{}", body);
+
+        // A worker picking it up afterwards finds nothing to claim
+        process_task(task_id.clone(), redis::Client::open(REDIS_URL).unwrap()).await.unwrap();
+        assert_eq!(status(&mut con, &task_id).await, "cancelled");
+        let port: Option<u16> = con.hget(&task_id, "port").await.unwrap();
+        assert_eq!(port, None);
+
+        assert_eq!(delete_task(&task_id).await, (409, "Task already cancelled".to_string()));
+        assert_eq!(delete_task("no-such-task").await.0, 404);
+        let _: () = con.del(&task_id).await.unwrap();
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires a Redis server on 127.0.0.1"]
+    async fn test_running_task_stops_at_checkpoint() {
+        let mut con = connection().await;
+        let task_id = enqueue(&mut con).await;
+        let worker = tokio::spawn(process_task(task_id.clone(), redis::Client::open(REDIS_URL).unwrap()));
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while status(&mut con, &task_id).await != "running" {
+            assert!(tokio::time::Instant::now() < deadline, "task never started");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let (code, body) = delete_task(&task_id).await;
+        assert_eq!(code, 202);
+        assert!(body.contains("\"status\":\"running\""), "{}", body);
+
+        tokio::time::timeout(Duration::from_secs(5), worker)
+            .await
+            .expect("task did not stop at a checkpoint")
+            .unwrap()
+            .unwrap();
+        assert_eq!(status(&mut con, &task_id).await, "cancelled");
+        let _: () = con.del(&task_id).await.unwrap();
+    }
+}
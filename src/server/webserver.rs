@@ -1,5 +1,10 @@
 use warp::{Filter, Rejection, Reply};
+use warp::hyper::body::Buf;
+use warp::multipart::FormData;
+use futures::TryStreamExt;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::net::SocketAddr;
 use log::{info, error};
 use validator::{Validate, ValidationErrors};
@@ -8,8 +13,39 @@ use std::convert::Infallible;
 use thiserror::Error;
 use sqlx::SqlitePool;
 use dotenv::dotenv;
-use bcrypt::{hash, verify};
 use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+mod auth;
+use auth::{issue_token, validate_token, Claims};
+
+#[path = "../auth_backend.rs"]
+#[allow(dead_code)]
+mod auth_backend;
+use auth_backend::{AuthBackend, AuthError, SqliteBackend};
+
+#[path = "../shutdown.rs"]
+mod shutdown;
+use shutdown::draining_shutdown_signal;
+
+#[path = "../body_limit.rs"]
+#[allow(dead_code)]
+mod body_limit;
+use body_limit::{max_body_bytes, warp_body_limit};
+
+#[path = "../compression.rs"]
+#[allow(dead_code)]
+mod compression;
+use compression::with_compression;
+
+#[path = "../config.rs"]
+#[allow(dead_code)]
+mod noxium_config;
+use noxium_config::{ConfigError, NoxiumConfig};
+
+// Roles granted to every authenticated user
+const DEFAULT_ROLES: &[&str] = &["user"];
 
 // Define a struct for a simple JSON response
 #[derive(Debug, Serialize, Deserialize)]
@@ -33,6 +69,8 @@ struct LoginRequest {
 #[derive(Debug, Serialize, Deserialize)]
 struct AuthResponse {
     token: String,
+    /// Expiry of `token` as a unix timestamp.
+    expires_at: usize,
 }
 
 // Custom error type for detailed error responses
@@ -46,8 +84,14 @@ pub enum AppError {
     AuthError,
     #[error("Internal server error")]
     InternalError,
+    #[error("Unsupported media type {0:?}")]
+    UnsupportedMediaType(String),
+    #[error("Invalid request body: {0}")]
+    InvalidBody(String),
 }
 
+impl warp::reject::Reject for AppError {}
+
 // Create a warp filter that handles GET requests to the root path
 async fn hello() -> Result<impl Reply, Rejection> {
     Ok(warp::reply::json(&Hello {
@@ -65,31 +109,127 @@ async fn echo(body: EchoRequest) -> Result<impl Reply, Rejection> {
     }))
 }
 
-// Simulate a database query
-async fn get_user_from_db(username: &str) -> Result<Option<(String, String)>, AppError> {
-    let pool = SqlitePool::connect("sqlite:./test.db").await?;
-    let row: (String, String) = sqlx::query_as("SELECT username, password FROM users WHERE username = ?")
-        .bind(username)
-        .fetch_one(&pool)
-        .await
-        .ok();
-    Ok(row)
+// Content types `negotiated_body` can deserialize from
+const BODY_TYPES: [&str; 3] = ["application/json", "application/x-www-form-urlencoded", "multipart/form-data"];
+
+// The lowercased `type/subtype` of the request's Content-Type, without
+// parameters. A body without one is taken as JSON, as it always was
+fn body_type() -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("content-type").map(|content_type: Option<String>| match content_type {
+        Some(content_type) => content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase(),
+        None => BODY_TYPES[0].to_string(),
+    })
+}
+
+// Passes requests with a body of type `expected`. Others get a not-found
+// rejection, so the next branch of an `or` is tried without a 404 winning
+fn body_type_is(expected: &'static str) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    body_type()
+        .and_then(move |body_type: String| async move {
+            if body_type == expected {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+// Text fields of a multipart form, deserialized like a JSON object of strings.
+// File uploads are skipped. Each part has to be read before the next one
+async fn multipart_body<T: DeserializeOwned>(mut form: FormData) -> Result<T, Rejection> {
+    let invalid = |e: &dyn std::fmt::Display| warp::reject::custom(AppError::InvalidBody(e.to_string()));
+    let mut fields = serde_json::Map::new();
+    while let Some(part) = form.try_next().await.map_err(|e| invalid(&e))? {
+        if part.filename().is_some() {
+            continue;
+        }
+        let name = part.name().to_string();
+        let data = part
+            .stream()
+            .try_fold(Vec::new(), |mut data, chunk| async move {
+                data.extend_from_slice(chunk.chunk());
+                Ok(data)
+            })
+            .await
+            .map_err(|e| invalid(&e))?;
+        let value = String::from_utf8(data).map_err(|e| invalid(&e))?;
+        fields.insert(name, Value::String(value));
+    }
+    serde_json::from_value(Value::Object(fields)).map_err(|e| invalid(&e))
+}
+
+// Deserializes the body as JSON, a urlencoded form or a multipart form,
+// depending on its Content-Type, so HTML forms can post to JSON endpoints.
+// Any other type is rejected as unsupported (415)
+fn negotiated_body<T>() -> impl Filter<Extract = (T,), Error = Rejection> + Clone
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    let json = body_type_is(BODY_TYPES[0]).and(warp::body::json());
+    let form = body_type_is(BODY_TYPES[1]).and(warp::body::form());
+    let multipart = body_type_is(BODY_TYPES[2]).and(warp::multipart::form()).and_then(multipart_body::<T>);
+    let unsupported = body_type().and_then(|body_type: String| async move {
+        Err::<T, _>(if BODY_TYPES.contains(&body_type.as_str()) {
+            warp::reject::not_found()
+        } else {
+            warp::reject::custom(AppError::UnsupportedMediaType(body_type))
+        })
+    });
+    json.or(form).unify().or(multipart).unify().or(unsupported).unify()
 }
 
 // Handle user login
-async fn login(body: LoginRequest) -> Result<impl Reply, Rejection> {
-    let (stored_username, stored_password) = match get_user_from_db(&body.username).await {
-        Ok(Some(row)) => row,
-        Ok(None) => return Err(warp::reject::custom(AppError::AuthError)),
-        Err(_) => return Err(warp::reject::custom(AppError::InternalError)),
+async fn login(body: LoginRequest, backend: Arc<dyn AuthBackend>, jwt_secret: String) -> Result<impl Reply, Rejection> {
+    let user = match backend.verify(&body.username, &body.password).await {
+        Ok(user) => user,
+        Err(AuthError::InvalidCredentials) => return Err(warp::reject::custom(AppError::AuthError)),
+        Err(e) => {
+            error!("Failed to look up user {}: {}", body.username, e);
+            return Err(warp::reject::custom(AppError::InternalError));
+        }
     };
 
-    if verify(&body.password, &stored_password).unwrap_or(false) {
-        let token = "mock-token"; // Replace with real token generation
-        Ok(warp::reply::json(&AuthResponse { token: token.to_string() }))
-    } else {
-        Err(warp::reject::custom(AppError::AuthError))
-    }
+    let roles = DEFAULT_ROLES.iter().map(|role| role.to_string()).collect();
+    let (token, expires_at) = issue_token(&jwt_secret, &user.username, roles, Vec::new()).map_err(|e| {
+        error!("Failed to sign token for {}: {}", user.username, e);
+        warp::reject::custom(AppError::InternalError)
+    })?;
+    Ok(warp::reply::json(&AuthResponse { token, expires_at }))
+}
+
+// Return the claims of the authenticated user
+async fn me(claims: Claims) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&claims))
+}
+
+// Share the user store with handlers
+fn with_auth_backend(backend: Arc<dyn AuthBackend>) -> impl Filter<Extract = (Arc<dyn AuthBackend>,), Error = Infallible> + Clone {
+    warp::any().map(move || backend.clone())
+}
+
+// Share the shutdown flag with the readiness check
+fn with_shutting_down(shutting_down: Arc<AtomicBool>) -> impl Filter<Extract = (Arc<AtomicBool>,), Error = Infallible> + Clone {
+    warp::any().map(move || shutting_down.clone())
+}
+
+// Share the JWT signing secret with handlers
+fn with_secret(jwt_secret: String) -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
+    warp::any().map(move || jwt_secret.clone())
+}
+
+// Require a valid `Authorization: Bearer <token>` header, extracting its claims
+fn with_auth(jwt_secret: String) -> impl Filter<Extract = (Claims,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization").and_then(move |header: Option<String>| {
+        let jwt_secret = jwt_secret.clone();
+        async move {
+            let token = header
+                .as_deref()
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .ok_or_else(|| warp::reject::custom(AppError::AuthError))?;
+            validate_token(&jwt_secret, token).map_err(|_| warp::reject::custom(AppError::AuthError))
+        }
+    })
 }
 
 // Middleware for logging requests
@@ -124,7 +264,35 @@ async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
                 "Internal server error",
                 warp::http::StatusCode::INTERNAL_SERVER_ERROR,
             )),
+            AppError::UnsupportedMediaType(_) => Ok(warp::reply::with_status(
+                "Unsupported media type",
+                warp::http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            )),
+            AppError::InvalidBody(_) => Ok(warp::reply::with_status(
+                "Invalid request body",
+                warp::http::StatusCode::BAD_REQUEST,
+            )),
         }
+    } else if err.find::<warp::body::BodyDeserializeError>().is_some() {
+        Ok(warp::reply::with_status(
+            "Invalid request body",
+            warp::http::StatusCode::BAD_REQUEST,
+        ))
+    } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+        Ok(warp::reply::with_status(
+            "Payload too large",
+            warp::http::StatusCode::PAYLOAD_TOO_LARGE,
+        ))
+    } else if err.find::<warp::reject::LengthRequired>().is_some() {
+        Ok(warp::reply::with_status(
+            "Content-Length header is required",
+            warp::http::StatusCode::LENGTH_REQUIRED,
+        ))
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        Ok(warp::reply::with_status(
+            "Method not allowed",
+            warp::http::StatusCode::METHOD_NOT_ALLOWED,
+        ))
     } else {
         error!("Unhandled rejection: {:?}", err);
         Ok(warp::reply::with_status(
@@ -134,20 +302,12 @@ async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
     }
 }
 
-// Define a struct for configuration
-#[derive(Debug, Deserialize)]
-struct Config {
-    port: u16,
-}
-
-// Load configuration from environment variables or default
-fn load_config() -> Config {
+// Load configuration from the file named by `NOXIUM_CONFIG` (`noxium.toml` by
+// default), with `NOXIUM_*` environment variables taking precedence
+fn load_config() -> Result<NoxiumConfig, ConfigError> {
     dotenv().ok();
-    let port = env::var("PORT")
-        .unwrap_or_else(|_| "3030".to_string())
-        .parse()
-        .unwrap_or(3030);
-    Config { port }
+    let path = env::var("NOXIUM_CONFIG").unwrap_or_else(|_| "noxium.toml".to_string());
+    NoxiumConfig::load(path)
 }
 
 // Create a new route for /info that provides server information
@@ -162,26 +322,66 @@ async fn health_check() -> Result<impl Reply, Rejection> {
     Ok(warp::reply::with_status("OK", warp::http::StatusCode::OK))
 }
 
+// Readiness check, failing from the moment shutdown begins so load balancers
+// stop sending new requests while in-flight ones drain
+async fn readiness_check(shutting_down: Arc<AtomicBool>) -> Result<impl Reply, Rejection> {
+    if shutting_down.load(Ordering::SeqCst) {
+        return Ok(warp::reply::with_status("Shutting down", warp::http::StatusCode::SERVICE_UNAVAILABLE));
+    }
+    Ok(warp::reply::with_status("OK", warp::http::StatusCode::OK))
+}
+
+// GET /readyz, reporting on `shutting_down`
+fn readyz_route(shutting_down: Arc<AtomicBool>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("readyz").and(with_shutting_down(shutting_down)).and_then(readiness_check)
+}
+
 #[tokio::main]
 async fn main() {
     // Initialize logging
     env_logger::init();
 
     // Load configuration
-    let config = load_config();
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+
+    // Create the connection pool once and share it with the handlers
+    let pool = match SqlitePool::connect(&config.database_url).await {
+        Ok(pool) => pool,
+        Err(e) => {
+            error!("Failed to connect to {}: {}", config.database_url, e);
+            return;
+        }
+    };
 
     // Define the routes
+    let body_limit = warp_body_limit(max_body_bytes());
     let hello_route = warp::path::end().and_then(hello);
     let echo_route = warp::path("echo")
         .and(warp::post())
-        .and(warp::body::json())
+        .and(body_limit)
+        .and(negotiated_body())
         .and_then(echo);
     let login_route = warp::path("login")
         .and(warp::post())
+        .and(body_limit)
         .and(warp::body::json())
+        .and(with_auth_backend(Arc::new(SqliteBackend::new(pool.clone()))))
+        .and(with_secret(config.jwt_secret.clone()))
         .and_then(login);
+    let me_route = warp::path("me")
+        .and(warp::get())
+        .and(with_auth(config.jwt_secret.clone()))
+        .and_then(me);
     let info_route = warp::path("info").and_then(info_route);
     let health_route = warp::path("health").and_then(health_check);
+    let shutting_down = Arc::new(AtomicBool::new(false));
+    let readyz_route = readyz_route(shutting_down.clone());
 
     // Combine the routes into a single filter with logging
     let routes = warp::get()
@@ -189,12 +389,251 @@ async fn main() {
         .or(warp::post().and(log_request(echo_route.boxed(), "POST /echo")))
         .or(warp::post().and(log_request(login_route.boxed(), "POST /login")))
         .or(log_request(info_route.boxed(), "GET /info"))
-        .or(log_request(health_route.boxed(), "GET /health"));
+        .or(log_request(health_route.boxed(), "GET /health"))
+        .or(log_request(readyz_route.boxed(), "GET /readyz"))
+        .or(log_request(me_route.boxed(), "GET /me"));
 
     // Define the address to bind to
     let addr = SocketAddr::from(([127, 0, 0, 1], config.port));
 
-    // Start the warp server
+    // Start the warp server; on shutdown /readyz fails first, then in-flight requests finish
+    let (addr, server) = warp::serve(with_compression(routes.recover(handle_rejection))).bind_with_graceful_shutdown(addr, draining_shutdown_signal(shutting_down));
     info!("Server running on http://{}", addr);
-    warp::serve(routes.with(warp::reject::custom(handle_rejection))).run(addr).await;
-}
\ No newline at end of file
+    server.await;
+    info!("Server stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    // A single connection keeps the in-memory database alive for the whole test
+    async fn test_pool() -> SqlitePool {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("Failed to open in-memory database")
+    }
+
+    async fn seeded_pool() -> SqlitePool {
+        let pool = test_pool().await;
+        sqlx::query("CREATE TABLE users (username TEXT PRIMARY KEY, password TEXT NOT NULL)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO users (username, password) VALUES (?, ?)")
+            .bind("alice")
+            .bind(bcrypt::hash("wonderland", 4).unwrap())
+            .execute(&pool)
+            .await
+            .unwrap();
+        pool
+    }
+
+    fn login_request(username: &str, password: &str) -> LoginRequest {
+        LoginRequest {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    const TEST_SECRET: &str = "test-secret";
+
+    async fn login_status(body: LoginRequest, pool: SqlitePool) -> u16 {
+        let response = match login(body, Arc::new(SqliteBackend::new(pool)), TEST_SECRET.to_string()).await {
+            Ok(reply) => reply.into_response(),
+            Err(rejection) => handle_rejection(rejection).await.unwrap().into_response(),
+        };
+        response.status().as_u16()
+    }
+
+    #[tokio::test]
+    async fn test_login_success() {
+        let pool = seeded_pool().await;
+        assert_eq!(login_status(login_request("alice", "wonderland"), pool).await, 200);
+    }
+
+    #[tokio::test]
+    async fn test_missing_user_is_unauthorized() {
+        let pool = seeded_pool().await;
+        assert_eq!(login_status(login_request("bob", "wonderland"), pool).await, 401);
+    }
+
+    #[tokio::test]
+    async fn test_database_failure_is_internal_error() {
+        // No users table, so the query itself fails
+        let pool = test_pool().await;
+        assert_eq!(login_status(login_request("alice", "wonderland"), pool).await, 500);
+    }
+
+    #[tokio::test]
+    async fn test_login_token_identifies_user() {
+        let pool = seeded_pool().await;
+        let response = login(login_request("alice", "wonderland"), Arc::new(SqliteBackend::new(pool)), TEST_SECRET.to_string())
+            .await
+            .unwrap()
+            .into_response();
+        let bytes = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let auth: AuthResponse = serde_json::from_slice(&bytes).unwrap();
+
+        let claims = validate_token(TEST_SECRET, &auth.token).unwrap();
+        assert_eq!(claims.sub, "alice");
+        assert_eq!(claims.exp, auth.expires_at);
+        assert_eq!(claims.roles, vec!["user".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_protected_route_rejects_tampered_token() {
+        let routes = warp::path("me")
+            .and(warp::get())
+            .and(with_auth(TEST_SECRET.to_string()))
+            .and_then(me)
+            .recover(handle_rejection);
+        let (token, _) = issue_token(TEST_SECRET, "alice", vec!["user".to_string()], Vec::new()).unwrap();
+
+        let response = warp::test::request()
+            .path("/me")
+            .header("authorization", format!("Bearer {}", token))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+
+        // Swap the payload for one claiming a different user, keeping the original signature
+        let mut parts: Vec<&str> = token.split('.').collect();
+        let (forged, _) = issue_token(TEST_SECRET, "mallory", vec!["admin".to_string()], Vec::new()).unwrap();
+        parts[1] = forged.split('.').nth(1).unwrap();
+        let tampered = parts.join(".");
+
+        let response = warp::test::request()
+            .path("/me")
+            .header("authorization", format!("Bearer {}", tampered))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 401);
+
+        let response = warp::test::request().path("/me").reply(&routes).await;
+        assert_eq!(response.status(), 401);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/me")
+            .header("authorization", format!("Bearer {}", token))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 405);
+    }
+
+    async fn post_echo(content_type: &str, body: &str) -> (u16, String) {
+        let routes = warp::path("echo")
+            .and(warp::post())
+            .and(negotiated_body())
+            .and_then(echo)
+            .recover(handle_rejection);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/echo")
+            .header("content-type", content_type)
+            .body(body)
+            .reply(&routes)
+            .await;
+        (response.status().as_u16(), String::from_utf8(response.body().to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_echo_accepts_json_and_form_bodies() {
+        let echoed = |message: &str| serde_json::json!({ "message": message }).to_string();
+
+        assert_eq!(post_echo("application/json", r#"{"message": "from json"}"#).await, (200, echoed("from json")));
+        assert_eq!(
+            post_echo("application/x-www-form-urlencoded; charset=utf-8", "message=from+a%20form").await,
+            (200, echoed("from a form"))
+        );
+        let multipart = "--XyZ\r\n\
+                         Content-Disposition: form-data; name=\"message\"\r\n\r\n\
+                         from multipart\r\n\
+                         --XyZ\r\n\
+                         Content-Disposition: form-data; name=\"avatar\"; filename=\"a.png\"\r\n\r\n\
+                         not a field\r\n\
+                         --XyZ--\r\n";
+        assert_eq!(
+            post_echo("multipart/form-data; boundary=XyZ", multipart).await,
+            (200, echoed("from multipart"))
+        );
+
+        // Still validated the same way, whatever the type
+        assert_eq!(post_echo("application/x-www-form-urlencoded", "message=").await.0, 400);
+        assert_eq!(post_echo("application/x-www-form-urlencoded", "other=1").await.0, 400);
+    }
+
+    #[tokio::test]
+    async fn test_echo_rejects_unsupported_content_type() {
+        assert_eq!(post_echo("text/plain", "message=hi").await, (415, "Unsupported media type".to_string()));
+        assert_eq!(post_echo("application/xml", "<message>hi</message>").await.0, 415);
+    }
+
+    #[tokio::test]
+    async fn test_readiness_fails_while_in_flight_requests_drain() {
+        use shutdown::drain_after;
+        use std::time::Duration;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpStream;
+        use tokio::sync::{oneshot, Notify};
+
+        async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path);
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        }
+
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        // The slow route holds its request open until the test releases it
+        let (started, release) = (Arc::new(Notify::new()), Arc::new(Notify::new()));
+
+        let (entered, gate) = (started.clone(), release.clone());
+        let slow = warp::path("slow").and_then(move || {
+            let (entered, gate) = (entered.clone(), gate.clone());
+            async move {
+                entered.notify_one();
+                gate.notified().await;
+                Ok::<_, Infallible>("done")
+            }
+        });
+
+        let (trigger, triggered) = oneshot::channel::<()>();
+        let shutdown = drain_after(
+            async {
+                triggered.await.ok();
+            },
+            shutting_down.clone(),
+            Duration::from_millis(500),
+        );
+        let routes = readyz_route(shutting_down.clone()).or(slow);
+        let (addr, server) = warp::serve(routes).bind_with_graceful_shutdown(([127, 0, 0, 1], 0), shutdown);
+        let server = tokio::spawn(server);
+
+        assert!(get(addr, "/readyz").await.starts_with("HTTP/1.1 200"));
+        let in_flight = tokio::spawn(get(addr, "/slow"));
+        started.notified().await;
+
+        trigger.send(()).unwrap();
+        while !shutting_down.load(Ordering::SeqCst) {
+            tokio::task::yield_now().await;
+        }
+        let response = get(addr, "/readyz").await;
+        assert!(response.starts_with("HTTP/1.1 503") && response.ends_with("Shutting down"), "{}", response);
+
+        release.notify_one();
+        let response = in_flight.await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("done"), "{}", response);
+
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server did not stop after draining")
+            .unwrap();
+    }
+}
@@ -0,0 +1,431 @@
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use thiserror::Error;
+use trust_dns_proto::rr::rdata::{A, AAAA, CNAME, MX, NS, SOA, TXT};
+use trust_dns_proto::rr::{Name, RData, Record, RecordType};
+
+#[derive(Debug, Error)]
+pub enum ZoneParseError {
+    #[error("failed to read zone file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+    #[error("zone file has no SOA record")]
+    MissingSoa,
+}
+
+fn syntax(line: usize, message: impl Into<String>) -> ZoneParseError {
+    ZoneParseError::Syntax { line, message: message.into() }
+}
+
+/// The records of one zone, as read from a zone file.
+#[derive(Debug, Clone)]
+pub struct Zone {
+    origin: Name,
+    records: HashMap<(Name, RecordType), Vec<Record>>,
+}
+
+impl Zone {
+    /// The zone apex: the owner of the SOA record.
+    pub fn origin(&self) -> &Name {
+        &self.origin
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        self.records.values().flatten()
+    }
+}
+
+// A word of the zone file; quoted strings are words too
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Open,
+    Close,
+}
+
+// One record or directive, possibly spread over several lines by parentheses
+struct Entry {
+    line: usize,
+    // The line started with whitespace, so the record belongs to the previous owner
+    inherits_owner: bool,
+    words: Vec<String>,
+}
+
+fn tokenize(text: &str, line: usize) -> Result<Vec<Token>, ZoneParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => break,
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '"' => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => word.extend(chars.next()),
+                        Some(c) => word.push(c),
+                        None => return Err(syntax(line, "unterminated quoted string")),
+                    }
+                }
+                tokens.push(Token::Word(word));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, ';' | '(' | ')' | '"') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn entries(text: &str) -> Result<Vec<Entry>, ZoneParseError> {
+    let mut entries = Vec::new();
+    let mut pending: Option<(Entry, usize)> = None;
+    for (index, text) in text.lines().enumerate() {
+        let line = index + 1;
+        let tokens = tokenize(text, line)?;
+        let (mut entry, mut depth) = match pending.take() {
+            Some(pending) => pending,
+            None if tokens.is_empty() => continue,
+            None => {
+                let inherits_owner = text.starts_with(|c: char| c.is_whitespace());
+                (Entry { line, inherits_owner, words: Vec::new() }, 0)
+            }
+        };
+        for token in tokens {
+            match token {
+                Token::Word(word) => entry.words.push(word),
+                Token::Open => depth += 1,
+                Token::Close if depth == 0 => return Err(syntax(line, "unmatched ')'")),
+                Token::Close => depth -= 1,
+            }
+        }
+        if depth > 0 {
+            pending = Some((entry, depth));
+        } else if !entry.words.is_empty() {
+            entries.push(entry);
+        }
+    }
+    match pending {
+        Some((entry, _)) => Err(syntax(entry.line, "unclosed '('")),
+        None => Ok(entries),
+    }
+}
+
+/// Parses a TTL in seconds, or in BIND's unit form such as `1h30m` or `2d`.
+fn parse_ttl(text: &str) -> Option<u32> {
+    if let Ok(seconds) = text.parse() {
+        return Some(seconds);
+    }
+    let mut total: u32 = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        let value: u32 = number.parse().ok()?;
+        total = total.checked_add(value.checked_mul(unit)?)?;
+        number.clear();
+    }
+    number.is_empty().then_some(total)
+}
+
+// `@` is the origin, a name ending in `.` is absolute, anything else is
+// relative to the origin
+fn parse_name(text: &str, origin: Option<&Name>, line: usize) -> Result<Name, ZoneParseError> {
+    let invalid = |e| syntax(line, format!("invalid name {:?}: {}", text, e));
+    let relative_to_origin = || origin.ok_or_else(|| syntax(line, format!("relative name {:?} with no $ORIGIN", text)));
+    if text == "@" {
+        return relative_to_origin().cloned();
+    }
+    let name = Name::from_ascii(text).map_err(invalid)?;
+    if name.is_fqdn() {
+        Ok(name)
+    } else {
+        name.append_domain(relative_to_origin()?).map_err(invalid)
+    }
+}
+
+fn parse_rdata(
+    record_type: RecordType,
+    words: &[String],
+    origin: Option<&Name>,
+    line: usize,
+) -> Result<RData, ZoneParseError> {
+    let expect = |count: usize| {
+        if words.len() == count {
+            Ok(())
+        } else {
+            Err(syntax(line, format!("{} takes {} fields, found {}", record_type, count, words.len())))
+        }
+    };
+    let number = |text: &str| parse_ttl(text).ok_or_else(|| syntax(line, format!("invalid number {:?}", text)));
+    // SOA timers are signed 32-bit on the wire
+    let timer = |text: &str, field: &str| {
+        i32::try_from(number(text)?).map_err(|_| syntax(line, format!("SOA {} {:?} is out of range", field, text)))
+    };
+    let address = |e| syntax(line, format!("invalid address {:?}: {}", words[0], e));
+
+    Ok(match record_type {
+        RecordType::A => {
+            expect(1)?;
+            RData::A(A(words[0].parse::<Ipv4Addr>().map_err(address)?))
+        }
+        RecordType::AAAA => {
+            expect(1)?;
+            RData::AAAA(AAAA(words[0].parse::<Ipv6Addr>().map_err(address)?))
+        }
+        RecordType::CNAME => {
+            expect(1)?;
+            RData::CNAME(CNAME(parse_name(&words[0], origin, line)?))
+        }
+        RecordType::NS => {
+            expect(1)?;
+            RData::NS(NS(parse_name(&words[0], origin, line)?))
+        }
+        RecordType::MX => {
+            expect(2)?;
+            let preference =
+                words[0].parse().map_err(|_| syntax(line, format!("invalid MX preference {:?}", words[0])))?;
+            RData::MX(MX::new(preference, parse_name(&words[1], origin, line)?))
+        }
+        RecordType::TXT => {
+            if words.is_empty() {
+                return Err(syntax(line, "TXT needs at least one string"));
+            }
+            RData::TXT(TXT::new(words.to_vec()))
+        }
+        RecordType::SOA => {
+            expect(7)?;
+            RData::SOA(SOA::new(
+                parse_name(&words[0], origin, line)?,
+                parse_name(&words[1], origin, line)?,
+                words[2].parse().map_err(|_| syntax(line, format!("invalid serial {:?}", words[2])))?,
+                timer(&words[3], "refresh")?,
+                timer(&words[4], "retry")?,
+                timer(&words[5], "expire")?,
+                number(&words[6])?,
+            ))
+        }
+        other => return Err(syntax(line, format!("unsupported record type {}", other))),
+    })
+}
+
+/// Parses a BIND-style zone file.
+///
+/// Supports `$ORIGIN` and `$TTL`, `@`, relative and absolute names, owners
+/// carried over from the previous record, parentheses spanning lines, `;`
+/// comments, and SOA, NS, A, AAAA, CNAME, MX, and TXT records in class IN.
+/// A record without a TTL gets `$TTL`, or else the last TTL given.
+///
+/// The zone must have exactly one SOA record, and every record must be at or
+/// below its owner.
+pub fn parse_zone(text: &str) -> Result<Zone, ZoneParseError> {
+    let mut origin: Option<Name> = None;
+    let mut default_ttl: Option<u32> = None;
+    let mut last_ttl: Option<u32> = None;
+    let mut last_owner: Option<Name> = None;
+    let mut records: Vec<(usize, Record)> = Vec::new();
+
+    for Entry { line, inherits_owner, words } in entries(text)? {
+        let directive = words[0].to_ascii_uppercase();
+        if directive.starts_with('$') {
+            let argument = match words.as_slice() {
+                [_, argument] => argument,
+                _ => return Err(syntax(line, format!("{} takes one argument", directive))),
+            };
+            match directive.as_str() {
+                "$ORIGIN" => origin = Some(parse_name(argument, origin.as_ref(), line)?),
+                "$TTL" => {
+                    default_ttl =
+                        Some(parse_ttl(argument).ok_or_else(|| syntax(line, format!("invalid TTL {:?}", argument)))?)
+                }
+                _ => return Err(syntax(line, format!("unsupported directive {}", directive))),
+            }
+            continue;
+        }
+
+        let mut rest = words.as_slice();
+        let owner = if inherits_owner {
+            last_owner.clone().ok_or_else(|| syntax(line, "record has no owner name"))?
+        } else {
+            let owner = parse_name(&rest[0], origin.as_ref(), line)?;
+            rest = &rest[1..];
+            owner
+        };
+
+        // The TTL and class may come in either order, and either may be left out
+        let mut ttl = None;
+        while let Some(word) = rest.first() {
+            if word.eq_ignore_ascii_case("IN") {
+                rest = &rest[1..];
+            } else if let Some(value) = parse_ttl(word).filter(|_| ttl.is_none()) {
+                ttl = Some(value);
+                rest = &rest[1..];
+            } else {
+                break;
+            }
+        }
+        let record_type = match rest.first().map(|word| word.to_ascii_uppercase()) {
+            Some(word) if matches!(word.as_str(), "CH" | "HS" | "CS") => {
+                return Err(syntax(line, format!("unsupported class {}", word)))
+            }
+            Some(word) => {
+                word.parse::<RecordType>().map_err(|_| syntax(line, format!("unknown record type {:?}", word)))?
+            }
+            None => return Err(syntax(line, "missing record type")),
+        };
+        let rdata = parse_rdata(record_type, &rest[1..], origin.as_ref(), line)?;
+
+        if ttl.is_some() {
+            last_ttl = ttl;
+        }
+        let ttl =
+            ttl.or(default_ttl).or(last_ttl).ok_or_else(|| syntax(line, "record has no TTL and there is no $TTL"))?;
+        last_owner = Some(owner.clone());
+        records.push((line, Record::from_rdata(owner, ttl, rdata)));
+    }
+
+    let mut soa = records.iter().filter(|(_, record)| record.record_type() == RecordType::SOA);
+    let apex = soa.next().ok_or(ZoneParseError::MissingSoa)?.1.name().clone();
+    if let Some((line, _)) = soa.next() {
+        return Err(syntax(*line, "zone has more than one SOA record"));
+    }
+
+    let mut zone = Zone { origin: apex, records: HashMap::new() };
+    for (line, record) in records {
+        if !zone.origin.zone_of(record.name()) {
+            return Err(syntax(line, format!("{} is outside the zone {}", record.name(), zone.origin)));
+        }
+        let name = record.name().clone();
+        let has = |record_type| zone.records.contains_key(&(name.clone(), record_type));
+        let conflicts = if record.record_type() == RecordType::CNAME {
+            zone.records.keys().any(|(owner, _)| *owner == name)
+        } else {
+            has(RecordType::CNAME)
+        };
+        if conflicts {
+            return Err(syntax(line, format!("{} has a CNAME and other records", name)));
+        }
+        zone.records.entry((name, record.record_type())).or_default().push(record);
+    }
+    Ok(zone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZONE: &str = r#"
+$ORIGIN example.com.
+$TTL 1h
+@       IN  SOA ns1 hostmaster (
+                2024010101 ; serial
+                2h         ; refresh
+                15m        ; retry
+                2w         ; expire
+                300 )      ; negative caching
+        IN  NS  ns1
+        IN  NS  ns2.example.net.
+        IN  MX  10 mail
+        IN  TXT "v=spf1 include:_spf.example.com ~all"
+@   300 IN  A   192.0.2.1
+        IN  AAAA 2001:db8::1
+ns1     IN  A   192.0.2.53
+mail    IN  A   192.0.2.25
+www     IN  CNAME @
+$ORIGIN dev.example.com.
+api 60  IN  A   192.0.2.80
+"#;
+
+    fn name(text: &str) -> Name {
+        Name::from_ascii(text).unwrap()
+    }
+
+    // The records at `owner` of `record_type`, as (TTL, data)
+    fn records(zone: &Zone, owner: &str, record_type: RecordType) -> Vec<(u32, String)> {
+        let mut records: Vec<_> = zone
+            .records()
+            .filter(|record| *record.name() == name(owner) && record.record_type() == record_type)
+            .map(|record| (record.ttl(), record.data().unwrap().to_string()))
+            .collect();
+        records.sort();
+        records
+    }
+
+    #[test]
+    fn test_records_are_read_with_names_and_ttls_resolved() {
+        let zone = parse_zone(ZONE).unwrap();
+        assert_eq!(zone.origin(), &name("example.com."));
+        assert_eq!(zone.records().count(), 11);
+
+        assert_eq!(records(&zone, "example.com.", RecordType::A), vec![(300, "192.0.2.1".to_string())]);
+        // No TTL of its own: $TTL applies, not the 300 on the line before
+        assert_eq!(records(&zone, "example.com.", RecordType::AAAA), vec![(3600, "2001:db8::1".to_string())]);
+        assert_eq!(
+            records(&zone, "example.com.", RecordType::NS),
+            vec![(3600, "ns1.example.com.".to_string()), (3600, "ns2.example.net.".to_string())]
+        );
+        assert_eq!(
+            records(&zone, "example.com.", RecordType::SOA),
+            vec![(3600, "ns1.example.com. hostmaster.example.com. 2024010101 7200 900 1209600 300".to_string())]
+        );
+        assert_eq!(records(&zone, "www.example.com.", RecordType::CNAME), vec![(3600, "example.com.".to_string())]);
+        assert_eq!(records(&zone, "api.dev.example.com.", RecordType::A), vec![(60, "192.0.2.80".to_string())]);
+    }
+
+    #[test]
+    fn test_errors_carry_the_line() {
+        let error = |text: &str| parse_zone(text).unwrap_err().to_string();
+        let soa = "$ORIGIN example.com.\n@ 3600 IN SOA ns1 hostmaster 1 2 3 4 5\n";
+
+        assert!(matches!(parse_zone("$TTL 60\nexample.com. IN A 192.0.2.1\n"), Err(ZoneParseError::MissingSoa)));
+        assert_eq!(error("www IN A 192.0.2.1\n"), "line 1: relative name \"www\" with no $ORIGIN");
+        assert_eq!(
+            error(&format!("{}www IN A 192.0.2.300\n", soa)),
+            "line 3: invalid address \"192.0.2.300\": invalid IPv4 address syntax"
+        );
+        assert_eq!(
+            error(&format!("{}www.example.org. IN A 192.0.2.1\n", soa)),
+            "line 3: www.example.org. is outside the zone example.com."
+        );
+        assert_eq!(
+            error(&format!("{}www IN CNAME @\nwww IN A 192.0.2.1\n", soa)),
+            "line 4: www.example.com. has a CNAME and other records"
+        );
+        assert_eq!(error("$ORIGIN example.com.\n@ IN SOA ns1 hostmaster (\n 1 2 3 4 5\n"), "line 2: unclosed '('");
+        assert_eq!(
+            error("$ORIGIN example.com.\n@ 3600 IN SOA ns1 hostmaster 1 2 4294967295 4 5\n"),
+            "line 2: SOA retry \"4294967295\" is out of range"
+        );
+    }
+
+    #[test]
+    fn test_ttl_units() {
+        assert_eq!(parse_ttl("3600"), Some(3600));
+        assert_eq!(parse_ttl("1h30m"), Some(5400));
+        assert_eq!(parse_ttl("1W"), Some(604800));
+        assert_eq!(parse_ttl("IN"), None);
+        assert_eq!(parse_ttl("10x"), None);
+    }
+}
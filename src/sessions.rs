@@ -5,6 +5,18 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use std::sync::Mutex;
 use std::collections::HashMap;
 
+#[allow(dead_code)]
+mod body_limit;
+use body_limit::{actix_body_limits, max_body_bytes};
+
+#[allow(dead_code)]
+mod request_log;
+use request_log::RequestLogger;
+
+#[allow(dead_code)]
+mod timeout;
+use timeout::{request_timeout, Timeout};
+
 // Struct for user information
 #[derive(Serialize, Deserialize, Clone)]
 struct User {
@@ -146,6 +158,7 @@ async fn main() -> std::io::Result<()> {
 
     HttpServer::new(move || {
         App::new()
+            .configure(actix_body_limits(max_body_bytes()))
             .app_data(app_state.clone())
             .wrap(middleware::Logger::default())
             .wrap(CookieSession::signed(&[0; 32]).secure(false))
@@ -156,6 +169,8 @@ async fn main() -> std::io::Result<()> {
             .route("/logout", web::post().to(logout))
             .route("/delete", web::delete().to(delete_user))
             .route("/users", web::get().to(list_users))
+            .wrap(Timeout::new(request_timeout()))
+            .wrap(RequestLogger)
     })
     .bind("127.0.0.1:8080")?
     .run()
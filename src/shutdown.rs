@@ -0,0 +1,190 @@
+use futures::stream::{self, Stream, StreamExt};
+use log::{error, info};
+use std::env;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_DRAIN_DELAY_SECS: u64 = 5;
+
+/// A process signal that asks a server to stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Interrupt,
+    Terminate,
+}
+
+/// Resolves on ctrl-c, or SIGTERM on unix, so servers can drain in-flight
+/// requests before exiting.
+///
+/// Pass it to `warp::serve(routes).bind_with_graceful_shutdown(addr, shutdown_signal())`
+/// or hyper's `with_graceful_shutdown`; the returned server future completes once
+/// open connections have finished. actix-web servers should call
+/// `disable_signals()` and stop their `ServerHandle` when this resolves, so
+/// every binary reacts to the same signals.
+pub async fn shutdown_signal() {
+    shutdown_on(Box::pin(stream::once(os_signal()))).await
+}
+
+/// Resolves on the first signal `signals` yields. A source that ends without
+/// one never resolves, the same as a signal that never arrives.
+pub async fn shutdown_on(mut signals: impl Stream<Item = Signal> + Unpin) {
+    match signals.next().await {
+        Some(Signal::Interrupt) => info!("Received ctrl-c"),
+        Some(Signal::Terminate) => info!("Received SIGTERM"),
+        None => std::future::pending::<()>().await,
+    }
+    info!("Shutting down, draining in-flight connections");
+}
+
+/// Like [`shutdown_signal`], but sets `shutting_down` as soon as the signal
+/// arrives and only resolves [`drain_delay`] later.
+///
+/// Readiness endpoints report 503 while the flag is set, so a load balancer
+/// stops routing new traffic here while the listener is still open; the
+/// server then drains in-flight requests as usual.
+pub async fn draining_shutdown_signal(shutting_down: Arc<AtomicBool>) {
+    drain_after(shutdown_signal(), shutting_down, drain_delay()).await
+}
+
+/// Sets `shutting_down` once `shutdown` resolves, then waits out `delay`.
+pub async fn drain_after(shutdown: impl Future<Output = ()>, shutting_down: Arc<AtomicBool>, delay: Duration) {
+    shutdown.await;
+    shutting_down.store(true, Ordering::SeqCst);
+    if !delay.is_zero() {
+        info!("Failing readiness for {:?} before closing the listener", delay);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// How long a shutting-down server keeps accepting connections while its
+/// readiness check fails: `NOXIUM_DRAIN_DELAY_SECS`, or 5 seconds.
+pub fn drain_delay() -> Duration {
+    match env::var("NOXIUM_DRAIN_DELAY_SECS") {
+        Ok(value) => match value.trim().parse::<u64>() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => {
+                log::warn!(
+                    "Ignoring invalid NOXIUM_DRAIN_DELAY_SECS {:?}, using {} seconds",
+                    value,
+                    DEFAULT_DRAIN_DELAY_SECS
+                );
+                Duration::from_secs(DEFAULT_DRAIN_DELAY_SECS)
+            }
+        },
+        Err(_) => Duration::from_secs(DEFAULT_DRAIN_DELAY_SECS),
+    }
+}
+
+async fn os_signal() -> Signal {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for ctrl-c: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => Signal::Interrupt,
+        _ = terminate => Signal::Terminate,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    // Signals delivered through a channel in place of the OS
+    fn channel_signals() -> (mpsc::UnboundedSender<Signal>, impl Stream<Item = Signal> + Unpin) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let signals = stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|signal| (signal, rx)) });
+        (tx, Box::pin(signals))
+    }
+
+    #[tokio::test]
+    async fn test_resolves_on_injected_signal() {
+        for signal in [Signal::Interrupt, Signal::Terminate] {
+            let (tx, signals) = channel_signals();
+            let mut shutdown = tokio::spawn(shutdown_on(signals));
+
+            assert!(tokio::time::timeout(Duration::from_millis(50), &mut shutdown).await.is_err());
+            tx.send(signal).unwrap();
+            tokio::time::timeout(Duration::from_secs(5), shutdown)
+                .await
+                .expect("shutdown did not resolve after the signal")
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_closed_source_never_resolves() {
+        let (tx, signals) = channel_signals();
+        drop(tx);
+        assert!(tokio::time::timeout(Duration::from_millis(50), shutdown_on(signals)).await.is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_server_drains_and_stops_on_sigterm() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpStream;
+        use tokio::signal::unix::{signal, SignalKind};
+        use warp::Filter;
+
+        // Replace the default SIGTERM action before anything sends one, so the
+        // signal can never kill the test binary, whichever future polls first
+        let _keep_handler = signal(SignalKind::terminate()).unwrap();
+
+        let routes = warp::path("slow").then(|| async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            "done"
+        });
+        let (addr, server) = warp::serve(routes).bind_with_graceful_shutdown(([127, 0, 0, 1], 0), shutdown_signal());
+        let server = tokio::spawn(server);
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let in_flight = tokio::spawn(async move {
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let status = std::process::Command::new("kill")
+            .args(["-TERM", &std::process::id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let response = tokio::time::timeout(Duration::from_secs(5), in_flight).await.unwrap().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("done"), "{}", response);
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server did not stop after SIGTERM")
+            .unwrap();
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+}
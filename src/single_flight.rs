@@ -0,0 +1,244 @@
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::OnceCell;
+use url::Url;
+
+use crate::http::{self, BodyError};
+
+/// Runs one call per key and hands its result to everyone who asks for that key.
+///
+/// Callers that arrive while the call is in flight wait for it rather than
+/// starting their own, and later callers get the stored result, so an audit
+/// that reaches the same URL from several places fetches it once. Results
+/// live as long as the `SingleFlight`, so make one per audit.
+pub struct SingleFlight<K, V> {
+    calls: Mutex<HashMap<K, Arc<OnceCell<V>>>>,
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        SingleFlight { calls: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl<K: Eq + Hash, V: Clone> SingleFlight<K, V> {
+    pub fn new() -> Self {
+        SingleFlight::default()
+    }
+
+    /// Returns the result for `key`, running `call` only if no caller has yet.
+    /// If the running caller is dropped before finishing, a waiting one takes over.
+    pub async fn run<F, Fut>(&self, key: K, call: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        // The map lock is only held to find the cell, never across the call
+        let cell = self.calls.lock().unwrap().entry(key).or_default().clone();
+        cell.get_or_init(call).await.clone()
+    }
+}
+
+/// [`SingleFlight`] for blocking code: threads asking for a key that is in
+/// flight block until it finishes.
+pub struct BlockingSingleFlight<K, V> {
+    calls: Mutex<HashMap<K, Arc<OnceLock<V>>>>,
+}
+
+impl<K, V> Default for BlockingSingleFlight<K, V> {
+    fn default() -> Self {
+        BlockingSingleFlight { calls: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl<K: Eq + Hash, V: Clone> BlockingSingleFlight<K, V> {
+    pub fn new() -> Self {
+        BlockingSingleFlight::default()
+    }
+
+    pub fn run(&self, key: K, call: impl FnOnce() -> V) -> V {
+        let cell = self.calls.lock().unwrap().entry(key).or_default().clone();
+        cell.get_or_init(call).clone()
+    }
+}
+
+/// A response shared by every caller that asked for its URL.
+#[derive(Debug, Clone)]
+pub struct Fetched {
+    pub status: StatusCode,
+    pub body: Arc<str>,
+}
+
+/// A failed fetch, shared the same way.
+pub type FetchError = Arc<BodyError>;
+
+// `https://example.com` and `https://example.com/` are the same page, and so
+// is `https://example.com/#top`: the fragment never reaches the server
+fn flight_key(url: &str) -> String {
+    Url::parse(url).map_or_else(
+        |_| url.to_string(),
+        |mut url| {
+            url.set_fragment(None);
+            url.to_string()
+        },
+    )
+}
+
+// Servers that don't implement HEAD answer it with one of these
+fn head_unsupported(status: StatusCode) -> bool {
+    matches!(status, StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED)
+}
+
+/// GETs through a `reqwest::Client`, fetching each URL at most once.
+///
+/// [`get`](Self::get) keeps the body for every later caller; [`status`](Self::status)
+/// is for callers that only need the status, such as link checks, and keeps
+/// no body at all.
+pub struct DedupClient {
+    client: reqwest::Client,
+    flights: SingleFlight<String, Result<Fetched, FetchError>>,
+    statuses: SingleFlight<String, Result<StatusCode, FetchError>>,
+}
+
+impl DedupClient {
+    pub fn new(client: reqwest::Client) -> Self {
+        DedupClient { client, flights: SingleFlight::new(), statuses: SingleFlight::new() }
+    }
+
+    /// The status of `url`, from a HEAD request, or from a GET whose body is
+    /// dropped unread when the server doesn't support HEAD.
+    pub async fn status(&self, url: &str) -> Result<StatusCode, FetchError> {
+        self.statuses
+            .run(flight_key(url), || async {
+                let status = self.client.head(url).send().await.map_err(BodyError::from)?.status();
+                if !head_unsupported(status) {
+                    return Ok(status);
+                }
+                Ok(self.client.get(url).send().await.map_err(BodyError::from)?.status())
+            })
+            .await
+    }
+
+    pub async fn get(&self, url: &str) -> Result<Fetched, FetchError> {
+        self.flights
+            .run(flight_key(url), || async {
+                let response = self.client.get(url).send().await.map_err(BodyError::from)?;
+                let status = response.status();
+                Ok(Fetched { status, body: http::text(response).await?.into() })
+            })
+            .await
+    }
+}
+
+/// [`DedupClient`] over a blocking `reqwest` client.
+pub struct BlockingDedupClient {
+    client: reqwest::blocking::Client,
+    flights: BlockingSingleFlight<String, Result<Fetched, FetchError>>,
+    statuses: BlockingSingleFlight<String, Result<StatusCode, FetchError>>,
+}
+
+impl BlockingDedupClient {
+    pub fn new(client: reqwest::blocking::Client) -> Self {
+        BlockingDedupClient { client, flights: BlockingSingleFlight::new(), statuses: BlockingSingleFlight::new() }
+    }
+
+    pub fn status(&self, url: &str) -> Result<StatusCode, FetchError> {
+        self.statuses.run(flight_key(url), || {
+            let status = self.client.head(url).send().map_err(BodyError::from)?.status();
+            if !head_unsupported(status) {
+                return Ok(status);
+            }
+            Ok(self.client.get(url).send().map_err(BodyError::from)?.status())
+        })
+    }
+
+    pub fn get(&self, url: &str) -> Result<Fetched, FetchError> {
+        self.flights.run(flight_key(url), || {
+            let response = self.client.get(url).send().map_err(BodyError::from)?;
+            let status = response.status();
+            Ok(Fetched { status, body: http::blocking_text(response)?.into() })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use warp::Filter;
+
+    // Answers every request slowly, so concurrent callers overlap, and counts them
+    fn mock_server() -> (SocketAddr, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let route = warp::any().and_then(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                Ok::<_, std::convert::Infallible>("<html>page</html>")
+            }
+        });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (addr, hits)
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_share_one_fetch() {
+        let (addr, hits) = mock_server();
+        let client = DedupClient::new(reqwest::Client::new());
+
+        // Half of them spell the root without its trailing slash
+        let urls: Vec<String> = (0..10)
+            .map(|i| if i % 2 == 0 { format!("http://{}", addr) } else { format!("http://{}/", addr) })
+            .collect();
+        let responses = futures::future::join_all(urls.iter().map(|url| client.get(url))).await;
+
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        for response in responses {
+            let response = response.unwrap();
+            assert_eq!(response.status, StatusCode::OK);
+            assert_eq!(&*response.body, "<html>page</html>");
+        }
+
+        // Finished fetches are reused too, while other URLs still go out
+        client.get(&format!("http://{}/", addr)).await.unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        client.get(&format!("http://{}/about", addr)).await.unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_status_checks_send_head_once_per_page() {
+        let methods = Arc::new(Mutex::new(Vec::new()));
+        let seen = methods.clone();
+        // `/legacy` doesn't implement HEAD
+        let route = warp::method().and(warp::path::full()).map(
+            move |method: warp::http::Method, path: warp::path::FullPath| {
+                seen.lock().unwrap().push(format!("{} {}", method, path.as_str()));
+                // warp's `http` is older than reqwest's, so its status codes are a different type
+                let status = match (method, path.as_str()) {
+                    (warp::http::Method::HEAD, "/legacy") => warp::http::StatusCode::METHOD_NOT_ALLOWED,
+                    (_, "/missing") => warp::http::StatusCode::NOT_FOUND,
+                    _ => warp::http::StatusCode::OK,
+                };
+                warp::reply::with_status("body", status)
+            },
+        );
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let client = DedupClient::new(reqwest::Client::new());
+
+        for fragment in ["", "#intro", "#usage"] {
+            assert_eq!(client.status(&format!("http://{}/docs{}", addr, fragment)).await.unwrap(), StatusCode::OK);
+        }
+        assert_eq!(client.status(&format!("http://{}/missing", addr)).await.unwrap(), StatusCode::NOT_FOUND);
+        assert_eq!(client.status(&format!("http://{}/legacy", addr)).await.unwrap(), StatusCode::OK);
+        assert_eq!(*methods.lock().unwrap(), ["HEAD /docs", "HEAD /missing", "HEAD /legacy", "GET /legacy"]);
+    }
+}
@@ -5,8 +5,35 @@ use std::path::{Path, PathBuf};
 use std::collections::HashMap;
 use std::io::{self, Write};
 use std::ffi::OsStr;
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use serde::Serialize;
 use serde_json::json;
 use std::fs::copy;
+use rayon::prelude::*;
+use syntect::html::{ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+use std::net::SocketAddr;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+mod template_engine;
+use template_engine::escape_html;
+
+mod fingerprint;
+use fingerprint::fingerprint_assets;
+
+mod html_minify;
+use html_minify::minify_html;
+
+mod dev_server;
+
+#[allow(dead_code)]
+mod shutdown;
+use shutdown::shutdown_signal;
+
+static SYNTAX_SET: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
 
 // Function to read the content of a file
 fn read_file(path: &Path) -> io::Result<String> {
@@ -19,19 +46,125 @@ fn write_file(path: &Path, content: &str) -> io::Result<()> {
     file.write_all(content.as_bytes())
 }
 
-// Function to replace placeholders in a template with actual content
-fn apply_template(template: &str, content_map: &HashMap<String, String>) -> String {
-    let mut result = template.to_string();
-    for (key, value) in content_map {
-        let re = Regex::new(&format!("{{{{{}}}}}", key)).unwrap();
-        result = re.replace_all(&result, value).into_owned();
+// Function to render a template against the site context
+fn apply_template(template: &str, context: &serde_json::Value) -> io::Result<String> {
+    template_engine::render(template, context).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Placeholder used to keep rendered code blocks away from the inline markdown passes
+fn code_block_placeholder(index: usize) -> String {
+    format!("\u{0}CODEBLOCK{}\u{0}", index)
+}
+
+// Function to render a fenced code block, highlighting it when the language is known
+fn render_code_block(language: &str, code: &str) -> String {
+    let class = if language.is_empty() {
+        String::new()
+    } else {
+        format!(" class=\"language-{}\"", escape_html(language))
+    };
+
+    let highlighted = SYNTAX_SET.find_syntax_by_token(language).and_then(|syntax| {
+        let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAX_SET, ClassStyle::Spaced);
+        for line in LinesWithEndings::from(code) {
+            generator.parse_html_for_line_which_includes_newline(line).ok()?;
+        }
+        Some(generator.finalize())
+    });
+
+    // Unknown languages fall back to escaped plain text
+    let body = highlighted.unwrap_or_else(|| escape_html(code));
+    format!("<pre><code{}>{}</code></pre>", class, body)
+}
+
+// Function to pull fenced code blocks out of the markdown, replacing each with a placeholder
+fn extract_code_blocks(markdown: &str) -> (String, Vec<String>) {
+    let mut text = String::with_capacity(markdown.len());
+    let mut blocks = Vec::new();
+    let mut lines = markdown.lines();
+
+    while let Some(line) = lines.next() {
+        let Some(info) = line.trim_start().strip_prefix("```") else {
+            text.push_str(line);
+            text.push('\n');
+            continue;
+        };
+
+        let language = info.split_whitespace().next().unwrap_or("");
+        let mut code = String::new();
+        // An unterminated fence runs to the end of the document
+        for code_line in lines.by_ref() {
+            if code_line.trim_start().starts_with("```") {
+                break;
+            }
+            code.push_str(code_line);
+            code.push('\n');
+        }
+
+        text.push_str(&code_block_placeholder(blocks.len()));
+        text.push('\n');
+        blocks.push(render_code_block(language, &code));
+    }
+
+    (text, blocks)
+}
+
+// Where a page sits in the output tree, for rewriting the urls it links to
+#[derive(Debug, Clone, Default)]
+struct PageLinks<'a> {
+    // The page's directory relative to the output root, e.g. `guides/setup`; empty at the root
+    dir: String,
+    // Prefix that makes intra-site urls absolute, e.g. `https://example.com/docs` or `/docs`
+    base_url: Option<&'a str>,
+}
+
+impl PageLinks<'_> {
+    // Point a link or image url at the generated site: `.md` targets become their
+    // `.html` pages and, with a base url, are resolved against the page's directory.
+    // External, absolute, and fragment-only urls are left alone
+    fn rewrite(&self, url: &str) -> String {
+        static SCHEME_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[A-Za-z][A-Za-z0-9+.-]*:").unwrap());
+        if SCHEME_RE.is_match(url) || url.starts_with('/') || url.starts_with('#') {
+            return url.to_string();
+        }
+
+        let (path, suffix) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+        let path = match path.strip_suffix(".md") {
+            Some(stem) => format!("{}.html", stem),
+            None => path.to_string(),
+        };
+        let Some(base_url) = self.base_url else {
+            let mut path = path.as_str();
+            while let Some(rest) = path.strip_prefix("./") {
+                path = rest;
+            }
+            return format!("{}{}", path, suffix);
+        };
+
+        let mut segments: Vec<&str> = self.dir.split('/').filter(|segment| !segment.is_empty()).collect();
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => {}
+                // Links can't climb above the site root
+                ".." => {
+                    segments.pop();
+                }
+                segment => segments.push(segment),
+            }
+        }
+        let trailing_slash = if path.ends_with('/') && !segments.is_empty() { "/" } else { "" };
+        format!("{}{}{}", absolute_url(base_url, &segments.join("/")), trailing_slash, suffix)
     }
-    result
+}
+
+// Join a path's components with `/`, for urls relative to the output root
+fn url_path(relative: &Path) -> String {
+    relative.iter().map(|part| part.to_string_lossy()).collect::<Vec<_>>().join("/")
 }
 
 // Function to convert markdown text to HTML
-fn markdown_to_html(markdown: &str) -> String {
-    let mut html = markdown.to_string();
+fn markdown_to_html(markdown: &str, links: &PageLinks) -> String {
+    let (mut html, code_blocks) = extract_code_blocks(markdown);
 
     let heading_re = Regex::new(r"(?m)^# (.+)$").unwrap();
     html = heading_re.replace_all(&html, "<h1>$1</h1>").into_owned();
@@ -45,20 +178,26 @@ fn markdown_to_html(markdown: &str) -> String {
     let ordered_list_re = Regex::new(r"(?m)^\d+\. (.+)$").unwrap();
     html = ordered_list_re.replace_all(&html, "<ol>\n<li>$1</li>\n</ol>").into_owned();
 
-    let code_re = Regex::new(r"```(.*?)```").unwrap();
-    html = code_re.replace_all(&html, "<pre><code>$1</code></pre>").into_owned();
-
     let bold_re = Regex::new(r"\*\*(.*?)\*\*").unwrap();
     html = bold_re.replace_all(&html, "<strong>$1</strong>").into_owned();
 
     let italic_re = Regex::new(r"\*(.*?)\*").unwrap();
     html = italic_re.replace_all(&html, "<em>$1</em>").into_owned();
 
+    // Images first, so the link pattern doesn't claim their `[alt](src)` part
+    let image_re = Regex::new(r"!\[([^\]]*)\]\(([^\)]+)\)").unwrap();
+    html = image_re
+        .replace_all(&html, |caps: &regex::Captures| format!("<img src=\"{}\" alt=\"{}\" />", links.rewrite(&caps[2]), &caps[1]))
+        .into_owned();
+
     let link_re = Regex::new(r"\[([^\]]+)\]\(([^\)]+)\)").unwrap();
-    html = link_re.replace_all(&html, "<a href=\"$2\">$1</a>").into_owned();
+    html = link_re
+        .replace_all(&html, |caps: &regex::Captures| format!("<a href=\"{}\">{}</a>", links.rewrite(&caps[2]), &caps[1]))
+        .into_owned();
 
-    let image_re = Regex::new(r"!\[([^\]]*)\]\(([^\)]+)\)").unwrap();
-    html = image_re.replace_all(&html, "<img src=\"$2\" alt=\"$1\" />").into_owned();
+    for (index, block) in code_blocks.iter().enumerate() {
+        html = html.replace(&code_block_placeholder(index), block);
+    }
 
     html = format!("<html><body>{}</body></html>", html);
     html
@@ -95,30 +234,171 @@ fn copy_assets(input_dir: &Path, output_dir: &Path) -> io::Result<()> {
     Ok(())
 }
 
-// Function to process markdown files and generate HTML
-fn process_markdown_files(input_dir: &Path, output_dir: &Path) -> io::Result<()> {
+// A generated page, listed in the site template's `pages` and in feeds
+#[derive(Debug, Clone, Serialize)]
+struct PageInfo {
+    title: String,
+    // Relative to the output root
+    url: String,
+    meta: HashMap<String, String>,
+}
+
+// A markdown source and where its generated files go
+struct MarkdownFile {
+    source: PathBuf,
+    output_dir: PathBuf,
+}
+
+// Function to walk the input tree, creating output directories up front so
+// pages can be rendered in any order
+fn collect_markdown_files(input_dir: &Path, output_dir: &Path, files: &mut Vec<MarkdownFile>) -> io::Result<()> {
     for entry in fs::read_dir(input_dir)? {
         let entry = entry?;
         let path = entry.path();
         if path.is_dir() {
-            let new_output_dir = output_dir.join(path.file_name().unwrap());
+            let dir_name = path.file_name().unwrap();
+            let new_output_dir = output_dir.join(dir_name);
             fs::create_dir_all(&new_output_dir)?;
-            process_markdown_files(&path, &new_output_dir)?;
+            collect_markdown_files(&path, &new_output_dir, files)?;
         } else if path.extension() == Some(OsStr::new("md")) {
-            let content = read_file(&path)?;
-            let metadata = extract_metadata(&content);
-            let html_content = markdown_to_html(&content);
-            let output_path = output_dir.join(path.file_stem().unwrap()).with_extension("html");
-            write_file(&output_path, &html_content)?;
-
-            let metadata_path = output_dir.join(path.file_stem().unwrap()).with_extension("json");
-            let metadata_content = serde_json::to_string(&metadata)?;
-            write_file(&metadata_path, &metadata_content)?;
+            files.push(MarkdownFile {
+                source: path,
+                output_dir: output_dir.to_path_buf(),
+            });
         }
     }
     Ok(())
 }
 
+// A converted page on its way to disk; plugins may change any of it
+struct RenderedPage {
+    html: String,
+    metadata: HashMap<String, String>,
+    // Where the HTML is written; the metadata JSON goes alongside it
+    output_path: PathBuf,
+}
+
+// What a plugin sees once every page and the site index have been written.
+// No built-in plugin reads it yet
+#[allow(dead_code)]
+struct BuildContext<'a> {
+    output_dir: &'a Path,
+    pages: &'a [PageInfo],
+}
+
+// A custom step in the build. Pages render in parallel, so `on_page` may be
+// called from several threads at once and in any order. The dev server
+// rebuilds on its own thread, hence `Send`
+trait SsgPlugin: Send + Sync {
+    fn on_page(&self, page: &mut RenderedPage);
+    fn on_complete(&self, _ctx: &BuildContext) {}
+}
+
+// Plugins in the order they run
+#[derive(Default)]
+struct PluginRegistry {
+    plugins: Vec<Box<dyn SsgPlugin>>,
+}
+
+impl PluginRegistry {
+    fn register(&mut self, plugin: impl SsgPlugin + 'static) {
+        self.plugins.push(Box::new(plugin));
+    }
+
+    // Build a registry from a comma-separated list of built-in plugin names
+    fn from_names(names: &str) -> io::Result<Self> {
+        let mut registry = PluginRegistry::default();
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name {
+                "minify-html" => registry.register(HtmlMinifier),
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown SSG plugin: {}", name))),
+            }
+        }
+        Ok(registry)
+    }
+
+    fn on_page(&self, page: &mut RenderedPage) {
+        for plugin in &self.plugins {
+            plugin.on_page(page);
+        }
+    }
+
+    fn on_complete(&self, ctx: &BuildContext) {
+        for plugin in &self.plugins {
+            plugin.on_complete(ctx);
+        }
+    }
+}
+
+// Built-in plugin that runs every page through `minify_html`
+struct HtmlMinifier;
+
+impl SsgPlugin for HtmlMinifier {
+    fn on_page(&self, page: &mut RenderedPage) {
+        page.html = minify_html(&page.html);
+    }
+}
+
+// Function to convert one markdown file, run the plugins over it, and write its HTML and metadata
+fn render_markdown_file(
+    file: &MarkdownFile,
+    output_root: &Path,
+    plugins: &PluginRegistry,
+    link_base_url: Option<&str>,
+) -> io::Result<PageInfo> {
+    let content = read_file(&file.source)?;
+    let stem = file.source.file_stem().unwrap();
+    let links = PageLinks {
+        dir: file.output_dir.strip_prefix(output_root).map(url_path).unwrap_or_default(),
+        base_url: link_base_url,
+    };
+    let mut page = RenderedPage {
+        metadata: extract_metadata(&content),
+        html: markdown_to_html(&content, &links),
+        output_path: file.output_dir.join(stem).with_extension("html"),
+    };
+    plugins.on_page(&mut page);
+
+    // A plugin may have moved the page, so its url comes from where it ends up
+    let relative = page.output_path.strip_prefix(output_root).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{} is outside the output directory", page.output_path.display()))
+    })?;
+    let url = url_path(relative);
+    if let Some(parent) = page.output_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_file(&page.output_path, &page.html)?;
+
+    let metadata_content = serde_json::to_string(&page.metadata)?;
+    write_file(&page.output_path.with_extension("json"), &metadata_content)?;
+
+    Ok(PageInfo {
+        title: page.metadata.get("title").cloned().unwrap_or_else(|| stem.to_string_lossy().to_string()),
+        url,
+        meta: page.metadata,
+    })
+}
+
+// Function to process markdown files and generate HTML, returning the page index
+fn process_markdown_files(
+    input_dir: &Path,
+    output_dir: &Path,
+    plugins: &PluginRegistry,
+    link_base_url: Option<&str>,
+) -> io::Result<Vec<PageInfo>> {
+    let mut files = Vec::new();
+    collect_markdown_files(input_dir, output_dir, &mut files)?;
+
+    // Each page writes only its own files, so they convert independently
+    let mut pages = files
+        .par_iter()
+        .map(|file| render_markdown_file(file, output_dir, plugins, link_base_url))
+        .collect::<io::Result<Vec<_>>>()?;
+    // Neither directory nor completion order is stable; keep the index sorted
+    pages.sort_by(|a, b| a.url.cmp(&b.url));
+    Ok(pages)
+}
+
 // Function to handle pagination
 fn paginate_content(content: &str, items_per_page: usize) -> Vec<String> {
     let mut pages = Vec::new();
@@ -137,39 +417,581 @@ fn paginate_content(content: &str, items_per_page: usize) -> Vec<String> {
 }
 
 // Function to generate the final site using a template
-fn generate_site(template_path: &Path, output_dir: &Path, content_map: &HashMap<String, String>) -> io::Result<()> {
+fn generate_site(template_path: &Path, output_dir: &Path, context: &serde_json::Value) -> io::Result<()> {
     let template_content = read_file(template_path)?;
-    let final_html = apply_template(&template_content, content_map);
+    let final_html = apply_template(&template_content, context)?;
     write_file(&output_dir.join("index.html"), &final_html)?;
     Ok(())
 }
 
+// Feed flavours the SSG can emit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FeedFormat {
+    Rss,
+    Atom,
+}
+
+impl FeedFormat {
+    fn file_name(self) -> &'static str {
+        match self {
+            FeedFormat::Rss => "rss.xml",
+            FeedFormat::Atom => "atom.xml",
+        }
+    }
+}
+
+// Site-wide settings for feeds; `base_url` turns page urls into the absolute links feeds require
+struct FeedConfig {
+    title: String,
+    description: String,
+    base_url: String,
+    max_items: usize,
+}
+
+// A page with the front matter a feed entry needs
+struct FeedEntry<'a> {
+    title: &'a str,
+    url: String,
+    date: DateTime<FixedOffset>,
+    description: Option<&'a str>,
+}
+
+// Accept RFC 3339 timestamps or plain `YYYY-MM-DD` dates (taken as midnight UTC)
+fn parse_front_matter_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    let value = value.trim();
+    DateTime::parse_from_rfc3339(value).ok().or_else(|| {
+        let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+        Some(date.and_hms_opt(0, 0, 0)?.and_utc().fixed_offset())
+    })
+}
+
+fn absolute_url(base_url: &str, path: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), path.trim_start_matches('/'))
+}
+
+// Pages with a `title` and parseable `date`, newest first, capped at `max_items`
+fn feed_entries<'a>(pages: &'a [PageInfo], config: &FeedConfig) -> Vec<FeedEntry<'a>> {
+    let mut entries: Vec<_> = pages
+        .iter()
+        .filter_map(|page| {
+            Some(FeedEntry {
+                title: page.meta.get("title")?.trim(),
+                url: absolute_url(&config.base_url, &page.url),
+                date: parse_front_matter_date(page.meta.get("date")?)?,
+                description: page.meta.get("description").map(|description| description.trim()),
+            })
+        })
+        .collect();
+    entries.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.url.cmp(&b.url)));
+    entries.truncate(config.max_items);
+    entries
+}
+
+// escape_html's entities are all valid XML too
+fn render_rss(entries: &[FeedEntry], config: &FeedConfig) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n");
+    xml.push_str(&format!("<title>{}</title>\n", escape_html(&config.title)));
+    xml.push_str(&format!("<link>{}</link>\n", escape_html(&absolute_url(&config.base_url, ""))));
+    xml.push_str(&format!("<description>{}</description>\n", escape_html(&config.description)));
+    xml.push_str(&format!(
+        "<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+        escape_html(&absolute_url(&config.base_url, FeedFormat::Rss.file_name()))
+    ));
+    for entry in entries {
+        let url = escape_html(&entry.url);
+        xml.push_str("<item>\n");
+        xml.push_str(&format!("<title>{}</title>\n", escape_html(entry.title)));
+        xml.push_str(&format!("<link>{}</link>\n", url));
+        xml.push_str(&format!("<guid isPermaLink=\"true\">{}</guid>\n", url));
+        xml.push_str(&format!("<pubDate>{}</pubDate>\n", entry.date.to_rfc2822()));
+        if let Some(description) = entry.description {
+            xml.push_str(&format!("<description>{}</description>\n", escape_html(description)));
+        }
+        xml.push_str("</item>\n");
+    }
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+fn render_atom(entries: &[FeedEntry], config: &FeedConfig) -> String {
+    // Atom requires a feed-level timestamp; use the newest entry's
+    let updated = entries.first().map_or_else(|| Utc::now().fixed_offset(), |entry| entry.date);
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str(&format!("<title>{}</title>\n", escape_html(&config.title)));
+    xml.push_str(&format!("<subtitle>{}</subtitle>\n", escape_html(&config.description)));
+    xml.push_str(&format!("<id>{}</id>\n", escape_html(&absolute_url(&config.base_url, ""))));
+    xml.push_str(&format!("<link href=\"{}\"/>\n", escape_html(&absolute_url(&config.base_url, ""))));
+    xml.push_str(&format!(
+        "<link rel=\"self\" href=\"{}\"/>\n",
+        escape_html(&absolute_url(&config.base_url, FeedFormat::Atom.file_name()))
+    ));
+    xml.push_str(&format!("<updated>{}</updated>\n", updated.to_rfc3339()));
+    for entry in entries {
+        let url = escape_html(&entry.url);
+        xml.push_str("<entry>\n");
+        xml.push_str(&format!("<title>{}</title>\n", escape_html(entry.title)));
+        xml.push_str(&format!("<link href=\"{}\"/>\n", url));
+        xml.push_str(&format!("<id>{}</id>\n", url));
+        xml.push_str(&format!("<updated>{}</updated>\n", entry.date.to_rfc3339()));
+        if let Some(description) = entry.description {
+            xml.push_str(&format!("<summary>{}</summary>\n", escape_html(description)));
+        }
+        xml.push_str("</entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+// Function to write an RSS or Atom feed of the most recent dated pages
+fn generate_feed(pages: &[PageInfo], output_dir: &Path, fmt: FeedFormat, config: &FeedConfig) -> io::Result<()> {
+    let entries = feed_entries(pages, config);
+    let xml = match fmt {
+        FeedFormat::Rss => render_rss(&entries, config),
+        FeedFormat::Atom => render_atom(&entries, config),
+    };
+    write_file(&output_dir.join(fmt.file_name()), &xml)
+}
+
+// "1"/"true"/"yes" or "0"/"false"/"no", in any case
+fn parse_flag(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" => Some(true),
+        "0" | "false" | "no" | "" => Some(false),
+        _ => None,
+    }
+}
+
+// An on/off setting from the environment, off when unset or invalid
+fn env_flag(name: &str) -> bool {
+    let Ok(value) = env::var(name) else { return false };
+    parse_flag(&value).unwrap_or_else(|| {
+        log::warn!("Ignoring invalid {} {:?}, leaving it off", name, value);
+        false
+    })
+}
+
+// Everything a build needs, read once so the dev server can rebuild with it
+struct Site {
+    input_dir: PathBuf,
+    output_dir: PathBuf,
+    template_path: PathBuf,
+    plugins: PluginRegistry,
+    link_base_url: Option<String>,
+    feed: Option<(FeedFormat, FeedConfig)>,
+    // Whether to fingerprint assets, and whether to keep the unhashed files too
+    fingerprint: Option<bool>,
+}
+
+impl Site {
+    fn from_env() -> io::Result<Self> {
+        // Feeds need absolute links, so they are only generated once the site's URL is known
+        let feed = env::var("BASE_URL").ok().map(|base_url| {
+            let fmt = match env::var("FEED_FORMAT").as_deref() {
+                Ok("atom") => FeedFormat::Atom,
+                _ => FeedFormat::Rss,
+            };
+            let feed_config = FeedConfig {
+                title: "My Static Site".to_string(),
+                description: "Welcome to My Static Site".to_string(),
+                base_url,
+                max_items: env::var("FEED_ITEMS").ok().and_then(|items| items.parse().ok()).unwrap_or(20),
+            };
+            (fmt, feed_config)
+        });
+
+        Ok(Site {
+            input_dir: env::var("INPUT_DIR").unwrap_or_else(|_| "content".to_string()).into(),
+            output_dir: env::var("OUTPUT_DIR").unwrap_or_else(|_| "public".to_string()).into(),
+            template_path: env::var("TEMPLATE_PATH").unwrap_or_else(|_| "template.html".to_string()).into(),
+            // e.g. SSG_PLUGINS=minify-html
+            plugins: PluginRegistry::from_names(&env::var("SSG_PLUGINS").unwrap_or_default())?,
+            // Makes links between pages absolute, e.g. LINK_BASE_URL=/docs when the site is served from a subpath
+            link_base_url: env::var("LINK_BASE_URL").ok(),
+            feed,
+            // Renames assets to name.<hash>.ext so browsers can cache them for good, e.g. FINGERPRINT_ASSETS=1.
+            // KEEP_UNHASHED_ASSETS=1 also keeps the original files, for links from outside the site
+            fingerprint: env_flag("FINGERPRINT_ASSETS").then(|| env_flag("KEEP_UNHASHED_ASSETS")),
+        })
+    }
+
+    // Generate the whole site into `output_dir`
+    fn build(&self) -> io::Result<()> {
+        if !self.output_dir.exists() {
+            fs::create_dir_all(&self.output_dir)?;
+        }
+
+        let pages =
+            process_markdown_files(&self.input_dir, &self.output_dir, &self.plugins, self.link_base_url.as_deref())?;
+        copy_assets(&self.input_dir, &self.output_dir)?;
+
+        let context = json!({
+            "title": "My Static Site",
+            "header": "Welcome to My Static Site",
+            "footer": "© 2024 My Static Site",
+            "pages": pages,
+        });
+
+        generate_site(&self.template_path, &self.output_dir, &context)?;
+
+        if let Some((fmt, feed_config)) = &self.feed {
+            generate_feed(&pages, &self.output_dir, *fmt, feed_config)?;
+        }
+
+        if let Some(keep_originals) = self.fingerprint {
+            let manifest = fingerprint_assets(&self.output_dir, keep_originals)?;
+            println!("Fingerprinted {} assets", manifest.len());
+        }
+
+        self.plugins.on_complete(&BuildContext { output_dir: &self.output_dir, pages: &pages });
+        Ok(())
+    }
+}
+
+// How long the sources must be quiet before the dev server rebuilds
+const REBUILD_DEBOUNCE: Duration = Duration::from_millis(200);
+
+// Serve the built site on `addr`, rebuilding it when its sources change and
+// reloading the open pages afterwards. Runs until ctrl-c or SIGTERM
+fn serve_with_reload(site: Site, addr: SocketAddr) -> io::Result<()> {
+    let to_io = |e: &dyn std::fmt::Display| io::Error::other(e.to_string());
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let (reloads, _) = broadcast::channel(16);
+        let output_dir = site.output_dir.clone();
+        let watched = [site.input_dir.clone(), site.template_path.clone()];
+        let rebuild = move || site.build();
+        let _watcher = dev_server::watch(&watched, &output_dir, REBUILD_DEBOUNCE, rebuild, reloads.clone())
+            .map_err(|e| to_io(&e))?;
+        let (addr, server) = dev_server::bind(addr, output_dir, reloads, shutdown_signal()).map_err(|e| to_io(&e))?;
+        println!("Serving on http://{} with live reload", addr);
+        server.await;
+        Ok(())
+    })
+}
+
 // Main function to execute the SSG
 fn main() -> io::Result<()> {
     env_logger::init();
 
-    let input_dir = env::var("INPUT_DIR").unwrap_or_else(|_| "content".to_string());
-    let output_dir = env::var("OUTPUT_DIR").unwrap_or_else(|_| "public".to_string());
-    let template_path = env::var("TEMPLATE_PATH").unwrap_or_else(|_| "template.html".to_string());
+    let site = Site::from_env()?;
+    site.build()?;
+    println!("Static site generated successfully in {}", site.output_dir.display());
+
+    // Keeps serving the site and rebuilding it on changes, e.g. SSG_DEV_ADDR=127.0.0.1:4000
+    if let Ok(addr) = env::var("SSG_DEV_ADDR") {
+        let addr = addr.parse().map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid SSG_DEV_ADDR {:?}: {}", addr, e))
+        })?;
+        serve_with_reload(site, addr)?;
+    }
+    Ok(())
+}
 
-    let input_dir_path = Path::new(&input_dir);
-    let output_dir_path = Path::new(&output_dir);
-    let template_path = Path::new(&template_path);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    if !output_dir_path.exists() {
-        fs::create_dir_all(output_dir_path)?;
+    #[test]
+    fn test_rust_code_block_is_highlighted() {
+        let html = markdown_to_html("# Example\n\n```rust\nfn main() { let x = 1; }\n```\n", &PageLinks::default());
+        assert!(html.contains("<pre><code class=\"language-rust\">"));
+        assert!(html.contains("<span class=\""));
+        assert!(html.contains("main"));
     }
 
-    process_markdown_files(input_dir_path, output_dir_path)?;
-    copy_assets(input_dir_path, output_dir_path)?;
+    #[test]
+    fn test_code_block_content_is_escaped() {
+        let html = markdown_to_html("```\nif a < b && *c* {}\n```\n", &PageLinks::default());
+        assert!(html.contains("<pre><code>if a &lt; b &amp;&amp; *c* {}\n</code></pre>"));
+        assert!(!html.contains("<em>"));
+    }
 
-    let mut content_map = HashMap::new();
-    content_map.insert("title".to_string(), "My Static Site".to_string());
-    content_map.insert("header".to_string(), "Welcome to My Static Site".to_string());
-    content_map.insert("footer".to_string(), "© 2024 My Static Site".to_string());
+    #[test]
+    fn test_multiple_code_blocks() {
+        let html = markdown_to_html("```unknownlang\nfirst\n```\ntext\n```\nsecond\n```\n", &PageLinks::default());
+        assert!(html.contains("<pre><code class=\"language-unknownlang\">first\n</code></pre>"));
+        assert!(html.contains("<pre><code>second\n</code></pre>"));
+        assert!(html.contains("text"));
+    }
 
-    generate_site(template_path, output_dir_path, &content_map)?;
+    #[test]
+    fn test_markdown_links_point_at_generated_pages() {
+        let links = PageLinks::default();
+        let html = markdown_to_html(
+            "See [x](./page.md), [y](guide.md#install), [z](https://example.com/readme.md) and [top](#top).\n\n![Logo](./img/logo.png)",
+            &links,
+        );
+        assert!(html.contains("<a href=\"page.html\">x</a>"), "{}", html);
+        assert!(html.contains("<a href=\"guide.html#install\">y</a>"));
+        assert!(html.contains("<a href=\"https://example.com/readme.md\">z</a>"));
+        assert!(html.contains("<a href=\"#top\">top</a>"));
+        assert!(html.contains("<img src=\"img/logo.png\" alt=\"Logo\" />"));
+    }
 
-    println!("Static site generated successfully in {}", output_dir);
-    Ok(())
-}
\ No newline at end of file
+    #[test]
+    fn test_links_resolve_against_base_url() {
+        let links = PageLinks { dir: "guides/setup".to_string(), base_url: Some("https://example.com/docs/") };
+        assert_eq!(links.rewrite("./page.md"), "https://example.com/docs/guides/setup/page.html");
+        assert_eq!(links.rewrite("../../index.md?v=2"), "https://example.com/docs/index.html?v=2");
+        assert_eq!(links.rewrite("../../../../escape.md"), "https://example.com/docs/escape.html");
+        assert_eq!(links.rewrite("images/"), "https://example.com/docs/guides/setup/images/");
+        for untouched in ["https://example.com/a.md", "mailto:team@example.com", "//cdn.example.com/x.png", "/about.md", "#intro"] {
+            assert_eq!(links.rewrite(untouched), untouched);
+        }
+    }
+
+    #[test]
+    fn test_site_template_lists_pages() {
+        let root = env::temp_dir().join(format!("noxium-ssg-{}", std::process::id()));
+        let (input, output) = (root.join("content"), root.join("public"));
+        fs::create_dir_all(input.join("guides")).unwrap();
+        fs::create_dir_all(output.join("guides")).unwrap();
+        write_file(&input.join("about.md"), "title: About <us>\n\nHello").unwrap();
+        write_file(&input.join("guides").join("setup.md"), "Install it").unwrap();
+        let template_path = root.join("template.html");
+        write_file(
+            &template_path,
+            "<h1>{{title}}</h1>{{#if pages}}<ul>{{#each pages}}<li><a href=\"{{url}}\">{{title}}</a></li>{{/each}}</ul>{{/if}}",
+        )
+        .unwrap();
+
+        let pages = process_markdown_files(&input, &output, &PluginRegistry::default(), None).unwrap();
+        generate_site(&template_path, &output, &json!({ "title": "Site", "pages": pages })).unwrap();
+
+        let index = read_file(&output.join("index.html")).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(
+            index,
+            "<h1>Site</h1><ul><li><a href=\"about.html\">About &lt;us&gt;</a></li>\
+             <li><a href=\"guides/setup.html\">setup</a></li></ul>"
+        );
+    }
+
+    #[test]
+    fn test_parallel_output_matches_sequential() {
+        let root = env::temp_dir().join(format!("noxium-ssg-many-{}", std::process::id()));
+        let input = root.join("content");
+        for section in 0..4 {
+            fs::create_dir_all(input.join(format!("section{}", section))).unwrap();
+            for page in 0..50 {
+                let markdown = format!("title: Page {} {}\n\n# Heading\n\n* **item** {}\n", section, page, page);
+                write_file(&input.join(format!("section{}", section)).join(format!("page{}.md", page)), &markdown).unwrap();
+            }
+        }
+        write_file(&input.join("index.md"), "title: Home\n\nWelcome").unwrap();
+
+        let parallel_output = root.join("parallel");
+        let pages = process_markdown_files(&input, &parallel_output, &PluginRegistry::default(), None).unwrap();
+
+        let sequential_output = root.join("sequential");
+        let mut files = Vec::new();
+        collect_markdown_files(&input, &sequential_output, &mut files).unwrap();
+        let mut expected: Vec<_> = files.iter().map(|file| render_markdown_file(file, &sequential_output, &PluginRegistry::default(), None).unwrap()).collect();
+        expected.sort_by(|a, b| a.url.cmp(&b.url));
+
+        assert_eq!(pages.len(), 201);
+        assert_eq!(serde_json::to_value(&pages).unwrap(), serde_json::to_value(&expected).unwrap());
+        for page in &pages {
+            for extension in ["html", "json"] {
+                let relative = Path::new(&page.url).with_extension(extension);
+                let parallel = read_file(&parallel_output.join(&relative)).unwrap();
+                assert_eq!(parallel, read_file(&sequential_output.join(&relative)).unwrap(), "{}", relative.display());
+            }
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    // Appends a footer and a `plugin` metadata key, and moves the page under `out/`
+    struct Stamp;
+
+    impl SsgPlugin for Stamp {
+        fn on_page(&self, page: &mut RenderedPage) {
+            page.html = page.html.replace("</body>", "<footer>stamped</footer></body>");
+            page.metadata.insert("plugin".to_string(), "stamp".to_string());
+            let file_name = page.output_path.file_name().unwrap().to_owned();
+            page.output_path = page.output_path.parent().unwrap().join("out").join(file_name);
+        }
+    }
+
+    // Records every on_complete call
+    #[derive(Clone, Default)]
+    struct CompletionLog(std::sync::Arc<std::sync::Mutex<Vec<Vec<String>>>>);
+
+    impl SsgPlugin for CompletionLog {
+        fn on_page(&self, _page: &mut RenderedPage) {}
+
+        fn on_complete(&self, ctx: &BuildContext) {
+            assert!(ctx.output_dir.join("out").join("post.html").exists());
+            self.0.lock().unwrap().push(ctx.pages.iter().map(|page| page.url.clone()).collect());
+        }
+    }
+
+    #[test]
+    fn test_plugins_mutate_pages_in_order() {
+        let root = env::temp_dir().join(format!("noxium-ssg-plugins-{}", std::process::id()));
+        let (input, output) = (root.join("content"), root.join("public"));
+        fs::create_dir_all(&input).unwrap();
+        write_file(&input.join("post.md"), "title: Post\n\n# Hello\n\n**bold** *italic*\n\n```\nkeep   this\n```\n").unwrap();
+        let template_path = root.join("template.html");
+        write_file(&template_path, "{{#each pages}}<a href=\"{{url}}\">{{title}}</a>{{/each}}").unwrap();
+
+        let log = CompletionLog::default();
+        let mut plugins = PluginRegistry::default();
+        plugins.register(Stamp);
+        // Runs second, so it sees the footer Stamp added
+        plugins.register(HtmlMinifier);
+        plugins.register(log.clone());
+        let site = Site {
+            input_dir: input,
+            output_dir: output.clone(),
+            template_path,
+            plugins,
+            link_base_url: None,
+            feed: None,
+            fingerprint: None,
+        };
+        site.build().unwrap();
+
+        let html = read_file(&output.join("out").join("post.html")).unwrap();
+        let metadata = read_file(&output.join("out").join("post.json")).unwrap();
+        let index = read_file(&output.join("index.html")).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        assert!(html.ends_with("</code></pre><footer>stamped</footer></body></html>"), "{}", html);
+        assert!(html.contains("<pre><code>keep   this\n</code></pre>"));
+        assert!(html.contains("<strong>bold</strong> <em>italic</em>"), "{}", html);
+        assert!(!html.contains(">\n<"));
+        assert_eq!(serde_json::from_str::<HashMap<String, String>>(&metadata).unwrap()["plugin"], "stamp");
+        assert_eq!(index, "<a href=\"out/post.html\">Post</a>");
+        assert_eq!(*log.0.lock().unwrap(), vec![vec!["out/post.html".to_string()]]);
+    }
+
+    #[test]
+    fn test_plugins_are_looked_up_by_name() {
+        assert_eq!(PluginRegistry::from_names("").unwrap().plugins.len(), 0);
+        assert_eq!(PluginRegistry::from_names("minify-html, ").unwrap().plugins.len(), 1);
+        assert_eq!(PluginRegistry::from_names("toc").err().unwrap().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    fn feed_pages() -> Vec<PageInfo> {
+        let page = |url: &str, meta: &[(&str, &str)]| PageInfo {
+            title: url.to_string(),
+            url: url.to_string(),
+            meta: meta.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+        };
+        vec![
+            page("old.html", &[("title", "Old"), ("date", "2023-01-05")]),
+            page("posts/new.html", &[("title", "Tom & Jerry <3"), ("date", "2024-03-01"), ("description", "Cats \"and\" mice")]),
+            page("middle.html", &[("title", "Middle"), ("date", "2023-06-15T12:00:00+02:00")]),
+            page("undated.html", &[("title", "No date")]),
+            page("bad-date.html", &[("title", "Bad"), ("date", "someday")]),
+        ]
+    }
+
+    // Parse with quick-xml, returning the text of every `<title>` in document order
+    fn xml_titles(xml: &str) -> Vec<String> {
+        use quick_xml::events::Event;
+        let mut reader = quick_xml::Reader::from_str(xml);
+        let (mut titles, mut raw, mut in_title) = (Vec::new(), String::new(), false);
+        loop {
+            match reader.read_event().expect("feed should be well-formed XML") {
+                Event::Start(tag) => in_title = tag.name().as_ref() == "title",
+                Event::Text(text) if in_title => raw.push_str(&text.xml10_content()),
+                // Entities arrive as their own events
+                Event::GeneralRef(reference) if in_title => raw.push_str(&format!("&{};", &*reference)),
+                Event::End(_) if in_title => {
+                    titles.push(quick_xml::escape::unescape(&raw).unwrap().into_owned());
+                    raw.clear();
+                    in_title = false;
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        titles
+    }
+
+    fn generate_test_feed(fmt: FeedFormat, max_items: usize) -> String {
+        let dir = env::temp_dir().join(format!("noxium-ssg-feed-{:?}-{}-{}", fmt, max_items, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = FeedConfig {
+            title: "Site".to_string(),
+            description: "News".to_string(),
+            base_url: "https://example.com/blog/".to_string(),
+            max_items,
+        };
+        generate_feed(&feed_pages(), &dir, fmt, &config).unwrap();
+        let xml = read_file(&dir.join(fmt.file_name())).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        xml
+    }
+
+    #[test]
+    fn test_flags_can_be_turned_off() {
+        assert_eq!(parse_flag("1"), Some(true));
+        assert_eq!(parse_flag("True"), Some(true));
+        assert_eq!(parse_flag("0"), Some(false));
+        assert_eq!(parse_flag("false"), Some(false));
+        assert_eq!(parse_flag(""), Some(false));
+        assert_eq!(parse_flag("maybe"), None);
+    }
+
+    #[test]
+    fn test_rss_feed_lists_dated_pages_newest_first() {
+        let xml = generate_test_feed(FeedFormat::Rss, 10);
+        // The channel title comes first, then one per item
+        assert_eq!(xml_titles(&xml), ["Site", "Tom & Jerry <3", "Middle", "Old"]);
+        assert!(xml.contains("<link>https://example.com/blog/posts/new.html</link>"));
+        assert!(xml.contains("<pubDate>Fri, 1 Mar 2024 00:00:00 +0000</pubDate>"));
+        assert!(xml.contains("<description>Cats &quot;and&quot; mice</description>"));
+    }
+
+    #[test]
+    fn test_atom_feed_is_limited_to_most_recent() {
+        let xml = generate_test_feed(FeedFormat::Atom, 2);
+        assert_eq!(xml_titles(&xml), ["Site", "Tom & Jerry <3", "Middle"]);
+        assert!(xml.contains("<updated>2024-03-01T00:00:00+00:00</updated>"));
+        assert!(xml.contains("<link href=\"https://example.com/blog/middle.html\"/>"));
+    }
+
+    #[tokio::test]
+    async fn test_changed_markdown_reloads_connected_pages_after_rebuild() {
+        use futures::StreamExt;
+
+        let root = env::temp_dir().join(format!("noxium-ssg-dev-{}", std::process::id()));
+        let (input, output) = (root.join("content"), root.join("public"));
+        fs::create_dir_all(&input).unwrap();
+        write_file(&input.join("about.md"), "Before the change").unwrap();
+        let template_path = root.join("template.html");
+        write_file(&template_path, "<h1>{{title}}</h1>").unwrap();
+        let site = Site {
+            input_dir: input.clone(),
+            output_dir: output.clone(),
+            template_path: template_path.clone(),
+            plugins: PluginRegistry::default(),
+            link_base_url: None,
+            feed: None,
+            fingerprint: None,
+        };
+        site.build().unwrap();
+
+        let (reloads, _) = broadcast::channel(16);
+        let watched = [input.clone(), template_path];
+        let debounce = Duration::from_millis(50);
+        let _watcher = dev_server::watch(&watched, &output, debounce, move || site.build(), reloads.clone()).unwrap();
+        let (addr, server) =
+            dev_server::bind(([127, 0, 0, 1], 0).into(), output.clone(), reloads, std::future::pending()).unwrap();
+        tokio::spawn(server);
+        let url = format!("ws://{}/{}", addr, dev_server::RELOAD_PATH);
+        let (mut client, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+        write_file(&input.join("about.md"), "After the change").unwrap();
+        let message = tokio::time::timeout(Duration::from_secs(10), client.next()).await.unwrap().unwrap().unwrap();
+        assert_eq!(message.into_text().unwrap(), dev_server::RELOAD_MESSAGE);
+        // The reload comes once the page has been regenerated
+        let page = read_file(&output.join("about.html")).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        assert!(page.contains("After the change"), "{}", page);
+    }
+}
@@ -7,18 +7,69 @@ use askama::Template;
 use serde::{Deserialize, Serialize};
 use log::{info, error, debug};
 use std::fs;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use actix_web::middleware::Logger;
-use actix_web::web::Json;
-use actix_web::http::header::{X_REQUEST_ID, CONTENT_TYPE};
+use actix_web::http::header::CONTENT_TYPE;
 use std::env;
 use sqlx::SqlitePool;
 use actix_web::middleware::NormalizePath;
-use actix_multipart::Multipart;
-use std::io::Write;
-use lazy_static::lazy_static;
+use actix_multipart::{Field, Multipart};
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
 use actix_web::http::header::HeaderValue;
 use actix_service::Service as _;
+use validator::Validate;
+
+mod error;
+use error::NoxiumError;
+
+mod health;
+use health::{health_routes, Metrics, RequestMetrics};
+
+#[allow(dead_code)]
+mod vdom;
+
+mod live;
+use live::{live_routes, LiveUpdates};
+
+mod template_engine;
+
+mod html_minify;
+use html_minify::minify_html;
+
+mod db;
+use db::items::{self, NewItem, Pagination};
+
+#[allow(dead_code)]
+mod body_limit;
+use body_limit::{actix_body_limits, max_body_bytes};
+
+#[allow(dead_code)]
+mod request_log;
+use request_log::RequestLogger;
+
+#[allow(dead_code)]
+mod timeout;
+use timeout::{request_timeout, Timeout};
+
+#[allow(dead_code)]
+mod rate_limit;
+use rate_limit::RateLimiter;
+
+mod static_files;
+use static_files::{static_dir, static_routes};
+
+mod validation;
+use validation::{not_blank, Valid};
+
+mod auth_backend;
+use auth_backend::{AuthBackend, AuthError, MemoryBackend, SqliteBackend};
+
+mod shutdown;
+use shutdown::draining_shutdown_signal;
 
 // Define a struct that represents our template data
 #[derive(Template)]
@@ -27,17 +78,40 @@ struct IndexTemplate {
     message: String,
 }
 
+// How pages are rendered: askama templates compiled into the binary, or
+// template_engine templates read from TEMPLATE_DIR on every request so they
+// can be edited without a rebuild
+enum TemplateMode {
+    Compiled,
+    Runtime(std::path::PathBuf),
+}
+
+// Whether rendered pages go through `minify_html` before they are sent;
+// SSR_MINIFY_HTML=1 turns it on
+#[derive(Clone, Copy)]
+struct MinifyHtml(bool);
+
+impl MinifyHtml {
+    fn from_env() -> Self {
+        MinifyHtml(env::var("SSR_MINIFY_HTML").is_ok_and(|value| matches!(value.trim(), "1" | "true")))
+    }
+}
+
 // Define a struct for configuration data
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Validate)]
 struct Config {
+    #[validate(range(min = 1, message = "Port cannot be zero"))]
     port: u16,
+    #[validate(custom(function = "not_blank"))]
     database_url: String,
 }
 
 // Define a struct for user registration
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Validate)]
 struct UserRegistration {
+    #[validate(custom(function = "not_blank"))]
     username: String,
+    #[validate(length(min = 1, message = "must not be empty"))]
     password: String,
 }
 
@@ -48,102 +122,249 @@ struct UserDetails {
     username: String,
 }
 
-// Define a custom error type for API errors
-#[derive(Debug)]
-enum ApiError {
-    InvalidInput(String),
-    InternalError(String),
-    DatabaseError(String),
-    AuthenticationError(String),
+// Requests allowed per client IP per minute
+const RATE_LIMIT_PER_MINUTE: u32 = 100;
+
+// Total upload size per request when NOXIUM_MAX_UPLOAD_BYTES is unset: 10 MiB
+const DEFAULT_MAX_UPLOAD_BYTES: u64 = 10 * 1024 * 1024;
+
+// Uploads log how far along they are every this many bytes
+const UPLOAD_PROGRESS_INTERVAL: u64 = 1024 * 1024;
+
+// Longest filename an upload is stored under
+const MAX_FILENAME_LEN: usize = 255;
+
+// How many `name-N.ext` variants an upload tries when its name is taken
+const MAX_NAME_VERSIONS: u32 = 100;
+
+// Uploads stream from clients that may be slow, so they get longer than the request timeout
+const UPLOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+
+// Where uploaded files are written and how many bytes one request may carry
+#[derive(Clone)]
+struct UploadConfig {
+    dir: PathBuf,
+    max_bytes: u64,
 }
 
-impl std::fmt::Display for ApiError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+impl UploadConfig {
+    // UPLOAD_DIR, or ./uploads; NOXIUM_MAX_UPLOAD_BYTES, or 10 MiB
+    fn from_env() -> Self {
+        let max_bytes = match env::var("NOXIUM_MAX_UPLOAD_BYTES") {
+            Ok(value) => match value.trim().parse::<u64>() {
+                Ok(limit) if limit > 0 => limit,
+                _ => {
+                    log::warn!(
+                        "Ignoring invalid NOXIUM_MAX_UPLOAD_BYTES {:?}, using {} bytes",
+                        value,
+                        DEFAULT_MAX_UPLOAD_BYTES
+                    );
+                    DEFAULT_MAX_UPLOAD_BYTES
+                }
+            },
+            Err(_) => DEFAULT_MAX_UPLOAD_BYTES,
+        };
+        let dir = env::var("UPLOAD_DIR").unwrap_or_else(|_| "./uploads".to_string());
+        UploadConfig { dir: dir.into(), max_bytes }
     }
 }
 
-impl std::error::Error for ApiError {}
-
-struct RateLimiter {
-    requests: Arc<std::sync::Mutex<std::collections::HashMap<String, usize>>>,
+// What the upload handler reports for each stored file
+#[derive(Debug, Serialize, PartialEq)]
+struct UploadedFile {
+    filename: String,
+    size: u64,
+    sha256: String,
 }
 
 async fn rate_limiter(req: ServiceRequest, srv: &actix_service::Service) -> Result<HttpResponse, Error> {
-    let client_ip = req.connection_info().realip().unwrap_or("unknown").to_string();
-    let mut state = req.app_data::<web::Data<RateLimiter>>().unwrap().requests.lock().unwrap();
-    
-    let counter = state.entry(client_ip.clone()).or_insert(0);
-    *counter += 1;
-    
-    if *counter > 100 {
-        return Ok(req.error_response(HttpResponse::TooManyRequests()));
+    // The connecting address; Forwarded and X-Forwarded-For are set by the client
+    let client_ip = req.peer_addr().map_or_else(|| "unknown".to_string(), |addr| addr.ip().to_string());
+    let limiter = req.app_data::<web::Data<RateLimiter>>().unwrap();
+
+    if let Err(retry_after) = limiter.check(&client_ip) {
+        return Ok(req.error_response(
+            HttpResponse::TooManyRequests()
+                .insert_header((actix_web::http::header::RETRY_AFTER, retry_after.as_secs_ceil()))
+                .finish(),
+        ));
     }
 
     Ok(srv.call(req).await?)
 }
 
-lazy_static! {
-    static ref DB_POOL: Arc<SqlitePool> = Arc::new(SqlitePool::connect(&env::var("DATABASE_URL").unwrap()).unwrap());
-}
-
-async fn index() -> HttpResponse {
+async fn index(mode: web::Data<TemplateMode>, minify: web::Data<MinifyHtml>) -> HttpResponse {
     let template = IndexTemplate {
         message: "Hello from the server!".to_string(),
     };
 
-    let rendered = match template.render() {
+    let rendered = match &**mode {
+        TemplateMode::Compiled => template.render().map_err(|err| err.to_string()),
+        TemplateMode::Runtime(dir) => render_runtime_template(dir, "index.html", &serde_json::json!({ "message": template.message })),
+    };
+    let rendered = match rendered {
         Ok(content) => content,
         Err(err) => {
             error!("Error rendering template: {}", err);
             return HttpResponse::InternalServerError().finish();
         }
     };
+    let rendered = if minify.0 { minify_html(&rendered) } else { rendered };
 
     HttpResponse::Ok()
         .content_type("text/html")
         .body(rendered)
 }
 
-async fn api_handler(req: HttpRequest, body: Json<Config>) -> ActixResult<HttpResponse> {
+fn render_runtime_template(dir: &std::path::Path, name: &str, context: &serde_json::Value) -> Result<String, String> {
+    let source = fs::read_to_string(dir.join(name)).map_err(|err| format!("{}: {}", name, err))?;
+    template_engine::render(&source, context).map_err(|err| format!("{}: {}", name, err))
+}
+
+async fn api_handler(req: HttpRequest, body: Valid<Config>) -> Result<HttpResponse, NoxiumError> {
     let config = body.into_inner();
 
     info!("Received API request with port: {}", config.port);
 
-    if config.port == 0 {
-        return Err(ApiError::InvalidInput("Port cannot be zero".into()).into());
-    }
-
     Ok(HttpResponse::Ok()
         .content_type("application/json")
         .json(config))
 }
 
-async fn upload_file(mut payload: Multipart) -> ActixResult<HttpResponse> {
-    while let Some(item) = payload.next().await {
-        let mut field = item?;
-        let filename = field.filename().to_string();
-        let filepath = format!("./uploads/{}", filename);
+// The last path segment of a client-supplied filename, with anything but
+// ASCII letters, digits, `.`, `-`, and `_` replaced by `_`. Leading dots are
+// dropped, so the result is never `..` or a hidden file; None if nothing is left.
+fn sanitize_filename(name: &str) -> Option<String> {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = base
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    let cleaned: String = cleaned.trim_start_matches('.').chars().take(MAX_FILENAME_LEN).collect();
+    (!cleaned.is_empty()).then_some(cleaned)
+}
 
-        let mut file = std::fs::File::create(filepath)?;
-        while let Some(chunk) = field.next().await {
-            let data = chunk?;
-            file.write_all(&data)?;
+fn multipart_error(err: actix_multipart::MultipartError) -> NoxiumError {
+    NoxiumError::Validation(format!("Malformed multipart body: {}", err))
+}
+
+// Streams one file field to `path`, counting it against the request's `total`
+// and hashing it as it goes. Returns the field's size and hex SHA-256.
+async fn write_field(
+    field: &mut Field,
+    path: &Path,
+    filename: &str,
+    total: &mut u64,
+    max_bytes: u64,
+) -> Result<(u64, String), NoxiumError> {
+    let mut file = tokio::fs::OpenOptions::new().write(true).create_new(true).open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut size = 0u64;
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(multipart_error)?;
+        let previous = size;
+        size += chunk.len() as u64;
+        *total += chunk.len() as u64;
+        if *total > max_bytes {
+            return Err(NoxiumError::PayloadTooLarge(format!("Uploads are limited to {} bytes", max_bytes)));
+        }
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+        if size / UPLOAD_PROGRESS_INTERVAL > previous / UPLOAD_PROGRESS_INTERVAL {
+            info!("Upload {}: {} bytes received", filename, size);
+        }
+    }
+    file.flush().await?;
+    let sha256 = hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok((size, sha256))
+}
+
+// Stores every file field of `payload` in `config.dir`. Fields without a
+// filename are ordinary form fields and are skipped.
+async fn save_uploads(mut payload: Multipart, config: &UploadConfig) -> Result<Vec<UploadedFile>, NoxiumError> {
+    tokio::fs::create_dir_all(&config.dir).await?;
+    let mut files = Vec::new();
+    let mut total = 0u64;
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(multipart_error)?;
+        let Some(name) = field.content_disposition().and_then(|cd| cd.get_filename()).map(|name| name.to_string()) else {
+            continue;
+        };
+        let filename = sanitize_filename(&name)
+            .ok_or_else(|| NoxiumError::Validation(format!("Invalid upload filename {:?}", name)))?;
+
+        // Sanitized names never start with a dot, so the partial file can't
+        // clobber an upload, and a failed upload leaves nothing under the real name.
+        // Each upload gets its own, so concurrent ones of the same name don't mix
+        let partial = config.dir.join(format!(".{}.{}.part", filename, uuid::Uuid::new_v4().simple()));
+        let written = match write_field(&mut field, &partial, &filename, &mut total, config.max_bytes).await {
+            Ok(written) => publish_upload(&partial, &config.dir, &filename).await.map(|stored| (stored, written)),
+            Err(e) => Err(e),
+        };
+        match written {
+            Ok((filename, (size, sha256))) => {
+                info!("Stored upload {} ({} bytes, sha256 {})", filename, size, sha256);
+                files.push(UploadedFile { filename, size, sha256 });
+            }
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                return Err(e);
+            }
         }
     }
+    Ok(files)
+}
+
+// Moves a finished upload from `partial` to `filename` in `dir`, or to
+// `stem-1.ext`, `stem-2.ext`, ... when that is taken, returning the name used.
+// Linking fails instead of replacing an existing file, unlike renaming
+async fn publish_upload(partial: &Path, dir: &Path, filename: &str) -> Result<String, NoxiumError> {
+    let (stem, extension) = match filename.rsplit_once('.') {
+        Some((stem, extension)) => (stem, Some(extension)),
+        None => (filename, None),
+    };
+    for version in 0..=MAX_NAME_VERSIONS {
+        let name = match (version, extension) {
+            (0, _) => filename.to_string(),
+            (_, Some(extension)) => format!("{}-{}.{}", stem, version, extension),
+            (_, None) => format!("{}-{}", stem, version),
+        };
+        match tokio::fs::hard_link(partial, dir.join(&name)).await {
+            Ok(()) => {
+                tokio::fs::remove_file(partial).await?;
+                return Ok(name);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Err(NoxiumError::Validation(format!("Too many uploads named {}", filename)))
+}
 
-    Ok(HttpResponse::Ok().body("File uploaded successfully"))
+// Streams uploads to disk without blocking the executor; more than
+// NOXIUM_MAX_UPLOAD_BYTES in one request is refused with 413
+async fn upload_file(payload: Multipart, config: web::Data<UploadConfig>) -> Result<HttpResponse, NoxiumError> {
+    let files = save_uploads(payload, &config).await?;
+    let total_bytes: u64 = files.iter().map(|file| file.size).sum();
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "files": files, "total_bytes": total_bytes })))
 }
 
-async fn get_data_from_db() -> ActixResult<HttpResponse> {
-    let pool = DB_POOL.clone();
-    let rows = sqlx::query!("SELECT id, name FROM items")
-        .fetch_all(&*pool)
-        .await
-        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+async fn get_data_from_db(pool: web::Data<SqlitePool>, page: web::Query<Pagination>) -> Result<HttpResponse, NoxiumError> {
+    let items = items::list_items(&pool, &page).await?;
+    Ok(HttpResponse::Ok().json(items))
+}
+
+async fn get_item_from_db(pool: web::Data<SqlitePool>, item_id: web::Path<i64>) -> Result<HttpResponse, NoxiumError> {
+    let id = item_id.into_inner();
+    match items::get_item(&pool, id).await? {
+        Some(item) => Ok(HttpResponse::Ok().json(item)),
+        None => Err(NoxiumError::NotFound(format!("No item with id {}", id))),
+    }
+}
 
-    let result: Vec<_> = rows.into_iter().map(|row| (row.id, row.name)).collect();
-    Ok(HttpResponse::Ok().json(result))
+async fn add_item_to_db(pool: web::Data<SqlitePool>, body: Valid<NewItem>) -> Result<HttpResponse, NoxiumError> {
+    let item = body.into_inner();
+    Ok(HttpResponse::Created().json(items::insert_item(&pool, &item).await?))
 }
 
 async fn log_request(req: ServiceRequest, srv: &actix_service::Service) -> Result<HttpResponse, Error> {
@@ -151,15 +372,6 @@ async fn log_request(req: ServiceRequest, srv: &actix_service::Service) -> Resul
     Ok(srv.call(req).await?)
 }
 
-async fn add_custom_headers(req: ServiceRequest, srv: &actix_service::Service) -> Result<HttpResponse, Error> {
-    let mut res = srv.call(req).await?;
-    res.headers_mut().insert(
-        X_REQUEST_ID,
-        HeaderValue::from_static("12345"),
-    );
-    Ok(res)
-}
-
 async fn handle_cors(req: ServiceRequest, srv: &actix_service::Service) -> Result<HttpResponse, Error> {
     let mut res = srv.call(req).await?;
     res.headers_mut().insert(
@@ -175,24 +387,14 @@ fn read_config_from_file(file_path: &str) -> Result<Config, std::io::Error> {
     Ok(config)
 }
 
-async fn shutdown_signal() {
-    tokio::signal::ctrl_c().await.expect("Failed to listen for shutdown signal");
-    info!("Received shutdown signal, shutting down gracefully.");
-}
-
-// Mock user authentication function
-fn authenticate_user(username: &str, password: &str) -> bool {
-    username == "admin" && password == "password"
-}
-
 // Handler for user registration
-async fn register_user(body: Json<UserRegistration>) -> ActixResult<HttpResponse> {
+async fn register_user(auth: web::Data<Box<dyn AuthBackend>>, body: Valid<UserRegistration>) -> Result<HttpResponse, NoxiumError> {
     let user = body.into_inner();
 
-    if authenticate_user(&user.username, &user.password) {
-        Ok(HttpResponse::Ok().body("User registered successfully"))
-    } else {
-        Err(ApiError::AuthenticationError("Invalid credentials".into()).into())
+    match auth.verify(&user.username, &user.password).await {
+        Ok(_) => Ok(HttpResponse::Ok().body("User registered successfully")),
+        Err(AuthError::InvalidCredentials) => Err(NoxiumError::Unauthorized("Invalid credentials".into())),
+        Err(AuthError::Backend(e)) => Err(NoxiumError::Internal(e)),
     }
 }
 
@@ -209,17 +411,6 @@ async fn get_user_details(user_id: web::Path<u32>) -> ActixResult<HttpResponse>
     Ok(HttpResponse::Ok().json(user))
 }
 
-// Handler for serving static files
-async fn static_file_handler(req: HttpRequest) -> ActixResult<HttpResponse> {
-    let filename = req.match_info().get("filename").unwrap_or("index.html");
-    let filepath = format!("./public/{}", filename);
-
-    match fs::read_to_string(&filepath) {
-        Ok(content) => Ok(HttpResponse::Ok().content_type("text/html").body(content)),
-        Err(_) => Ok(HttpResponse::NotFound().body("File not found")),
-    }
-}
-
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
@@ -227,32 +418,291 @@ async fn main() -> std::io::Result<()> {
     let port = env::var("PORT").unwrap_or_else(|_| "8080".to_string()).parse::<u16>().unwrap();
     let database_url = env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite://:memory:".to_string());
 
-    let pool = SqlitePool::connect(&database_url).await.unwrap();
-    let pool = Arc::new(pool);
-    DB_POOL = pool;
-
-    HttpServer::new(move || {
+    let pool = db::connect(&database_url).await.expect("Failed to open database");
+    let health_pool = pool.clone();
+    // AUTH_USERS lists users inline; otherwise they come from the users table
+    let auth_backend: web::Data<Box<dyn AuthBackend>> = web::Data::new(match env::var("AUTH_USERS") {
+        Ok(spec) => Box::new(MemoryBackend::parse(&spec).map_err(std::io::Error::other)?),
+        Err(_) => Box::new(SqliteBackend::new(pool.clone())),
+    });
+    let db_pool = web::Data::new(pool);
+
+    let metrics = Arc::new(Metrics::new());
+    // Set on shutdown so /readyz fails while in-flight requests drain
+    let shutting_down = Arc::new(AtomicBool::new(false));
+    let drain_flag = shutting_down.clone();
+    let request_limiter = web::Data::new(RateLimiter::sliding_window(
+        RATE_LIMIT_PER_MINUTE,
+        std::time::Duration::from_secs(60),
+    ));
+    let live_updates = Arc::new(LiveUpdates::new());
+    let upload_config = web::Data::new(UploadConfig::from_env());
+    let template_mode = web::Data::new(match env::var("TEMPLATE_DIR") {
+        Ok(dir) => TemplateMode::Runtime(dir.into()),
+        Err(_) => TemplateMode::Compiled,
+    });
+    let minify_html = web::Data::new(MinifyHtml::from_env());
+
+    let server = HttpServer::new(move || {
         App::new()
+            .configure(actix_body_limits(max_body_bytes()))
+            .wrap(RequestMetrics::new(metrics.clone()))
             .wrap(Logger::default())
             .wrap_fn(log_request)
-            .wrap_fn(add_custom_headers)
             .wrap_fn(handle_cors)
             .wrap_fn(rate_limiter)
+            .configure(health_routes(metrics.clone(), Some(health_pool.clone()), shutting_down.clone()))
+            .configure(live_routes(live_updates.clone()))
+            .app_data(template_mode.clone())
+            .app_data(minify_html.clone())
+            .app_data(request_limiter.clone())
+            .app_data(db_pool.clone())
+            .app_data(auth_backend.clone())
+            .app_data(upload_config.clone())
             .service(web::resource("/").route(web::get().to(index)))
             .service(web::resource("/api").route(web::post().to(api_handler)))
-            .service(web::resource("/upload").route(web::post().to(upload_file)))
-            .service(web::resource("/data").route(web::get().to(get_data_from_db)))
+            .service(web::resource("/upload").wrap(Timeout::new(UPLOAD_TIMEOUT)).route(web::post().to(upload_file)))
+            .service(
+                web::resource("/data")
+                    .route(web::get().to(get_data_from_db))
+                    .route(web::post().to(add_item_to_db)),
+            )
+            .service(web::resource("/data/{item_id}").route(web::get().to(get_item_from_db)))
             .service(web::resource("/register").route(web::post().to(register_user)))
             .service(web::resource("/user/{user_id}").route(web::get().to(get_user_details)))
-            .service(web::resource("/static/{filename:.*}").route(web::get().to(static_file_handler)))
+            .configure(static_routes(static_dir()))
             .default_service(web::route().to(|| HttpResponse::NotFound()))
             .service(
                 web::resource("/status")
                     .route(web::get().to(|| HttpResponse::Ok().body("Server is running.")))
             )
             .wrap(NormalizePath::default())
+            .wrap(Timeout::new(request_timeout()))
+            .wrap(RequestLogger)
     })
+    // Stop on the same signals as the other servers, finishing in-flight requests
+    .disable_signals()
     .bind(format!("127.0.0.1:{}", port))?
-    .run()
-    .await
-}
\ No newline at end of file
+    .run();
+
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        draining_shutdown_signal(drain_flag).await;
+        handle.stop(true).await;
+    });
+    server.await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+    use serde_json::{json, Value};
+
+    async fn post_json(uri: &str, body: Value) -> (u16, Value) {
+        let mut users = MemoryBackend::default();
+        users.insert("admin", bcrypt::hash("letmein", 4).unwrap());
+        let auth: Box<dyn AuthBackend> = Box::new(users);
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(auth))
+                .service(web::resource("/api").route(web::post().to(api_handler)))
+                .service(web::resource("/register").route(web::post().to(register_user))),
+        )
+        .await;
+        let response = test::call_service(&app, test::TestRequest::post().uri(uri).set_json(body).to_request()).await;
+        let status = response.status().as_u16();
+        let body = test::read_body(response).await;
+        (status, serde_json::from_slice(&body).unwrap_or_else(|_| json!(String::from_utf8_lossy(&body))))
+    }
+
+    #[actix_web::test]
+    async fn test_zero_port_is_a_field_error() {
+        let (status, body) = post_json("/api", json!({ "port": 0, "database_url": "sqlite://app.db" })).await;
+        assert_eq!(status, 400);
+        assert_eq!(body, json!({
+            "error": "validation",
+            "message": "Invalid fields: port",
+            "fields": { "port": ["Port cannot be zero"] },
+        }));
+
+        let (status, _) = post_json("/api", json!({ "port": 8080, "database_url": "sqlite://app.db" })).await;
+        assert_eq!(status, 200);
+    }
+
+    #[actix_web::test]
+    async fn test_empty_registration_fields_are_listed() {
+        let (status, body) = post_json("/register", json!({ "username": "", "password": "" })).await;
+        assert_eq!(status, 400);
+        assert_eq!(body["fields"], json!({ "password": ["must not be empty"], "username": ["must not be blank"] }));
+
+        // Valid input still reaches the credential check
+        let (status, body) = post_json("/register", json!({ "username": "mallory", "password": "guess" })).await;
+        assert_eq!(status, 401);
+        assert_eq!(body["error"], "unauthorized");
+    }
+
+    #[actix_web::test]
+    async fn test_registration_checks_the_auth_backend() {
+        let register = |password: &str| post_json("/register", json!({ "username": "admin", "password": password }));
+        assert_eq!(register("letmein").await, (200, json!("User registered successfully")));
+        // The old hardcoded password no longer works
+        assert_eq!(register("password").await.0, 401);
+    }
+
+    const BOUNDARY: &str = "noxium-test-boundary";
+
+    // A multipart body with a plain form field and one file field
+    fn multipart_body(filename: &str, content: &[u8]) -> Vec<u8> {
+        let mut body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nhello\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{f}\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n",
+            b = BOUNDARY,
+            f = filename
+        )
+        .into_bytes();
+        body.extend_from_slice(content);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    fn upload_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("noxium-ssr-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[actix_web::test]
+    async fn test_chunked_upload_reports_size_and_hash() {
+        let content: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        // Arrives in many small chunks, as it would over a slow connection
+        let chunks: Vec<_> = multipart_body("../../etc/report.bin", &content)
+            .chunks(4096)
+            .map(|chunk| Ok(actix_web::web::Bytes::copy_from_slice(chunk)))
+            .collect();
+        let mut headers = actix_web::http::header::HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_str(&format!("multipart/form-data; boundary={}", BOUNDARY)).unwrap(),
+        );
+        let payload = Multipart::new(&headers, futures::stream::iter(chunks));
+
+        let dir = upload_dir("chunked");
+        let config = UploadConfig { dir: dir.clone(), max_bytes: 1024 * 1024 };
+        let files = save_uploads(payload, &config).await.unwrap();
+
+        let sha256: String = Sha256::digest(&content).iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(files, vec![UploadedFile { filename: "report.bin".to_string(), size: 200_000, sha256 }]);
+        // Only the sanitized name was written, and no partial file was left behind
+        assert_eq!(std::fs::read(dir.join("report.bin")).unwrap(), content);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn upload_payload(filename: &str, content: &[u8]) -> Multipart {
+        let mut headers = actix_web::http::header::HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_str(&format!("multipart/form-data; boundary={}", BOUNDARY)).unwrap(),
+        );
+        let chunks: Vec<_> = multipart_body(filename, content)
+            .chunks(1024)
+            .map(|chunk| Ok(actix_web::web::Bytes::copy_from_slice(chunk)))
+            .collect();
+        Multipart::new(&headers, futures::stream::iter(chunks))
+    }
+
+    #[actix_web::test]
+    async fn test_uploads_of_one_name_never_overwrite_each_other() {
+        let dir = upload_dir("collide");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("notes.txt"), "already here").unwrap();
+        let config = UploadConfig { dir: dir.clone(), max_bytes: 1024 * 1024 };
+
+        let first: Vec<u8> = vec![b'a'; 50_000];
+        let second: Vec<u8> = vec![b'b'; 50_000];
+        let (first_files, second_files) = futures::join!(
+            save_uploads(upload_payload("notes.txt", &first), &config),
+            save_uploads(upload_payload("notes.txt", &second), &config),
+        );
+
+        let mut stored = Vec::new();
+        for (files, content) in [(first_files.unwrap(), &first), (second_files.unwrap(), &second)] {
+            let [file] = &files[..] else { panic!("expected one file, got {:?}", files) };
+            // The reported hash is of what ended up on disk
+            let on_disk = std::fs::read(dir.join(&file.filename)).unwrap();
+            assert_eq!(&on_disk, content);
+            let sha256: String = Sha256::digest(&on_disk).iter().map(|byte| format!("{:02x}", byte)).collect();
+            assert_eq!(file.sha256, sha256);
+            stored.push(file.filename.clone());
+        }
+        stored.sort();
+        assert_eq!(stored, vec!["notes-1.txt", "notes-2.txt"]);
+        assert_eq!(std::fs::read_to_string(dir.join("notes.txt")).unwrap(), "already here");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_upload_over_the_limit_is_413() {
+        let dir = upload_dir("limit");
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(UploadConfig { dir: dir.clone(), max_bytes: 1024 }))
+                .service(web::resource("/upload").route(web::post().to(upload_file))),
+        )
+        .await;
+        let upload = |content: Vec<u8>| {
+            test::TestRequest::post()
+                .uri("/upload")
+                .insert_header((CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY)))
+                .set_payload(multipart_body("big.bin", &content))
+                .to_request()
+        };
+
+        let response = test::call_service(&app, upload(vec![7; 1025])).await;
+        assert_eq!(response.status(), 413);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        let response = test::call_service(&app, upload(vec![7; 1024])).await;
+        assert_eq!(response.status(), 200);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["files"][0]["size"], 1024);
+        assert_eq!(body["total_bytes"], 1024);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_upload_filenames_are_sanitized() {
+        assert_eq!(sanitize_filename("report.pdf").as_deref(), Some("report.pdf"));
+        assert_eq!(sanitize_filename("..\\..\\win.ini").as_deref(), Some("win.ini"));
+        assert_eq!(sanitize_filename("my file (1).txt").as_deref(), Some("my_file__1_.txt"));
+        assert_eq!(sanitize_filename(".env").as_deref(), Some("env"));
+        assert_eq!(sanitize_filename("uploads/.."), None);
+        assert_eq!(sanitize_filename(""), None);
+    }
+
+    #[actix_web::test]
+    async fn test_index_is_minified_when_enabled() {
+        let dir = upload_dir("templates");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.html"), "<html>\n  <!-- greeting -->\n  <p>{{ message }}</p>\n</html>\n").unwrap();
+
+        for (minify, expected) in [
+            (false, "<html>\n  <!-- greeting -->\n  <p>Hello from the server!</p>\n</html>\n"),
+            (true, "<html><p>Hello from the server!</p></html>"),
+        ] {
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(TemplateMode::Runtime(dir.clone())))
+                    .app_data(web::Data::new(MinifyHtml(minify)))
+                    .service(web::resource("/").route(web::get().to(index))),
+            )
+            .await;
+            let body = test::call_and_read_body(&app, test::TestRequest::get().uri("/").to_request()).await;
+            assert_eq!(body, expected.as_bytes());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+use actix_files::NamedFile;
+use actix_web::{web, HttpRequest, HttpResponse};
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+/// Directory served under `/static`: `STATIC_DIR`, or `./public`.
+pub fn static_dir() -> PathBuf {
+    env::var("STATIC_DIR").unwrap_or_else(|_| "./public".to_string()).into()
+}
+
+struct StaticRoot(PathBuf);
+
+/// Routes serving files from `root` under `/static`, mounted with
+/// `App::new().configure(static_routes(static_dir()))`.
+///
+/// Content types come from the file extension, range requests are
+/// honoured, and directories serve their `index.html`. Responses carry
+/// `ETag` and `Last-Modified`, and conditional requests get `304`, with
+/// `If-None-Match` taking precedence over `If-Modified-Since`.
+pub fn static_routes(root: PathBuf) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |cfg| {
+        cfg.app_data(web::Data::new(StaticRoot(root)))
+            .route("/static/{filename:.*}", web::get().to(serve_static));
+    }
+}
+
+// Resolve `relative` inside `root`, or `None` when it would leave it, either
+// through `..` and absolute segments or through a symlink
+fn resolve(root: &Path, relative: &str) -> Option<PathBuf> {
+    let relative = Path::new(relative);
+    if !relative.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+        return None;
+    }
+    let root = root.canonicalize().ok()?;
+    match root.join(relative).canonicalize() {
+        Ok(path) => path.starts_with(&root).then_some(path),
+        // Missing files are a 404, not a traversal attempt
+        Err(_) => Some(root.join(relative)),
+    }
+}
+
+async fn serve_static(req: HttpRequest, root: web::Data<StaticRoot>) -> HttpResponse {
+    let filename = req.match_info().get("filename").unwrap_or_default();
+    let Some(mut path) = resolve(&root.0, filename) else {
+        return HttpResponse::Forbidden().body("Forbidden");
+    };
+    if path.is_dir() {
+        path.push("index.html");
+    }
+
+    match NamedFile::open_async(&path).await {
+        Ok(file) => file.use_etag(true).use_last_modified(true).into_response(&req),
+        Err(_) => HttpResponse::NotFound().body("File not found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE};
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use std::fs;
+
+    // A minimal PNG: signature plus an IHDR chunk header; enough for byte checks
+    const PNG_BYTES: &[u8] = &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, b'I', b'H', b'D', b'R', 0xff, 0x00];
+
+    fn static_root(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("noxium-static-{}-{}", name, std::process::id()));
+        fs::create_dir_all(root.join("public").join("docs")).unwrap();
+        fs::write(root.join("public").join("logo.png"), PNG_BYTES).unwrap();
+        fs::write(root.join("public").join("docs").join("index.html"), "<h1>Docs</h1>").unwrap();
+        fs::write(root.join("secret.txt"), "top secret").unwrap();
+        root
+    }
+
+    #[actix_web::test]
+    async fn test_serves_binary_files_with_their_content_type() {
+        let root = static_root("png");
+        let app = test::init_service(App::new().configure(static_routes(root.join("public")))).await;
+
+        let response = test::call_service(&app, test::TestRequest::get().uri("/static/logo.png").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "image/png");
+        assert_eq!(test::read_body(response).await, PNG_BYTES);
+
+        let request = test::TestRequest::get().uri("/static/logo.png").insert_header((RANGE, "bytes=1-3")).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(test::read_body(response).await, "PNG");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_conditional_get_by_last_modified() {
+        let root = static_root("conditional");
+        let app = test::init_service(App::new().configure(static_routes(root.join("public")))).await;
+
+        let response = test::call_service(&app, test::TestRequest::get().uri("/static/logo.png").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let last_modified = response.headers().get(LAST_MODIFIED).expect("Last-Modified should be sent").clone();
+        let etag = response.headers().get(ETAG).unwrap().clone();
+
+        let request = test::TestRequest::get()
+            .uri("/static/logo.png")
+            .insert_header((IF_MODIFIED_SINCE, last_modified.clone()))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert!(test::read_body(response).await.is_empty());
+
+        // A stale ETag wins over a date that would otherwise match
+        let request = test::TestRequest::get()
+            .uri("/static/logo.png")
+            .insert_header((IF_NONE_MATCH, "\"stale\""))
+            .insert_header((IF_MODIFIED_SINCE, last_modified))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::OK);
+
+        let request = test::TestRequest::get().uri("/static/logo.png").insert_header((IF_NONE_MATCH, etag)).to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::NOT_MODIFIED);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_directories_serve_their_index() {
+        let root = static_root("index");
+        let app = test::init_service(App::new().configure(static_routes(root.join("public")))).await;
+
+        let response = test::call_service(&app, test::TestRequest::get().uri("/static/docs/").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(CONTENT_TYPE).unwrap().to_str().unwrap().starts_with("text/html"));
+        assert_eq!(test::read_body(response).await, "<h1>Docs</h1>");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_path_traversal_is_rejected() {
+        let root = static_root("traversal");
+        let app = test::init_service(App::new().configure(static_routes(root.join("public")))).await;
+
+        for uri in ["/static/../secret.txt", "/static/docs/%2e%2e/%2e%2e/secret.txt", "/static/%2Fetc%2Fpasswd"] {
+            let response = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert!(matches!(response.status(), StatusCode::FORBIDDEN | StatusCode::NOT_FOUND), "{}: {}", uri, response.status());
+            assert_ne!(test::read_body(response).await, "top secret");
+        }
+
+        let response = test::call_service(&app, test::TestRequest::get().uri("/static/missing.css").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,17 +1,37 @@
 use actix_files::NamedFile;
 use actix_web::{web, App, HttpServer, Result};
 
+#[path = "../timeout.rs"]
+#[allow(dead_code)]
+mod timeout;
+use timeout::{request_timeout, Timeout};
+
+#[path = "../shutdown.rs"]
+#[allow(dead_code)]
+mod shutdown;
+use shutdown::shutdown_signal;
+
 async fn index() -> Result<NamedFile> {
     NamedFile::open("./static/index.html") // Serve a basic HTML file initially
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    HttpServer::new(|| {
+    let server = HttpServer::new(|| {
         App::new()
             .route("/", web::get().to(index))
+            .wrap(Timeout::new(request_timeout()))
     })
+    // Stopped below on shutdown_signal, like the other servers
+    .disable_signals()
     .bind("127.0.0.1:8080")?
-    .run()
-    .await
+    .run();
+
+    // Finish in-flight requests before exiting
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        handle.stop(true).await;
+    });
+    server.await
 }
\ No newline at end of file
@@ -0,0 +1,285 @@
+use serde_json::Value;
+use thiserror::Error;
+
+#[path = "escaping.rs"]
+#[allow(dead_code)]
+mod escaping;
+// `{{var}}` output can land inside a quoted attribute as well as between tags
+pub use escaping::attr_escape as escape_html;
+
+/// Errors found while parsing a template.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum TemplateError {
+    #[error("Unterminated tag starting at byte {0}")]
+    UnterminatedTag(usize),
+    #[error("Block `{0}` is never closed")]
+    UnclosedBlock(String),
+    #[error("Unexpected `{{{{/{0}}}}}`")]
+    UnexpectedClose(String),
+    #[error("`{{{{else}}}}` outside of an `{{{{#if}}}}` block")]
+    UnexpectedElse,
+    #[error("Unknown block helper `{0}`")]
+    UnknownHelper(String),
+    #[error("Block `{0}` needs a path argument")]
+    MissingArgument(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Var { path: String, escape: bool },
+    Each { path: String, body: Vec<Node> },
+    If { path: String, then: Vec<Node>, otherwise: Vec<Node> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tag {
+    Text(String),
+    Var { path: String, escape: bool },
+    Open { helper: String, path: String },
+    Else,
+    Close(String),
+}
+
+/// A parsed template, rendered against a `serde_json::Value` context.
+///
+/// Supported syntax:
+/// - `{{ path }}` inserts a value, HTML-escaped; `{{{ path }}}` inserts it raw
+/// - `{{#each path}}...{{/each}}` repeats the body for each array item
+/// - `{{#if path}}...{{else}}...{{/if}}` renders the first body when the value is truthy
+///
+/// Paths are dot-separated (`page.title`). Inside `each`, `this` is the
+/// current item, `@index` its position, and names not found on the item are
+/// looked up in the enclosing scopes. Missing values render as nothing and
+/// are falsy; so are `null`, `false`, `0`, `""`, and `[]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        let mut tags = tokenize(source)?.into_iter();
+        let (nodes, end) = parse_block(&mut tags)?;
+        match end {
+            None => Ok(Template { nodes }),
+            Some(Tag::Else) => Err(TemplateError::UnexpectedElse),
+            Some(Tag::Close(helper)) => Err(TemplateError::UnexpectedClose(helper)),
+            Some(_) => unreachable!("parse_block only stops at else or a close tag"),
+        }
+    }
+
+    pub fn render(&self, context: &Value) -> String {
+        let mut out = String::new();
+        render_nodes(&self.nodes, &[Scope { value: context, index: None }], &mut out);
+        out
+    }
+}
+
+/// Parses and renders `source` in one step.
+pub fn render(source: &str, context: &Value) -> Result<String, TemplateError> {
+    Ok(Template::parse(source)?.render(context))
+}
+
+// Split the source into text and tags
+fn tokenize(source: &str) -> Result<Vec<Tag>, TemplateError> {
+    let mut tags = Vec::new();
+    let mut rest = source;
+    let mut offset = 0;
+
+    while let Some(start) = rest.find("{{") {
+        if start > 0 {
+            tags.push(Tag::Text(rest[..start].to_string()));
+        }
+        let raw = rest[start..].starts_with("{{{");
+        let (open, close) = if raw { ("{{{", "}}}") } else { ("{{", "}}") };
+        let inner_start = start + open.len();
+        let inner_len = rest[inner_start..]
+            .find(close)
+            .ok_or(TemplateError::UnterminatedTag(offset + start))?;
+        let inner = rest[inner_start..inner_start + inner_len].trim();
+
+        tags.push(if raw {
+            Tag::Var { path: inner.to_string(), escape: false }
+        } else if let Some(block) = inner.strip_prefix('#') {
+            let (helper, path) = block.split_once(char::is_whitespace).unwrap_or((block, ""));
+            Tag::Open { helper: helper.to_string(), path: path.trim().to_string() }
+        } else if let Some(helper) = inner.strip_prefix('/') {
+            Tag::Close(helper.trim().to_string())
+        } else if inner == "else" {
+            Tag::Else
+        } else {
+            Tag::Var { path: inner.to_string(), escape: true }
+        });
+
+        let consumed = inner_start + inner_len + close.len();
+        rest = &rest[consumed..];
+        offset += consumed;
+    }
+    if !rest.is_empty() {
+        tags.push(Tag::Text(rest.to_string()));
+    }
+    Ok(tags)
+}
+
+// Collect nodes until the end of input, an `else`, or a close tag, returning whichever stopped it
+fn parse_block(tags: &mut impl Iterator<Item = Tag>) -> Result<(Vec<Node>, Option<Tag>), TemplateError> {
+    let mut nodes = Vec::new();
+    while let Some(tag) = tags.next() {
+        match tag {
+            Tag::Text(text) => nodes.push(Node::Text(text)),
+            Tag::Var { path, escape } => nodes.push(Node::Var { path, escape }),
+            Tag::Open { helper, path } => {
+                if helper != "each" && helper != "if" {
+                    return Err(TemplateError::UnknownHelper(helper));
+                }
+                if path.is_empty() {
+                    return Err(TemplateError::MissingArgument(helper));
+                }
+                let (body, mut end) = parse_block(tags)?;
+                let mut otherwise = Vec::new();
+                if helper == "if" && end == Some(Tag::Else) {
+                    (otherwise, end) = parse_block(tags)?;
+                }
+                match end {
+                    Some(Tag::Close(closed)) if closed == helper => {}
+                    Some(Tag::Close(closed)) => return Err(TemplateError::UnexpectedClose(closed)),
+                    Some(Tag::Else) => return Err(TemplateError::UnexpectedElse),
+                    _ => return Err(TemplateError::UnclosedBlock(helper)),
+                }
+                nodes.push(if helper == "each" {
+                    Node::Each { path, body }
+                } else {
+                    Node::If { path, then: body, otherwise }
+                });
+            }
+            end @ (Tag::Else | Tag::Close(_)) => return Ok((nodes, Some(end))),
+        }
+    }
+    Ok((nodes, None))
+}
+
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    value: &'a Value,
+    index: Option<usize>,
+}
+
+// Resolve `path` against the innermost scope that has its first segment
+fn lookup(scopes: &[Scope], path: &str) -> Option<Value> {
+    let current = scopes.last()?;
+    if path == "this" || path == "." {
+        return Some(current.value.clone());
+    }
+    if path == "@index" {
+        return scopes.iter().rev().find_map(|scope| scope.index).map(Value::from);
+    }
+
+    let (path, candidates): (&str, Vec<&Value>) = match path.strip_prefix("this.") {
+        Some(path) => (path, vec![current.value]),
+        None => (path, scopes.iter().rev().map(|scope| scope.value).collect()),
+    };
+    let mut segments = path.split('.');
+    let first = segments.next()?;
+    let mut value = candidates.into_iter().find_map(|scope| scope.get(first))?;
+    for segment in segments {
+        value = match value {
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => value.get(segment)?,
+        };
+    }
+    Some(value.clone())
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(flag) => *flag,
+        Value::Number(number) => number.as_f64() != Some(0.0),
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(_) => true,
+    }
+}
+
+fn render_nodes(nodes: &[Node], scopes: &[Scope], out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Var { path, escape } => {
+                let text = match lookup(scopes, path) {
+                    None | Some(Value::Null) => String::new(),
+                    Some(Value::String(text)) => text,
+                    Some(other) => other.to_string(),
+                };
+                if *escape {
+                    out.push_str(&escape_html(&text));
+                } else {
+                    out.push_str(&text);
+                }
+            }
+            Node::Each { path, body } => {
+                let Some(Value::Array(items)) = lookup(scopes, path) else { continue };
+                for (index, item) in items.iter().enumerate() {
+                    let mut item_scopes = scopes.to_vec();
+                    item_scopes.push(Scope { value: item, index: Some(index) });
+                    render_nodes(body, &item_scopes, out);
+                }
+            }
+            Node::If { path, then, otherwise } => {
+                let truthy = lookup(scopes, path).is_some_and(|value| is_truthy(&value));
+                render_nodes(if truthy { then } else { otherwise }, scopes, out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_nested_loops_see_outer_scope() {
+        let template = "{{#each sections}}<h2>{{name}}</h2><ul>{{#each pages}}<li>{{site}}: {{@index}} {{title}}</li>{{/each}}</ul>{{/each}}";
+        let context = json!({
+            "site": "Docs",
+            "sections": [
+                { "name": "Guides", "pages": [{ "title": "Intro" }, { "title": "Setup" }] },
+                { "name": "Empty", "pages": [] },
+            ],
+        });
+
+        assert_eq!(
+            render(template, &context).unwrap(),
+            "<h2>Guides</h2><ul><li>Docs: 0 Intro</li><li>Docs: 1 Setup</li></ul><h2>Empty</h2><ul></ul>"
+        );
+    }
+
+    #[test]
+    fn test_missing_keys_render_empty_and_are_falsy() {
+        let template = "[{{missing}}][{{page.missing.deeper}}]{{#if missing}}yes{{else}}no{{/if}}{{#each missing}}x{{/each}}";
+        assert_eq!(render(template, &json!({ "page": {} })).unwrap(), "[][]no");
+        assert_eq!(render("{{#if items}}some{{else}}none{{/if}}", &json!({ "items": [] })).unwrap(), "none");
+        assert_eq!(render("{{#if n}}{{n}}{{/if}} {{flag}}", &json!({ "n": 2, "flag": true })).unwrap(), "2 true");
+    }
+
+    #[test]
+    fn test_values_are_escaped_unless_triple_braced() {
+        let context = json!({ "body": "<b>\"Tom\" & 'Jerry'</b>" });
+        assert_eq!(
+            render("{{ body }}|{{{ body }}}", &context).unwrap(),
+            "&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;|<b>\"Tom\" & 'Jerry'</b>"
+        );
+    }
+
+    #[test]
+    fn test_malformed_templates_are_rejected() {
+        assert_eq!(Template::parse("{{#each items}}x"), Err(TemplateError::UnclosedBlock("each".to_string())));
+        assert_eq!(Template::parse("{{#if a}}x{{/each}}"), Err(TemplateError::UnexpectedClose("each".to_string())));
+        assert_eq!(Template::parse("x{{/if}}"), Err(TemplateError::UnexpectedClose("if".to_string())));
+        assert_eq!(Template::parse("{{#each a}}{{else}}{{/each}}"), Err(TemplateError::UnexpectedElse));
+        assert_eq!(Template::parse("ab{{ oops"), Err(TemplateError::UnterminatedTag(2)));
+        assert_eq!(Template::parse("{{#with a}}{{/with}}"), Err(TemplateError::UnknownHelper("with".to_string())));
+    }
+}
@@ -0,0 +1,197 @@
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::ErrorGatewayTimeout;
+use actix_web::{Error, HttpMessage};
+use std::cell::Cell;
+use std::env;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+/// Handler time limit used when `NOXIUM_REQUEST_TIMEOUT_MS` is unset: 30 seconds.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The default handler time limit for the actix apps, taken from
+/// `NOXIUM_REQUEST_TIMEOUT_MS` (in milliseconds).
+pub fn request_timeout() -> Duration {
+    match env::var("NOXIUM_REQUEST_TIMEOUT_MS") {
+        Ok(value) => match value.trim().parse::<u64>() {
+            Ok(millis) if millis > 0 => Duration::from_millis(millis),
+            _ => {
+                log::warn!(
+                    "Ignoring invalid NOXIUM_REQUEST_TIMEOUT_MS {:?}, using {:?}",
+                    value,
+                    DEFAULT_REQUEST_TIMEOUT
+                );
+                DEFAULT_REQUEST_TIMEOUT
+            }
+        },
+        Err(_) => DEFAULT_REQUEST_TIMEOUT,
+    }
+}
+
+// When the outermost `Timeout` gives up on the request; a `Timeout` on the
+// matched resource moves it
+struct Deadline {
+    started: Instant,
+    at: Cell<Instant>,
+}
+
+/// Middleware failing a request with `504 Gateway Timeout` when it is not handled
+/// within its time limit, dropping the handler future so a slow query or
+/// upstream call stops tying up the worker.
+///
+/// Wrap an app with `.wrap(Timeout::new(request_timeout()))` for the default,
+/// and override it for a route by wrapping its resource or scope in another
+/// `Timeout`; the limit is counted from when the request arrived. A handler
+/// that blocks the thread instead of awaiting cannot be interrupted.
+pub struct Timeout(Duration);
+
+impl Timeout {
+    pub fn new(limit: Duration) -> Self {
+        Timeout(limit)
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Timeout
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = TimeoutMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TimeoutMiddleware { service, limit: self.0 }))
+    }
+}
+
+pub struct TimeoutMiddleware<S> {
+    service: S,
+    limit: Duration,
+}
+
+impl<S, B> Service<ServiceRequest> for TimeoutMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let outer = req.extensions().get::<Rc<Deadline>>().cloned();
+        let deadline = match outer {
+            // An outer `Timeout` is already timing this request, and keeps waiting if
+            // the deadline moves later; a sooner one is enforced here
+            Some(deadline) => {
+                let at = deadline.started + self.limit;
+                if deadline.at.replace(at) <= at {
+                    return Box::pin(self.service.call(req));
+                }
+                deadline
+            }
+            None => {
+                let started = Instant::now();
+                let deadline = Rc::new(Deadline { started, at: Cell::new(started + self.limit) });
+                req.extensions_mut().insert(deadline.clone());
+                deadline
+            }
+        };
+        let method = req.method().clone();
+        let path = req.path().to_string();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            tokio::pin!(fut);
+            // Follows the deadline if it moves later while waiting
+            loop {
+                let at = deadline.at.get();
+                tokio::select! {
+                    result = &mut fut => return result,
+                    _ = sleep_until(at) => {
+                        if deadline.at.get() <= at {
+                            break;
+                        }
+                    }
+                }
+            }
+            let elapsed = deadline.started.elapsed();
+            log::warn!("{} {} timed out after {:?}", method, path, elapsed);
+            Err(ErrorGatewayTimeout(format!("Request timed out after {:?}", elapsed)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App, HttpResponse};
+
+    const LIMIT: Duration = Duration::from_millis(100);
+
+    async fn slow() -> HttpResponse {
+        tokio::time::sleep(LIMIT * 3).await;
+        HttpResponse::Ok().body("slow")
+    }
+
+    async fn fast() -> HttpResponse {
+        HttpResponse::Ok().body("fast")
+    }
+
+    // The status the client sees; a timeout is an error the server turns into a response
+    fn status<B>(result: Result<ServiceResponse<B>, Error>) -> StatusCode {
+        match result {
+            Ok(response) => response.status(),
+            Err(e) => e.as_response_error().status_code(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_slow_handler_times_out_and_fast_one_completes() {
+        let app = test::init_service(
+            App::new()
+                .wrap(Timeout::new(LIMIT))
+                .route("/slow", web::get().to(slow))
+                .route("/fast", web::get().to(fast)),
+        )
+        .await;
+
+        let started = Instant::now();
+        let result = test::try_call_service(&app, test::TestRequest::get().uri("/slow").to_request()).await;
+        assert_eq!(status(result), 504);
+        assert!(started.elapsed() < LIMIT * 2, "{:?}", started.elapsed());
+
+        let response = test::call_service(&app, test::TestRequest::get().uri("/fast").to_request()).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(test::read_body(response).await, "fast");
+    }
+
+    #[actix_web::test]
+    async fn test_route_overrides_the_default() {
+        let app = test::init_service(
+            App::new()
+                .wrap(Timeout::new(LIMIT))
+                .service(web::resource("/report").wrap(Timeout::new(LIMIT * 10)).route(web::get().to(slow)))
+                .service(web::resource("/strict").wrap(Timeout::new(LIMIT / 4)).route(web::get().to(slow))),
+        )
+        .await;
+
+        let response = test::call_service(&app, test::TestRequest::get().uri("/report").to_request()).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(test::read_body(response).await, "slow");
+
+        let started = Instant::now();
+        let result = test::try_call_service(&app, test::TestRequest::get().uri("/strict").to_request()).await;
+        assert_eq!(status(result), 504);
+        assert!(started.elapsed() < LIMIT, "{:?}", started.elapsed());
+    }
+}
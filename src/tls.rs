@@ -0,0 +1,48 @@
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+use std::fs;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+/// A server config presenting the PEM certificate chain at `cert_path` with
+/// the PEM private key at `key_path`, without client authentication.
+pub fn tls_config(cert_path: &Path, key_path: &Path) -> Result<ServerConfig, Box<dyn std::error::Error + Send + Sync>> {
+    let certs = load_certs(cert_path)?;
+    let key = load_private_key(key_path)?;
+    Ok(ServerConfig::builder().with_no_client_auth().with_single_cert(certs, key)?)
+}
+
+/// Every certificate in the PEM file at `path`, in order.
+pub fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    rustls_pemfile::certs(&mut reader).collect()
+}
+
+/// The first private key in the PEM file at `path`.
+pub fn load_private_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("no private key found in {}", path.display()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loads_a_pem_pair_and_rejects_a_missing_key() {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("noxium-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::write(&cert_path, certified.cert.pem()).unwrap();
+        fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+
+        assert_eq!(load_certs(&cert_path).unwrap(), vec![certified.cert.der().clone()]);
+        assert!(tls_config(&cert_path, &key_path).is_ok());
+        let error = load_private_key(&cert_path).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
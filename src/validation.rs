@@ -0,0 +1,113 @@
+use actix_web::error::JsonPayloadError;
+use actix_web::{dev::Payload, web::Json, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+use validator::{Validate, ValidationError};
+
+// Relative, so it resolves wherever error.rs is declared next to this module, e.g. inside vdom
+use super::error::NoxiumError;
+
+/// A JSON body that has passed its `#[derive(Validate)]` rules.
+///
+/// Use it in place of `web::Json<T>`. Bodies that fail validation are
+/// answered with `400` and the per-field messages (see
+/// [`NoxiumError::InvalidFields`]); malformed JSON is a plain `400`, and
+/// bodies over the configured limit are `413`.
+#[derive(Debug)]
+pub struct Valid<T>(pub T);
+
+impl<T> Valid<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate + 'static> FromRequest for Valid<T> {
+    type Error = NoxiumError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = Json::<T>::from_request(req, payload);
+        Box::pin(async move {
+            let value = json.await.map_err(json_error)?.into_inner();
+            value.validate()?;
+            Ok(Valid(value))
+        })
+    }
+}
+
+fn json_error(err: actix_web::Error) -> NoxiumError {
+    match err.as_error::<JsonPayloadError>() {
+        Some(JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. }) => {
+            NoxiumError::PayloadTooLarge(err.to_string())
+        }
+        _ => NoxiumError::Validation(err.to_string()),
+    }
+}
+
+/// Custom rule for strings that must contain more than whitespace.
+pub fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(ValidationError::new("not_blank").with_message("must not be blank".into()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::body_limit::actix_body_limits;
+    use actix_web::{test, web, App, HttpResponse};
+    use serde::Deserialize;
+    use serde_json::{json, Value};
+
+    #[derive(Deserialize, Validate)]
+    struct Signup {
+        #[validate(custom(function = "not_blank"))]
+        name: String,
+        #[validate(range(min = 13, message = "must be at least 13"))]
+        age: u8,
+    }
+
+    async fn signup(body: Valid<Signup>) -> HttpResponse {
+        HttpResponse::Ok().body(body.into_inner().name)
+    }
+
+    async fn post(body: &str) -> (u16, Value) {
+        let app = test::init_service(App::new().configure(actix_body_limits(64)).route("/", web::post().to(signup))).await;
+        let request = test::TestRequest::post()
+            .uri("/")
+            .insert_header(("content-type", "application/json"))
+            .set_payload(body.to_string())
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        let status = response.status().as_u16();
+        let body = test::read_body(response).await;
+        (status, serde_json::from_slice(&body).unwrap_or_else(|_| json!(String::from_utf8_lossy(&body))))
+    }
+
+    #[actix_web::test]
+    async fn test_valid_body_reaches_handler() {
+        assert_eq!(post(r#"{"name": "ada", "age": 36}"#).await, (200, json!("ada")));
+    }
+
+    #[actix_web::test]
+    async fn test_every_invalid_field_is_reported() {
+        let (status, body) = post(r#"{"name": "  ", "age": 7}"#).await;
+        assert_eq!(status, 400);
+        assert_eq!(body["error"], "validation");
+        assert_eq!(body["fields"], json!({ "age": ["must be at least 13"], "name": ["must not be blank"] }));
+    }
+
+    #[actix_web::test]
+    async fn test_unparseable_and_oversized_bodies() {
+        let (status, body) = post(r#"{"name": "ada"}"#).await;
+        assert_eq!(status, 400);
+        assert_eq!(body["error"], "validation");
+        assert!(body.get("fields").is_none());
+
+        let (status, body) = post(&json!({ "name": "x".repeat(100), "age": 20 }).to_string()).await;
+        assert_eq!(status, 413);
+        assert_eq!(body["error"], "payload_too_large");
+    }
+}
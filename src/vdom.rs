@@ -5,12 +5,12 @@ use actix_web::{
 use actix_service::Service;
 use askama::Template;
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 use log::{info, error, debug};
 use std::fs;
 use std::sync::Arc;
 use actix_web::middleware::Logger;
-use actix_web::web::Json;
-use actix_web::http::header::{X_REQUEST_ID, CONTENT_TYPE};
+use actix_web::http::header::CONTENT_TYPE;
 use std::env;
 use sqlx::SqlitePool;
 use actix_web::middleware::NormalizePath;
@@ -19,15 +19,70 @@ use std::io::Write;
 use lazy_static::lazy_static;
 use actix_web::http::header::HeaderValue;
 use actix_service::Service as _;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+
+// The `#[path]`s resolve from src/ both when this file is the vdom binary and
+// when ssr.rs or wwwroot/parser.rs include it as a module
+#[path = "auth_backend.rs"]
+#[allow(dead_code)]
+mod auth_backend;
+use auth_backend::{AuthBackend, AuthError, MemoryBackend, SqliteBackend};
+
+#[path = "body_limit.rs"]
+#[allow(dead_code)]
+mod body_limit;
+use body_limit::{actix_body_limits, max_body_bytes};
+
+#[path = "error.rs"]
+#[allow(dead_code)]
+mod error;
+
+#[path = "validation.rs"]
+#[allow(dead_code)]
+mod validation;
+use validation::{not_blank, Valid};
+
+#[path = "escaping.rs"]
+#[allow(dead_code)]
+mod escaping;
+use escaping::{attr_escape, html_escape};
+
+#[path = "static_files.rs"]
+#[allow(dead_code)]
+mod static_files;
+use static_files::{static_dir, static_routes};
+
+#[path = "request_log.rs"]
+#[allow(dead_code)]
+mod request_log;
+use request_log::RequestLogger;
+
+#[path = "timeout.rs"]
+#[allow(dead_code)]
+mod timeout;
+use timeout::{request_timeout, Timeout};
+
+#[path = "shutdown.rs"]
+#[allow(dead_code)]
+mod shutdown;
+use shutdown::shutdown_signal;
+
+// Event handlers are reference counted so trees and patches can share them
+pub type EventHandler = Rc<dyn Fn()>;
 
 // Virtual DOM implementation
-#[derive(Debug, Clone)]
 pub enum VNode {
     Element {
         tag: String,
         children: Vec<Rc<RefCell<VNode>>>,
         attributes: HashMap<String, String>,
-        event_handlers: HashMap<String, Box<dyn Fn()>>,
+        event_handlers: HashMap<String, EventHandler>,
     },
     Text(String),
     Fragment(Vec<Rc<RefCell<VNode>>>),
@@ -35,28 +90,110 @@ pub enum VNode {
         name: String,
         props: HashMap<String, String>,
         state: Rc<RefCell<dyn Any>>,
+        // Raised by `State::set`/`update`, cleared by the next `diff` with this node in its new tree
+        dirty: Rc<Cell<bool>>,
         component: Box<dyn Component>,
     },
 }
 
-#[derive(Debug, Clone)]
+// Child-list patches (`Add`, `Insert`, `Move`) act on the children of the
+// targeted node; `Remove` and `Replace` act on the targeted node itself.
 pub enum Patch {
     Replace(Rc<RefCell<VNode>>),
     Add(Rc<RefCell<VNode>>),
+    Insert(usize, Rc<RefCell<VNode>>),
+    Move { from: usize, to: usize },
     Remove,
     UpdateAttributes(HashMap<String, Option<String>>),
-    UpdateEventHandlers(HashMap<String, Box<dyn Fn()>>),
+    // Form-control state (see `FORM_PROPERTIES`), set as DOM properties
+    UpdateProperties(HashMap<String, Option<String>>),
+    UpdateEventHandlers(HashMap<String, EventHandler>),
     UpdateState(String, Box<dyn Any>),
 }
 
+// A patch plus the child index path from the root to the node it targets.
+// Element and fragment children are addressed the same way.
+#[derive(Debug)]
+pub struct NodePatch {
+    pub path: Vec<usize>,
+    pub patch: Patch,
+}
+
+// Attribute used to match element children across renders
+pub const KEY_ATTRIBUTE: &str = "key";
+
+// Form-control state the browser keeps as live properties. The attributes of
+// the same name only hold the initial value: once the user has typed into an
+// input, setting its `value` attribute no longer changes what it shows, so
+// changes to these are diffed as `Patch::UpdateProperties` instead.
+pub const FORM_PROPERTIES: [&str; 3] = ["value", "checked", "selected"];
+
+// `option` is included for `selected`, which only exists there
+fn is_form_control(tag: &str) -> bool {
+    matches!(tag, "input" | "textarea" | "select" | "option")
+}
+
+impl fmt::Debug for VNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VNode::Element { tag, children, attributes, event_handlers } => f
+                .debug_struct("Element")
+                .field("tag", tag)
+                .field("attributes", attributes)
+                .field("event_handlers", &event_handlers.keys().collect::<Vec<_>>())
+                .field("children", children)
+                .finish(),
+            VNode::Text(text) => f.debug_tuple("Text").field(text).finish(),
+            VNode::Fragment(children) => f.debug_tuple("Fragment").field(children).finish(),
+            VNode::Component { name, props, .. } => f
+                .debug_struct("Component")
+                .field("name", name)
+                .field("props", props)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+impl fmt::Debug for Patch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Patch::Replace(node) => f.debug_tuple("Replace").field(node).finish(),
+            Patch::Add(node) => f.debug_tuple("Add").field(node).finish(),
+            Patch::Insert(index, node) => f.debug_tuple("Insert").field(index).field(node).finish(),
+            Patch::Move { from, to } => f.debug_struct("Move").field("from", from).field("to", to).finish(),
+            Patch::Remove => write!(f, "Remove"),
+            Patch::UpdateAttributes(attrs) => f.debug_tuple("UpdateAttributes").field(attrs).finish(),
+            Patch::UpdateProperties(props) => f.debug_tuple("UpdateProperties").field(props).finish(),
+            Patch::UpdateEventHandlers(handlers) => f
+                .debug_tuple("UpdateEventHandlers")
+                .field(&handlers.keys().collect::<Vec<_>>())
+                .finish(),
+            Patch::UpdateState(key, _) => f.debug_tuple("UpdateState").field(key).finish_non_exhaustive(),
+        }
+    }
+}
+
 pub trait Component {
     fn render(&self) -> Rc<RefCell<VNode>>;
     fn component_did_mount(&mut self) {}
     fn component_will_unmount(&mut self) {}
+
+    /// Called by `diff` on the new component with the props and state of the
+    /// node it replaces; returning `false` skips the subtree without patches.
+    fn should_update(&self, _prev_props: &HashMap<String, String>, _prev_state: &dyn Any) -> bool {
+        true
+    }
+
+    /// Makes this component an error boundary: when anything it renders
+    /// panics, [`render`] shows this in its place, given the panic message.
+    /// `None`, the default, passes the panic on to the next boundary up.
+    fn render_error(&self, _err: &str) -> Option<Rc<RefCell<VNode>>> {
+        None
+    }
 }
 
 impl VNode {
-    pub fn new_element(tag: &str, attributes: HashMap<String, String>, children: Vec<Rc<RefCell<VNode>>>, event_handlers: HashMap<String, Box<dyn Fn()>>) -> Rc<RefCell<Self>> {
+    pub fn new_element(tag: &str, attributes: HashMap<String, String>, children: Vec<Rc<RefCell<VNode>>>, event_handlers: HashMap<String, EventHandler>) -> Rc<RefCell<Self>> {
         Rc::new(RefCell::new(VNode::Element {
             tag: tag.to_string(),
             attributes,
@@ -78,19 +215,366 @@ impl VNode {
             name: name.to_string(),
             props,
             state,
+            dirty: Rc::new(Cell::new(false)),
             component,
         }))
     }
+
+    /// A component whose state is `state`, re-rendered by `diff` whenever
+    /// the state was set or updated since the last diff.
+    pub fn new_stateful_component<T: 'static>(name: &str, props: HashMap<String, String>, state: &State<T>, component: Box<dyn Component>) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(VNode::Component {
+            name: name.to_string(),
+            props,
+            state: state.cell.clone(),
+            dirty: state.dirty.clone(),
+            component,
+        }))
+    }
+}
+
+/// A typed handle to component state, created by [`use_state`].
+///
+/// Pass it to [`VNode::new_stateful_component`]; the node holds the `T`
+/// itself, so `to_wire` sees the value as before. Clones share the value and
+/// a dirty flag, which `set` and `update` raise. The flag lives on the
+/// component node too, and the next `diff` that reaches the node re-renders
+/// the component and clears it.
+pub struct State<T> {
+    cell: Rc<RefCell<dyn Any>>,
+    dirty: Rc<Cell<bool>>,
+    value: PhantomData<T>,
+}
+
+impl<T> Clone for State<T> {
+    fn clone(&self) -> Self {
+        State { cell: self.cell.clone(), dirty: self.dirty.clone(), value: PhantomData }
+    }
+}
+
+/// Creates the state for a component, starting at `initial`.
+pub fn use_state<T: 'static>(initial: T) -> State<T> {
+    State { cell: Rc::new(RefCell::new(initial)), dirty: Rc::new(Cell::new(false)), value: PhantomData }
+}
+
+impl<T: 'static> State<T> {
+    pub fn get(&self) -> T
+    where
+        T: Clone,
+    {
+        self.with(T::clone)
+    }
+
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        // The cell was created holding a `T`, and a `dyn Any` can't be
+        // overwritten with another type through the `RefCell`
+        f(self.cell.borrow().downcast_ref::<T>().expect("state cell holds its original type"))
+    }
+
+    pub fn set(&self, value: T) {
+        self.update(|current| *current = value);
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut T)) {
+        f(self.cell.borrow_mut().downcast_mut::<T>().expect("state cell holds its original type"));
+        self.dirty.set(true);
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.get()
+    }
+
+    /// Whether the state changed since the last call, clearing the flag.
+    pub fn take_dirty(&self) -> bool {
+        self.dirty.replace(false)
+    }
+
+    pub fn cell(&self) -> Rc<RefCell<dyn Any>> {
+        self.cell.clone()
+    }
+}
+
+// Anything that can be appended as a child by `ElementBuilder`
+pub trait IntoVNode {
+    fn into_vnode(self) -> Rc<RefCell<VNode>>;
+}
+
+impl IntoVNode for Rc<RefCell<VNode>> {
+    fn into_vnode(self) -> Rc<RefCell<VNode>> {
+        self
+    }
+}
+
+impl IntoVNode for ElementBuilder {
+    fn into_vnode(self) -> Rc<RefCell<VNode>> {
+        self.finish()
+    }
+}
+
+impl IntoVNode for &str {
+    fn into_vnode(self) -> Rc<RefCell<VNode>> {
+        VNode::new_text(self)
+    }
+}
+
+impl IntoVNode for String {
+    fn into_vnode(self) -> Rc<RefCell<VNode>> {
+        VNode::new_text(&self)
+    }
+}
+
+// Fluent builder for `VNode::Element`
+pub struct ElementBuilder {
+    tag: String,
+    attributes: HashMap<String, String>,
+    children: Vec<Rc<RefCell<VNode>>>,
+    event_handlers: HashMap<String, EventHandler>,
+}
+
+impl ElementBuilder {
+    pub fn new(tag: &str) -> Self {
+        ElementBuilder {
+            tag: tag.to_string(),
+            attributes: HashMap::new(),
+            children: Vec::new(),
+            event_handlers: HashMap::new(),
+        }
+    }
+
+    pub fn attr(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.attributes.insert(key.to_string(), value.into());
+        self
+    }
+
+    pub fn on(mut self, event: &str, handler: impl Fn() + 'static) -> Self {
+        self.event_handlers.insert(event.to_string(), Rc::new(handler));
+        self
+    }
+
+    pub fn child(mut self, node: impl IntoVNode) -> Self {
+        self.children.push(node.into_vnode());
+        self
+    }
+
+    pub fn text(mut self, text: &str) -> Self {
+        self.children.push(VNode::new_text(text));
+        self
+    }
+
+    pub fn finish(self) -> Rc<RefCell<VNode>> {
+        VNode::new_element(&self.tag, self.attributes, self.children, self.event_handlers)
+    }
+}
+
+impl VNode {
+    pub fn element(tag: &str) -> ElementBuilder {
+        ElementBuilder::new(tag)
+    }
+}
+
+/// Builds an element tree: `html!(tag ["key" => value, ...] { child, ... })`.
+/// Children may be nodes, builders, or strings (rendered as text).
+/// It expands to [`VNode::element`], so callers need `VNode` in scope. Code
+/// after this in the file sees the macro; an includer gets it by putting
+/// `#[macro_use]` on its `mod vdom`.
+#[allow(unused_macros)]
+macro_rules! html {
+    ($tag:ident $([ $($key:literal => $value:expr),* $(,)? ])? $({ $($child:expr),* $(,)? })?) => {{
+        let builder = VNode::element(stringify!($tag));
+        $($(let builder = builder.attr($key, $value);)*)?
+        $($(let builder = builder.child($child);)*)?
+        builder.finish()
+    }};
+}
+
+// Elements whose text is shown as written
+const WHITESPACE_SENSITIVE_TAGS: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+/// Options for [`normalize_with`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NormalizeOptions {
+    /// Collapse whitespace runs in text to a single space and drop
+    /// whitespace-only text containing a line break (source indentation),
+    /// except inside `pre`, `textarea`, `script` and `style`
+    pub collapse_whitespace: bool,
+}
+
+/// Merges adjacent text children and drops empty ones, the way a browser
+/// builds its DOM. See [`normalize_with`].
+pub fn normalize(node: &Rc<RefCell<VNode>>) {
+    normalize_with(node, NormalizeOptions::default());
+}
+
+/// Normalizes the element and fragment children under `node` in place.
+/// Components are left alone. Text nodes that change are replaced rather
+/// than edited, so nodes shared with another tree are unaffected.
+pub fn normalize_with(node: &Rc<RefCell<VNode>>, options: NormalizeOptions) {
+    let mut node = node.borrow_mut();
+    let options = match &*node {
+        VNode::Element { tag, .. } if WHITESPACE_SENSITIVE_TAGS.contains(&tag.as_str()) => NormalizeOptions { collapse_whitespace: false },
+        _ => options,
+    };
+    let Some(children) = children_mut(&mut node) else {
+        return;
+    };
+
+    let mut normalized = Vec::with_capacity(children.len());
+    let mut texts = Vec::new();
+    for child in children.drain(..) {
+        if matches!(&*child.borrow(), VNode::Text(_)) {
+            texts.push(child);
+            continue;
+        }
+        flush_text_run(&mut texts, options, &mut normalized);
+        normalize_with(&child, options);
+        normalized.push(child);
+    }
+    flush_text_run(&mut texts, options, &mut normalized);
+    *children = normalized;
+}
+
+// Replace a run of adjacent text nodes with at most one
+fn flush_text_run(texts: &mut Vec<Rc<RefCell<VNode>>>, options: NormalizeOptions, out: &mut Vec<Rc<RefCell<VNode>>>) {
+    texts.retain(|text| !matches!(&*text.borrow(), VNode::Text(text) if text.is_empty()));
+    if texts.is_empty() {
+        return;
+    }
+    let original: String = texts
+        .iter()
+        .map(|text| match &*text.borrow() {
+            VNode::Text(text) => text.clone(),
+            _ => unreachable!("only text nodes are collected"),
+        })
+        .collect();
+
+    let text = if !options.collapse_whitespace {
+        original.clone()
+    } else if original.trim().is_empty() && original.contains('\n') {
+        String::new()
+    } else {
+        collapse_whitespace(&original)
+    };
+
+    if text.is_empty() {
+        // Nothing to render
+    } else if texts.len() == 1 && text == original {
+        out.push(texts[0].clone());
+    } else {
+        out.push(VNode::new_text(&text));
+    }
+    texts.clear();
 }
 
-pub fn diff(old: &Rc<RefCell<VNode>>, new: &Rc<RefCell<VNode>>) -> Vec<Patch> {
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut in_whitespace = false;
+    for c in text.chars() {
+        if !c.is_whitespace() {
+            collapsed.push(c);
+        } else if !in_whitespace {
+            collapsed.push(' ');
+        }
+        in_whitespace = c.is_whitespace();
+    }
+    collapsed
+}
+
+/// Expands every component in `node` into the tree its `render` returns,
+/// giving the element tree that is diffed and sent to clients.
+///
+/// When rendering a component's output panics, the nearest enclosing error
+/// boundary (see [`Component::render_error`]) is rendered as its fallback
+/// instead and the panic is logged; the rest of the tree is unaffected. A
+/// boundary's own `render` is not covered by itself, and a panic no boundary
+/// takes propagates. Builds with `panic = "abort"`, such as wasm, can't
+/// recover at all.
+pub fn render(node: &Rc<RefCell<VNode>>) -> Rc<RefCell<VNode>> {
+    match &*node.borrow() {
+        VNode::Element { tag, children, attributes, event_handlers } => {
+            VNode::new_element(tag, attributes.clone(), children.iter().map(render).collect(), event_handlers.clone())
+        }
+        VNode::Text(text) => VNode::new_text(text),
+        VNode::Fragment(children) => VNode::new_fragment(children.iter().map(render).collect()),
+        VNode::Component { name, component, .. } => {
+            let output = component.render();
+            match panic::catch_unwind(AssertUnwindSafe(|| render(&output))) {
+                Ok(rendered) => rendered,
+                Err(payload) => {
+                    let message = panic_message(&*payload);
+                    match component.render_error(&message) {
+                        Some(fallback) => {
+                            error!("Rendering inside {} panicked, showing its fallback: {}", name, message);
+                            render(&fallback)
+                        }
+                        None => panic::resume_unwind(payload),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Diffs normalized copies of both trees, leaving the trees themselves as
+/// built. Plain [`diff`] compares the trees exactly as built.
+pub fn diff_normalized(old: &Rc<RefCell<VNode>>, new: &Rc<RefCell<VNode>>, options: NormalizeOptions) -> Vec<NodePatch> {
+    let (old, new) = (copy_children(old), copy_children(new));
+    normalize_with(&old, options);
+    normalize_with(&new, options);
+    diff(&old, &new)
+}
+
+// Copies the elements and fragments `normalize_with` edits in place; the
+// text and component nodes it never edits are shared
+fn copy_children(node: &Rc<RefCell<VNode>>) -> Rc<RefCell<VNode>> {
+    match &*node.borrow() {
+        VNode::Element { tag, children, attributes, event_handlers } => VNode::new_element(
+            tag,
+            attributes.clone(),
+            children.iter().map(copy_children).collect(),
+            event_handlers.clone(),
+        ),
+        VNode::Fragment(children) => VNode::new_fragment(children.iter().map(copy_children).collect()),
+        VNode::Text(_) | VNode::Component { .. } => node.clone(),
+    }
+}
+
+pub fn diff(old: &Rc<RefCell<VNode>>, new: &Rc<RefCell<VNode>>) -> Vec<NodePatch> {
     let mut patches = Vec::new();
-    
+    diff_at(old, new, &mut Vec::new(), &mut patches);
+    clear_dirty(new);
+    patches
+}
+
+// Once diffed, the components in `new` are up to date with their state
+fn clear_dirty(node: &Rc<RefCell<VNode>>) {
+    match &*node.borrow() {
+        VNode::Element { children, .. } | VNode::Fragment(children) => children.iter().for_each(clear_dirty),
+        VNode::Text(_) => {}
+        VNode::Component { dirty, .. } => dirty.set(false),
+    }
+}
+
+// New subtrees in patches are rendered (see [`render`]), so clients get elements
+// rather than components, and a panic while rendering one shows the nearest
+// error boundary's fallback
+fn diff_at(old: &Rc<RefCell<VNode>>, new: &Rc<RefCell<VNode>>, path: &mut Vec<usize>, patches: &mut Vec<NodePatch>) {
+    let mut push = |patch| patches.push(NodePatch { path: path.clone(), patch });
+
     match (&*old.borrow(), &*new.borrow()) {
         (VNode::Element { tag: old_tag, attributes: old_attrs, children: old_children, event_handlers: old_handlers },
          VNode::Element { tag: new_tag, attributes: new_attrs, children: new_children, event_handlers: new_handlers }) => {
             if old_tag != new_tag {
-                patches.push(Patch::Replace(new.clone()));
+                push(Patch::Replace(render(new)));
             } else {
                 let mut attrs_diff = HashMap::new();
                 for (key, value) in new_attrs.iter() {
@@ -107,14 +591,22 @@ pub fn diff(old: &Rc<RefCell<VNode>>, new: &Rc<RefCell<VNode>>) -> Vec<Patch> {
                         attrs_diff.insert(key.clone(), None);
                     }
                 }
+                if is_form_control(new_tag) {
+                    let (props_diff, rest): (HashMap<_, _>, HashMap<_, _>) =
+                        attrs_diff.into_iter().partition(|(key, _)| FORM_PROPERTIES.contains(&key.as_str()));
+                    attrs_diff = rest;
+                    if !props_diff.is_empty() {
+                        push(Patch::UpdateProperties(props_diff));
+                    }
+                }
                 if !attrs_diff.is_empty() {
-                    patches.push(Patch::UpdateAttributes(attrs_diff));
+                    push(Patch::UpdateAttributes(attrs_diff));
                 }
 
                 let mut handlers_diff = HashMap::new();
                 for (event, handler) in new_handlers.iter() {
                     if let Some(old_handler) = old_handlers.get(event) {
-                        if !std::ptr::eq(&**handler as *const _, &**old_handler as *const _) {
+                        if !Rc::ptr_eq(handler, old_handler) {
                             handlers_diff.insert(event.clone(), handler.clone());
                         }
                     } else {
@@ -123,120 +615,310 @@ pub fn diff(old: &Rc<RefCell<VNode>>, new: &Rc<RefCell<VNode>>) -> Vec<Patch> {
                 }
                 for event in old_handlers.keys() {
                     if !new_handlers.contains_key(event) {
-                        handlers_diff.insert(event.clone(), Box::new(|| ()) as Box<dyn Fn()>);
+                        handlers_diff.insert(event.clone(), Rc::new(|| ()) as EventHandler);
                     }
                 }
                 if !handlers_diff.is_empty() {
-                    patches.push(Patch::UpdateEventHandlers(handlers_diff));
+                    push(Patch::UpdateEventHandlers(handlers_diff));
                 }
 
-                let mut children_patches = Vec::new();
-                let len = old_children.len().min(new_children.len());
-                for i in 0..len {
-                    children_patches.extend(diff(&old_children[i], &new_children[i]));
-                }
-                if old_children.len() > new_children.len() {
-                    for i in new_children.len()..old_children.len() {
-                        children_patches.push(Patch::Remove);
-                    }
-                } else if new_children.len() > old_children.len() {
-                    for i in old_children.len()..new_children.len() {
-                        children_patches.push(Patch::Add(new_children[i].clone()));
-                    }
-                }
-                patches.extend(children_patches);
+                diff_children(old_children, new_children, path, patches);
             }
         }
         (VNode::Text(old_text), VNode::Text(new_text)) => {
             if old_text != new_text {
-                patches.push(Patch::Replace(new.clone()));
+                push(Patch::Replace(render(new)));
             }
         }
         (VNode::Fragment(old_children), VNode::Fragment(new_children)) => {
-            let mut children_patches = Vec::new();
-            let len = old_children.len().min(new_children.len());
-            for i in 0..len {
-                children_patches.extend(diff(&old_children[i], &new_children[i]));
-            }
-            if old_children.len() > new_children.len() {
-                for i in new_children.len()..old_children.len() {
-                    children_patches.push(Patch::Remove);
-                }
-            } else if new_children.len() > old_children.len() {
-                for i in old_children.len()..new_children.len() {
-                    children_patches.push(Patch::Add(new_children[i].clone()));
+            diff_children(old_children, new_children, path, patches);
+        }
+        (VNode::Component { name: old_name, props: old_props, state: old_state, .. },
+         VNode::Component { name: new_name, props: new_props, state: new_state, dirty, component }) => {
+            // State changed in place is shared with the old node, so it can't be compared against that
+            if old_name != new_name || dirty.get() {
+                push(Patch::Replace(render(new)));
+            } else if !component.should_update(old_props, &*old_state.borrow()) {
+                // Memoized: the component reports its output is unchanged
+            } else if old_props != new_props {
+                // There is no props patch, so the component is remounted with the new ones
+                push(Patch::Replace(render(new)));
+            } else if let Some(new_state) = new_state.borrow().downcast_ref::<String>() {
+                let changed = match old_state.borrow().downcast_ref::<String>() {
+                    Some(old_state) => old_state != new_state,
+                    None => true,
+                };
+                if changed {
+                    push(Patch::UpdateState("state".to_string(), Box::new(new_state.clone())));
                 }
             }
-            patches.extend(children_patches);
         }
-        (VNode::Component { name: old_name, props: old_props, state: old_state, component: old_component },
-         VNode::Component { name: new_name, props: new_props, state: new_state, component: new_component }) => {
-            if old_name != new_name {
-                patches.push(Patch::Replace(new.clone()));
-            } else {
-                let mut state_diff = HashMap::new();
-                if let Some(new_state) = new_state.borrow().downcast_ref::<String>() {
-                    if let Some(old_state) = old_state.borrow().downcast_ref::<String>() {
-                        if old_state != new_state {
-                            state_diff.insert("state".to_string(), Box::new(new_state.clone()) as Box<dyn Any>);
-                        }
-                    } else {
-                        state_diff.insert("state".to_string(), Box::new(new_state.clone()) as Box<dyn Any>);
-                    }
-                }
-                if !state_diff.is_empty() {
-                    patches.push(Patch::UpdateState("state".to_string(), Box::new(state_diff) as Box<dyn Any>));
-                }
+        _ => push(Patch::Replace(render(new))),
+    }
+}
+
+// The `key` attribute of an element child, if any
+fn node_key(node: &Rc<RefCell<VNode>>) -> Option<String> {
+    match &*node.borrow() {
+        VNode::Element { attributes, .. } => attributes.get(KEY_ATTRIBUTE).cloned(),
+        _ => None,
+    }
+}
+
+// Keys for every child, or `None` unless all children are elements with unique keys
+fn child_keys(children: &[Rc<RefCell<VNode>>]) -> Option<Vec<String>> {
+    let keys: Vec<String> = children.iter().map(node_key).collect::<Option<_>>()?;
+    let mut unique: Vec<&String> = keys.iter().collect();
+    unique.sort();
+    unique.dedup();
+    (unique.len() == keys.len()).then_some(keys)
+}
+
+fn diff_children(old_children: &[Rc<RefCell<VNode>>], new_children: &[Rc<RefCell<VNode>>], path: &mut Vec<usize>, patches: &mut Vec<NodePatch>) {
+    match (child_keys(old_children), child_keys(new_children)) {
+        (Some(old_keys), Some(new_keys)) => diff_keyed_children(old_children, &old_keys, new_children, &new_keys, path, patches),
+        _ => diff_positional_children(old_children, new_children, path, patches),
+    }
+}
+
+// Whether diffing `old` against `new` would find nothing to patch, checked
+// without rendering anything or building the patches
+fn unchanged(old: &Rc<RefCell<VNode>>, new: &Rc<RefCell<VNode>>) -> bool {
+    if Rc::ptr_eq(old, new) {
+        return true;
+    }
+    let all_unchanged = |old: &[Rc<RefCell<VNode>>], new: &[Rc<RefCell<VNode>>]| {
+        old.len() == new.len() && old.iter().zip(new).all(|(old, new)| unchanged(old, new))
+    };
+    match (&*old.borrow(), &*new.borrow()) {
+        (VNode::Element { tag: old_tag, attributes: old_attrs, children: old_children, event_handlers: old_handlers },
+         VNode::Element { tag: new_tag, attributes: new_attrs, children: new_children, event_handlers: new_handlers }) => {
+            old_tag == new_tag
+                && old_attrs == new_attrs
+                && old_handlers.len() == new_handlers.len()
+                && new_handlers
+                    .iter()
+                    .all(|(event, handler)| old_handlers.get(event).is_some_and(|old| Rc::ptr_eq(old, handler)))
+                && all_unchanged(old_children, new_children)
+        }
+        (VNode::Text(old_text), VNode::Text(new_text)) => old_text == new_text,
+        (VNode::Fragment(old_children), VNode::Fragment(new_children)) => all_unchanged(old_children, new_children),
+        (VNode::Component { name: old_name, props: old_props, state: old_state, .. },
+         VNode::Component { name: new_name, props: new_props, state: new_state, dirty, component }) => {
+            old_name == new_name
+                && !dirty.get()
+                && (!component.should_update(old_props, &*old_state.borrow())
+                    || old_props == new_props
+                        && match new_state.borrow().downcast_ref::<String>() {
+                            Some(new_state) => old_state.borrow().downcast_ref::<String>() == Some(new_state),
+                            None => true,
+                        })
+        }
+        _ => false,
+    }
+}
+
+fn diff_positional_children(old_children: &[Rc<RefCell<VNode>>], new_children: &[Rc<RefCell<VNode>>], path: &mut Vec<usize>, patches: &mut Vec<NodePatch>) {
+    let len = old_children.len().min(new_children.len());
+
+    // Children inserted or removed in one place, with the rest unchanged, are
+    // patched as just that rather than by rewriting every child after them
+    if old_children.len() != new_children.len() {
+        let prefix = (0..len).take_while(|&i| unchanged(&old_children[i], &new_children[i])).count();
+        let suffix = (1..=len - prefix)
+            .take_while(|&i| unchanged(&old_children[old_children.len() - i], &new_children[new_children.len() - i]))
+            .count();
+        if prefix + suffix == len {
+            for i in (prefix..old_children.len() - suffix).rev() {
+                path.push(i);
+                patches.push(NodePatch { path: path.clone(), patch: Patch::Remove });
+                path.pop();
+            }
+            for (i, child) in new_children.iter().enumerate().take(new_children.len() - suffix).skip(prefix) {
+                let patch = if suffix == 0 { Patch::Add(render(child)) } else { Patch::Insert(i, render(child)) };
+                patches.push(NodePatch { path: path.clone(), patch });
             }
+            return;
+        }
+    }
+
+    for i in 0..len {
+        path.push(i);
+        diff_at(&old_children[i], &new_children[i], path, patches);
+        path.pop();
+    }
+    // Remove from the back so earlier indices stay valid
+    for i in (new_children.len()..old_children.len()).rev() {
+        path.push(i);
+        patches.push(NodePatch { path: path.clone(), patch: Patch::Remove });
+        path.pop();
+    }
+    for child in new_children.iter().skip(old_children.len()) {
+        patches.push(NodePatch { path: path.clone(), patch: Patch::Add(render(child)) });
+    }
+}
+
+// Match children by key: removals first, then moves/inserts into the new order,
+// then recursive diffs of the retained children at their final positions
+fn diff_keyed_children(
+    old_children: &[Rc<RefCell<VNode>>],
+    old_keys: &[String],
+    new_children: &[Rc<RefCell<VNode>>],
+    new_keys: &[String],
+    path: &mut Vec<usize>,
+    patches: &mut Vec<NodePatch>,
+) {
+    let mut current: Vec<&String> = old_keys.iter().collect();
+
+    for i in (0..old_keys.len()).rev() {
+        if !new_keys.contains(&old_keys[i]) {
+            path.push(i);
+            patches.push(NodePatch { path: path.clone(), patch: Patch::Remove });
+            path.pop();
+            current.remove(i);
+        }
+    }
+
+    for (to, key) in new_keys.iter().enumerate() {
+        match current.iter().position(|k| *k == key) {
+            Some(from) if from == to => {}
+            Some(from) => {
+                patches.push(NodePatch { path: path.clone(), patch: Patch::Move { from, to } });
+                let moved = current.remove(from);
+                current.insert(to, moved);
+            }
+            None => {
+                patches.push(NodePatch { path: path.clone(), patch: Patch::Insert(to, render(&new_children[to])) });
+                current.insert(to, key);
+            }
+        }
+    }
+
+    for (to, key) in new_keys.iter().enumerate() {
+        if let Some(from) = old_keys.iter().position(|k| k == key) {
+            path.push(to);
+            diff_at(&old_children[from], &new_children[to], path, patches);
+            path.pop();
         }
-        _ => patches.push(Patch::Replace(new.clone())),
     }
-    
-    patches
 }
 
+// Elements that never have children or a closing tag
+const VOID_TAGS: [&str; 13] =
+    ["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr"];
+
+// Elements whose text the browser reads as written, so it isn't escaped
+const RAW_TEXT_TAGS: [&str; 2] = ["script", "style"];
+
+// Renders HTML, escaping text and attribute values
 impl fmt::Display for VNode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             VNode::Element { tag, children, attributes, .. } => {
-                write!(f, "<{} ", tag)?;
+                // Sort attributes so rendering is deterministic
+                let mut attributes: Vec<_> = attributes.iter().collect();
+                attributes.sort();
+                write!(f, "<{}", tag)?;
                 for (key, value) in attributes {
-                    write!(f, "{}=\"{}\" ", key, value)?;
+                    write!(f, " {}=\"{}\"", key, attr_escape(value))?;
                 }
                 write!(f, ">")?;
+                if VOID_TAGS.contains(&tag.as_str()) {
+                    return Ok(());
+                }
                 for child in children {
-                    write!(f, "{}", child.borrow())?;
+                    match &*child.borrow() {
+                        VNode::Text(text) if RAW_TEXT_TAGS.contains(&tag.as_str()) => write!(f, "{}", text)?,
+                        child => write!(f, "{}", child)?,
+                    }
                 }
                 write!(f, "</{}>", tag)
             }
-            VNode::Text(text) => write!(f, "{}", text),
+            VNode::Text(text) => write!(f, "{}", html_escape(text)),
             VNode::Fragment(children) => {
                 for child in children {
                     write!(f, "{}", child.borrow())?;
                 }
                 Ok(())
             }
-            VNode::Component { name, props, state, .. } => {
-                write!(f, "<Component name=\"{}\" props=\"{:?}\" state=\"{:?}\"/>", name, props, state.borrow())
-            }
+            VNode::Component { name, props, state, .. } => write!(
+                f,
+                "<Component name=\"{}\" props=\"{}\" state=\"{}\"/>",
+                attr_escape(name),
+                attr_escape(&format!("{:?}", props)),
+                attr_escape(&format!("{:?}", state.borrow()))
+            ),
         }
     }
 }
 
-pub fn apply_patches(root: &mut VNode, patches: &[Patch]) {
-    let root = match root {
-        VNode::Element { children, .. } => children,
-        VNode::Fragment(children) => children,
-        _ => return,
-    };
+fn children_mut(node: &mut VNode) -> Option<&mut Vec<Rc<RefCell<VNode>>>> {
+    match node {
+        VNode::Element { children, .. } | VNode::Fragment(children) => Some(children),
+        _ => None,
+    }
+}
 
-    for patch in patches {
+// Follow a child index path from `root`, through elements and fragments alike
+fn node_at(root: &Rc<RefCell<VNode>>, path: &[usize]) -> Option<Rc<RefCell<VNode>>> {
+    let mut node = root.clone();
+    for &index in path {
+        let child = match &*node.borrow() {
+            VNode::Element { children, .. } | VNode::Fragment(children) => children.get(index)?.clone(),
+            _ => return None,
+        };
+        node = child;
+    }
+    Some(node)
+}
+
+pub fn apply_patches(root: &mut Rc<RefCell<VNode>>, patches: &[NodePatch]) {
+    for NodePatch { path, patch } in patches {
+        // Patches that act on the node itself may need to swap it out of its parent
+        if let Patch::Replace(_) | Patch::Remove = patch {
+            let Some((&index, parent_path)) = path.split_last() else {
+                if let Patch::Replace(new_node) = patch {
+                    *root = new_node.clone();
+                }
+                continue;
+            };
+            let Some(parent) = node_at(root, parent_path) else { continue };
+            let mut parent = parent.borrow_mut();
+            let Some(children) = children_mut(&mut parent) else { continue };
+            if index < children.len() {
+                match patch {
+                    Patch::Replace(new_node) => children[index] = new_node.clone(),
+                    _ => { children.remove(index); }
+                }
+            }
+            continue;
+        }
+
+        let Some(target) = node_at(root, path) else { continue };
+        let mut target = target.borrow_mut();
         match patch {
-            Patch::Replace(new_node) => *root = vec![new_node.clone()],
-            Patch::Add(node) => root.push(node.clone()),
-            Patch::Remove => { root.pop(); },
-            Patch::UpdateAttributes(attrs) => {
-                if let VNode::Element { attributes, .. } = root.last_mut().unwrap().borrow_mut() {
+            Patch::Add(node) => {
+                if let Some(children) = children_mut(&mut target) {
+                    children.push(node.clone());
+                }
+            }
+            Patch::Insert(index, node) => {
+                if let Some(children) = children_mut(&mut target) {
+                    let index = (*index).min(children.len());
+                    children.insert(index, node.clone());
+                }
+            }
+            Patch::Move { from, to } => {
+                if let Some(children) = children_mut(&mut target) {
+                    if *from < children.len() && *to < children.len() {
+                        let node = children.remove(*from);
+                        children.insert(*to, node);
+                    }
+                }
+            }
+            // The tree has no separate properties; they render as attributes
+            Patch::UpdateAttributes(attrs) | Patch::UpdateProperties(attrs) => {
+                if let VNode::Element { attributes, .. } = &mut *target {
                     for (key, value) in attrs {
                         match value {
                             Some(val) => attributes.insert(key.clone(), val.clone()),
@@ -246,19 +928,380 @@ pub fn apply_patches(root: &mut VNode, patches: &[Patch]) {
                 }
             }
             Patch::UpdateEventHandlers(handlers) => {
-                if let VNode::Element { event_handlers, .. } = root.last_mut().unwrap().borrow_mut() {
+                if let VNode::Element { event_handlers, .. } = &mut *target {
                     for (event, handler) in handlers {
                         event_handlers.insert(event.clone(), handler.clone());
                     }
                 }
             }
-            Patch::UpdateState(key, state) => {
-                if let VNode::Component { state: component_state, .. } = root.last_mut().unwrap().borrow_mut() {
-                    if let Some(state) = state.downcast_ref::<String>() {
-                        component_state.replace_with(|_| state.clone());
+            Patch::UpdateState(_, state) => {
+                if let VNode::Component { state: component_state, .. } = &*target {
+                    if let (Some(new_state), Some(current)) = (
+                        state.downcast_ref::<String>(),
+                        component_state.borrow_mut().downcast_mut::<String>(),
+                    ) {
+                        *current = new_state.clone();
+                    }
+                }
+            }
+            Patch::Replace(_) | Patch::Remove => unreachable!("handled above"),
+        }
+    }
+}
+
+// Wire format for patches sent to a client runtime as JSON.
+//
+// A patch set is an array of objects, each with the target `path` (child
+// indices from the root) and an `op` tag plus that op's fields:
+//
+//   {"path":[0],"op":"replace","node":NODE}
+//   {"path":[0],"op":"add","node":NODE}
+//   {"path":[0],"op":"insert","index":1,"node":NODE}
+//   {"path":[0],"op":"move","from":2,"to":0}
+//   {"path":[0,1],"op":"remove"}
+//   {"path":[0],"op":"update_attributes","attributes":{"class":"on","hidden":null}}
+//   {"path":[0],"op":"update_properties","properties":{"value":"hi","checked":null}}
+//   {"path":[0],"op":"update_event_handlers","handlers":{"click":"h7f3a10"}}
+//   {"path":[0],"op":"update_state","key":"count","value":JSON}
+//
+// A null attribute value removes the attribute. Properties are set on the
+// live element rather than as attributes; a null `value` clears it, and
+// `checked`/`selected` are true when present, like the boolean attributes.
+// NODE is tagged by `type`:
+//
+//   {"type":"element","tag":"li","attributes":{},"handlers":{},"children":[NODE]}
+//   {"type":"text","text":"hello"}
+//   {"type":"fragment","children":[NODE]}
+//   {"type":"component","name":"Counter","props":{},"state":JSON}
+//
+// Handlers are sent as ids (see `handler_id`) that the client reports back
+// when the event fires. State that isn't a JSON value, string, number, or
+// bool is sent as null.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WireNode {
+    Element {
+        tag: String,
+        attributes: BTreeMap<String, String>,
+        handlers: BTreeMap<String, String>,
+        children: Vec<WireNode>,
+    },
+    Text {
+        text: String,
+    },
+    Fragment {
+        children: Vec<WireNode>,
+    },
+    Component {
+        name: String,
+        props: BTreeMap<String, String>,
+        state: serde_json::Value,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum WirePatch {
+    Replace { node: WireNode },
+    Add { node: WireNode },
+    Insert { index: usize, node: WireNode },
+    Move { from: usize, to: usize },
+    Remove,
+    UpdateAttributes { attributes: BTreeMap<String, Option<String>> },
+    UpdateProperties { properties: BTreeMap<String, Option<String>> },
+    UpdateEventHandlers { handlers: BTreeMap<String, String> },
+    UpdateState { key: String, value: serde_json::Value },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WireNodePatch {
+    pub path: Vec<usize>,
+    #[serde(flatten)]
+    pub patch: WirePatch,
+}
+
+// Stable for as long as the server-side tree keeps the handler alive
+pub fn handler_id(handler: &EventHandler) -> String {
+    format!("h{:x}", Rc::as_ptr(handler) as *const () as usize)
+}
+
+fn wire_handlers(handlers: &HashMap<String, EventHandler>) -> BTreeMap<String, String> {
+    handlers.iter().map(|(event, handler)| (event.clone(), handler_id(handler))).collect()
+}
+
+fn wire_state(state: &dyn Any) -> serde_json::Value {
+    if let Some(value) = state.downcast_ref::<serde_json::Value>() {
+        value.clone()
+    } else if let Some(text) = state.downcast_ref::<String>() {
+        text.clone().into()
+    } else if let Some(number) = state.downcast_ref::<i64>() {
+        (*number).into()
+    } else if let Some(number) = state.downcast_ref::<f64>() {
+        (*number).into()
+    } else if let Some(flag) = state.downcast_ref::<bool>() {
+        (*flag).into()
+    } else {
+        serde_json::Value::Null
+    }
+}
+
+impl VNode {
+    pub fn to_wire(&self) -> WireNode {
+        let wire_children = |children: &[Rc<RefCell<VNode>>]| children.iter().map(|child| child.borrow().to_wire()).collect();
+        match self {
+            VNode::Element { tag, children, attributes, event_handlers } => WireNode::Element {
+                tag: tag.clone(),
+                attributes: attributes.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+                handlers: wire_handlers(event_handlers),
+                children: wire_children(children),
+            },
+            VNode::Text(text) => WireNode::Text { text: text.clone() },
+            VNode::Fragment(children) => WireNode::Fragment { children: wire_children(children) },
+            VNode::Component { name, props, state, .. } => WireNode::Component {
+                name: name.clone(),
+                props: props.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+                state: wire_state(&*state.borrow()),
+            },
+        }
+    }
+}
+
+impl Patch {
+    pub fn to_wire(&self) -> WirePatch {
+        match self {
+            Patch::Replace(node) => WirePatch::Replace { node: node.borrow().to_wire() },
+            Patch::Add(node) => WirePatch::Add { node: node.borrow().to_wire() },
+            Patch::Insert(index, node) => WirePatch::Insert { index: *index, node: node.borrow().to_wire() },
+            Patch::Move { from, to } => WirePatch::Move { from: *from, to: *to },
+            Patch::Remove => WirePatch::Remove,
+            Patch::UpdateAttributes(attrs) => WirePatch::UpdateAttributes {
+                attributes: attrs.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            },
+            Patch::UpdateProperties(props) => WirePatch::UpdateProperties {
+                properties: props.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            },
+            Patch::UpdateEventHandlers(handlers) => WirePatch::UpdateEventHandlers { handlers: wire_handlers(handlers) },
+            Patch::UpdateState(key, state) => WirePatch::UpdateState { key: key.clone(), value: wire_state(&**state) },
+        }
+    }
+}
+
+impl NodePatch {
+    pub fn to_wire(&self) -> WireNodePatch {
+        WireNodePatch { path: self.path.clone(), patch: self.patch.to_wire() }
+    }
+}
+
+// Applies wire patches to the browser DOM, for client runtimes built to wasm.
+//
+// Paths are followed through `childNodes`, so they line up with the DOM as
+// long as the patched subtree has no fragments: those render without a
+// wrapper and shift the indices of everything after them. Handler and state
+// patches belong to the client runtime and are skipped here.
+#[cfg(target_arch = "wasm32")]
+pub mod dom {
+    use super::{is_form_control, WireNode, WireNodePatch, WirePatch, FORM_PROPERTIES};
+    use wasm_bindgen::{JsCast, JsValue};
+    use web_sys::{Document, Element, HtmlInputElement, HtmlOptionElement, HtmlSelectElement, HtmlTextAreaElement, Node};
+
+    // `root` is the DOM node the tree's root was rendered as, and is updated
+    // when a patch replaces it
+    pub fn apply_dom_patches(root: &mut Node, patches: &[WireNodePatch]) -> Result<(), JsValue> {
+        let document = root.owner_document().ok_or_else(|| JsValue::from_str("root is not in a document"))?;
+        for WireNodePatch { path, patch } in patches {
+            let target = node_at(root, path)?;
+            match patch {
+                WirePatch::Replace { node } => {
+                    let node = create_node(&document, node)?;
+                    if let Some(parent) = target.parent_node() {
+                        parent.replace_child(&node, &target)?;
+                    }
+                    if path.is_empty() {
+                        *root = node;
+                    }
+                }
+                WirePatch::Remove => {
+                    if let Some(parent) = target.parent_node() {
+                        parent.remove_child(&target)?;
+                    }
+                }
+                WirePatch::Add { node } => {
+                    target.append_child(&create_node(&document, node)?)?;
+                }
+                WirePatch::Insert { index, node } => {
+                    let before = target.child_nodes().item(*index as u32);
+                    target.insert_before(&create_node(&document, node)?, before.as_ref())?;
+                }
+                WirePatch::Move { from, to } => {
+                    if let Some(node) = target.child_nodes().item(*from as u32) {
+                        target.remove_child(&node)?;
+                        let before = target.child_nodes().item(*to as u32);
+                        target.insert_before(&node, before.as_ref())?;
+                    }
+                }
+                WirePatch::UpdateAttributes { attributes } => {
+                    let element = as_element(target)?;
+                    for (name, value) in attributes {
+                        set_attribute(&element, name, value.as_deref())?;
+                    }
+                }
+                WirePatch::UpdateProperties { properties } => {
+                    let element = as_element(target)?;
+                    for (name, value) in properties {
+                        set_property(&element, name, value.as_deref())?;
+                    }
+                }
+                WirePatch::UpdateEventHandlers { .. } | WirePatch::UpdateState { .. } => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn node_at(root: &Node, path: &[usize]) -> Result<Node, JsValue> {
+        path.iter().try_fold(root.clone(), |node, &index| {
+            node.child_nodes()
+                .item(index as u32)
+                .ok_or_else(|| JsValue::from_str(&format!("no node at path {:?}", path)))
+        })
+    }
+
+    fn as_element(node: Node) -> Result<Element, JsValue> {
+        node.dyn_into::<Element>().map_err(|_| JsValue::from_str("patch targets a non-element node"))
+    }
+
+    // Sets one of `FORM_PROPERTIES` on the live element, falling back to the
+    // attribute for elements that don't have that property
+    fn set_property(element: &Element, name: &str, value: Option<&str>) -> Result<(), JsValue> {
+        let text = value.unwrap_or_default();
+        if let Some(input) = element.dyn_ref::<HtmlInputElement>() {
+            match name {
+                "value" => input.set_value(text),
+                "checked" => input.set_checked(value.is_some()),
+                _ => return set_attribute(element, name, value),
+            }
+        } else if let Some(option) = element.dyn_ref::<HtmlOptionElement>() {
+            match name {
+                "value" => option.set_value(text),
+                "selected" => option.set_selected(value.is_some()),
+                _ => return set_attribute(element, name, value),
+            }
+        } else if let (Some(textarea), "value") = (element.dyn_ref::<HtmlTextAreaElement>(), name) {
+            textarea.set_value(text);
+        } else if let (Some(select), "value") = (element.dyn_ref::<HtmlSelectElement>(), name) {
+            select.set_value(text);
+        } else {
+            return set_attribute(element, name, value);
+        }
+        Ok(())
+    }
+
+    fn set_attribute(element: &Element, name: &str, value: Option<&str>) -> Result<(), JsValue> {
+        match value {
+            Some(value) => element.set_attribute(name, value),
+            None => element.remove_attribute(name),
+        }
+    }
+
+    fn create_node(document: &Document, node: &WireNode) -> Result<Node, JsValue> {
+        Ok(match node {
+            WireNode::Element { tag, attributes, children, .. } => {
+                let element = document.create_element(tag)?;
+                for (name, value) in attributes {
+                    element.set_attribute(name, value)?;
+                }
+                for child in children {
+                    element.append_child(&create_node(document, child)?)?;
+                }
+                // After the children, so a select's options exist to pick from
+                if is_form_control(tag) {
+                    for name in FORM_PROPERTIES {
+                        if let Some(value) = attributes.get(name) {
+                            set_property(&element, name, Some(value))?;
+                        }
                     }
                 }
+                element.into()
+            }
+            WireNode::Text { text } => document.create_text_node(text).into(),
+            WireNode::Fragment { children } => {
+                let fragment = document.create_document_fragment();
+                for child in children {
+                    fragment.append_child(&create_node(document, child)?)?;
+                }
+                fragment.into()
             }
+            WireNode::Component { name, .. } => {
+                return Err(JsValue::from_str(&format!("component {} can't be rendered in the browser", name)))
+            }
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::super::{diff, NodePatch, VNode};
+        use super::*;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        use wasm_bindgen_test::*;
+
+        wasm_bindgen_test_configure!(run_in_browser);
+
+        // Render `tree` into the page and return its root node
+        fn mount(tree: &Rc<RefCell<VNode>>) -> Node {
+            let document = web_sys::window().unwrap().document().unwrap();
+            let node = create_node(&document, &tree.borrow().to_wire()).unwrap();
+            document.body().unwrap().append_child(&node).unwrap();
+            node
+        }
+
+        fn patch(root: &mut Node, old: &Rc<RefCell<VNode>>, new: &Rc<RefCell<VNode>>) {
+            let patches: Vec<_> = diff(old, new).iter().map(NodePatch::to_wire).collect();
+            apply_dom_patches(root, &patches).unwrap();
+        }
+
+        #[wasm_bindgen_test]
+        fn test_value_patch_updates_the_live_value() {
+            let old = VNode::element("input").attr("type", "text").attr("value", "draft").finish();
+            let new = VNode::element("input").attr("type", "text").attr("value", "saved").finish();
+            let mut root = mount(&old);
+            let input = root.clone().dyn_into::<HtmlInputElement>().unwrap();
+            // Typing makes the value dirty, after which the attribute no longer shows
+            input.set_value("typed");
+
+            patch(&mut root, &old, &new);
+            assert_eq!(input.value(), "saved");
+            assert_eq!(input.get_attribute("value").as_deref(), Some("draft"));
+        }
+
+        #[wasm_bindgen_test]
+        fn test_checked_and_selected_patches_update_live_state() {
+            let form = |checked: bool, choice: &str| {
+                let mut checkbox = VNode::element("input").attr("type", "checkbox");
+                if checked {
+                    checkbox = checkbox.attr("checked", "");
+                }
+                let mut select = VNode::element("select");
+                for value in ["a", "b"] {
+                    let mut option = VNode::element("option").attr("value", value);
+                    if value == choice {
+                        option = option.attr("selected", "");
+                    }
+                    select = select.child(option.text(value));
+                }
+                VNode::element("form").child(checkbox).child(select).finish()
+            };
+            let old = form(true, "a");
+            let new = form(false, "b");
+            let mut root = mount(&old);
+            let element = root.clone().dyn_into::<Element>().unwrap();
+            let checkbox = element.query_selector("input").unwrap().unwrap().dyn_into::<HtmlInputElement>().unwrap();
+            let select = element.query_selector("select").unwrap().unwrap().dyn_into::<HtmlSelectElement>().unwrap();
+            assert!(checkbox.checked());
+            assert_eq!(select.value(), "a");
+
+            patch(&mut root, &old, &new);
+            assert!(!checkbox.checked());
+            assert_eq!(select.value(), "b");
         }
     }
 }
@@ -271,16 +1314,20 @@ struct IndexTemplate {
 }
 
 // Define a struct for configuration data
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Validate)]
 struct Config {
+    #[validate(range(min = 1, message = "Port cannot be zero"))]
     port: u16,
+    #[validate(custom(function = "not_blank"))]
     database_url: String,
 }
 
 // Define a struct for user registration
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Validate)]
 struct UserRegistration {
+    #[validate(custom(function = "not_blank"))]
     username: String,
+    #[validate(length(min = 1, message = "must not be empty"))]
     password: String,
 }
 
@@ -294,7 +1341,6 @@ struct UserDetails {
 // Define a custom error type for API errors
 #[derive(Debug)]
 enum ApiError {
-    InvalidInput(String),
     InternalError(String),
     DatabaseError(String),
     AuthenticationError(String),
@@ -313,7 +1359,8 @@ struct RateLimiter {
 }
 
 async fn rate_limiter(req: ServiceRequest, srv: &actix_service::Service) -> Result<HttpResponse, Error> {
-    let client_ip = req.connection_info().realip().unwrap_or("unknown").to_string();
+    // The connecting address; Forwarded and X-Forwarded-For are set by the client
+    let client_ip = req.peer_addr().map_or_else(|| "unknown".to_string(), |addr| addr.ip().to_string());
     let mut state = req.app_data::<web::Data<RateLimiter>>().unwrap().requests.lock().unwrap();
     
     let counter = state.entry(client_ip.clone()).or_insert(0);
@@ -348,15 +1395,11 @@ async fn index() -> HttpResponse {
         .body(rendered)
 }
 
-async fn api_handler(req: HttpRequest, body: Json<Config>) -> ActixResult<HttpResponse> {
+async fn api_handler(req: HttpRequest, body: Valid<Config>) -> ActixResult<HttpResponse> {
     let config = body.into_inner();
 
     info!("Received API request with port: {}", config.port);
 
-    if config.port == 0 {
-        return Err(ApiError::InvalidInput("Port cannot be zero".into()).into());
-    }
-
     Ok(HttpResponse::Ok()
         .content_type("application/json")
         .json(config))
@@ -394,15 +1437,6 @@ async fn log_request(req: ServiceRequest, srv: &actix_service::Service) -> Resul
     Ok(srv.call(req).await?)
 }
 
-async fn add_custom_headers(req: ServiceRequest, srv: &actix_service::Service) -> Result<HttpResponse, Error> {
-    let mut res = srv.call(req).await?;
-    res.headers_mut().insert(
-        X_REQUEST_ID,
-        HeaderValue::from_static("12345"),
-    );
-    Ok(res)
-}
-
 async fn handle_cors(req: ServiceRequest, srv: &actix_service::Service) -> Result<HttpResponse, Error> {
     let mut res = srv.call(req).await?;
     res.headers_mut().insert(
@@ -418,24 +1452,14 @@ fn read_config_from_file(file_path: &str) -> Result<Config, std::io::Error> {
     Ok(config)
 }
 
-async fn shutdown_signal() {
-    tokio::signal::ctrl_c().await.expect("Failed to listen for shutdown signal");
-    info!("Received shutdown signal, shutting down gracefully.");
-}
-
-// Mock user authentication function
-fn authenticate_user(username: &str, password: &str) -> bool {
-    username == "admin" && password == "password"
-}
-
 // Handler for user registration
-async fn register_user(body: Json<UserRegistration>) -> ActixResult<HttpResponse> {
+async fn register_user(auth: web::Data<Box<dyn AuthBackend>>, body: Valid<UserRegistration>) -> ActixResult<HttpResponse> {
     let user = body.into_inner();
 
-    if authenticate_user(&user.username, &user.password) {
-        Ok(HttpResponse::Ok().body("User registered successfully"))
-    } else {
-        Err(ApiError::AuthenticationError("Invalid credentials".into()).into())
+    match auth.verify(&user.username, &user.password).await {
+        Ok(_) => Ok(HttpResponse::Ok().body("User registered successfully")),
+        Err(AuthError::InvalidCredentials) => Err(ApiError::AuthenticationError("Invalid credentials".into()).into()),
+        Err(AuthError::Backend(e)) => Err(ApiError::InternalError(e).into()),
     }
 }
 
@@ -452,17 +1476,6 @@ async fn get_user_details(user_id: web::Path<u32>) -> ActixResult<HttpResponse>
     Ok(HttpResponse::Ok().json(user))
 }
 
-// Handler for serving static files
-async fn static_file_handler(req: HttpRequest) -> ActixResult<HttpResponse> {
-    let filename = req.match_info().get("filename").unwrap_or("index.html");
-    let filepath = format!("./public/{}", filename);
-
-    match fs::read_to_string(&filepath) {
-        Ok(content) => Ok(HttpResponse::Ok().content_type("text/html").body(content)),
-        Err(_) => Ok(HttpResponse::NotFound().body("File not found")),
-    }
-}
-
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
@@ -471,31 +1484,582 @@ async fn main() -> std::io::Result<()> {
     let database_url = env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite://:memory:".to_string());
 
     let pool = SqlitePool::connect(&database_url).await.unwrap();
+    // AUTH_USERS lists users inline, as for the ssr server; otherwise they come from the users table
+    let auth_backend: web::Data<Box<dyn AuthBackend>> = web::Data::new(match env::var("AUTH_USERS") {
+        Ok(spec) => Box::new(MemoryBackend::parse(&spec).map_err(std::io::Error::other)?),
+        Err(_) => Box::new(SqliteBackend::new(pool.clone())),
+    });
     let pool = Arc::new(pool);
     DB_POOL = pool;
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
+            .configure(actix_body_limits(max_body_bytes()))
             .wrap(Logger::default())
             .wrap_fn(log_request)
-            .wrap_fn(add_custom_headers)
             .wrap_fn(handle_cors)
             .wrap_fn(rate_limiter)
+            .app_data(auth_backend.clone())
             .service(web::resource("/").route(web::get().to(index)))
             .service(web::resource("/api").route(web::post().to(api_handler)))
             .service(web::resource("/upload").route(web::post().to(upload_file)))
             .service(web::resource("/data").route(web::get().to(get_data_from_db)))
             .service(web::resource("/register").route(web::post().to(register_user)))
             .service(web::resource("/user/{user_id}").route(web::get().to(get_user_details)))
-            .service(web::resource("/static/{filename:.*}").route(web::get().to(static_file_handler)))
+            .configure(static_routes(static_dir()))
             .default_service(web::route().to(|| HttpResponse::NotFound()))
             .service(
                 web::resource("/status")
                     .route(web::get().to(|| HttpResponse::Ok().body("Server is running.")))
             )
             .wrap(NormalizePath::default())
+            .wrap(Timeout::new(request_timeout()))
+            .wrap(RequestLogger)
     })
+    .disable_signals()
     .bind(format!("127.0.0.1:{}", port))?
-    .run()
-    .await
-}
\ No newline at end of file
+    .run();
+
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        handle.stop(true).await;
+    });
+    server.await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_builder_renders_form() {
+        let form = VNode::element("form")
+            .attr("action", "/login")
+            .attr("method", "post")
+            .child(VNode::element("label").attr("for", "user").text("Username"))
+            .child(VNode::element("input").attr("id", "user").attr("name", "user"))
+            .child(VNode::element("button").attr("type", "submit").text("Sign in"))
+            .finish();
+
+        assert_eq!(
+            form.borrow().to_string(),
+            "<form action=\"/login\" method=\"post\">\
+             <label for=\"user\">Username</label>\
+             <input id=\"user\" name=\"user\">\
+             <button type=\"submit\">Sign in</button>\
+             </form>"
+        );
+    }
+
+    #[test]
+    fn test_text_and_attributes_are_escaped() {
+        let node = html!(p ["title" => "say \"hi\" & <bye>"] {
+            "1 < 2 && <b>not bold</b>",
+            html!(script { "if (a < b && c) {}" }),
+        });
+        assert_eq!(
+            node.borrow().to_string(),
+            "<p title=\"say &quot;hi&quot; &amp; &lt;bye&gt;\">1 &lt; 2 &amp;&amp; &lt;b&gt;not bold&lt;/b&gt;\
+             <script>if (a < b && c) {}</script></p>"
+        );
+    }
+
+    #[test]
+    fn test_builder_registers_handlers() {
+        let clicked = Rc::new(Cell::new(false));
+        let flag = clicked.clone();
+        let button = VNode::element("button").on("click", move || flag.set(true)).finish();
+
+        if let VNode::Element { event_handlers, .. } = &*button.borrow() {
+            event_handlers["click"]();
+        }
+        assert!(clicked.get());
+    }
+
+    #[test]
+    fn test_html_macro_matches_builder() {
+        let from_macro = html!(form ["action" => "/login"] {
+            html!(input ["name" => "user"]),
+            "Submit",
+        });
+        let from_builder = VNode::element("form")
+            .attr("action", "/login")
+            .child(VNode::element("input").attr("name", "user"))
+            .text("Submit")
+            .finish();
+
+        assert_eq!(from_macro.borrow().to_string(), from_builder.borrow().to_string());
+    }
+
+    fn keyed_item(key: &str, text: &str) -> Rc<RefCell<VNode>> {
+        VNode::element("li").attr(KEY_ATTRIBUTE, key).text(text).finish()
+    }
+
+    #[test]
+    fn test_reversed_keyed_fragment_moves_children() {
+        let old = VNode::new_fragment(vec![keyed_item("a", "A"), keyed_item("b", "B"), keyed_item("c", "C")]);
+        let new = VNode::new_fragment(vec![keyed_item("c", "C"), keyed_item("b", "B"), keyed_item("a", "A")]);
+        let original_c = match &*old.borrow() {
+            VNode::Fragment(children) => children[2].clone(),
+            _ => unreachable!(),
+        };
+
+        let patches = diff(&old, &new);
+        assert!(!patches.is_empty());
+        assert!(patches.iter().all(|p| matches!(p.patch, Patch::Move { .. }) && p.path.is_empty()), "{:?}", patches);
+
+        let mut root = old.clone();
+        apply_patches(&mut root, &patches);
+        assert_eq!(root.borrow().to_string(), new.borrow().to_string());
+
+        // The moved node is the original instance, not a replacement
+        match &*root.borrow() {
+            VNode::Fragment(children) => assert!(Rc::ptr_eq(&children[0], &original_c)),
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn test_keyed_children_insert_remove_and_update() {
+        let old = html!(ul { keyed_item("a", "A"), keyed_item("b", "B"), keyed_item("c", "C") });
+        let new = html!(ul { keyed_item("c", "C2"), keyed_item("d", "D"), keyed_item("a", "A") });
+
+        let patches = diff(&old, &new);
+        assert!(patches.iter().any(|p| matches!(p.patch, Patch::Insert(1, _))));
+        assert!(patches.iter().any(|p| matches!(p.patch, Patch::Remove) && p.path == vec![1]));
+        // The text change inside "c" is addressed at its final position
+        assert!(patches.iter().any(|p| matches!(p.patch, Patch::Replace(_)) && p.path == vec![0, 0]));
+
+        let mut root = old.clone();
+        apply_patches(&mut root, &patches);
+        assert_eq!(root.borrow().to_string(), new.borrow().to_string());
+    }
+
+    fn numbered_list(items: &[&str]) -> Rc<RefCell<VNode>> {
+        VNode::new_element("ol", HashMap::new(), items.iter().map(|item| html!(li { *item })).collect(), HashMap::new())
+    }
+
+    #[test]
+    fn test_unkeyed_insertion_is_a_single_patch() {
+        let old = numbered_list(&["1", "2", "3", "4", "5"]);
+        for (new_items, index) in [
+            (["new", "1", "2", "3", "4", "5"], 0),
+            (["1", "2", "new", "3", "4", "5"], 2),
+            (["1", "2", "3", "4", "5", "new"], 5),
+        ] {
+            let new = numbered_list(&new_items);
+            let patches = diff(&old, &new);
+            assert_eq!(patches.len(), 1, "{:?}", patches);
+            assert!(patches[0].path.is_empty());
+            match &patches[0].patch {
+                Patch::Insert(at, _) => assert_eq!(*at, index),
+                Patch::Add(_) => assert_eq!(index, 5),
+                other => panic!("expected an insertion, got {:?}", other),
+            }
+
+            let mut root = numbered_list(&["1", "2", "3", "4", "5"]);
+            apply_patches(&mut root, &patches);
+            assert_eq!(root.borrow().to_string(), new.borrow().to_string());
+        }
+    }
+
+    #[test]
+    fn test_unkeyed_removal_is_a_single_patch() {
+        let old = numbered_list(&["1", "2", "3", "4", "5"]);
+        for (new_items, index) in [(["2", "3", "4", "5"], 0), (["1", "2", "4", "5"], 2), (["1", "2", "3", "4"], 4)] {
+            let new = numbered_list(&new_items);
+            let patches = diff(&old, &new);
+            assert_eq!(patches.len(), 1, "{:?}", patches);
+            assert_eq!(patches[0].path, vec![index]);
+            assert!(matches!(patches[0].patch, Patch::Remove));
+
+            let mut root = numbered_list(&["1", "2", "3", "4", "5"]);
+            apply_patches(&mut root, &patches);
+            assert_eq!(root.borrow().to_string(), new.borrow().to_string());
+        }
+
+        // Changes elsewhere fall back to patching position by position
+        let patches = diff(&old, &numbered_list(&["1", "two", "4", "5"]));
+        assert!(patches.len() > 1, "{:?}", patches);
+    }
+
+    #[test]
+    fn test_deep_trees_diff_in_linear_time() {
+        // Each level holds a label beside the next level, `depth` levels down
+        fn nested(depth: usize, leaf: &[&str]) -> Rc<RefCell<VNode>> {
+            let list = leaf.iter().fold(VNode::element("ul"), |list, text| list.child(html!(li { *text })));
+            let mut node = list.finish();
+            for _ in 0..depth {
+                node = html!(div { "level", node });
+            }
+            node
+        }
+
+        let started = std::time::Instant::now();
+        assert!(diff(&nested(30, &["a"]), &nested(30, &["a"])).is_empty());
+
+        let patches = diff(&nested(30, &["a"]), &nested(30, &["b"]));
+        assert_eq!(patches.len(), 1, "{:?}", patches);
+        assert_eq!(patches[0].path, [vec![1; 30], vec![0, 0]].concat());
+
+        let (mut root, new) = (nested(30, &["a"]), nested(30, &["a", "b"]));
+        let patches = diff(&root, &new);
+        assert_eq!(patches.len(), 1, "{:?}", patches);
+        apply_patches(&mut root, &patches);
+        assert_eq!(root.borrow().to_string(), new.borrow().to_string());
+        assert!(started.elapsed() < std::time::Duration::from_secs(1), "took {:?}", started.elapsed());
+    }
+
+    #[test]
+    fn test_patches_address_nested_fragment_children() {
+        let old = html!(div { VNode::new_fragment(vec![VNode::new_text("one"), VNode::new_text("two")]), "tail" });
+        let new = html!(div { VNode::new_fragment(vec![VNode::new_text("one"), VNode::new_text("2")]), "tail" });
+
+        let patches = diff(&old, &new);
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path, vec![0, 1]);
+
+        let mut root = old.clone();
+        apply_patches(&mut root, &patches);
+        assert_eq!(root.borrow().to_string(), "<div>one2tail</div>");
+    }
+
+    #[test]
+    fn test_attribute_patch_targets_only_its_child() {
+        let old = html!(ul { html!(li ["class" => "a"] { "one" }), html!(li ["class" => "b"] { "two" }), html!(li ["class" => "c"] { "three" }) });
+        let new = html!(ul { html!(li ["class" => "on"] { "one" }), html!(li ["class" => "b"] { "two" }), html!(li ["class" => "c"] { "three" }) });
+
+        let patches = diff(&old, &new);
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path, vec![0]);
+
+        let mut root = old.clone();
+        apply_patches(&mut root, &patches);
+        assert_eq!(
+            root.borrow().to_string(),
+            "<ul><li class=\"on\">one</li><li class=\"b\">two</li><li class=\"c\">three</li></ul>"
+        );
+    }
+
+    #[test]
+    fn test_form_control_state_is_diffed_as_properties() {
+        let old = VNode::element("input").attr("value", "draft").attr("checked", "").attr("class", "a").finish();
+        let new = VNode::element("input").attr("value", "saved").attr("class", "b").finish();
+
+        let patches = diff(&old, &new);
+        assert_eq!(patches.len(), 2, "{:?}", patches);
+        let Patch::UpdateProperties(props) = &patches[0].patch else { panic!("expected properties first: {:?}", patches) };
+        assert_eq!(props, &HashMap::from([("value".to_string(), Some("saved".to_string())), ("checked".to_string(), None)]));
+        let Patch::UpdateAttributes(attrs) = &patches[1].patch else { panic!("expected attributes: {:?}", patches) };
+        assert_eq!(attrs, &HashMap::from([("class".to_string(), Some("b".to_string()))]));
+
+        let mut root = old.clone();
+        apply_patches(&mut root, &patches);
+        assert_eq!(root.borrow().to_string(), "<input class=\"b\" value=\"saved\">");
+
+        // Elements without a live `value` keep it as an attribute
+        let old = VNode::element("li").attr("value", "1").finish();
+        let new = VNode::element("li").attr("value", "2").finish();
+        assert!(matches!(&diff(&old, &new)[..], [NodePatch { patch: Patch::UpdateAttributes(_), .. }]));
+    }
+
+    #[test]
+    fn test_adjacent_text_nodes_merge() {
+        let shared = VNode::new_text("alone");
+        let node = html!(p { "Hello, ", "world", "", "!", html!(br), shared.clone(), "" });
+        normalize(&node);
+
+        match &*node.borrow() {
+            VNode::Element { children, .. } => {
+                assert_eq!(children.len(), 3);
+                assert!(matches!(&*children[0].borrow(), VNode::Text(text) if text == "Hello, world!"));
+                // A lone text node is kept as is
+                assert!(Rc::ptr_eq(&children[2], &shared));
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(node.borrow().to_string(), "<p>Hello, world!<br>alone</p>");
+    }
+
+    #[test]
+    fn test_collapsing_whitespace_spares_preformatted_text() {
+        let node = html!(div { "\n    ", html!(span { "a  \t b" }), " ", html!(em { "c" }), "\n    ", html!(pre { "  keep\n  ", "this" }), "\n" });
+        normalize_with(&node, NormalizeOptions { collapse_whitespace: true });
+        assert_eq!(node.borrow().to_string(), "<div><span>a b</span> <em>c</em><pre>  keep\n  this</pre></div>");
+    }
+
+    #[test]
+    fn test_normalized_trees_diff_with_fewer_patches() {
+        let old = || html!(p { "Count: ", "1", html!(b { "!" }) });
+        let new = || html!(p { "Count: 1", html!(b { "!" }) });
+        assert_eq!(diff(&old(), &new()).len(), 3);
+
+        let (old, new) = (old(), new());
+        assert!(diff_normalized(&old, &new, NormalizeOptions::default()).is_empty());
+        // The trees themselves are left as built
+        assert_eq!(diff(&old, &new).len(), 3);
+        let VNode::Element { children, .. } = &*old.borrow() else { unreachable!() };
+        assert_eq!(children.len(), 3);
+    }
+
+    #[test]
+    fn test_every_patch_variant_round_trips_as_wire_json() {
+        let handler: EventHandler = Rc::new(|| {});
+        let node = VNode::element("li").attr("key", "a").on("click", || {}).text("A").finish();
+        let patches = vec![
+            Patch::Replace(VNode::new_text("new")),
+            Patch::Add(node.clone()),
+            Patch::Insert(1, VNode::new_fragment(vec![VNode::new_text("x")])),
+            Patch::Move { from: 2, to: 0 },
+            Patch::Remove,
+            Patch::UpdateAttributes(HashMap::from([
+                ("class".to_string(), Some("on".to_string())),
+                ("hidden".to_string(), None),
+            ])),
+            Patch::UpdateEventHandlers(HashMap::from([("click".to_string(), handler.clone())])),
+            Patch::UpdateState("count".to_string(), Box::new(3i64)),
+            Patch::UpdateProperties(HashMap::from([
+                ("value".to_string(), Some("hi".to_string())),
+                ("checked".to_string(), None),
+            ])),
+        ];
+
+        for patch in &patches {
+            let wire = WireNodePatch { path: vec![0, 1], patch: patch.to_wire() };
+            let json = serde_json::to_string(&wire).unwrap();
+            let decoded: WireNodePatch = serde_json::from_str(&json).unwrap();
+            assert_eq!(decoded, wire, "{}", json);
+        }
+
+        let json = |patch: &Patch| serde_json::to_value(patch.to_wire()).unwrap();
+        assert_eq!(json(&patches[0]), serde_json::json!({ "op": "replace", "node": { "type": "text", "text": "new" } }));
+        assert_eq!(json(&patches[3]), serde_json::json!({ "op": "move", "from": 2, "to": 0 }));
+        assert_eq!(json(&patches[4]), serde_json::json!({ "op": "remove" }));
+        assert_eq!(
+            json(&patches[5]),
+            serde_json::json!({ "op": "update_attributes", "attributes": { "class": "on", "hidden": null } })
+        );
+        assert_eq!(
+            json(&patches[6]),
+            serde_json::json!({ "op": "update_event_handlers", "handlers": { "click": handler_id(&handler) } })
+        );
+        assert_eq!(json(&patches[7]), serde_json::json!({ "op": "update_state", "key": "count", "value": 3 }));
+        assert_eq!(
+            json(&patches[8]),
+            serde_json::json!({ "op": "update_properties", "properties": { "value": "hi", "checked": null } })
+        );
+
+        let WirePatch::Add { node: WireNode::Element { tag, attributes, handlers, children } } = patches[1].to_wire() else {
+            panic!("expected an element");
+        };
+        assert_eq!(tag, "li");
+        assert_eq!(attributes["key"], "a");
+        assert!(handlers.contains_key("click"));
+        assert_eq!(children, vec![WireNode::Text { text: "A".to_string() }]);
+    }
+
+    #[test]
+    fn test_wire_patches_use_node_path() {
+        let old = html!(ul { keyed_item("a", "A"), keyed_item("b", "B") });
+        let new = html!(ul { keyed_item("b", "B"), keyed_item("a", "A") });
+
+        let wire: Vec<_> = diff(&old, &new).iter().map(NodePatch::to_wire).collect();
+        let json = serde_json::to_value(&wire).unwrap();
+        assert_eq!(json, serde_json::json!([{ "path": [], "op": "move", "from": 1, "to": 0 }]));
+    }
+
+    // Re-renders only when its `title` prop or its state changes, ignoring `rendered_at`
+    struct Headline {
+        title: String,
+        state: String,
+    }
+
+    impl Component for Headline {
+        fn render(&self) -> Rc<RefCell<VNode>> {
+            VNode::new_text(&self.title)
+        }
+
+        fn should_update(&self, prev_props: &HashMap<String, String>, prev_state: &dyn Any) -> bool {
+            prev_props.get("title") != Some(&self.title) || prev_state.downcast_ref::<String>() != Some(&self.state)
+        }
+    }
+
+    fn headline(title: &str, rendered_at: &str, state: &str) -> Rc<RefCell<VNode>> {
+        let props = HashMap::from([
+            ("title".to_string(), title.to_string()),
+            ("rendered_at".to_string(), rendered_at.to_string()),
+        ]);
+        let component = Headline { title: title.to_string(), state: state.to_string() };
+        html!(div { VNode::new_component("Headline", props, Rc::new(RefCell::new(state.to_string())), Box::new(component)) })
+    }
+
+    #[test]
+    fn test_memoized_component_skips_unchanged_subtree() {
+        let old = headline("News", "10:00", "open");
+
+        // Only the ignored prop changed: no patches, where an unmemoized component would be replaced
+        assert!(diff(&old, &headline("News", "10:05", "open")).is_empty());
+
+        let patches = diff(&old, &headline("Sports", "10:05", "open"));
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path, vec![0]);
+        assert!(matches!(patches[0].patch, Patch::Replace(_)));
+
+        let patches = diff(&old, &headline("News", "10:00", "closed"));
+        assert_eq!(patches.len(), 1);
+        let Patch::UpdateState(_, state) = &patches[0].patch else {
+            panic!("expected a state update, got {:?}", patches[0].patch);
+        };
+        assert_eq!(state.downcast_ref::<String>().map(String::as_str), Some("closed"));
+    }
+
+    // Panics while rendering, like a component that hit a bug
+    struct PriceTicker;
+
+    impl Component for PriceTicker {
+        fn render(&self) -> Rc<RefCell<VNode>> {
+            panic!("price feed missing")
+        }
+    }
+
+    // Wraps its child in a section, or shows what went wrong if the child panics
+    struct Boundary {
+        child: Rc<RefCell<VNode>>,
+    }
+
+    impl Component for Boundary {
+        fn render(&self) -> Rc<RefCell<VNode>> {
+            html!(section { self.child.clone() })
+        }
+
+        fn render_error(&self, err: &str) -> Option<Rc<RefCell<VNode>>> {
+            Some(html!(p ["class" => "error"] { format!("Unavailable: {}", err) }))
+        }
+    }
+
+    fn component(name: &str, component: impl Component + 'static) -> Rc<RefCell<VNode>> {
+        VNode::new_component(name, HashMap::new(), Rc::new(RefCell::new(())), Box::new(component))
+    }
+
+    fn news() -> Rc<RefCell<VNode>> {
+        component("Headline", Headline { title: "News".to_string(), state: String::new() })
+    }
+
+    #[test]
+    fn test_error_boundary_renders_fallback_for_panicking_child() {
+        let page = html!(main {
+            component("Boundary", Boundary { child: html!(div { "Prices", component("PriceTicker", PriceTicker) }) }),
+            component("Boundary", Boundary { child: news() }),
+            html!(footer { "Contact" }),
+        });
+
+        assert_eq!(
+            render(&page).borrow().to_string(),
+            "<main><p class=\"error\">Unavailable: price feed missing</p>\
+             <section>News</section>\
+             <footer>Contact</footer></main>"
+        );
+    }
+
+    #[test]
+    fn test_diff_renders_remounted_components_inside_boundaries() {
+        let old = html!(main { news() });
+        let new = html!(main { component("Boundary", Boundary { child: component("PriceTicker", PriceTicker) }) });
+
+        let patches = diff(&old, &new);
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path, vec![0]);
+        let Patch::Replace(node) = &patches[0].patch else {
+            panic!("expected a replace, got {:?}", patches[0].patch);
+        };
+        assert_eq!(node.borrow().to_string(), "<p class=\"error\">Unavailable: price feed missing</p>");
+    }
+
+    #[test]
+    fn test_panic_without_boundary_propagates() {
+        let page = html!(main { news(), component("PriceTicker", PriceTicker) });
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| render(&page))).is_err());
+    }
+
+    // A button showing how often it was clicked
+    struct Counter {
+        count: State<i64>,
+    }
+
+    impl Component for Counter {
+        fn render(&self) -> Rc<RefCell<VNode>> {
+            let count = self.count.clone();
+            VNode::element("button")
+                .on("click", move || count.update(|n| *n += 1))
+                .text(&self.count.get().to_string())
+                .finish()
+        }
+    }
+
+    fn click(node: &Rc<RefCell<VNode>>) {
+        let VNode::Element { event_handlers, .. } = &*node.borrow() else {
+            panic!("expected an element, got {:?}", node.borrow());
+        };
+        event_handlers["click"]();
+    }
+
+    #[test]
+    fn test_state_set_and_update_mark_the_component_dirty() {
+        let count = use_state(0i64);
+        let counter = Counter { count: count.clone() };
+        assert!(!count.is_dirty());
+
+        click(&counter.render());
+        click(&counter.render());
+        assert_eq!(count.get(), 2);
+        assert!(count.take_dirty());
+        assert!(!count.take_dirty());
+        assert_eq!(counter.render().borrow().to_string(), "<button>2</button>");
+
+        count.set(10);
+        assert!(count.is_dirty());
+        assert_eq!(counter.count.get(), 10);
+    }
+
+    fn counter(count: &State<i64>) -> Rc<RefCell<VNode>> {
+        html!(div { VNode::new_stateful_component("Counter", HashMap::new(), count, Box::new(Counter { count: count.clone() })) })
+    }
+
+    #[test]
+    fn test_diff_rerenders_components_whose_state_changed() {
+        let count = use_state(0i64);
+        let old = counter(&count);
+        let new = counter(&count);
+        assert!(diff(&old, &new).is_empty());
+
+        let rendered = render(&new);
+        click(&node_at(&rendered, &[0]).unwrap());
+        let newer = counter(&count);
+        let patches = diff(&new, &newer);
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path, vec![0]);
+        let Patch::Replace(node) = &patches[0].patch else {
+            panic!("expected a replace, got {:?}", patches[0].patch);
+        };
+        assert_eq!(node.borrow().to_string(), "<button>1</button>");
+
+        // The diff cleared the flag, so the next one finds nothing to do
+        assert!(!count.is_dirty());
+        assert!(diff(&newer, &counter(&count)).is_empty());
+    }
+
+    #[test]
+    fn test_state_cell_holds_the_typed_value() {
+        let title = use_state("open".to_string());
+        assert_eq!(title.with(String::len), 4);
+        let node = VNode::new_component("Title", HashMap::new(), title.cell(), Box::new(PriceTicker));
+        let VNode::Component { state, .. } = &*node.borrow() else { unreachable!() };
+
+        title.set("closed".to_string());
+        assert_eq!(state.borrow().downcast_ref::<String>().map(String::as_str), Some("closed"));
+        assert!(state.borrow().downcast_ref::<i64>().is_none());
+        assert_eq!(wire_state(&*state.borrow()), serde_json::json!("closed"));
+    }
+}
@@ -1,119 +1,272 @@
-use futures::{StreamExt, SinkExt}; // For working with async streams and sinks
+use futures::{Stream, StreamExt, SinkExt}; // For working with async streams and sinks
 use std::collections::HashMap; // To store client data and mappings
+use std::path::Path; // For certificate and key locations
 use std::sync::{Arc, Mutex}; // For thread-safe shared state
+use std::time::Duration; // For the authentication deadline
+use tokio::io::{AsyncRead, AsyncWrite}; // To handle plain and TLS connections alike
 use tokio::net::TcpListener; // To accept incoming TCP connections
-use tokio_tungstenite::{accept_async, WebSocketStream}; // For WebSocket handling
-use tungstenite::protocol::Message; // For WebSocket messages
+use tokio_rustls::TlsAcceptor; // To terminate TLS for wss:// clients
+use tokio_tungstenite::accept_hdr_async; // For WebSocket handling
+use tungstenite::handshake::server::{Request, Response}; // For inspecting the upgrade request
+use tungstenite::http::header::{HeaderValue, SEC_WEBSOCKET_PROTOCOL}; // For the token-carrying subprotocol header
+use tungstenite::protocol::frame::coding::CloseCode; // For rejecting unauthenticated clients
+use tungstenite::protocol::{CloseFrame, Message}; // For WebSocket messages
 use tokio::sync::broadcast; // For broadcasting messages to multiple clients
 use log::{info, error, warn}; // For logging information, warnings, and errors
+use serde::{Deserialize, Serialize}; // For the JSON message envelopes
+use serde_json::Value; // For RPC ids, params, and results
 
-// Type aliases for managing client sender, receiver, and username mappings
-type SenderMap = Arc<Mutex<HashMap<u32, tokio::sync::broadcast::Sender<String>>>>;
-type ReceiverMap = Arc<Mutex<HashMap<u32, tokio::sync::broadcast::Receiver<String>>>>;
+#[path = "../server/auth.rs"]
+#[allow(dead_code)]
+mod auth;
+use auth::{validate_token, Claims};
+
+#[path = "../tls.rs"]
+#[allow(dead_code)]
+mod tls;
+use tls::tls_config;
+
+// Subprotocol a client offers, followed by its JWT, to authenticate during the
+// handshake: `Sec-WebSocket-Protocol: bearer, <token>`
+const BEARER_PROTOCOL: &str = "bearer";
+
+// How long a client that sent no handshake token has to send `/auth <token>`
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Type aliases for managing client sender and username mappings
+type SenderMap = Arc<Mutex<HashMap<u32, tokio::sync::broadcast::Sender<ServerMessage>>>>;
 type UserMap = Arc<Mutex<HashMap<u32, String>>>;
 
+// A message from a client. Programmatic clients send the JSON envelope
+// `{ "type": "chat|nick|pm|rpc", ... }`; anything else is read as a
+// slash-command or plain chat line, see `parse_client_message`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientMessage {
+    Chat { text: String },
+    Nick { name: String },
+    Pm { to: String, text: String },
+    // `id` is echoed back so clients can match results to calls
+    Rpc {
+        #[serde(default)]
+        id: Option<Value>,
+        method: String,
+        #[serde(default)]
+        params: Value,
+    },
+}
+
+// A message to a client, serialized as `{ "type": "welcome|chat|pm|rpc_result|error", ... }`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    Welcome { username: String },
+    Chat { from: String, text: String },
+    Pm { from: String, text: String },
+    RpcResult { id: Option<Value>, result: Value },
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<Value>,
+        message: String,
+    },
+}
+
+impl ServerMessage {
+    fn error(message: impl Into<String>) -> Self {
+        ServerMessage::Error { id: None, message: message.into() }
+    }
+}
+
+// How a client framed its message; replies are framed the same way so
+// slash-command clients keep getting the plain text they always did
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Framing {
+    Json,
+    Text,
+}
+
+impl Framing {
+    fn render(self, message: &ServerMessage) -> String {
+        match (self, message) {
+            (Framing::Text, ServerMessage::Welcome { username }) => format!("Welcome, {}", username),
+            (Framing::Text, ServerMessage::Chat { from, text }) => format!("{}: {}", from, text),
+            (Framing::Text, ServerMessage::Pm { from, text }) => format!("Private message from {}: {}", from, text),
+            (Framing::Text, ServerMessage::Error { message, .. }) => message.clone(),
+            // RPC results only exist in the JSON protocol
+            _ => serde_json::to_string(message).expect("server messages serialize to JSON"),
+        }
+    }
+}
+
+// Parse a client's text frame. Frames starting with `{` must be a valid JSON
+// envelope: an unknown `type` or bad fields come back as an error to send to
+// the client, never as chat. Other frames fall back to the slash-commands
+// `/nick <name>` and `/msg <user> <message>`, or are chat
+fn parse_client_message(text: &str) -> (Framing, Result<ClientMessage, ServerMessage>) {
+    if text.trim_start().starts_with('{') {
+        let parsed = serde_json::from_str(text).map_err(|e| ServerMessage::error(format!("Invalid message: {}", e)));
+        return (Framing::Json, parsed);
+    }
+
+    let parsed = if let Some(name) = text.strip_prefix("/nick ") {
+        Ok(ClientMessage::Nick { name: name.trim().to_string() })
+    } else if text.starts_with("/msg ") {
+        match text.splitn(3, ' ').collect::<Vec<_>>()[..] {
+            [_, to, text] if !to.is_empty() => Ok(ClientMessage::Pm { to: to.to_string(), text: text.to_string() }),
+            _ => Err(ServerMessage::error("Usage: /msg <user> <message>")),
+        }
+    } else {
+        Ok(ClientMessage::Chat { text: text.to_string() })
+    };
+    (Framing::Text, parsed)
+}
+
+// A client connection, either plain TCP or TLS over TCP
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
 #[tokio::main]
 async fn main() {
     env_logger::init(); // Initialize logging
 
+    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set"); // Key that client tokens are signed with
     let addr = "127.0.0.1:8080"; // Define the server address
     let listener = TcpListener::bind(addr).await.expect("Failed to bind"); // Bind the server to the address
 
+    // Serve wss:// when a certificate is configured, plain ws:// otherwise
+    let tls = match (std::env::var("TLS_CERT_PATH"), std::env::var("TLS_KEY_PATH")) {
+        (Ok(cert_path), Ok(key_path)) => {
+            let config = tls_config(Path::new(&cert_path), Path::new(&key_path)).expect("Failed to load TLS certificate");
+            info!("WebSocket server listening on wss://{}", addr);
+            Some(TlsAcceptor::from(Arc::new(config)))
+        }
+        (Err(_), Err(_)) => {
+            warn!("No TLS certificate configured, WebSocket server listening on ws://{}", addr);
+            None
+        }
+        _ => panic!("TLS_CERT_PATH and TLS_KEY_PATH must be set together"),
+    };
+    serve(listener, Arc::new(secret), tls).await;
+}
+
+// Accept chat clients on `listener`, over TLS when `tls` is set, admitting
+// only those with a valid token signed with `secret`
+async fn serve(listener: TcpListener, secret: Arc<String>, tls: Option<TlsAcceptor>) {
     // Initialize shared state for managing client connections and usernames
     let sender_map = Arc::new(Mutex::new(HashMap::new()));
-    let receiver_map = Arc::new(Mutex::new(HashMap::new()));
     let user_map = Arc::new(Mutex::new(HashMap::new()));
 
-    // Create a broadcast channel for sending messages to all connected clients
-    let (broadcast_tx, _) = broadcast::channel(100);
-
-    info!("WebSocket server listening on {}", addr);
-
     let mut client_id = 0; // Counter for assigning unique client IDs
 
     // Main loop to accept incoming TCP connections
-    while let Ok((stream, _)) = listener.accept().await {
+    while let Ok((stream, peer)) = listener.accept().await {
         // Create a broadcast channel for each client
-        let (tx, rx) = broadcast::channel(100);
-        let mut tx = tx.clone();
-        let mut rx = rx.clone();
+        let (tx, mut rx) = broadcast::channel(100);
         let id = client_id;
         client_id += 1; // Increment client ID for the next connection
 
         // Clone Arc pointers for shared access across tasks
         let sender_map = Arc::clone(&sender_map);
-        let receiver_map = Arc::clone(&receiver_map);
         let user_map = Arc::clone(&user_map);
+        let secret = Arc::clone(&secret);
+        let tls = tls.clone();
 
         // Spawn a new task to handle the client connection
         tokio::spawn(async move {
-            // Upgrade the TCP stream to a WebSocket stream
-            let ws_stream = accept_async(stream)
-                .await
-                .expect("Error during WebSocket handshake");
+            // Finish the TLS handshake first so a slow client cannot stall the accept loop
+            let stream: Box<dyn Connection> = match tls {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => Box::new(stream),
+                    Err(e) => {
+                        warn!("TLS handshake with {} failed: {}", peer, e);
+                        return;
+                    }
+                },
+                None => Box::new(stream),
+            };
+
+            // Upgrade the TCP stream to a WebSocket stream, picking up a token offered as a subprotocol
+            let mut handshake_token = None;
+            let ws_stream = accept_hdr_async(stream, |req: &Request, mut response: Response| {
+                handshake_token = protocol_token(req);
+                if handshake_token.is_some() {
+                    // Clients fail the handshake unless the server confirms a subprotocol they offered
+                    response.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(BEARER_PROTOCOL));
+                }
+                Ok(response)
+            })
+            .await
+            .expect("Error during WebSocket handshake");
 
             let (mut ws_sender, mut ws_receiver) = ws_stream.split(); // Split the WebSocket stream into sender and receiver
 
-            // Store the client's sender and receiver in shared maps
+            // Reject unauthenticated clients before they can read or send anything
+            let username = match authenticate(handshake_token, &mut ws_receiver, &secret).await {
+                Ok(claims) => claims.sub,
+                Err(reason) => {
+                    warn!("Rejecting client {}: {}", id, reason);
+                    let frame = CloseFrame { code: CloseCode::Policy, reason: reason.into() };
+                    let _ = ws_sender.send(Message::Close(Some(frame))).await;
+                    return;
+                }
+            };
+
+            // Store the client's sender in the shared map so others can reach it
             {
                 let mut sender_map = sender_map.lock().unwrap();
                 sender_map.insert(id, tx);
             }
 
-            {
-                let mut receiver_map = receiver_map.lock().unwrap();
-                receiver_map.insert(id, rx);
-            }
-
-            // Set a default username for the client
+            // The username is the token's subject
             {
                 let mut user_map = user_map.lock().unwrap();
-                user_map.insert(id, format!("User{}", id));
+                user_map.insert(id, username.clone());
             }
 
-            info!("Client {} connected", id); // Log the new connection
+            info!("Client {} connected as {}", id, username); // Log the new connection
+            // Sent before the client has shown which framing it uses, so in the legacy text form
+            let welcome = Framing::Text.render(&ServerMessage::Welcome { username: username.clone() });
+            ws_sender.send(Message::Text(welcome)).await.expect("Failed to send message");
+
+            // Replies and messages from other clients share the socket, and both use
+            // the framing of the client's latest message
+            let ws_sender = Arc::new(tokio::sync::Mutex::new(ws_sender));
+            let framing = Arc::new(Mutex::new(Framing::Text));
+
+            // Forward chat and private messages sent to this client by others
+            let forwarder = {
+                let (ws_sender, framing) = (Arc::clone(&ws_sender), Arc::clone(&framing));
+                tokio::spawn(async move {
+                    loop {
+                        let message = match rx.recv().await {
+                            Ok(message) => message,
+                            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                                warn!("Client {} fell behind, dropped {} messages", id, skipped);
+                                continue;
+                            }
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        };
+                        let text = framing.lock().unwrap().render(&message);
+                        if ws_sender.lock().await.send(Message::Text(text)).await.is_err() {
+                            break; // The client is gone; the receive loop cleans up
+                        }
+                    }
+                })
+            };
 
             // Handle incoming messages from the client
             while let Some(message) = ws_receiver.next().await {
                 match message {
                     Ok(Message::Text(text)) => {
-                        // Process text messages from the client
-                        if text.starts_with("/nick ") {
-                            // Command to change the client's username
-                            let new_username = text.trim_start_matches("/nick ").trim().to_string();
-                            let mut user_map = user_map.lock().unwrap();
-                            if new_username.is_empty() {
-                                ws_sender.send(Message::Text("Username cannot be empty".to_string())).await.expect("Failed to send message");
-                            } else {
-                                let old_username = user_map.insert(id, new_username.clone());
-                                let message = format!("{} changed username to {}", old_username.unwrap_or("Unknown".to_string()), new_username);
-                                broadcast_message(&sender_map, &message).await;
-                            }
-                        } else if text.starts_with("/msg ") {
-                            // Command to send a private message to another user
-                            let parts: Vec<&str> = text.splitn(3, ' ').collect();
-                            if parts.len() < 3 {
-                                ws_sender.send(Message::Text("Usage: /msg <user> <message>".to_string())).await.expect("Failed to send message");
-                                continue;
-                            }
-                            let recipient_username = parts[1];
-                            let message = parts[2];
-                            let recipient_id = {
-                                let user_map = user_map.lock().unwrap();
-                                user_map.iter().find_map(|(&id, username)| if username == recipient_username { Some(id) } else { None })
-                            };
-                            if let Some(recipient_id) = recipient_id {
-                                let sender_map = sender_map.lock().unwrap();
-                                if let Some(tx) = sender_map.get(&recipient_id) {
-                                    tx.send(format!("Private message from {}: {}", user_map.lock().unwrap().get(&id).unwrap_or(&"Unknown".to_string()), message)).expect("Failed to send private message");
-                                }
-                            } else {
-                                ws_sender.send(Message::Text(format!("User {} not found", recipient_username))).await.expect("Failed to send message");
-                            }
-                        } else {
-                            // Broadcast the message to all connected clients
-                            let message = format!("{}: {}", user_map.lock().unwrap().get(&id).unwrap_or(&"Unknown".to_string()), text);
-                            broadcast_message(&sender_map, &message).await;
+                        let (message_framing, parsed) = parse_client_message(&text);
+                        *framing.lock().unwrap() = message_framing;
+                        let reply = match parsed {
+                            Ok(message) => handle_message(message, &username, &sender_map, &user_map).await,
+                            Err(error) => Some(error),
+                        };
+                        if let Some(reply) = reply {
+                            let reply = Message::Text(message_framing.render(&reply));
+                            ws_sender.lock().await.send(reply).await.expect("Failed to send message");
                         }
                     }
                     Ok(Message::Close(_)) => {
@@ -133,11 +286,7 @@ async fn main() {
                 let mut sender_map = sender_map.lock().unwrap();
                 sender_map.remove(&id);
             }
-
-            {
-                let mut receiver_map = receiver_map.lock().unwrap();
-                receiver_map.remove(&id);
-            }
+            forwarder.abort();
 
             {
                 let mut user_map = user_map.lock().unwrap();
@@ -147,10 +296,290 @@ async fn main() {
     }
 }
 
+// The token from a `Sec-WebSocket-Protocol: bearer, <token>` header, if the client sent one
+fn protocol_token(req: &Request) -> Option<String> {
+    let protocols = req.headers().get(SEC_WEBSOCKET_PROTOCOL)?.to_str().ok()?;
+    let mut protocols = protocols.split(',').map(str::trim);
+    if protocols.next()? != BEARER_PROTOCOL {
+        return None;
+    }
+    protocols.next().filter(|token| !token.is_empty()).map(str::to_string)
+}
+
+// Validate the client's token, taken from the handshake or else from a
+// `/auth <token>` first message; the error is the close reason sent back
+async fn authenticate<S>(handshake_token: Option<String>, receiver: &mut S, secret: &str) -> Result<Claims, &'static str>
+where
+    S: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+{
+    let token = match handshake_token {
+        Some(token) => token,
+        None => match tokio::time::timeout(AUTH_TIMEOUT, receiver.next()).await {
+            Ok(Some(Ok(Message::Text(text)))) => match text.strip_prefix("/auth ") {
+                Some(token) => token.trim().to_string(),
+                None => return Err("Expected /auth <token> as the first message"),
+            },
+            Ok(_) => return Err("Expected /auth <token> as the first message"),
+            Err(_) => return Err("Timed out waiting for /auth <token>"),
+        },
+    };
+    validate_token(secret, &token).map_err(|_| "Invalid or expired token")
+}
+
+// Act on a parsed client message, returning the reply for the sender if there is one
+async fn handle_message(message: ClientMessage, username: &str, sender_map: &SenderMap, user_map: &UserMap) -> Option<ServerMessage> {
+    match message {
+        // Usernames come from the token so nobody can pose as another user
+        ClientMessage::Nick { .. } => Some(ServerMessage::error("Your username is set by your token and cannot be changed")),
+        ClientMessage::Pm { to, text } => {
+            let recipient_id = {
+                let user_map = user_map.lock().unwrap();
+                user_map.iter().find_map(|(&id, name)| (*name == to).then_some(id))
+            };
+            let Some(recipient_id) = recipient_id else {
+                return Some(ServerMessage::error(format!("User {} not found", to)));
+            };
+            if let Some(tx) = sender_map.lock().unwrap().get(&recipient_id) {
+                // Fails only if the recipient disconnected since the lookup
+                let _ = tx.send(ServerMessage::Pm { from: username.to_string(), text });
+            }
+            None
+        }
+        ClientMessage::Chat { text } => {
+            broadcast_message(sender_map, &ServerMessage::Chat { from: username.to_string(), text }).await;
+            None
+        }
+        ClientMessage::Rpc { id, method, params } => Some(match call(&method, params, username, user_map) {
+            Ok(result) => ServerMessage::RpcResult { id, result },
+            Err(message) => ServerMessage::Error { id, message },
+        }),
+    }
+}
+
+// The RPC methods: `echo` returns its params, `whoami` the caller's username,
+// and `users` everyone connected
+fn call(method: &str, params: Value, username: &str, user_map: &UserMap) -> Result<Value, String> {
+    match method {
+        "echo" => Ok(params),
+        "whoami" => Ok(Value::from(username)),
+        "users" => {
+            let mut users: Vec<String> = user_map.lock().unwrap().values().cloned().collect();
+            users.sort();
+            users.dedup();
+            Ok(Value::from(users))
+        }
+        _ => Err(format!("Unknown method {}", method)),
+    }
+}
+
 // Function to broadcast a message to all connected clients
-async fn broadcast_message(sender_map: &SenderMap, message: &str) {
+async fn broadcast_message(sender_map: &SenderMap, message: &ServerMessage) {
     let sender_map = sender_map.lock().unwrap();
     for (_, tx) in sender_map.iter() {
-        tx.send(message.to_string()).expect("Failed to broadcast message");
+        // Fails only for a client that is disconnecting
+        let _ = tx.send(message.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use auth::issue_token;
+    use std::fs;
+    use std::net::SocketAddr;
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+    use tungstenite::client::IntoClientRequest;
+
+    const SECRET: &str = "websocket-test-secret";
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn start() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::new(SECRET.to_string()), None));
+        addr
+    }
+
+    async fn connect(addr: SocketAddr, protocol: Option<String>) -> Client {
+        let mut request = format!("ws://{}", addr).into_client_request().unwrap();
+        if let Some(protocol) = protocol {
+            request.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, protocol.parse().unwrap());
+        }
+        connect_async(request).await.expect("handshake should succeed").0
+    }
+
+    async fn next_message<S: AsyncRead + AsyncWrite + Unpin>(client: &mut WebSocketStream<S>) -> Message {
+        tokio::time::timeout(Duration::from_secs(5), client.next()).await.unwrap().unwrap().unwrap()
+    }
+
+    fn token_for(user: &str) -> String {
+        issue_token(SECRET, user, vec![], vec![]).unwrap().0
+    }
+
+    #[tokio::test]
+    async fn test_valid_token_connects_as_its_subject() {
+        let addr = start().await;
+
+        let mut client = connect(addr, Some(format!("bearer, {}", token_for("alice")))).await;
+        assert_eq!(next_message(&mut client).await, Message::Text("Welcome, alice".to_string()));
+
+        // Renaming is refused rather than letting alice pose as someone else
+        client.send(Message::Text("/nick bob".to_string())).await.unwrap();
+        assert!(matches!(next_message(&mut client).await, Message::Text(text) if text.contains("cannot be changed")));
+
+        // The token can also arrive as the first message
+        let mut client = connect(addr, None).await;
+        client.send(Message::Text(format!("/auth {}", token_for("carol")))).await.unwrap();
+        assert_eq!(next_message(&mut client).await, Message::Text("Welcome, carol".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_bad_or_missing_token_is_closed() {
+        let addr = start().await;
+        let forged = issue_token("some-other-secret", "alice", vec![], vec![]).unwrap().0;
+
+        let mut client = connect(addr, Some(format!("bearer, {}", forged))).await;
+        assert!(matches!(next_message(&mut client).await, Message::Close(Some(frame)) if frame.code == CloseCode::Policy));
+
+        let mut client = connect(addr, None).await;
+        client.send(Message::Text("hello".to_string())).await.unwrap();
+        assert!(matches!(next_message(&mut client).await, Message::Close(Some(frame)) if frame.code == CloseCode::Policy));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_json_envelopes() {
+        let parse = |text: &str| parse_client_message(text);
+        assert_eq!(
+            parse(r#"{"type": "chat", "text": "hi"}"#),
+            (Framing::Json, Ok(ClientMessage::Chat { text: "hi".to_string() }))
+        );
+        assert_eq!(
+            parse(r#"{"type": "nick", "name": "bob"}"#),
+            (Framing::Json, Ok(ClientMessage::Nick { name: "bob".to_string() }))
+        );
+        assert_eq!(
+            parse(r#"{"type": "pm", "to": "bob", "text": "psst"}"#),
+            (Framing::Json, Ok(ClientMessage::Pm { to: "bob".to_string(), text: "psst".to_string() }))
+        );
+        assert_eq!(
+            parse(r#"{"type": "rpc", "id": 7, "method": "echo", "params": [1, 2]}"#),
+            (Framing::Json, Ok(ClientMessage::Rpc { id: Some(Value::from(7)), method: "echo".to_string(), params: serde_json::json!([1, 2]) }))
+        );
+        assert_eq!(
+            parse(r#"{"type": "rpc", "method": "whoami"}"#),
+            (Framing::Json, Ok(ClientMessage::Rpc { id: None, method: "whoami".to_string(), params: Value::Null }))
+        );
+    }
+
+    #[test]
+    fn test_parse_slash_command_fallback() {
+        assert_eq!(parse_client_message("/nick bob").1, Ok(ClientMessage::Nick { name: "bob".to_string() }));
+        assert_eq!(
+            parse_client_message("/msg bob see you at 5").1,
+            Ok(ClientMessage::Pm { to: "bob".to_string(), text: "see you at 5".to_string() })
+        );
+        assert_eq!(parse_client_message("/msg bob").1, Err(ServerMessage::error("Usage: /msg <user> <message>")));
+        assert_eq!(parse_client_message("hello all"), (Framing::Text, Ok(ClientMessage::Chat { text: "hello all".to_string() })));
+    }
+
+    #[test]
+    fn test_unknown_or_malformed_envelopes_are_errors() {
+        for text in [r#"{"type": "dance"}"#, r#"{"text": "no type"}"#, r#"{"type": "pm", "text": "no recipient"}"#, "{not json"] {
+            let (framing, parsed) = parse_client_message(text);
+            assert_eq!(framing, Framing::Json);
+            assert!(matches!(parsed, Err(ServerMessage::Error { .. })), "{}: {:?}", text, parsed);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_json_clients_get_structured_replies() {
+        let addr = start().await;
+        let mut client = connect(addr, Some(format!("bearer, {}", token_for("alice")))).await;
+        assert_eq!(next_message(&mut client).await, Message::Text("Welcome, alice".to_string()));
+
+        // An unknown type is answered with an error instead of being broadcast as chat
+        client.send(Message::Text(r#"{"type": "dance", "text": "not chat"}"#.to_string())).await.unwrap();
+        let reply = |message: Message| serde_json::from_str::<Value>(message.to_text().unwrap()).unwrap();
+        let error = reply(next_message(&mut client).await);
+        assert_eq!(error["type"], "error");
+        assert!(error["message"].as_str().unwrap().contains("unknown variant `dance`"), "{}", error);
+
+        client.send(Message::Text(r#"{"type": "rpc", "id": "a1", "method": "echo", "params": {"n": 1}}"#.to_string())).await.unwrap();
+        assert_eq!(reply(next_message(&mut client).await), serde_json::json!({ "type": "rpc_result", "id": "a1", "result": { "n": 1 } }));
+
+        client.send(Message::Text(r#"{"type": "rpc", "id": 2, "method": "users"}"#.to_string())).await.unwrap();
+        assert_eq!(reply(next_message(&mut client).await), serde_json::json!({ "type": "rpc_result", "id": 2, "result": ["alice"] }));
+
+        client.send(Message::Text(r#"{"type": "rpc", "id": 3, "method": "reboot"}"#.to_string())).await.unwrap();
+        assert_eq!(reply(next_message(&mut client).await), serde_json::json!({ "type": "error", "id": 3, "message": "Unknown method reboot" }));
+
+        client.send(Message::Text(r#"{"type": "pm", "to": "erin", "text": "hi"}"#.to_string())).await.unwrap();
+        assert_eq!(reply(next_message(&mut client).await), serde_json::json!({ "type": "error", "message": "User erin not found" }));
+    }
+
+    #[tokio::test]
+    async fn test_chat_and_private_messages_reach_peers() {
+        let addr = start().await;
+        let mut alice = connect(addr, Some(format!("bearer, {}", token_for("alice")))).await;
+        assert_eq!(next_message(&mut alice).await, Message::Text("Welcome, alice".to_string()));
+        let mut bob = connect(addr, Some(format!("bearer, {}", token_for("bob")))).await;
+        assert_eq!(next_message(&mut bob).await, Message::Text("Welcome, bob".to_string()));
+
+        // Bob switches to the JSON protocol, so what peers send him arrives as JSON too
+        bob.send(Message::Text(r#"{"type": "rpc", "id": 1, "method": "whoami"}"#.to_string())).await.unwrap();
+        let reply = |message: Message| serde_json::from_str::<Value>(message.to_text().unwrap()).unwrap();
+        assert_eq!(reply(next_message(&mut bob).await), serde_json::json!({ "type": "rpc_result", "id": 1, "result": "bob" }));
+
+        alice.send(Message::Text("hello everyone".to_string())).await.unwrap();
+        assert_eq!(
+            reply(next_message(&mut bob).await),
+            serde_json::json!({ "type": "chat", "from": "alice", "text": "hello everyone" })
+        );
+        // Chat is broadcast to its sender as well
+        assert_eq!(next_message(&mut alice).await, Message::Text("alice: hello everyone".to_string()));
+
+        alice.send(Message::Text("/msg bob just for you".to_string())).await.unwrap();
+        assert_eq!(
+            reply(next_message(&mut bob).await),
+            serde_json::json!({ "type": "pm", "from": "alice", "text": "just for you" })
+        );
+
+        // Text-framed clients get the plain-text forms
+        bob.send(Message::Text(r#"{"type": "pm", "to": "alice", "text": "thanks"}"#.to_string())).await.unwrap();
+        assert_eq!(next_message(&mut alice).await, Message::Text("Private message from bob: thanks".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_tls_client_exchanges_messages() {
+        // A self-signed certificate for localhost, loaded the way `main` loads one
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("noxium-wss-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::write(&cert_path, certified.cert.pem()).unwrap();
+        fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(tls_config(&cert_path, &key_path).unwrap()));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::new(SECRET.to_string()), Some(acceptor)));
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let client_config = rustls::ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let tls = connector.connect("localhost".try_into().unwrap(), tcp).await.expect("TLS handshake should succeed");
+
+        let mut request = format!("wss://localhost:{}", addr.port()).into_client_request().unwrap();
+        request.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, format!("bearer, {}", token_for("dave")).parse().unwrap());
+        let (mut client, _) = tokio_tungstenite::client_async(request, tls).await.expect("handshake should succeed");
+
+        assert_eq!(next_message(&mut client).await, Message::Text("Welcome, dave".to_string()));
+        client.send(Message::Text("/msg erin hello over tls".to_string())).await.unwrap();
+        assert_eq!(next_message(&mut client).await, Message::Text("User erin not found".to_string()));
+    }
+}
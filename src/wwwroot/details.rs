@@ -1,8 +1,16 @@
-use reqwest::blocking::get;
+use encoding_rs::{Encoding, UTF_8};
+use reqwest::header::CONTENT_TYPE;
 use reqwest::StatusCode;
 use scraper::{Html, Selector};
 use log::{info, error};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+
+#[allow(dead_code)]
+#[path = "../http.rs"]
+mod http;
+
+// Details are keyed in a BTreeMap so the output order is stable across runs
+type Details = BTreeMap<String, Vec<String>>;
 
 // Initialize logger
 fn init_logger() {
@@ -30,31 +38,63 @@ fn main() {
 }
 
 // Function to fetch the webpage content
-fn fetch_webpage(url: &str) -> Result<String, reqwest::Error> {
+fn fetch_webpage(url: &str) -> Result<String, Box<dyn std::error::Error>> {
     info!("Fetching webpage: {}", url);
 
-    // Send a blocking GET request
-    let response = get(url)?;
+    // Send a blocking GET request, bounded by the shared client's timeouts
+    let response = http::blocking_client().get(url).send()?;
 
     // Check if the response status is success
     match response.status() {
         StatusCode::OK => {
             info!("Successfully fetched webpage.");
-            response.text()
+            let content_type = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string());
+            // Decompressed by its Content-Encoding if the client left it compressed
+            let bytes = http::blocking_bytes(response)?;
+            Ok(decode_body(&bytes, content_type.as_deref()))
         },
         status => {
             error!("Failed to fetch webpage. Status: {}", status);
-            Err(reqwest::Error::new(
-                reqwest::ErrorKind::Status,
-                format!("Failed to fetch webpage: {}", status),
-            ))
+            Err(format!("Failed to fetch webpage: {}", status).into())
         }
     }
 }
 
+// Function to pull the charset parameter out of a Content-Type header value
+fn charset_from_content_type(content_type: &str) -> Option<&str> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .map(|(_, value)| value.trim().trim_matches('"'))
+}
+
+// Function to decode the response body using the declared charset, falling back to lossy UTF-8
+fn decode_body(bytes: &[u8], content_type: Option<&str>) -> String {
+    let encoding = content_type
+        .and_then(charset_from_content_type)
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .unwrap_or(UTF_8);
+
+    if encoding == UTF_8 {
+        return String::from_utf8_lossy(bytes).into_owned();
+    }
+
+    let (decoded, _, had_errors) = encoding.decode(bytes);
+    if had_errors {
+        error!("Body contained invalid {} sequences; replaced them.", encoding.name());
+    }
+    decoded.into_owned()
+}
+
 // Function to extract details from the HTML body
-fn extract_webpage_details(body: &str) -> HashMap<String, Vec<String>> {
-    let mut details: HashMap<String, Vec<String>> = HashMap::new();
+fn extract_webpage_details(body: &str) -> Details {
+    let mut details = Details::new();
     let document = Html::parse_document(body);
 
     // Extract the title
@@ -71,11 +111,14 @@ fn extract_webpage_details(body: &str) -> HashMap<String, Vec<String>> {
     // Extract all images
     extract_images(&document, &mut details);
 
+    // Extract all <link rel> resources
+    extract_link_resources(&document, &mut details);
+
     details
 }
 
 // Function to extract meta tags from the document
-fn extract_meta_tags(document: &Html, details: &mut HashMap<String, Vec<String>>) {
+fn extract_meta_tags(document: &Html, details: &mut Details) {
     let meta_selector = Selector::parse("meta").unwrap();
     for meta in document.select(&meta_selector) {
         if let Some(name) = meta.value().attr("name") {
@@ -92,7 +135,7 @@ fn extract_meta_tags(document: &Html, details: &mut HashMap<String, Vec<String>>
 }
 
 // Function to extract all hyperlinks from the document
-fn extract_links(document: &Html, details: &mut HashMap<String, Vec<String>>) {
+fn extract_links(document: &Html, details: &mut Details) {
     let link_selector = Selector::parse("a").unwrap();
     for link in document.select(&link_selector) {
         if let Some(href) = link.value().attr("href") {
@@ -102,7 +145,7 @@ fn extract_links(document: &Html, details: &mut HashMap<String, Vec<String>>) {
 }
 
 // Function to extract all images from the document
-fn extract_images(document: &Html, details: &mut HashMap<String, Vec<String>>) {
+fn extract_images(document: &Html, details: &mut Details) {
     let img_selector = Selector::parse("img").unwrap();
     for img in document.select(&img_selector) {
         if let Some(src) = img.value().attr("src") {
@@ -111,12 +154,69 @@ fn extract_images(document: &Html, details: &mut HashMap<String, Vec<String>>) {
     }
 }
 
+// Function to extract <link rel> resources (stylesheets, icons, canonical, etc.)
+fn extract_link_resources(document: &Html, details: &mut Details) {
+    let link_selector = Selector::parse("link[rel]").unwrap();
+    for link in document.select(&link_selector) {
+        if let (Some(rel), Some(href)) = (link.value().attr("rel"), link.value().attr("href")) {
+            details.entry(format!("Link - {}", rel)).or_default().push(href.to_string());
+        }
+    }
+}
+
 // Function to display extracted details
-fn display_details(details: &HashMap<String, Vec<String>>) {
+fn display_details(details: &Details) {
     for (key, values) in details {
         println!("{}:", key);
         for value in values {
             println!("  - {}", value);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<html><head>
+        <title>Example</title>
+        <meta name="description" content="An example page">
+        <meta property="og:title" content="Example OG">
+        <link rel="stylesheet" href="/style.css">
+        <link rel="icon" href="/favicon.ico">
+        </head><body>
+        <a href="/about">About</a>
+        <img src="/logo.png">
+        </body></html>"#;
+
+    #[test]
+    fn test_details_order_is_deterministic() {
+        let first: Vec<String> = extract_webpage_details(PAGE).into_keys().collect();
+        for _ in 0..10 {
+            let keys: Vec<String> = extract_webpage_details(PAGE).into_keys().collect();
+            assert_eq!(keys, first, "Detail keys should come back in the same order every run");
+        }
+
+        let mut sorted = first.clone();
+        sorted.sort();
+        assert_eq!(first, sorted);
+    }
+
+    #[test]
+    fn test_link_rel_resources_extracted() {
+        let details = extract_webpage_details(PAGE);
+        assert_eq!(details["Link - stylesheet"], vec!["/style.css".to_string()]);
+        assert_eq!(details["Link - icon"], vec!["/favicon.ico".to_string()]);
+    }
+
+    #[test]
+    fn test_decode_body_uses_charset() {
+        // "café" in Latin-1
+        let latin1 = [0x63, 0x61, 0x66, 0xE9];
+        assert_eq!(decode_body(&latin1, Some("text/html; charset=ISO-8859-1")), "café");
+
+        // Unknown charset falls back to lossy UTF-8
+        assert_eq!(decode_body(&latin1, Some("text/html; charset=bogus")), "caf\u{FFFD}");
+        assert_eq!(decode_body("café".as_bytes(), None), "café");
+    }
+}
@@ -1,9 +1,17 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::iter::Peekable;
+use std::rc::Rc;
 use std::str::Chars;
 
-#[derive(Debug, PartialEq)]
+#[path = "../vdom.rs"]
+#[allow(dead_code)]
+mod vdom;
+use vdom::VNode;
+
+#[derive(Debug, Clone, PartialEq)]
 enum Token {
-    TagOpen(String),
+    TagOpen(String, Vec<(String, String)>),
     TagClose(String),
     Text(String),
     Attribute(String, String),
@@ -13,6 +21,7 @@ enum Token {
 enum ParseError {
     UnexpectedEndOfInput,
     UnexpectedToken(Token),
+    EmptyNode,
 }
 
 struct Tokenizer<'a> {
@@ -36,6 +45,7 @@ impl<'a> Tokenizer<'a> {
                         self.chars.next(); // Consume '/'
                         let tag_name = self.consume_while(|c| c.is_alphanumeric());
                         self.consume_until('>');
+                        self.chars.next(); // Consume '>'
                         Some(Ok(Token::TagClose(tag_name)))
                     }
                     Some(_) => {
@@ -61,7 +71,7 @@ impl<'a> Tokenizer<'a> {
                                 None => return Some(Err(ParseError::UnexpectedEndOfInput)),
                             }
                         }
-                        Some(Ok(Token::TagOpen(tag_name)))
+                        Some(Ok(Token::TagOpen(tag_name, attributes)))
                     }
                     None => Some(Err(ParseError::UnexpectedEndOfInput)),
                 }
@@ -104,6 +114,7 @@ impl<'a> Tokenizer<'a> {
 #[derive(Debug)]
 struct Node {
     tag: String,
+    attributes: Vec<(String, String)>,
     children: Vec<Node>,
     text: Option<String>,
 }
@@ -112,11 +123,18 @@ impl Node {
     fn new(tag: String) -> Self {
         Node {
             tag,
+            attributes: vec![],
             children: vec![],
             text: None,
         }
     }
 
+    fn text(text: String) -> Self {
+        let mut node = Node::new(String::new());
+        node.set_text(text);
+        node
+    }
+
     fn add_child(&mut self, child: Node) {
         self.children.push(child);
     }
@@ -146,8 +164,9 @@ impl<'a> Parser<'a> {
 
     fn parse_node(&mut self) -> Result<Node, ParseError> {
         match self.current_token.take() {
-            Some(Ok(Token::TagOpen(tag_name))) => {
+            Some(Ok(Token::TagOpen(tag_name, attributes))) => {
                 let mut node = Node::new(tag_name);
+                node.attributes = attributes;
                 self.current_token = self.tokenizer.next_token();
                 while let Some(Ok(token)) = &self.current_token {
                     match token {
@@ -155,12 +174,13 @@ impl<'a> Parser<'a> {
                             self.current_token = self.tokenizer.next_token();
                             break;
                         }
-                        Token::TagOpen(_) => {
+                        Token::TagOpen(..) => {
                             let child = self.parse_node()?;
                             node.add_child(child);
                         }
+                        // Text becomes a child node so it keeps its place among sibling elements
                         Token::Text(text) => {
-                            node.set_text(text.clone());
+                            node.add_child(Node::text(text.clone()));
                             self.current_token = self.tokenizer.next_token();
                         }
                         _ => return Err(ParseError::UnexpectedToken(token.clone())),
@@ -168,17 +188,30 @@ impl<'a> Parser<'a> {
                 }
                 Ok(node)
             }
-            Some(Ok(Token::Text(text))) => {
-                let mut node = Node::new(String::new());
-                node.set_text(text);
-                Ok(node)
-            }
+            Some(Ok(Token::Text(text))) => Ok(Node::text(text)),
             Some(Err(e)) => Err(e),
             _ => Err(ParseError::UnexpectedEndOfInput),
         }
     }
 }
 
+/// Builds a vdom tree from parsed HTML, e.g. to hydrate server-rendered markup.
+///
+/// Elements keep their tag, attributes, and children; text nodes become
+/// `VNode::Text`. Event handlers are left empty, to be attached by handler id.
+impl TryFrom<&Node> for Rc<RefCell<VNode>> {
+    type Error = ParseError;
+
+    fn try_from(node: &Node) -> Result<Self, Self::Error> {
+        if node.tag.is_empty() {
+            return node.text.as_deref().map(VNode::new_text).ok_or(ParseError::EmptyNode);
+        }
+        let children = node.children.iter().map(|child| child.try_into()).collect::<Result<Vec<_>, _>>()?;
+        let attributes = node.attributes.iter().cloned().collect();
+        Ok(VNode::new_element(&node.tag, attributes, children, HashMap::new()))
+    }
+}
+
 fn main() {
     let html = "<html><body><h1>Hello, World!</h1><p>This is a paragraph.</p></body></html>";
     let mut parser = Parser::new(html);
@@ -186,4 +219,23 @@ fn main() {
         Ok(document) => println!("{:?}", document),
         Err(e) => println!("Error: {:?}", e),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parsed_html_converts_to_vnode() {
+        let html = r#"<div class="x"><p>hi</p></div>"#;
+        let document = Parser::new(html).parse().unwrap();
+        let vnode = Rc::<RefCell<VNode>>::try_from(&document).unwrap();
+
+        assert_eq!(vnode.borrow().to_string(), html);
+        let VNode::Element { attributes, event_handlers, .. } = &*vnode.borrow() else {
+            panic!("expected an element");
+        };
+        assert_eq!(attributes.get("class").map(String::as_str), Some("x"));
+        assert!(event_handlers.is_empty());
+    }
+}